@@ -28,6 +28,8 @@ pub enum Error {
     WebsocketErr(#[from] tungstenite::error::Error),
     #[error("HTTP error: {0}")]
     HttpErr(#[from] http::Error),
+    #[error("Error reading file: {0}")]
+    IoErr(#[from] std::io::Error),
 }
 
 pub fn run(matches: &ArgMatches) -> Result<Response, Error> {
@@ -47,11 +49,30 @@ pub fn run(matches: &ArgMatches) -> Result<Response, Error> {
             }
             ("info", Some(submatches)) => client.get_account(submatches),
             ("list", Some(submatches)) => client.get_accounts(submatches),
+            ("payment-job", Some(submatches)) => client.get_account_payment_job(submatches),
+            ("payments", Some(submatches)) => client.get_account_payments(submatches),
             ("update", Some(submatches)) => client.put_account(submatches),
             ("update-settings", Some(submatches)) => client.put_account_settings(submatches),
             _ => Err(Error::UsageErr("ilp-cli help accounts")),
         },
+        ("audit", Some(audit_matches)) => client.get_audit(audit_matches),
+        ("capture", Some(capture_matches)) => match capture_matches.subcommand() {
+            ("start", Some(submatches)) => client.put_capture(submatches),
+            ("status", Some(submatches)) => client.get_capture(submatches),
+            ("stop", Some(submatches)) => client.delete_capture(submatches),
+            _ => Err(Error::UsageErr("ilp-cli help capture")),
+        },
+        ("circuit-breakers", Some(circuit_breakers_matches)) => {
+            client.get_circuit_breakers(circuit_breakers_matches)
+        }
+        ("fees", Some(fees_matches)) => match fees_matches.subcommand() {
+            ("list", Some(submatches)) => client.get_fees(submatches),
+            ("revenue", Some(submatches)) => client.get_fees_revenue(submatches),
+            ("set-all", Some(submatches)) => client.put_fees(submatches),
+            _ => Err(Error::UsageErr("ilp-cli help fees")),
+        },
         ("pay", Some(pay_matches)) => client.post_account_payments(pay_matches),
+        ("ping", Some(ping_matches)) => client.post_account_ping(ping_matches),
         ("rates", Some(rates_matches)) => match rates_matches.subcommand() {
             ("list", Some(submatches)) => client.get_rates(submatches),
             ("set-all", Some(submatches)) => client.put_rates(submatches),
@@ -68,6 +89,12 @@ pub fn run(matches: &ArgMatches) -> Result<Response, Error> {
             _ => Err(Error::UsageErr("ilp-cli help settlement-engines")),
         },
         ("status", Some(status_matches)) => client.get_root(status_matches),
+        ("tokens", Some(tokens_matches)) => match tokens_matches.subcommand() {
+            ("create", Some(submatches)) => client.post_tokens(submatches),
+            ("list", Some(submatches)) => client.get_tokens(submatches),
+            ("revoke", Some(submatches)) => client.delete_token(submatches),
+            _ => Err(Error::UsageErr("ilp-cli help tokens")),
+        },
         ("logs", Some(log_level)) => client.put_tracing_level(log_level),
         ("testnet", Some(testnet_matches)) => match testnet_matches.subcommand() {
             ("setup", Some(submatches)) => client.xpring_account(submatches),
@@ -189,13 +216,179 @@ impl NodeClient<'_> {
             .map_err(Error::SendErr)
     }
 
+    // GET /accounts/:username/payments
+    fn get_account_payments(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, mut args) = extract_args(matches);
+        let user = args.remove("username").unwrap(); // infallible unwrap
+        self.client
+            .get(&format!("{}/accounts/{}/payments", self.url, user))
+            .bearer_auth(auth)
+            .query(&args)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET or DELETE /accounts/:username/payments/jobs/:id
+    fn get_account_payment_job(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, args) = extract_args(matches);
+        let url = format!(
+            "{}/accounts/{}/payments/jobs/{}",
+            self.url, args["username"], args["id"]
+        );
+        let request = if matches.is_present("cancel") {
+            self.client.delete(&url)
+        } else {
+            self.client.get(&url)
+        };
+        request.bearer_auth(auth).send().map_err(Error::SendErr)
+    }
+
+    // GET /audit
+    fn get_audit(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, args) = extract_args(matches);
+        self.client
+            .get(&format!("{}/audit", self.url))
+            .bearer_auth(auth)
+            .query(&args)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET /capture
+    fn get_capture(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, _) = extract_args(matches);
+        self.client
+            .get(&format!("{}/capture", self.url))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // PUT /capture
+    fn put_capture(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let auth = matches.value_of("authorization_key").unwrap(); // infallible unwrap
+        let values = |arg: &str| -> Vec<&str> {
+            matches
+                .values_of(arg)
+                .map(|values| values.collect())
+                .unwrap_or_default()
+        };
+        self.client
+            .put(&format!("{}/capture", self.url))
+            .bearer_auth(auth)
+            .json(&serde_json::json!({
+                "accounts": values("account"),
+                "destination_prefixes": values("prefix"),
+            }))
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // DELETE /capture
+    fn delete_capture(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, _) = extract_args(matches);
+        self.client
+            .delete(&format!("{}/capture", self.url))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET /circuit-breakers
+    fn get_circuit_breakers(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, _) = extract_args(matches);
+        self.client
+            .get(&format!("{}/circuit-breakers", self.url))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET /fees
+    fn get_fees(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, _) = extract_args(matches);
+        self.client
+            .get(&format!("{}/fees", self.url))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET /fees/revenue
+    fn get_fees_revenue(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, _) = extract_args(matches);
+        self.client
+            .get(&format!("{}/fees/revenue", self.url))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // PUT /fees
+    fn put_fees(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let auth = matches.value_of("authorization_key").unwrap(); // infallible unwrap
+        let mut body = serde_json::Map::new();
+        for (arg, field) in &[
+            ("incoming", "incoming_accounts"),
+            ("outgoing", "outgoing_accounts"),
+            ("pair", "asset_pairs"),
+        ] {
+            let mut schedules = serde_json::Map::new();
+            if let Some(values) = matches.values_of(arg) {
+                let values: Vec<&str> = values.collect();
+                for schedule in values.chunks(4) {
+                    // Fixed and minimum fees are amounts, the percentage is sent as a decimal string
+                    let amount = |value: &str| {
+                        value
+                            .parse::<u64>()
+                            .map_err(|_| Error::UsageErr("ilp-cli help fees set-all"))
+                    };
+                    schedules.insert(
+                        schedule[0].to_string(),
+                        serde_json::json!({
+                            "fixed": amount(schedule[1])?,
+                            "percentage": schedule[2],
+                            "minimum": amount(schedule[3])?,
+                        }),
+                    );
+                }
+            }
+            body.insert(field.to_string(), schedules.into());
+        }
+        self.client
+            .put(&format!("{}/fees", self.url))
+            .bearer_auth(auth)
+            .json(&body)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
     // POST /accounts/:username/payments
     fn post_account_payments(&self, matches: &ArgMatches) -> Result<Response, Error> {
         let (auth, mut args) = extract_args(matches);
         let user = args.remove("sender_username").unwrap(); // infallible unwrap
+        let mut body: serde_json::Map<String, serde_json::Value> = args
+            .into_iter()
+            .map(|(key, val)| (key.to_string(), val.into()))
+            .collect();
+        if matches.is_present("background") {
+            body.insert("background".to_string(), true.into());
+        }
         self.client
             .post(&format!("{}/accounts/{}/payments", self.url, user))
             .bearer_auth(auth)
+            .json(&body)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // POST /accounts/:username/ping
+    fn post_account_ping(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, mut args) = extract_args(matches);
+        let user = args.remove("username").unwrap(); // infallible unwrap
+        self.client
+            .post(&format!("{}/accounts/{}/ping", self.url, user))
+            .bearer_auth(auth)
             .json(&args)
             .send()
             .map_err(Error::SendErr)
@@ -261,6 +454,44 @@ impl NodeClient<'_> {
             .map_err(Error::SendErr)
     }
 
+    // POST /tokens
+    fn post_tokens(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, args) = extract_args(matches);
+        let mut body: serde_json::Map<String, serde_json::Value> = args
+            .into_iter()
+            .map(|(key, val)| (key.to_string(), val.into()))
+            .collect();
+        // extract_args only keeps the first value of each argument
+        let scopes: Vec<&str> = matches.values_of("scopes").unwrap().collect(); // infallible unwrap
+        body.insert("scopes".to_string(), scopes.into());
+        self.client
+            .post(&format!("{}/tokens", self.url))
+            .bearer_auth(auth)
+            .json(&body)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET /tokens
+    fn get_tokens(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, _) = extract_args(matches);
+        self.client
+            .get(&format!("{}/tokens", self.url))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // DELETE /tokens/:id
+    fn delete_token(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, args) = extract_args(matches);
+        self.client
+            .delete(&format!("{}/tokens/{}", self.url, args["id"]))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
     // PUT /tracing-level
     fn put_tracing_level(&self, matches: &ArgMatches) -> Result<Response, Error> {
         let (auth, args) = extract_args(matches);
@@ -75,7 +75,7 @@ mod interface_tests {
     #[test]
     fn accounts_create() {
         should_parse(&[
-            "ilp-cli accounts create alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
+            "ilp-cli accounts create alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --packets-burst-limit 8 --amount-burst-limit 84 --amount-per-day-limit 1000 --amount-per-month-limit 10000 --max-in-flight-packets 10 --max-in-flight-amount 1000 --circuit-breaker-failure-threshold 10 --circuit-breaker-cool-down 60000 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
             "ilp-cli accounts create alice --auth foo --asset-code ABC --asset-scale 3 --min-balance -1000 --settle-threshold -10", // negative numbers
         ]);
     }
@@ -84,7 +84,7 @@ mod interface_tests {
     fn accounts_update() {
         should_parse(&[
             "ilp-cli accounts update alice --auth foo --asset-code ABC --asset-scale 9", // minimal
-            "ilp-cli accounts update alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
+            "ilp-cli accounts update alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --packets-burst-limit 8 --amount-burst-limit 84 --amount-per-day-limit 1000 --amount-per-month-limit 10000 --max-in-flight-packets 10 --max-in-flight-amount 1000 --circuit-breaker-failure-threshold 10 --circuit-breaker-cool-down 60000 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
         ]);
     }
 
@@ -124,19 +124,92 @@ mod interface_tests {
         ]);
     }
 
+    #[test]
+    fn accounts_payment_job() {
+        should_parse(&[
+            "ilp-cli accounts payment-job alice 5d2e3d4a-9a31-4b6e-8ab2-2f5ad2b1e0c7 --auth foo", // minimal
+            "ilp-cli accounts payment-job alice 5d2e3d4a-9a31-4b6e-8ab2-2f5ad2b1e0c7 --auth foo --cancel", // maximal
+        ]);
+    }
+
+    #[test]
+    fn accounts_payments() {
+        should_parse(&[
+            "ilp-cli accounts payments alice --auth foo", // minimal
+            "ilp-cli accounts payments alice --auth foo --direction incoming --status completed --since 0 --until 1593561600 --offset 10 --limit 5", // maximal
+        ]);
+    }
+
     #[test]
     fn accounts_update_settings() {
         should_parse(&[
             "ilp-cli accounts update-settings alice --auth foo", // minimal
-            "ilp-cli accounts update-settings alice --auth foo --ilp-over-http-incoming-token bar --ilp-over-btp-incoming-token qux --ilp-over-http-outgoing-token baz --ilp-over-btp-outgoing-token qaz --ilp-over-http-url spam --ilp-over-btp-url eggs --settle-threshold 0 --settle-to 0", // maximal
+            "ilp-cli accounts update-settings alice --auth foo --ilp-over-http-incoming-token bar --ilp-over-btp-incoming-token qux --ilp-over-http-outgoing-token baz --ilp-over-btp-outgoing-token qaz --ilp-over-http-url spam --ilp-over-btp-url eggs --settle-threshold 0 --settle-to 0 --circuit-breaker-failure-threshold 10 --circuit-breaker-cool-down 60000", // maximal
             "ilp-cli accounts update-settings alice --auth foo --settle-threshold -1000 --settle-to -10", // negative numbers
         ]);
     }
 
+    #[test]
+    fn audit() {
+        should_parse(&[
+            "ilp-cli audit --auth foo", // minimal
+            "ilp-cli audit --auth foo --actor admin --action set_rates --target alice --since 0 --until 1593561600 --offset 10 --limit 5", // maximal
+        ]);
+    }
+
+    #[test]
+    fn capture() {
+        should_parse(&[
+            "ilp-cli capture status --auth foo",                // minimal
+            "ilp-cli capture start --auth foo",                 // minimal
+            "ilp-cli capture stop --auth foo",                  // minimal
+            "ilp-cli capture start --auth foo --account alice --account bob --prefix g.us --prefix g.eu", // maximal
+        ]);
+    }
+
+    #[test]
+    fn circuit_breakers() {
+        should_parse(&[
+            "ilp-cli circuit-breakers --auth foo", // minimal
+        ]);
+    }
+
+    #[test]
+    fn fees_list() {
+        should_parse(&[
+            "ilp-cli fees list --auth foo", // minimal
+        ]);
+    }
+
+    #[test]
+    fn fees_revenue() {
+        should_parse(&[
+            "ilp-cli fees revenue --auth foo", // minimal
+        ]);
+    }
+
+    #[test]
+    fn fees_set_all() {
+        should_parse(&[
+            "ilp-cli fees set-all --auth foo", // minimal
+            "ilp-cli fees set-all --auth foo --incoming alice 1 0.5 0", // one
+            "ilp-cli fees set-all --auth foo --incoming alice 1 0.5 0 --incoming bob 0 0 10 --outgoing charlie 0 0.1 0 --pair USD/EUR 0 0.25 1", // many
+        ]);
+    }
+
     #[test]
     fn pay() {
         should_parse(&[
             "ilp-cli pay alice --auth foo --amount 500 --to bar", // minimal
+            "ilp-cli pay alice --auth foo --amount 500 --to bar --background", // maximal
+        ]);
+    }
+
+    #[test]
+    fn ping() {
+        should_parse(&[
+            "ilp-cli ping example.bob --auth foo --from alice", // minimal
+            "ilp-cli ping example.bob --auth foo --from alice --count 10 --amount 1 --interval 500 --timeout 2000", // maximal
         ]);
     }
 
@@ -198,6 +271,28 @@ mod interface_tests {
         ]);
     }
 
+    #[test]
+    fn tokens_create() {
+        should_parse(&[
+            "ilp-cli tokens create --auth foo --scope pay", // minimal
+            "ilp-cli tokens create --auth foo --scope pay --scope read-only --username alice --expires-at 4102444800 --description shop", // maximal
+        ]);
+    }
+
+    #[test]
+    fn tokens_list() {
+        should_parse(&[
+            "ilp-cli tokens list --auth foo", // minimal
+        ]);
+    }
+
+    #[test]
+    fn tokens_revoke() {
+        should_parse(&[
+            "ilp-cli tokens revoke 6f4ad2a5-2bd7-4c2e-b1ec-d0a2a3a6f6f0 --auth foo", // minimal
+        ]);
+    }
+
     #[test]
     fn testnet_setup() {
         should_parse(&[
@@ -215,9 +310,11 @@ mod interface_tests {
             match parser_result {
                 Err(e) => panic!("Failed to parse command `{}`: {}", example, e),
                 Ok(matches) => match run(&matches) {
-                    // Because these are interface tests, not integration tests, network errors are expected
+                    // Because these are interface tests, not integration tests, network errors are expected,
+                    // and so are errors reading the files which are given as arguments
                     Ok(_)
                     | Err(Error::SendErr(_))
+                    | Err(Error::IoErr(_))
                     | Err(Error::WebsocketErr(_))
                     | Err(Error::TestnetErr(_)) => (),
                     Err(e) => panic!("Unexpected interpreter failure: {}", e),
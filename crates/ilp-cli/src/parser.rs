@@ -9,14 +9,22 @@ pub fn build<'a, 'b>() -> App<'a, 'b> {
             accounts_incoming_payments(),
             accounts_info(),
             accounts_list(),
+            accounts_payment_job(),
+            accounts_payments(),
             accounts_update(),
             accounts_update_settings(),
         ]),
+        audit(),
+        capture().subcommands(vec![capture_start(), capture_status(), capture_stop()]),
+        circuit_breakers(),
+        fees().subcommands(vec![fees_list(), fees_revenue(), fees_set_all()]),
         pay(),
+        ping(),
         rates().subcommands(vec![rates_list(), rates_set_all()]),
         routes().subcommands(vec![routes_list(), routes_set(), routes_set_all()]),
         settlement_engines().subcommands(vec![settlement_engines_set_all()]),
         status(),
+        tokens().subcommands(vec![tokens_create(), tokens_list(), tokens_revoke()]),
         logs(),
         testnet().subcommands(vec![testnet_setup()]),
     ])
@@ -142,6 +150,30 @@ fn accounts_create<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("packets_per_minute_limit")
                 .long("packets-per-minute-limit")
                 .takes_value(true),
+            Arg::with_name("packets_burst_limit")
+                .long("packets-burst-limit")
+                .takes_value(true),
+            Arg::with_name("amount_burst_limit")
+                .long("amount-burst-limit")
+                .takes_value(true),
+            Arg::with_name("amount_per_day_limit")
+                .long("amount-per-day-limit")
+                .takes_value(true),
+            Arg::with_name("amount_per_month_limit")
+                .long("amount-per-month-limit")
+                .takes_value(true),
+            Arg::with_name("max_in_flight_packets")
+                .long("max-in-flight-packets")
+                .takes_value(true),
+            Arg::with_name("max_in_flight_amount")
+                .long("max-in-flight-amount")
+                .takes_value(true),
+            Arg::with_name("circuit_breaker_failure_threshold")
+                .long("circuit-breaker-failure-threshold")
+                .takes_value(true),
+            Arg::with_name("circuit_breaker_cool_down")
+                .long("circuit-breaker-cool-down")
+                .takes_value(true),
             Arg::with_name("settlement_engine_url")
                 .long("settlement-engine-url")
                 .takes_value(true),
@@ -213,6 +245,30 @@ fn accounts_update<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("packets_per_minute_limit")
                 .long("packets-per-minute-limit")
                 .takes_value(true),
+            Arg::with_name("packets_burst_limit")
+                .long("packets-burst-limit")
+                .takes_value(true),
+            Arg::with_name("amount_burst_limit")
+                .long("amount-burst-limit")
+                .takes_value(true),
+            Arg::with_name("amount_per_day_limit")
+                .long("amount-per-day-limit")
+                .takes_value(true),
+            Arg::with_name("amount_per_month_limit")
+                .long("amount-per-month-limit")
+                .takes_value(true),
+            Arg::with_name("max_in_flight_packets")
+                .long("max-in-flight-packets")
+                .takes_value(true),
+            Arg::with_name("max_in_flight_amount")
+                .long("max-in-flight-amount")
+                .takes_value(true),
+            Arg::with_name("circuit_breaker_failure_threshold")
+                .long("circuit-breaker-failure-threshold")
+                .takes_value(true),
+            Arg::with_name("circuit_breaker_cool_down")
+                .long("circuit-breaker-cool-down")
+                .takes_value(true),
             Arg::with_name("settlement_engine_url")
                 .long("settlement-engine-url")
                 .takes_value(true),
@@ -262,6 +318,66 @@ fn accounts_list<'a, 'b>() -> App<'a, 'b> {
     AuthorizedSubCommand::with_name("list").about("List all accounts on this node")
 }
 
+fn accounts_payment_job<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("payment-job")
+        .about("View the progress of a payment sent in the background, or cancel it")
+        .args(&[
+            Arg::with_name("username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account which sent the payment"),
+            Arg::with_name("id")
+                .index(2)
+                .takes_value(true)
+                .required(true)
+                .help("The id of the payment job"),
+            Arg::with_name("cancel")
+                .long("cancel")
+                .help("Stop the payment from sending any more packets"),
+        ])
+}
+
+fn accounts_payments<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("payments")
+        .about(
+            "List the payments sent and received by an account, from the most recent to the oldest",
+        )
+        .args(&[
+            Arg::with_name("username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account whose payments to list"),
+            Arg::with_name("direction")
+                .long("direction")
+                .takes_value(true)
+                .possible_values(&["incoming", "outgoing"])
+                .help("Only list the payments in this direction"),
+            Arg::with_name("status")
+                .long("status")
+                .takes_value(true)
+                .possible_values(&["completed", "failed"])
+                .help("Only list the payments with this status"),
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only list the payments made at or after this UNIX timestamp, in seconds"),
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .help("Only list the payments made at or before this UNIX timestamp, in seconds"),
+            Arg::with_name("offset")
+                .long("offset")
+                .takes_value(true)
+                .help("The number of matching payments to skip"),
+            Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .help("The maximum number of payments to list"),
+        ])
+}
+
 fn accounts_update_settings<'a, 'b>() -> App<'a, 'b> {
     AuthorizedSubCommand::with_name("update-settings")
         .about("Overwrite the details of an account on this node")
@@ -295,6 +411,12 @@ fn accounts_update_settings<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("settle_to")
                 .long("settle-to")
                 .takes_value(true),
+            Arg::with_name("circuit_breaker_failure_threshold")
+                .long("circuit-breaker-failure-threshold")
+                .takes_value(true),
+            Arg::with_name("circuit_breaker_cool_down")
+                .long("circuit-breaker-cool-down")
+                .takes_value(true),
         ])
 }
 
@@ -318,6 +440,42 @@ fn pay<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .required(true)
                 .help("The Payment Pointer or SPSP address of the account receiving the payment"),
+            Arg::with_name("background")
+                .long("background")
+                .help("Send the payment in the background and return its payment job right away, instead of waiting for the payment to finish"),
+        ])
+}
+
+fn ping<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("ping")
+        .about("Send echo requests to an ILP address from an account on this node, and report their round-trip times and the hops which rejected them")
+        .args(&[
+            Arg::with_name("destination")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The ILP address to ping"),
+            Arg::with_name("username")
+                .long("from")
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account on this node sending the echo requests"),
+            Arg::with_name("count")
+                .long("count")
+                .takes_value(true)
+                .help("The number of echo requests to send, from 1 to 100. Defaults to 4"),
+            Arg::with_name("amount")
+                .long("amount")
+                .takes_value(true)
+                .help("The amount of each echo request, denominated in units of the sender's assets. Defaults to 0"),
+            Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .help("The time, in milliseconds, to wait between two echo requests. Defaults to 1000"),
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .help("The time, in milliseconds, after which an echo request expires. Defaults to 5000"),
         ])
 }
 
@@ -395,6 +553,181 @@ fn settlement_engines_set_all<'a, 'b>() -> App<'a, 'b> {
         )
 }
 
+fn tokens<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("tokens").about("Issue and revoke scoped API tokens")
+}
+
+fn tokens_create<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("create")
+        .about("Issue a new API token, which is only displayed this once")
+        .args(&[
+            Arg::with_name("scopes")
+                .long("scope")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+                .possible_values(&[
+                    "read-only",
+                    "pay",
+                    "manage-accounts",
+                    "manage-routes",
+                    "manage-rates",
+                ])
+                .help("An action the token allows; may appear multiple times"),
+            Arg::with_name("username")
+                .long("username")
+                .takes_value(true)
+                .help("The only account the token acts for"),
+            Arg::with_name("expires_at")
+                .long("expires-at")
+                .takes_value(true)
+                .help("UNIX timestamp, in seconds, after which the token is rejected"),
+            Arg::with_name("description")
+                .long("description")
+                .takes_value(true)
+                .help("A note about what the token is used for"),
+        ])
+}
+
+fn tokens_list<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("list").about("List the API tokens issued by this node")
+}
+
+fn tokens_revoke<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("revoke")
+        .about("Revoke an API token")
+        .arg(
+            Arg::with_name("id")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The id of the API token to revoke"),
+        )
+}
+
+fn audit<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("audit")
+        .about("List the administrative actions taken on this node, from the most recent to the oldest")
+        .args(&[
+            Arg::with_name("actor")
+                .long("actor")
+                .takes_value(true)
+                .help("Only list the actions of this actor, e.g. admin, api_token:<id> or user:<username>"),
+            Arg::with_name("action")
+                .long("action")
+                .takes_value(true)
+                .possible_values(&[
+                    "create_account",
+                    "update_account",
+                    "delete_account",
+                    "update_account_settings",
+                    "set_rates",
+                    "set_static_routes",
+                    "set_static_route",
+                    "set_settlement_engines",
+                    "set_fees",
+                    "start_packet_capture",
+                    "stop_packet_capture",
+                ])
+                .help("Only list the actions of this kind"),
+            Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .help("Only list the actions which applied to this username or route prefix"),
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only list the actions taken at or after this UNIX timestamp, in seconds"),
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .help("Only list the actions taken at or before this UNIX timestamp, in seconds"),
+            Arg::with_name("offset")
+                .long("offset")
+                .takes_value(true)
+                .help("The number of matching actions to skip"),
+            Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .help("The maximum number of actions to list"),
+        ])
+}
+
+fn capture<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("capture")
+        .about("Operations for capturing the packets this node receives")
+}
+
+fn capture_start<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("start")
+        .about("Start capturing the packets this node receives to its capture file, or replace the filter of the capture if it already started")
+        .args(&[
+            Arg::with_name("account")
+                .long("account")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only capture the packets sent by this account; may appear multiple times"),
+            Arg::with_name("prefix")
+                .long("prefix")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only capture the packets whose destination starts with this prefix; may appear multiple times"),
+        ])
+}
+
+fn capture_status<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("status")
+        .about("Show whether packets are being captured, and which ones")
+}
+
+fn capture_stop<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("stop").about("Stop capturing packets")
+}
+
+fn circuit_breakers<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("circuit-breakers")
+        .about("List the state of the circuit breakers of the accounts this node sent packets to")
+}
+
+fn fees<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("fees")
+        .about("Operations for interacting with the fees charged on packets")
+}
+
+fn fees_list<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("list").about("List the fee schedules charged by this node")
+}
+
+fn fees_revenue<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("revenue")
+        .about("List the fees earned on the packets received from each account")
+}
+
+fn fees_set_all<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("set-all")
+        .about("Overwrite the fee schedules charged by this node. Fees are in units of the incoming account's asset and the fees of every schedule which matches a packet are added up")
+        .args(&[
+            Arg::with_name("incoming")
+                .long("incoming")
+                .number_of_values(4)
+                .multiple(true)
+                .help("The username of an account, followed by the fixed fee, the percentage and the minimum fee charged on the packets received from it; may appear multiple times"),
+            Arg::with_name("outgoing")
+                .long("outgoing")
+                .number_of_values(4)
+                .multiple(true)
+                .help("The username of an account, followed by the fixed fee, the percentage and the minimum fee charged on the packets sent to it; may appear multiple times"),
+            Arg::with_name("pair")
+                .long("pair")
+                .number_of_values(4)
+                .multiple(true)
+                .help("An asset pair such as USD/EUR, followed by the fixed fee, the percentage and the minimum fee charged on the packets exchanged from the first asset to the second; may appear multiple times"),
+        ])
+}
+
 fn logs<'a, 'b>() -> App<'a, 'b> {
     AuthorizedSubCommand::with_name("logs")
         .about("Modify the logging level of the server")
@@ -0,0 +1,92 @@
+use clap::{crate_version, App, Arg};
+use ilp_node::replay::{replay_capture, ReplayConfig};
+use interledger::{service::IlpResult, service_util::CaptureReader};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    process,
+};
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("ilp-replay")
+        .about("Replay the packets captured by a node through a node built from a config, offline, and compare the responses they get with the captured ones. The packets which are forwarded get the response their captured packet got instead of reaching any peer")
+        .version(crate_version!())
+        .args(&[
+            Arg::with_name("config")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("JSON file with the node's `ilp_address`, and its `accounts`, `routes`, `exchange_rates`, `spread` and `fees`"),
+            Arg::with_name("capture")
+                .index(2)
+                .takes_value(true)
+                .required(true)
+                .help("The capture file to replay"),
+        ])
+        .get_matches();
+
+    // infallible unwraps, since both arguments are required
+    match run(
+        matches.value_of("config").unwrap(),
+        matches.value_of("capture").unwrap(),
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+/// Replays the capture and prints how each packet fared. Returns whether they all matched
+async fn run(config_path: &str, capture_path: &str) -> Result<bool, String> {
+    let config = fs::read(config_path)
+        .map_err(|err| format!("Error reading the config {}: {}", config_path, err))?;
+    let config: ReplayConfig = serde_json::from_slice(&config)
+        .map_err(|err| format!("Invalid config {}: {}", config_path, err))?;
+    let records = File::open(capture_path)
+        .and_then(|file| CaptureReader::new(BufReader::new(file)))
+        .and_then(|reader| reader.collect::<io::Result<Vec<_>>>())
+        .map_err(|err| format!("Error reading the capture file {}: {}", capture_path, err))?;
+
+    let packets = replay_capture(config, records).await?;
+    for packet in packets.iter() {
+        let record = &packet.outcome.record;
+        let forwarded = match packet.forwarded {
+            Some((ref username, amount)) => format!(", forwarded to {} ({})", username, amount),
+            None => String::new(),
+        };
+        println!(
+            "{}{} {} {}: captured {}, replayed {}{}",
+            if packet.outcome.matches() {
+                ""
+            } else {
+                "MISMATCH "
+            },
+            record.username,
+            record.prepare.destination(),
+            record.prepare.amount(),
+            describe(&record.result),
+            describe(&packet.outcome.result),
+            forwarded
+        );
+    }
+    let matched = packets
+        .iter()
+        .filter(|packet| packet.outcome.matches())
+        .count();
+    println!("Replayed {} packets, {} matched", packets.len(), matched);
+    Ok(matched == packets.len())
+}
+
+/// `fulfilled`, or the code of the reject
+fn describe(result: &IlpResult) -> String {
+    match result {
+        Ok(_) => "fulfilled".to_string(),
+        Err(reject) => reject.code().to_string(),
+    }
+}
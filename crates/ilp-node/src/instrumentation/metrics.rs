@@ -3,6 +3,7 @@ use interledger::{
     service::{
         Account, IlpResult, IncomingRequest, IncomingService, OutgoingRequest, OutgoingService,
     },
+    service_util::{CircuitBreakers, CircuitState},
 };
 use metrics::{self, labels, recorder, Key};
 use std::time::Instant;
@@ -80,3 +81,28 @@ pub async fn outgoing_metrics<A: Account + CcpRoutingAccount>(
 
     result
 }
+
+/// Reports the state of each account's circuit breaker, which is 0 if it is closed,
+/// 1 if it is half-open and 2 if it is open
+pub fn circuit_breaker_metrics(circuit_breakers: &CircuitBreakers) {
+    for status in circuit_breakers.status() {
+        let labels = labels!("to_username" => status.username.to_string());
+        let state = match status.state {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        };
+        recorder().update_gauge(
+            Key::from_name_and_labels("circuit_breaker.state", labels.clone()),
+            state,
+        );
+        recorder().update_gauge(
+            Key::from_name_and_labels("circuit_breaker.consecutive_failures", labels.clone()),
+            i64::from(status.consecutive_failures),
+        );
+        recorder().update_gauge(
+            Key::from_name_and_labels("circuit_breaker.rejected_packets", labels),
+            status.rejected_packets as i64,
+        );
+    }
+}
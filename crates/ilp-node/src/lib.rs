@@ -1,8 +1,18 @@
 #![type_length_limit = "10000000"]
 mod instrumentation;
 mod node;
+mod tls;
+
+#[cfg(feature = "memory")]
+mod memory_store;
+
+#[cfg(feature = "memory")]
+pub mod replay;
 
 #[cfg(feature = "redis")]
 mod redis_store;
 
+#[cfg(feature = "sql")]
+mod sql_store;
+
 pub use node::*;
@@ -1,10 +1,17 @@
 #![type_length_limit = "10000000"]
 mod instrumentation;
 pub mod node;
+mod tls;
+
+#[cfg(feature = "memory")]
+mod memory_store;
 
 #[cfg(feature = "redis")]
 mod redis_store;
 
+#[cfg(feature = "sql")]
+mod sql_store;
+
 use clap::{crate_version, App, Arg, ArgMatches};
 use config::{Config, Source};
 use config::{ConfigError, FileFormat, Value};
@@ -66,11 +73,23 @@ async fn main() {
             .alias("redis_url")
             .takes_value(true)
             .default_value("redis://127.0.0.1:6379")
-            .help("Redis URI (for example, \"redis://127.0.0.1:6379\" or \"unix:/tmp/redis.sock\")"),
+            .help("Data store URI (for example, \"redis://127.0.0.1:6379\", \"unix:/tmp/redis.sock\", \"postgres://localhost/interledger\", \"sqlite://node.db?mode=rwc\" or \"memory://\")"),
         Arg::with_name("http_bind_address")
             .long("http_bind_address")
             .takes_value(true)
             .help("IP address and port to listen for HTTP connections. This is used for both the API and ILP over HTTP packets. ILP over HTTP is a means to transfer ILP packets instead of BTP connections"),
+        Arg::with_name("http_tls.cert_path")
+            .long("http_tls.cert_path")
+            .takes_value(true)
+            .help("Path of the PEM file with the certificate chain to serve HTTPS and WSS with on http_bind_address. If it is not set, plain HTTP is served"),
+        Arg::with_name("http_tls.key_path")
+            .long("http_tls.key_path")
+            .takes_value(true)
+            .help("Path of the PEM file with the private key of http_tls.cert_path"),
+        Arg::with_name("http_tls.client_ca_path")
+            .long("http_tls.client_ca_path")
+            .takes_value(true)
+            .help("Path of the PEM file with the certificate authorities which issue the certificates of the HTTPS clients. If it is set, client certificates are verified"),
         Arg::with_name("settlement_api_bind_address")
             .long("settlement_api_bind_address")
             .takes_value(true)
@@ -83,6 +102,29 @@ async fn main() {
             .long("route_broadcast_interval")
             .takes_value(true)
             .help("Interval, defined in milliseconds, on which the node will broadcast routing information to other nodes using CCP. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("routing.failover_codes")
+            .long("routing.failover_codes")
+            .takes_value(true)
+            .help("Comma-separated list of reject codes which make the node retry a packet through the next route for its destination, if it has several. Defaults to \"T01,T04\"."),
+        Arg::with_name("load_shedding.latency_target")
+            .long("load_shedding.latency_target")
+            .takes_value(true)
+            .help("Average time, in milliseconds, the forwarded packets should take to be fulfilled or rejected. While packets take longer, \
+                a share of them proportional to the excess latency is rejected with T03. If this is not set, no load is shed."),
+        Arg::with_name("rate_limits.local")
+            .long("rate_limits.local")
+            .takes_value(true)
+            .help("Apply the accounts' rate limits with token buckets in the node's memory, which are reconciled with the store periodically, \
+                rather than with the store on every packet. Defaults to false."),
+        Arg::with_name("rate_limits.reconcile_interval")
+            .long("rate_limits.reconcile_interval")
+            .takes_value(true)
+            .help("Interval, defined in milliseconds, on which the local rate limits are reconciled with the store. Defaults to 1000ms (1 second)."),
+        Arg::with_name("packet_capture.path")
+            .long("packet_capture.path")
+            .takes_value(true)
+            .help("File the incoming packets are captured to, once the capture is started via the API. \
+                The file is rotated once it exceeds 100MB. If this is not set, packets cannot be captured."),
         Arg::with_name("exchange_rate.provider")
             .long("exchange_rate.provider")
             .takes_value(true)
@@ -92,6 +134,17 @@ async fn main() {
             .long("exchange_rate.poll_interval")
             .default_value("60000")
             .help("Interval, defined in milliseconds, on which the node will poll the exchange_rate.provider (if specified) for exchange rates."),
+        Arg::with_name("exchange_rate.max_deviation")
+            .long("exchange_rate.max_deviation")
+            .takes_value(true)
+            .help("Maximum deviation, as a fraction of the median of an asset's rates, of the rates polled from several providers. Rates which deviate more are rejected as outliers. \
+                Several providers can be configured via a config file or stdin, with exchange_rate.providers."),
+        Arg::with_name("exchange_rate.max_age")
+            .long("exchange_rate.max_age")
+            .takes_value(true)
+            .help("Maximum age, in milliseconds, of the rate of an asset. The rate of an asset which the providers stop returning is removed once it is older than this, so that packets in that asset are rejected. \
+                The rates of the assets without a max age are replaced on every poll. \
+                The max ages of particular assets can be configured via a config file or stdin, with exchange_rate.asset_max_ages."),
         Arg::with_name("exchange_rate.spread")
             .long("exchange_rate.spread")
             .default_value("0")
@@ -0,0 +1,17 @@
+#![cfg(feature = "memory")]
+
+use crate::node::InterledgerNode;
+pub use interledger::{packet::Address, store::memory::MemoryStoreBuilder};
+
+pub fn default_memory_url() -> String {
+    String::from("memory://")
+}
+
+// Like the Redis equivalent, this is kept out of InterledgerNode itself so that
+// the conditionally-compiled code lives in as few places as possible.
+pub async fn serve_memory_node(node: InterledgerNode, ilp_address: Address) -> Result<(), ()> {
+    let store = MemoryStoreBuilder::new()
+        .node_ilp_address(ilp_address.clone())
+        .build();
+    node.chain_services(store, ilp_address).await
+}
@@ -14,7 +14,7 @@ cfg_if! {
         use tracing_futures::Instrument;
         use tracing::debug_span;
         use crate::instrumentation::{
-            metrics::{incoming_metrics, outgoing_metrics},
+            metrics::{circuit_breaker_metrics, incoming_metrics, outgoing_metrics},
             prometheus::{serve_prometheus, PrometheusConfig},
             trace::{trace_forwarding, trace_incoming, trace_outgoing},
         };
@@ -30,52 +30,74 @@ use bytes::Bytes;
 use futures::TryFutureExt;
 use hex::FromHex;
 use interledger::{
-    api::{NodeApi, NodeStore},
-    btp::{btp_service_as_filter, connect_client, BtpOutgoingService, BtpStore},
+    api::{ApiTokenStore, AuditLogStore, NodeApi, NodeStore},
+    btp::{
+        btp_service_as_filter, connect_client, reconnect_clients, BtpOutgoingService, BtpStore,
+        ReconnectOptions,
+    },
     ccp::{CcpRouteManagerBuilder, CcpRoutingAccount, CcpRoutingStore, RoutingRelation},
     errors::*,
-    http::{HttpClientService, HttpServer as IlpOverHttpServer, HttpStore},
+    http::{HttpClientService, HttpClientSettings, HttpServer as IlpOverHttpServer, HttpStore},
     ildcp::IldcpService,
     packet::Address,
     packet::{ErrorCode, RejectBuilder},
-    rates::{ExchangeRateFetcher, ExchangeRateStore},
-    router::{Router, RouterStore},
+    rates::{deserialize_optional_rate, deserialize_rate, ExchangeRateFetcher, ExchangeRateStore},
+    router::{Router, RouterStore, DEFAULT_FAILOVER_CODES},
     service::{
         outgoing_service_fn, Account as AccountTrait, AccountStore, AddressStore, OutgoingRequest,
         Username,
     },
     service_util::{
-        BalanceStore, EchoService, ExchangeRateService, ExpiryShortenerService,
-        MaxPacketAmountService, RateLimitService, RateLimitStore, ValidatorService,
+        BalanceStore, CaptureService, CaptureSettings, CircuitBreakerService,
+        CircuitBreakerSettings, EchoService, ExchangeRateService, ExpiryShortenerService,
+        FeeService, FeeStore, InFlightLimitService, LoadSheddingSettings, MaxPacketAmountService,
+        PacketCapture, RateLimitService, RateLimitStore, ValidatorService,
     },
     settlement::{
         api::{create_settlements_filter, SettlementMessageService},
         core::{
             idempotency::IdempotentStore,
-            types::{LeftoversStore, SettlementStore},
+            types::{LeftoversStore, SettlementOutboxStore, SettlementStore},
         },
     },
     store::account::Account,
-    stream::{StreamNotificationsStore, StreamReceiverService},
+    stream::{
+        PaymentHistoryStore, StreamConnectionStore, StreamNotificationsStore, StreamReceiverService,
+    },
 };
 use num_bigint::BigUint;
+use num_rational::BigRational;
+use num_traits::Zero;
 use once_cell::sync::Lazy;
 use serde::{de::Error as DeserializeError, Deserialize, Deserializer};
-use std::{convert::TryFrom, net::SocketAddr, str, str::FromStr, time::Duration};
+use std::{
+    collections::HashMap, convert::TryFrom, net::SocketAddr, str, str::FromStr, time::Duration,
+};
 use tokio::spawn;
 use tracing::{debug, error, info};
 use url::Url;
 use uuid::Uuid;
 use warp::{self, Filter};
 
+#[cfg(feature = "memory")]
+use crate::memory_store::*;
 #[cfg(feature = "redis")]
 use crate::redis_store::*;
+#[cfg(feature = "sql")]
+use crate::sql_store::*;
+use crate::tls::{load_peer_certificates, serve_tls, PeerCertificateConfig, TlsConfig};
 #[cfg(feature = "balance-tracking")]
 use interledger::service_util::BalanceService;
 
 #[doc(hidden)]
 pub use interledger::rates::ExchangeRateProvider;
 
+/// How often the idle STREAM connections are deleted, when they are tracked
+const STREAM_CONNECTIONS_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the state of the circuit breakers is reported to the metrics
+#[cfg(feature = "monitoring")]
+const CIRCUIT_BREAKER_METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
 static DEFAULT_ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("local.host").unwrap());
 
 fn default_settlement_api_bind_address() -> SocketAddr {
@@ -92,6 +114,8 @@ fn default_http_bind_address() -> SocketAddr {
 fn default_database_url() -> String {
     #[cfg(feature = "redis")]
     return default_redis_url();
+    #[cfg(feature = "memory")]
+    return default_memory_url();
     panic!("no backing store configured")
 }
 
@@ -133,32 +157,222 @@ where
     }
 }
 
-/// Configuration for calculating exchange rates between various pairs.
+fn deserialize_error_codes<'de, D>(deserializer: D) -> Result<Vec<ErrorCode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Lists can't be passed as command-line arguments or environment variables,
+    // so the codes may also be given as a comma-separated string
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Codes {
+        List(Vec<String>),
+        CommaSeparated(String),
+    }
+
+    let codes = match Codes::deserialize(deserializer)? {
+        Codes::List(codes) => codes,
+        Codes::CommaSeparated(codes) => codes
+            .split(',')
+            .map(|code| code.trim().to_string())
+            .filter(|code| !code.is_empty())
+            .collect(),
+    };
+    codes
+        .iter()
+        .map(|code| {
+            let bytes = code.as_bytes();
+            match bytes {
+                [class @ b'F', a, b] | [class @ b'T', a, b] | [class @ b'R', a, b]
+                    if a.is_ascii_digit() && b.is_ascii_digit() =>
+                {
+                    Ok(ErrorCode::new([*class, *a, *b]))
+                }
+                _ => Err(DeserializeError::custom(format!(
+                    "Invalid ILP error code: {:?}",
+                    code
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Configuration for forwarding packets to prefixes which have several next hops.
+#[derive(Deserialize, Clone)]
+pub struct RoutingConfig {
+    /// Reject codes which make the node retry a packet through the next route
+    /// for its destination, if there is one. Defaults to T01 and T04.
+    #[serde(
+        default = "RoutingConfig::default_failover_codes",
+        deserialize_with = "deserialize_error_codes"
+    )]
+    pub failover_codes: Vec<ErrorCode>,
+}
+
+impl RoutingConfig {
+    fn default_failover_codes() -> Vec<ErrorCode> {
+        DEFAULT_FAILOVER_CODES.to_vec()
+    }
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        RoutingConfig {
+            failover_codes: RoutingConfig::default_failover_codes(),
+        }
+    }
+}
+
+/// Configuration for the circuit breakers which stop forwarding packets to the accounts
+/// which failed too many packets in a row.
 #[derive(Deserialize, Clone, Default)]
+pub struct CircuitBreakerConfig {
+    /// Settings of the circuit breaker of every account. If this is not set,
+    /// only the accounts in `accounts` have a circuit breaker.
+    #[serde(default)]
+    pub default: Option<CircuitBreakerSettings>,
+    /// Settings of the circuit breakers of specific accounts, by username,
+    /// which override the default ones.
+    #[serde(default)]
+    pub accounts: HashMap<String, CircuitBreakerSettings>,
+}
+
+/// Configuration for the STREAM receiver which receives payments for the node's accounts.
+#[derive(Deserialize, Clone)]
+pub struct StreamConfig {
+    /// Keep each STREAM connection's total received and close state in the store,
+    /// so that the receive max of connections created via the API is enforced.
+    /// Defaults to false.
+    #[serde(default)]
+    pub track_connections: bool,
+    /// Time, defined in seconds, after which a tracked connection which has not received
+    /// any money is forgotten. Defaults to 86400s (24 hours).
+    #[serde(default = "StreamConfig::default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Save each packet received by the node's accounts in their payment history,
+    /// which is served at `GET /accounts/:username/payments`. Defaults to true.
+    #[serde(default = "StreamConfig::default_record_payments")]
+    pub record_payments: bool,
+}
+
+impl StreamConfig {
+    fn default_idle_timeout() -> u64 {
+        86_400
+    }
+
+    fn default_record_payments() -> bool {
+        true
+    }
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            track_connections: false,
+            idle_timeout: StreamConfig::default_idle_timeout(),
+            record_payments: StreamConfig::default_record_payments(),
+        }
+    }
+}
+
+/// Configuration for limiting the packets and amounts the accounts send to the node.
+#[derive(Deserialize, Clone)]
+pub struct RateLimitConfig {
+    /// Apply the accounts' per-minute limits with token buckets kept in the node's memory,
+    /// rather than with the store on every packet. The buckets are reconciled with the store
+    /// periodically, so that nodes sharing a store still share the limits approximately.
+    /// Defaults to false.
+    #[serde(default)]
+    pub local: bool,
+    /// Interval, defined in milliseconds, on which the local token buckets are reconciled
+    /// with the store. Defaults to 1000ms (1 second).
+    #[serde(default = "RateLimitConfig::default_reconcile_interval")]
+    pub reconcile_interval: u64,
+}
+
+impl RateLimitConfig {
+    fn default_reconcile_interval() -> u64 {
+        1000
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            local: false,
+            reconcile_interval: RateLimitConfig::default_reconcile_interval(),
+        }
+    }
+}
+
+/// Configuration for calculating exchange rates between various pairs.
+#[derive(Deserialize, Clone)]
 pub struct ExchangeRateConfig {
     /// Interval, defined in milliseconds, on which the node will poll the exchange rate provider.
     /// Defaults to 60000ms (60 seconds).
     #[serde(default = "ExchangeRateConfig::default_poll_interval")]
     pub poll_interval: u64,
-    /// The number of consecutive failed polls to the exchange rate provider
+    /// The number of consecutive failed polls to the exchange rate providers
     /// that the connector will tolerate before invalidating the exchange rate cache.
+    /// A poll only fails if none of the providers could be reached.
     #[serde(default = "ExchangeRateConfig::default_poll_failure_tolerance")]
     pub poll_failure_tolerance: u32,
     /// API to poll for exchange rates. Currently the supported options are:
     /// - [CoinCap](https://docs.coincap.io)
     /// - [CryptoCompare](https://cryptocompare.com) (note this requires an API key)
-    /// If this value is not set, the node will not poll for exchange rates and will
-    /// instead use the rates configured via the HTTP API.
+    /// - Json, any HTTP API which serves the rates in a JSON object
+    /// - File, a local JSON file which maps asset codes to rates
+    ///
+    /// If neither this value nor `providers` is set, the node will not poll for exchange
+    /// rates and will instead use the rates configured via the HTTP API.
     #[serde(default)]
     pub provider: Option<ExchangeRateProvider>,
+    /// More providers to poll along with `provider`. The rate of each asset
+    /// is the median of the rates the providers return for it.
+    #[serde(default)]
+    pub providers: Vec<ExchangeRateProvider>,
+    /// Maximum deviation, as a fraction of the median of an asset's rates, of the
+    /// rates which are aggregated. Rates which deviate more are rejected as outliers.
+    /// It may be a number, or a string with a decimal or a fraction.
+    #[serde(default, deserialize_with = "deserialize_optional_rate")]
+    pub max_deviation: Option<BigRational>,
+    /// Maximum age, in milliseconds, of the rate of an asset. The rate of an asset which
+    /// the providers stop returning is kept until it is older than this, and then removed,
+    /// so that packets in that asset are rejected. The rates of the assets without a max
+    /// age are replaced on every poll.
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// Maximum ages, in milliseconds, of the rates of particular assets, by asset code.
+    /// These take precedence over `max_age`.
+    #[serde(default)]
+    pub asset_max_ages: HashMap<String, u64>,
     /// Spread, as a fraction, to add on top of the exchange rate.
     /// This amount is kept as the node operator's profit, or may cover
     /// fluctuations in exchange rates.
     /// For example, take an incoming packet with an amount of 100. If the
     /// exchange rate is 1:2 and the spread is 0.01, the amount on the
     /// outgoing packet would be 198 (instead of 200 without the spread).
-    #[serde(default)]
-    pub spread: f64,
+    /// It may be a number, or a string with a decimal or a fraction, and is applied exactly.
+    #[serde(
+        default = "ExchangeRateConfig::default_spread",
+        deserialize_with = "deserialize_rate"
+    )]
+    pub spread: BigRational,
+}
+
+impl Default for ExchangeRateConfig {
+    fn default() -> Self {
+        ExchangeRateConfig {
+            poll_interval: ExchangeRateConfig::default_poll_interval(),
+            poll_failure_tolerance: ExchangeRateConfig::default_poll_failure_tolerance(),
+            provider: None,
+            providers: Vec::new(),
+            max_deviation: None,
+            max_age: None,
+            asset_max_ages: HashMap::new(),
+            spread: ExchangeRateConfig::default_spread(),
+        }
+    }
 }
 
 impl ExchangeRateConfig {
@@ -168,6 +382,9 @@ impl ExchangeRateConfig {
     fn default_poll_failure_tolerance() -> u32 {
         5
     }
+    fn default_spread() -> BigRational {
+        BigRational::zero()
+    }
 }
 
 /// An all-in-one Interledger node that includes sender and receiver functionality,
@@ -185,7 +402,9 @@ pub struct InterledgerNode {
     pub secret_seed: [u8; 32],
     /// HTTP Authorization token for the node admin (sent as a Bearer token)
     pub admin_auth_token: String,
-    /// Data store URI (for example, "redis://127.0.0.1:6379" or "redis+unix:/tmp/redis.sock")
+    /// Data store URI (for example, "redis://127.0.0.1:6379", "redis+unix:/tmp/redis.sock",
+    /// "postgres://localhost/interledger", "sqlite://node.db?mode=rwc"
+    /// or "memory://" for a non-persistent in-memory store)
     #[serde(
         default = "default_database_url",
         // temporary alias for backwards compatibility
@@ -196,9 +415,24 @@ pub struct InterledgerNode {
     /// This is used for both the API and ILP over HTTP packets
     #[serde(default = "default_http_bind_address")]
     pub http_bind_address: SocketAddr,
+    /// Serve HTTPS and WSS on `http_bind_address`, instead of plain HTTP.
+    /// If this configuration is not provided, the node serves plain HTTP.
+    #[serde(default)]
+    pub http_tls: Option<TlsConfig>,
     /// IP address and port to listen for the Settlement Engine API
     #[serde(default = "default_settlement_api_bind_address")]
     pub settlement_api_bind_address: SocketAddr,
+    /// Serve HTTPS on `settlement_api_bind_address`, instead of plain HTTP.
+    #[serde(default)]
+    pub settlement_api_tls: Option<TlsConfig>,
+    /// TLS client certificates presented to peers, by username, when connecting
+    /// to their ILP over HTTP or BTP URLs over HTTPS or WSS
+    #[serde(default)]
+    pub peer_certificates: HashMap<String, PeerCertificateConfig>,
+    /// Settings of the HTTP clients used to send ILP over HTTP requests to peers, by username.
+    /// Peers without settings share a client with the default settings
+    #[serde(default)]
+    pub peer_http_clients: HashMap<String, HttpClientSettings>,
     /// When SPSP payments are sent to the root domain, the payment pointer is resolved
     /// to <domain>/.well-known/pay. This value determines which account those payments
     /// will be sent to.
@@ -207,6 +441,26 @@ pub struct InterledgerNode {
     /// Interval, defined in milliseconds, on which the node will broadcast routing
     /// information to other nodes using CCP. Defaults to 30000ms (30 seconds).
     pub route_broadcast_interval: Option<u64>,
+    /// Configuration for forwarding packets to prefixes which have several next hops.
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    /// Configuration for the circuit breakers of the outgoing packets.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Configuration for shedding load across the connector when packets take too long to be forwarded.
+    /// If this configuration is not provided, no load is shed.
+    #[serde(default)]
+    pub load_shedding: Option<LoadSheddingSettings>,
+    /// Configuration for applying the accounts' rate limits.
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    /// Configuration for the file the incoming packets are captured to, once the capture
+    /// is started via the API. If this configuration is not provided, packets cannot be captured.
+    #[serde(default)]
+    pub packet_capture: Option<CaptureSettings>,
+    /// Configuration for the STREAM receiver.
+    #[serde(default)]
+    pub stream: StreamConfig,
     #[serde(default)]
     /// Configuration for calculating exchange rates between various pairs.
     pub exchange_rate: ExchangeRateConfig,
@@ -269,6 +523,10 @@ impl InterledgerNode {
         match database_url.scheme() {
             #[cfg(feature = "redis")]
             "redis" | "redis+unix" => serve_redis_node(self, ilp_address).await,
+            #[cfg(feature = "memory")]
+            "memory" => serve_memory_node(self, ilp_address).await,
+            #[cfg(feature = "sql")]
+            "postgres" | "postgresql" | "sqlite" => serve_sql_node(self, ilp_address).await,
             other => {
                 error!("unsupported data source scheme: {}", other);
                 Err(())
@@ -284,11 +542,17 @@ impl InterledgerNode {
             + BtpStore<Account = Account>
             + HttpStore<Account = Account>
             + StreamNotificationsStore<Account = Account>
+            + StreamConnectionStore
+            + PaymentHistoryStore
+            + ApiTokenStore
+            + AuditLogStore
             + BalanceStore
             + SettlementStore<Account = Account>
             + ExchangeRateStore
+            + FeeStore
             + BalanceStore
             + SettlementStore<Account = Account>
+            + SettlementOutboxStore
             + RouterStore<Account = Account>
             + CcpRoutingStore<Account = Account>
             + RateLimitStore<Account = Account>
@@ -307,16 +571,45 @@ impl InterledgerNode {
 
         let secret_seed = Bytes::from(&self.secret_seed[..]);
         let http_bind_address = self.http_bind_address;
+        let http_tls = self.http_tls.clone();
         let settlement_api_bind_address = self.settlement_api_bind_address;
+        let settlement_api_tls = self.settlement_api_tls.clone();
+        let peer_certificates = load_peer_certificates(&self.peer_certificates)?;
         let ilp_address_clone = ilp_address.clone();
         let ilp_address_clone2 = ilp_address.clone();
         let admin_auth_token = self.admin_auth_token.clone();
         let default_spsp_account = self.default_spsp_account.clone();
         let route_broadcast_interval = self.route_broadcast_interval;
-        let exchange_rate_provider = self.exchange_rate.provider.clone();
+        let failover_codes = self.routing.failover_codes.clone();
+        let circuit_breaker = self.circuit_breaker.clone();
+        let load_shedding = self.load_shedding.clone();
+        let rate_limits = self.rate_limits.clone();
+        let packet_capture = self
+            .packet_capture
+            .clone()
+            .map(PacketCapture::new)
+            .unwrap_or_default();
+        let stream_track_connections = self.stream.track_connections;
+        let stream_record_payments = self.stream.record_payments;
+        let stream_idle_timeout = Duration::from_secs(self.stream.idle_timeout);
+        let exchange_rate_providers: Vec<_> = self
+            .exchange_rate
+            .provider
+            .iter()
+            .chain(self.exchange_rate.providers.iter())
+            .cloned()
+            .collect();
+        let exchange_rate_max_deviation = self.exchange_rate.max_deviation.clone();
+        let exchange_rate_max_age = self.exchange_rate.max_age;
+        let exchange_rate_asset_max_ages: HashMap<String, Duration> = self
+            .exchange_rate
+            .asset_max_ages
+            .iter()
+            .map(|(asset_code, max_age)| (asset_code.clone(), Duration::from_millis(*max_age)))
+            .collect();
         let exchange_rate_poll_interval = self.exchange_rate.poll_interval;
         let exchange_rate_poll_failure_tolerance = self.exchange_rate.poll_failure_tolerance;
-        let exchange_rate_spread = self.exchange_rate.spread;
+        let exchange_rate_spread = self.exchange_rate.spread.clone();
         #[cfg(feature = "google-pubsub")]
         let google_pubsub = self.google_pubsub.clone();
 
@@ -350,15 +643,22 @@ impl InterledgerNode {
 
         // Connect to all of the accounts that have outgoing ilp_over_btp_urls configured
         // but don't fail if we are unable to connect
-        // TODO try reconnecting to those accounts later
         let btp_client_service = connect_client(
             ilp_address_clone2.clone(),
             btp_accounts,
             false,
             outgoing_service,
+            peer_certificates.clone(),
         )
         .map_err(|err| error!("{}", err))
         .await?;
+        // Retry the accounts we could not connect to (or whose connections drop) in the
+        // background, and pick up accounts that are added or changed through the API
+        tokio::spawn(reconnect_clients(
+            store.clone(),
+            btp_client_service.clone(),
+            ReconnectOptions::default(),
+        ));
         let btp_server_service =
             BtpOutgoingService::new(ilp_address_clone2, btp_client_service.clone());
         let btp_server_service_clone = btp_server_service.clone();
@@ -367,7 +667,45 @@ impl InterledgerNode {
         // The BTP service is both an Incoming and Outgoing one so we pass it first as the Outgoing
         // service to others like the router and then call handle_incoming on it to set up the incoming handler
         let outgoing_service = btp_server_service.clone();
-        let outgoing_service = HttpClientService::new(store.clone(), outgoing_service);
+        let mut outgoing_service = HttpClientService::new(store.clone(), outgoing_service);
+        for (username, certificate) in peer_certificates {
+            outgoing_service
+                .client_certificate(username.clone(), &certificate)
+                .map_err(|err| {
+                    error!(target: "interledger-node", "Invalid client certificate for account {}: {}", username, err)
+                })?;
+        }
+        for (username, settings) in self.peer_http_clients.iter() {
+            let username = Username::from_str(username).map_err(|err| {
+                error!(target: "interledger-node", "Invalid username {} for HTTP client settings: {}", username, err)
+            })?;
+            outgoing_service
+                .client_settings(username.clone(), settings.clone())
+                .map_err(|err| {
+                    error!(target: "interledger-node", "Invalid HTTP client settings for account {}: {}", username, err)
+                })?;
+        }
+        // Reject the packets to peers which are down right away, rather than waiting for them to time out
+        let mut outgoing_service =
+            CircuitBreakerService::new(store.clone(), circuit_breaker.default, outgoing_service);
+        for (username, settings) in circuit_breaker.accounts {
+            let username = Username::from_str(&username).map_err(|err| {
+                error!(target: "interledger-node", "Invalid username {} for circuit breaker settings: {}", username, err)
+            })?;
+            outgoing_service.account_settings(username, settings);
+        }
+        let circuit_breakers = outgoing_service.circuit_breakers();
+        #[cfg(feature = "monitoring")]
+        tokio::spawn({
+            let circuit_breakers = circuit_breakers.clone();
+            async move {
+                let mut interval = tokio::time::interval(CIRCUIT_BREAKER_METRICS_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    circuit_breaker_metrics(&circuit_breakers);
+                }
+            }
+        });
 
         #[cfg(feature = "monitoring")]
         let outgoing_service = outgoing_service.wrap(outgoing_metrics);
@@ -376,12 +714,27 @@ impl InterledgerNode {
         // is shortened before we check whether there is enough time left
         let outgoing_service = ValidatorService::outgoing(store.clone(), outgoing_service);
         let outgoing_service = ExpiryShortenerService::new(outgoing_service);
-        let outgoing_service =
+        let mut outgoing_service =
             StreamReceiverService::new(secret_seed.clone(), store.clone(), outgoing_service);
+        outgoing_service.track_connections(stream_track_connections);
+        outgoing_service.record_payments(stream_record_payments);
         #[cfg(feature = "balance-tracking")]
         let outgoing_service = BalanceService::new(store.clone(), outgoing_service);
+        // Keep retrying the outgoing settlements which were interrupted, e.g. by a crash,
+        // or which could not reach the settlement engine
+        #[cfg(feature = "balance-tracking")]
+        tokio::spawn({
+            let balance_service = outgoing_service.clone();
+            balance_service.retry_pending_settlements()
+        });
+        // Cap the packets in flight to and from each account. The exchange rate service comes after this
+        // one so that the amounts of the packets sent to the outgoing accounts are in their assets
+        let outgoing_service =
+            InFlightLimitService::new(store.clone(), load_shedding, outgoing_service);
         let outgoing_service =
             ExchangeRateService::new(exchange_rate_spread, store.clone(), outgoing_service);
+        // Fees are charged in the incoming account's asset, before the amount is converted
+        let outgoing_service = FeeService::new(store.clone(), outgoing_service);
 
         #[cfg(feature = "google-pubsub")]
         let outgoing_service = outgoing_service.wrap(create_google_pubsub_wrapper(google_pubsub));
@@ -398,7 +751,9 @@ impl InterledgerNode {
         }
 
         // Set up the Router and Routing Manager
-        let incoming_service = Router::new(store.clone(), outgoing_service_fwd);
+        let mut router = Router::new(store.clone(), outgoing_service_fwd);
+        router.failover_codes(failover_codes);
+        let incoming_service = router;
 
         // Add tracing to track the outgoing request details
         #[cfg(feature = "monitoring")]
@@ -421,7 +776,21 @@ impl InterledgerNode {
         let incoming_service = IldcpService::new(incoming_service);
         let incoming_service = MaxPacketAmountService::new(store.clone(), incoming_service);
         let incoming_service = ValidatorService::incoming(store.clone(), incoming_service);
-        let incoming_service = RateLimitService::new(store.clone(), incoming_service);
+        let mut incoming_service = RateLimitService::new(store.clone(), incoming_service);
+        incoming_service.local_limits(rate_limits.local);
+        // Share the packets and amounts let through by the local token buckets with the other nodes
+        if rate_limits.local {
+            let rate_limit_service = incoming_service.clone();
+            let reconcile_interval = Duration::from_millis(rate_limits.reconcile_interval);
+            spawn(async move {
+                let mut interval = tokio::time::interval(reconcile_interval);
+                loop {
+                    interval.tick().await;
+                    rate_limit_service.reconcile_local_limits().await;
+                }
+            });
+        }
+        let incoming_service = CaptureService::new(packet_capture.clone(), incoming_service);
 
         // Add tracing to track the incoming request details
         #[cfg(feature = "monitoring")]
@@ -480,6 +849,8 @@ impl InterledgerNode {
             api.default_spsp_account(username);
         }
         api.node_version(env!("CARGO_PKG_VERSION").to_string());
+        api.circuit_breakers(circuit_breakers);
+        api.packet_capture(packet_capture);
 
         cfg_if! {
             if #[cfg(feature = "monitoring")] {
@@ -563,21 +934,57 @@ impl InterledgerNode {
             .with(warp::log("interledger-api"))
             .boxed();
 
-        info!(target: "interledger-node", "Interledger.rs node HTTP API listening on: {}", http_bind_address);
-        spawn(warp::serve(api).bind(http_bind_address));
+        if let Some(tls) = http_tls {
+            serve_tls(api, http_bind_address, tls).await?;
+            info!(target: "interledger-node", "Interledger.rs node HTTPS API listening on: {}", http_bind_address);
+        } else {
+            info!(target: "interledger-node", "Interledger.rs node HTTP API listening on: {}", http_bind_address);
+            spawn(warp::serve(api).bind(http_bind_address));
+        }
 
         // Settlement API
         let settlement_api = create_settlements_filter(store.clone(), outgoing_service.clone());
-        info!(target: "interledger-node", "Settlement API listening on: {}", settlement_api_bind_address);
-        spawn(warp::serve(settlement_api).bind(settlement_api_bind_address));
+        if let Some(tls) = settlement_api_tls {
+            serve_tls(settlement_api, settlement_api_bind_address, tls).await?;
+            info!(target: "interledger-node", "Settlement API listening over HTTPS on: {}", settlement_api_bind_address);
+        } else {
+            info!(target: "interledger-node", "Settlement API listening on: {}", settlement_api_bind_address);
+            spawn(warp::serve(settlement_api).bind(settlement_api_bind_address));
+        }
+
+        // Forget the STREAM connections which stopped receiving money
+        if stream_track_connections {
+            let store = store.clone();
+            spawn(async move {
+                let mut interval = tokio::time::interval(STREAM_CONNECTIONS_CLEANUP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if let Err(err) = store
+                        .delete_idle_stream_connections(stream_idle_timeout)
+                        .await
+                    {
+                        error!(target: "interledger-node", "Error deleting idle STREAM connections: {}", err);
+                    }
+                }
+            });
+        }
 
         // Exchange Rate Polling
-        if let Some(provider) = exchange_rate_provider {
-            let exchange_rate_fetcher = ExchangeRateFetcher::new(
-                provider,
+        if !exchange_rate_providers.is_empty() {
+            let mut exchange_rate_fetcher = ExchangeRateFetcher::new(
+                exchange_rate_providers,
                 exchange_rate_poll_failure_tolerance,
                 store.clone(),
             );
+            if let Some(max_deviation) = exchange_rate_max_deviation {
+                exchange_rate_fetcher = exchange_rate_fetcher.max_deviation(max_deviation);
+            }
+            if let Some(max_age) = exchange_rate_max_age {
+                exchange_rate_fetcher =
+                    exchange_rate_fetcher.max_age(Duration::from_millis(max_age));
+            }
+            exchange_rate_fetcher =
+                exchange_rate_fetcher.asset_max_ages(exchange_rate_asset_max_ages);
             exchange_rate_fetcher
                 .spawn_interval(Duration::from_millis(exchange_rate_poll_interval));
         } else {
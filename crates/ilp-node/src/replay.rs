@@ -0,0 +1,244 @@
+#![cfg(feature = "memory")]
+
+//! Replays the packets captured by a node offline, through the services of a node built
+//! from a description of its accounts, routes, exchange rates and fees, over a memory store.
+//! This shows how a change to these would route and convert the captured packets, without
+//! touching a live node or reaching any peer.
+
+use interledger::{
+    api::{AccountDetails, NodeStore},
+    packet::{Address, ErrorCode, RejectBuilder},
+    rates::{deserialize_rate, rates_map, ExchangeRateStore},
+    router::Router,
+    service::{outgoing_service_fn, Account as AccountTrait, IlpResult, OutgoingRequest, Username},
+    service_util::{
+        replay, CaptureRecord, ExchangeRateService, ExpiryShortenerService, FeeSchedules,
+        FeeService, FeeStore, MaxPacketAmountService, ReplayOutcome, ValidatorService,
+    },
+    store::{account::Account, memory::MemoryStoreBuilder},
+};
+use num_rational::BigRational;
+use num_traits::Zero;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+/// The node to replay the captured packets through
+#[derive(Debug, Deserialize)]
+pub struct ReplayConfig {
+    /// The ILP address of the node
+    pub ilp_address: Address,
+    /// The accounts of the node, in the format of `POST /accounts`. The captured packets
+    /// are sent from the accounts with the same usernames as the ones they were captured from
+    #[serde(default)]
+    pub accounts: Vec<AccountDetails>,
+    /// Static routes, from ILP address prefixes to usernames
+    #[serde(default)]
+    pub routes: HashMap<String, Username>,
+    /// Exchange rates by asset code, as numbers or as strings with a decimal or a fraction
+    #[serde(default, deserialize_with = "rates_map::deserialize")]
+    pub exchange_rates: HashMap<String, BigRational>,
+    /// Spread charged on the packets exchanged between assets
+    #[serde(default = "BigRational::zero", deserialize_with = "deserialize_rate")]
+    pub spread: BigRational,
+    /// Fee schedules, in the format of `PUT /fees`
+    #[serde(default)]
+    pub fees: FeeSchedules,
+}
+
+/// A captured packet and how it fared when it was replayed
+#[derive(Clone, Debug)]
+pub struct ReplayedPacket {
+    pub outcome: ReplayOutcome,
+    /// The account the packet was forwarded to, and the amount it was forwarded with
+    pub forwarded: Option<(Username, u64)>,
+}
+
+/// Where each packet was forwarded to, by the execution condition of the packet
+type Forwarded = Arc<Mutex<HashMap<Vec<u8>, (Username, u64)>>>;
+
+/// Replays the records through the incoming and outgoing services of the node which route,
+/// validate, convert and charge fees on the packets, and returns the response each packet got.
+/// Instead of reaching the peers, the packets which are forwarded get the response their captured
+/// packet got, so the responses only differ where the node handles the packets differently
+pub async fn replay_capture(
+    config: ReplayConfig,
+    records: Vec<CaptureRecord>,
+) -> Result<Vec<ReplayedPacket>, String> {
+    let store = MemoryStoreBuilder::new()
+        .node_ilp_address(config.ilp_address.clone())
+        .build();
+
+    let mut account_ids: HashMap<Username, Uuid> = HashMap::new();
+    for details in config.accounts {
+        let username = details.username.clone();
+        let account = store
+            .insert_account(details)
+            .await
+            .map_err(|err| format!("Invalid account {}: {}", username, err))?;
+        account_ids.insert(username, account.id());
+    }
+    let mut routes = HashMap::new();
+    for (prefix, username) in config.routes {
+        let account_id = account_ids
+            .get(&username)
+            .ok_or_else(|| format!("The route {} is to an unknown account {}", prefix, username))?;
+        routes.insert(prefix, *account_id);
+    }
+    store
+        .set_static_routes(routes)
+        .await
+        .map_err(|err| format!("Invalid routes: {}", err))?;
+    store
+        .set_exchange_rates(config.exchange_rates)
+        .map_err(|err| format!("Invalid exchange rates: {}", err))?;
+    store
+        .set_fee_schedules(config.fees)
+        .await
+        .map_err(|err| format!("Invalid fees: {}", err))?;
+
+    // Packets are told apart by their condition
+    let captured: HashMap<Vec<u8>, IlpResult> = records
+        .iter()
+        .map(|record| {
+            (
+                record.prepare.execution_condition().to_vec(),
+                record.result.clone(),
+            )
+        })
+        .collect();
+    let forwarded: Forwarded = Arc::default();
+    let ilp_address = config.ilp_address;
+    let outgoing_service = {
+        let forwarded = forwarded.clone();
+        outgoing_service_fn(move |request: OutgoingRequest<Account>| {
+            let condition = request.prepare.execution_condition().to_vec();
+            let result = captured.get(&condition).cloned().unwrap_or_else(|| {
+                Err(RejectBuilder {
+                    code: ErrorCode::T00_INTERNAL_ERROR,
+                    message: b"No captured response for this packet",
+                    triggered_by: Some(&ilp_address),
+                    data: &[],
+                }
+                .build())
+            });
+            forwarded.lock().unwrap().insert(
+                condition,
+                (request.to.username().clone(), request.prepare.amount()),
+            );
+            result
+        })
+    };
+
+    // The same services as the node's, in the same order, besides the ones which
+    // keep state such as balances, limits and connections
+    let outgoing_service = ValidatorService::outgoing(store.clone(), outgoing_service);
+    let outgoing_service = ExpiryShortenerService::new(outgoing_service);
+    let outgoing_service = ExchangeRateService::new(config.spread, store.clone(), outgoing_service);
+    let outgoing_service = FeeService::new(store.clone(), outgoing_service);
+    let incoming_service = Router::new(store.clone(), outgoing_service);
+    let incoming_service = MaxPacketAmountService::new(store.clone(), incoming_service);
+    let incoming_service = ValidatorService::incoming(store.clone(), incoming_service);
+
+    let outcomes = replay(&store, incoming_service, records)
+        .await
+        .map_err(|err| format!("Error replaying the packets: {}", err))?;
+    let mut forwarded = forwarded.lock().unwrap();
+    Ok(outcomes
+        .into_iter()
+        .map(|outcome| ReplayedPacket {
+            forwarded: forwarded.remove(outcome.record.prepare.execution_condition()),
+            outcome,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger::packet::{FulfillBuilder, PrepareBuilder};
+    use ring::digest::{digest, SHA256};
+    use serde_json::json;
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+
+    fn record(destination: &str, amount: u64, fulfilled: bool) -> CaptureRecord {
+        let fulfillment = [amount as u8; 32];
+        let mut condition = [0; 32];
+        condition.copy_from_slice(digest(&SHA256, &fulfillment).as_ref());
+        CaptureRecord {
+            account_id: Uuid::new_v4(),
+            username: Username::from_str("alice").unwrap(),
+            prepared_at: SystemTime::now(),
+            round_trip_time: Duration::from_millis(10),
+            prepare: PrepareBuilder {
+                destination: Address::from_str(destination).unwrap(),
+                amount,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &condition,
+                data: &[],
+            }
+            .build(),
+            result: if fulfilled {
+                Ok(FulfillBuilder {
+                    fulfillment: &fulfillment,
+                    data: &[],
+                }
+                .build())
+            } else {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    triggered_by: Some(&Address::from_str("example.node").unwrap()),
+                    data: &[],
+                }
+                .build())
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_captured_packets() {
+        let config: ReplayConfig = serde_json::from_str(
+            &json!({
+            "ilp_address": "example.node",
+            "accounts": [
+                { "username": "alice", "asset_code": "ABC", "asset_scale": 2, "max_packet_amount": 100 },
+                { "username": "bob", "asset_code": "XYZ", "asset_scale": 2 },
+            ],
+            "routes": { "example.bob": "bob" },
+            "exchange_rates": { "ABC": 1, "XYZ": "2" },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let packets = replay_capture(
+            config,
+            vec![
+                record("example.bob.one", 10, true),
+                record("example.unknown", 20, false),
+                // The packet is now too large for alice
+                record("example.bob.two", 200, true),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert!(packets[0].outcome.matches());
+        assert_eq!(
+            packets[0].forwarded,
+            Some((Username::from_str("bob").unwrap(), 5))
+        );
+        assert!(packets[1].outcome.matches());
+        assert_eq!(packets[1].forwarded, None);
+        assert!(!packets[2].outcome.matches());
+        assert_eq!(
+            packets[2].outcome.result.as_ref().unwrap_err().code(),
+            ErrorCode::F08_AMOUNT_TOO_LARGE
+        );
+        assert_eq!(packets[2].forwarded, None);
+    }
+}
@@ -0,0 +1,32 @@
+#![cfg(feature = "sql")]
+
+use crate::node::InterledgerNode;
+use futures::TryFutureExt;
+pub use interledger::{packet::Address, store::sql::SqlStoreBuilder};
+use ring::hmac;
+use tracing::error;
+
+static SQL_SECRET_GENERATION_STRING: &str = "ilp_sql_secret";
+
+// Like the Redis equivalent, this is kept out of InterledgerNode itself so that
+// the conditionally-compiled code lives in as few places as possible.
+pub async fn serve_sql_node(node: InterledgerNode, ilp_address: Address) -> Result<(), ()> {
+    let database_url = node.database_url.clone();
+    let sql_secret = generate_sql_secret(&node.secret_seed);
+    let store = SqlStoreBuilder::new(database_url, sql_secret)
+        .node_ilp_address(ilp_address.clone())
+        .connect()
+        .map_err(move |err| error!(target: "interledger-node", "Error connecting to the SQL database: {:?}", err))
+        .await?;
+    node.chain_services(store, ilp_address).await
+}
+
+pub fn generate_sql_secret(secret_seed: &[u8; 32]) -> [u8; 32] {
+    let mut sql_secret: [u8; 32] = [0; 32];
+    let sig = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret_seed),
+        SQL_SECRET_GENERATION_STRING.as_bytes(),
+    );
+    sql_secret.copy_from_slice(sig.as_ref());
+    sql_secret
+}
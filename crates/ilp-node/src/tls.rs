@@ -0,0 +1,303 @@
+use hyper::{server::conn::Http, service::service_fn, Body, Request};
+use interledger::{
+    http::ClientCertificateUsername,
+    service::{ClientCertificate, Username},
+};
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{net::TcpListener, spawn};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+        AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+        RootCertStore, ServerConfig, Session,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tower_service::Service;
+use tracing::{debug, error, info, warn};
+use warp::{filters::BoxedFilter, Reply};
+
+/// Configuration for serving HTTPS and WSS, instead of plain HTTP, on one of the node's listeners.
+#[derive(Deserialize, Clone)]
+pub struct TlsConfig {
+    /// Path of the PEM file with the server's certificate chain
+    pub cert_path: PathBuf,
+    /// Path of the PEM file with the server's private key, in PKCS #8 or RSA format
+    pub key_path: PathBuf,
+    /// Path of the PEM file with the certificate authorities which issue the clients'
+    /// certificates. If it is set, the certificates presented by clients are verified.
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+    /// Reject the connections of clients which do not present a certificate issued by
+    /// one of the `client_ca_path` authorities. Defaults to false, in which case clients
+    /// without a certificate may still use tokens.
+    #[serde(default)]
+    pub require_client_certificate: bool,
+    /// Client certificates, by their hex-encoded SHA-256 fingerprint, mapped to the username
+    /// of the account they authenticate. ILP over HTTP requests made with one of them
+    /// need no token.
+    #[serde(default)]
+    pub client_certificate_accounts: HashMap<String, String>,
+    /// Interval, defined in milliseconds, on which the certificate, key and authority files
+    /// are checked for changes, and reloaded if they changed. Defaults to 60000ms (60 seconds).
+    #[serde(default = "TlsConfig::default_reload_interval")]
+    pub reload_interval: u64,
+}
+
+impl TlsConfig {
+    fn default_reload_interval() -> u64 {
+        60_000
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.cert_path)
+            .chain(std::iter::once(&self.key_path))
+            .chain(self.client_ca_path.iter())
+    }
+}
+
+/// A TLS client certificate presented to a peer when connecting to it over HTTPS or WSS.
+#[derive(Deserialize, Clone)]
+pub struct PeerCertificateConfig {
+    /// Path of the DER-encoded PKCS #12 archive with the certificate chain and its private key
+    pub pkcs12_path: PathBuf,
+    /// Password the archive is encrypted with. Defaults to an empty password.
+    #[serde(default)]
+    pub password: String,
+}
+
+/// Loads the client certificates presented to the peers, indexed by their usernames
+pub fn load_peer_certificates(
+    configs: &HashMap<String, PeerCertificateConfig>,
+) -> Result<HashMap<Username, ClientCertificate>, ()> {
+    configs
+        .iter()
+        .map(|(username, config)| {
+            let username = Username::from_str(username).map_err(|err| {
+                error!(target: "interledger-node", "Invalid username {} for a peer certificate: {}", username, err)
+            })?;
+            let pkcs12 = fs::read(&config.pkcs12_path).map_err(|err| {
+                error!(target: "interledger-node",
+                    "Error reading the client certificate of account {} from {}: {}",
+                    username,
+                    config.pkcs12_path.display(),
+                    err
+                )
+            })?;
+            let certificate = ClientCertificate {
+                pkcs12,
+                password: config.password.clone(),
+            };
+            Ok((username, certificate))
+        })
+        .collect()
+}
+
+/// Lowercase hex without separators, so that fingerprints copied
+/// from e.g. `openssl x509 -fingerprint -sha256` also match
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_lowercase()
+}
+
+fn read_pem<T>(
+    path: &Path,
+    parse: fn(&mut dyn std::io::BufRead) -> Result<Vec<T>, ()>,
+) -> Result<Vec<T>, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    parse(&mut BufReader::new(file)).map_err(|_| format!("{}: invalid PEM file", path.display()))
+}
+
+/// Builds the rustls configuration from the files the config points to
+fn load_server_config(config: &TlsConfig) -> Result<ServerConfig, String> {
+    let cert_chain = read_pem(&config.cert_path, certs)?;
+    if cert_chain.is_empty() {
+        return Err(format!(
+            "{}: no certificates found",
+            config.cert_path.display()
+        ));
+    }
+    let mut keys = read_pem(&config.key_path, pkcs8_private_keys)?;
+    if keys.is_empty() {
+        keys = read_pem(&config.key_path, rsa_private_keys)?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("{}: no private key found", config.key_path.display()))?;
+
+    let verifier = match config.client_ca_path {
+        Some(ref path) => {
+            let mut roots = RootCertStore::empty();
+            let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            match roots.add_pem_file(&mut BufReader::new(file)) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => return Err(format!("{}: no valid certificates found", path.display())),
+            }
+            if config.require_client_certificate {
+                AllowAnyAuthenticatedClient::new(roots)
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+            }
+        }
+        None if config.require_client_certificate => {
+            return Err("client_ca_path is needed to require client certificates".to_string())
+        }
+        None => NoClientAuth::new(),
+    };
+
+    let mut server_config = ServerConfig::new(verifier);
+    server_config
+        .set_single_cert(cert_chain, key)
+        .map_err(|err| format!("{}: {}", config.key_path.display(), err))?;
+    // WebSocket upgrades (for BTP) need HTTP/1.1
+    server_config.set_protocols(&[b"http/1.1".to_vec()]);
+    Ok(server_config)
+}
+
+/// When the most recently changed of the config's files was modified
+fn last_modified(config: &TlsConfig) -> Option<SystemTime> {
+    config
+        .paths()
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// Accepts TLS connections with the current certificates, which are
+/// swapped when their files change
+#[derive(Clone)]
+struct ReloadingAcceptor {
+    config: TlsConfig,
+    server_config: Arc<RwLock<Arc<ServerConfig>>>,
+    /// Usernames of the accounts the client certificates authenticate, by fingerprint
+    accounts: Arc<HashMap<String, Username>>,
+}
+
+impl ReloadingAcceptor {
+    fn new(config: TlsConfig) -> Result<Self, String> {
+        let server_config = load_server_config(&config)?;
+        let accounts = config
+            .client_certificate_accounts
+            .iter()
+            .map(|(fingerprint, username)| {
+                Username::from_str(username)
+                    .map(|username| (normalize_fingerprint(fingerprint), username))
+                    .map_err(|err| format!("Invalid username {}: {}", username, err))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(ReloadingAcceptor {
+            config,
+            server_config: Arc::new(RwLock::new(Arc::new(server_config))),
+            accounts: Arc::new(accounts),
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.server_config.read().unwrap().clone())
+    }
+
+    /// The username of the account the connection's client certificate authenticates, if any
+    fn client_username<IO>(&self, stream: &TlsStream<IO>) -> Option<Username> {
+        let certificates = stream.get_ref().1.get_peer_certificates()?;
+        let fingerprint = hex::encode(digest(&SHA256, &certificates.first()?.0));
+        self.accounts.get(&fingerprint).cloned()
+    }
+
+    /// Reloads the certificates whenever their files change. Files which cannot be loaded
+    /// are reported, and the previous certificates are kept until they are fixed
+    async fn watch(self) {
+        let mut loaded_at = last_modified(&self.config);
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.reload_interval));
+        loop {
+            interval.tick().await;
+            let modified_at = last_modified(&self.config);
+            if modified_at == loaded_at {
+                continue;
+            }
+            match load_server_config(&self.config) {
+                Ok(server_config) => {
+                    *self.server_config.write().unwrap() = Arc::new(server_config);
+                    info!(target: "interledger-node", "Reloaded TLS certificate from {}", self.config.cert_path.display());
+                }
+                Err(err) => {
+                    warn!(target: "interledger-node", "Keeping the previous TLS certificate, error reloading it: {}", err)
+                }
+            }
+            loaded_at = modified_at;
+        }
+    }
+}
+
+/// Serves the filter over HTTPS on the address, in the background.
+/// Fails if the certificates cannot be loaded or the address cannot be bound.
+///
+/// Requests made over connections whose client certificate is mapped to an account
+/// carry the account's username in their `ClientCertificateUsername` extension.
+pub async fn serve_tls<T>(
+    filter: BoxedFilter<(T,)>,
+    addr: SocketAddr,
+    config: TlsConfig,
+) -> Result<(), ()>
+where
+    T: Reply + Send + 'static,
+{
+    let tls = ReloadingAcceptor::new(config).map_err(|err| {
+        error!(target: "interledger-node", "Error loading the TLS configuration for {}: {}", addr, err)
+    })?;
+    let mut listener = TcpListener::bind(addr)
+        .await
+        .map_err(|err| error!(target: "interledger-node", "Error binding to {}: {}", addr, err))?;
+    spawn(tls.clone().watch());
+
+    spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!(target: "interledger-node", "Error accepting a connection on {}: {}", addr, err);
+                    continue;
+                }
+            };
+            let tls = tls.clone();
+            let service = warp::service(filter.clone());
+            spawn(async move {
+                let stream = match tls.acceptor().accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        debug!(target: "interledger-node", "TLS handshake failed: {}", err);
+                        return;
+                    }
+                };
+                let username = tls.client_username(&stream);
+                let service = service_fn(move |mut request: Request<Body>| {
+                    if let Some(ref username) = username {
+                        request
+                            .extensions_mut()
+                            .insert(ClientCertificateUsername(username.clone()));
+                    }
+                    service.clone().call(request)
+                });
+                if let Err(err) = Http::new()
+                    .serve_connection(stream, service)
+                    .with_upgrades()
+                    .await
+                {
+                    debug!(target: "interledger-node", "Error serving a TLS connection: {}", err);
+                }
+            });
+        }
+    });
+    Ok(())
+}
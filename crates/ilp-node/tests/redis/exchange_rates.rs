@@ -106,3 +106,58 @@ async fn cryptocompare() {
     assert!(obj.get("ETH").is_some());
     assert!(obj.get("XRP").is_some());
 }
+
+#[tokio::test]
+async fn aggregates_file_providers() {
+    let context = TestContext::new();
+
+    let http_port = get_open_port(None);
+
+    let mut paths = Vec::new();
+    for (i, rates) in [
+        json!({ "EUR": "1.1", "BTC": 9000 }),
+        json!({ "EUR": "1.2", "BTC": 9100 }),
+        json!({ "EUR": "5" }),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let path = env::temp_dir().join(format!("ilp-node-rates-{}-{}.json", http_port, i));
+        std::fs::write(&path, rates.to_string()).unwrap();
+        paths.push(path);
+    }
+
+    let node: InterledgerNode = serde_json::from_value(json!({
+        "ilp_address": "example.one",
+        "default_spsp_account": "one",
+        "admin_auth_token": "admin",
+        "database_url": connection_info_to_string(context.get_client_connection_info()),
+        "http_bind_address": format!("127.0.0.1:{}", http_port),
+        "settlement_api_bind_address": format!("127.0.0.1:{}", get_open_port(None)),
+        "secret_seed": random_secret(),
+        "route_broadcast_interval": 200,
+        "exchange_rate": {
+            "poll_interval": 100,
+            "provider": { "file": paths[0] },
+            "providers": [{ "file": paths[1] }, { "file": paths[2] }],
+            "max_deviation": "0.1",
+            "max_age": 60000,
+        },
+    }))
+    .unwrap();
+    node.serve().await.unwrap();
+
+    // Wait so our node can poll the files
+    tokio::time::delay_for(Duration::from_millis(500)).await;
+
+    let ret = Client::new()
+        .get(&format!("http://localhost:{}/rates", http_port))
+        .send()
+        .await
+        .unwrap();
+    let txt = ret.text().await.unwrap();
+    let obj: Value = serde_json::from_str(&txt).unwrap();
+
+    // The EUR rate of 5 is rejected as an outlier
+    assert_eq!(obj, json!({ "USD": 1.0, "EUR": 1.15, "BTC": 9050.0 }));
+}
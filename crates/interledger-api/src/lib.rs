@@ -6,13 +6,14 @@ use interledger_errors::NodeStoreError;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, RouterStore};
 use interledger_service::{
     Account, AccountStore, AddressStore, IncomingService, OutgoingService, Username,
 };
-use interledger_service_util::BalanceStore;
+use interledger_service_util::{BalanceStore, CircuitBreakers, FeeStore, PacketCapture};
 use interledger_settlement::core::types::{SettlementAccount, SettlementStore};
-use interledger_stream::StreamNotificationsStore;
+use interledger_stream::{PaymentHistoryStore, StreamConnectionStore, StreamNotificationsStore};
+use num_rational::BigRational;
 use secrecy::SecretString;
 use serde::{de, Deserialize, Serialize};
 use std::{boxed::*, collections::HashMap, fmt::Display, net::SocketAddr, str::FromStr};
@@ -20,7 +21,19 @@ use url::Url;
 use uuid::Uuid;
 use warp::{self, Filter};
 
+/// Append-only log of the administrative actions taken through the API
+mod audit;
 mod routes;
+/// Scoped API tokens issued by the node's administrator
+mod tokens;
+
+pub use audit::{
+    redact_secrets, AuditAction, AuditActor, AuditLogEntry, AuditLogStore, AuditQuery,
+    MAX_AUDIT_ENTRIES_LIMIT,
+};
+pub use tokens::{
+    generate_api_token, hash_api_token, ApiToken, ApiTokenScope, ApiTokenStore, API_TOKEN_PREFIX,
+};
 
 // This enum and the following functions are used to allow clients to send either
 // numbers or strings and have them be properly deserialized into the appropriate
@@ -58,17 +71,6 @@ where
     }
 }
 
-pub fn map_of_number_or_string<'de, D>(deserializer: D) -> Result<HashMap<String, f64>, D::Error>
-where
-    D: de::Deserializer<'de>,
-{
-    #[derive(Deserialize)]
-    struct Wrapper(#[serde(deserialize_with = "number_or_string")] f64);
-
-    let v = HashMap::<String, Wrapper>::deserialize(deserializer)?;
-    Ok(v.into_iter().map(|(k, Wrapper(v))| (k, v)).collect())
-}
-
 // TODO should the methods from this trait be split up and put into the
 // traits that are more specific to what they're doing?
 // One argument against doing that is that the NodeStore allows admin-only
@@ -115,6 +117,15 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         // https://github.com/dtolnay/async-trait/issues/8#issuecomment-514812245
         R: IntoIterator<Item = (String, Uuid)> + Send + 'async_trait;
 
+    /// Sets the static routes for routing, where each prefix may have several next hops.
+    /// Like `set_static_routes`, this replaces all of the existing static routes.
+    /// Each prefix is routed to its first next hop, the others are only used by
+    /// the `Router` to spread packets across them or to fail over to.
+    /// Prefixes without any next hops are ignored.
+    async fn set_static_multipath_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait;
+
     /// Sets a single static route
     async fn set_static_route(
         &self,
@@ -141,9 +152,11 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
     ) -> Result<Option<Url>, NodeStoreError>;
 }
 
+/// Exchange rates by asset code. They are read from numbers or from strings with decimals
+/// or fractions, which keep their exact value, and written as numbers
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExchangeRates(
-    #[serde(deserialize_with = "map_of_number_or_string")] HashMap<String, f64>,
+    #[serde(with = "interledger_rates::rates_map")] pub HashMap<String, BigRational>,
 );
 
 /// AccountSettings is a subset of the user parameters defined in
@@ -176,6 +189,12 @@ pub struct AccountSettings {
     /// would pre-fund with the user)
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub settle_to: Option<u64>,
+    /// The number of consecutive failed packets to the account after which its circuit breaker opens
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub circuit_breaker_failure_threshold: Option<u32>,
+    /// The time, in milliseconds, during which the packets to the account are rejected once its circuit breaker opens
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub circuit_breaker_cool_down: Option<u64>,
 }
 
 /// EncryptedAccountSettings is created by encrypting the incoming and outgoing
@@ -196,6 +215,12 @@ pub struct EncryptedAccountSettings {
     #[serde(default, deserialize_with = "optional_number_or_string")]
     /// The amount which the balance service will attempt to settle down to
     pub settle_to: Option<u64>,
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    /// The number of consecutive failed packets to the account after which its circuit breaker opens
+    pub circuit_breaker_failure_threshold: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    /// The time, in milliseconds, during which the packets to the account are rejected once its circuit breaker opens
+    pub circuit_breaker_cool_down: Option<u64>,
 }
 
 /// The Account type for the RedisStore.
@@ -257,6 +282,30 @@ pub struct AccountDetails {
     /// The limit of packets the account can send per minute
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub packets_per_minute_limit: Option<u32>,
+    /// The maximum number of packets the account can send at once, after which it is held to its packets per minute limit
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub packets_burst_limit: Option<u32>,
+    /// The maximum amount the account can send at once, after which it is held to its amount per minute limit
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub amount_burst_limit: Option<u64>,
+    /// The maximum amount the account can send per calendar day (in UTC)
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub amount_per_day_limit: Option<u64>,
+    /// The maximum amount the account can send per calendar month (in UTC)
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub amount_per_month_limit: Option<u64>,
+    /// The maximum number of packets to or from the account which may be in flight at once
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub max_in_flight_packets: Option<u32>,
+    /// The maximum amount of the packets to or from the account which may be in flight at once
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub max_in_flight_amount: Option<u64>,
+    /// The number of consecutive failed packets to the account after which its circuit breaker opens
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub circuit_breaker_failure_threshold: Option<u32>,
+    /// The time, in milliseconds, during which the packets to the account are rejected once its circuit breaker opens
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub circuit_breaker_cool_down: Option<u64>,
     /// The account's settlement engine URL. If a global engine url is configured
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
@@ -279,6 +328,10 @@ pub struct NodeApi<S, I, O, B, A: Account> {
     /// Server secret used to instantiate SPSP/Stream connections
     server_secret: Bytes,
     node_version: Option<String>,
+    /// The circuit breakers of the outgoing packets, whose state is served to the admin
+    circuit_breakers: CircuitBreakers,
+    /// The capture of the incoming packets, which the admin can start and stop
+    packet_capture: PacketCapture,
 }
 
 impl<S, I, O, B, A> NodeApi<S, I, O, B, A>
@@ -290,6 +343,11 @@ where
         + BalanceStore
         + SettlementStore<Account = A>
         + StreamNotificationsStore<Account = A>
+        + StreamConnectionStore
+        + PaymentHistoryStore
+        + ApiTokenStore
+        + AuditLogStore
+        + FeeStore
         + RouterStore
         + ExchangeRateStore,
     I: IncomingService<A> + Clone + Send + Sync + 'static,
@@ -322,6 +380,8 @@ where
             btp,
             server_secret,
             node_version: None,
+            circuit_breakers: CircuitBreakers::default(),
+            packet_capture: PacketCapture::default(),
         }
     }
 
@@ -339,6 +399,18 @@ where
         self
     }
 
+    /// Sets the circuit breakers whose state is served at `GET /circuit-breakers`
+    pub fn circuit_breakers(&mut self, circuit_breakers: CircuitBreakers) -> &mut Self {
+        self.circuit_breakers = circuit_breakers;
+        self
+    }
+
+    /// Sets the packet capture which is started and stopped at `PUT /capture` and `DELETE /capture`
+    pub fn packet_capture(&mut self, packet_capture: PacketCapture) -> &mut Self {
+        self.packet_capture = packet_capture;
+        self
+    }
+
     /// Returns a Warp Filter which exposes the accounts and admin APIs
     pub fn into_warp_filter(self) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
         routes::accounts_api(
@@ -351,10 +423,29 @@ where
             self.store.clone(),
         )
         .or(routes::node_settings_api(
-            self.admin_api_token,
+            self.admin_api_token.clone(),
             self.node_version,
-            self.store,
+            self.store.clone(),
+        ))
+        .or(routes::api_tokens_api(
+            self.admin_api_token.clone(),
+            self.store.clone(),
+        ))
+        .or(routes::fees_api(
+            self.admin_api_token.clone(),
+            self.store.clone(),
+        ))
+        .or(routes::circuit_breakers_api(
+            self.admin_api_token.clone(),
+            self.store.clone(),
+            self.circuit_breakers,
+        ))
+        .or(routes::capture_api(
+            self.admin_api_token.clone(),
+            self.store.clone(),
+            self.packet_capture,
         ))
+        .or(routes::audit_api(self.admin_api_token, self.store))
         .boxed()
     }
 
@@ -425,6 +516,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn exchange_rates_accept_numbers_and_strings() {
+        let rates: ExchangeRates = serde_json::from_value(json!({
+            "ABC": 0.1,
+            "XYZ": "123456789.123456789123456789",
+            "THIRD": "1/3",
+        }))
+        .unwrap();
+        assert_eq!(
+            interledger_rates::format_rate(&rates.0["XYZ"]),
+            "123456789.123456789123456789"
+        );
+        assert_eq!(interledger_rates::format_rate(&rates.0["THIRD"]), "1/3");
+        assert_eq!(
+            serde_json::to_value(&rates).unwrap(),
+            json!({
+                "ABC": 0.1,
+                "XYZ": 123456789.12345679,
+                "THIRD": 1.0 / 3.0,
+            })
+        );
+        assert!(serde_json::from_value::<ExchangeRates>(json!({ "ABC": "a lot" })).is_err());
+    }
+
     #[test]
     fn account_settings_deserialization() {
         let settings: AccountSettings = serde_json::from_value(json!({
@@ -1,30 +1,47 @@
-use crate::{number_or_string, AccountDetails, AccountSettings, NodeStore};
+use super::{audit, auth, payment_jobs::PaymentJobs};
+use crate::{
+    number_or_string, optional_number_or_string, AccountDetails, AccountSettings, ApiTokenScope,
+    ApiTokenStore, AuditAction, AuditActor, AuditLogEntry, AuditLogStore, NodeStore,
+};
 use bytes::Bytes;
 use futures::{Future, FutureExt, StreamExt, TryFutureExt};
-use interledger_btp::{connect_to_service_account, BtpAccount, BtpOutgoingService};
+use interledger_btp::{
+    connect_to_service_account, BtpAccount, BtpConnectionState, BtpOutgoingService,
+};
 use interledger_ccp::{CcpRoutingAccount, Mode, RouteControlRequest, RoutingRelation};
 use interledger_errors::*;
 use interledger_http::{deserialize_json, HttpAccount, HttpStore};
 use interledger_ildcp::IldcpRequest;
 use interledger_ildcp::IldcpResponse;
+use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
 use interledger_router::RouterStore;
 use interledger_service::{
     Account, AccountStore, AddressStore, IncomingService, OutgoingRequest, OutgoingService,
     Username,
 };
-use interledger_service_util::BalanceStore;
+use interledger_service_util::{ping, BalanceStore, PingOptions};
 use interledger_settlement::core::{types::SettlementAccount, SettlementClient};
-use interledger_spsp::{pay, SpspResponder};
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_spsp::{pay_with_options, Error as SpspError, SpspResponder};
+use interledger_stream::{
+    connection_tag, ConnectionGenerator, PaymentHandle, PaymentHistoryStore, PaymentNotification,
+    PaymentQuery, PaymentRecord, PaymentStatus, SendMoneyOptions, StreamConnectionStore,
+    StreamDelivery, StreamNotificationsStore,
+};
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::time::Duration;
 use tracing::{debug, error, trace};
 use uuid::Uuid;
-use warp::{self, reply::Json, Filter, Rejection};
+use warp::{
+    self,
+    http::{HeaderMap, StatusCode},
+    reply::Json,
+    Filter, Rejection,
+};
 
 pub const BEARER_TOKEN_START: usize = 7;
 
@@ -42,6 +59,34 @@ struct SpspPayRequest {
         default = "get_default_max_slippage"
     )]
     slippage: f64,
+    /// Send the payment in a background job and respond with the job right away,
+    /// instead of waiting for the payment to finish
+    #[serde(default)]
+    background: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct PingRequest {
+    destination: Address,
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    count: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    amount: Option<u64>,
+    /// Milliseconds to wait between two echo requests
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    interval: Option<u64>,
+    /// Milliseconds after which an echo request expires
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    timeout: Option<u64>,
+}
+
+/// The most echo requests a single ping may send, so that it can't keep a request open for long
+const MAX_PING_COUNT: u32 = 100;
+
+#[derive(Deserialize, Debug)]
+struct StreamConnectionRequest {
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    receive_max: Option<u64>,
 }
 
 pub fn accounts_api<I, O, S, A, B>(
@@ -63,6 +108,10 @@ where
         + HttpStore<Account = A>
         + BalanceStore
         + StreamNotificationsStore<Account = A>
+        + StreamConnectionStore
+        + PaymentHistoryStore
+        + ApiTokenStore
+        + AuditLogStore
         + ExchangeRateStore
         + RouterStore,
     A: BtpAccount
@@ -76,27 +125,22 @@ where
         + 'static,
 {
     // TODO can we make any of the Filters const or put them in once_cell?
+    let store_clone = store.clone();
     let with_store = warp::any().map(move || store.clone());
     let with_incoming_handler = warp::any().map(move || incoming_handler.clone());
 
     // Helper filters
     let admin_auth_header = format!("Bearer {}", admin_api_token);
-    let admin_auth_header_clone = admin_auth_header.clone();
     let with_admin_auth_header = warp::any().map(move || admin_auth_header.clone());
-    let admin_only = warp::header::<SecretString>("authorization")
-        .and_then(move |authorization: SecretString| {
-            let admin_auth_header = admin_auth_header_clone.clone();
-            async move {
-                if authorization.expose_secret() == &admin_auth_header {
-                    Ok::<(), Rejection>(())
-                } else {
-                    Err(Rejection::from(ApiError::unauthorized()))
-                }
-            }
-        })
-        // This call makes it so we do not pass on a () value on
-        // success to the next filter, it just gets rid of it
-        .untuple_one();
+    let admin_actor = move |scope: ApiTokenScope| {
+        auth::admin_actor(admin_api_token.clone(), store_clone.clone(), scope)
+    };
+    let admin_actor_clone = admin_actor.clone();
+    let admin_only = move |scope: ApiTokenScope| {
+        admin_actor_clone(scope)
+            .map(|_actor: AuditActor| ())
+            .untuple_one()
+    };
 
     // Converts an account username to an account id or errors out
     let account_username_to_id = warp::path::param::<Username>()
@@ -130,38 +174,66 @@ where
         }
     };
 
-    // Checks if the account is an admin or if they have provided a valid password
-    let admin_or_authorized_user_only = warp::path::param::<Username>()
-        .and(warp::header::<SecretString>("authorization"))
-        .and(with_store.clone())
-        .and(with_admin_auth_header)
-        .and_then(
-            move |path_username: Username,
-                  auth_string: SecretString,
-                  store: S,
-                  admin_auth_header: String| {
-                async move {
-                    // If it's an admin, there's no need for more checks
-                    if auth_string.expose_secret() == &admin_auth_header {
+    // Checks if the account is an admin, if they have provided a valid password,
+    // or if they have provided an API token with the scope which acts for the account.
+    // Returns the account's id along with who made the request
+    let with_store_clone = with_store.clone();
+    let admin_or_authorized_user = move |scope: ApiTokenScope| {
+        warp::path::param::<Username>()
+            .and(warp::header::<SecretString>("authorization"))
+            .and(with_store_clone.clone())
+            .and(with_admin_auth_header.clone())
+            .and_then(
+                move |path_username: Username,
+                      auth_string: SecretString,
+                      store: S,
+                      admin_auth_header: String| {
+                    async move {
+                        // If it's an admin, there's no need for more checks
+                        if auth_string.expose_secret() == &admin_auth_header {
+                            let account_id =
+                                store.get_account_id_from_username(&path_username).await?;
+                            return Ok((account_id, AuditActor::Admin));
+                        }
+                        if let Some(api_token) = auth::get_api_token(&store, &auth_string).await? {
+                            let account_id =
+                                store.get_account_id_from_username(&path_username).await?;
+                            auth::check_api_token(&api_token, scope, Some(account_id))?;
+                            return Ok((account_id, AuditActor::ApiToken(api_token.id)));
+                        }
+                        let account = is_authorized_user(store, path_username, auth_string).await?;
+                        let actor = AuditActor::User(account.username().clone());
+                        Ok::<(Uuid, AuditActor), Rejection>((account.id(), actor))
+                    }
+                },
+            )
+            .untuple_one()
+    };
+    let admin_or_authorized_user_clone = admin_or_authorized_user.clone();
+    let admin_or_authorized_user_only = move |scope: ApiTokenScope| {
+        admin_or_authorized_user_clone(scope).map(|id: Uuid, _actor: AuditActor| id)
+    };
+
+    // Checks if the account has provided a valid password, or an API token with the scope
+    // which acts for the account (same as admin-or-auth call, minus one call, can we refactor them together?)
+    let with_store_clone = with_store.clone();
+    let authorized_user_only = move |scope: ApiTokenScope| {
+        warp::path::param::<Username>()
+            .and(warp::header::<SecretString>("authorization"))
+            .and(with_store_clone.clone())
+            .and_then(
+                move |path_username: Username, auth_string: SecretString, store: S| async move {
+                    if let Some(api_token) = auth::get_api_token(&store, &auth_string).await? {
                         let account_id = store.get_account_id_from_username(&path_username).await?;
-                        return Ok(account_id);
+                        auth::check_api_token(&api_token, scope, Some(account_id))?;
+                        let mut accounts = store.get_accounts(vec![account_id]).await?;
+                        return Ok(accounts.remove(0));
                     }
                     let account = is_authorized_user(store, path_username, auth_string).await?;
-                    Ok::<Uuid, Rejection>(account.id())
-                }
-            },
-        );
-
-    // Checks if the account has provided a valid password (same as admin-or-auth call, minus one call, can we refactor them together?)
-    let authorized_user_only = warp::path::param::<Username>()
-        .and(warp::header::<SecretString>("authorization"))
-        .and(with_store.clone())
-        .and_then(
-            move |path_username: Username, auth_string: SecretString, store: S| async move {
-                let account = is_authorized_user(store, path_username, auth_string).await?;
-                Ok::<A, Rejection>(account)
-            },
-        );
+                    Ok::<A, Rejection>(account)
+                },
+            )
+    };
 
     // POST /accounts
     let btp_clone = btp.clone();
@@ -169,26 +241,37 @@ where
     let post_accounts = warp::post()
         .and(warp::path("accounts"))
         .and(warp::path::end())
-        .and(admin_only.clone())
+        .and(admin_actor(ApiTokenScope::ManageAccounts))
         .and(deserialize_json()) // Why does warp::body::json not work?
         .and(with_store.clone())
-        .and_then(move |account_details: AccountDetails, store: S| {
-            let store_clone = store.clone();
-            let handler = outgoing_handler_clone.clone();
-            let btp = btp_clone.clone();
-            async move {
-                let account = store.insert_account(account_details.clone()).await?;
-
-                connect_to_external_services(handler, account.clone(), store_clone, btp).await?;
-                Ok::<Json, Rejection>(warp::reply::json(&account))
-            }
-        });
+        .and_then(
+            move |actor: AuditActor, account_details: AccountDetails, store: S| {
+                let store_clone = store.clone();
+                let handler = outgoing_handler_clone.clone();
+                let btp = btp_clone.clone();
+                async move {
+                    let account = store.insert_account(account_details.clone()).await?;
+                    let entry = AuditLogEntry::new(
+                        &actor,
+                        AuditAction::CreateAccount,
+                        Some(account.username().to_string()),
+                        None,
+                        Some(json!(account)),
+                    );
+                    audit::record(&store, entry).await;
+
+                    connect_to_external_services(handler, account.clone(), store_clone, btp)
+                        .await?;
+                    Ok::<Json, Rejection>(warp::reply::json(&account))
+                }
+            },
+        );
 
     // GET /accounts
     let get_accounts = warp::get()
         .and(warp::path("accounts"))
         .and(warp::path::end())
-        .and(admin_only.clone())
+        .and(admin_only(ApiTokenScope::ReadOnly))
         .and(with_store.clone())
         .and_then(|store: S| async move {
             let accounts = store.get_all_accounts().await?;
@@ -202,32 +285,44 @@ where
         .and(warp::path("accounts"))
         .and(account_username_to_id.clone())
         .and(warp::path::end())
-        .and(admin_only.clone())
+        .and(admin_actor(ApiTokenScope::ManageAccounts))
         .and(deserialize_json()) // warp::body::json() is not able to decode this!
         .and(with_store.clone())
-        .and_then(move |id: Uuid, account_details: AccountDetails, store: S| {
-            let outgoing_handler = outgoing_handler_clone.clone();
-            let btp = btp_clone.clone();
-            if account_details.ilp_over_btp_incoming_token.is_some() {
-                // if the BTP token was provided, assume that it's different
-                // from the existing one and drop the connection
-                // the saved websocket connection
-                // a new one will be initialized in the `connect_to_external_services` call
-                btp.close_connection(&id);
-            }
-            async move {
-                let account = store.update_account(id, account_details).await?;
-                connect_to_external_services(outgoing_handler, account.clone(), store, btp).await?;
-
-                Ok::<Json, Rejection>(warp::reply::json(&account))
-            }
-        });
+        .and_then(
+            move |id: Uuid, actor: AuditActor, account_details: AccountDetails, store: S| {
+                let outgoing_handler = outgoing_handler_clone.clone();
+                let btp = btp_clone.clone();
+                if account_details.ilp_over_btp_incoming_token.is_some() {
+                    // if the BTP token was provided, assume that it's different
+                    // from the existing one and drop the connection
+                    // the saved websocket connection
+                    // a new one will be initialized in the `connect_to_external_services` call
+                    btp.close_connection(&id);
+                }
+                async move {
+                    let before = store.get_accounts(vec![id]).await?.pop();
+                    let account = store.update_account(id, account_details).await?;
+                    let entry = AuditLogEntry::new(
+                        &actor,
+                        AuditAction::UpdateAccount,
+                        Some(account.username().to_string()),
+                        before.map(|before| json!(before)),
+                        Some(json!(account)),
+                    );
+                    audit::record(&store, entry).await;
+                    connect_to_external_services(outgoing_handler, account.clone(), store, btp)
+                        .await?;
+
+                    Ok::<Json, Rejection>(warp::reply::json(&account))
+                }
+            },
+        );
 
     // GET /accounts/:username
     let get_account = warp::get()
         .and(warp::path("accounts"))
         // takes the username and the authorization header and checks if it's authorized, returns the uid
-        .and(admin_or_authorized_user_only.clone())
+        .and(admin_or_authorized_user_only(ApiTokenScope::ReadOnly))
         .and(warp::path::end())
         .and(with_store.clone())
         .and_then(|id: Uuid, store: S| async move {
@@ -240,7 +335,7 @@ where
     let get_account_balance = warp::get()
         .and(warp::path("accounts"))
         // takes the username and the authorization header and checks if it's authorized, returns the uid
-        .and(admin_or_authorized_user_only.clone())
+        .and(admin_or_authorized_user_only(ApiTokenScope::ReadOnly))
         .and(warp::path("balance"))
         .and(warp::path::end())
         .and(with_store.clone())
@@ -262,18 +357,47 @@ where
             }
         });
 
+    // GET /accounts/:username/btp
+    let btp_clone = btp.clone();
+    let get_account_btp_connection = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only(ApiTokenScope::ReadOnly))
+        .and(warp::path("btp"))
+        .and(warp::path::end())
+        .and_then(move |id: Uuid| {
+            let (state, reconnect_attempts) = match btp_clone.connection_state(&id) {
+                BtpConnectionState::Connected => ("connected", 0),
+                BtpConnectionState::Reconnecting { attempts } => ("reconnecting", attempts),
+                BtpConnectionState::Disconnected => ("disconnected", 0),
+            };
+            async move {
+                Ok::<Json, Rejection>(warp::reply::json(&json!({
+                    "state": state,
+                    "reconnect_attempts": reconnect_attempts,
+                })))
+            }
+        });
+
     // DELETE /accounts/:username
     let btp_clone = btp.clone();
     let delete_account = warp::delete()
         .and(warp::path("accounts"))
         .and(account_username_to_id.clone())
         .and(warp::path::end())
-        .and(admin_only)
+        .and(admin_actor(ApiTokenScope::ManageAccounts))
         .and(with_store.clone())
-        .and_then(move |id: Uuid, store: S| {
+        .and_then(move |id: Uuid, actor: AuditActor, store: S| {
             let btp = btp_clone.clone();
             async move {
                 let account = store.delete_account(id).await?;
+                let entry = AuditLogEntry::new(
+                    &actor,
+                    AuditAction::DeleteAccount,
+                    Some(account.username().to_string()),
+                    Some(json!(account)),
+                    None,
+                );
+                audit::record(&store, entry).await;
                 // close the btp connection (if any)
                 btp.close_connection(&id);
                 Ok::<Json, Rejection>(warp::reply::json(&account))
@@ -284,39 +408,101 @@ where
     let outgoing_handler_clone = outgoing_handler;
     let put_account_settings = warp::put()
         .and(warp::path("accounts"))
-        .and(admin_or_authorized_user_only.clone())
+        .and(admin_or_authorized_user(ApiTokenScope::ManageAccounts))
         .and(warp::path("settings"))
         .and(warp::path::end())
         .and(deserialize_json())
         .and(with_store.clone())
-        .and_then(move |id: Uuid, settings: AccountSettings, store: S| {
-            let btp = btp.clone();
-            let outgoing_handler = outgoing_handler_clone.clone();
-            async move {
-                if settings.ilp_over_btp_incoming_token.is_some() {
-                    // if the BTP token was provided, assume that it's different
-                    // from the existing one and drop the connection
-                    // the saved websocket connection
-                    btp.close_connection(&id);
+        .and_then(
+            move |id: Uuid, actor: AuditActor, settings: AccountSettings, store: S| {
+                let btp = btp.clone();
+                let outgoing_handler = outgoing_handler_clone.clone();
+                async move {
+                    if settings.ilp_over_btp_incoming_token.is_some() {
+                        // if the BTP token was provided, assume that it's different
+                        // from the existing one and drop the connection
+                        // the saved websocket connection
+                        btp.close_connection(&id);
+                    }
+                    let before = store.get_accounts(vec![id]).await?.pop();
+                    let modified_account = store.modify_account_settings(id, settings).await?;
+                    let entry = AuditLogEntry::new(
+                        &actor,
+                        AuditAction::UpdateAccountSettings,
+                        Some(modified_account.username().to_string()),
+                        before.map(|before| json!(before)),
+                        Some(json!(modified_account)),
+                    );
+                    audit::record(&store, entry).await;
+
+                    // Since the account was modified, we should also try to
+                    // connect to the new account:
+                    connect_to_external_services(
+                        outgoing_handler,
+                        modified_account.clone(),
+                        store,
+                        btp,
+                    )
+                    .await?;
+                    Ok::<Json, Rejection>(warp::reply::json(&modified_account))
                 }
-                let modified_account = store.modify_account_settings(id, settings).await?;
-
-                // Since the account was modified, we should also try to
-                // connect to the new account:
-                connect_to_external_services(
-                    outgoing_handler,
-                    modified_account.clone(),
-                    store,
-                    btp,
-                )
-                .await?;
-                Ok::<Json, Rejection>(warp::reply::json(&modified_account))
-            }
+            },
+        );
+
+    // POST /accounts/:username/stream/connections
+    let server_secret_clone = server_secret.clone();
+    let post_stream_connections = warp::post()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only(ApiTokenScope::Pay))
+        .and(warp::path("stream"))
+        .and(warp::path("connections"))
+        .and(warp::path::end())
+        .and(deserialize_json())
+        .and(with_store.clone())
+        .and_then(
+            move |id: Uuid, connection_request: StreamConnectionRequest, store: S| {
+                let server_secret = bytes04::Bytes::from(&server_secret_clone[..]);
+                async move {
+                    let accounts = store.get_accounts(vec![id]).await?;
+                    let (destination_account, shared_secret) =
+                        ConnectionGenerator::new(server_secret)
+                            .generate_address_and_secret(accounts[0].ilp_address());
+                    // Track the connection right away so that its receive max is enforced
+                    store
+                        .create_stream_connection(
+                            connection_tag(&destination_account),
+                            connection_request.receive_max,
+                        )
+                        .await?;
+                    Ok::<Json, Rejection>(warp::reply::json(&json!({
+                        "destination_account": destination_account,
+                        "shared_secret": base64::encode(&shared_secret[..]),
+                        "receive_max": connection_request.receive_max,
+                    })))
+                }
+            },
+        );
+
+    // GET /accounts/:username/stream/connections/:connection_tag
+    let get_stream_connection = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only(ApiTokenScope::ReadOnly))
+        .and(warp::path("stream"))
+        .and(warp::path("connections"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(with_store.clone())
+        .and_then(|_id: Uuid, tag: String, store: S| async move {
+            let details = store
+                .get_stream_connection(&tag)
+                .await?
+                .ok_or(StreamConnectionStoreError::ConnectionNotFound(tag))?;
+            Ok::<Json, Rejection>(warp::reply::json(&details))
         });
 
     // (Websocket) /accounts/:username/payments/incoming
     let incoming_payment_notifications = warp::path("accounts")
-        .and(admin_or_authorized_user_only)
+        .and(admin_or_authorized_user_only(ApiTokenScope::ReadOnly))
         .and(warp::path("payments"))
         .and(warp::path("incoming"))
         .and(warp::path::end())
@@ -328,40 +514,171 @@ where
             })
         });
 
+    // Payments sent in the background, which are tracked until they finish
+    let payment_jobs = PaymentJobs::default();
+    let with_payment_jobs = warp::any().map(move || payment_jobs.clone());
+
     // POST /accounts/:username/payments
     let post_payments = warp::post()
         .and(warp::path("accounts"))
-        .and(authorized_user_only)
+        .and(authorized_user_only(ApiTokenScope::Pay))
         .and(warp::path("payments"))
         .and(warp::path::end())
         .and(deserialize_json())
-        .and(with_incoming_handler)
+        .and(with_incoming_handler.clone())
         .and(with_store.clone())
+        .and(with_payment_jobs.clone())
         .and_then(
-            move |account: A, pay_request: SpspPayRequest, incoming_handler: I, store: S| {
+            move |account: A,
+                  pay_request: SpspPayRequest,
+                  incoming_handler: I,
+                  store: S,
+                  payment_jobs: PaymentJobs| {
                 async move {
-                    let receipt = pay(
+                    if pay_request.background {
+                        let (job, handle) = payment_jobs.start(
+                            account.id(),
+                            pay_request.receiver.clone(),
+                            pay_request.source_amount,
+                        );
+                        let job_id = job.id;
+                        tokio::spawn(async move {
+                            let result = send_payment(
+                                incoming_handler,
+                                account,
+                                store,
+                                &pay_request,
+                                job_id,
+                                handle,
+                            )
+                            .await;
+                            payment_jobs.finish(job_id, &result);
+                        });
+                        return Ok::<_, Rejection>(warp::reply::with_status(
+                            warp::reply::json(&job),
+                            StatusCode::ACCEPTED,
+                        ));
+                    }
+
+                    let receipt = send_payment(
                         incoming_handler,
-                        account.clone(),
+                        account,
                         store,
-                        &pay_request.receiver,
-                        pay_request.source_amount,
-                        pay_request.slippage,
+                        &pay_request,
+                        Uuid::new_v4(),
+                        PaymentHandle::new(),
                     )
+                    .await
                     .map_err(|err| {
-                        let msg = format!("Error sending SPSP payment: {}", err);
-                        error!("{}", msg);
                         // TODO give a different error message depending on what type of error it is
-                        Rejection::from(ApiError::internal_server_error().detail(msg))
-                    })
-                    .await?;
+                        Rejection::from(
+                            ApiError::internal_server_error()
+                                .detail(format!("Error sending SPSP payment: {}", err)),
+                        )
+                    })?;
 
                     debug!("Sent SPSP payment, receipt: {:?}", receipt);
-                    Ok::<Json, Rejection>(warp::reply::json(&json!(receipt)))
+                    Ok(warp::reply::with_status(
+                        warp::reply::json(&json!(receipt)),
+                        StatusCode::OK,
+                    ))
                 }
             },
         );
 
+    // POST /accounts/:username/ping
+    let post_ping = warp::post()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only(ApiTokenScope::Pay))
+        .and(warp::path("ping"))
+        .and(warp::path::end())
+        .and(deserialize_json())
+        .and(with_incoming_handler)
+        .and(with_store.clone())
+        .and_then(
+            move |id: Uuid, ping_request: PingRequest, incoming_handler: I, store: S| async move {
+                let defaults = PingOptions::default();
+                let options = PingOptions {
+                    count: ping_request.count.unwrap_or(defaults.count),
+                    amount: ping_request.amount.unwrap_or(defaults.amount),
+                    interval: ping_request
+                        .interval
+                        .map(Duration::from_millis)
+                        .unwrap_or(defaults.interval),
+                    timeout: ping_request
+                        .timeout
+                        .map(Duration::from_millis)
+                        .unwrap_or(defaults.timeout),
+                };
+                if options.count == 0 || options.count > MAX_PING_COUNT {
+                    return Err(Rejection::from(ApiError::bad_request().detail(format!(
+                        "The count must be between 1 and {}",
+                        MAX_PING_COUNT
+                    ))));
+                }
+
+                let mut accounts = store.get_accounts(vec![id]).await?;
+                let account = accounts.remove(0);
+                let report = ping(
+                    incoming_handler,
+                    account,
+                    ping_request.destination,
+                    &options,
+                )
+                .await;
+                Ok::<Json, Rejection>(warp::reply::json(&report))
+            },
+        );
+
+    // GET /accounts/:username/payments/jobs/:id
+    let get_payment_job = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only(ApiTokenScope::ReadOnly))
+        .and(warp::path("payments"))
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(with_payment_jobs.clone())
+        .and_then(
+            |account_id: Uuid, id: Uuid, payment_jobs: PaymentJobs| async move {
+                let job = payment_jobs
+                    .get(account_id, id)
+                    .ok_or_else(|| ApiError::not_found().detail("Payment job not found"))?;
+                Ok::<Json, Rejection>(warp::reply::json(&job))
+            },
+        );
+
+    // DELETE /accounts/:username/payments/jobs/:id
+    let delete_payment_job = warp::delete()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only(ApiTokenScope::Pay))
+        .and(warp::path("payments"))
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(with_payment_jobs)
+        .and_then(
+            |account_id: Uuid, id: Uuid, payment_jobs: PaymentJobs| async move {
+                let job = payment_jobs
+                    .cancel(account_id, id)
+                    .ok_or_else(|| ApiError::not_found().detail("Payment job not found"))?;
+                Ok::<Json, Rejection>(warp::reply::json(&job))
+            },
+        );
+
+    // GET /accounts/:username/payments
+    let get_payments = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only(ApiTokenScope::ReadOnly))
+        .and(warp::path("payments"))
+        .and(warp::path::end())
+        .and(warp::query::<PaymentQuery>())
+        .and(with_store.clone())
+        .and_then(|id: Uuid, query: PaymentQuery, store: S| async move {
+            let payments = store.get_payments(id, query).await?;
+            Ok::<Json, Rejection>(warp::reply::json(&payments))
+        });
+
     // GET /accounts/:username/spsp
     let server_secret_clone = server_secret.clone();
     let get_spsp = warp::get()
@@ -370,7 +687,8 @@ where
         .and(warp::path("spsp"))
         .and(warp::path::end())
         .and(with_store.clone())
-        .and_then(move |id: Uuid, store: S| {
+        .and(warp::header::headers_cloned())
+        .and_then(move |id: Uuid, store: S, headers: HeaderMap| {
             let server_secret_clone = server_secret_clone.clone();
             async move {
                 let accounts = store.get_accounts(vec![id]).await?;
@@ -380,7 +698,7 @@ where
                         accounts[0].ilp_address().clone(),
                         server_secret_clone.clone(),
                     )
-                    .generate_http_response(),
+                    .generate_http_response_from_headers(&headers),
                 )
             }
         });
@@ -393,7 +711,8 @@ where
         .and(warp::path("pay"))
         .and(warp::path::end())
         .and(with_store)
-        .and_then(move |store: S| {
+        .and(warp::header::headers_cloned())
+        .and_then(move |store: S, headers: HeaderMap| {
             let default_spsp_account = default_spsp_account.clone();
             let server_secret_clone = server_secret.clone();
             async move {
@@ -410,7 +729,7 @@ where
                             account.ilp_address().clone(),
                             server_secret_clone.clone(),
                         )
-                        .generate_http_response(),
+                        .generate_http_response_from_headers(&headers),
                     )
                 } else {
                     Err(Rejection::from(
@@ -428,9 +747,66 @@ where
         .or(delete_account)
         .or(get_account)
         .or(get_account_balance)
+        .or(get_account_btp_connection)
         .or(put_account_settings)
         .or(incoming_payment_notifications)
         .or(post_payments)
+        .or(get_payments)
+        .or(post_ping)
+        .or(get_payment_job)
+        .or(delete_payment_job)
+        .or(post_stream_connections)
+        .or(get_stream_connection)
+}
+
+/// Sends the SPSP payment and saves it in the account's payment history with the given id
+async fn send_payment<I, A, S>(
+    incoming_handler: I,
+    account: A,
+    store: S,
+    pay_request: &SpspPayRequest,
+    id: Uuid,
+    handle: PaymentHandle,
+) -> Result<StreamDelivery, SpspError>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: PaymentHistoryStore + ExchangeRateStore + Clone + Send + Sync + 'static,
+{
+    let result = pay_with_options(
+        incoming_handler,
+        account.clone(),
+        store.clone(),
+        &pay_request.receiver,
+        pay_request.source_amount,
+        pay_request.slippage,
+        SendMoneyOptions {
+            handle: Some(handle.clone()),
+            ..SendMoneyOptions::default()
+        },
+    )
+    .await;
+
+    let mut payment = match (&result, handle.progress()) {
+        (Ok(receipt), _) => {
+            PaymentRecord::outgoing_completed(account.id(), &pay_request.receiver, receipt)
+        }
+        // Keep the amounts sent before the payment failed or was cancelled
+        (Err(_), Some(progress)) => PaymentRecord {
+            status: PaymentStatus::Failed,
+            ..PaymentRecord::outgoing_completed(account.id(), &pay_request.receiver, &progress)
+        },
+        (Err(_), None) => PaymentRecord::outgoing_failed(&account, &pay_request.receiver),
+    };
+    payment.id = id;
+    if let Err(err) = store.save_payment(payment).await {
+        error!("Error saving outgoing payment: {}", err);
+    }
+
+    if let Err(ref err) = result {
+        error!("Error sending SPSP payment: {}", err);
+    }
+    result
 }
 
 fn notify_user(
@@ -628,6 +1004,7 @@ where
 #[cfg(test)]
 mod tests {
     use crate::routes::test_helpers::*;
+    use crate::AuditAction;
     // TODO: Add test for GET /accounts/:username/spsp and /.well_known
 
     #[tokio::test]
@@ -640,6 +1017,52 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn api_tokens_are_limited_to_their_scopes() {
+        let api = test_accounts_api();
+        let resp = api_call(&api, "GET", "/accounts", READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = api_call(&api, "GET", "/accounts/alice", READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts",
+            READ_ONLY_API_TOKEN,
+            DETAILS.clone(),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = api_call(
+            &api,
+            "PUT",
+            "/accounts/alice/settings",
+            READ_ONLY_API_TOKEN,
+            Some(serde_json::json!({"settle_to": 5})),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        // Tokens which act for another account are not allowed, even with every scope
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/balance",
+            OTHER_ACCOUNT_API_TOKEN,
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = api_call(&api, "GET", "/accounts", OTHER_ACCOUNT_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let resp = api_call(&api, "GET", "/accounts/alice", EXPIRED_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = api_call(&api, "GET", "/accounts/alice", "ilp_api_unknown", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
     #[tokio::test]
     async fn only_admin_can_delete_account() {
         let api = test_accounts_api();
@@ -698,6 +1121,22 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn only_admin_or_user_can_get_btp_connection_state() {
+        let api = test_accounts_api();
+        let resp = api_call(&api, "GET", "/accounts/alice/btp", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["state"], "disconnected");
+        assert_eq!(body["reconnect_attempts"], 0);
+
+        let resp = api_call(&api, "GET", "/accounts/alice/btp", "password", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(&api, "GET", "/accounts/alice/btp", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
     #[tokio::test]
     async fn only_admin_or_user_can_modify_accounts_settings() {
         let api = test_accounts_api();
@@ -733,6 +1172,34 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn records_account_changes_in_audit_log() {
+        let api = test_accounts_api();
+        let resp = api_call(
+            &api,
+            "PUT",
+            "/accounts/alice/settings",
+            "password",
+            DETAILS.clone(),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(audit_log().iter().any(|entry| {
+            entry.action == AuditAction::UpdateAccountSettings
+                && entry.actor == "user:alice"
+                && entry.target.as_deref() == Some("alice")
+        }));
+
+        let resp = api_call(&api, "DELETE", "/accounts/alice", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(audit_log().iter().any(|entry| {
+            entry.action == AuditAction::DeleteAccount
+                && entry.actor == "admin"
+                && entry.before.is_some()
+                && entry.after.is_none()
+        }));
+    }
+
     #[tokio::test]
     async fn only_admin_or_user_can_send_payment() {
         let payment: Option<serde_json::Value> = Some(serde_json::json!({
@@ -774,4 +1241,188 @@ mod tests {
         .await;
         assert_eq!(resp.status().as_u16(), 401);
     }
+
+    #[tokio::test]
+    async fn pings_address_from_account() {
+        let api = test_accounts_api();
+        let ping = Some(serde_json::json!({
+            "destination": "example.bob",
+            "count": 2,
+            "interval": 1,
+        }));
+        let resp = api_call(&api, "POST", "/accounts/alice/ping", "admin", ping.clone()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let report: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(report["destination"], "example.bob");
+        assert_eq!(report["sent"], 2);
+        assert_eq!(report["received"], 0);
+        assert_eq!(report["results"][1]["sequence"], 1);
+        assert_eq!(report["results"][1]["code"], "F02");
+
+        let resp = api_call(&api, "POST", "/accounts/alice/ping", "wrong", ping).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let too_many = Some(serde_json::json!({
+            "destination": "example.bob",
+            "count": 1000,
+        }));
+        let resp = api_call(&api, "POST", "/accounts/alice/ping", "admin", too_many).await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn sends_payment_in_background_job() {
+        let api = test_accounts_api();
+        let payment = Some(serde_json::json!({
+            "receiver": "some_receiver",
+            "source_amount": 10,
+            "background": true,
+        }));
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            payment,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 202);
+        let job: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(job["status"], "running");
+        assert_eq!(job["source_amount"], 10);
+        let path = format!(
+            "/accounts/alice/payments/jobs/{}",
+            job["id"].as_str().unwrap()
+        );
+
+        let resp = api_call(&api, "GET", &path, "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = api_call(&api, "DELETE", &path, "password", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = api_call(&api, "GET", &path, "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            &format!("/accounts/alice/payments/jobs/{}", uuid::Uuid::new_v4()),
+            "password",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    #[tokio::test]
+    async fn only_admin_or_user_can_get_payments() {
+        let api = test_accounts_api();
+        let resp = api_call(&api, "GET", "/accounts/alice/payments", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body[0]["direction"], "incoming");
+        assert_eq!(body[0]["amount"], 100);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/payments?direction=outgoing&limit=10",
+            "password",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body, serde_json::json!([]));
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/payments?direction=sideways",
+            "admin",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let resp = api_call(&api, "GET", "/accounts/alice/payments", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn only_admin_or_user_can_create_stream_connections() {
+        let api = test_accounts_api();
+        let request = Some(serde_json::json!({ "receive_max": "1000" }));
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/stream/connections",
+            "admin",
+            request.clone(),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["receive_max"], 1000);
+        assert!(body["destination_account"]
+            .as_str()
+            .unwrap()
+            .starts_with("example.alice."));
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/stream/connections",
+            "password",
+            request.clone(),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/stream/connections",
+            "wrong",
+            request,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn gets_stream_connection_totals() {
+        let api = test_accounts_api();
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/stream/connections/some-tag",
+            "password",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["total_received"], 100);
+        assert_eq!(body["receive_max"], 1000);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/stream/connections/unknown",
+            "admin",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/stream/connections/some-tag",
+            "wrong",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
 }
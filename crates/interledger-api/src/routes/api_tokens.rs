@@ -0,0 +1,182 @@
+use super::auth;
+use crate::{
+    generate_api_token, optional_number_or_string, ApiToken, ApiTokenScope, ApiTokenStore,
+};
+use interledger_errors::*;
+use interledger_http::deserialize_json;
+use interledger_service::{AccountStore, Username};
+use interledger_stream::unix_timestamp;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::{self, reply::Json, Filter, Rejection};
+
+#[derive(Deserialize, Debug)]
+struct ApiTokenRequest {
+    /// The only account the token acts for, if any
+    username: Option<Username>,
+    scopes: Vec<ApiTokenScope>,
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    expires_at: Option<u64>,
+    description: Option<String>,
+}
+
+/// A newly created API token. This is the only time the token itself is returned
+#[derive(Serialize)]
+struct CreatedApiToken {
+    token: String,
+    #[serde(flatten)]
+    details: ApiToken,
+}
+
+/// Admin-only endpoints which issue and revoke API tokens.
+/// API tokens themselves are not allowed to use them
+pub fn api_tokens_api<S>(
+    admin_api_token: String,
+    store: S,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: ApiTokenStore + AccountStore + Clone + Send + Sync + 'static,
+{
+    let admin_only = auth::admin_token_only(admin_api_token);
+    let with_store = warp::any().map(move || store.clone());
+
+    // POST /tokens
+    let post_tokens = warp::post()
+        .and(warp::path("tokens"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(deserialize_json())
+        .and(with_store.clone())
+        .and_then(|request: ApiTokenRequest, store: S| async move {
+            if request.scopes.is_empty() {
+                return Err(Rejection::from(
+                    ApiError::bad_request().detail("API tokens need at least one scope"),
+                ));
+            }
+            let now = unix_timestamp();
+            if request
+                .expires_at
+                .map_or(false, |expires_at| expires_at <= now)
+            {
+                return Err(Rejection::from(
+                    ApiError::bad_request().detail("API token expiry must be in the future"),
+                ));
+            }
+            let account_id = match request.username {
+                Some(ref username) => Some(store.get_account_id_from_username(username).await?),
+                None => None,
+            };
+
+            let mut scopes = request.scopes;
+            scopes.sort_by_key(|scope| scope.as_str());
+            scopes.dedup();
+            let details = ApiToken {
+                id: Uuid::new_v4(),
+                account_id,
+                scopes,
+                expires_at: request.expires_at,
+                created_at: now,
+                description: request.description,
+            };
+            let (token, token_hash) = generate_api_token();
+            store.insert_api_token(details.clone(), token_hash).await?;
+            Ok::<Json, Rejection>(warp::reply::json(&CreatedApiToken { token, details }))
+        });
+
+    // GET /tokens
+    let get_tokens = warp::get()
+        .and(warp::path("tokens"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(with_store.clone())
+        .and_then(|store: S| async move {
+            let tokens = store.get_all_api_tokens().await?;
+            Ok::<Json, Rejection>(warp::reply::json(&tokens))
+        });
+
+    // DELETE /tokens/:id
+    let delete_token = warp::delete()
+        .and(warp::path("tokens"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(admin_only)
+        .and(with_store)
+        .and_then(|id: Uuid, store: S| async move {
+            let token = store.delete_api_token(id).await?;
+            Ok::<Json, Rejection>(warp::reply::json(&token))
+        });
+
+    post_tokens.or(get_tokens).or(delete_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::test_helpers::{api_call, test_api_tokens_api, READ_ONLY_API_TOKEN};
+    use crate::API_TOKEN_PREFIX;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn only_admin_can_create_tokens() {
+        let api = test_api_tokens_api();
+        let request = Some(json!({
+            "username": "alice",
+            "scopes": ["pay", "read-only", "pay"],
+            "expires_at": "4102444800",
+        }));
+        let resp = api_call(&api, "POST", "/tokens", "admin", request.clone()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert!(body["token"]
+            .as_str()
+            .unwrap()
+            .starts_with(API_TOKEN_PREFIX));
+        assert_eq!(body["scopes"], json!(["pay", "read-only"]));
+        assert_eq!(body["expires_at"], 4_102_444_800u64);
+
+        let resp = api_call(&api, "POST", "/tokens", READ_ONLY_API_TOKEN, request).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/tokens",
+            "admin",
+            Some(json!({ "scopes": [] })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/tokens",
+            "admin",
+            Some(json!({ "scopes": ["pay"], "expires_at": 1 })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn only_admin_can_list_and_revoke_tokens() {
+        let api = test_api_tokens_api();
+        let resp = api_call(&api, "GET", "/tokens", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        let id = body[0]["id"].as_str().unwrap().to_string();
+        assert!(body[0].get("token").is_none());
+
+        let resp = api_call(&api, "GET", "/tokens", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let path = format!("/tokens/{}", id);
+        let resp = api_call(&api, "DELETE", &path, READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = api_call(&api, "DELETE", &path, "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let path = format!("/tokens/{}", uuid::Uuid::new_v4());
+        let resp = api_call(&api, "DELETE", &path, "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+}
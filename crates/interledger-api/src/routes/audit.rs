@@ -0,0 +1,66 @@
+use super::auth;
+use crate::{ApiTokenScope, ApiTokenStore, AuditLogEntry, AuditLogStore, AuditQuery};
+use tracing::error;
+use warp::{self, reply::Json, Filter, Rejection};
+
+/// Appends the entry to the audit log. The action was already taken by then,
+/// so failing to log it is reported but does not fail the request
+pub async fn record<S: AuditLogStore>(store: &S, entry: AuditLogEntry) {
+    let action = entry.action;
+    if let Err(err) = store.append_audit_entry(entry).await {
+        error!(
+            "Error appending {} to the audit log: {}",
+            action.as_str(),
+            err
+        );
+    }
+}
+
+/// Admin-only endpoint which serves the audit log
+pub fn audit_api<S>(
+    admin_api_token: String,
+    store: S,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: AuditLogStore + ApiTokenStore + Clone + Send + Sync + 'static,
+{
+    let admin_only = auth::admin_only(admin_api_token, store.clone(), ApiTokenScope::ReadOnly);
+    let with_store = warp::any().map(move || store.clone());
+
+    // GET /audit
+    warp::get()
+        .and(warp::path("audit"))
+        .and(warp::path::end())
+        .and(admin_only)
+        .and(warp::query::<AuditQuery>())
+        .and(with_store)
+        .and_then(|query: AuditQuery, store: S| async move {
+            let entries = store.get_audit_entries(query).await?;
+            Ok::<Json, Rejection>(warp::reply::json(&entries))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::test_helpers::{
+        api_call, test_audit_api, OTHER_ACCOUNT_API_TOKEN, READ_ONLY_API_TOKEN,
+    };
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn only_admin_can_read_audit_log() {
+        let api = test_audit_api();
+        let resp = api_call(&api, "GET", "/audit?action=set_rates", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body[0]["action"], "set_rates");
+
+        let resp = api_call(&api, "GET", "/audit", READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        // Tokens bound to an account cannot see the whole node's log
+        let resp = api_call(&api, "GET", "/audit", OTHER_ACCOUNT_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = api_call(&api, "GET", "/audit", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+}
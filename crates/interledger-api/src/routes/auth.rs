@@ -0,0 +1,126 @@
+use super::accounts::BEARER_TOKEN_START;
+use crate::{
+    hash_api_token, ApiToken, ApiTokenScope, ApiTokenStore, AuditActor, API_TOKEN_PREFIX,
+};
+use interledger_errors::ApiError;
+use interledger_stream::unix_timestamp;
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+use warp::{self, Filter, Rejection};
+
+/// Loads the API token the request was made with, if its bearer token is one.
+/// Unknown and expired API tokens are rejected
+pub async fn get_api_token<S: ApiTokenStore>(
+    store: &S,
+    authorization: &SecretString,
+) -> Result<Option<ApiToken>, Rejection> {
+    let authorization = authorization.expose_secret();
+    let token = match authorization.get(BEARER_TOKEN_START..) {
+        Some(token)
+            if authorization.starts_with("Bearer ") && token.starts_with(API_TOKEN_PREFIX) =>
+        {
+            token
+        }
+        _ => return Ok(None),
+    };
+    let api_token = store
+        .get_api_token(hash_api_token(token))
+        .await?
+        .filter(|api_token| !api_token.is_expired(unix_timestamp()))
+        .ok_or_else(|| ApiError::unauthorized().detail("invalid or expired API token provided"))?;
+    Ok(Some(api_token))
+}
+
+/// Rejects API tokens which lack the scope, or which do not act for the account.
+/// Actions on the whole node, without an account, require a token which is not bound to one
+pub fn check_api_token(
+    api_token: &ApiToken,
+    scope: ApiTokenScope,
+    account_id: Option<Uuid>,
+) -> Result<(), Rejection> {
+    if !api_token.allows(scope) {
+        return Err(ApiError::forbidden()
+            .detail(format!("API token does not have the {} scope", scope))
+            .into());
+    }
+    let allows_account = match account_id {
+        Some(account_id) => api_token.allows_account(account_id),
+        None => api_token.account_id.is_none(),
+    };
+    if allows_account {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden()
+            .detail("API token does not act for this account")
+            .into())
+    }
+}
+
+/// Only lets through requests made with the admin's token
+pub fn admin_token_only(
+    admin_api_token: String,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let admin_auth_header = format!("Bearer {}", admin_api_token);
+    warp::header::<SecretString>("authorization")
+        .and_then(move |authorization: SecretString| {
+            let admin_auth_header = admin_auth_header.clone();
+            async move {
+                if authorization.expose_secret() == &admin_auth_header {
+                    Ok::<(), Rejection>(())
+                } else {
+                    Err(Rejection::from(
+                        ApiError::unauthorized().detail("invalid admin auth token provided"),
+                    ))
+                }
+            }
+        })
+        // This call makes it so we do not pass on a () value on
+        // success to the next filter, it just gets rid of it
+        .untuple_one()
+}
+
+/// Only lets through requests made with the admin's token, or with an API token
+/// which has the scope and is not bound to an account
+pub fn admin_only<S>(
+    admin_api_token: String,
+    store: S,
+    scope: ApiTokenScope,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone
+where
+    S: ApiTokenStore + Clone + Send + Sync + 'static,
+{
+    admin_actor(admin_api_token, store, scope)
+        .map(|_actor: AuditActor| ())
+        .untuple_one()
+}
+
+/// Same as `admin_only`, but passes on who made the request
+pub fn admin_actor<S>(
+    admin_api_token: String,
+    store: S,
+    scope: ApiTokenScope,
+) -> impl Filter<Extract = (AuditActor,), Error = Rejection> + Clone
+where
+    S: ApiTokenStore + Clone + Send + Sync + 'static,
+{
+    let admin_auth_header = format!("Bearer {}", admin_api_token);
+    warp::header::<SecretString>("authorization")
+        .and(warp::any().map(move || store.clone()))
+        .and_then(move |authorization: SecretString, store: S| {
+            let admin_auth_header = admin_auth_header.clone();
+            async move {
+                if authorization.expose_secret() == &admin_auth_header {
+                    return Ok::<AuditActor, Rejection>(AuditActor::Admin);
+                }
+                match get_api_token(&store, &authorization).await? {
+                    Some(api_token) => {
+                        check_api_token(&api_token, scope, None)?;
+                        Ok(AuditActor::ApiToken(api_token.id))
+                    }
+                    None => Err(Rejection::from(
+                        ApiError::unauthorized().detail("invalid admin auth token provided"),
+                    )),
+                }
+            }
+        })
+}
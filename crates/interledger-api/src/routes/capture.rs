@@ -0,0 +1,156 @@
+use super::{audit, auth};
+use crate::{ApiTokenScope, ApiTokenStore, AuditAction, AuditActor, AuditLogEntry, AuditLogStore};
+use interledger_errors::*;
+use interledger_http::deserialize_json;
+use interledger_service_util::{CaptureFilter, PacketCapture};
+use serde_json::json;
+use std::io;
+use warp::{self, reply::Json, Filter, Rejection};
+
+/// Admin-only endpoints which start and stop capturing the packets the node receives.
+/// Since the captures hold the packets of every account, API tokens are not allowed to
+/// change them
+pub fn capture_api<S>(
+    admin_api_token: String,
+    store: S,
+    capture: PacketCapture,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: ApiTokenStore + AuditLogStore + Clone + Send + Sync + 'static,
+{
+    let admin_only = auth::admin_only(
+        admin_api_token.clone(),
+        store.clone(),
+        ApiTokenScope::ReadOnly,
+    );
+    let admin_token_only = auth::admin_token_only(admin_api_token);
+    let with_store = warp::any().map(move || store.clone());
+    let with_capture = warp::any().map(move || capture.clone());
+
+    // GET /capture
+    let get_capture = warp::get()
+        .and(warp::path("capture"))
+        .and(warp::path::end())
+        .and(admin_only)
+        .and(with_capture.clone())
+        .map(|capture: PacketCapture| warp::reply::json(&capture.status()));
+
+    // PUT /capture
+    let put_capture = warp::put()
+        .and(warp::path("capture"))
+        .and(warp::path::end())
+        .and(admin_token_only.clone())
+        .and(deserialize_json())
+        .and(with_capture.clone())
+        .and(with_store.clone())
+        .and_then(
+            |filter: CaptureFilter, capture: PacketCapture, store: S| async move {
+                let before = capture.status();
+                let status = capture.start(filter).map_err(|err| {
+                    if err.kind() == io::ErrorKind::NotFound {
+                        ApiError::bad_request().detail(err.to_string())
+                    } else {
+                        ApiError::internal_server_error()
+                            .detail(format!("Error opening the capture file: {}", err))
+                    }
+                })?;
+                let entry = AuditLogEntry::new(
+                    &AuditActor::Admin,
+                    AuditAction::StartPacketCapture,
+                    None,
+                    Some(json!(before)),
+                    Some(json!(status)),
+                );
+                audit::record(&store, entry).await;
+                Ok::<Json, Rejection>(warp::reply::json(&status))
+            },
+        );
+
+    // DELETE /capture
+    let delete_capture = warp::delete()
+        .and(warp::path("capture"))
+        .and(warp::path::end())
+        .and(admin_token_only)
+        .and(with_capture)
+        .and(with_store)
+        .and_then(|capture: PacketCapture, store: S| async move {
+            let before = capture.status();
+            let status = capture.stop();
+            if before.capturing {
+                let entry = AuditLogEntry::new(
+                    &AuditActor::Admin,
+                    AuditAction::StopPacketCapture,
+                    None,
+                    Some(json!(before)),
+                    Some(json!(status)),
+                );
+                audit::record(&store, entry).await;
+            }
+            Ok::<Json, Rejection>(warp::reply::json(&status))
+        });
+
+    get_capture.or(put_capture).or(delete_capture)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::test_helpers::{api_call, audit_log, test_capture_api, READ_ONLY_API_TOKEN};
+    use crate::AuditAction;
+    use interledger_service_util::{CaptureSettings, PacketCapture};
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn starts_and_stops_capturing() {
+        let path =
+            std::env::temp_dir().join(format!("capture-api-{}.ilpcap", uuid::Uuid::new_v4()));
+        let capture = PacketCapture::new(CaptureSettings {
+            path: path.clone(),
+            max_file_size: 1_000_000,
+            max_files: 1,
+        });
+        let api = test_capture_api(capture.clone());
+
+        let resp = api_call(&api, "GET", "/capture", READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["capturing"], false);
+
+        let filter = json!({ "accounts": ["alice"], "destination_prefixes": ["example.bob"] });
+        let resp = api_call(
+            &api,
+            "PUT",
+            "/capture",
+            READ_ONLY_API_TOKEN,
+            Some(filter.clone()),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = api_call(&api, "PUT", "/capture", "admin", Some(filter.clone())).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["capturing"], true);
+        assert_eq!(body["filter"], filter);
+        assert!(capture.status().capturing);
+        assert!(audit_log()
+            .iter()
+            .any(|entry| entry.action == AuditAction::StartPacketCapture
+                && entry.after.as_ref() == Some(&body)));
+
+        let resp = api_call(&api, "DELETE", "/capture", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["capturing"], false);
+        assert!(!capture.status().capturing);
+        assert!(audit_log()
+            .iter()
+            .any(|entry| entry.action == AuditAction::StopPacketCapture));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cannot_capture_without_a_capture_file() {
+        let api = test_capture_api(PacketCapture::default());
+        let resp = api_call(&api, "PUT", "/capture", "admin", Some(json!({}))).await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+}
@@ -0,0 +1,94 @@
+use super::auth;
+use crate::{ApiTokenScope, ApiTokenStore};
+use interledger_service_util::CircuitBreakers;
+use warp::{self, Filter};
+
+/// Admin-only endpoint which serves the state of the accounts' circuit breakers
+pub fn circuit_breakers_api<S>(
+    admin_api_token: String,
+    store: S,
+    circuit_breakers: CircuitBreakers,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: ApiTokenStore + Clone + Send + Sync + 'static,
+{
+    let admin_only = auth::admin_only(admin_api_token, store, ApiTokenScope::ReadOnly);
+
+    // GET /circuit-breakers
+    warp::get()
+        .and(warp::path("circuit-breakers"))
+        .and(warp::path::end())
+        .and(admin_only)
+        .map(move || warp::reply::json(&circuit_breakers.status()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::test_helpers::{
+        api_call, test_circuit_breakers_api, TestAccount, TestStore, OTHER_ACCOUNT_API_TOKEN,
+        READ_ONLY_API_TOKEN,
+    };
+    use interledger_packet::{Address, ErrorCode, PrepareBuilder, RejectBuilder};
+    use interledger_service::{outgoing_service_fn, OutgoingRequest, OutgoingService};
+    use interledger_service_util::{CircuitBreakerService, CircuitBreakerSettings};
+    use serde_json::Value;
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test]
+    async fn gets_circuit_breakers() {
+        let mut service = CircuitBreakerService::new(
+            TestStore,
+            Some(CircuitBreakerSettings {
+                failure_threshold: 1,
+                cool_down: 60_000,
+            }),
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::T01_PEER_UNREACHABLE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let result = service
+            .send_request(OutgoingRequest {
+                from: TestAccount,
+                to: TestAccount,
+                original_amount: 100,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    execution_condition: &[1; 32],
+                    data: &[],
+                }
+                .build(),
+            })
+            .await;
+        assert!(result.is_err());
+
+        let api = test_circuit_breakers_api(service.circuit_breakers());
+        let resp = api_call(&api, "GET", "/circuit-breakers", READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body[0]["username"], "alice");
+        assert_eq!(body[0]["state"], "open");
+        assert_eq!(body[0]["consecutive_failures"], 1);
+        assert!(body[0]["retry_in"].as_u64().unwrap() > 0);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/circuit-breakers",
+            OTHER_ACCOUNT_API_TOKEN,
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = api_call(&api, "GET", "/circuit-breakers", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+}
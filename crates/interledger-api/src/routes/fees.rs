@@ -0,0 +1,247 @@
+use super::{audit, auth};
+use crate::{
+    ApiTokenScope, ApiTokenStore, AuditAction, AuditActor, AuditLogEntry, AuditLogStore, NodeStore,
+};
+use interledger_errors::*;
+use interledger_http::deserialize_json;
+use interledger_service::{Account, Username};
+use interledger_service_util::{FeeSchedule, FeeSchedules, FeeStore};
+use num_rational::BigRational;
+use serde::Serialize;
+use serde_json::json;
+use std::{collections::HashMap, str::FromStr};
+use uuid::Uuid;
+use warp::{self, reply::Json, Filter, Rejection};
+
+/// The fees earned on the packets received from an account
+#[derive(Serialize)]
+struct FeeRevenue {
+    account_id: Uuid,
+    /// The account's details are omitted if it was deleted since
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset_scale: Option<u8>,
+    amount: u64,
+}
+
+/// Normalizes the usernames the schedules apply to, which are matched case-insensitively
+/// like the accounts' usernames
+fn normalize_usernames(
+    schedules: HashMap<String, FeeSchedule>,
+) -> Result<HashMap<String, FeeSchedule>, ApiError> {
+    schedules
+        .into_iter()
+        .map(|(username, schedule)| {
+            let username = Username::from_str(&username).map_err(|_| {
+                ApiError::bad_request().detail(format!("Invalid username: {}", username))
+            })?;
+            Ok((username.to_lowercase(), schedule))
+        })
+        .collect()
+}
+
+/// Checks the fee schedules and normalizes the usernames they apply to
+fn validate_fee_schedules(schedules: FeeSchedules) -> Result<FeeSchedules, ApiError> {
+    for (pair, _) in schedules.asset_pairs.iter() {
+        let assets: Vec<&str> = pair.split('/').collect();
+        if assets.len() != 2 || assets.iter().any(|asset_code| asset_code.is_empty()) {
+            return Err(ApiError::bad_request().detail(format!(
+                "Invalid asset pair: {}. Asset pairs are written as <incoming asset code>/<outgoing asset code>",
+                pair
+            )));
+        }
+    }
+    let schedules = FeeSchedules {
+        incoming_accounts: normalize_usernames(schedules.incoming_accounts)?,
+        outgoing_accounts: normalize_usernames(schedules.outgoing_accounts)?,
+        asset_pairs: schedules.asset_pairs,
+    };
+    let zero = BigRational::from_integer(0.into());
+    let negative = |schedule: &FeeSchedule| schedule.percentage < zero;
+    if schedules
+        .incoming_accounts
+        .values()
+        .chain(schedules.outgoing_accounts.values())
+        .chain(schedules.asset_pairs.values())
+        .any(negative)
+    {
+        return Err(ApiError::bad_request().detail("Fee percentages cannot be negative"));
+    }
+    Ok(schedules)
+}
+
+/// Admin-only endpoints which manage the fee schedules and serve the fees earned
+pub fn fees_api<S, A>(
+    admin_api_token: String,
+    store: S,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: NodeStore<Account = A> + FeeStore + ApiTokenStore + AuditLogStore,
+    A: Account + Send + Sync + 'static,
+{
+    // Helper filters
+    let store_clone = store.clone();
+    let admin_actor = move |scope: ApiTokenScope| {
+        auth::admin_actor(admin_api_token.clone(), store_clone.clone(), scope)
+    };
+    let with_store = warp::any().map(move || store.clone());
+
+    // GET /fees
+    let get_fees = warp::get()
+        .and(warp::path("fees"))
+        .and(warp::path::end())
+        .and(admin_actor(ApiTokenScope::ReadOnly))
+        .and(with_store.clone())
+        .map(|_actor: AuditActor, store: S| warp::reply::json(&*store.get_fee_schedules()));
+
+    // PUT /fees
+    let put_fees = warp::put()
+        .and(warp::path("fees"))
+        .and(warp::path::end())
+        .and(admin_actor(ApiTokenScope::ManageRates))
+        .and(deserialize_json())
+        .and(with_store.clone())
+        .and_then(
+            |actor: AuditActor, schedules: FeeSchedules, store: S| async move {
+                let schedules = validate_fee_schedules(schedules)?;
+                let before = store.get_fee_schedules();
+                store.set_fee_schedules(schedules.clone()).await?;
+                let entry = AuditLogEntry::new(
+                    &actor,
+                    AuditAction::SetFees,
+                    None,
+                    Some(json!(*before)),
+                    Some(json!(schedules)),
+                );
+                audit::record(&store, entry).await;
+                Ok::<Json, Rejection>(warp::reply::json(&schedules))
+            },
+        );
+
+    // GET /fees/revenue
+    let get_revenue = warp::get()
+        .and(warp::path("fees"))
+        .and(warp::path("revenue"))
+        .and(warp::path::end())
+        .and(admin_actor(ApiTokenScope::ReadOnly))
+        .and(with_store)
+        .and_then(|_actor: AuditActor, store: S| async move {
+            let revenue = store.get_fee_revenue().await?;
+            let accounts: HashMap<Uuid, A> = store
+                .get_all_accounts()
+                .await?
+                .into_iter()
+                .map(|account| (account.id(), account))
+                .collect();
+            let mut revenue: Vec<FeeRevenue> = revenue
+                .into_iter()
+                .map(|(account_id, amount)| {
+                    let account = accounts.get(&account_id);
+                    FeeRevenue {
+                        account_id,
+                        username: account.map(|account| account.username().to_string()),
+                        asset_code: account.map(|account| account.asset_code().to_string()),
+                        asset_scale: account.map(|account| account.asset_scale()),
+                        amount,
+                    }
+                })
+                .collect();
+            revenue.sort_by(|a, b| a.username.cmp(&b.username));
+            Ok::<Json, Rejection>(warp::reply::json(&revenue))
+        });
+
+    get_fees.or(put_fees).or(get_revenue)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::test_helpers::{
+        api_call, audit_log, test_fees_api, MANAGE_RATES_API_TOKEN, OTHER_ACCOUNT_API_TOKEN,
+        READ_ONLY_API_TOKEN,
+    };
+    use crate::AuditAction;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn gets_fee_schedules() {
+        let api = test_fees_api();
+        let resp = api_call(&api, "GET", "/fees", READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(
+            body["incoming_accounts"]["alice"],
+            json!({ "fixed": 1, "percentage": "0.25", "minimum": 0 })
+        );
+
+        let resp = api_call(&api, "GET", "/fees", OTHER_ACCOUNT_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = api_call(&api, "GET", "/fees", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn sets_fee_schedules() {
+        let api = test_fees_api();
+        let schedules = json!({
+            "incoming_accounts": { "Bob": { "fixed": 2 } },
+            "asset_pairs": { "ABC/XYZ": { "percentage": 0.1, "minimum": 5 } },
+        });
+        let resp = api_call(
+            &api,
+            "PUT",
+            "/fees",
+            MANAGE_RATES_API_TOKEN,
+            Some(schedules.clone()),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        // usernames are normalized
+        assert_eq!(
+            body["incoming_accounts"]["bob"],
+            json!({ "fixed": 2, "percentage": "0", "minimum": 0 })
+        );
+        assert_eq!(
+            body["asset_pairs"]["ABC/XYZ"],
+            json!({ "fixed": 0, "percentage": "0.1", "minimum": 5 })
+        );
+        assert!(audit_log()
+            .iter()
+            .any(|entry| entry.action == AuditAction::SetFees
+                && entry.after.as_ref() == Some(&body)
+                && entry.before.as_ref().unwrap()["incoming_accounts"]["alice"]["fixed"] == 1));
+
+        let resp = api_call(&api, "PUT", "/fees", READ_ONLY_API_TOKEN, Some(schedules)).await;
+        assert_eq!(resp.status().as_u16(), 403);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_fee_schedules() {
+        let api = test_fees_api();
+        for schedules in &[
+            json!({ "asset_pairs": { "ABC": { "fixed": 1 } } }),
+            json!({ "asset_pairs": { "ABC/": { "fixed": 1 } } }),
+            json!({ "outgoing_accounts": { "not a username": { "fixed": 1 } } }),
+            json!({ "outgoing_accounts": { "bob": { "percentage": "-1" } } }),
+            json!({ "outgoing_accounts": { "bob": { "fixed": -1 } } }),
+        ] {
+            let resp = api_call(&api, "PUT", "/fees", "admin", Some(schedules.clone())).await;
+            assert_eq!(resp.status().as_u16(), 400, "{}", schedules);
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_fee_revenue() {
+        let api = test_fees_api();
+        let resp = api_call(&api, "GET", "/fees/revenue", READ_ONLY_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body[0]["amount"], 1000);
+
+        let resp = api_call(&api, "GET", "/fees/revenue", OTHER_ACCOUNT_API_TOKEN, None).await;
+        assert_eq!(resp.status().as_u16(), 403);
+    }
+}
@@ -1,7 +1,19 @@
 mod accounts;
+mod api_tokens;
+mod audit;
+mod auth;
+mod capture;
+mod circuit_breakers;
+mod fees;
 mod node_settings;
+mod payment_jobs;
 
 pub use accounts::accounts_api;
+pub use api_tokens::api_tokens_api;
+pub use audit::audit_api;
+pub use capture::capture_api;
+pub use circuit_breakers::circuit_breakers_api;
+pub use fees::fees_api;
 pub use node_settings::node_settings_api;
 
 #[cfg(test)]
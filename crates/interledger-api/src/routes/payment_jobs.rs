@@ -0,0 +1,228 @@
+use interledger_stream::{unix_timestamp, PaymentHandle, StreamDelivery};
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Number of finished jobs kept for later retrieval, after which the oldest are forgotten.
+/// Their outcome is still recorded in the account's payment history
+const MAX_FINISHED_JOBS: usize = 1000;
+
+/// State of a payment sent in the background
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentJobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// A payment sent in the background, as returned by the API
+#[derive(Clone, Debug, Serialize)]
+pub struct PaymentJob {
+    /// Id of the job, which is also the id of the payment in the account's payment history
+    pub id: Uuid,
+    #[serde(skip)]
+    pub account_id: Uuid,
+    pub status: PaymentJobStatus,
+    /// Whether the job was asked to stop. It keeps running until the packets in flight
+    /// are fulfilled or rejected
+    pub cancel_requested: bool,
+    pub receiver: String,
+    /// Amount to send, in the account's asset
+    pub source_amount: u64,
+    /// Amount fulfilled or currently in flight, in the account's asset
+    pub sent_amount: u64,
+    /// Amount in flight, in the account's asset
+    pub in_flight_amount: u64,
+    /// Amount delivered to the receiver, in the receiver's asset
+    pub delivered_amount: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_asset_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_asset_scale: Option<u8>,
+    /// Why the payment failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// UNIX timestamp, in seconds, of when the job was created
+    pub created_at: u64,
+    /// UNIX timestamp, in seconds, of when the payment completed, failed or was cancelled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+impl PaymentJob {
+    fn update_progress(&mut self, progress: &StreamDelivery) {
+        self.sent_amount = progress.sent_amount;
+        self.in_flight_amount = progress.in_flight_amount;
+        self.delivered_amount = progress.delivered_amount;
+        self.destination_asset_code = progress.destination_asset_code.clone();
+        self.destination_asset_scale = progress.destination_asset_scale;
+    }
+}
+
+struct JobEntry {
+    job: PaymentJob,
+    handle: PaymentHandle,
+}
+
+#[derive(Default)]
+struct PaymentJobsData {
+    jobs: HashMap<Uuid, JobEntry>,
+    /// Ids of the finished jobs, from the oldest to the most recent
+    finished: VecDeque<Uuid>,
+}
+
+/// Registry of the payments sent in the background, which tracks their
+/// progress while they run and keeps their final state once they finish
+#[derive(Clone, Default)]
+pub struct PaymentJobs {
+    data: Arc<RwLock<PaymentJobsData>>,
+}
+
+impl PaymentJobs {
+    /// Registers a new running job, and returns it along with the handle to send its payment with
+    pub fn start(
+        &self,
+        account_id: Uuid,
+        receiver: String,
+        source_amount: u64,
+    ) -> (PaymentJob, PaymentHandle) {
+        let job = PaymentJob {
+            id: Uuid::new_v4(),
+            account_id,
+            status: PaymentJobStatus::Running,
+            cancel_requested: false,
+            receiver,
+            source_amount,
+            sent_amount: 0,
+            in_flight_amount: 0,
+            delivered_amount: 0,
+            destination_asset_code: None,
+            destination_asset_scale: None,
+            error: None,
+            created_at: unix_timestamp(),
+            finished_at: None,
+        };
+        let handle = PaymentHandle::new();
+        self.data.write().jobs.insert(
+            job.id,
+            JobEntry {
+                job: job.clone(),
+                handle: handle.clone(),
+            },
+        );
+        (job, handle)
+    }
+
+    /// Returns the current state of the account's job
+    pub fn get(&self, account_id: Uuid, id: Uuid) -> Option<PaymentJob> {
+        let data = self.data.read();
+        let entry = data
+            .jobs
+            .get(&id)
+            .filter(|entry| entry.job.account_id == account_id)?;
+        let mut job = entry.job.clone();
+        if job.status == PaymentJobStatus::Running {
+            if let Some(progress) = entry.handle.progress() {
+                job.update_progress(&progress);
+            }
+        }
+        Some(job)
+    }
+
+    /// Asks the account's job to stop sending and returns its current state
+    pub fn cancel(&self, account_id: Uuid, id: Uuid) -> Option<PaymentJob> {
+        {
+            let mut data = self.data.write();
+            let entry = data
+                .jobs
+                .get_mut(&id)
+                .filter(|entry| entry.job.account_id == account_id)?;
+            if entry.job.status == PaymentJobStatus::Running {
+                entry.job.cancel_requested = true;
+                entry.handle.cancel();
+            }
+        }
+        self.get(account_id, id)
+    }
+
+    /// Records the outcome of the job's payment
+    pub fn finish<E: ToString>(&self, id: Uuid, result: &Result<StreamDelivery, E>) {
+        let mut data = self.data.write();
+        if let Some(entry) = data.jobs.get_mut(&id) {
+            let job = &mut entry.job;
+            if let Some(progress) = entry.handle.progress() {
+                job.update_progress(&progress);
+            }
+            job.status = match result {
+                Ok(receipt) => {
+                    job.update_progress(receipt);
+                    PaymentJobStatus::Completed
+                }
+                Err(_) if entry.handle.is_cancelled() => PaymentJobStatus::Cancelled,
+                Err(_) => PaymentJobStatus::Failed,
+            };
+            job.error = result.as_ref().err().map(ToString::to_string);
+            job.finished_at = Some(unix_timestamp());
+        } else {
+            return;
+        }
+
+        data.finished.push_back(id);
+        while data.finished.len() > MAX_FINISHED_JOBS {
+            if let Some(oldest) = data.finished.pop_front() {
+                data.jobs.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_job_until_finished() {
+        let jobs = PaymentJobs::default();
+        let account_id = Uuid::new_v4();
+        let (job, handle) = jobs.start(account_id, "$example.com".to_string(), 100);
+        assert_eq!(job.status, PaymentJobStatus::Running);
+
+        // Other accounts cannot see or cancel the job
+        assert!(jobs.get(Uuid::new_v4(), job.id).is_none());
+        assert!(jobs.cancel(Uuid::new_v4(), job.id).is_none());
+        assert!(!handle.is_cancelled());
+
+        let cancelled = jobs.cancel(account_id, job.id).unwrap();
+        assert!(cancelled.cancel_requested);
+        assert_eq!(cancelled.status, PaymentJobStatus::Running);
+        assert!(handle.is_cancelled());
+
+        jobs.finish::<String>(job.id, &Err("Payment was cancelled".to_string()));
+        let finished = jobs.get(account_id, job.id).unwrap();
+        assert_eq!(finished.status, PaymentJobStatus::Cancelled);
+        assert_eq!(finished.error.as_deref(), Some("Payment was cancelled"));
+        assert!(finished.finished_at.is_some());
+    }
+
+    #[test]
+    fn forgets_oldest_finished_jobs() {
+        let jobs = PaymentJobs::default();
+        let account_id = Uuid::new_v4();
+        let ids: Vec<Uuid> = (0..=MAX_FINISHED_JOBS)
+            .map(|_| {
+                let (job, _) = jobs.start(account_id, "$example.com".to_string(), 100);
+                jobs.finish::<String>(job.id, &Err("failed".to_string()));
+                job.id
+            })
+            .collect();
+        assert!(jobs.get(account_id, ids[0]).is_none());
+        assert_eq!(
+            jobs.get(account_id, ids[MAX_FINISHED_JOBS]).unwrap().status,
+            PaymentJobStatus::Failed
+        );
+    }
+}
@@ -1,6 +1,11 @@
 use crate::{
-    routes::{accounts_api, node_settings_api},
-    AccountDetails, AccountSettings, NodeStore,
+    hash_api_token,
+    routes::{
+        accounts_api, api_tokens_api, audit_api, capture_api, circuit_breakers_api, fees_api,
+        node_settings_api,
+    },
+    AccountDetails, AccountSettings, ApiToken, ApiTokenScope, ApiTokenStore, AuditAction,
+    AuditActor, AuditLogEntry, AuditLogStore, AuditQuery, NodeStore,
 };
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -12,20 +17,31 @@ use interledger_errors::*;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_packet::{Address, ErrorCode, FulfillBuilder, RejectBuilder};
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, PrefixMap, RouterStore};
 use interledger_service::{
     incoming_service_fn, outgoing_service_fn, Account, AccountStore, AddressStore, Username,
 };
-use interledger_service_util::BalanceStore;
-use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_service_util::{
+    BalanceStore, CircuitBreakerAccount, CircuitBreakers, FeeSchedule, FeeSchedules, FeeStore,
+    PacketCapture,
+};
+use interledger_settlement::core::types::{
+    OutgoingSettlement, SettlementAccount, SettlementEngineDetails,
+};
+use interledger_stream::{
+    PaymentDirection, PaymentHistoryStore, PaymentNotification, PaymentQuery, PaymentRecord,
+    PaymentStatus, StreamConnectionDetails, StreamConnectionStore, StreamNotificationsStore,
+};
+use num_rational::BigRational;
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 use uuid::Uuid;
 use warp::{self, Filter};
@@ -58,6 +74,34 @@ pub fn test_node_settings_api(
     node_settings_api("admin".to_owned(), None, TestStore).recover(default_rejection_handler)
 }
 
+pub fn test_api_tokens_api(
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    api_tokens_api("admin".to_owned(), TestStore).recover(default_rejection_handler)
+}
+
+pub fn test_audit_api(
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    audit_api("admin".to_owned(), TestStore).recover(default_rejection_handler)
+}
+
+pub fn test_circuit_breakers_api(
+    circuit_breakers: CircuitBreakers,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    circuit_breakers_api("admin".to_owned(), TestStore, circuit_breakers)
+        .recover(default_rejection_handler)
+}
+
+pub fn test_capture_api(
+    capture: PacketCapture,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    capture_api("admin".to_owned(), TestStore, capture).recover(default_rejection_handler)
+}
+
+pub fn test_fees_api(
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    fees_api("admin".to_owned(), TestStore).recover(default_rejection_handler)
+}
+
 pub fn test_accounts_api(
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     let incoming = incoming_service_fn(|_request| {
@@ -82,7 +126,7 @@ pub fn test_accounts_api(
     );
     let store = TestStore;
     accounts_api(
-        Bytes::from("admin"),
+        Bytes::from(&[0; 32][..]),
         "admin".to_owned(),
         None,
         incoming,
@@ -99,9 +143,10 @@ pub fn test_accounts_api(
  */
 
 #[derive(Clone)]
-struct TestStore;
+pub struct TestStore;
 
 use serde_json::json;
+pub static ACCOUNT_ID: Lazy<Uuid> = Lazy::new(Uuid::new_v4);
 pub static USERNAME: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
 pub static EXAMPLE_ADDRESS: Lazy<Address> =
     Lazy::new(|| Address::from_str("example.alice").unwrap());
@@ -116,12 +161,72 @@ pub static DETAILS: Lazy<Option<Value>> = Lazy::new(|| {
 });
 const AUTH_PASSWORD: &str = "password";
 
+/// API token with the read-only scope
+pub const READ_ONLY_API_TOKEN: &str = "ilp_api_read_only";
+/// API token with the manage-rates scope
+pub const MANAGE_RATES_API_TOKEN: &str = "ilp_api_manage_rates";
+/// API token with every scope, which only acts for an account other than alice
+pub const OTHER_ACCOUNT_API_TOKEN: &str = "ilp_api_other_account";
+/// API token with every scope, which expired
+pub const EXPIRED_API_TOKEN: &str = "ilp_api_expired";
+
+/// Entries appended to the audit log by all of the tests
+static AUDIT_LOG: Lazy<Mutex<Vec<AuditLogEntry>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Returns the entries appended to the audit log so far, from the oldest to the most recent.
+/// Tests run concurrently, so this may include the entries of other tests
+pub fn audit_log() -> Vec<AuditLogEntry> {
+    AUDIT_LOG.lock().clone()
+}
+
+static API_TOKENS: Lazy<Vec<(&str, ApiToken)>> = Lazy::new(|| {
+    let all_scopes = vec![
+        ApiTokenScope::ReadOnly,
+        ApiTokenScope::Pay,
+        ApiTokenScope::ManageAccounts,
+        ApiTokenScope::ManageRoutes,
+        ApiTokenScope::ManageRates,
+    ];
+    let api_token = |scopes: Vec<ApiTokenScope>| ApiToken {
+        id: Uuid::new_v4(),
+        account_id: None,
+        scopes,
+        expires_at: None,
+        created_at: 0,
+        description: None,
+    };
+    vec![
+        (
+            READ_ONLY_API_TOKEN,
+            api_token(vec![ApiTokenScope::ReadOnly]),
+        ),
+        (
+            MANAGE_RATES_API_TOKEN,
+            api_token(vec![ApiTokenScope::ManageRates]),
+        ),
+        (
+            OTHER_ACCOUNT_API_TOKEN,
+            ApiToken {
+                account_id: Some(Uuid::new_v4()),
+                ..api_token(all_scopes.clone())
+            },
+        ),
+        (
+            EXPIRED_API_TOKEN,
+            ApiToken {
+                expires_at: Some(1),
+                ..api_token(all_scopes)
+            },
+        ),
+    ]
+});
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TestAccount;
 
 impl Account for TestAccount {
     fn id(&self) -> Uuid {
-        Uuid::new_v4()
+        *ACCOUNT_ID
     }
 
     fn username(&self) -> &Username {
@@ -167,6 +272,8 @@ impl SettlementAccount for TestAccount {
     }
 }
 
+impl CircuitBreakerAccount for TestAccount {}
+
 impl CcpRoutingAccount for TestAccount {
     fn routing_relation(&self) -> RoutingRelation {
         RoutingRelation::NonRoutingAccount
@@ -189,7 +296,7 @@ impl AccountStore for TestStore {
         &self,
         _username: &Username,
     ) -> Result<Uuid, AccountStoreError> {
-        Ok(Uuid::new_v4())
+        Ok(*ACCOUNT_ID)
     }
 }
 
@@ -197,28 +304,33 @@ impl ExchangeRateStore for TestStore {
     fn get_exchange_rates(
         &self,
         _asset_codes: &[&str],
-    ) -> Result<Vec<f64>, ExchangeRateStoreError> {
-        Ok(vec![1.0, 2.0])
+    ) -> Result<Vec<BigRational>, ExchangeRateStoreError> {
+        Ok(vec![
+            BigRational::from_integer(1.into()),
+            BigRational::from_integer(2.into()),
+        ])
     }
 
     fn set_exchange_rates(
         &self,
-        _rates: HashMap<String, f64>,
+        _rates: HashMap<String, BigRational>,
     ) -> Result<(), ExchangeRateStoreError> {
         Ok(())
     }
 
-    fn get_all_exchange_rates(&self) -> Result<HashMap<String, f64>, ExchangeRateStoreError> {
+    fn get_all_exchange_rates(
+        &self,
+    ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError> {
         let mut ret = HashMap::new();
-        ret.insert("ABC".to_owned(), 1.0);
-        ret.insert("XYZ".to_owned(), 2.0);
+        ret.insert("ABC".to_owned(), BigRational::from_integer(1.into()));
+        ret.insert("XYZ".to_owned(), BigRational::from_integer(2.into()));
         Ok(ret)
     }
 }
 
 impl RouterStore for TestStore {
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-        Arc::new(HashMap::new())
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+        Arc::new(PrefixMap::new())
     }
 }
 
@@ -264,6 +376,13 @@ impl NodeStore for TestStore {
         Ok(())
     }
 
+    async fn set_static_multipath_routes<R>(&self, _routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    {
+        Ok(())
+    }
+
     async fn set_static_route(
         &self,
         _prefix: String,
@@ -324,6 +443,181 @@ impl StreamNotificationsStore for TestStore {
     }
 }
 
+#[async_trait]
+impl StreamConnectionStore for TestStore {
+    async fn create_stream_connection(
+        &self,
+        _connection_tag: &str,
+        _receive_max: Option<u64>,
+    ) -> Result<(), StreamConnectionStoreError> {
+        Ok(())
+    }
+
+    async fn get_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        if connection_tag == "unknown" {
+            return Ok(None);
+        }
+        Ok(Some(StreamConnectionDetails {
+            total_received: 100,
+            receive_max: Some(1000),
+            closed: false,
+            last_activity: 0,
+        }))
+    }
+
+    async fn add_stream_connection_amount(
+        &self,
+        _connection_tag: &str,
+        _amount: u64,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        unimplemented!()
+    }
+
+    async fn close_stream_connection(
+        &self,
+        _connection_tag: &str,
+    ) -> Result<(), StreamConnectionStoreError> {
+        unimplemented!()
+    }
+
+    async fn delete_idle_stream_connections(
+        &self,
+        _idle_timeout: Duration,
+    ) -> Result<(), StreamConnectionStoreError> {
+        unimplemented!()
+    }
+}
+
+#[async_trait]
+impl PaymentHistoryStore for TestStore {
+    async fn save_payment(&self, _payment: PaymentRecord) -> Result<(), PaymentHistoryStoreError> {
+        Ok(())
+    }
+
+    async fn get_payments(
+        &self,
+        account_id: Uuid,
+        query: PaymentQuery,
+    ) -> Result<Vec<PaymentRecord>, PaymentHistoryStoreError> {
+        let payment = PaymentRecord {
+            id: Uuid::new_v4(),
+            account_id,
+            direction: PaymentDirection::Incoming,
+            status: PaymentStatus::Completed,
+            destination: EXAMPLE_ADDRESS.clone(),
+            counterparty: "bob".to_string(),
+            amount: 100,
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            delivered_amount: None,
+            delivered_asset_code: None,
+            delivered_asset_scale: None,
+            timestamp: 1000,
+        };
+        Ok(Some(payment)
+            .into_iter()
+            .filter(|payment| query.matches(payment))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ApiTokenStore for TestStore {
+    async fn insert_api_token(
+        &self,
+        _token: ApiToken,
+        _token_hash: [u8; 32],
+    ) -> Result<(), ApiTokenStoreError> {
+        Ok(())
+    }
+
+    async fn get_api_token(
+        &self,
+        token_hash: [u8; 32],
+    ) -> Result<Option<ApiToken>, ApiTokenStoreError> {
+        Ok(API_TOKENS
+            .iter()
+            .find(|(token, _)| hash_api_token(token) == token_hash)
+            .map(|(_, api_token)| api_token.clone()))
+    }
+
+    async fn get_all_api_tokens(&self) -> Result<Vec<ApiToken>, ApiTokenStoreError> {
+        Ok(API_TOKENS
+            .iter()
+            .map(|(_, api_token)| api_token.clone())
+            .collect())
+    }
+
+    async fn delete_api_token(&self, id: Uuid) -> Result<ApiToken, ApiTokenStoreError> {
+        API_TOKENS
+            .iter()
+            .find(|(_, api_token)| api_token.id == id)
+            .map(|(_, api_token)| api_token.clone())
+            .ok_or_else(|| ApiTokenStoreError::TokenNotFound(id.to_string()))
+    }
+}
+
+#[async_trait]
+impl AuditLogStore for TestStore {
+    async fn append_audit_entry(&self, entry: AuditLogEntry) -> Result<(), AuditLogStoreError> {
+        AUDIT_LOG.lock().push(entry);
+        Ok(())
+    }
+
+    async fn get_audit_entries(
+        &self,
+        query: AuditQuery,
+    ) -> Result<Vec<AuditLogEntry>, AuditLogStoreError> {
+        let entry = AuditLogEntry {
+            timestamp: 1000,
+            ..AuditLogEntry::new(
+                &AuditActor::Admin,
+                AuditAction::SetRates,
+                None,
+                Some(json!({ "XYZ": 1.0 })),
+                Some(json!({ "XYZ": 2.0 })),
+            )
+        };
+        Ok(Some(entry)
+            .into_iter()
+            .filter(|entry| query.matches(entry))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl FeeStore for TestStore {
+    fn get_fee_schedules(&self) -> Arc<FeeSchedules> {
+        let mut schedules = FeeSchedules::default();
+        schedules.incoming_accounts.insert(
+            "alice".to_owned(),
+            FeeSchedule {
+                fixed: 1,
+                percentage: BigRational::new(1.into(), 4.into()),
+                minimum: 0,
+            },
+        );
+        Arc::new(schedules)
+    }
+
+    async fn set_fee_schedules(&self, _schedules: FeeSchedules) -> Result<(), FeeStoreError> {
+        Ok(())
+    }
+
+    async fn record_fee_revenue(&self, _: Uuid, _fee: u64) -> Result<(), FeeStoreError> {
+        unimplemented!()
+    }
+
+    async fn get_fee_revenue(&self) -> Result<HashMap<Uuid, u64>, FeeStoreError> {
+        let mut revenue = HashMap::new();
+        revenue.insert(Uuid::new_v4(), 1000);
+        Ok(revenue)
+    }
+}
+
 #[async_trait]
 impl BalanceStore for TestStore {
     async fn get_balance(&self, _: Uuid) -> Result<i64, BalanceStoreError> {
@@ -346,6 +640,15 @@ impl BalanceStore for TestStore {
         unimplemented!()
     }
 
+    async fn update_balances_for_fulfill_with_settlement(
+        &self,
+        _: Uuid,
+        _outgoing_amount: u64,
+        _settlement: OutgoingSettlement,
+    ) -> Result<(i64, Option<OutgoingSettlement>), BalanceStoreError> {
+        unimplemented!()
+    }
+
     async fn update_balances_for_reject(
         &self,
         _: Uuid,
@@ -0,0 +1,196 @@
+use async_trait::async_trait;
+use interledger_errors::ApiTokenStoreError;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use uuid::Uuid;
+
+/// Prefix of the API tokens issued by the node, which tells them apart from accounts' tokens
+pub const API_TOKEN_PREFIX: &str = "ilp_api_";
+
+/// What an API token allows its bearer to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiTokenScope {
+    /// View accounts, balances, payments and the node's settings.
+    /// Every other scope allows this too
+    ReadOnly,
+    /// Send payments and create STREAM connections to receive them
+    Pay,
+    /// Create, update and delete accounts, and configure settlement engines
+    ManageAccounts,
+    /// Set the static routes
+    ManageRoutes,
+    /// Set the exchange rates
+    ManageRates,
+}
+
+impl ApiTokenScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiTokenScope::ReadOnly => "read-only",
+            ApiTokenScope::Pay => "pay",
+            ApiTokenScope::ManageAccounts => "manage-accounts",
+            ApiTokenScope::ManageRoutes => "manage-routes",
+            ApiTokenScope::ManageRates => "manage-rates",
+        }
+    }
+}
+
+impl fmt::Display for ApiTokenScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ApiTokenScope {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "read-only" => Ok(ApiTokenScope::ReadOnly),
+            "pay" => Ok(ApiTokenScope::Pay),
+            "manage-accounts" => Ok(ApiTokenScope::ManageAccounts),
+            "manage-routes" => Ok(ApiTokenScope::ManageRoutes),
+            "manage-rates" => Ok(ApiTokenScope::ManageRates),
+            _ => Err(format!("Invalid API token scope: {}", src)),
+        }
+    }
+}
+
+/// An API token issued by the node's administrator. Only the token's hash is stored,
+/// the token itself is returned once when it is created
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ApiToken {
+    pub id: Uuid,
+    /// The only account the token acts for. Tokens without an account act for every account
+    /// and may also change the node's settings, as allowed by their scopes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<Uuid>,
+    pub scopes: Vec<ApiTokenScope>,
+    /// UNIX timestamp, in seconds, after which the token is no longer accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// UNIX timestamp, in seconds, of when the token was created
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl ApiToken {
+    /// Whether the token allows an action which requires the given scope
+    pub fn allows(&self, scope: ApiTokenScope) -> bool {
+        scope == ApiTokenScope::ReadOnly || self.scopes.contains(&scope)
+    }
+
+    /// Whether the token may act for the given account
+    pub fn allows_account(&self, account_id: Uuid) -> bool {
+        self.account_id.map_or(true, |id| id == account_id)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| now >= expires_at)
+    }
+}
+
+/// Generates a new random API token, and returns it along with its hash
+pub fn generate_api_token() -> (String, [u8; 32]) {
+    let mut bytes = [0; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Unable to generate a random API token");
+    let token = format!(
+        "{}{}",
+        API_TOKEN_PREFIX,
+        base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+    );
+    let hash = hash_api_token(&token);
+    (token, hash)
+}
+
+/// The SHA-256 hash of the API token, which is what the store keeps
+pub fn hash_api_token(token: &str) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(digest(&SHA256, token.as_bytes()).as_ref());
+    hash
+}
+
+/// Store trait for the API tokens issued by the node's administrator
+#[async_trait]
+pub trait ApiTokenStore {
+    /// Saves the API token along with the hash it is looked up by
+    async fn insert_api_token(
+        &self,
+        token: ApiToken,
+        token_hash: [u8; 32],
+    ) -> Result<(), ApiTokenStoreError>;
+
+    /// Loads the API token with the given hash, if any
+    async fn get_api_token(
+        &self,
+        token_hash: [u8; 32],
+    ) -> Result<Option<ApiToken>, ApiTokenStoreError>;
+
+    /// Loads all of the API tokens
+    async fn get_all_api_tokens(&self) -> Result<Vec<ApiToken>, ApiTokenStoreError>;
+
+    /// Revokes the API token with the given id and returns it
+    async fn delete_api_token(&self, id: Uuid) -> Result<ApiToken, ApiTokenStoreError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_distinct_tokens() {
+        let (token, hash) = generate_api_token();
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        assert_eq!(hash, hash_api_token(&token));
+        let (other_token, other_hash) = generate_api_token();
+        assert_ne!(token, other_token);
+        assert_ne!(hash, other_hash);
+    }
+
+    #[test]
+    fn checks_scopes_accounts_and_expiry() {
+        let account_id = Uuid::new_v4();
+        let token = ApiToken {
+            id: Uuid::new_v4(),
+            account_id: Some(account_id),
+            scopes: vec![ApiTokenScope::Pay],
+            expires_at: Some(100),
+            created_at: 0,
+            description: None,
+        };
+        assert!(token.allows(ApiTokenScope::Pay));
+        assert!(token.allows(ApiTokenScope::ReadOnly));
+        assert!(!token.allows(ApiTokenScope::ManageAccounts));
+        assert!(token.allows_account(account_id));
+        assert!(!token.allows_account(Uuid::new_v4()));
+        assert!(!token.is_expired(99));
+        assert!(token.is_expired(100));
+    }
+
+    #[test]
+    fn parses_scopes() {
+        for scope in &[
+            ApiTokenScope::ReadOnly,
+            ApiTokenScope::Pay,
+            ApiTokenScope::ManageAccounts,
+            ApiTokenScope::ManageRoutes,
+            ApiTokenScope::ManageRates,
+        ] {
+            assert_eq!(ApiTokenScope::from_str(scope.as_str()), Ok(*scope));
+            assert_eq!(
+                serde_json::to_string(scope).unwrap(),
+                format!("\"{}\"", scope)
+            );
+        }
+        assert!(ApiTokenScope::from_str("root").is_err());
+    }
+}
@@ -1,31 +1,40 @@
 use super::packet::*;
 use super::service::BtpOutgoingService;
 use super::BtpAccount;
-use futures::{future::join_all, SinkExt, StreamExt, TryFutureExt};
+use futures::{future::join_all, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
 use interledger_errors::ApiError;
 use interledger_packet::Address;
 use interledger_service::*;
+use native_tls::TlsConnector;
 use rand::random;
+use std::collections::HashMap;
 use thiserror::Error;
-use tokio_tungstenite::connect_async;
-use tracing::{debug, error, trace};
-use tungstenite::Message;
+use tokio::net::TcpStream;
+use tokio_tls::TlsStream;
+use tokio_tungstenite::{client_async, connect_async, WebSocketStream};
+use tracing::{debug, error, trace, warn};
+use tungstenite::{Error as WsError, Message};
 use url::Url;
 
 /// Create a BtpOutgoingService wrapping BTP connections to the accounts specified.
 /// Calling `handle_incoming` with an `IncomingService` will turn the returned
 /// BtpOutgoingService into a bidirectional handler.
+/// The client certificates, indexed by username, are presented to the accounts whose URL uses WSS.
 pub async fn connect_client<A, S>(
     ilp_address: Address,
     accounts: Vec<A>,
     error_on_unavailable: bool,
     next_outgoing: S,
+    client_certificates: HashMap<Username, ClientCertificate>,
 ) -> Result<BtpOutgoingService<S, A>, BtpClientError>
 where
     S: OutgoingService<A> + Clone + 'static,
     A: BtpAccount + Send + Sync + 'static,
 {
     let service = BtpOutgoingService::new(ilp_address, next_outgoing);
+    for (username, certificate) in client_certificates {
+        service.client_certificate(username, &certificate)?;
+    }
     let mut connect_btp = Vec::new();
     for account in accounts {
         // Can we make this take a reference to a service?
@@ -50,6 +59,8 @@ pub enum BtpClientError {
     Unavailable(String),
     #[error("Could not connect to at least one BTP account ")]
     CannotConnectMultiple,
+    #[error("Invalid client certificate for account {0}: {1}")]
+    InvalidClientCertificate(String, String),
 }
 
 impl From<BtpClientError> for warp::Rejection {
@@ -73,11 +84,11 @@ where
     O: OutgoingService<A> + Clone + 'static,
     A: BtpAccount + Send + Sync + 'static,
 {
-    let account_id = account.id();
-    let mut url = account
+    let account_url = account
         .get_ilp_over_btp_url()
         .expect("Accounts must have BTP URLs")
         .clone();
+    let mut url = account_url.clone();
     if url.scheme().starts_with("btp+") {
         // Re-parse the URL after stripping off the leading "btp+" prefix.
         // We cannot use set_scheme here because the URL specification
@@ -93,20 +104,95 @@ where
         .unwrap_or_default();
     debug!("Connecting to {}", url);
 
-    let (mut connection, _) = connect_async(url.clone())
-        .map_err(|err| {
-            BtpClientError::CannotConnect(
-                account.username().to_string(),
-                url.clone(),
-                err.to_string(),
+    let cannot_connect = |err: String| {
+        BtpClientError::CannotConnect(account.username().to_string(), url.clone(), err)
+    };
+    let connector = service.tls_connector(account.username());
+    match connector {
+        Some(connector) if url.scheme() == "wss" => {
+            let connection = connect_with_certificate(&url, connector)
+                .map_err(cannot_connect)
+                .await?;
+            authenticate(
+                connection,
+                account,
+                account_url,
+                url,
+                token,
+                error_on_unavailable,
+                service,
             )
-        })
-        .await?;
+            .await
+        }
+        connector => {
+            if connector.is_some() {
+                warn!(
+                    "Not presenting the client certificate of account {} because its BTP URL does not use WSS",
+                    account.username()
+                );
+            }
+            let (connection, _) = connect_async(url.clone())
+                .map_err(|err| cannot_connect(err.to_string()))
+                .await?;
+            authenticate(
+                connection,
+                account,
+                account_url,
+                url,
+                token,
+                error_on_unavailable,
+                service,
+            )
+            .await
+        }
+    }
+}
+
+/// Opens a WebSocket connection over TLS which presents the connector's client certificate
+async fn connect_with_certificate(
+    url: &Url,
+    connector: TlsConnector,
+) -> Result<WebSocketStream<TlsStream<TcpStream>>, String> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| "URL has no host".to_string())?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let tcp = TcpStream::connect((host, port))
+        .await
+        .map_err(|err| err.to_string())?;
+    let tls = tokio_tls::TlsConnector::from(connector)
+        .connect(host, tcp)
+        .await
+        .map_err(|err| err.to_string())?;
+    let (connection, _) = client_async(url.clone(), tls)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(connection)
+}
 
+/// Sends the BTP authorization packet on the new connection, and adds it to the service
+async fn authenticate<O, A, W>(
+    mut connection: W,
+    account: A,
+    account_url: Url,
+    url: Url,
+    token: Vec<u8>,
+    error_on_unavailable: bool,
+    service: BtpOutgoingService<O, A>,
+) -> Result<(), BtpClientError>
+where
+    O: OutgoingService<A> + Clone + 'static,
+    A: BtpAccount + Send + Sync + 'static,
+    W: Stream<Item = Result<Message, WsError>>
+        + Sink<Message, Error = WsError>
+        + Unpin
+        + Send
+        + 'static,
+{
     trace!(
         "Connected to account {} (UID: {}) (URI: {}), sending auth packet",
         account.username(),
-        account_id,
+        account.id(),
         url
     );
 
@@ -139,7 +225,7 @@ where
         Ok(_) => {
             debug!("Connected to account {}'s server", account.id());
             let connection = connection.filter_map(|v| async move { v.ok() });
-            service.add_connection(account, connection);
+            service.add_connection(account, Some(account_url), connection);
             Ok(())
         }
         Err(err) => {
@@ -14,13 +14,15 @@ mod client;
 mod errors;
 mod oer;
 mod packet;
+mod reconnect;
 mod server;
 mod service;
 mod wrapped_ws;
 
 pub use self::client::{connect_client, connect_to_service_account};
+pub use self::reconnect::{reconnect_clients, ReconnectOptions};
 pub use self::server::btp_service_as_filter; // This is consumed only by the node.
-pub use self::service::{BtpOutgoingService, BtpService};
+pub use self::service::{BtpConnectionState, BtpOutgoingService, BtpService};
 
 use interledger_errors::BtpStoreError;
 
@@ -57,6 +59,7 @@ mod client_server {
     use net2::TcpBuilder;
     use std::str::FromStr;
     use std::{
+        collections::HashMap,
         net::SocketAddr,
         sync::Arc,
         time::{Duration, SystemTime},
@@ -229,6 +232,7 @@ mod client_server {
                 }
                 .build())
             }),
+            HashMap::new(),
         )
         .await
         .unwrap();
@@ -305,4 +309,122 @@ mod client_server {
 
         btp_service.close();
     }
+
+    #[tokio::test]
+    async fn reconnects_when_the_server_comes_up() {
+        let bind_addr = get_open_port();
+
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_over_btp_url: Some(
+                Url::parse(&format!("btp+ws://{}/accounts/alice/ilp/btp", bind_addr)).unwrap(),
+            ),
+            ilp_over_btp_outgoing_token: Some("test_auth_token".to_string()),
+            ilp_over_btp_incoming_token: None,
+        };
+        let client_store = TestStore {
+            accounts: Arc::new(vec![account.clone()]),
+        };
+        let addr = Address::from_str("example.address").unwrap();
+        let btp_client = BtpOutgoingService::new(
+            addr.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr),
+                }
+                .build())
+            }),
+        );
+        tokio::spawn(reconnect_clients(
+            client_store,
+            btp_client.clone(),
+            ReconnectOptions {
+                initial_backoff: Duration::from_millis(50),
+                max_backoff: Duration::from_millis(100),
+                refresh_interval: Duration::from_secs(10),
+            },
+        ));
+
+        // Nothing is listening yet
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        match btp_client.connection_state(&account.id) {
+            BtpConnectionState::Reconnecting { attempts } => assert!(attempts >= 1),
+            state => panic!("Unexpected connection state {:?}", state),
+        }
+
+        let server_store = TestStore {
+            accounts: Arc::new(vec![TestAccount {
+                id: Uuid::new_v4(),
+                ilp_over_btp_incoming_token: Some("test_auth_token".to_string()),
+                ilp_over_btp_outgoing_token: None,
+                ilp_over_btp_url: None,
+            }]),
+        };
+        let server_address = Address::from_str("example.server").unwrap();
+        let btp_server = BtpOutgoingService::new(
+            server_address.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    triggered_by: Some(&server_address),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        btp_server
+            .clone()
+            .handle_incoming(incoming_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: b"test data",
+                }
+                .build())
+            }))
+            .await;
+        tokio::spawn(
+            warp::serve(btp_service_as_filter(btp_server.clone(), server_store)).bind(bind_addr),
+        );
+
+        tokio::time::delay_for(Duration::from_millis(500)).await;
+        assert_eq!(
+            btp_client.connection_state(&account.id),
+            BtpConnectionState::Connected
+        );
+
+        let mut btp_client = btp_client
+            .handle_incoming(incoming_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: None,
+                }
+                .build())
+            }))
+            .await;
+        let res = btp_client
+            .send_request(OutgoingRequest {
+                from: account.clone(),
+                to: account.clone(),
+                original_amount: 100,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[0; 32],
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    data: b"test data",
+                }
+                .build(),
+            })
+            .await;
+        assert!(res.is_ok());
+
+        btp_client.close();
+        btp_server.close();
+    }
 }
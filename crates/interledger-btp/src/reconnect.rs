@@ -0,0 +1,195 @@
+use super::client::connect_to_service_account;
+use super::service::BtpOutgoingService;
+use super::{BtpAccount, BtpStore};
+use interledger_service::*;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{debug, trace, warn};
+use uuid::Uuid;
+
+/// Settings for reconnecting the BTP client to its outgoing accounts
+#[derive(Clone, Debug)]
+pub struct ReconnectOptions {
+    /// Delay after the first failed attempt to connect to an account.
+    /// It is doubled after every further failure, up to `max_backoff`
+    pub initial_backoff: Duration,
+    /// Longest delay between two attempts to connect to an account
+    pub max_backoff: Duration,
+    /// How often the accounts with an `ilp_over_btp_url` are reloaded from the store,
+    /// so that accounts added or changed through the API are picked up
+    pub refresh_interval: Duration,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        ReconnectOptions {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            refresh_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ReconnectOptions {
+    /// Exponential backoff with "equal jitter": a random delay between
+    /// half of and the full backoff for this number of failed attempts
+    fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        let half = backoff / 2;
+        let jitter = rand::thread_rng().gen_range(0, half.as_millis() as u64 + 1);
+        half + Duration::from_millis(jitter)
+    }
+}
+
+/// Failed attempts to connect to an account
+struct Backoff {
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// Keeps the BTP client connected to all of the store's accounts that have an
+/// `ilp_over_btp_url` configured.
+///
+/// Connections that drop or could not be opened are retried with exponential backoff
+/// and jitter. The accounts are reloaded from the store every `refresh_interval`: new accounts
+/// are connected, the connections to accounts whose URL changed are replaced, and the ones
+/// to accounts which no longer have a URL are closed.
+///
+/// The returned future runs until `close` is called on the service, so it should be spawned.
+pub async fn reconnect_clients<S, O, A>(
+    store: S,
+    service: BtpOutgoingService<O, A>,
+    options: ReconnectOptions,
+) where
+    S: BtpStore<Account = A>,
+    O: OutgoingService<A> + Clone + 'static,
+    A: BtpAccount + Send + Sync + 'static,
+{
+    let mut accounts: Vec<A> = Vec::new();
+    let mut backoffs: HashMap<Uuid, Backoff> = HashMap::new();
+    let mut last_refresh: Option<Instant> = None;
+    let mut interval = time::interval(options.initial_backoff.min(options.refresh_interval));
+
+    loop {
+        interval.tick().await;
+        if service.is_closed() {
+            debug!("BTP service was closed, no longer reconnecting clients");
+            return;
+        }
+
+        let now = Instant::now();
+        let refresh_due = match last_refresh {
+            Some(last_refresh) => now.duration_since(last_refresh) >= options.refresh_interval,
+            None => true,
+        };
+        if refresh_due {
+            match store.get_btp_outgoing_accounts().await {
+                Ok(new_accounts) => {
+                    let new_ids: HashSet<Uuid> =
+                        new_accounts.iter().map(|account| account.id()).collect();
+                    for account in accounts.iter() {
+                        let id = account.id();
+                        if !new_ids.contains(&id) {
+                            // Close the connections we opened to accounts which were deleted
+                            // or no longer have a URL
+                            if service.connection_url(&id).is_some() {
+                                debug!(
+                                    "Account {} no longer has a BTP URL, closing its connection",
+                                    account.username()
+                                );
+                                service.close_connection(&id);
+                            }
+                            backoffs.remove(&id);
+                            service.set_reconnect_attempts(id, 0);
+                        }
+                    }
+                    accounts = new_accounts;
+                    last_refresh = Some(now);
+                }
+                Err(err) => warn!("Error loading the BTP outgoing accounts: {}", err),
+            }
+        }
+
+        for account in accounts.iter() {
+            let id = account.id();
+            if service.is_connected(&id) {
+                let connection_url = service.connection_url(&id);
+                if connection_url.is_none()
+                    || connection_url.as_ref() == account.get_ilp_over_btp_url()
+                {
+                    backoffs.remove(&id);
+                    service.set_reconnect_attempts(id, 0);
+                    continue;
+                }
+                debug!(
+                    "BTP URL of account {} changed, replacing its connection",
+                    account.username()
+                );
+                service.close_connection(&id);
+            }
+
+            if let Some(backoff) = backoffs.get(&id) {
+                if now < backoff.next_attempt {
+                    continue;
+                }
+            }
+
+            trace!("Connecting to account {}", account.username());
+            match connect_to_service_account(account.clone(), true, service.clone()).await {
+                Ok(_) => {
+                    debug!("Connected to account {}", account.username());
+                    backoffs.remove(&id);
+                    service.set_reconnect_attempts(id, 0);
+                }
+                Err(err) => {
+                    let attempts = backoffs.get(&id).map(|b| b.attempts).unwrap_or(0) + 1;
+                    let delay = options.backoff(attempts);
+                    warn!(
+                        "Could not connect to account {} (attempt {}), retrying in {:?}: {}",
+                        account.username(),
+                        attempts,
+                        delay,
+                        err
+                    );
+                    backoffs.insert(
+                        id,
+                        Backoff {
+                            attempts,
+                            next_attempt: Instant::now() + delay,
+                        },
+                    );
+                    service.set_reconnect_attempts(id, attempts);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod backoff {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_the_max_backoff_with_jitter() {
+        let options = ReconnectOptions {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            refresh_interval: Duration::from_secs(10),
+        };
+        for _ in 0..100 {
+            let first = options.backoff(1);
+            assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+            let fourth = options.backoff(4);
+            assert!(fourth >= Duration::from_secs(4) && fourth <= Duration::from_secs(8));
+            let last = options.backoff(100);
+            assert!(last >= Duration::from_secs(30) && last <= Duration::from_secs(60));
+        }
+    }
+}
@@ -86,7 +86,7 @@ where
     // We need to wrap our Warp connection in order to cast the Sink type
     // to tungstenite::Message. This probably can be implemented with SinkExt::with
     // but couldn't figure out how.
-    service.add_connection(account.clone(), WsWrap { connection });
+    service.add_connection(account.clone(), None, WsWrap { connection });
     debug!(
         "Added connection for account {}: (id: {})",
         account.username(),
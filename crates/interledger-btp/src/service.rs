@@ -1,4 +1,4 @@
-use super::{packet::*, BtpAccount};
+use super::{client::BtpClientError, packet::*, BtpAccount};
 use async_trait::async_trait;
 use bytes::BytesMut;
 use futures::{
@@ -10,6 +10,7 @@ use futures::{
 };
 use interledger_packet::{Address, ErrorCode, Fulfill, Packet, Prepare, Reject, RejectBuilder};
 use interledger_service::*;
+use native_tls::{Identity, TlsConnector};
 use once_cell::sync::Lazy;
 use parking_lot::{Mutex, RwLock};
 use rand::random;
@@ -19,6 +20,7 @@ use stream_cancel::{Trigger, Valve};
 use tokio::time;
 use tracing::{debug, error, trace, warn};
 use tungstenite::Message;
+use url::Url;
 use uuid::Uuid;
 
 const PING_INTERVAL: u64 = 30; // seconds
@@ -34,6 +36,27 @@ const SEND_MSG_TIMEOUT: Duration = Duration::from_secs(30);
 type IlpResultChannel = oneshot::Sender<Result<Fulfill, Reject>>;
 type IncomingRequestBuffer<A> = UnboundedReceiver<(A, u32, Prepare)>;
 
+/// An open WebSocket connection to an account
+#[derive(Clone)]
+struct Connection {
+    /// Outgoing messages for the receiver of the websocket
+    sender: UnboundedSender<Message>,
+    /// The URL we connected to, if we opened the connection as a client
+    url: Option<Url>,
+}
+
+/// The state of the BTP connection to an account
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BtpConnectionState {
+    /// A WebSocket connection to the account is open
+    Connected,
+    /// The connection to the account dropped or could not be opened,
+    /// and the client has failed to reconnect this many times in a row
+    Reconnecting { attempts: u32 },
+    /// There is no open connection to the account
+    Disconnected,
+}
+
 /// The BtpOutgoingService wraps all BTP/WebSocket connections that come
 /// in on the given address. It implements OutgoingService for sending
 /// outgoing ILP Prepare packets over one of the connected BTP connections.
@@ -45,8 +68,13 @@ type IncomingRequestBuffer<A> = UnboundedReceiver<(A, u32, Prepare)>;
 #[derive(Clone)]
 pub struct BtpOutgoingService<O, A: Account> {
     ilp_address: Address,
-    /// Outgoing messages for the receiver of the websocket indexed by account uid
-    connections: Arc<RwLock<HashMap<Uuid, UnboundedSender<Message>>>>,
+    /// Open websocket connections indexed by account uid
+    connections: Arc<RwLock<HashMap<Uuid, Connection>>>,
+    /// Failed reconnection attempts indexed by account uid
+    reconnect_attempts: Arc<Mutex<HashMap<Uuid, u32>>>,
+    /// TLS connectors which present a client certificate, indexed by the username
+    /// of the account they are used for
+    client_certificates: Arc<RwLock<HashMap<Username, TlsConnector>>>,
     pending_outgoing: Arc<Mutex<HashMap<u32, IlpResultChannel>>>,
     pending_incoming: Arc<Mutex<Option<IncomingRequestBuffer<A>>>>,
     incoming_sender: UnboundedSender<(A, u32, Prepare)>,
@@ -132,6 +160,8 @@ where
         BtpOutgoingService {
             ilp_address,
             connections: Arc::new(RwLock::new(HashMap::new())),
+            reconnect_attempts: Arc::new(Mutex::new(HashMap::new())),
+            client_certificates: Arc::new(RwLock::new(HashMap::new())),
             pending_outgoing: Arc::new(Mutex::new(HashMap::new())),
             pending_incoming: Arc::new(Mutex::new(Some(incoming_receiver))),
             incoming_sender,
@@ -154,13 +184,80 @@ where
         self.close_all_connections.lock().take();
     }
 
+    /// Returns true once `close` has been called
+    pub(crate) fn is_closed(&self) -> bool {
+        self.close_all_connections.lock().is_none()
+    }
+
+    /// Returns the state of the connection to the provided `account_id`
+    pub fn connection_state(&self, account_id: &Uuid) -> BtpConnectionState {
+        if self.is_connected(account_id) {
+            BtpConnectionState::Connected
+        } else if let Some(attempts) = self.reconnect_attempts.lock().get(account_id) {
+            BtpConnectionState::Reconnecting {
+                attempts: *attempts,
+            }
+        } else {
+            BtpConnectionState::Disconnected
+        }
+    }
+
+    /// Returns true if there is an open WebSocket connection to the provided `account_id`
+    pub(crate) fn is_connected(&self, account_id: &Uuid) -> bool {
+        match self.connections.read().get(account_id) {
+            Some(connection) => !connection.sender.is_closed(),
+            None => false,
+        }
+    }
+
+    /// Returns the URL of the open connection to `account_id` if we are its client
+    pub(crate) fn connection_url(&self, account_id: &Uuid) -> Option<Url> {
+        self.connections
+            .read()
+            .get(account_id)
+            .and_then(|connection| connection.url.clone())
+    }
+
+    /// Presents the certificate when connecting to the account with this username,
+    /// if its URL uses WSS
+    pub fn client_certificate(
+        &self,
+        username: Username,
+        certificate: &ClientCertificate,
+    ) -> Result<(), BtpClientError> {
+        let connector = Identity::from_pkcs12(&certificate.pkcs12, &certificate.password)
+            .and_then(|identity| TlsConnector::builder().identity(identity).build())
+            .map_err(|err| {
+                BtpClientError::InvalidClientCertificate(username.to_string(), err.to_string())
+            })?;
+        self.client_certificates.write().insert(username, connector);
+        Ok(())
+    }
+
+    /// Returns the TLS connector which presents the client certificate of the account, if any
+    pub(crate) fn tls_connector(&self, username: &Username) -> Option<TlsConnector> {
+        self.client_certificates.read().get(username).cloned()
+    }
+
+    /// Records a failed reconnection attempt (or clears them, if `attempts` is 0)
+    pub(crate) fn set_reconnect_attempts(&self, account_id: Uuid, attempts: u32) {
+        let mut reconnect_attempts = self.reconnect_attempts.lock();
+        if attempts == 0 {
+            reconnect_attempts.remove(&account_id);
+        } else {
+            reconnect_attempts.insert(account_id, attempts);
+        }
+    }
+
     // Set up a WebSocket connection so that outgoing Prepare packets can be sent to it,
     // incoming Prepare packets are buffered in a channel (until an IncomingService is added
     // via the handle_incoming method), and ILP Fulfill and Reject packets will be
     // sent back to the Future that sent the outgoing request originally.
+    // `url` is the URL we connected to if we are the client of the connection.
     pub(crate) fn add_connection(
         &self,
         account: A,
+        url: Option<Url>,
         ws_stream: impl Stream<Item = Message> + Sink<Message> + Send + 'static,
     ) {
         let account_id = account.id();
@@ -201,11 +298,21 @@ where
         // Close connections trigger
         let read = valve.wrap(read); // close when `write_to_ws` calls `drop(connection)`
         let read = self.stream_valve.wrap(read);
+        let connections = self.connections.clone();
+        let client_tx_read = client_tx.clone();
         let read_from_ws = read.for_each(handle_message_fn).then(move |_| async move {
             debug!(
                 "Finished reading from WebSocket stream for account: {}",
                 account_id
             );
+            // Forget the connection (unless it was already replaced by a new one)
+            // so that requests are no longer sent to it and it can be reconnected
+            let mut connections = connections.write();
+            if let Some(connection) = connections.get(&account_id) {
+                if connection.sender.same_receiver(&client_tx_read) {
+                    connections.remove(&account_id);
+                }
+            }
             Ok::<(), ()>(())
         });
         tokio::spawn(read_from_ws);
@@ -228,7 +335,13 @@ where
         tokio::spawn(send_pings);
 
         // Save the sender side of the channel so we have a way to forward outgoing requests to the WebSocket
-        self.connections.write().insert(account_id, client_tx);
+        self.connections.write().insert(
+            account_id,
+            Connection {
+                sender: client_tx,
+                url,
+            },
+        );
     }
 
     /// Convert this BtpOutgoingService into a bidirectional BtpService by adding a handler for incoming requests.
@@ -269,12 +382,15 @@ where
 
                 if let Some(connection) = connections_clone.clone().read().get(&account_id) {
                     let message = ilp_packet_to_ws_message(request_id, packet);
-                    let _ = connection.unbounded_send(message).map_err(move |err| {
-                        error!(
-                            "Error sending response to account: {} {:?}",
-                            account_id, err
-                        )
-                    });
+                    let _ = connection
+                        .sender
+                        .unbounded_send(message)
+                        .map_err(move |err| {
+                            error!(
+                                "Error sending response to account: {} {:?}",
+                                account_id, err
+                            )
+                        });
                 } else {
                     error!(
                         "Error sending response to account: {}, connection was closed. {:?}",
@@ -326,7 +442,7 @@ where
 
             // Connection is an unbounded sender which sends to the rx that
             // forwards to the sink which sends the data over
-            match connection.unbounded_send(ilp_packet_to_ws_message(
+            match connection.sender.unbounded_send(ilp_packet_to_ws_message(
                 request_id,
                 Packet::Prepare(request.prepare),
             )) {
@@ -370,7 +486,7 @@ where
                                 request_id, account_id, err
                             );
                             Err(RejectBuilder {
-                                code: ErrorCode::T00_INTERNAL_ERROR,
+                                code: ErrorCode::T01_PEER_UNREACHABLE,
                                 message: &[],
                                 triggered_by: Some(&ilp_address),
                                 data: &[],
@@ -384,8 +500,10 @@ where
                         "Error sending websocket message for request {} to account {}: {:?}",
                         request_id, account_id, send_error
                     );
+                    // The connection is closed so there is no point in keeping it
+                    self.close_connection(&account_id);
                     Err(RejectBuilder {
-                        code: ErrorCode::T00_INTERNAL_ERROR,
+                        code: ErrorCode::T01_PEER_UNREACHABLE,
                         message: &[],
                         triggered_by: Some(&ilp_address),
                         data: &[],
@@ -427,6 +545,11 @@ where
     pub fn close_connection(&self, account_id: &Uuid) {
         self.outgoing.close_connection(account_id);
     }
+
+    /// Returns the state of the connection to the provided `account_id`
+    pub fn connection_state(&self, account_id: &Uuid) -> BtpConnectionState {
+        self.outgoing.connection_state(account_id)
+    }
 }
 
 #[async_trait]
@@ -11,6 +11,7 @@
 
 use async_trait::async_trait;
 use interledger_errors::CcpRoutingStoreError;
+use interledger_router::NextHop;
 use interledger_service::Account;
 use std::collections::HashMap;
 use std::{fmt, str::FromStr};
@@ -127,4 +128,13 @@ pub trait CcpRoutingStore: Clone {
         &mut self,
         routes: impl IntoIterator<Item = (String, Self::Account)> + Send + 'async_trait,
     ) -> Result<(), CcpRoutingStoreError>;
+
+    /// Sets the prefixes which we have learned more than one route for
+    /// (prefix -> next hops, best first). The first next hop of each prefix is
+    /// the account it is mapped to in the routes passed to `set_routes`, the others
+    /// are backups which the `Router` fails over to.
+    async fn set_multipath_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError>;
 }
@@ -1,4 +1,5 @@
 use crate::packet::{Route, RouteUpdateRequest};
+use interledger_router::PrefixMap;
 use once_cell::sync::Lazy;
 use ring::rand::{SecureRandom, SystemRandom};
 use std::collections::HashMap;
@@ -7,36 +8,6 @@ use tracing::{debug, trace};
 
 static RANDOM: Lazy<SystemRandom> = Lazy::new(SystemRandom::new);
 
-#[derive(Debug, Clone)]
-struct PrefixMap<T> {
-    map: HashMap<String, T>,
-}
-
-impl<T> PrefixMap<T> {
-    pub fn new() -> Self {
-        PrefixMap {
-            map: HashMap::new(),
-        }
-    }
-
-    pub fn insert(&mut self, prefix: String, item: T) -> bool {
-        self.map.insert(prefix, item).is_none()
-    }
-
-    pub fn remove(&mut self, prefix: &str) -> bool {
-        self.map.remove(prefix).is_some()
-    }
-
-    pub fn resolve(&self, prefix: &str) -> Option<&T> {
-        // TODO use parallel iterator
-        self.map
-            .iter()
-            .filter(|(p, _)| prefix.starts_with(p.as_str()))
-            .max_by_key(|(p, _)| p.len())
-            .map(|(_prefix, item)| item)
-    }
-}
-
 /// The routing table is identified by an ID (a UUID in array form) and an "epoch".
 /// When an Interledger node reloads, it will generate a new UUID for its routing table.
 /// Each update applied increments the epoch number, so it acts as a version tracker.
@@ -87,32 +58,32 @@ where
 
     /// Set a particular route, overwriting the one that was there before
     pub(crate) fn set_route(&mut self, prefix: String, account: A, route: Route) {
-        self.prefix_map.remove(&prefix);
-        self.prefix_map.insert(prefix, (account, route));
+        self.prefix_map.insert(&prefix, (account, route));
     }
 
     /// Remove the route for the given prefix. Returns true if that route existed before
     pub(crate) fn delete_route(&mut self, prefix: &str) -> bool {
-        self.prefix_map.remove(prefix)
+        self.prefix_map.remove(prefix).is_some()
     }
 
     /// Add the given route. Returns true if that routed did not already exist
     pub(crate) fn add_route(&mut self, account: A, route: Route) -> bool {
-        self.prefix_map
-            .insert(route.prefix.clone(), (account, route))
+        let prefix = route.prefix.clone();
+        self.prefix_map.insert(&prefix, (account, route)).is_none()
     }
 
     /// Get the best route we have for the given prefix
     pub(crate) fn get_route(&self, prefix: &str) -> Option<&(A, Route)> {
-        self.prefix_map.resolve(prefix)
+        self.prefix_map
+            .resolve(prefix)
+            .map(|(_prefix, entry)| entry)
     }
 
     pub(crate) fn get_simplified_table(&self) -> HashMap<String, A> {
         HashMap::from_iter(
             self.prefix_map
-                .map
                 .iter()
-                .map(|(address, (account, _route))| (address.clone(), account.clone())),
+                .map(|(address, (account, _route))| (address, account.clone())),
         )
     }
 
@@ -200,39 +171,6 @@ where
     }
 }
 
-#[cfg(test)]
-mod prefix_map {
-    use super::*;
-
-    #[test]
-    fn doesnt_insert_duplicates() {
-        let mut map = PrefixMap::new();
-        assert!(map.insert("example.a".to_string(), 1));
-        assert!(!map.insert("example.a".to_string(), 1));
-    }
-
-    #[test]
-    fn removes_entry() {
-        let mut map = PrefixMap::new();
-        assert!(map.insert("example.a".to_string(), 1));
-        assert!(map.remove("example.a"));
-        assert!(map.map.is_empty());
-    }
-
-    #[test]
-    fn resolves_to_longest_matching_prefix() {
-        let mut map = PrefixMap::new();
-        map.insert("example.a".to_string(), 1);
-        map.insert("example.a.b.c".to_string(), 2);
-        map.insert("example.a.b".to_string(), 3);
-
-        assert_eq!(map.resolve("example.a").unwrap(), &1);
-        assert_eq!(map.resolve("example.a.b.c").unwrap(), &2);
-        assert_eq!(map.resolve("example.a.b.c.d.e").unwrap(), &2);
-        assert!(map.resolve("example.other").is_none());
-    }
-}
-
 #[cfg(test)]
 mod table {
     use super::*;
@@ -10,13 +10,13 @@ use async_trait::async_trait;
 use futures::future::join_all;
 use interledger_errors::CcpRoutingStoreError;
 use interledger_packet::{Address, ErrorCode, RejectBuilder};
+use interledger_router::NextHop;
 use interledger_service::{
     Account, AddressStore, IlpResult, IncomingRequest, IncomingService, OutgoingRequest,
     OutgoingService,
 };
 use parking_lot::{Mutex, RwLock};
 use ring::digest::{digest, SHA256};
-use std::cmp::Ordering as StdOrdering;
 use std::collections::HashMap;
 use std::{
     cmp::min,
@@ -110,6 +110,7 @@ where
             last_epoch_updates_sent_for: Arc::new(AtomicU32::new(0)),
             local_table: Arc::new(RwLock::new(RoutingTable::default())),
             incoming_tables: Arc::new(RwLock::new(HashMap::new())),
+            multipath_routes: Arc::new(RwLock::new(HashMap::new())),
             unavailable_accounts: Arc::new(Mutex::new(HashMap::new())),
         };
 
@@ -169,6 +170,10 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     /// Updates from peers are applied to our local_table if they are better than the
     /// existing best route and if they do not attempt to overwrite configured routes.
     incoming_tables: Arc<RwLock<HashMap<Uuid, RoutingTable<A>>>>,
+    /// The prefixes which we have more than one route for, mapped to their next hops
+    /// (best first). These are saved to the Store so that the Router can fail over
+    /// to the other routes when the best one rejects a packet.
+    multipath_routes: Arc<RwLock<HashMap<String, Vec<NextHop>>>>,
     store: S,
     /// If we get final errors while sending to specific accounts, we'll
     /// wait before trying to broadcast to them
@@ -533,10 +538,11 @@ where
             self.store.get_local_and_configured_routes().await?;
 
         // TODO: Should we extract this to a function and #[inline] it?
-        let (better_routes, withdrawn_routes) = {
+        let (better_routes, withdrawn_routes, multipath_changes) = {
             // Note we only use a read lock here and later get a write lock if we need to update the table
             let local_table = local_table.read();
             let incoming_tables = incoming_tables.read();
+            let multipath_routes = self.multipath_routes.read();
 
             // Either check the given prefixes or check all of our local and configured routes
             let prefixes_to_check: Box<dyn Iterator<Item = &str>> =
@@ -552,35 +558,64 @@ where
             let mut better_routes: Vec<(&str, A, Route)> =
                 Vec::with_capacity(prefixes_to_check.size_hint().0);
             let mut withdrawn_routes: Vec<&str> = Vec::new();
+            let mut multipath_changes: Vec<(&str, Vec<NextHop>)> = Vec::new();
             for prefix in prefixes_to_check {
-                // See which prefixes there is now a better route for
-                if let Some((best_next_account, best_route)) = get_best_route_for_prefix(
+                let mut routes = get_routes_for_prefix(
                     &local_routes,
                     &configured_routes,
                     &incoming_tables,
                     prefix,
-                ) {
+                );
+
+                // The best route is used by default and the others are only used
+                // if it rejects packets, in the order we would have picked them
+                let next_hops: Vec<NextHop> = routes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (account, _route))| NextHop {
+                        account_id: account.id(),
+                        weight: if index == 0 { 1 } else { 0 },
+                    })
+                    .collect();
+                let old_next_hops = multipath_routes.get(prefix).map(Vec::as_slice);
+                if next_hops.len() > 1 && old_next_hops != Some(&next_hops[..]) {
+                    multipath_changes.push((prefix, next_hops));
+                } else if next_hops.len() <= 1 && old_next_hops.is_some() {
+                    multipath_changes.push((prefix, Vec::new()));
+                }
+
+                // See which prefixes there is now a better route for
+                if !routes.is_empty() {
+                    let (best_next_account, best_route) = routes.swap_remove(0);
                     if let Some((ref next_account, ref _route)) = local_table.get_route(prefix) {
                         if next_account.id() == best_next_account.id() {
                             continue;
-                        } else {
-                            better_routes.push((
-                                prefix,
-                                best_next_account.clone(),
-                                best_route.clone(),
-                            ));
                         }
-                    } else {
-                        better_routes.push((prefix, best_next_account, best_route));
                     }
+                    better_routes.push((prefix, best_next_account, best_route));
                 } else {
                     // No longer have a route to this prefix
                     withdrawn_routes.push(prefix);
                 }
             }
-            (better_routes, withdrawn_routes)
+            (better_routes, withdrawn_routes, multipath_changes)
         };
 
+        if !multipath_changes.is_empty() {
+            let multipath_routes = {
+                let mut multipath_routes = self.multipath_routes.write();
+                for (prefix, next_hops) in multipath_changes {
+                    if next_hops.is_empty() {
+                        multipath_routes.remove(prefix);
+                    } else {
+                        multipath_routes.insert(prefix.to_string(), next_hops);
+                    }
+                }
+                multipath_routes.clone()
+            };
+            store.set_multipath_routes(multipath_routes).await?;
+        }
+
         // Update the local and forwarding tables
         if !better_routes.is_empty() || !withdrawn_routes.is_empty() {
             let update_routes = {
@@ -890,12 +925,29 @@ where
     }
 }
 
+#[cfg(test)]
 fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     local_routes: &HashMap<String, A>,
     configured_routes: &HashMap<String, A>,
     incoming_tables: &HashMap<Uuid, RoutingTable<A>>,
     prefix: &str,
 ) -> Option<(A, Route)> {
+    get_routes_for_prefix(local_routes, configured_routes, incoming_tables, prefix)
+        .into_iter()
+        .next()
+}
+
+/// Returns the routes we have for the given prefix, best first.
+///
+/// Configured and local routes take precedence over everything else, so if
+/// there is one for the prefix it is the only route returned. Otherwise, the
+/// routes our peers sent us for the prefix are returned in order of preference.
+fn get_routes_for_prefix<A: CcpRoutingAccount>(
+    local_routes: &HashMap<String, A>,
+    configured_routes: &HashMap<String, A>,
+    incoming_tables: &HashMap<Uuid, RoutingTable<A>>,
+    prefix: &str,
+) -> Vec<(A, Route)> {
     // Check if we have a configured route for that specific prefix
     // or any shorter prefix ("example.a.b.c" will match "example.a.b" and "example.a")
     // Note that this logic is duplicated from the Address type. We are not using
@@ -905,7 +957,7 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     for i in 0..segments.len() {
         let prefix = &segments[0..segments.len() - i].join(".");
         if let Some(account) = configured_routes.get(prefix) {
-            return Some((
+            return vec![(
                 account.clone(),
                 Route {
                     prefix: account.ilp_address().to_string(),
@@ -913,12 +965,12 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
                     path: Vec::new(),
                     props: Vec::new(),
                 },
-            ));
+            )];
         }
     }
 
     if let Some(account) = local_routes.get(prefix) {
-        return Some((
+        return vec![(
             account.clone(),
             Route {
                 prefix: account.ilp_address().to_string(),
@@ -926,45 +978,24 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
                 path: Vec::new(),
                 props: Vec::new(),
             },
-        ));
+        )];
     }
 
-    let mut candidate_routes = incoming_tables
+    let mut candidate_routes: Vec<&(A, Route)> = incoming_tables
         .values()
-        .filter_map(|incoming_table| incoming_table.get_route(prefix));
-    if let Some((account, route)) = candidate_routes.next() {
-        let (best_account, best_route) = candidate_routes.fold(
-            (account, route),
-            |(best_account, best_route), (account, route)| {
-                // Prioritize child > peer > parent
-                match best_account
-                    .routing_relation()
-                    .cmp(&account.routing_relation())
-                {
-                    StdOrdering::Greater => (best_account, best_route),
-                    StdOrdering::Less => (account, route),
-                    _ => {
-                        // Prioritize shortest path
-                        match best_route.path.len().cmp(&route.path.len()) {
-                            StdOrdering::Less => (best_account, best_route),
-                            StdOrdering::Greater => (account, route),
-                            _ => {
-                                // Finally base it on account ID
-                                if best_account.id().to_string() < account.id().to_string() {
-                                    (best_account, best_route)
-                                } else {
-                                    (account, route)
-                                }
-                            }
-                        }
-                    }
-                }
-            },
-        );
-        Some((best_account.clone(), best_route.clone()))
-    } else {
-        None
-    }
+        .filter_map(|incoming_table| incoming_table.get_route(prefix))
+        .collect();
+    candidate_routes.sort_by(|(account_a, route_a), (account_b, route_b)| {
+        // Prioritize child > peer > parent
+        account_b
+            .routing_relation()
+            .cmp(&account_a.routing_relation())
+            // Prioritize shortest path
+            .then_with(|| route_a.path.len().cmp(&route_b.path.len()))
+            // Finally base it on account ID
+            .then_with(|| account_a.id().to_string().cmp(&account_b.id().to_string()))
+    });
+    candidate_routes.into_iter().cloned().collect()
 }
 
 #[async_trait]
@@ -1591,6 +1622,62 @@ mod handle_route_update_request {
             .is_none());
     }
 
+    #[tokio::test]
+    async fn writes_alternative_routes_to_store() {
+        let mut service = test_service();
+        let other_peer = TestAccount::new(Uuid::from_slice(&[1; 16]).unwrap(), "example.peer2");
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        for from in [ROUTING_ACCOUNT.clone(), other_peer.clone()]
+            .iter()
+            .cloned()
+        {
+            service
+                .handle_request(IncomingRequest {
+                    from,
+                    prepare: request.to_prepare(),
+                })
+                .await
+                .unwrap();
+        }
+
+        let best = service.store.routes.lock()["example.prefix1"].id();
+        let next_hops = service.store.multipath_routes.lock()["example.prefix1"].clone();
+        assert_eq!(next_hops.len(), 2);
+        assert_eq!(next_hops[0].account_id, best);
+        assert_eq!(next_hops[0].weight, 1);
+        assert_eq!(next_hops[1].weight, 0);
+        assert!(next_hops
+            .iter()
+            .any(|hop| hop.account_id == ROUTING_ACCOUNT.id()));
+        assert!(next_hops
+            .iter()
+            .any(|hop| hop.account_id == other_peer.id()));
+
+        // Once only one peer has a route, the prefix has a single next hop again
+        service
+            .handle_request(IncomingRequest {
+                from: other_peer,
+                prepare: RouteUpdateRequest {
+                    routing_table_id: UPDATE_REQUEST_COMPLEX.routing_table_id,
+                    from_epoch_index: 1,
+                    to_epoch_index: 3,
+                    current_epoch_index: 3,
+                    hold_down_time: 45000,
+                    speaker: UPDATE_REQUEST_COMPLEX.speaker.clone(),
+                    new_routes: Vec::new(),
+                    withdrawn_routes: vec!["example.prefix1".to_string()],
+                }
+                .to_prepare(),
+            })
+            .await
+            .unwrap();
+        let multipath_routes = service.store.multipath_routes.lock();
+        assert!(!multipath_routes.contains_key("example.prefix1"));
+        assert!(multipath_routes.contains_key("example.prefix2"));
+    }
+
     #[tokio::test]
     async fn sends_control_request_if_routing_table_id_changed() {
         let (mut service, outgoing_requests) = test_service_with_routes();
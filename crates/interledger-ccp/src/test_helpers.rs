@@ -83,6 +83,7 @@ pub struct TestStore {
     pub local: HashMap<String, TestAccount>,
     pub configured: HashMap<String, TestAccount>,
     pub routes: Arc<Mutex<HashMap<String, TestAccount>>>,
+    pub multipath_routes: Arc<Mutex<HashMap<String, Vec<NextHop>>>>,
 }
 
 impl TestStore {
@@ -91,6 +92,7 @@ impl TestStore {
             local: HashMap::new(),
             configured: HashMap::new(),
             routes: Arc::new(Mutex::new(HashMap::new())),
+            multipath_routes: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -102,6 +104,7 @@ impl TestStore {
             local,
             configured,
             routes: Arc::new(Mutex::new(HashMap::new())),
+            multipath_routes: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -175,6 +178,14 @@ impl CcpRoutingStore for TestStore {
         *self.routes.lock() = HashMap::from_iter(routes.into_iter());
         Ok(())
     }
+
+    async fn set_multipath_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        *self.multipath_routes.lock() = HashMap::from_iter(routes);
+        Ok(())
+    }
 }
 
 pub fn test_service() -> CcpRouteManager<
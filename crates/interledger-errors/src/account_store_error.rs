@@ -54,3 +54,13 @@ impl From<RedisError> for AccountStoreError {
         AccountStoreError::Other(Box::new(err))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for AccountStoreError {
+    fn from(src: SqlError) -> AccountStoreError {
+        AccountStoreError::Other(Box::new(src))
+    }
+}
@@ -43,3 +43,13 @@ impl From<AddressStoreError> for warp::Rejection {
         ApiError::from(src).into()
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for AddressStoreError {
+    fn from(src: SqlError) -> AddressStoreError {
+        AddressStoreError::Other(Box::new(src))
+    }
+}
@@ -0,0 +1,49 @@
+use crate::error::ApiError;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// Errors for the ApiTokenStore
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ApiTokenStoreError {
+    #[error("{0}")]
+    Other(#[from] Box<dyn StdError + Send + 'static>),
+    #[error("API token `{0}` was not found")]
+    TokenNotFound(String),
+}
+
+impl From<ApiTokenStoreError> for ApiError {
+    fn from(src: ApiTokenStoreError) -> Self {
+        match src {
+            ApiTokenStoreError::TokenNotFound(_) => ApiError::not_found().detail(src.to_string()),
+            _ => ApiError::internal_server_error().detail(src.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "warp_errors")]
+impl From<ApiTokenStoreError> for warp::Rejection {
+    fn from(src: ApiTokenStoreError) -> Self {
+        ApiError::from(src).into()
+    }
+}
+
+#[cfg(feature = "redis_errors")]
+use redis::RedisError;
+
+#[cfg(feature = "redis_errors")]
+impl From<RedisError> for ApiTokenStoreError {
+    fn from(src: RedisError) -> ApiTokenStoreError {
+        ApiTokenStoreError::Other(Box::new(src))
+    }
+}
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for ApiTokenStoreError {
+    fn from(src: SqlError) -> ApiTokenStoreError {
+        ApiTokenStoreError::Other(Box::new(src))
+    }
+}
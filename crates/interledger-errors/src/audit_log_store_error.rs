@@ -0,0 +1,44 @@
+use crate::error::ApiError;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// Errors for the AuditLogStore
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum AuditLogStoreError {
+    #[error("{0}")]
+    Other(#[from] Box<dyn StdError + Send + 'static>),
+}
+
+impl From<AuditLogStoreError> for ApiError {
+    fn from(src: AuditLogStoreError) -> Self {
+        ApiError::internal_server_error().detail(src.to_string())
+    }
+}
+
+#[cfg(feature = "warp_errors")]
+impl From<AuditLogStoreError> for warp::Rejection {
+    fn from(src: AuditLogStoreError) -> Self {
+        ApiError::from(src).into()
+    }
+}
+
+#[cfg(feature = "redis_errors")]
+use redis::RedisError;
+
+#[cfg(feature = "redis_errors")]
+impl From<RedisError> for AuditLogStoreError {
+    fn from(src: RedisError) -> AuditLogStoreError {
+        AuditLogStoreError::Other(Box::new(src))
+    }
+}
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for AuditLogStoreError {
+    fn from(src: SqlError) -> AuditLogStoreError {
+        AuditLogStoreError::Other(Box::new(src))
+    }
+}
@@ -32,3 +32,13 @@ impl From<RedisError> for BalanceStoreError {
         BalanceStoreError::Other(Box::new(src))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for BalanceStoreError {
+    fn from(src: SqlError) -> BalanceStoreError {
+        BalanceStoreError::Other(Box::new(src))
+    }
+}
@@ -42,3 +42,13 @@ impl From<RedisError> for BtpStoreError {
         BtpStoreError::Other(Box::new(src))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for BtpStoreError {
+    fn from(src: SqlError) -> BtpStoreError {
+        BtpStoreError::Other(Box::new(src))
+    }
+}
@@ -45,3 +45,13 @@ impl From<RedisError> for CcpRoutingStoreError {
         CcpRoutingStoreError::Other(Box::new(src))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for CcpRoutingStoreError {
+    fn from(src: SqlError) -> CcpRoutingStoreError {
+        CcpRoutingStoreError::Other(Box::new(src))
+    }
+}
@@ -44,3 +44,13 @@ impl From<RedisError> for CreateAccountError {
         CreateAccountError::Other(Box::new(err))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for CreateAccountError {
+    fn from(src: SqlError) -> CreateAccountError {
+        CreateAccountError::Other(Box::new(src))
+    }
+}
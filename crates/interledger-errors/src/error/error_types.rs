@@ -26,6 +26,13 @@ pub const DEFAULT_UNAUTHORIZED_TYPE: ApiErrorType = ApiErrorType {
     status: StatusCode::UNAUTHORIZED,
 };
 
+/// 403 Forbidden HTTP Status Code
+pub const DEFAULT_FORBIDDEN_TYPE: ApiErrorType = ApiErrorType {
+    r#type: &ProblemType::Default,
+    title: "Forbidden",
+    status: StatusCode::FORBIDDEN,
+};
+
 /// 404 Not Found HTTP Status Code
 pub const DEFAULT_NOT_FOUND_TYPE: ApiErrorType = ApiErrorType {
     r#type: &ProblemType::Default,
@@ -148,6 +148,11 @@ impl ApiError {
         ApiError::from_api_error_type(&DEFAULT_UNAUTHORIZED_TYPE)
     }
 
+    /// Returns a Forbidden [ApiError](./struct.ApiError.html)
+    pub fn forbidden() -> Self {
+        ApiError::from_api_error_type(&DEFAULT_FORBIDDEN_TYPE)
+    }
+
     #[allow(dead_code)]
     /// Returns an Error Not Found [ApiError](./struct.ApiError.html)
     pub fn not_found() -> Self {
@@ -348,6 +353,10 @@ pub async fn default_rejection_handler(err: warp::Rejection) -> Result<impl Repl
         Ok(api_error.clone().into_response())
     } else if let Some(json_error) = err.find::<JsonDeserializeError>() {
         Ok(json_error.clone().into_response())
+    } else if let Some(query_error) = err.find::<warp::reject::InvalidQuery>() {
+        Ok(ApiError::bad_request()
+            .detail(query_error.to_string())
+            .into_response())
     } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
         Ok(ApiError::from_api_error_type(&DEFAULT_METHOD_NOT_ALLOWED_TYPE).into_response())
     } else {
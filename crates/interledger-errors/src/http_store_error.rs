@@ -42,3 +42,13 @@ impl From<RedisError> for HttpStoreError {
         HttpStoreError::Other(Box::new(src))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for HttpStoreError {
+    fn from(src: SqlError) -> HttpStoreError {
+        HttpStoreError::Other(Box::new(src))
+    }
+}
@@ -31,3 +31,18 @@ pub use settlement_errors::{IdempotentStoreError, LeftoversStoreError, Settlemen
 
 mod create_account_error;
 pub use create_account_error::CreateAccountError;
+
+mod stream_connection_store_error;
+pub use stream_connection_store_error::StreamConnectionStoreError;
+
+mod payment_history_store_error;
+pub use payment_history_store_error::PaymentHistoryStoreError;
+
+mod api_token_store_error;
+pub use api_token_store_error::ApiTokenStoreError;
+
+mod audit_log_store_error;
+pub use audit_log_store_error::AuditLogStoreError;
+
+mod fee_store_error;
+pub use fee_store_error::FeeStoreError;
@@ -68,3 +68,13 @@ impl From<RedisError> for NodeStoreError {
         NodeStoreError::Other(Box::new(src))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for NodeStoreError {
+    fn from(src: SqlError) -> NodeStoreError {
+        NodeStoreError::Other(Box::new(src))
+    }
+}
@@ -0,0 +1,44 @@
+use crate::error::ApiError;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// Errors for the PaymentHistoryStore
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum PaymentHistoryStoreError {
+    #[error("{0}")]
+    Other(#[from] Box<dyn StdError + Send + 'static>),
+}
+
+impl From<PaymentHistoryStoreError> for ApiError {
+    fn from(src: PaymentHistoryStoreError) -> Self {
+        ApiError::internal_server_error().detail(src.to_string())
+    }
+}
+
+#[cfg(feature = "warp_errors")]
+impl From<PaymentHistoryStoreError> for warp::Rejection {
+    fn from(src: PaymentHistoryStoreError) -> Self {
+        ApiError::from(src).into()
+    }
+}
+
+#[cfg(feature = "redis_errors")]
+use redis::RedisError;
+
+#[cfg(feature = "redis_errors")]
+impl From<RedisError> for PaymentHistoryStoreError {
+    fn from(src: RedisError) -> PaymentHistoryStoreError {
+        PaymentHistoryStoreError::Other(Box::new(src))
+    }
+}
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for PaymentHistoryStoreError {
+    fn from(src: SqlError) -> PaymentHistoryStoreError {
+        PaymentHistoryStoreError::Other(Box::new(src))
+    }
+}
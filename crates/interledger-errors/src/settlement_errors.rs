@@ -98,3 +98,27 @@ impl From<RedisError> for IdempotentStoreError {
         IdempotentStoreError::Other(Box::new(src))
     }
 }
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for SettlementStoreError {
+    fn from(src: SqlError) -> SettlementStoreError {
+        SettlementStoreError::Other(Box::new(src))
+    }
+}
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for LeftoversStoreError {
+    fn from(src: SqlError) -> LeftoversStoreError {
+        LeftoversStoreError::Other(Box::new(src))
+    }
+}
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for IdempotentStoreError {
+    fn from(src: SqlError) -> IdempotentStoreError {
+        IdempotentStoreError::Other(Box::new(src))
+    }
+}
@@ -0,0 +1,51 @@
+use crate::error::ApiError;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// Errors for the StreamConnectionStore
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum StreamConnectionStoreError {
+    #[error("STREAM connection `{0}` was not found")]
+    ConnectionNotFound(String),
+    #[error("{0}")]
+    Other(#[from] Box<dyn StdError + Send + 'static>),
+}
+
+impl From<StreamConnectionStoreError> for ApiError {
+    fn from(src: StreamConnectionStoreError) -> Self {
+        match src {
+            StreamConnectionStoreError::ConnectionNotFound(_) => {
+                ApiError::not_found().detail(src.to_string())
+            }
+            _ => ApiError::internal_server_error().detail(src.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "warp_errors")]
+impl From<StreamConnectionStoreError> for warp::Rejection {
+    fn from(src: StreamConnectionStoreError) -> Self {
+        ApiError::from(src).into()
+    }
+}
+
+#[cfg(feature = "redis_errors")]
+use redis::RedisError;
+
+#[cfg(feature = "redis_errors")]
+impl From<RedisError> for StreamConnectionStoreError {
+    fn from(src: RedisError) -> StreamConnectionStoreError {
+        StreamConnectionStoreError::Other(Box::new(src))
+    }
+}
+
+#[cfg(feature = "sql_errors")]
+use sqlx::Error as SqlError;
+
+#[cfg(feature = "sql_errors")]
+impl From<SqlError> for StreamConnectionStoreError {
+    fn from(src: SqlError) -> StreamConnectionStoreError {
+        StreamConnectionStoreError::Other(Box::new(src))
+    }
+}
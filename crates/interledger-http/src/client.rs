@@ -6,21 +6,89 @@ use interledger_packet::{Address, ErrorCode, Packet, RejectBuilder};
 use interledger_service::*;
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue},
-    Client, ClientBuilder, Response as HttpResponse,
+    Client, ClientBuilder, Identity, Response as HttpResponse, StatusCode,
 };
 use secrecy::{ExposeSecret, SecretString};
-use std::{convert::TryFrom, iter::FromIterator, marker::PhantomData, sync::Arc, time::Duration};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    iter::FromIterator,
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 use tracing::{error, trace};
 
+/// How much of a Prepare's remaining time is kept back when waiting for the peer,
+/// so that the Reject sent when it does not respond reaches the previous hop
+/// before the packet expires there too
+const RESPONSE_MARGIN: Duration = Duration::from_millis(500);
+
+/// Settings of the HTTP client used to send ILP over HTTP requests to one account
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct HttpClientSettings {
+    /// Maximum number of idle connections kept open to the peer. Unlimited by default
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long, in milliseconds, idle connections are kept open. Defaults to 90 seconds
+    #[serde(default)]
+    pub pool_idle_timeout: Option<u64>,
+    /// Speak HTTP/2 to the peer without negotiating it first, which also works over plain HTTP
+    #[serde(default)]
+    pub http2_prior_knowledge: bool,
+    /// Extra headers sent with every request to the peer
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// Errors for an account's HTTP client settings or certificate
+#[derive(Debug)]
+pub enum HttpClientSettingsError {
+    /// A header's name or value is invalid
+    InvalidHeader(String),
+    /// The certificate could not be loaded, or the client could not be built
+    Client(reqwest::Error),
+}
+
+impl fmt::Display for HttpClientSettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpClientSettingsError::InvalidHeader(header) => {
+                write!(f, "Invalid header: {}", header)
+            }
+            HttpClientSettingsError::Client(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for HttpClientSettingsError {}
+
+impl From<reqwest::Error> for HttpClientSettingsError {
+    fn from(err: reqwest::Error) -> Self {
+        HttpClientSettingsError::Client(err)
+    }
+}
+
 /// The HttpClientService implements [OutgoingService](../../interledger_service/trait.OutgoingService)
 /// for sending ILP Prepare packets over to the HTTP URL associated with the provided account
 /// If no [ILP-over-HTTP](https://interledger.org/rfcs/0035-ilp-over-http) URL is specified for
 /// the account in the request, then it is forwarded to the next service.
+///
+/// Each request times out shortly before the Prepare packet it carries expires.
 #[derive(Clone)]
 pub struct HttpClientService<S, O, A> {
-    /// An HTTP client configured with a 30 second timeout by default. It is used to send the
-    /// ILP over HTTP messages to the peer
+    /// The HTTP client used to send the ILP over HTTP messages to the peers
+    /// which have neither their own settings nor a client certificate
     client: Client,
+    /// Clients built with an account's own settings or client certificate,
+    /// indexed by the username of the peer they are used for
+    account_clients: Arc<HashMap<Username, Client>>,
+    /// The settings each of the `account_clients` was built with
+    account_settings: Arc<HashMap<Username, HttpClientSettings>>,
+    /// The certificates each of the `account_clients` presents
+    account_certificates: Arc<HashMap<Username, ClientCertificate>>,
     /// The store used by the client to get the node's ILP Address,
     /// used to populate the `triggered_by` field in Reject packets
     store: Arc<S>,
@@ -38,24 +106,121 @@ where
 {
     /// Constructs the HttpClientService
     pub fn new(store: S, next: O) -> Self {
-        let mut headers = HeaderMap::with_capacity(2);
-        headers.insert(
-            HeaderName::from_static("content-type"),
-            HeaderValue::from_static("application/octet-stream"),
-        );
-        let client = ClientBuilder::new()
-            .default_headers(headers)
-            .timeout(Duration::from_secs(30))
-            .build()
-            .unwrap();
-
         HttpClientService {
-            client,
+            client: build_client(&HttpClientSettings::default(), None).unwrap(),
+            account_clients: Arc::new(HashMap::new()),
+            account_settings: Arc::new(HashMap::new()),
+            account_certificates: Arc::new(HashMap::new()),
             store: Arc::new(store),
             next,
             account_type: PhantomData,
         }
     }
+
+    /// Presents the certificate when sending ILP over HTTP requests to the account
+    /// with this username, if its URL uses HTTPS
+    pub fn client_certificate(
+        &mut self,
+        username: Username,
+        certificate: &ClientCertificate,
+    ) -> Result<(), HttpClientSettingsError> {
+        Arc::make_mut(&mut self.account_certificates).insert(username.clone(), certificate.clone());
+        self.rebuild_account_client(username)
+    }
+
+    /// Sends the ILP over HTTP requests to the account with this username
+    /// with a client configured with the settings
+    pub fn client_settings(
+        &mut self,
+        username: Username,
+        settings: HttpClientSettings,
+    ) -> Result<(), HttpClientSettingsError> {
+        Arc::make_mut(&mut self.account_settings).insert(username.clone(), settings);
+        self.rebuild_account_client(username)
+    }
+
+    fn rebuild_account_client(
+        &mut self,
+        username: Username,
+    ) -> Result<(), HttpClientSettingsError> {
+        let default_settings = HttpClientSettings::default();
+        let settings = self
+            .account_settings
+            .get(&username)
+            .unwrap_or(&default_settings);
+        let client = build_client(settings, self.account_certificates.get(&username))?;
+        Arc::make_mut(&mut self.account_clients).insert(username, client);
+        Ok(())
+    }
+}
+
+fn build_client(
+    settings: &HttpClientSettings,
+    certificate: Option<&ClientCertificate>,
+) -> Result<Client, HttpClientSettingsError> {
+    let mut headers = HeaderMap::with_capacity(settings.headers.len() + 1);
+    headers.insert(
+        HeaderName::from_static("content-type"),
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    for (name, value) in settings.headers.iter() {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| HttpClientSettingsError::InvalidHeader(name.clone()))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| HttpClientSettingsError::InvalidHeader(name.to_string()))?;
+        headers.insert(name, value);
+    }
+    let mut builder = ClientBuilder::new().default_headers(headers);
+    if let Some(max_idle) = settings.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(idle_timeout) = settings.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(Duration::from_millis(idle_timeout));
+    }
+    if settings.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    if let Some(certificate) = certificate {
+        let identity = Identity::from_pkcs12_der(&certificate.pkcs12, &certificate.password)?;
+        builder = builder.identity(identity);
+    }
+    Ok(builder.build()?)
+}
+
+/// How long to wait for the peer to respond to a Prepare which expires at `expires_at`,
+/// or `None` if it expires too soon for the response to make it back in time
+fn request_timeout(expires_at: SystemTime) -> Option<Duration> {
+    expires_at
+        .duration_since(SystemTime::now())
+        .ok()?
+        .checked_sub(RESPONSE_MARGIN)
+        .filter(|timeout| *timeout > Duration::from_secs(0))
+}
+
+/// The reject code for a request which failed before the peer responded, or
+/// while its response was read
+fn transport_error_code(err: &reqwest::Error) -> ErrorCode {
+    if err.is_timeout() {
+        ErrorCode::R00_TRANSFER_TIMED_OUT
+    } else if let Some(status) = err.status() {
+        status_error_code(status)
+    } else {
+        ErrorCode::T01_PEER_UNREACHABLE
+    }
+}
+
+/// The reject code for an error status the peer responded with
+fn status_error_code(status: StatusCode) -> ErrorCode {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => ErrorCode::T05_RATE_LIMITED,
+        StatusCode::SERVICE_UNAVAILABLE => ErrorCode::T02_PEER_BUSY,
+        StatusCode::GATEWAY_TIMEOUT | StatusCode::REQUEST_TIMEOUT => {
+            ErrorCode::R00_TRANSFER_TIMED_OUT
+        }
+        StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::F00_BAD_REQUEST,
+        status if status.is_client_error() => ErrorCode::F02_UNREACHABLE,
+        _ => ErrorCode::T01_PEER_UNREACHABLE,
+    }
 }
 
 #[async_trait]
@@ -81,25 +246,34 @@ where
                 .get_http_auth_token()
                 .unwrap_or_else(|| SecretString::new("".to_owned()));
             let header = format!("Bearer {}", token.expose_secret());
+            let timeout = match request_timeout(request.prepare.expires_at()) {
+                Some(timeout) => timeout,
+                None => {
+                    return Err(RejectBuilder {
+                        code: ErrorCode::R00_TRANSFER_TIMED_OUT,
+                        message: b"Packet expires too soon to be sent over HTTP",
+                        triggered_by: Some(&ilp_address),
+                        data: &[],
+                    }
+                    .build())
+                }
+            };
             let body = request.prepare.as_ref().to_owned();
-            let resp = self_clone
-                .client
+            let client = self_clone
+                .account_clients
+                .get(request.to.username())
+                .unwrap_or(&self_clone.client);
+            let resp = client
                 .post(url.as_ref())
                 .header("authorization", &header)
+                .timeout(timeout)
                 .body(body)
                 .send()
                 .map_err(move |err| {
                     error!("Error sending HTTP request: {:?}", err);
-                    let mut code = ErrorCode::T01_PEER_UNREACHABLE;
-                    if let Some(status) = err.status() {
-                        if status.is_client_error() {
-                            code = ErrorCode::F00_BAD_REQUEST
-                        }
-                    };
-
                     let message = format!("Error sending ILP over HTTP request: {}", err);
                     RejectBuilder {
-                        code,
+                        code: transport_error_code(&err),
                         message: message.as_bytes(),
                         triggered_by: Some(&ilp_address),
                         data: &[],
@@ -117,23 +291,18 @@ where
 /// Parses an ILP over HTTP response.
 ///
 /// # Errors
-/// 1. If the response's status code is an error
-/// 1. If the response's body cannot be parsed as bytes
+/// 1. If the response's status code is an error. Rate limiting (429), overload (503)
+///    and timeout (408, 504) statuses map to T05, T02 and R00 Rejects
+/// 1. If the response's body cannot be parsed as bytes, or the packet expires while it is read
 /// 1. If the response's body is not a valid Packet (Fulfill or Reject)
 /// 1. If the packet is a Reject packet
 async fn parse_packet_from_response(response: HttpResponse, ilp_address: Address) -> IlpResult {
     let response = response.error_for_status().map_err(|err| {
         error!("HTTP error sending ILP over HTTP packet: {:?}", err);
-        let code = if let Some(status) = err.status() {
-            if status.is_client_error() {
-                ErrorCode::F02_UNREACHABLE
-            } else {
-                // TODO more specific errors for rate limiting, etc?
-                ErrorCode::T01_PEER_UNREACHABLE
-            }
-        } else {
-            ErrorCode::T00_INTERNAL_ERROR
-        };
+        let code = err
+            .status()
+            .map(status_error_code)
+            .unwrap_or(ErrorCode::T00_INTERNAL_ERROR);
         RejectBuilder {
             code,
             message: &[],
@@ -149,7 +318,7 @@ async fn parse_packet_from_response(response: HttpResponse, ilp_address: Address
         .map_err(|err| {
             error!("Error getting HTTP response body: {:?}", err);
             RejectBuilder {
-                code: ErrorCode::T01_PEER_UNREACHABLE,
+                code: transport_error_code(&err),
                 message: &[],
                 triggered_by: Some(&ilp_address_clone),
                 data: &[],
@@ -171,3 +340,225 @@ async fn parse_packet_from_response(response: HttpResponse, ilp_address: Address
         .build()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_errors::{AddressStoreError, HttpStoreError};
+    use interledger_packet::PrepareBuilder;
+    use once_cell::sync::Lazy;
+    use std::{net::TcpListener, str::FromStr, time::UNIX_EPOCH};
+    use url::Url;
+    use uuid::Uuid;
+
+    static USERNAME: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("example.alice").unwrap());
+    static URL: Lazy<Url> =
+        Lazy::new(|| Url::parse("http://127.0.0.1:1/accounts/bob/ilp").unwrap());
+
+    #[test]
+    fn maps_rate_limited_status_to_t05() {
+        assert_eq!(
+            status_error_code(StatusCode::TOO_MANY_REQUESTS),
+            ErrorCode::T05_RATE_LIMITED
+        );
+    }
+
+    #[test]
+    fn maps_unavailable_status_to_t02() {
+        assert_eq!(
+            status_error_code(StatusCode::SERVICE_UNAVAILABLE),
+            ErrorCode::T02_PEER_BUSY
+        );
+    }
+
+    #[test]
+    fn maps_timeout_statuses_to_r00() {
+        assert_eq!(
+            status_error_code(StatusCode::GATEWAY_TIMEOUT),
+            ErrorCode::R00_TRANSFER_TIMED_OUT
+        );
+        assert_eq!(
+            status_error_code(StatusCode::REQUEST_TIMEOUT),
+            ErrorCode::R00_TRANSFER_TIMED_OUT
+        );
+    }
+
+    #[test]
+    fn maps_payload_too_large_status_to_f00() {
+        assert_eq!(
+            status_error_code(StatusCode::PAYLOAD_TOO_LARGE),
+            ErrorCode::F00_BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn maps_other_client_error_statuses_to_f02() {
+        assert_eq!(
+            status_error_code(StatusCode::UNAUTHORIZED),
+            ErrorCode::F02_UNREACHABLE
+        );
+        assert_eq!(
+            status_error_code(StatusCode::NOT_FOUND),
+            ErrorCode::F02_UNREACHABLE
+        );
+    }
+
+    #[test]
+    fn maps_server_error_statuses_to_t01() {
+        assert_eq!(
+            status_error_code(StatusCode::INTERNAL_SERVER_ERROR),
+            ErrorCode::T01_PEER_UNREACHABLE
+        );
+        assert_eq!(
+            status_error_code(StatusCode::BAD_GATEWAY),
+            ErrorCode::T01_PEER_UNREACHABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn maps_request_timeouts_to_r00() {
+        // The peer accepts the connection but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let err = Client::new()
+            .post(&url)
+            .timeout(Duration::from_millis(50))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            transport_error_code(&err),
+            ErrorCode::R00_TRANSFER_TIMED_OUT
+        );
+    }
+
+    #[tokio::test]
+    async fn maps_connection_errors_to_t01() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let err = Client::new().post(&url).send().await.unwrap_err();
+        assert_eq!(transport_error_code(&err), ErrorCode::T01_PEER_UNREACHABLE);
+    }
+
+    #[test]
+    fn keeps_a_margin_before_the_packet_expires() {
+        let timeout = request_timeout(SystemTime::now() + Duration::from_secs(30)).unwrap();
+        assert!(timeout <= Duration::from_secs(30) - RESPONSE_MARGIN);
+        assert!(timeout > Duration::from_secs(29));
+        assert_eq!(
+            request_timeout(SystemTime::now() + RESPONSE_MARGIN / 2),
+            None
+        );
+        assert_eq!(request_timeout(UNIX_EPOCH), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_packets_without_sending_them() {
+        let mut service = HttpClientService::new(
+            TestStore,
+            outgoing_service_fn(|_| -> IlpResult { panic!("the packet was forwarded") }),
+        );
+        let reject = service
+            .send_request(OutgoingRequest {
+                from: TestAccount,
+                to: TestAccount,
+                original_amount: 100,
+                prepare: PrepareBuilder {
+                    destination: ILP_ADDRESS.clone(),
+                    amount: 100,
+                    expires_at: UNIX_EPOCH,
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::R00_TRANSFER_TIMED_OUT);
+        assert_eq!(reject.triggered_by(), Some(ILP_ADDRESS.clone()));
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let mut settings = HttpClientSettings::default();
+        settings
+            .headers
+            .insert("x-tenant".to_string(), "alice".to_string());
+        assert!(build_client(&settings, None).is_ok());
+        settings
+            .headers
+            .insert("bad header".to_string(), "value".to_string());
+        match build_client(&settings, None) {
+            Err(HttpClientSettingsError::InvalidHeader(name)) => assert_eq!(name, "bad header"),
+            _ => panic!("invalid header accepted"),
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            Uuid::nil()
+        }
+
+        fn username(&self) -> &Username {
+            &USERNAME
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &ILP_ADDRESS
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+    }
+
+    impl HttpAccount for TestAccount {
+        fn get_http_auth_token(&self) -> Option<SecretString> {
+            None
+        }
+
+        fn get_http_url(&self) -> Option<&Url> {
+            Some(&URL)
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestStore;
+
+    #[async_trait]
+    impl HttpStore for TestStore {
+        type Account = TestAccount;
+
+        async fn get_account_from_http_auth(
+            &self,
+            username: &Username,
+            _token: &str,
+        ) -> Result<Self::Account, HttpStoreError> {
+            Err(HttpStoreError::Unauthorized(username.to_string()))
+        }
+    }
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _ilp_address: Address) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            ILP_ADDRESS.clone()
+        }
+    }
+}
@@ -17,8 +17,8 @@ mod client;
 /// [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) API (implemented with [Warp](https://docs.rs/warp/0.2.0/warp/))
 mod server;
 
-pub use self::client::HttpClientService;
-pub use self::server::HttpServer;
+pub use self::client::{HttpClientService, HttpClientSettings, HttpClientSettingsError};
+pub use self::server::{ClientCertificateUsername, HttpServer};
 
 /// Extension trait for [Account](../interledger_service/trait.Account.html) with [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) related information
 pub trait HttpAccount: Account {
@@ -3,7 +3,7 @@ use bytes::{Bytes, BytesMut};
 use interledger_errors::ApiError;
 use interledger_packet::Prepare;
 use interledger_service::Username;
-use interledger_service::{IncomingRequest, IncomingService};
+use interledger_service::{AccountStore, IncomingRequest, IncomingService};
 use secrecy::{ExposeSecret, SecretString};
 use std::convert::TryFrom;
 use std::net::SocketAddr;
@@ -16,6 +16,12 @@ pub const MAX_PACKET_SIZE: u64 = 40000;
 /// e.g. in `token = "Bearer: MyAuthToken"`, `MyAuthToken` can be taken via token[BEARER_TOKEN_START..]
 pub const BEARER_TOKEN_START: usize = 7;
 
+/// The username of the account a request's TLS client certificate was issued to.
+/// Servers which verify client certificates insert it in the extensions of the requests,
+/// and ILP over HTTP requests which carry it for the account in their path need no token
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientCertificateUsername(pub Username);
+
 /// A warp filter that parses incoming ILP-Over-HTTP requests, validates the authorization,
 /// and passes the request to an IncomingService handler.
 #[derive(Clone)]
@@ -29,15 +35,27 @@ pub struct HttpServer<I, S> {
 #[inline]
 /// Returns the account which matches the provided username/password combination
 /// from the store, or returns an error if the account was not found or if the
-/// credentials were incorrect
+/// credentials were incorrect. The password is not needed if the request's
+/// client certificate was issued to the account
 async fn get_account<S>(
     store: S,
     path_username: &Username,
-    password: &SecretString,
-) -> Result<S::Account, ApiError>
+    password: Option<&SecretString>,
+    certificate_username: Option<&ClientCertificateUsername>,
+) -> Result<<S as HttpStore>::Account, ApiError>
 where
-    S: HttpStore,
+    S: HttpStore + AccountStore<Account = <S as HttpStore>::Account>,
 {
+    if certificate_username.map_or(false, |c| &c.0 == path_username) {
+        let id = store.get_account_id_from_username(path_username).await?;
+        let mut accounts = store.get_accounts(vec![id]).await?;
+        return accounts.pop().ok_or_else(|| {
+            ApiError::account_not_found().detail(format!("account {} not found", path_username))
+        });
+    }
+    let password = password.ok_or_else(|| {
+        ApiError::unauthorized().detail("no bearer token or client certificate was provided")
+    })?;
     if password.expose_secret().len() < BEARER_TOKEN_START {
         return Err(ApiError::unauthorized().detail("provided token was not a bearer token"));
     }
@@ -62,17 +80,24 @@ where
 /// 1. A Reject packet was returned by the next incoming service
 async fn ilp_over_http<S, I>(
     path_username: Username,
-    password: SecretString,
+    password: Option<SecretString>,
+    certificate_username: Option<ClientCertificateUsername>,
     body: Bytes,
     store: S,
     incoming: I,
 ) -> Result<impl warp::Reply, warp::Rejection>
 where
-    S: HttpStore,
-    I: IncomingService<S::Account> + Clone,
+    S: HttpStore + AccountStore<Account = <S as HttpStore>::Account>,
+    I: IncomingService<<S as HttpStore>::Account> + Clone,
 {
     let mut incoming = incoming.clone();
-    let account = get_account(store, &path_username, &password).await?;
+    let account = get_account(
+        store,
+        &path_username,
+        password.as_ref(),
+        certificate_username.as_ref(),
+    )
+    .await?;
 
     let buffer = bytes::BytesMut::from(body.as_ref());
     if let Ok(prepare) = Prepare::try_from(buffer) {
@@ -101,15 +126,16 @@ where
 
 impl<I, S> HttpServer<I, S>
 where
-    I: IncomingService<S::Account> + Clone + Send + Sync,
-    S: HttpStore + Clone,
+    I: IncomingService<<S as HttpStore>::Account> + Clone + Send + Sync,
+    S: HttpStore + AccountStore<Account = <S as HttpStore>::Account> + Clone,
 {
     pub fn new(incoming: I, store: S) -> Self {
         HttpServer { incoming, store }
     }
 
     /// Returns a Warp filter which exposes per-account endpoints for [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/).
-    /// The endpoint is /accounts/:username/ilp.
+    /// The endpoint is /accounts/:username/ilp. Requests authenticate with the account's
+    /// incoming token, or with a TLS client certificate issued to the account.
     pub fn as_filter(
         &self,
     ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
@@ -122,7 +148,8 @@ where
             .and(warp::path::param::<Username>())
             .and(warp::path("ilp"))
             .and(warp::path::end())
-            .and(warp::header::<SecretString>("authorization"))
+            .and(warp::header::optional::<SecretString>("authorization"))
+            .and(warp::ext::optional::<ClientCertificateUsername>())
             .and(warp::body::content_length_limit(MAX_PACKET_SIZE))
             .and(warp::body::bytes())
             .and(with_store)
@@ -144,7 +171,7 @@ mod tests {
     use async_trait::async_trait;
     use bytes::BytesMut;
     use http::Response;
-    use interledger_errors::{default_rejection_handler, HttpStoreError};
+    use interledger_errors::{default_rejection_handler, AccountStoreError, HttpStoreError};
     use interledger_packet::{Address, ErrorCode, PrepareBuilder, RejectBuilder};
     use interledger_service::{incoming_service_fn, Account};
     use once_cell::sync::Lazy;
@@ -221,6 +248,39 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 200);
     }
 
+    #[tokio::test]
+    async fn client_certificate_replaces_token() {
+        let incoming = incoming_service_fn(|_request| {
+            Err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: b"No other incoming handler!",
+                data: &[],
+                triggered_by: None,
+            }
+            .build())
+        });
+        let api = HttpServer::new(incoming, TestStore)
+            .as_filter()
+            .recover(default_rejection_handler);
+        let request = |username: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/accounts/alice/ilp")
+                .header("Content-length", 1000)
+                .extension(ClientCertificateUsername(
+                    Username::from_str(username).unwrap(),
+                ))
+                .body(PREPARE_BYTES.clone())
+        };
+
+        let resp = request("alice").reply(&api).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // The certificate only authenticates the account it was issued to
+        let resp = request("bob").reply(&api).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
     #[derive(Debug, Clone)]
     struct TestAccount;
     impl Account for TestAccount {
@@ -272,4 +332,27 @@ mod tests {
             }
         }
     }
+
+    #[async_trait]
+    impl AccountStore for TestStore {
+        type Account = TestAccount;
+
+        async fn get_accounts(
+            &self,
+            _account_ids: Vec<Uuid>,
+        ) -> Result<Vec<Self::Account>, AccountStoreError> {
+            Ok(vec![TestAccount])
+        }
+
+        async fn get_account_id_from_username(
+            &self,
+            username: &Username,
+        ) -> Result<Uuid, AccountStoreError> {
+            if username == &*USERNAME {
+                Ok(Uuid::new_v4())
+            } else {
+                Err(AccountStoreError::AccountNotFound(username.to_string()))
+            }
+        }
+    }
 }
@@ -490,6 +490,19 @@ impl fmt::Debug for Reject {
     }
 }
 
+impl fmt::Display for Reject {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{}: {}",
+            self.code(),
+            String::from_utf8_lossy(self.message())
+        )
+    }
+}
+
+impl std::error::Error for Reject {}
+
 impl<'a> RejectBuilder<'a> {
     pub fn build(&self) -> Reject {
         let (trigerred_by_message, len) = match self.triggered_by {
@@ -923,6 +936,18 @@ mod test_reject {
     fn test_into_data() {
         assert_eq!(REJECT.clone().into_data(), BytesMut::from(REJECT.data()));
     }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            REJECT.to_string(),
+            format!(
+                "{}: {}",
+                REJECT_BUILDER.code,
+                str::from_utf8(REJECT_BUILDER.message).unwrap()
+            )
+        );
+    }
 }
 
 #[cfg(test)]
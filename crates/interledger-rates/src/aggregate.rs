@@ -0,0 +1,101 @@
+use num::{rational::BigRational, traits::Signed};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Median of the rates. The median of an even number of rates is the mean of the middle two
+fn median(rates: &mut [BigRational]) -> BigRational {
+    rates.sort();
+    let middle = rates.len() / 2;
+    if rates.len() % 2 == 1 {
+        rates[middle].clone()
+    } else {
+        (&rates[middle - 1] + &rates[middle]) / BigRational::from_integer(2.into())
+    }
+}
+
+/// Combines the rates fetched from several providers into one rate per asset, the median of its rates.
+/// If `max_deviation` is set, the rates which deviate from the median by more than that fraction of it
+/// are rejected and the median of the remaining ones is used instead. Assets with no remaining rate are
+/// left out, as are non-positive rates
+pub fn aggregate_rates(
+    fetched: Vec<HashMap<String, BigRational>>,
+    max_deviation: Option<&BigRational>,
+) -> HashMap<String, BigRational> {
+    let mut rates_by_asset: HashMap<String, Vec<BigRational>> = HashMap::new();
+    for rates in fetched {
+        for (asset_code, rate) in rates {
+            if rate.is_positive() {
+                rates_by_asset.entry(asset_code).or_default().push(rate);
+            } else {
+                warn!("Ignoring non-positive {} rate: {}", asset_code, rate);
+            }
+        }
+    }
+
+    rates_by_asset
+        .into_iter()
+        .filter_map(|(asset_code, mut rates)| {
+            let mut rate = median(&mut rates);
+            if let Some(max_deviation) = max_deviation {
+                let (mut accepted, rejected): (Vec<_>, Vec<_>) = rates
+                    .into_iter()
+                    .partition(|candidate| (candidate - &rate).abs() / &rate <= *max_deviation);
+                if !rejected.is_empty() {
+                    warn!(
+                        "Rejecting {} rates which deviate from their median {} by more than {}: {:?}",
+                        asset_code, rate, max_deviation, rejected
+                    );
+                }
+                if accepted.is_empty() {
+                    return None;
+                }
+                rate = median(&mut accepted);
+            }
+            Some((asset_code, rate))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_rate;
+
+    fn rates(pairs: &[(&str, &str)]) -> HashMap<String, BigRational> {
+        pairs
+            .iter()
+            .map(|(asset_code, rate)| (asset_code.to_string(), parse_rate(rate).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn takes_the_median() {
+        let aggregated = aggregate_rates(
+            vec![
+                rates(&[("EUR", "1.1"), ("BTC", "9000"), ("XYZ", "0")]),
+                rates(&[("EUR", "1.3"), ("BTC", "9100")]),
+                rates(&[("EUR", "1.2"), ("ETH", "200")]),
+            ],
+            None,
+        );
+        assert_eq!(
+            aggregated,
+            rates(&[("EUR", "1.2"), ("BTC", "9050"), ("ETH", "200")])
+        );
+    }
+
+    #[test]
+    fn rejects_outliers() {
+        let fetched = vec![
+            rates(&[("EUR", "1.1"), ("BTC", "9000"), ("ETH", "100")]),
+            rates(&[("EUR", "1.12"), ("BTC", "1"), ("ETH", "300")]),
+            rates(&[("EUR", "2"), ("BTC", "20000")]),
+            rates(&[("EUR", "1.13")]),
+        ];
+        let max_deviation = parse_rate("0.05").unwrap();
+        let aggregated = aggregate_rates(fetched, Some(&max_deviation));
+        // The EUR median is 1.125, so 2 is rejected and the median of the rest is used.
+        // Both ETH rates deviate from their median of 200 by 50%, so there is no ETH rate
+        assert_eq!(aggregated, rates(&[("EUR", "1.12"), ("BTC", "9000")]));
+    }
+}
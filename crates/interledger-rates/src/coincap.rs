@@ -1,8 +1,10 @@
+use crate::parse_rate;
 use futures::TryFutureExt;
+use num::rational::BigRational;
 use once_cell::sync::Lazy;
 use reqwest::{Client, Url};
 use serde::Deserialize;
-use std::{collections::HashMap, str::FromStr};
+use std::collections::HashMap;
 use tracing::{error, warn};
 
 // We use both endpoints because they contain different sets of rates
@@ -25,24 +27,21 @@ struct RateResponse {
     data: Vec<Rate>,
 }
 
-pub async fn query_coincap(client: &Client) -> Result<HashMap<String, f64>, ()> {
+pub async fn query_coincap(client: &Client) -> Result<HashMap<String, BigRational>, ()> {
     let (assets, rates) = futures::future::join(
         query_coincap_endpoint(client, COINCAP_ASSETS_URL.clone()),
         query_coincap_endpoint(client, COINCAP_RATES_URL.clone()),
     )
     .await;
 
-    let all_rates: HashMap<String, f64> = assets?
+    let all_rates: HashMap<String, BigRational> = assets?
         .data
         .into_iter()
         .chain(rates?.data.into_iter())
-        .filter_map(|record| match f64::from_str(record.rate_usd.as_str()) {
+        .filter_map(|record| match parse_rate(record.rate_usd.as_str()) {
             Ok(rate) => Some((record.symbol.to_uppercase(), rate)),
             Err(err) => {
-                warn!(
-                    "Unable to parse {} rate as an f64: {} {:?}",
-                    record.symbol, record.rate_usd, err
-                );
+                warn!("Unable to parse {} rate: {}", record.symbol, err);
                 None
             }
         })
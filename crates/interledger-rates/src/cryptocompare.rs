@@ -1,4 +1,6 @@
+use crate::deserialize_rate;
 use futures::TryFutureExt;
+use num::{rational::BigRational, traits::One};
 use once_cell::sync::Lazy;
 use reqwest::{Client, Url};
 use secrecy::{ExposeSecret, SecretString};
@@ -15,8 +17,8 @@ static CRYPTOCOMPARE_URL: Lazy<Url> = Lazy::new(|| {
 
 #[derive(Deserialize, Debug)]
 struct Price {
-    #[serde(rename = "PRICE")]
-    price: f64,
+    #[serde(rename = "PRICE", deserialize_with = "deserialize_rate")]
+    price: BigRational,
 }
 
 #[derive(Deserialize, Debug)]
@@ -48,7 +50,7 @@ struct Response {
 pub async fn query_cryptocompare(
     client: &Client,
     api_key: &SecretString,
-) -> Result<HashMap<String, f64>, ()> {
+) -> Result<HashMap<String, BigRational>, ()> {
     // ref: https://github.com/rust-lang/rust/pull/64856
     let header = format!("Apikey {}", api_key.expose_secret());
     let res = client
@@ -91,6 +93,6 @@ pub async fn query_cryptocompare(
                 None
             }
         })
-        .chain(once(("USD".to_string(), 1.0)));
+        .chain(once(("USD".to_string(), BigRational::one())));
     Ok(HashMap::from_iter(rates))
 }
@@ -0,0 +1,258 @@
+use num::{
+    bigint::{BigInt, Sign},
+    rational::BigRational,
+    traits::{One, Signed, ToPrimitive, Zero},
+};
+use serde::{
+    de::{self, Visitor},
+    ser::SerializeMap,
+    Deserializer, Serializer,
+};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// Rates with larger exponents are rejected, rather than expanded to huge integers
+const MAX_EXPONENT: i32 = 1000;
+
+/// Parses an exact rate from a decimal such as `1.25`, `-0.5` or `3.2e-5`,
+/// or from a fraction such as `1/3`
+pub fn parse_rate(src: &str) -> Result<BigRational, String> {
+    let invalid = || format!("Invalid rate: {}", src);
+    let src = src.trim();
+    if let Some(slash) = src.find('/') {
+        let numer = BigInt::from_str(src[..slash].trim()).map_err(|_| invalid())?;
+        let denom = BigInt::from_str(src[slash + 1..].trim()).map_err(|_| invalid())?;
+        if denom.is_zero() {
+            return Err(invalid());
+        }
+        return Ok(BigRational::new(numer, denom));
+    }
+
+    let (mantissa, exponent) = match src.find(&['e', 'E'][..]) {
+        Some(e) => (
+            &src[..e],
+            i32::from_str(&src[e + 1..])
+                .ok()
+                .filter(|exponent| exponent.abs() <= MAX_EXPONENT)
+                .ok_or_else(invalid)?,
+        ),
+        None => (src, 0),
+    };
+    let (sign, unsigned) = match mantissa.chars().next() {
+        Some('-') => (Sign::Minus, &mantissa[1..]),
+        Some('+') => (Sign::Plus, &mantissa[1..]),
+        _ => (Sign::Plus, mantissa),
+    };
+    let (integer, fraction) = match unsigned.find('.') {
+        Some(dot) => (&unsigned[..dot], &unsigned[dot + 1..]),
+        None => (unsigned, ""),
+    };
+    let digits = format!("{}{}", integer, fraction);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let digits = BigInt::parse_bytes(digits.as_bytes(), 10).ok_or_else(invalid)?;
+    let digits = if sign == Sign::Minus { -digits } else { digits };
+
+    let exponent = exponent - fraction.len() as i32;
+    if exponent.abs() > MAX_EXPONENT {
+        return Err(invalid());
+    }
+    let ten = BigInt::from(10u8);
+    let scale = num::pow(ten, exponent.abs() as usize);
+    if exponent >= 0 {
+        Ok(BigRational::from_integer(digits * scale))
+    } else {
+        Ok(BigRational::new(digits, scale))
+    }
+}
+
+/// Converts the float to the rate it reads as, e.g. `0.1` becomes exactly 1/10
+/// rather than the nearest binary fraction. Returns None for NaN and infinities
+pub fn rate_from_f64(rate: f64) -> Option<BigRational> {
+    if rate.is_finite() {
+        parse_rate(&rate.to_string()).ok()
+    } else {
+        None
+    }
+}
+
+/// Formats the rate as an exact decimal, such as `1.25`, or as a fraction,
+/// such as `1/3`, if it has no finite decimal expansion
+pub fn format_rate(rate: &BigRational) -> String {
+    let ten = BigInt::from(10u8);
+    let mut denom = rate.denom().clone();
+    let mut places = 0usize;
+    // The denominator only divides a power of ten if its prime factors are 2 and 5
+    for factor in &[BigInt::from(2u8), BigInt::from(5u8)] {
+        let mut count = 0;
+        while (&denom % factor).is_zero() {
+            denom /= factor;
+            count += 1;
+        }
+        places = places.max(count);
+    }
+    if !denom.is_one() {
+        return format!("{}/{}", rate.numer(), rate.denom());
+    }
+
+    let scaled = rate * BigRational::from_integer(num::pow(ten, places));
+    let digits = scaled.to_integer().abs().to_string();
+    let sign = if rate.is_negative() { "-" } else { "" };
+    if places == 0 {
+        return format!("{}{}", sign, digits);
+    }
+    let digits = format!("{:0>width$}", digits, width = places + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - places);
+    format!("{}{}.{}", sign, integer, fraction)
+}
+
+/// Converts the rate to the nearest float, e.g. to write it to clients which expect numbers
+pub fn rate_to_f64(rate: &BigRational) -> f64 {
+    format_rate(rate).parse().unwrap_or_else(|_| {
+        // Fractions without a finite decimal expansion
+        match (rate.numer().to_f64(), rate.denom().to_f64()) {
+            (Some(numer), Some(denom)) => numer / denom,
+            _ => std::f64::NAN,
+        }
+    })
+}
+
+struct RateVisitor;
+
+impl<'de> Visitor<'de> for RateVisitor {
+    type Value = BigRational;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number, or a string with a decimal or a fraction")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(BigRational::from_integer(BigInt::from(value)))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(BigRational::from_integer(BigInt::from(value)))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        rate_from_f64(value).ok_or_else(|| E::custom(format!("Invalid rate: {}", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        parse_rate(value).map_err(E::custom)
+    }
+}
+
+/// Deserializes a rate from a number or a string, see [`parse_rate`](./fn.parse_rate.html)
+pub fn deserialize_rate<'de, D>(deserializer: D) -> Result<BigRational, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(RateVisitor)
+}
+
+/// Deserializes an optional rate, see [`deserialize_rate`](./fn.deserialize_rate.html)
+pub fn deserialize_optional_rate<'de, D>(deserializer: D) -> Result<Option<BigRational>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    struct Wrapper(#[serde(deserialize_with = "deserialize_rate")] BigRational);
+
+    let rate: Option<Wrapper> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(rate.map(|Wrapper(rate)| rate))
+}
+
+/// Serializes a rate as a string, see [`format_rate`](./fn.format_rate.html)
+pub fn serialize_rate<S>(rate: &BigRational, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&format_rate(rate))
+}
+
+/// (De)serializes a map of asset codes to rates, with `#[serde(with = "interledger_rates::rates_map")]`.
+/// Rates are read from numbers or strings, but written as numbers so that clients which
+/// expect them keep working
+pub mod rates_map {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Wrapper(#[serde(deserialize_with = "deserialize_rate")] BigRational);
+
+    pub fn serialize<S>(
+        rates: &HashMap<String, BigRational>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(rates.len()))?;
+        for (asset_code, rate) in rates {
+            map.serialize_entry(asset_code, &rate_to_f64(rate))?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<HashMap<String, BigRational>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let rates = HashMap::<String, Wrapper>::deserialize(deserializer)?;
+        Ok(rates
+            .into_iter()
+            .map(|(asset_code, Wrapper(rate))| (asset_code, rate))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(BigInt::from(numer), BigInt::from(denom))
+    }
+
+    #[test]
+    fn parses_decimals_and_fractions() {
+        assert_eq!(parse_rate("1.25").unwrap(), ratio(5, 4));
+        assert_eq!(parse_rate("-0.5").unwrap(), ratio(-1, 2));
+        assert_eq!(parse_rate("3.2e-5").unwrap(), ratio(32, 1_000_000));
+        assert_eq!(parse_rate("1E3").unwrap(), ratio(1000, 1));
+        assert_eq!(parse_rate(".5").unwrap(), ratio(1, 2));
+        assert_eq!(parse_rate("1/3").unwrap(), ratio(1, 3));
+        assert!(parse_rate("").is_err());
+        assert!(parse_rate("1.2.3").is_err());
+        assert!(parse_rate("1/0").is_err());
+        assert!(parse_rate("abc").is_err());
+        assert!(parse_rate("1e100000").is_err());
+    }
+
+    #[test]
+    fn converts_floats_to_the_decimal_they_read_as() {
+        assert_eq!(rate_from_f64(0.1).unwrap(), ratio(1, 10));
+        assert_eq!(rate_from_f64(1e-7).unwrap(), ratio(1, 10_000_000));
+        assert!(rate_from_f64(std::f64::NAN).is_none());
+        assert!(rate_from_f64(std::f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn converts_rates_to_the_nearest_float() {
+        assert_eq!(rate_to_f64(&ratio(5, 4)), 1.25);
+        assert_eq!(rate_to_f64(&ratio(1, 10)), 0.1);
+        assert_eq!(rate_to_f64(&ratio(1, 3)), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn formats_exactly() {
+        assert_eq!(format_rate(&ratio(5, 4)), "1.25");
+        assert_eq!(format_rate(&ratio(-1, 20)), "-0.05");
+        assert_eq!(format_rate(&ratio(7, 1)), "7");
+        assert_eq!(format_rate(&ratio(1, 3)), "1/3");
+        for rate in &["0.000001", "123456789.987654321", "2/3"] {
+            assert_eq!(format_rate(&parse_rate(rate).unwrap()), *rate);
+        }
+    }
+}
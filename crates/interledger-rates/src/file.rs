@@ -0,0 +1,34 @@
+use crate::rates_map;
+use num::rational::BigRational;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+use tracing::error;
+
+#[derive(Deserialize)]
+struct RatesFile(#[serde(with = "rates_map")] HashMap<String, BigRational>);
+
+/// Reads the rates from a JSON file which maps asset codes to rates, such as
+/// `{ "EUR": "1.1", "BTC": 9000 }`. The file is read on every poll, so it may
+/// be changed while the node is running
+pub async fn read_rates_file(path: &Path) -> Result<HashMap<String, BigRational>, ()> {
+    let contents = tokio::fs::read(path).await.map_err(|err| {
+        error!(
+            "Error reading exchange rates file {}: {:?}",
+            path.display(),
+            err
+        );
+    })?;
+
+    let RatesFile(rates) = serde_json::from_slice(&contents).map_err(|err| {
+        error!(
+            "Error parsing exchange rates file {}: {:?}",
+            path.display(),
+            err
+        );
+    })?;
+
+    Ok(rates
+        .into_iter()
+        .map(|(asset_code, rate)| (asset_code.to_uppercase(), rate))
+        .collect())
+}
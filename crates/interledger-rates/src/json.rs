@@ -0,0 +1,153 @@
+use crate::deserialize_rate;
+use futures::TryFutureExt;
+use num::{rational::BigRational, traits::Zero};
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::{error, warn};
+
+/// Any HTTP API which serves the rates of several assets in a JSON object, such as
+/// `{ "data": { "rates": { "EUR": "1.1", "BTC": 9000 } } }`
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonProvider {
+    /// URL to GET the rates from
+    pub url: String,
+    /// Dot-separated path to the object which maps asset codes to rates in the response,
+    /// e.g. `data.rates`. Array elements are selected by their index. Defaults to the whole response
+    #[serde(default)]
+    pub path: String,
+    /// Set if the API serves the amount of each asset a dollar buys, rather than
+    /// the price of each asset in dollars
+    #[serde(default)]
+    pub inverse: bool,
+    /// Headers to send along with the request, e.g. an API key
+    #[serde(default)]
+    pub headers: HashMap<String, SecretString>,
+}
+
+pub async fn query_json(
+    client: &Client,
+    provider: &JsonProvider,
+) -> Result<HashMap<String, BigRational>, ()> {
+    let mut request = client.get(provider.url.as_str());
+    for (name, value) in provider.headers.iter() {
+        request = request.header(name.as_str(), value.expose_secret().as_str());
+    }
+    let res = request
+        .send()
+        .map_err(|err| {
+            error!(
+                "Error fetching exchange rates from {}: {:?}",
+                provider.url, err
+            );
+        })
+        .await?;
+
+    let res = res.error_for_status().map_err(|err| {
+        error!(
+            "HTTP error getting exchange rates from {}: {:?}",
+            provider.url, err
+        );
+    })?;
+
+    let body: Value = res
+        .json()
+        .map_err(|err| {
+            error!(
+                "Error getting exchange rate response body from {}, invalid JSON: {:?}",
+                provider.url, err
+            );
+        })
+        .await?;
+
+    parse_rates(&body, &provider.path, provider.inverse).map_err(|err| {
+        error!(
+            "Error getting exchange rates from {}: {}",
+            provider.url, err
+        );
+    })
+}
+
+/// Finds the object at the path and parses the rates in it, skipping the invalid ones
+fn parse_rates(
+    body: &Value,
+    path: &str,
+    inverse: bool,
+) -> Result<HashMap<String, BigRational>, String> {
+    let rates = path
+        .split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(body, |value, segment| {
+            match value {
+                Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => value.get(segment),
+            }
+            .ok_or_else(|| format!("no value at {} in the response", path))
+        })?
+        .as_object()
+        .ok_or_else(|| format!("the value at {} in the response is not an object", path))?;
+
+    Ok(rates
+        .iter()
+        .filter_map(|(asset_code, rate)| match deserialize_rate(rate) {
+            Ok(rate) if inverse && rate.is_zero() => {
+                warn!("Unable to invert the {} rate of 0", asset_code);
+                None
+            }
+            Ok(rate) if inverse => Some((asset_code.to_uppercase(), rate.recip())),
+            Ok(rate) => Some((asset_code.to_uppercase(), rate)),
+            Err(err) => {
+                warn!("Unable to parse {} rate: {}", asset_code, err);
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_rate;
+    use serde_json::json;
+
+    #[test]
+    fn finds_rates_at_the_path() {
+        let body = json!({ "data": [{ "rates": { "eur": "1.1", "BTC": 9000, "XYZ": null } }] });
+        let rates = parse_rates(&body, "data.0.rates", false).unwrap();
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates["EUR"], parse_rate("1.1").unwrap());
+        assert_eq!(rates["BTC"], parse_rate("9000").unwrap());
+
+        let rates = parse_rates(&json!({ "EUR": 1.25 }), "", false).unwrap();
+        assert_eq!(rates["EUR"], parse_rate("1.25").unwrap());
+
+        assert!(parse_rates(&body, "data.1.rates", false).is_err());
+        assert!(parse_rates(&body, "data", false).is_err());
+    }
+
+    #[test]
+    fn inverts_rates() {
+        let rates = parse_rates(&json!({ "EUR": "0.8", "XYZ": 0 }), "", true).unwrap();
+        assert_eq!(rates.len(), 1);
+        assert_eq!(rates["EUR"], parse_rate("1.25").unwrap());
+    }
+
+    #[tokio::test]
+    async fn queries_the_url() {
+        let m = mockito::mock("GET", "/rates")
+            .match_header("authorization", "Bearer key")
+            .with_body(json!({ "rates": { "EUR": "1.1" } }).to_string())
+            .create();
+        let provider: JsonProvider = serde_json::from_value(json!({
+            "url": format!("{}/rates", mockito::server_url()),
+            "path": "rates",
+            "headers": { "Authorization": "Bearer key" },
+        }))
+        .unwrap();
+        let rates = query_json(&Client::new(), &provider).await.unwrap();
+        m.assert();
+        assert_eq!(rates["EUR"], parse_rate("1.1").unwrap());
+    }
+}
@@ -1,25 +1,48 @@
-use futures::TryFutureExt;
+use futures::future::join_all;
 use interledger_errors::ExchangeRateStoreError;
+use num::{rational::BigRational, traits::One};
 use reqwest::Client;
 use secrecy::SecretString;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, trace, warn};
 
+mod aggregate;
+pub use aggregate::aggregate_rates;
+
 mod cryptocompare;
 
+mod decimal;
+pub use decimal::{
+    deserialize_optional_rate, deserialize_rate, format_rate, parse_rate, rate_from_f64,
+    rate_to_f64, rates_map, serialize_rate,
+};
+
 mod coincap;
 
+mod file;
+
+mod json;
+pub use json::JsonProvider;
+
+/// Store trait for the exchange rates of the assets, relative to a common base asset (USD).
+/// Rates are exact rationals, so that converting amounts with them loses no precision
 pub trait ExchangeRateStore: Clone {
     // TODO we may want to make this async if/when we use pubsub to broadcast
     // rate changes to different instances of a horizontally-scalable node
-    fn set_exchange_rates(&self, rates: HashMap<String, f64>)
-        -> Result<(), ExchangeRateStoreError>;
+    fn set_exchange_rates(
+        &self,
+        rates: HashMap<String, BigRational>,
+    ) -> Result<(), ExchangeRateStoreError>;
 
-    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ExchangeRateStoreError>;
+    fn get_exchange_rates(
+        &self,
+        asset_codes: &[&str],
+    ) -> Result<Vec<BigRational>, ExchangeRateStoreError>;
 
     // TODO should this be on the API instead? That's where it's actually used
     // TODO should we combine this method with get_exchange_rates?
@@ -27,7 +50,9 @@ pub trait ExchangeRateStore: Clone {
     // (so that we don't accidentally lock up the RwLock on the store's exchange_rates)
     // but in the normal case of getting the rate between two assets, we don't want to
     // copy all the rate data
-    fn get_all_exchange_rates(&self) -> Result<HashMap<String, f64>, ExchangeRateStoreError>;
+    fn get_all_exchange_rates(
+        &self,
+    ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError>;
 }
 
 /// This determines which external API service to poll for exchange rates.
@@ -51,14 +76,31 @@ pub enum ExchangeRateProvider {
     /// [CryptoCompare]: https://cryptocompare.com
     #[serde(alias = "cryptocompare")]
     CryptoCompare(SecretString),
+    /// Use any HTTP API which serves the rates in a JSON object, found
+    /// at the configured path in its response.
+    ///
+    /// Note that when configured with YAML, this MUST be specified as
+    /// "Json", not "JSON".
+    #[serde(alias = "json")]
+    Json(JsonProvider),
+    /// Read the rates from a local JSON file which maps asset codes to rates.
+    #[serde(alias = "file")]
+    File(PathBuf),
 }
 
 /// Poll exchange rate providers for the current exchange rates
 #[derive(Clone)]
 pub struct ExchangeRateFetcher<S> {
-    provider: ExchangeRateProvider,
+    providers: Vec<ExchangeRateProvider>,
     consecutive_failed_polls: Arc<AtomicU32>,
     failed_polls_before_invalidation: u32,
+    max_deviation: Option<BigRational>,
+    /// The default max age of the rates
+    max_age: Option<Duration>,
+    /// The max ages of the rates of particular assets, by asset code
+    asset_max_ages: HashMap<String, Duration>,
+    /// The latest aggregated rate of each asset and when it was fetched
+    latest_rates: Arc<Mutex<HashMap<String, (BigRational, Instant)>>>,
     store: S,
     client: Client,
 }
@@ -67,26 +109,63 @@ impl<S> ExchangeRateFetcher<S>
 where
     S: ExchangeRateStore + Send + Sync + 'static,
 {
-    /// Simple constructor
+    /// Simple constructor. A poll only fails if none of the providers could be reached
     pub fn new(
-        provider: ExchangeRateProvider,
+        providers: Vec<ExchangeRateProvider>,
         failed_polls_before_invalidation: u32,
         store: S,
     ) -> Self {
         ExchangeRateFetcher {
-            provider,
+            providers,
             consecutive_failed_polls: Arc::new(AtomicU32::new(0)),
             failed_polls_before_invalidation,
+            max_deviation: None,
+            max_age: None,
+            asset_max_ages: HashMap::new(),
+            latest_rates: Arc::new(Mutex::new(HashMap::new())),
             store,
             client: Client::new(),
         }
     }
 
+    /// Rejects the rates which deviate from the median of an asset's rates
+    /// by more than this fraction of it, see [`aggregate_rates`](./fn.aggregate_rates.html)
+    pub fn max_deviation(mut self, max_deviation: BigRational) -> Self {
+        self.max_deviation = Some(max_deviation);
+        self
+    }
+
+    /// Keeps the rate of an asset which is missing from a poll until it is older than
+    /// `max_age`, and removes it then, so that the packets in that asset are rejected.
+    /// The rates of the assets without a max age are replaced on every poll
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets the max ages of the rates of particular assets, by asset code.
+    /// These take precedence over the default [`max_age`](#method.max_age)
+    pub fn asset_max_ages(mut self, asset_max_ages: HashMap<String, Duration>) -> Self {
+        self.asset_max_ages = asset_max_ages
+            .into_iter()
+            .map(|(asset_code, max_age)| (asset_code.to_uppercase(), max_age))
+            .collect();
+        self
+    }
+
+    /// The max age of the rate of the asset, if it has one
+    fn max_age_of(&self, asset_code: &str) -> Option<Duration> {
+        self.asset_max_ages
+            .get(asset_code)
+            .cloned()
+            .or(self.max_age)
+    }
+
     /// Spawns a future which calls [`self.update_rates()`](./struct.ExchangeRateFetcher.html#method.update_rates) every `interval`
     pub fn spawn_interval(self, interval: Duration) {
         debug!(
-            "Starting interval to poll exchange rate provider: {:?} for rates",
-            self.provider
+            "Starting interval to poll exchange rate providers: {:?} for rates",
+            self.providers
         );
         let interval = async move {
             let mut interval = tokio::time::interval(interval);
@@ -100,52 +179,274 @@ where
     }
 
     /// Calls the proper exchange rate provider
-    async fn fetch_rates(&self) -> Result<HashMap<String, f64>, ()> {
-        match self.provider {
+    async fn fetch_rates(
+        &self,
+        provider: &ExchangeRateProvider,
+    ) -> Result<HashMap<String, BigRational>, ()> {
+        match provider {
             ExchangeRateProvider::CryptoCompare(ref api_key) => {
                 cryptocompare::query_cryptocompare(&self.client, api_key).await
             }
             ExchangeRateProvider::CoinCap => coincap::query_coincap(&self.client).await,
+            ExchangeRateProvider::Json(ref provider) => {
+                json::query_json(&self.client, provider).await
+            }
+            ExchangeRateProvider::File(ref path) => file::read_rates_file(path).await,
         }
     }
 
-    /// Gets the exchange rates and proceeds to update the store with the newly polled values
+    /// Gets the exchange rates from every provider, aggregates them and
+    /// proceeds to update the store with the newly polled values
     async fn update_rates(&self) -> Result<(), ()> {
-        let consecutive_failed_polls = self.consecutive_failed_polls.clone();
-        let consecutive_failed_polls_zeroer = consecutive_failed_polls.clone();
-        let failed_polls_before_invalidation = self.failed_polls_before_invalidation;
-        let store = self.store.clone();
-        let store_clone = self.store.clone();
-        let provider = self.provider.clone();
-        #[allow(clippy::cognitive_complexity)]
-        let mut rates = self.fetch_rates()
-            .map_err(move |_| {
-                // Note that a race between the read on this line and the check on the line after
-                // is quite unlikely as long as the interval between polls is reasonable.
-                let failed_polls = consecutive_failed_polls.fetch_add(1, Ordering::Relaxed);
-                if failed_polls < failed_polls_before_invalidation {
-                    warn!("Failed to update exchange rates (previous consecutive failed attempts: {})", failed_polls);
-                } else {
-                    error!("Failed to update exchange rates (previous consecutive failed attempts: {}), removing old rates for safety", failed_polls);
-                    // Clear out all of the old rates
-                    if store.set_exchange_rates(HashMap::new()).is_err() {
-                        error!("Failed to clear exchange rates cache after exchange rates server became unresponsive; panicking");
-                        panic!("Failed to clear exchange rates cache after exchange rates server became unresponsive");
+        let fetched: Vec<HashMap<String, BigRational>> = join_all(
+            self.providers
+                .iter()
+                .map(|provider| self.fetch_rates(provider)),
+        )
+        .await
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect();
+        let now = Instant::now();
+        let polled = !fetched.is_empty();
+
+        let mut latest_rates = self.latest_rates.lock().unwrap();
+        if polled {
+            trace!("Fetched exchange rates: {:?}", fetched);
+            let rates = aggregate_rates(fetched, self.max_deviation.as_ref());
+            for (asset_code, rate) in rates {
+                latest_rates.insert(asset_code, (rate, now));
+            }
+        } else {
+            // Note that a race between the read on this line and the check on the line after
+            // is quite unlikely as long as the interval between polls is reasonable.
+            let failed_polls = self
+                .consecutive_failed_polls
+                .fetch_add(1, Ordering::Relaxed);
+            if failed_polls < self.failed_polls_before_invalidation {
+                warn!(
+                    "Failed to update exchange rates (previous consecutive failed attempts: {})",
+                    failed_polls
+                );
+                if self.max_age.is_none() && self.asset_max_ages.is_empty() {
+                    return Err(());
+                }
+            } else {
+                error!("Failed to update exchange rates (previous consecutive failed attempts: {}), removing old rates for safety", failed_polls);
+                latest_rates.clear();
+                // Clear out all of the old rates
+                if self.store.set_exchange_rates(HashMap::new()).is_err() {
+                    error!("Failed to clear exchange rates cache after exchange rates server became unresponsive; panicking");
+                    panic!("Failed to clear exchange rates cache after exchange rates server became unresponsive");
+                }
+                return Err(());
+            }
+        }
+
+        latest_rates.retain(
+            |asset_code, (_, fetched_at)| match self.max_age_of(asset_code) {
+                Some(max_age) => {
+                    let stale = now.duration_since(*fetched_at) > max_age;
+                    if stale {
+                        warn!(
+                            "The {} exchange rate was not updated for more than {:?}, removing it",
+                            asset_code, max_age
+                        );
                     }
+                    !stale
                 }
-            }).await?;
+                // Without a max age, only the rates from the latest successful poll are kept
+                None => !polled || *fetched_at == now,
+            },
+        );
+        let mut rates: HashMap<String, BigRational> = latest_rates
+            .iter()
+            .map(|(asset_code, (rate, _))| (asset_code.clone(), rate.clone()))
+            .collect();
+        drop(latest_rates);
 
-        trace!("Fetched exchange rates: {:?}", rates);
         let num_rates = rates.len();
-        rates.insert("USD".to_string(), 1.0);
-        if store_clone.set_exchange_rates(rates).is_ok() {
+        rates.insert("USD".to_string(), BigRational::one());
+        if self.store.set_exchange_rates(rates).is_err() {
+            error!("Error setting exchange rates in store");
+            return Err(());
+        }
+        if polled {
             // Reset our invalidation counter
-            consecutive_failed_polls_zeroer.store(0, Ordering::Relaxed);
-            debug!("Updated {} exchange rates from {:?}", num_rates, provider);
+            self.consecutive_failed_polls.store(0, Ordering::Relaxed);
+            debug!(
+                "Updated {} exchange rates from {:?}",
+                num_rates, self.providers
+            );
             Ok(())
         } else {
-            error!("Error setting exchange rates in store");
             Err(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[derive(Clone, Default)]
+    struct TestStore {
+        rates: Arc<Mutex<HashMap<String, BigRational>>>,
+    }
+
+    impl ExchangeRateStore for TestStore {
+        fn set_exchange_rates(
+            &self,
+            rates: HashMap<String, BigRational>,
+        ) -> Result<(), ExchangeRateStoreError> {
+            *self.rates.lock().unwrap() = rates;
+            Ok(())
+        }
+
+        fn get_exchange_rates(
+            &self,
+            _asset_codes: &[&str],
+        ) -> Result<Vec<BigRational>, ExchangeRateStoreError> {
+            unimplemented!()
+        }
+
+        fn get_all_exchange_rates(
+            &self,
+        ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError> {
+            Ok(self.rates.lock().unwrap().clone())
+        }
+    }
+
+    fn rates_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "interledger-rates-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn rate(rate: &str) -> BigRational {
+        parse_rate(rate).unwrap()
+    }
+
+    #[tokio::test]
+    async fn aggregates_the_rates_of_all_providers() {
+        let store = TestStore::default();
+        let fetcher = ExchangeRateFetcher::new(
+            vec![
+                ExchangeRateProvider::File(rates_file(
+                    "aggregate-1",
+                    r#"{ "EUR": "1.1", "BTC": 9000 }"#,
+                )),
+                ExchangeRateProvider::File(rates_file(
+                    "aggregate-2",
+                    r#"{ "EUR": "1.2", "btc": 9100 }"#,
+                )),
+                ExchangeRateProvider::File(rates_file("aggregate-3", r#"{ "EUR": "5" }"#)),
+                ExchangeRateProvider::File("/does/not/exist.json".into()),
+            ],
+            0,
+            store.clone(),
+        )
+        .max_deviation(rate("0.1"));
+        fetcher.update_rates().await.unwrap();
+
+        let rates = store.get_all_exchange_rates().unwrap();
+        assert_eq!(rates.len(), 3);
+        assert_eq!(rates["USD"], rate("1"));
+        assert_eq!(rates["EUR"], rate("1.15"));
+        assert_eq!(rates["BTC"], rate("9050"));
+    }
+
+    #[tokio::test]
+    async fn removes_stale_rates() {
+        let store = TestStore::default();
+        let path = rates_file("stale", r#"{ "EUR": "1.1", "BTC": 9000 }"#);
+        let provider = ExchangeRateProvider::File(path.clone());
+        let fetcher = ExchangeRateFetcher::new(vec![provider], 5, store.clone())
+            .max_age(Duration::from_millis(100));
+        fetcher.update_rates().await.unwrap();
+
+        // BTC is missing from the next polls, so it is only kept until it is too old
+        fs::write(&path, r#"{ "EUR": "1.2" }"#).unwrap();
+        fetcher.update_rates().await.unwrap();
+        assert_eq!(store.get_all_exchange_rates().unwrap()["BTC"], rate("9000"));
+        tokio::time::delay_for(Duration::from_millis(150)).await;
+        fetcher.update_rates().await.unwrap();
+        let rates = store.get_all_exchange_rates().unwrap();
+        assert_eq!(rates["EUR"], rate("1.2"));
+        assert!(!rates.contains_key("BTC"));
+
+        // The rates expire even while the providers cannot be reached
+        fs::remove_file(&path).unwrap();
+        tokio::time::delay_for(Duration::from_millis(150)).await;
+        assert!(fetcher.update_rates().await.is_err());
+        let rates = store.get_all_exchange_rates().unwrap();
+        assert_eq!(rates.len(), 1);
+        assert_eq!(rates["USD"], rate("1"));
+    }
+
+    #[tokio::test]
+    async fn uses_the_max_age_of_each_asset() {
+        let store = TestStore::default();
+        let path = rates_file(
+            "asset-max-age",
+            r#"{ "EUR": "1.1", "BTC": 9000, "XRP": "0.2" }"#,
+        );
+        let provider = ExchangeRateProvider::File(path.clone());
+        let mut asset_max_ages = HashMap::new();
+        asset_max_ages.insert("btc".to_string(), Duration::from_secs(60));
+        let fetcher = ExchangeRateFetcher::new(vec![provider], 5, store.clone())
+            .max_age(Duration::from_millis(100))
+            .asset_max_ages(asset_max_ages);
+        fetcher.update_rates().await.unwrap();
+
+        fs::write(&path, r#"{ "EUR": "1.2" }"#).unwrap();
+        tokio::time::delay_for(Duration::from_millis(150)).await;
+        fetcher.update_rates().await.unwrap();
+        let rates = store.get_all_exchange_rates().unwrap();
+        assert_eq!(rates["EUR"], rate("1.2"));
+        // BTC has a longer max age than the default one
+        assert_eq!(rates["BTC"], rate("9000"));
+        assert!(!rates.contains_key("XRP"));
+
+        // Without a default, the assets without a max age are replaced on every poll
+        let store = TestStore::default();
+        fs::write(&path, r#"{ "EUR": "1.1", "BTC": 9000 }"#).unwrap();
+        let mut asset_max_ages = HashMap::new();
+        asset_max_ages.insert("BTC".to_string(), Duration::from_secs(60));
+        let fetcher = ExchangeRateFetcher::new(
+            vec![ExchangeRateProvider::File(path.clone())],
+            5,
+            store.clone(),
+        )
+        .asset_max_ages(asset_max_ages);
+        fetcher.update_rates().await.unwrap();
+        fs::write(&path, r#"{ "XRP": "0.2" }"#).unwrap();
+        fetcher.update_rates().await.unwrap();
+        let rates = store.get_all_exchange_rates().unwrap();
+        assert_eq!(rates["BTC"], rate("9000"));
+        assert_eq!(rates["XRP"], rate("0.2"));
+        assert!(!rates.contains_key("EUR"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clears_rates_after_failed_polls() {
+        let store = TestStore::default();
+        let path = rates_file("failures", r#"{ "EUR": "1.1" }"#);
+        let provider = ExchangeRateProvider::File(path.clone());
+        let fetcher = ExchangeRateFetcher::new(vec![provider], 1, store.clone());
+        fetcher.update_rates().await.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // The rates are kept until more polls than the tolerance fail
+        assert!(fetcher.update_rates().await.is_err());
+        assert_eq!(store.get_all_exchange_rates().unwrap()["EUR"], rate("1.1"));
+        assert!(fetcher.update_rates().await.is_err());
+        assert!(store.get_all_exchange_rates().unwrap().is_empty());
+    }
+}
@@ -11,14 +11,42 @@
 //! only using the information provided by the store. The routing table in the
 //! store can either be configured or populated using the `CcpRouteManager`
 //! (see the `interledger-ccp` crate for more details).
+//!
+//! A prefix may also have several next hops (see [`NextHop`](./struct.NextHop.html)),
+//! in which case the Router spreads packets across them by weight and retries
+//! rejected packets through the other next hops.
 
 use interledger_service::AccountStore;
-use std::{collections::HashMap, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use uuid::Uuid;
 
+mod prefix_map;
 mod router;
 
-pub use self::router::Router;
+pub use self::prefix_map::{Iter, PrefixMap};
+pub use self::router::{Router, DEFAULT_FAILOVER_CODES};
+
+/// One of the next hops of a prefix which has several of them.
+///
+/// Next hops with a non-zero weight share the packets for their prefix
+/// in proportion to their weights. Next hops with a weight of zero are
+/// backups which only receive packets after all the weighted ones rejected
+/// them, in the order in which they are listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NextHop {
+    /// The account packets are forwarded to
+    pub account_id: Uuid,
+    /// The share of packets which are forwarded to this account
+    #[serde(default = "NextHop::default_weight")]
+    pub weight: u32,
+}
+
+impl NextHop {
+    fn default_weight() -> u32 {
+        1
+    }
+}
 
 /// A trait for Store implmentations that have ILP routing tables.
 pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
@@ -27,5 +55,17 @@ pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
     /// keep the routing table in memory and use PubSub or polling to keep it updated.
     /// This ensures that individual packets can be routed without hitting the underlying store.
     /// An Arc is returned to avoid copying the underlying data while processing each packet.
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>>;
+    /// Stores should build a new table whenever the routes change and swap it in as a whole,
+    /// so that packets are never routed using a partially updated table.
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>>;
+
+    /// **Synchronously** return the prefixes which have more than one next hop.
+    /// Each prefix in this table must also be in the `routing_table`, and its first
+    /// next hop must be the account the prefix is mapped to there; other entries
+    /// are ignored by the Router. Prefixes which are not in this table are routed
+    /// to the single account from the `routing_table`.
+    /// The default implementation returns an empty table.
+    fn multipath_routing_table(&self) -> Arc<PrefixMap<Vec<NextHop>>> {
+        Arc::new(PrefixMap::new())
+    }
 }
@@ -0,0 +1,396 @@
+use std::{fmt, iter::FromIterator, mem, ops::Index};
+
+/// A map from ILP address prefixes to values, which resolves addresses to
+/// the value of their longest matching prefix.
+///
+/// The prefixes are stored in a radix tree (a trie in which nodes with a single
+/// child are merged with that child), so looking up an address takes time
+/// proportional to the length of the address, regardless of how many prefixes
+/// are in the map. The empty prefix matches every address, so it can be used
+/// as a catch-all route.
+///
+/// Prefixes are matched byte by byte, so the prefix `example.a` matches both
+/// `example.a.b` and `example.ab`.
+#[derive(Clone, PartialEq)]
+pub struct PrefixMap<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+#[derive(Clone, PartialEq)]
+struct Node<T> {
+    /// The part of the prefix between this node's parent and this node
+    label: Vec<u8>,
+    /// The value of the prefix which ends at this node, if any
+    value: Option<T>,
+    /// Sorted by the first byte of their labels, which are all distinct and non-empty
+    children: Vec<Node<T>>,
+}
+
+impl<T> Node<T> {
+    fn new(label: Vec<u8>, value: Option<T>) -> Self {
+        Node {
+            label,
+            value,
+            children: Vec::new(),
+        }
+    }
+
+    fn child_index(&self, first_byte: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by_key(&first_byte, |child| child.label[0])
+    }
+
+    /// Returns the child whose label the key starts with
+    fn matching_child(&self, key: &[u8]) -> Option<&Node<T>> {
+        let index = self.child_index(*key.first()?).ok()?;
+        let child = &self.children[index];
+        if key.starts_with(&child.label) {
+            Some(child)
+        } else {
+            None
+        }
+    }
+
+    fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
+        if key.is_empty() {
+            return self.value.replace(value);
+        }
+
+        match self.child_index(key[0]) {
+            Ok(index) => {
+                let child = &mut self.children[index];
+                let common = child
+                    .label
+                    .iter()
+                    .zip(key)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if common < child.label.len() {
+                    child.split(common);
+                }
+                child.insert(&key[common..], value)
+            }
+            Err(index) => {
+                self.children
+                    .insert(index, Node::new(key.to_vec(), Some(value)));
+                None
+            }
+        }
+    }
+
+    /// Splits this node's label so that it ends after `at` bytes,
+    /// moving the rest of the label and this node's contents into a new child
+    fn split(&mut self, at: usize) {
+        let child = Node {
+            label: self.label.split_off(at),
+            value: self.value.take(),
+            children: mem::take(&mut self.children),
+        };
+        self.children.push(child);
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<T> {
+        if key.is_empty() {
+            return self.value.take();
+        }
+
+        let index = self.child_index(key[0]).ok()?;
+        let child = &mut self.children[index];
+        if !key.starts_with(&child.label) {
+            return None;
+        }
+        let removed = child.remove(&key[child.label.len()..])?;
+
+        // Keep the tree compact: nodes without a value must have at least two children
+        if child.value.is_none() {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(index);
+                }
+                1 => {
+                    let grandchild = child.children.remove(0);
+                    child.label.extend(grandchild.label);
+                    child.value = grandchild.value;
+                    child.children = grandchild.children;
+                }
+                _ => {}
+            }
+        }
+        Some(removed)
+    }
+}
+
+impl<T> PrefixMap<T> {
+    pub fn new() -> Self {
+        PrefixMap {
+            root: Node::new(Vec::new(), None),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of prefixes in the map
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Sets the value of the given prefix, returning its previous value if there was one
+    pub fn insert(&mut self, prefix: &str, value: T) -> Option<T> {
+        let previous = self.root.insert(prefix.as_bytes(), value);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Removes the given prefix, returning its value if it was in the map
+    pub fn remove(&mut self, prefix: &str) -> Option<T> {
+        let removed = self.root.remove(prefix.as_bytes());
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Returns the value of the given prefix (exact match only)
+    pub fn get(&self, prefix: &str) -> Option<&T> {
+        let mut node = &self.root;
+        let mut key = prefix.as_bytes();
+        while !key.is_empty() {
+            node = node.matching_child(key)?;
+            key = &key[node.label.len()..];
+        }
+        node.value.as_ref()
+    }
+
+    pub fn contains_key(&self, prefix: &str) -> bool {
+        self.get(prefix).is_some()
+    }
+
+    /// Returns the longest prefix in the map which the given address starts with,
+    /// along with its value
+    pub fn resolve<'a>(&self, address: &'a str) -> Option<(&'a str, &T)> {
+        let mut node = &self.root;
+        let mut matched = 0;
+        let mut best = node.value.as_ref().map(|value| (0, value));
+        while let Some(child) = node.matching_child(&address.as_bytes()[matched..]) {
+            matched += child.label.len();
+            node = child;
+            if let Some(ref value) = node.value {
+                best = Some((matched, value));
+            }
+        }
+        // Every prefix in the map is a valid string, so it ends on a character boundary
+        best.map(|(len, value)| (&address[..len], value))
+    }
+
+    /// Iterates over the prefixes and their values, ordered by prefix
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            stack: vec![(&self.root, 0)],
+            key: Vec::new(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = String> + '_ {
+        self.iter().map(|(prefix, _)| prefix)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> + '_ {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<T> Default for PrefixMap<T> {
+    fn default() -> Self {
+        PrefixMap::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PrefixMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T> Index<&str> for PrefixMap<T> {
+    type Output = T;
+
+    fn index(&self, prefix: &str) -> &T {
+        self.get(prefix).expect("prefix not found in PrefixMap")
+    }
+}
+
+impl<K: AsRef<str>, T> FromIterator<(K, T)> for PrefixMap<T> {
+    fn from_iter<I: IntoIterator<Item = (K, T)>>(iter: I) -> Self {
+        let mut map = PrefixMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: AsRef<str>, T> Extend<(K, T)> for PrefixMap<T> {
+    fn extend<I: IntoIterator<Item = (K, T)>>(&mut self, iter: I) {
+        for (prefix, value) in iter {
+            self.insert(prefix.as_ref(), value);
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PrefixMap<T> {
+    type Item = (String, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// An iterator over the prefixes of a [`PrefixMap`](./struct.PrefixMap.html) and their values
+pub struct Iter<'a, T> {
+    /// Nodes which remain to be visited, along with the length of their parent's prefix
+    stack: Vec<(&'a Node<T>, usize)>,
+    /// The prefix of the last visited node
+    key: Vec<u8>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (String, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, parent_len)) = self.stack.pop() {
+            self.key.truncate(parent_len);
+            self.key.extend_from_slice(&node.label);
+            let len = self.key.len();
+            self.stack
+                .extend(node.children.iter().rev().map(|child| (child, len)));
+            if let Some(ref value) = node.value {
+                // Labels may be split in the middle of a character,
+                // but the full prefixes are always valid strings
+                let prefix = String::from_utf8(self.key.clone())
+                    .expect("PrefixMap keys are always valid UTF-8");
+                return Some((prefix, value));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doesnt_insert_duplicates() {
+        let mut map = PrefixMap::new();
+        assert!(map.insert("example.a", 1).is_none());
+        assert_eq!(map.insert("example.a", 2), Some(1));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["example.a"], 2);
+    }
+
+    #[test]
+    fn removes_entry() {
+        let mut map = PrefixMap::new();
+        map.insert("example.a", 1);
+        assert_eq!(map.remove("example.a"), Some(1));
+        assert!(map.remove("example.a").is_none());
+        assert!(map.is_empty());
+        assert!(map == PrefixMap::new());
+    }
+
+    #[test]
+    fn resolves_to_longest_matching_prefix() {
+        let mut map = PrefixMap::new();
+        map.insert("example.a", 1);
+        map.insert("example.a.b.c", 2);
+        map.insert("example.a.b", 3);
+
+        assert_eq!(map.resolve("example.a"), Some(("example.a", &1)));
+        assert_eq!(map.resolve("example.a.b.c"), Some(("example.a.b.c", &2)));
+        assert_eq!(
+            map.resolve("example.a.b.c.d.e"),
+            Some(("example.a.b.c", &2))
+        );
+        assert_eq!(map.resolve("example.a.b.x"), Some(("example.a.b", &3)));
+        assert_eq!(map.resolve("example.ab"), Some(("example.a", &1)));
+        assert!(map.resolve("example.other").is_none());
+    }
+
+    #[test]
+    fn empty_prefix_matches_everything() {
+        let mut map = PrefixMap::new();
+        map.insert("", 0);
+        map.insert("example.a", 1);
+
+        assert_eq!(map.resolve("example.a.b"), Some(("example.a", &1)));
+        assert_eq!(map.resolve("example.b"), Some(("", &0)));
+        assert_eq!(map.resolve(""), Some(("", &0)));
+    }
+
+    #[test]
+    fn get_only_returns_exact_matches() {
+        let map: PrefixMap<i32> = vec![("example.a", 1), ("example.a.b", 2)]
+            .into_iter()
+            .collect();
+        assert_eq!(map.get("example.a"), Some(&1));
+        assert_eq!(map.get("example.a.b"), Some(&2));
+        assert!(map.get("example.").is_none());
+        assert!(map.get("example.a.b.c").is_none());
+        assert!(!map.contains_key(""));
+    }
+
+    #[test]
+    fn compacts_after_removing_entries() {
+        let mut map = PrefixMap::new();
+        map.insert("example.ab", 1);
+        map.insert("example.ac", 2);
+        map.insert("example.a", 3);
+        map.remove("example.a");
+        map.remove("example.ac");
+
+        let expected: PrefixMap<i32> = vec![("example.ab", 1)].into_iter().collect();
+        assert!(map == expected);
+        assert_eq!(map.resolve("example.abc"), Some(("example.ab", &1)));
+        assert!(map.resolve("example.a").is_none());
+    }
+
+    #[test]
+    fn iterates_in_prefix_order() {
+        let map: PrefixMap<i32> = vec![
+            ("example.b", 3),
+            ("", 0),
+            ("example.a.b", 2),
+            ("example.a", 1),
+        ]
+        .into_iter()
+        .collect();
+        let entries: Vec<(String, i32)> = map.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("".to_string(), 0),
+                ("example.a".to_string(), 1),
+                ("example.a.b".to_string(), 2),
+                ("example.b".to_string(), 3),
+            ]
+        );
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn handles_multibyte_characters() {
+        let mut map = PrefixMap::new();
+        map.insert("example.é", 1);
+        map.insert("example.è", 2);
+
+        assert_eq!(map.resolve("example.é.x"), Some(("example.é", &1)));
+        assert_eq!(map.resolve("example.è"), Some(("example.è", &2)));
+        let keys: Vec<String> = map.keys().collect();
+        assert_eq!(keys, vec!["example.è", "example.é"]);
+    }
+}
@@ -1,9 +1,19 @@
-use super::RouterStore;
+use super::{NextHop, RouterStore};
 use async_trait::async_trait;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::*;
+use rand::Rng;
 use std::str;
-use tracing::{error, trace};
+use std::time::SystemTime;
+use tracing::{debug, error, trace};
+use uuid::Uuid;
+
+/// The reject codes which make the Router retry a packet through the next
+/// hop of its prefix, if the prefix has more than one
+pub const DEFAULT_FAILOVER_CODES: &[ErrorCode] = &[
+    ErrorCode::T01_PEER_UNREACHABLE,
+    ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+];
 
 /// # Interledger Router
 ///
@@ -15,6 +25,12 @@ use tracing::{error, trace};
 /// The router implements the IncomingService trait and uses the routing table
 /// to determine the `to` (or "next hop") Account for the given request.
 ///
+/// If the matching prefix has several next hops in the store's multipath routing table,
+/// the router picks one of them according to their weights, and if that account rejects
+/// the packet with one of the failover codes, retries the packet through the others.
+/// Rejects triggered by this node itself (for example by its own balance checks) are
+/// returned as they are, and no route is tried once the Prepare has expired.
+///
 /// Note that the router does **not**:
 ///   - apply exchange rates or fees to the Prepare packet
 ///   - adjust account balances
@@ -26,6 +42,7 @@ use tracing::{error, trace};
 pub struct Router<S, O> {
     store: S,
     next: O,
+    failover_codes: Vec<ErrorCode>,
 }
 
 impl<S, O> Router<S, O>
@@ -34,10 +51,55 @@ where
     O: OutgoingService<S::Account>,
 {
     pub fn new(store: S, next: O) -> Self {
-        Router { store, next }
+        Router {
+            store,
+            next,
+            failover_codes: DEFAULT_FAILOVER_CODES.to_vec(),
+        }
+    }
+
+    /// Sets the reject codes which make the router retry a packet through
+    /// the other next hops of its prefix (defaults to T01 and T04)
+    pub fn failover_codes(&mut self, failover_codes: Vec<ErrorCode>) -> &mut Self {
+        self.failover_codes = failover_codes;
+        self
     }
 }
 
+/// Returns the order in which the next hops should be tried.
+/// The next hops with a weight are shuffled so that each one is more likely to
+/// come first the larger its weight is, and are followed by the backup next hops
+/// (those with a weight of zero) in the order they were configured in.
+fn attempt_order(next_hops: &[NextHop]) -> Vec<Uuid> {
+    let mut rng = rand::thread_rng();
+    let mut weighted: Vec<&NextHop> = next_hops.iter().filter(|hop| hop.weight > 0).collect();
+    let mut order = Vec::with_capacity(next_hops.len());
+    while !weighted.is_empty() {
+        let total: u64 = weighted.iter().map(|hop| u64::from(hop.weight)).sum();
+        let mut point = rng.gen_range(0, total);
+        let index = weighted
+            .iter()
+            .position(|hop| {
+                let weight = u64::from(hop.weight);
+                if point < weight {
+                    true
+                } else {
+                    point -= weight;
+                    false
+                }
+            })
+            .expect("point is always less than the total weight");
+        order.push(weighted.remove(index).account_id);
+    }
+    order.extend(
+        next_hops
+            .iter()
+            .filter(|hop| hop.weight == 0)
+            .map(|hop| hop.account_id),
+    );
+    order
+}
+
 #[async_trait]
 impl<S, O> IncomingService<S::Account> for Router<S, O>
 where
@@ -46,68 +108,46 @@ where
 {
     /// Figures out the next node to pass the received Prepare packet to.
     ///
-    /// Firstly, it checks if there is a direct path for that account and uses that.
-    /// If not it scans through the routing table and checks if the route prefix matches
-    /// the prepare packet's destination or if it's a catch-all address (i.e. empty prefix)
+    /// The next hop is the account of the longest prefix in the routing table
+    /// which matches the prepare packet's destination. This is either a direct
+    /// path for that address, a route for one of its prefixes, or the catch-all
+    /// route (i.e. empty prefix)
+    ///
+    /// If that prefix has several next hops, they are tried one after the other
+    /// for as long as they reject the packet with one of the failover codes.
     async fn handle_request(&mut self, request: IncomingRequest<S::Account>) -> IlpResult {
         let destination = request.prepare.destination();
-        let mut next_hop = None;
         let routing_table = self.store.routing_table();
         let ilp_address = self.store.get_ilp_address();
 
-        // Check if we have a direct path for that account or if we need to scan
-        // through the routing table
         let dest: &str = &destination;
-        if let Some(account_id) = routing_table.get(dest) {
-            trace!(
-                "Found direct route for address: \"{}\". Account: {}",
-                destination,
-                account_id
-            );
-            next_hop = Some(*account_id);
-        } else if !routing_table.is_empty() {
-            let mut matching_prefix = "";
-            let routing_table = self.store.routing_table();
-            for (ref prefix, account) in (*routing_table).iter() {
-                // Check if the route prefix matches or is empty (meaning it's a catch-all address)
-                if (prefix.is_empty() || dest.starts_with(prefix.as_str()))
-                    && prefix.len() >= matching_prefix.len()
-                {
-                    next_hop.replace(account.clone());
-                    matching_prefix = prefix.as_str();
-                }
-            }
-            if let Some(account_id) = next_hop {
+        let next_hops = match routing_table.resolve(dest) {
+            Some((prefix, account_id)) => {
                 trace!(
                     "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
                     destination,
-                    matching_prefix,
+                    prefix,
                     account_id,
                 );
-            }
-        } else {
-            error!("Unable to route request because routing table is empty");
-        }
-
-        if let Some(account_id) = next_hop {
-            let mut next = self.next.clone();
-            match self.store.get_accounts(vec![account_id]).await {
-                Ok(mut accounts) => {
-                    let request = request.into_outgoing(accounts.remove(0));
-                    next.send_request(request).await
-                }
-                Err(_) => {
-                    error!("No record found for account: {}", account_id);
-                    Err(RejectBuilder {
-                        code: ErrorCode::F02_UNREACHABLE,
-                        message: &[],
-                        triggered_by: Some(&ilp_address),
-                        data: &[],
+                match self.store.multipath_routing_table().get(prefix) {
+                    Some(next_hops)
+                        if next_hops.first().map(|hop| hop.account_id) == Some(*account_id) =>
+                    {
+                        trace!("Prefix \"{}\" has next hops: {:?}", prefix, next_hops);
+                        attempt_order(next_hops)
                     }
-                    .build())
+                    _ => vec![*account_id],
+                }
+            }
+            None => {
+                if routing_table.is_empty() {
+                    error!("Unable to route request because routing table is empty");
                 }
+                Vec::new()
             }
-        } else {
+        };
+
+        if next_hops.is_empty() {
             error!(
                 "No route found for request {}: {:?}",
                 {
@@ -126,20 +166,58 @@ where
                 },
                 request
             );
-            Err(RejectBuilder {
+            return Err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: &[],
                 triggered_by: Some(&ilp_address),
                 data: &[],
             }
-            .build())
+            .build());
         }
+
+        let last = next_hops.len() - 1;
+        for (attempt, account_id) in next_hops.into_iter().enumerate() {
+            let result = match self.store.get_accounts(vec![account_id]).await {
+                Ok(mut accounts) => {
+                    let mut next = self.next.clone();
+                    let request = request.clone().into_outgoing(accounts.remove(0));
+                    next.send_request(request).await
+                }
+                Err(_) => {
+                    error!("No record found for account: {}", account_id);
+                    Err(RejectBuilder {
+                        code: ErrorCode::F02_UNREACHABLE,
+                        message: &[],
+                        triggered_by: Some(&ilp_address),
+                        data: &[],
+                    }
+                    .build())
+                }
+            };
+            match result {
+                Err(ref reject)
+                    if attempt < last
+                        && self.failover_codes.contains(&reject.code())
+                        && reject.triggered_by().as_ref() != Some(&ilp_address)
+                        && SystemTime::now() < request.prepare.expires_at() =>
+                {
+                    debug!(
+                        "Account {} rejected packet with code {}, trying the next route",
+                        account_id,
+                        reject.code()
+                    );
+                }
+                _ => return result,
+            }
+        }
+        unreachable!("the last next hop always returns")
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::PrefixMap;
     use interledger_errors::*;
     use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
     use interledger_service::outgoing_service_fn;
@@ -149,7 +227,7 @@ mod tests {
     use std::iter::FromIterator;
     use std::str::FromStr;
     use std::sync::Arc;
-    use std::time::UNIX_EPOCH;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     use uuid::Uuid;
 
     #[derive(Debug, Clone)]
@@ -181,9 +259,10 @@ mod tests {
         }
     }
 
-    #[derive(Clone)]
+    #[derive(Clone, Default)]
     struct TestStore {
         routes: HashMap<String, Uuid>,
+        multipath_routes: HashMap<String, Vec<NextHop>>,
     }
 
     #[async_trait]
@@ -224,8 +303,12 @@ mod tests {
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-            Arc::new(self.routes.clone())
+        fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+            Arc::new(PrefixMap::from_iter(self.routes.clone()))
+        }
+
+        fn multipath_routing_table(&self) -> Arc<PrefixMap<Vec<NextHop>>> {
+            Arc::new(PrefixMap::from_iter(self.multipath_routes.clone()))
         }
     }
 
@@ -234,6 +317,7 @@ mod tests {
         let mut router = Router::new(
             TestStore {
                 routes: HashMap::new(),
+                ..Default::default()
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -267,6 +351,7 @@ mod tests {
                 routes: HashMap::from_iter(
                     vec![("example.other".to_string(), Uuid::new_v4())].into_iter(),
                 ),
+                ..Default::default()
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -300,6 +385,7 @@ mod tests {
                 routes: HashMap::from_iter(
                     vec![("example.destination".to_string(), Uuid::new_v4())].into_iter(),
                 ),
+                ..Default::default()
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -331,6 +417,7 @@ mod tests {
         let mut router = Router::new(
             TestStore {
                 routes: HashMap::from_iter(vec![(String::new(), Uuid::new_v4())].into_iter()),
+                ..Default::default()
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -364,6 +451,7 @@ mod tests {
                 routes: HashMap::from_iter(
                     vec![("example.".to_string(), Uuid::new_v4())].into_iter(),
                 ),
+                ..Default::default()
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -407,6 +495,7 @@ mod tests {
                     ]
                     .into_iter(),
                 ),
+                ..Default::default()
             },
             outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
                 *to_clone.lock() = Some(request.to);
@@ -435,4 +524,332 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(to.lock().take().unwrap().0, id2);
     }
+
+    fn prepare_to(destination: &str) -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount(Uuid::new_v4()),
+            prepare: PrepareBuilder {
+                destination: Address::from_str(destination).unwrap(),
+                amount: 100,
+                execution_condition: &[1; 32],
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    fn reject(code: ErrorCode) -> IlpResult {
+        Err(RejectBuilder {
+            code,
+            message: &[],
+            triggered_by: None,
+            data: &[],
+        }
+        .build())
+    }
+
+    fn fulfill() -> IlpResult {
+        Ok(FulfillBuilder {
+            fulfillment: &[0; 32],
+            data: &[],
+        }
+        .build())
+    }
+
+    fn multipath_store(prefix: &str, next_hops: Vec<NextHop>) -> TestStore {
+        TestStore {
+            routes: HashMap::from_iter(vec![(prefix.to_string(), next_hops[0].account_id)]),
+            multipath_routes: HashMap::from_iter(vec![(prefix.to_string(), next_hops)]),
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_next_route() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let tried: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let tried_clone = tried.clone();
+        let mut router = Router::new(
+            multipath_store(
+                "example.",
+                vec![
+                    NextHop {
+                        account_id: id1,
+                        weight: 1,
+                    },
+                    NextHop {
+                        account_id: id2,
+                        weight: 0,
+                    },
+                ],
+            ),
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                tried_clone.lock().push(request.to.0);
+                if request.to.0 == id1 {
+                    reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY)
+                } else {
+                    fulfill()
+                }
+            }),
+        );
+
+        let result = router
+            .handle_request(prepare_to("example.destination"))
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(*tried.lock(), vec![id1, id2]);
+    }
+
+    #[tokio::test]
+    async fn returns_last_reject_if_all_routes_fail() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let tried: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let tried_clone = tried.clone();
+        let mut router = Router::new(
+            multipath_store(
+                "example.",
+                vec![
+                    NextHop {
+                        account_id: id1,
+                        weight: 0,
+                    },
+                    NextHop {
+                        account_id: id2,
+                        weight: 0,
+                    },
+                ],
+            ),
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                tried_clone.lock().push(request.to.0);
+                if request.to.0 == id1 {
+                    reject(ErrorCode::T01_PEER_UNREACHABLE)
+                } else {
+                    reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY)
+                }
+            }),
+        );
+
+        let result = router
+            .handle_request(prepare_to("example.destination"))
+            .await;
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        );
+        assert_eq!(*tried.lock(), vec![id1, id2]);
+    }
+
+    #[tokio::test]
+    async fn only_fails_over_on_failover_codes() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let tried: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let tried_clone = tried.clone();
+        let mut router = Router::new(
+            multipath_store(
+                "example.",
+                vec![
+                    NextHop {
+                        account_id: id1,
+                        weight: 1,
+                    },
+                    NextHop {
+                        account_id: id2,
+                        weight: 0,
+                    },
+                ],
+            ),
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                tried_clone.lock().push(request.to.0);
+                reject(ErrorCode::F99_APPLICATION_ERROR)
+            }),
+        );
+
+        let result = router
+            .clone()
+            .handle_request(prepare_to("example.destination"))
+            .await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert_eq!(*tried.lock(), vec![id1]);
+
+        tried.lock().clear();
+        router.failover_codes(vec![ErrorCode::F99_APPLICATION_ERROR]);
+        let result = router
+            .handle_request(prepare_to("example.destination"))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(*tried.lock(), vec![id1, id2]);
+    }
+
+    fn failover_router(
+        tried: Arc<Mutex<Vec<Uuid>>>,
+        result: IlpResult,
+    ) -> Router<TestStore, impl OutgoingService<TestAccount> + Clone> {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        Router::new(
+            multipath_store(
+                "example.",
+                vec![
+                    NextHop {
+                        account_id: id1,
+                        weight: 1,
+                    },
+                    NextHop {
+                        account_id: id2,
+                        weight: 0,
+                    },
+                ],
+            ),
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                tried.lock().push(request.to.0);
+                result.clone()
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_on_its_own_rejects() {
+        let tried: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut router = failover_router(
+            tried.clone(),
+            Err(RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: Some(&Address::from_str("example.connector").unwrap()),
+                data: &[],
+            }
+            .build()),
+        );
+
+        let result = router
+            .handle_request(prepare_to("example.destination"))
+            .await;
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        );
+        assert_eq!(tried.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_once_expired() {
+        let tried: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut router = failover_router(tried.clone(), reject(ErrorCode::T01_PEER_UNREACHABLE));
+
+        let mut request = prepare_to("example.destination");
+        request.prepare.set_expires_at(UNIX_EPOCH);
+        let result = router.handle_request(request).await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_eq!(tried.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ignores_next_hops_which_dont_match_the_route() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let tried: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let tried_clone = tried.clone();
+        let mut store = multipath_store(
+            "example.",
+            vec![
+                NextHop {
+                    account_id: id2,
+                    weight: 1,
+                },
+                NextHop {
+                    account_id: id1,
+                    weight: 0,
+                },
+            ],
+        );
+        store.routes.insert("example.".to_string(), id1);
+        let mut router = Router::new(
+            store,
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                tried_clone.lock().push(request.to.0);
+                reject(ErrorCode::T01_PEER_UNREACHABLE)
+            }),
+        );
+
+        let result = router
+            .handle_request(prepare_to("example.destination"))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(*tried.lock(), vec![id1]);
+    }
+
+    #[tokio::test]
+    async fn spreads_packets_by_weight() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let id3 = Uuid::from_slice(&[3; 16]).unwrap();
+        let tried: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let tried_clone = tried.clone();
+        let mut router = Router::new(
+            multipath_store(
+                "example.",
+                vec![
+                    NextHop {
+                        account_id: id1,
+                        weight: 3,
+                    },
+                    NextHop {
+                        account_id: id2,
+                        weight: 1,
+                    },
+                    NextHop {
+                        account_id: id3,
+                        weight: 0,
+                    },
+                ],
+            ),
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                tried_clone.lock().push(request.to.0);
+                fulfill()
+            }),
+        );
+
+        for _ in 0..1000 {
+            let result = router
+                .handle_request(prepare_to("example.destination"))
+                .await;
+            assert!(result.is_ok());
+        }
+        let tried = tried.lock();
+        let to_id1 = tried.iter().filter(|id| **id == id1).count();
+        let to_id2 = tried.iter().filter(|id| **id == id2).count();
+        assert_eq!(to_id1 + to_id2, 1000);
+        // The expected split is 750/250, with a standard deviation of about 14
+        assert!(to_id1 > 650 && to_id1 < 850, "{} packets to id1", to_id1);
+    }
+
+    #[test]
+    fn tries_backup_routes_last() {
+        let id = |byte| Uuid::from_slice(&[byte; 16]).unwrap();
+        let order = attempt_order(&[
+            NextHop {
+                account_id: id(1),
+                weight: 0,
+            },
+            NextHop {
+                account_id: id(2),
+                weight: 5,
+            },
+            NextHop {
+                account_id: id(3),
+                weight: 0,
+            },
+            NextHop {
+                account_id: id(4),
+                weight: 5,
+            },
+        ]);
+        assert_eq!(order.len(), 4);
+        assert!(order[..2].contains(&id(2)));
+        assert!(order[..2].contains(&id(4)));
+        assert_eq!(order[2..], [id(1), id(3)]);
+    }
 }
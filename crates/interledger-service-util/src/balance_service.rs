@@ -4,11 +4,11 @@ use interledger_errors::BalanceStoreError;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::*;
 use interledger_settlement::core::{
-    types::{SettlementAccount, SettlementStore},
+    types::{OutgoingSettlement, SettlementAccount, SettlementOutboxStore, SettlementStore},
     SettlementClient,
 };
-use std::marker::PhantomData;
-use tracing::{debug, error};
+use std::{marker::PhantomData, time::Duration};
+use tracing::{debug, error, warn};
 use uuid::Uuid;
 
 // TODO: Remove AccountStore dependency, use `AccountId: ToString` as associated type
@@ -34,6 +34,18 @@ pub trait BalanceStore {
         outgoing_amount: u64,
     ) -> Result<(i64, u64), BalanceStoreError>;
 
+    /// Same as `update_balances_for_fulfill`, but if the account needs to be settled, the
+    /// outgoing settlement is also saved, with its amount set to the amount to settle.
+    /// This MUST be done in the same atomic operation as the balance update, so that the
+    /// amount moved out of the balance is never lost if the node crashes in between.
+    /// Returns the updated balance along with the saved settlement, if any
+    async fn update_balances_for_fulfill_with_settlement(
+        &self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+        settlement: OutgoingSettlement,
+    ) -> Result<(i64, Option<OutgoingSettlement>), BalanceStoreError>;
+
     async fn update_balances_for_reject(
         &self,
         from_account_id: Uuid,
@@ -41,6 +53,11 @@ pub trait BalanceStore {
     ) -> Result<(), BalanceStoreError>;
 }
 
+/// How long to wait before retrying the outgoing settlements whose engine could not be reached.
+/// It is doubled after every round which leaves settlements pending, up to the maximum
+const MIN_SETTLEMENT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_SETTLEMENT_RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
 /// # Balance Service
 ///
 /// Responsible for managing the balances of the account and the interaction with the Settlement Engine
@@ -56,7 +73,7 @@ pub struct BalanceService<S, O, A> {
 
 impl<S, O, A> BalanceService<S, O, A>
 where
-    S: AddressStore + BalanceStore + SettlementStore<Account = A>,
+    S: AddressStore + BalanceStore + SettlementStore<Account = A> + SettlementOutboxStore,
     O: OutgoingService<A>,
     A: Account + SettlementAccount,
 {
@@ -68,12 +85,107 @@ where
             account_type: PhantomData,
         }
     }
+
+    /// Sends the outgoing settlements which were saved in the store but neither
+    /// completed nor refunded, for example because the node crashed while sending them
+    /// or their engine could not be reached.
+    /// Returns the number of settlements which are still pending
+    pub async fn send_pending_settlements(&self) -> Result<usize, ()> {
+        let settlements = self
+            .store
+            .get_pending_outgoing_settlements()
+            .map_err(|err| error!("Error loading the pending outgoing settlements: {}", err))
+            .await?;
+        let mut pending = 0;
+        for settlement in settlements {
+            debug!(
+                "Retrying outgoing settlement {} to account {}",
+                settlement.idempotency_key, settlement.account_id
+            );
+            if !send_outgoing_settlement(&self.store, &self.settlement_client, settlement).await {
+                pending += 1;
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Sends the pending outgoing settlements, and keeps retrying them for as long as the
+    /// node runs. The wait between two rounds is doubled while settlements stay pending,
+    /// so that an engine which is down is not flooded with requests.
+    /// This should be spawned once on startup.
+    pub async fn retry_pending_settlements(self) {
+        let mut interval = MIN_SETTLEMENT_RETRY_INTERVAL;
+        loop {
+            interval = match self.send_pending_settlements().await {
+                Ok(0) => MIN_SETTLEMENT_RETRY_INTERVAL,
+                _ => (interval * 2).min(MAX_SETTLEMENT_RETRY_INTERVAL),
+            };
+            tokio::time::delay_for(interval).await;
+        }
+    }
+}
+
+/// Sends a saved outgoing settlement to the settlement engine. It is deleted from the store
+/// if the engine accepts it and refunded if the engine refuses it. If the engine could not be
+/// reached, it stays in the store so that it is retried (with the same idempotency key) later.
+/// Returns false if the settlement is still pending
+async fn send_outgoing_settlement<S>(
+    store: &S,
+    settlement_client: &SettlementClient,
+    settlement: OutgoingSettlement,
+) -> bool
+where
+    S: SettlementOutboxStore,
+{
+    let result = settlement_client
+        .send_settlement_with_idempotency_key(
+            settlement.account_id,
+            settlement.engine_url,
+            settlement.amount,
+            settlement.asset_scale,
+            &settlement.idempotency_key,
+        )
+        .await;
+    let key = &settlement.idempotency_key;
+    match result {
+        Ok(_) => {
+            if let Err(err) = store.complete_outgoing_settlement(key).await {
+                error!("Error completing outgoing settlement {}: {}", key, err);
+            }
+            true
+        }
+        // The engine responded with an error, so the settlement will not happen
+        Err(err) if err.status().is_some() => {
+            warn!(
+                "Settlement engine refused outgoing settlement {} to account {}, refunding {}: {}",
+                key, settlement.account_id, settlement.amount, err
+            );
+            if let Err(err) = store.refund_outgoing_settlement(key).await {
+                error!("Error refunding outgoing settlement {}: {}", key, err);
+            }
+            true
+        }
+        Err(err) => {
+            error!(
+                "Could not reach the settlement engine to send outgoing settlement {} to account {}, it will be retried later: {}",
+                key, settlement.account_id, err
+            );
+            false
+        }
+    }
 }
 
 #[async_trait]
 impl<S, O, A> OutgoingService<A> for BalanceService<S, O, A>
 where
-    S: AddressStore + BalanceStore + SettlementStore<Account = A> + Clone + Send + Sync + 'static,
+    S: AddressStore
+        + BalanceStore
+        + SettlementStore<Account = A>
+        + SettlementOutboxStore
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     O: OutgoingService<A> + Send + Clone + 'static,
     A: SettlementAccount + Send + Sync + 'static,
 {
@@ -143,41 +255,53 @@ where
                     // for the packet we forwarded. Note this means that we will
                     // relay the fulfillment _even if saving to the DB fails._
                     tokio::spawn(async move {
-                        let (balance, amount_to_settle) = store
-                            .update_balances_for_fulfill(to.id(), outgoing_amount)
-                            .map_err(|err| error!("Error applying balance changes for fulfill from account: {} to account: {}. Incoming amount was: {}, outgoing amount was: {}. Error: {}", from_id, to_id, incoming_amount, outgoing_amount, err))
-                            .await?;
-                        debug!(
-                            "Account balance after fulfill: {}. Amount that needs to be settled: {}",
-                            balance, amount_to_settle
-                        );
-                        if amount_to_settle > 0 {
-                            if let Some(engine_details) = to.settlement_engine_details() {
-                                let engine_url = engine_details.url;
-                                // Note that if this program crashes after changing the balance (in the PROCESS_FULFILL script)
-                                // and the send_settlement fails but the program isn't alive to hear that, the balance will be incorrect.
-                                // No other instance will know that it was trying to send an outgoing settlement. We could
-                                // make this more robust by saving something to the DB about the outgoing settlement when we change the balance
-                                // but then we would also need to prevent a situation where every connector instance is polling the
-                                // settlement engine for the status of each
-                                // outgoing settlement and putting unnecessary
-                                // load on the settlement engine.
-                                if settlement_client
-                                    .send_settlement(
-                                        to.id(),
-                                        engine_url,
-                                        amount_to_settle,
-                                        to.asset_scale(),
+                        let log_error = |err| {
+                            error!("Error applying balance changes for fulfill from account: {} to account: {}. Incoming amount was: {}, outgoing amount was: {}. Error: {}", from_id, to_id, incoming_amount, outgoing_amount, err)
+                        };
+                        match to.settlement_engine_details() {
+                            Some(engine_details) => {
+                                // The settlement is saved along with the balance change, so that
+                                // if this program crashes before the engine responds, it is retried
+                                // (with the same idempotency key) rather than lost
+                                let settlement = OutgoingSettlement {
+                                    idempotency_key: Uuid::new_v4().to_hyphenated().to_string(),
+                                    account_id: to_id,
+                                    amount: 0,
+                                    asset_scale: to.asset_scale(),
+                                    engine_url: engine_details.url,
+                                };
+                                let (balance, settlement) = store
+                                    .update_balances_for_fulfill_with_settlement(
+                                        to_id,
+                                        outgoing_amount,
+                                        settlement,
                                     )
-                                    .await
-                                    .is_err()
-                                {
-                                    store
-                                        .refund_settlement(to_id, amount_to_settle)
-                                        .map_err(|_| ())
-                                        .await?;
+                                    .map_err(log_error)
+                                    .await?;
+                                debug!(
+                                    "Account balance after fulfill: {}. Amount that needs to be settled: {}",
+                                    balance,
+                                    settlement.as_ref().map(|settlement| settlement.amount).unwrap_or(0)
+                                );
+                                if let Some(settlement) = settlement {
+                                    send_outgoing_settlement(
+                                        &store,
+                                        &settlement_client,
+                                        settlement,
+                                    )
+                                    .await;
                                 }
                             }
+                            None => {
+                                let (balance, amount_to_settle) = store
+                                    .update_balances_for_fulfill(to_id, outgoing_amount)
+                                    .map_err(log_error)
+                                    .await?;
+                                debug!(
+                                    "Account balance after fulfill: {}. Amount that needs to be settled: {}",
+                                    balance, amount_to_settle
+                                );
+                            }
                         }
                         Ok::<(), ()>(())
                     });
@@ -219,6 +343,7 @@ mod tests {
     use interledger_settlement::core::types::SettlementEngineDetails;
     use once_cell::sync::Lazy;
     use parking_lot::RwLock;
+    use std::collections::HashMap;
     use std::str::FromStr;
     use std::sync::Arc;
     use std::time::Duration;
@@ -243,6 +368,57 @@ mod tests {
         mock.assert();
         assert_eq!(*store.refunded_settlement.read(), false);
         assert_eq!(*store.rejected_message.read(), false);
+        assert!(store.outbox.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sends_pending_settlements() {
+        let mock = mockito::mock("POST", mockito::Matcher::Any)
+            .match_header("Idempotency-Key", "pending-settlement")
+            .create();
+        let store = TestStore::new(0);
+        store.outbox.write().insert(
+            "pending-settlement".to_string(),
+            OutgoingSettlement {
+                idempotency_key: "pending-settlement".to_string(),
+                account_id: Uuid::new_v4(),
+                amount: 100,
+                asset_scale: 9,
+                engine_url: Url::parse(&mockito::server_url()).unwrap(),
+            },
+        );
+        let next = outgoing_service_fn(move |_| -> IlpResult { unreachable!() });
+        let service = BalanceService::new(store.clone(), next);
+        assert_eq!(service.send_pending_settlements().await.unwrap(), 0);
+
+        mock.assert();
+        assert!(store.outbox.read().is_empty());
+        assert_eq!(*store.refunded_settlement.read(), false);
+    }
+
+    #[tokio::test]
+    async fn keeps_settlements_pending_while_the_engine_is_unreachable() {
+        // Nothing listens on this port
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let engine_url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+        let store = TestStore::new(0);
+        store.outbox.write().insert(
+            "pending-settlement".to_string(),
+            OutgoingSettlement {
+                idempotency_key: "pending-settlement".to_string(),
+                account_id: Uuid::new_v4(),
+                amount: 100,
+                asset_scale: 9,
+                engine_url,
+            },
+        );
+        let next = outgoing_service_fn(move |_| -> IlpResult { unreachable!() });
+        let service = BalanceService::new(store.clone(), next);
+        assert_eq!(service.send_pending_settlements().await.unwrap(), 1);
+
+        assert!(store.outbox.read().contains_key("pending-settlement"));
+        assert!(!*store.refunded_settlement.read());
     }
 
     #[tokio::test]
@@ -289,6 +465,7 @@ mod tests {
         mock.assert();
         assert_eq!(*store.refunded_settlement.read(), true);
         assert_eq!(*store.rejected_message.read(), false);
+        assert!(store.outbox.read().is_empty());
     }
 
     #[tokio::test]
@@ -363,6 +540,7 @@ mod tests {
         amount_to_settle: u64,
         rejected_message: Arc<RwLock<bool>>,
         refunded_settlement: Arc<RwLock<bool>>,
+        outbox: Arc<RwLock<HashMap<String, OutgoingSettlement>>>,
     }
 
     impl TestStore {
@@ -371,6 +549,7 @@ mod tests {
                 amount_to_settle,
                 rejected_message: Arc::new(RwLock::new(false)),
                 refunded_settlement: Arc::new(RwLock::new(false)),
+                outbox: Arc::new(RwLock::new(HashMap::new())),
             }
         }
     }
@@ -412,6 +591,25 @@ mod tests {
             Ok((0, self.amount_to_settle))
         }
 
+        async fn update_balances_for_fulfill_with_settlement(
+            &self,
+            _: Uuid,
+            _: u64,
+            settlement: OutgoingSettlement,
+        ) -> Result<(i64, Option<OutgoingSettlement>), BalanceStoreError> {
+            if self.amount_to_settle == 0 {
+                return Ok((0, None));
+            }
+            let settlement = OutgoingSettlement {
+                amount: self.amount_to_settle,
+                ..settlement
+            };
+            self.outbox
+                .write()
+                .insert(settlement.idempotency_key.clone(), settlement.clone());
+            Ok((0, Some(settlement)))
+        }
+
         async fn update_balances_for_reject(
             &self,
             _: Uuid,
@@ -441,6 +639,43 @@ mod tests {
         }
     }
 
+    #[async_trait]
+    impl SettlementOutboxStore for TestStore {
+        async fn save_outgoing_settlement(
+            &self,
+            settlement: OutgoingSettlement,
+        ) -> Result<(), SettlementStoreError> {
+            self.outbox
+                .write()
+                .insert(settlement.idempotency_key.clone(), settlement);
+            Ok(())
+        }
+
+        async fn complete_outgoing_settlement(
+            &self,
+            idempotency_key: &str,
+        ) -> Result<(), SettlementStoreError> {
+            self.outbox.write().remove(idempotency_key);
+            Ok(())
+        }
+
+        async fn refund_outgoing_settlement(
+            &self,
+            idempotency_key: &str,
+        ) -> Result<(), SettlementStoreError> {
+            if self.outbox.write().remove(idempotency_key).is_some() {
+                *self.refunded_settlement.write() = true;
+            }
+            Ok(())
+        }
+
+        async fn get_pending_outgoing_settlements(
+            &self,
+        ) -> Result<Vec<OutgoingSettlement>, SettlementStoreError> {
+            Ok(self.outbox.read().values().cloned().collect())
+        }
+    }
+
     static TEST_REQUEST: Lazy<OutgoingRequest<TestAccount>> = Lazy::new(|| {
         let url = mockito::server_url();
         OutgoingRequest {
@@ -0,0 +1,881 @@
+//! Packets are captured to a binary file, which starts with the 8 bytes `ILPCAP01`
+//! followed by one record per packet. Each record starts with its length, as a 32-bit
+//! big-endian integer, followed by:
+//!
+//! - the time the Prepare was received, in microseconds since the UNIX epoch (64 bits)
+//! - the time until the Fulfill or Reject came back, in microseconds (64 bits)
+//! - the id of the account which sent the Prepare (16 bytes)
+//! - the username of that account, prefixed with its length (16 bits)
+//! - the Prepare, prefixed with its length (32 bits)
+//! - the Fulfill or Reject, prefixed with its length (32 bits)
+//!
+//! All the integers are big-endian, and the packets are encoded as in ILPv4.
+
+use async_trait::async_trait;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use bytes::BytesMut;
+use interledger_errors::AccountStoreError;
+use interledger_packet::{Address, Packet, Prepare};
+use interledger_service::{
+    Account, AccountStore, IlpResult, IncomingRequest, IncomingService, Username,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+/// The first bytes of a capture file
+const CAPTURE_MAGIC: &[u8; 8] = b"ILPCAP01";
+
+/// Number of records which may wait to be written to the capture file. The records of
+/// the packets handled while the queue is full are dropped, so that a slow disk does not
+/// make the node run out of memory
+const CAPTURE_QUEUE_SIZE: usize = 10_000;
+
+/// Settings of the files the packets are captured to
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct CaptureSettings {
+    /// Path of the capture file. When it is rotated, it is renamed with `.1` appended,
+    /// and the previously rotated files are renamed with `.2`, `.3`, etc.
+    pub path: PathBuf,
+    /// Size, in bytes, after which the capture file is rotated. Defaults to 100MB
+    #[serde(default = "CaptureSettings::default_max_file_size")]
+    pub max_file_size: u64,
+    /// Number of rotated capture files kept, besides the current one. Defaults to 5
+    #[serde(default = "CaptureSettings::default_max_files")]
+    pub max_files: u32,
+}
+
+impl CaptureSettings {
+    fn default_max_file_size() -> u64 {
+        100_000_000
+    }
+    fn default_max_files() -> u32 {
+        5
+    }
+}
+
+/// Which packets are captured
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct CaptureFilter {
+    /// Only capture the packets sent by these accounts. All accounts if empty
+    #[serde(default)]
+    pub accounts: Vec<Username>,
+    /// Only capture the packets whose destination starts with one of these prefixes.
+    /// All destinations if empty
+    #[serde(default)]
+    pub destination_prefixes: Vec<String>,
+}
+
+impl CaptureFilter {
+    fn matches(&self, username: &Username, destination: &Address) -> bool {
+        let destination: &[u8] = destination.as_ref();
+        (self.accounts.is_empty() || self.accounts.contains(username))
+            && (self.destination_prefixes.is_empty()
+                || self
+                    .destination_prefixes
+                    .iter()
+                    .any(|prefix| destination.starts_with(prefix.as_bytes())))
+    }
+}
+
+/// Whether packets are being captured, and which ones
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CaptureStatus {
+    pub capturing: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<CaptureFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Number of packets captured since the capture started
+    pub captured_packets: u64,
+    /// Number of packets which were not captured since the capture started, because
+    /// their records could not be written to the capture file fast enough
+    pub dropped_packets: u64,
+}
+
+/// A Prepare along with the Fulfill or Reject it got
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureRecord {
+    pub account_id: Uuid,
+    pub username: Username,
+    pub prepared_at: SystemTime,
+    pub round_trip_time: Duration,
+    pub prepare: Prepare,
+    pub result: IlpResult,
+}
+
+impl CaptureRecord {
+    /// Encodes the record as laid out in the capture file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let prepare = BytesMut::from(self.prepare.clone());
+        let response = match self.result.clone() {
+            Ok(fulfill) => BytesMut::from(fulfill),
+            Err(reject) => BytesMut::from(reject),
+        };
+        let prepared_at = self
+            .prepared_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut record = Vec::with_capacity(
+            8 + 8 + 16 + 2 + self.username.len() + 4 + prepare.len() + 4 + response.len(),
+        );
+        // Writing to a Vec cannot fail
+        record.write_u64::<BigEndian>(prepared_at).unwrap();
+        record
+            .write_u64::<BigEndian>(self.round_trip_time.as_micros() as u64)
+            .unwrap();
+        record.extend_from_slice(self.account_id.as_bytes());
+        record
+            .write_u16::<BigEndian>(self.username.len() as u16)
+            .unwrap();
+        record.extend_from_slice(self.username.as_bytes());
+        record.write_u32::<BigEndian>(prepare.len() as u32).unwrap();
+        record.extend_from_slice(&prepare);
+        record
+            .write_u32::<BigEndian>(response.len() as u32)
+            .unwrap();
+        record.extend_from_slice(&response);
+        record
+    }
+
+    /// Decodes a record encoded with `to_bytes`
+    pub fn from_bytes(mut record: &[u8]) -> io::Result<Self> {
+        let prepared_at = UNIX_EPOCH + Duration::from_micros(record.read_u64::<BigEndian>()?);
+        let round_trip_time = Duration::from_micros(record.read_u64::<BigEndian>()?);
+        let mut account_id = [0; 16];
+        record.read_exact(&mut account_id)?;
+        let length = record.read_u16::<BigEndian>()?;
+        let username = read_field(&mut record, u64::from(length))?;
+        let username = std::str::from_utf8(&username)
+            .ok()
+            .and_then(|username| Username::from_str(username).ok())
+            .ok_or_else(|| invalid_data("Invalid username"))?;
+        let length = record.read_u32::<BigEndian>()?;
+        let prepare = read_field(&mut record, u64::from(length))?;
+        let prepare = Prepare::try_from(BytesMut::from(&prepare[..]))
+            .map_err(|err| invalid_data(&format!("Invalid Prepare: {}", err)))?;
+        let length = record.read_u32::<BigEndian>()?;
+        let response = read_field(&mut record, u64::from(length))?;
+        let result = match Packet::try_from(BytesMut::from(&response[..])) {
+            Ok(Packet::Fulfill(fulfill)) => Ok(fulfill),
+            Ok(Packet::Reject(reject)) => Err(reject),
+            _ => return Err(invalid_data("Invalid Fulfill or Reject")),
+        };
+
+        Ok(CaptureRecord {
+            account_id: Uuid::from_bytes(account_id),
+            username,
+            prepared_at,
+            round_trip_time,
+            prepare,
+            result,
+        })
+    }
+}
+
+fn read_field(reader: &mut &[u8], length: u64) -> io::Result<Vec<u8>> {
+    let mut field = Vec::new();
+    reader.take(length).read_to_end(&mut field)?;
+    if field.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(field)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the records of a capture file, in the order they were captured
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Checks that the reader starts with the header of a capture file
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(invalid_data("Not a packet capture file"));
+        }
+        Ok(CaptureReader { reader })
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let length = match self.reader.read_u32::<BigEndian>() {
+            Ok(length) => length,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        };
+        let mut record = vec![0; length as usize];
+        if let Err(err) = self.reader.read_exact(&mut record) {
+            return Some(Err(err));
+        }
+        Some(CaptureRecord::from_bytes(&record))
+    }
+}
+
+/// Writes records in the format of a capture file, for example to replay them
+pub struct CaptureWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the header of a capture file
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        Ok(CaptureWriter { writer })
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        write_record(&mut self.writer, record).map(|_| ())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Writes the record prefixed with its length, and returns the number of bytes written
+fn write_record<W: Write>(writer: &mut W, record: &CaptureRecord) -> io::Result<u64> {
+    let record = record.to_bytes();
+    writer.write_u32::<BigEndian>(record.len() as u32)?;
+    writer.write_all(&record)?;
+    Ok(4 + record.len() as u64)
+}
+
+/// The capture file currently written to, which is rotated once it gets too large
+struct CaptureFile {
+    settings: CaptureSettings,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl CaptureFile {
+    fn open(settings: CaptureSettings) -> io::Result<Self> {
+        let (file, size) = open_capture_file(&settings.path)?;
+        Ok(CaptureFile {
+            settings,
+            file,
+            size,
+        })
+    }
+
+    fn write(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        if self.size >= self.settings.max_file_size && self.size > CAPTURE_MAGIC.len() as u64 {
+            self.rotate()?;
+        }
+        self.size += write_record(&mut self.file, record)?;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let path = &self.settings.path;
+        if self.settings.max_files == 0 {
+            fs::remove_file(path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(path, self.settings.max_files));
+            for index in (1..self.settings.max_files).rev() {
+                let from = rotated_path(path, index);
+                if from.exists() {
+                    fs::rename(from, rotated_path(path, index + 1))?;
+                }
+            }
+            fs::rename(path, rotated_path(path, 1))?;
+        }
+        let (file, size) = open_capture_file(path)?;
+        self.file = file;
+        self.size = size;
+        debug!("Rotated the packet capture file {}", path.display());
+        Ok(())
+    }
+}
+
+/// Opens the capture file to append to it, and writes its header if it is new
+fn open_capture_file(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut size = file.metadata()?.len();
+    let mut file = BufWriter::new(file);
+    if size == 0 {
+        file.write_all(CAPTURE_MAGIC)?;
+        file.flush()?;
+        size = CAPTURE_MAGIC.len() as u64;
+    }
+    Ok((file, size))
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", index));
+    PathBuf::from(path)
+}
+
+/// Writes the records it receives to the capture file until the capture stops
+fn write_records(mut file: CaptureFile, records: Receiver<CaptureRecord>) {
+    while let Ok(record) = records.recv() {
+        let mut result = file.write(&record);
+        // Flush once the records received so far are written, so that the file can be read right away
+        while result.is_ok() {
+            match records.try_recv() {
+                Ok(record) => result = file.write(&record),
+                Err(_) => break,
+            }
+        }
+        if let Err(err) = result.and_then(|_| file.file.flush()) {
+            error!(
+                "Error writing to the packet capture file {}: {}",
+                file.settings.path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Counts of the records of an active capture
+#[derive(Default)]
+struct CaptureCounters {
+    captured_packets: AtomicU64,
+    dropped_packets: AtomicU64,
+}
+
+struct ActiveCapture {
+    filter: CaptureFilter,
+    records: SyncSender<CaptureRecord>,
+    counters: Arc<CaptureCounters>,
+}
+
+/// Handle to start and stop capturing packets, shared by the clones of a
+/// [`CaptureService`](./struct.CaptureService.html)
+#[derive(Clone, Default)]
+pub struct PacketCapture {
+    settings: Option<CaptureSettings>,
+    active: Arc<Mutex<Option<ActiveCapture>>>,
+}
+
+impl PacketCapture {
+    /// Packets can be captured to the file of the settings once the capture is started
+    pub fn new(settings: CaptureSettings) -> Self {
+        PacketCapture {
+            settings: Some(settings),
+            active: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Whether there is a capture file to capture packets to
+    pub fn is_configured(&self) -> bool {
+        self.settings.is_some()
+    }
+
+    /// Starts capturing the packets which match the filter. If packets are already being
+    /// captured, only the filter is replaced
+    pub fn start(&self, filter: CaptureFilter) -> io::Result<CaptureStatus> {
+        let settings = self.settings.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "Packet capture is not configured")
+        })?;
+        let mut active = self.active.lock().unwrap();
+        if let Some(ref mut active) = *active {
+            active.filter = filter;
+        } else {
+            let file = CaptureFile::open(settings.clone())?;
+            let (sender, receiver) = mpsc::sync_channel(CAPTURE_QUEUE_SIZE);
+            thread::spawn(move || write_records(file, receiver));
+            *active = Some(ActiveCapture {
+                filter,
+                records: sender,
+                counters: Arc::new(CaptureCounters::default()),
+            });
+            info!("Started capturing packets to {}", settings.path.display());
+        }
+        Ok(self.status_of(&active))
+    }
+
+    /// Stops capturing packets. The records captured so far are still written to the file
+    pub fn stop(&self) -> CaptureStatus {
+        let mut active = self.active.lock().unwrap();
+        // Dropping the sender lets the writer finish and close the file
+        if active.take().is_some() {
+            info!("Stopped capturing packets");
+        }
+        self.status_of(&active)
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        self.status_of(&self.active.lock().unwrap())
+    }
+
+    fn status_of(&self, active: &Option<ActiveCapture>) -> CaptureStatus {
+        match active {
+            Some(active) => CaptureStatus {
+                capturing: true,
+                filter: Some(active.filter.clone()),
+                path: self.settings.as_ref().map(|settings| settings.path.clone()),
+                captured_packets: active.counters.captured_packets.load(Ordering::Relaxed),
+                dropped_packets: active.counters.dropped_packets.load(Ordering::Relaxed),
+            },
+            None => CaptureStatus {
+                capturing: false,
+                filter: None,
+                path: self.settings.as_ref().map(|settings| settings.path.clone()),
+                captured_packets: 0,
+                dropped_packets: 0,
+            },
+        }
+    }
+
+    /// The channel to send the record of the packet to, if it should be captured
+    fn recorder(
+        &self,
+        username: &Username,
+        destination: &Address,
+    ) -> Option<(SyncSender<CaptureRecord>, Arc<CaptureCounters>)> {
+        match *self.active.lock().unwrap() {
+            Some(ref active) if active.filter.matches(username, destination) => {
+                Some((active.records.clone(), active.counters.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// # Capture Service
+///
+/// Incoming Service which records the Prepare packets it receives along with the
+/// Fulfill or Reject they get, while the capture is started with its
+/// [`PacketCapture`](./struct.PacketCapture.html) handle.
+/// The records are written to the capture file by a separate thread, so capturing
+/// doesn't hold up the packets. The records which the thread cannot write fast enough
+/// are dropped, and counted in the [`CaptureStatus`](./struct.CaptureStatus.html).
+#[derive(Clone)]
+pub struct CaptureService<I> {
+    capture: PacketCapture,
+    next: I,
+}
+
+impl<I> CaptureService<I> {
+    pub fn new(capture: PacketCapture, next: I) -> Self {
+        CaptureService { capture, next }
+    }
+}
+
+#[async_trait]
+impl<I, A> IncomingService<A> for CaptureService<I>
+where
+    I: IncomingService<A> + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+{
+    async fn handle_request(&mut self, request: IncomingRequest<A>) -> IlpResult {
+        let recorder = self
+            .capture
+            .recorder(request.from.username(), &request.prepare.destination());
+        let (records, counters) = match recorder {
+            Some(recorder) => recorder,
+            None => return self.next.handle_request(request).await,
+        };
+
+        let account_id = request.from.id();
+        let username = request.from.username().clone();
+        let prepare = request.prepare.clone();
+        let prepared_at = SystemTime::now();
+        let started_at = Instant::now();
+        let result = self.next.handle_request(request).await;
+
+        let record = CaptureRecord {
+            account_id,
+            username,
+            prepared_at,
+            round_trip_time: started_at.elapsed(),
+            prepare,
+            result: result.clone(),
+        };
+        match records.try_send(record) {
+            Ok(_) => {
+                counters.captured_packets.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Full(_)) => {
+                counters.dropped_packets.fetch_add(1, Ordering::Relaxed);
+            }
+            // The capture stopped in the meantime
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+        result
+    }
+}
+
+/// The response to a captured packet when it was replayed
+#[derive(Clone, Debug)]
+pub struct ReplayOutcome {
+    pub record: CaptureRecord,
+    pub result: IlpResult,
+}
+
+impl ReplayOutcome {
+    /// Whether the replayed packet was fulfilled like the captured one, or rejected with the same code
+    pub fn matches(&self) -> bool {
+        match (&self.record.result, &self.result) {
+            (Ok(_), Ok(_)) => true,
+            (Err(captured), Err(replayed)) => captured.code() == replayed.code(),
+            _ => false,
+        }
+    }
+}
+
+/// Sends the captured Prepare packets through the service one after the other, from the
+/// accounts of the store with the same usernames as the accounts which sent them, and
+/// returns the responses they get. Each packet expires as long after it is replayed as
+/// it did after it was captured.
+///
+/// This reproduces how a node routes and converts the packets, for example with the
+/// routes and exchange rates of the store. The packets are forwarded, so the service
+/// should not reach the peers of a live node.
+pub async fn replay<I, S, A>(
+    store: &S,
+    mut service: I,
+    records: Vec<CaptureRecord>,
+) -> Result<Vec<ReplayOutcome>, AccountStoreError>
+where
+    I: IncomingService<A>,
+    S: AccountStore<Account = A>,
+    A: Account,
+{
+    let mut accounts: HashMap<Username, A> = HashMap::new();
+    let mut outcomes = Vec::with_capacity(records.len());
+    for record in records {
+        let from = match accounts.get(&record.username) {
+            Some(account) => account.clone(),
+            None => {
+                let id = store.get_account_id_from_username(&record.username).await?;
+                let account = store.get_accounts(vec![id]).await?.remove(0);
+                accounts.insert(record.username.clone(), account.clone());
+                account
+            }
+        };
+
+        let mut prepare = record.prepare.clone();
+        let expires_in = prepare
+            .expires_at()
+            .duration_since(record.prepared_at)
+            .unwrap_or_default();
+        prepare.set_expires_at(SystemTime::now() + expires_in);
+        let result = service
+            .handle_request(IncomingRequest { from, prepare })
+            .await;
+        outcomes.push(ReplayOutcome { record, result });
+    }
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_packet::{ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use interledger_service::incoming_service_fn;
+    use once_cell::sync::Lazy;
+
+    static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static BOB: Lazy<Username> = Lazy::new(|| Username::from_str("bob").unwrap());
+    static EXAMPLE_ADDRESS: Lazy<Address> =
+        Lazy::new(|| Address::from_str("example.alice").unwrap());
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(Username);
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            Uuid::nil()
+        }
+
+        fn username(&self) -> &Username {
+            &self.0
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &EXAMPLE_ADDRESS
+        }
+    }
+
+    fn prepare(destination: &str) -> Prepare {
+        PrepareBuilder {
+            destination: Address::from_str(destination).unwrap(),
+            amount: 100,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &[0; 32],
+            data: b"test data",
+        }
+        .build()
+    }
+
+    fn test_service() -> impl IncomingService<TestAccount> + Clone {
+        incoming_service_fn(|request: IncomingRequest<TestAccount>| {
+            if request
+                .prepare
+                .destination()
+                .to_string()
+                .starts_with("example.fulfill")
+            {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: b"fulfilled",
+                }
+                .build())
+            } else {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No route",
+                    triggered_by: Some(&EXAMPLE_ADDRESS),
+                    data: &[],
+                }
+                .build())
+            }
+        })
+    }
+
+    fn capture_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.ilpcap", name, Uuid::new_v4()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// The records of the capture file, unless it is being written to
+    fn read_capture(path: &Path) -> Option<Vec<CaptureRecord>> {
+        CaptureReader::new(File::open(path).ok()?)
+            .ok()?
+            .collect::<io::Result<Vec<_>>>()
+            .ok()
+    }
+
+    /// Waits for the writer thread to write the records to the capture files
+    async fn wait_for_destinations(paths: &[PathBuf], destinations: &[&str]) {
+        let expected: Vec<String> = destinations.iter().map(|d| d.to_string()).collect();
+        for _ in 0..100 {
+            let written: Option<Vec<String>> = paths
+                .iter()
+                .map(|path| read_capture(path))
+                .collect::<Option<Vec<_>>>()
+                .map(|files| {
+                    files
+                        .iter()
+                        .flatten()
+                        .map(|record| record.prepare.destination().to_string())
+                        .collect()
+                });
+            if written.as_ref() == Some(&expected) {
+                return;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        panic!("The records were not written");
+    }
+
+    #[test]
+    fn encodes_and_decodes_records() {
+        let record = CaptureRecord {
+            account_id: Uuid::new_v4(),
+            username: ALICE.clone(),
+            prepared_at: UNIX_EPOCH + Duration::from_micros(1_577_836_800_123_456),
+            round_trip_time: Duration::from_micros(1500),
+            // Packets encode their expiry in milliseconds
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.bob").unwrap(),
+                amount: 100,
+                expires_at: UNIX_EPOCH + Duration::from_millis(1_577_836_830_123),
+                execution_condition: &[0; 32],
+                data: b"test data",
+            }
+            .build(),
+            result: Err(RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: b"Insufficient liquidity",
+                triggered_by: Some(&EXAMPLE_ADDRESS),
+                data: &[],
+            }
+            .build()),
+        };
+        assert_eq!(
+            CaptureRecord::from_bytes(&record.to_bytes()).unwrap(),
+            record
+        );
+        assert!(CaptureRecord::from_bytes(&record.to_bytes()[..40]).is_err());
+    }
+
+    #[tokio::test]
+    async fn captures_matching_packets_while_started() {
+        let path = capture_path("captures_matching_packets");
+        let capture = PacketCapture::new(CaptureSettings {
+            path: path.clone(),
+            max_file_size: 1_000_000,
+            max_files: 1,
+        });
+        let mut service = CaptureService::new(capture.clone(), test_service());
+        let alice = TestAccount(ALICE.clone());
+        let bob = TestAccount(BOB.clone());
+
+        // Nothing is captured before the capture starts
+        let _ = service
+            .handle_request(IncomingRequest {
+                from: alice.clone(),
+                prepare: prepare("example.fulfill.one"),
+            })
+            .await;
+        assert!(!capture.status().capturing);
+
+        capture
+            .start(CaptureFilter {
+                accounts: vec![ALICE.clone()],
+                destination_prefixes: vec!["example.fulfill".to_string()],
+            })
+            .unwrap();
+        let _ = service
+            .handle_request(IncomingRequest {
+                from: alice.clone(),
+                prepare: prepare("example.fulfill.two"),
+            })
+            .await;
+        let _ = service
+            .handle_request(IncomingRequest {
+                from: alice.clone(),
+                prepare: prepare("example.reject"),
+            })
+            .await;
+        let _ = service
+            .handle_request(IncomingRequest {
+                from: bob,
+                prepare: prepare("example.fulfill.three"),
+            })
+            .await;
+        assert_eq!(capture.status().captured_packets, 1);
+
+        // Any packet from alice matches once the filter is replaced
+        capture
+            .start(CaptureFilter {
+                accounts: vec![ALICE.clone()],
+                destination_prefixes: Vec::new(),
+            })
+            .unwrap();
+        let _ = service
+            .handle_request(IncomingRequest {
+                from: alice.clone(),
+                prepare: prepare("example.reject"),
+            })
+            .await;
+        assert_eq!(capture.status().captured_packets, 2);
+        assert!(!capture.stop().capturing);
+
+        // Nothing is captured once the capture stops
+        let _ = service
+            .handle_request(IncomingRequest {
+                from: alice,
+                prepare: prepare("example.fulfill.four"),
+            })
+            .await;
+
+        wait_for_destinations(
+            std::slice::from_ref(&path),
+            &["example.fulfill.two", "example.reject"],
+        )
+        .await;
+        let records = read_capture(&path).unwrap();
+        assert_eq!(records[0].username, *ALICE);
+        assert!(records[0].result.is_ok());
+        assert_eq!(
+            records[1].result.as_ref().unwrap_err().code(),
+            ErrorCode::F02_UNREACHABLE
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn drops_the_records_which_cannot_be_queued() {
+        let capture = PacketCapture::default();
+        // A queue of one record, which nothing reads
+        let (sender, _receiver) = mpsc::sync_channel(1);
+        *capture.active.lock().unwrap() = Some(ActiveCapture {
+            filter: CaptureFilter::default(),
+            records: sender,
+            counters: Arc::new(CaptureCounters::default()),
+        });
+        let mut service = CaptureService::new(capture.clone(), test_service());
+        for _ in 0..3 {
+            let result = service
+                .handle_request(IncomingRequest {
+                    from: TestAccount(ALICE.clone()),
+                    prepare: prepare("example.fulfill"),
+                })
+                .await;
+            assert!(result.is_ok());
+        }
+        let status = capture.status();
+        assert_eq!(status.captured_packets, 1);
+        assert_eq!(status.dropped_packets, 2);
+    }
+
+    #[tokio::test]
+    async fn rotates_the_capture_file() {
+        let path = capture_path("rotates_the_capture_file");
+        let capture = PacketCapture::new(CaptureSettings {
+            path: path.clone(),
+            // Every record fills up the file
+            max_file_size: 1,
+            max_files: 2,
+        });
+        let mut service = CaptureService::new(capture.clone(), test_service());
+        capture.start(CaptureFilter::default()).unwrap();
+        for index in 0..4 {
+            let _ = service
+                .handle_request(IncomingRequest {
+                    from: TestAccount(ALICE.clone()),
+                    prepare: prepare(&format!("example.fulfill.{}", index)),
+                })
+                .await;
+        }
+
+        capture.stop();
+
+        // The oldest record was dropped since only 2 rotated files are kept
+        wait_for_destinations(
+            &[rotated_path(&path, 2), rotated_path(&path, 1), path.clone()],
+            &[
+                "example.fulfill.1",
+                "example.fulfill.2",
+                "example.fulfill.3",
+            ],
+        )
+        .await;
+        assert!(!rotated_path(&path, 3).exists());
+
+        for index in 1..=2 {
+            fs::remove_file(rotated_path(&path, index)).unwrap();
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,620 @@
+use async_trait::async_trait;
+use interledger_packet::{ErrorCode, Reject, RejectBuilder};
+use interledger_service::{
+    Account, AddressStore, IlpResult, OutgoingRequest, OutgoingService, Username,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Extension trait for [`Account`](../interledger_service/trait.Account.html) with the settings
+/// of its circuit breaker, which override the ones the service is configured with
+pub trait CircuitBreakerAccount: Account {
+    /// Number of consecutive failed packets to this account after which its circuit opens
+    fn circuit_breaker_failure_threshold(&self) -> Option<u32> {
+        None
+    }
+
+    /// Time, in milliseconds, during which the packets to this account are rejected once its circuit opens
+    fn circuit_breaker_cool_down(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Settings of the circuit breaker of one account
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CircuitBreakerSettings {
+    /// Number of consecutive failed packets after which the circuit opens.
+    /// Defaults to 5. 0 disables the circuit breaker
+    #[serde(default = "CircuitBreakerSettings::default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Time, in milliseconds, during which packets are rejected once the circuit opens,
+    /// before one packet is let through to test the peer. Defaults to 30000ms (30 seconds)
+    #[serde(default = "CircuitBreakerSettings::default_cool_down")]
+    pub cool_down: u64,
+}
+
+impl CircuitBreakerSettings {
+    fn default_failure_threshold() -> u32 {
+        5
+    }
+    fn default_cool_down() -> u64 {
+        30_000
+    }
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        CircuitBreakerSettings {
+            failure_threshold: CircuitBreakerSettings::default_failure_threshold(),
+            cool_down: CircuitBreakerSettings::default_cool_down(),
+        }
+    }
+}
+
+/// State of the circuit of an account
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Packets are forwarded
+    Closed,
+    /// Packets are rejected until the cool-down elapses
+    Open,
+    /// One packet at a time is forwarded to test whether the peer recovered
+    HalfOpen,
+}
+
+/// Snapshot of the circuit breaker of an account
+#[derive(Clone, Debug, Serialize)]
+pub struct CircuitBreakerStatus {
+    pub account_id: Uuid,
+    pub username: Username,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// Milliseconds until the circuit half-opens, if it is open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in: Option<u64>,
+    /// Number of packets rejected without being forwarded since the circuit opened
+    pub rejected_packets: u64,
+}
+
+struct Circuit {
+    username: Username,
+    consecutive_failures: u32,
+    /// Packets are rejected until then, and the circuit half-opens afterwards
+    open_until: Option<Instant>,
+    /// When the packet testing the peer of the half-open circuit was forwarded
+    test_started_at: Option<Instant>,
+    rejected_packets: u64,
+}
+
+impl Circuit {
+    fn new(username: Username) -> Self {
+        Circuit {
+            username,
+            consecutive_failures: 0,
+            open_until: None,
+            test_started_at: None,
+            rejected_packets: 0,
+        }
+    }
+
+    fn state(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(open_until) if now < open_until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+}
+
+/// Handle to the circuits of every account, shared by the clones of a
+/// [`CircuitBreakerService`](./struct.CircuitBreakerService.html)
+#[derive(Clone, Default)]
+pub struct CircuitBreakers {
+    circuits: Arc<Mutex<HashMap<Uuid, Circuit>>>,
+}
+
+impl CircuitBreakers {
+    /// The state of the circuit breaker of each account which was sent packets, sorted by username
+    pub fn status(&self) -> Vec<CircuitBreakerStatus> {
+        let now = Instant::now();
+        let mut status: Vec<CircuitBreakerStatus> = self
+            .circuits
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(account_id, circuit)| {
+                let state = circuit.state(now);
+                CircuitBreakerStatus {
+                    account_id: *account_id,
+                    username: circuit.username.clone(),
+                    state,
+                    consecutive_failures: circuit.consecutive_failures,
+                    retry_in: circuit
+                        .open_until
+                        .filter(|_| state == CircuitState::Open)
+                        .map(|open_until| open_until.duration_since(now).as_millis() as u64),
+                    rejected_packets: circuit.rejected_packets,
+                }
+            })
+            .collect();
+        status.sort_by(|a, b| a.username.cmp(&b.username));
+        status
+    }
+}
+
+/// Whether the reject shows that the peer is unreachable or overloaded. The T00 rejects of this
+/// node are internal errors, which say nothing about the peer, so they are ignored
+fn is_failure(reject: &Reject) -> bool {
+    let code = reject.code();
+    code == ErrorCode::T01_PEER_UNREACHABLE
+        || code == ErrorCode::T02_PEER_BUSY
+        || code == ErrorCode::R00_TRANSFER_TIMED_OUT
+}
+
+/// # Circuit Breaker Service
+///
+/// Stops forwarding packets to an account once too many of them failed in a row, so that
+/// packets to a peer which is down are rejected right away instead of waiting for a timeout
+/// and tying up liquidity in the meantime.
+/// After `failure_threshold` consecutive T01, T02 or R00 rejects, or transport errors, the circuit
+/// opens and the packets to the account are rejected with T01 for the `cool_down` period. The circuit then
+/// half-opens and lets one packet through: it closes again if the packet is fulfilled or rejected
+/// for another reason, and opens for another cool-down otherwise.
+/// The settings an account is configured with, e.g. through the API, take precedence over the
+/// ones of the service.
+/// Requires a `CircuitBreakerAccount` and an `AddressStore`.
+#[derive(Clone)]
+pub struct CircuitBreakerService<S, O> {
+    store: S,
+    next: O,
+    default_settings: Option<CircuitBreakerSettings>,
+    account_settings: Arc<HashMap<Username, CircuitBreakerSettings>>,
+    circuit_breakers: CircuitBreakers,
+}
+
+impl<S, O> CircuitBreakerService<S, O> {
+    /// Creates a service which applies the default settings to every account. Without them,
+    /// only the accounts configured with [`account_settings`](#method.account_settings) have a circuit breaker
+    pub fn new(store: S, default_settings: Option<CircuitBreakerSettings>, next: O) -> Self {
+        CircuitBreakerService {
+            store,
+            next,
+            default_settings,
+            account_settings: Arc::new(HashMap::new()),
+            circuit_breakers: CircuitBreakers::default(),
+        }
+    }
+
+    /// Sets the settings of an account's circuit breaker, which override the default ones
+    pub fn account_settings(
+        &mut self,
+        username: Username,
+        settings: CircuitBreakerSettings,
+    ) -> &mut Self {
+        Arc::make_mut(&mut self.account_settings).insert(username, settings);
+        self
+    }
+
+    /// Handle to the circuits, to report their state
+    pub fn circuit_breakers(&self) -> CircuitBreakers {
+        self.circuit_breakers.clone()
+    }
+}
+
+#[async_trait]
+impl<S, O, A> OutgoingService<A> for CircuitBreakerService<S, O>
+where
+    S: AddressStore + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Send + Sync + Clone + 'static,
+    A: CircuitBreakerAccount + Send + Sync + 'static,
+{
+    /// On send request:
+    /// 1. If the account's circuit is open, or half-open while another packet tests the peer, reject the packet
+    /// 1. Otherwise forward it, and count the failures in a row to decide whether to open or close the circuit
+    async fn send_request(&mut self, request: OutgoingRequest<A>) -> IlpResult {
+        let mut settings = self
+            .account_settings
+            .get(request.to.username())
+            .or_else(|| self.default_settings.as_ref())
+            .cloned();
+        let failure_threshold = request.to.circuit_breaker_failure_threshold();
+        let cool_down = request.to.circuit_breaker_cool_down();
+        if failure_threshold.is_some() || cool_down.is_some() {
+            let settings = settings.get_or_insert_with(CircuitBreakerSettings::default);
+            if let Some(failure_threshold) = failure_threshold {
+                settings.failure_threshold = failure_threshold;
+            }
+            if let Some(cool_down) = cool_down {
+                settings.cool_down = cool_down;
+            }
+        }
+        let settings = settings.filter(|settings| settings.failure_threshold > 0);
+        let settings = match settings {
+            Some(settings) => settings,
+            None => return self.next.send_request(request).await,
+        };
+        let cool_down = Duration::from_millis(settings.cool_down);
+        let account_id = request.to.id();
+        let username = request.to.username().clone();
+        let ilp_address = self.store.get_ilp_address();
+
+        let rejected = {
+            let now = Instant::now();
+            let mut circuits = self.circuit_breakers.circuits.lock().unwrap();
+            let circuit = circuits
+                .entry(account_id)
+                .or_insert_with(|| Circuit::new(username.clone()));
+            let rejected = match circuit.state(now) {
+                CircuitState::Closed => false,
+                CircuitState::Open => true,
+                // A test packet which never gets a response does not keep the
+                // circuit half-open for longer than the cool-down
+                CircuitState::HalfOpen => match circuit.test_started_at {
+                    Some(started_at) if now.duration_since(started_at) < cool_down => true,
+                    _ => {
+                        debug!(
+                            "Forwarding a packet to test account {} whose circuit is half-open",
+                            username
+                        );
+                        circuit.test_started_at = Some(now);
+                        false
+                    }
+                },
+            };
+            if rejected {
+                circuit.rejected_packets += 1;
+            }
+            rejected
+        };
+        if rejected {
+            return Err(RejectBuilder {
+                code: ErrorCode::T01_PEER_UNREACHABLE,
+                message: format!(
+                    "Not forwarding packets to account {} until it recovers, its circuit breaker is open",
+                    username
+                )
+                .as_bytes(),
+                triggered_by: Some(&ilp_address),
+                data: &[],
+            }
+            .build());
+        }
+
+        let result = self.next.send_request(request).await;
+
+        let failed = match result {
+            Err(ref reject) => is_failure(reject),
+            Ok(_) => false,
+        };
+        let mut circuits = self.circuit_breakers.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(&account_id) {
+            if failed {
+                circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+                if circuit.open_until.is_some() {
+                    // The test packet failed, or one which was forwarded before the circuit opened
+                    circuit.open_until = Some(Instant::now() + cool_down);
+                    circuit.test_started_at = None;
+                } else if circuit.consecutive_failures >= settings.failure_threshold {
+                    warn!(
+                        "Opening the circuit breaker of account {} for {:?} after {} consecutive failed packets",
+                        username, cool_down, circuit.consecutive_failures
+                    );
+                    circuit.open_until = Some(Instant::now() + cool_down);
+                    circuit.rejected_packets = 0;
+                }
+            } else {
+                if circuit.open_until.is_some() {
+                    info!(
+                        "Closing the circuit breaker of account {}, which rejected {} packets while it was open",
+                        username, circuit.rejected_packets
+                    );
+                }
+                circuit.consecutive_failures = 0;
+                circuit.open_until = None;
+                circuit.test_started_at = None;
+                circuit.rejected_packets = 0;
+            }
+        }
+        drop(circuits);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_errors::AddressStoreError;
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use interledger_service::outgoing_service_fn;
+    use once_cell::sync::Lazy;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::SystemTime;
+
+    static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static BOB: Lazy<Username> = Lazy::new(|| Username::from_str("bob").unwrap());
+    static ALICE_ID: Lazy<Uuid> = Lazy::new(Uuid::new_v4);
+    static BOB_ID: Lazy<Uuid> = Lazy::new(Uuid::new_v4);
+    static CONNECTOR: Lazy<Address> = Lazy::new(|| Address::from_str("example.connector").unwrap());
+
+    fn reject(code: ErrorCode, triggered_by: &Address) -> Reject {
+        RejectBuilder {
+            code,
+            message: &[],
+            triggered_by: Some(triggered_by),
+            data: &[],
+        }
+        .build()
+    }
+
+    /// Service whose packets are all fulfilled, unless it is down, and which counts the packets it receives
+    fn test_service(
+        code: ErrorCode,
+    ) -> (
+        CircuitBreakerService<TestStore, impl OutgoingService<TestAccount> + Clone>,
+        Arc<AtomicBool>,
+        Arc<AtomicUsize>,
+    ) {
+        let down = Arc::new(AtomicBool::new(true));
+        let forwarded = Arc::new(AtomicUsize::new(0));
+        let (down_clone, forwarded_clone) = (down.clone(), forwarded.clone());
+        let next = outgoing_service_fn(move |_| {
+            forwarded_clone.fetch_add(1, Ordering::SeqCst);
+            if down_clone.load(Ordering::SeqCst) {
+                Err(reject(code, &CONNECTOR))
+            } else {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }
+        });
+        let service = CircuitBreakerService::new(
+            TestStore,
+            Some(CircuitBreakerSettings {
+                failure_threshold: 3,
+                cool_down: 50,
+            }),
+            next,
+        );
+        (service, down, forwarded)
+    }
+
+    #[tokio::test]
+    async fn opens_and_closes_the_circuit() {
+        let (mut service, down, forwarded) = test_service(ErrorCode::T01_PEER_UNREACHABLE);
+        for _ in 0..3 {
+            let reject = service
+                .send_request(request(&BOB, *BOB_ID))
+                .await
+                .unwrap_err();
+            assert_eq!(reject.triggered_by().unwrap(), *CONNECTOR);
+        }
+        assert_eq!(forwarded.load(Ordering::SeqCst), 3);
+
+        // The circuit is open, so packets are rejected without being forwarded
+        let reject = service
+            .send_request(request(&BOB, *BOB_ID))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert!(reject.message().ends_with(b"its circuit breaker is open"));
+        assert_eq!(forwarded.load(Ordering::SeqCst), 3);
+        let status = service.circuit_breakers().status();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].username, *BOB);
+        assert_eq!(status[0].state, CircuitState::Open);
+        assert_eq!(status[0].consecutive_failures, 3);
+        assert_eq!(status[0].rejected_packets, 1);
+        assert!(status[0].retry_in.is_some());
+
+        // Other accounts are not affected
+        down.store(false, Ordering::SeqCst);
+        assert!(service
+            .send_request(request(&ALICE, *ALICE_ID))
+            .await
+            .is_ok());
+        assert_eq!(forwarded.load(Ordering::SeqCst), 4);
+
+        // Once the cool-down elapses, a packet is let through and the circuit closes
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+        assert_eq!(
+            service.circuit_breakers().status()[1].state,
+            CircuitState::HalfOpen
+        );
+        assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_ok());
+        assert_eq!(forwarded.load(Ordering::SeqCst), 5);
+        let status = service.circuit_breakers().status();
+        assert_eq!(status[1].state, CircuitState::Closed);
+        assert_eq!(status[1].consecutive_failures, 0);
+        assert_eq!(status[1].rejected_packets, 0);
+    }
+
+    #[tokio::test]
+    async fn reopens_the_circuit_if_the_test_packet_fails() {
+        let (mut service, _down, forwarded) = test_service(ErrorCode::R00_TRANSFER_TIMED_OUT);
+        for _ in 0..3 {
+            assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_err());
+        }
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+        let reject = service
+            .send_request(request(&BOB, *BOB_ID))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::R00_TRANSFER_TIMED_OUT);
+        assert_eq!(forwarded.load(Ordering::SeqCst), 4);
+
+        let reject = service
+            .send_request(request(&BOB, *BOB_ID))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_eq!(forwarded.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            service.circuit_breakers().status()[0].state,
+            CircuitState::Open
+        );
+    }
+
+    #[tokio::test]
+    async fn only_counts_failures_in_a_row() {
+        let (mut service, down, forwarded) = test_service(ErrorCode::T02_PEER_BUSY);
+        for _ in 0..2 {
+            assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_err());
+        }
+        down.store(false, Ordering::SeqCst);
+        assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_ok());
+        down.store(true, Ordering::SeqCst);
+        for _ in 0..2 {
+            assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_err());
+        }
+        assert_eq!(forwarded.load(Ordering::SeqCst), 5);
+        assert_eq!(
+            service.circuit_breakers().status()[0].state,
+            CircuitState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_other_rejects() {
+        let (mut service, _down, forwarded) = test_service(ErrorCode::F99_APPLICATION_ERROR);
+        for _ in 0..5 {
+            assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_err());
+        }
+        assert_eq!(forwarded.load(Ordering::SeqCst), 5);
+
+        // Nor do the internal errors of this node
+        let (mut service, _down, forwarded) = test_service(ErrorCode::T00_INTERNAL_ERROR);
+        for _ in 0..5 {
+            assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_err());
+        }
+        assert_eq!(forwarded.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn uses_the_settings_of_each_account() {
+        let (mut service, _down, forwarded) = test_service(ErrorCode::T01_PEER_UNREACHABLE);
+        service.account_settings(
+            BOB.clone(),
+            CircuitBreakerSettings {
+                failure_threshold: 0,
+                cool_down: 50,
+            },
+        );
+        for _ in 0..5 {
+            assert!(service.send_request(request(&BOB, *BOB_ID)).await.is_err());
+        }
+        assert_eq!(forwarded.load(Ordering::SeqCst), 5);
+        assert!(service.circuit_breakers().status().is_empty());
+    }
+
+    #[tokio::test]
+    async fn prefers_the_settings_of_the_account() {
+        let (mut service, _down, forwarded) = test_service(ErrorCode::T01_PEER_UNREACHABLE);
+        service.account_settings(
+            BOB.clone(),
+            CircuitBreakerSettings {
+                failure_threshold: 0,
+                cool_down: 50,
+            },
+        );
+        let bob_request = || {
+            let mut request = request(&BOB, *BOB_ID);
+            request.to.failure_threshold = Some(1);
+            request
+        };
+        for _ in 0..2 {
+            assert!(service.send_request(bob_request()).await.is_err());
+        }
+        assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            service.circuit_breakers().status()[0].state,
+            CircuitState::Open
+        );
+    }
+
+    fn request(username: &Username, id: Uuid) -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: TestAccount {
+                id: Uuid::new_v4(),
+                username: Username::from_str("charlie").unwrap(),
+                failure_threshold: None,
+            },
+            to: TestAccount {
+                id,
+                username: username.clone(),
+                failure_threshold: None,
+            },
+            original_amount: 100,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[1; 32],
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestAccount {
+        id: Uuid,
+        username: Username,
+        failure_threshold: Option<u32>,
+    }
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn username(&self) -> &Username {
+            &self.username
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &CONNECTOR
+        }
+    }
+
+    impl CircuitBreakerAccount for TestAccount {
+        fn circuit_breaker_failure_threshold(&self) -> Option<u32> {
+            self.failure_threshold
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestStore;
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _ilp_address: Address) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            CONNECTOR.clone()
+        }
+    }
+}
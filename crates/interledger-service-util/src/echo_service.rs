@@ -13,17 +13,21 @@ use std::time::SystemTime;
 use tracing::debug;
 
 /// The prefix that echo packets should have in its data section
-const ECHO_PREFIX: &str = "ECHOECHOECHOECHO";
+pub(crate) const ECHO_PREFIX: &str = "ECHOECHOECHOECHO";
 /// The length of the `ECHO_PREFIX`
-const ECHO_PREFIX_LEN: usize = 16;
+pub(crate) const ECHO_PREFIX_LEN: usize = 16;
 
-enum EchoPacketType {
+pub(crate) enum EchoPacketType {
     Request = 0,
     Response = 1,
 }
 
 /// A service that implements the Echo Protocol.
-/// Currently, this service only supports bidirectional mode (unidirectional mode is not supported yet).
+/// In bidirectional mode, the echo request carries the initiator's address and the service
+/// sends an echo response to it, whose fulfill or reject is relayed back to the initiator.
+/// In unidirectional mode, the echo request carries no source address and the service rejects
+/// it right away with an `F99` reject whose data is the echo response, so that the initiator
+/// doesn't need to be reachable from the echoing node.
 /// The service doesn't shorten expiry as it expects the expiry to be shortened by another service
 /// like `ExpiryShortenerService`.
 #[derive(Clone)]
@@ -106,6 +110,18 @@ where
             .build());
         }
 
+        // in unidirectional mode there is no source address to send the response to,
+        // so the response is sent back in the reject
+        if reader.is_empty() {
+            return Err(RejectBuilder {
+                code: ErrorCode::F99_APPLICATION_ERROR,
+                message: b"Echo",
+                triggered_by: Some(&ilp_address),
+                data: &echo_response_data(),
+            }
+            .build());
+        }
+
         // check source address
         let source_address = match reader.read_var_octet_string() {
             Ok(value) => match Address::try_from(value) {
@@ -198,20 +214,25 @@ pub struct EchoResponseBuilder<'a> {
 
 impl<'a> EchoResponseBuilder<'a> {
     pub fn build(&self) -> Prepare {
-        let mut data_buffer = BytesMut::with_capacity(ECHO_PREFIX_LEN + 1);
-        data_buffer.put(ECHO_PREFIX.as_bytes());
-        data_buffer.put_u8(EchoPacketType::Response as u8);
         PrepareBuilder {
             amount: self.amount,
             expires_at: self.expires_at,
             execution_condition: self.execution_condition,
             destination: self.destination.clone(),
-            data: data_buffer.borrow(),
+            data: echo_response_data().borrow(),
         }
         .build()
     }
 }
 
+/// The data section of an echo response
+pub(crate) fn echo_response_data() -> BytesMut {
+    let mut data_buffer = BytesMut::with_capacity(ECHO_PREFIX_LEN + 1);
+    data_buffer.put(ECHO_PREFIX.as_bytes());
+    data_buffer.put_u8(EchoPacketType::Response as u8);
+    data_buffer
+}
+
 #[cfg(test)]
 mod echo_tests {
     use super::*;
@@ -413,6 +434,41 @@ mod echo_tests {
         assert!(result.is_ok());
     }
 
+    /// If the echo request has no source address, the service echoes it in unidirectional mode
+    /// by rejecting it with the echo response, without calling the next service.
+    #[tokio::test]
+    async fn test_unidirectional_echo_packet() {
+        let fulfillment = &get_random_fulfillment();
+        let execution_condition = &get_hash_of(fulfillment);
+        let node_address = Address::from_str("example.recipient").unwrap();
+
+        // setup service
+        let handler = incoming_service_fn(|_| -> IlpResult {
+            panic!("The echo request should not be forwarded");
+        });
+        let mut echo_service = EchoService::new(TestStore(node_address.clone()), handler);
+
+        // setup request
+        let prepare = PrepareBuilder {
+            amount: 0,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition,
+            destination: node_address.clone(),
+            data: b"ECHOECHOECHOECHO\x00",
+        }
+        .build();
+        let from = TestAccount(Uuid::new_v4());
+
+        // test
+        let reject = echo_service
+            .handle_request(IncomingRequest { prepare, from })
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert_eq!(reject.triggered_by(), Some(node_address));
+        assert_eq!(reject.data(), b"ECHOECHOECHOECHO\x01");
+    }
+
     /// If echo packet type is neither `1` nor `2`, the packet is considered to be malformed.
     #[tokio::test]
     async fn test_invalid_echo_packet_type() {
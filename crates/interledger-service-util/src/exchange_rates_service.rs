@@ -1,8 +1,13 @@
 use async_trait::async_trait;
 use interledger_packet::{ErrorCode, RejectBuilder};
-use interledger_rates::ExchangeRateStore;
+use interledger_rates::{format_rate, ExchangeRateStore};
 use interledger_service::*;
 use interledger_settlement::core::types::{Convert, ConvertDetails};
+use num::{
+    bigint::BigInt,
+    rational::BigRational,
+    traits::{CheckedDiv, One, Signed, ToPrimitive, Zero},
+};
 use std::marker::PhantomData;
 use tracing::{error, trace, warn};
 
@@ -10,9 +15,12 @@ use tracing::{error, trace, warn};
 ///
 /// Responsible for getting the exchange rates for the two assets in the outgoing request (`request.from.asset_code`, `request.to.asset_code`).
 /// Requires a `ExchangeRateStore`
+///
+/// Amounts are converted with exact rational arithmetic and the outgoing amount is rounded down,
+/// so the node never forwards more than the rates and spread allow.
 #[derive(Clone)]
 pub struct ExchangeRateService<S, O, A> {
-    spread: f64,
+    spread: BigRational,
     store: S,
     next: O,
     account_type: PhantomData<A>,
@@ -24,7 +32,7 @@ where
     O: OutgoingService<A>,
     A: Account,
 {
-    pub fn new(spread: f64, store: S, next: O) -> Self {
+    pub fn new(spread: BigRational, store: S, next: O) -> Self {
         ExchangeRateService {
             spread,
             store,
@@ -47,105 +55,65 @@ where
     /// 1. Retrieves the exchange rate from the store (the store independently is responsible for polling the rates)
     ///     - return reject if the call to the store fails
     /// 1. Calculates the exchange rate AND scales it up/down depending on how many decimals each asset requires
-    /// 1. Updates the amount in the prepare packet and forwards it
+    /// 1. Rounds the result down, updates the amount in the prepare packet and forwards it
     async fn send_request(&mut self, mut request: OutgoingRequest<A>) -> IlpResult {
         let ilp_address = self.store.get_ilp_address();
         if request.prepare.amount() > 0 {
-            let rate: f64 = if request.from.asset_code() == request.to.asset_code() {
-                1f64
-            } else if let Ok(rates) = self
-                .store
-                .get_exchange_rates(&[&request.from.asset_code(), &request.to.asset_code()])
-            {
-                // Exchange rates are expressed as `base asset / asset`. To calculate the outgoing amount,
-                // we multiply by the incoming asset's rate and divide by the outgoing asset's rate. For example,
-                // if an incoming packet is denominated in an asset worth 1 USD and the outgoing asset is worth
-                // 10 USD, the outgoing amount will be 1/10th of the source amount.
-                rates[0] / rates[1]
+            let rate = if request.from.asset_code() == request.to.asset_code() {
+                Some(BigRational::one())
             } else {
-                error!(
-                    "No exchange rates available for assets: {}, {}",
-                    request.from.asset_code(),
-                    request.to.asset_code()
-                );
-                return Err(RejectBuilder {
-                    code: ErrorCode::T00_INTERNAL_ERROR,
-                    message: format!(
-                        "No exchange rate available from asset: {} to: {}",
+                self.store
+                    .get_exchange_rates(&[&request.from.asset_code(), &request.to.asset_code()])
+                    .ok()
+                    // Exchange rates are expressed as `base asset / asset`. To calculate the outgoing amount,
+                    // we multiply by the incoming asset's rate and divide by the outgoing asset's rate. For example,
+                    // if an incoming packet is denominated in an asset worth 1 USD and the outgoing asset is worth
+                    // 10 USD, the outgoing amount will be 1/10th of the source amount.
+                    .and_then(|rates| rates[0].checked_div(&rates[1]))
+            };
+            let rate = match rate {
+                Some(rate) => rate,
+                None => {
+                    error!(
+                        "No exchange rates available for assets: {}, {}",
                         request.from.asset_code(),
                         request.to.asset_code()
-                    )
-                    .as_bytes(),
-                    triggered_by: Some(&ilp_address),
-                    data: &[],
+                    );
+                    return Err(RejectBuilder {
+                        code: ErrorCode::T00_INTERNAL_ERROR,
+                        message: format!(
+                            "No exchange rate available from asset: {} to: {}",
+                            request.from.asset_code(),
+                            request.to.asset_code()
+                        )
+                        .as_bytes(),
+                        triggered_by: Some(&ilp_address),
+                        data: &[],
+                    }
+                    .build());
                 }
-                .build());
             };
 
-            // Apply spread
-            // TODO should this be applied differently for "local" or same-currency packets?
-            let rate = rate * (1.0 - self.spread);
-            let rate = if rate.is_finite() && rate.is_sign_positive() {
-                rate
-            } else {
+            // Apply spread. It is applied to same-currency packets too, the fees of
+            // specific accounts and asset pairs are charged by the FeeService instead
+            let rate = rate * (BigRational::one() - &self.spread);
+            let rate = if rate.is_negative() {
                 warn!(
-                    "Exchange rate would have been {} based on rate and spread, using 0.0 instead",
-                    rate
+                    "Exchange rate would have been {} based on rate and spread, using 0 instead",
+                    format_rate(&rate)
                 );
-                0.0
+                BigRational::zero()
+            } else {
+                rate
             };
 
-            // Can we overflow here?
-            let outgoing_amount = (request.prepare.amount() as f64) * rate;
-            let outgoing_amount = outgoing_amount.normalize_scale(ConvertDetails {
+            let incoming_amount = BigRational::from_integer(BigInt::from(request.prepare.amount()));
+            let outgoing_amount = match (incoming_amount * rate).normalize_scale(ConvertDetails {
                 from: request.from.asset_scale(),
                 to: request.to.asset_scale(),
-            });
-
-            match outgoing_amount {
-                Ok(outgoing_amount) => {
-                    // The conversion succeeded, but the produced f64
-                    // is larger than the maximum value for a u64.
-                    // When it gets cast to a u64, it will end up being 0.
-                    if outgoing_amount != 0.0 && outgoing_amount as u64 == 0 {
-                        let (code, message) = if outgoing_amount < 1.0 {
-                            // user wanted to send a positive value but it got rounded down to 0
-                            (
-                                ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT,
-                                format!(
-                                    "Could not cast to f64, amount too small: {}",
-                                    outgoing_amount
-                                ),
-                            )
-                        } else {
-                            // amount that arrived was too large for us to forward
-                            (
-                                ErrorCode::F08_AMOUNT_TOO_LARGE,
-                                format!(
-                                    "Could not cast to f64, amount too large: {}",
-                                    outgoing_amount
-                                ),
-                            )
-                        };
-
-                        return Err(RejectBuilder {
-                            code,
-                            message: message.as_bytes(),
-                            triggered_by: Some(&ilp_address),
-                            data: &[],
-                        }
-                        .build());
-                    }
-                    request.prepare.set_amount(outgoing_amount as u64);
-                    trace!("Converted incoming amount of: {} {} (scale {}) from account {} to outgoing amount of: {} {} (scale {}) for account {}",
-                        request.original_amount, request.from.asset_code(), request.from.asset_scale(), request.from.id(),
-                        outgoing_amount, request.to.asset_code(), request.to.asset_scale(), request.to.id());
-                }
+            }) {
+                Ok(outgoing_amount) => outgoing_amount,
                 Err(_) => {
-                    // This branch gets executed when the `Convert` trait
-                    // returns an error. Happens due to float
-                    // multiplication overflow .
-                    // (float overflow in Rust produces +inf)
                     return Err(RejectBuilder {
                         code: ErrorCode::F08_AMOUNT_TOO_LARGE,
                         message: format!(
@@ -162,6 +130,42 @@ where
                     }
                     .build());
                 }
+            };
+
+            // Round down, so that the node never delivers more than the rate allows
+            let rounded_amount = outgoing_amount.floor().to_integer();
+            match rounded_amount.to_u64() {
+                Some(0) if outgoing_amount.is_positive() => {
+                    // user wanted to send a positive value but it got rounded down to 0
+                    return Err(RejectBuilder {
+                        code: ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT,
+                        message: format!(
+                            "Converted amount too small: {}",
+                            format_rate(&outgoing_amount)
+                        )
+                        .as_bytes(),
+                        triggered_by: Some(&ilp_address),
+                        data: &[],
+                    }
+                    .build());
+                }
+                Some(rounded_amount) => {
+                    request.prepare.set_amount(rounded_amount);
+                    trace!("Converted incoming amount of: {} {} (scale {}) from account {} to outgoing amount of: {} {} (scale {}) for account {}",
+                        request.original_amount, request.from.asset_code(), request.from.asset_scale(), request.from.id(),
+                        rounded_amount, request.to.asset_code(), request.to.asset_scale(), request.to.id());
+                }
+                None => {
+                    // amount that arrived was too large for us to forward
+                    return Err(RejectBuilder {
+                        code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                        message: format!("Converted amount too large: {}", rounded_amount)
+                            .as_bytes(),
+                        triggered_by: Some(&ilp_address),
+                        data: &[],
+                    }
+                    .build());
+                }
             }
         }
 
@@ -174,6 +178,7 @@ mod tests {
     use super::*;
     use interledger_errors::{AddressStoreError, ExchangeRateStoreError};
     use interledger_packet::{Address, Fulfill, FulfillBuilder, PrepareBuilder, Reject};
+    use interledger_rates::rate_from_f64;
     use interledger_service::{outgoing_service_fn, Account};
     use once_cell::sync::Lazy;
     use std::collections::HashMap;
@@ -197,29 +202,43 @@ mod tests {
         assert_eq!(ret.1[0].prepare.amount(), 1_500_000);
     }
 
+    #[tokio::test]
+    async fn converts_large_amounts_exactly() {
+        // 2^53 + 1 is the smallest integer an f64 cannot represent
+        let ret = exchange_rate(9_007_199_254_740_993, 9, 1.0, 9, 1.0, 0.0).await;
+        assert_eq!(ret.1[0].prepare.amount(), 9_007_199_254_740_993);
+
+        let ret = exchange_rate(std::u64::MAX, 9, 0.3, 9, 0.3, 0.0).await;
+        assert_eq!(ret.1[0].prepare.amount(), std::u64::MAX);
+
+        // rounds down, so 1/3 of 10 is 3
+        let ret = exchange_rate(10, 0, 1.0, 0, 3.0, 0.0).await;
+        assert_eq!(ret.1[0].prepare.amount(), 3);
+    }
+
     #[tokio::test]
     async fn exchange_conversion_error() {
-        // rejects f64 that does not fit in u64
+        // rejects amounts that do not fit in u64
         let ret = exchange_rate(std::u64::MAX, 1, 2.0, 1, 1.0, 0.0).await;
         let reject = ret.0.unwrap_err();
         assert_eq!(reject.code(), ErrorCode::F08_AMOUNT_TOO_LARGE);
-        assert!(reject
-            .message()
-            .starts_with(b"Could not cast to f64, amount too large"));
+        assert!(reject.message().starts_with(b"Converted amount too large"));
 
-        // rejects f64 which gets rounded down to 0
+        // rejects amounts which get rounded down to 0
         let ret = exchange_rate(1, 2, 1.0, 1, 1.0, 0.0).await;
         let reject = ret.0.unwrap_err();
         assert_eq!(reject.code(), ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT);
-        assert!(reject
-            .message()
-            .starts_with(b"Could not cast to f64, amount too small"));
+        assert!(reject.message().starts_with(b"Converted amount too small"));
 
-        // `Convert` errored
+        // huge rates and scales do not overflow, the result is just too large
         let ret = exchange_rate(std::u64::MAX, 1, std::f64::MAX, 255, 1.0, 0.0).await;
         let reject = ret.0.unwrap_err();
         assert_eq!(reject.code(), ErrorCode::F08_AMOUNT_TOO_LARGE);
-        assert!(reject.message().starts_with(b"Could not convert"));
+
+        // a rate of 0 for the outgoing asset is treated as missing
+        let ret = exchange_rate(100, 1, 1.0, 1, 0.0, 0.0).await;
+        let reject = ret.0.unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T00_INTERNAL_ERROR);
     }
 
     #[tokio::test]
@@ -233,7 +252,7 @@ mod tests {
 
         // Rounds down
         let ret = exchange_rate(4, 1, 1.0, 1, 2.0, 0.01).await;
-        // this would've been 2, but it becomes 1.99 and gets rounded down to 1
+        // this would've been 2, but it becomes 1.98 and gets rounded down to 1
         assert_eq!(ret.1[0].prepare.amount(), 1);
 
         // Spread >= 1 means the node takes everything
@@ -343,20 +362,20 @@ mod tests {
 
     #[derive(Debug, Clone)]
     struct TestStore {
-        rates: HashMap<Vec<String>, (f64, f64)>,
+        rates: HashMap<Vec<String>, (BigRational, BigRational)>,
     }
 
     impl ExchangeRateStore for TestStore {
         fn get_exchange_rates(
             &self,
             asset_codes: &[&str],
-        ) -> Result<Vec<f64>, ExchangeRateStoreError> {
+        ) -> Result<Vec<BigRational>, ExchangeRateStoreError> {
             let mut ret = Vec::new();
             let key = vec![asset_codes[0].to_owned(), asset_codes[1].to_owned()];
             let v = self.rates.get(&key);
             if let Some(v) = v {
-                ret.push(v.0);
-                ret.push(v.1);
+                ret.push(v.0.clone());
+                ret.push(v.1.clone());
             } else {
                 return Err(ExchangeRateStoreError::PairNotFound {
                     from: key[0].clone(),
@@ -368,19 +387,24 @@ mod tests {
 
         fn set_exchange_rates(
             &self,
-            _rates: HashMap<String, f64>,
+            _rates: HashMap<String, BigRational>,
         ) -> Result<(), ExchangeRateStoreError> {
             unimplemented!()
         }
 
-        fn get_all_exchange_rates(&self) -> Result<HashMap<String, f64>, ExchangeRateStoreError> {
+        fn get_all_exchange_rates(
+            &self,
+        ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError> {
             unimplemented!()
         }
     }
 
     fn test_store(rate1: f64, rate2: f64) -> TestStore {
         let mut rates = HashMap::new();
-        rates.insert(vec!["ABC".to_owned(), "XYZ".to_owned()], (rate1, rate2));
+        rates.insert(
+            vec!["ABC".to_owned(), "XYZ".to_owned()],
+            (rate_from_f64(rate1).unwrap(), rate_from_f64(rate2).unwrap()),
+        );
         TestStore { rates }
     }
 
@@ -395,6 +419,6 @@ mod tests {
         TestAccount,
     > {
         let store = test_store(rate1, rate2);
-        ExchangeRateService::new(spread, store, handler)
+        ExchangeRateService::new(rate_from_f64(spread).unwrap(), store, handler)
     }
 }
@@ -0,0 +1,477 @@
+use async_trait::async_trait;
+use interledger_errors::FeeStoreError;
+use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_rates::{deserialize_rate, serialize_rate};
+use interledger_service::*;
+use num::{
+    bigint::BigInt,
+    rational::BigRational,
+    traits::{Signed, ToPrimitive, Zero},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+/// The fees charged on each packet matching a schedule, in units of the incoming account's asset
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FeeSchedule {
+    /// Fee charged on every packet, regardless of its amount
+    #[serde(default)]
+    pub fixed: u64,
+    /// Percentage of the packet's amount which is charged, e.g. `0.25` for 0.25%
+    #[serde(
+        default = "BigRational::zero",
+        deserialize_with = "deserialize_rate",
+        serialize_with = "serialize_rate"
+    )]
+    pub percentage: BigRational,
+    /// The fee charged on a packet if the fixed and percentage fees add up to less
+    #[serde(default)]
+    pub minimum: u64,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule {
+            fixed: 0,
+            percentage: BigRational::zero(),
+            minimum: 0,
+        }
+    }
+}
+
+impl FeeSchedule {
+    /// Returns the fee charged on a packet of the provided amount.
+    /// The percentage fee is rounded up and negative percentages are ignored
+    pub fn fee(&self, amount: u64) -> BigInt {
+        let percentage_fee = if self.percentage.is_positive() {
+            (BigRational::from_integer(BigInt::from(amount)) * &self.percentage
+                / BigRational::from_integer(BigInt::from(100u8)))
+            .ceil()
+            .to_integer()
+        } else {
+            BigInt::zero()
+        };
+        (BigInt::from(self.fixed) + percentage_fee).max(BigInt::from(self.minimum))
+    }
+}
+
+/// The fee schedules of the node. The fees of every schedule which matches
+/// a packet are added up
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct FeeSchedules {
+    /// Fees charged on the packets received from each account, by lowercase username
+    #[serde(default)]
+    pub incoming_accounts: HashMap<String, FeeSchedule>,
+    /// Fees charged on the packets sent to each account, by lowercase username
+    #[serde(default)]
+    pub outgoing_accounts: HashMap<String, FeeSchedule>,
+    /// Fees charged on the packets exchanged between two assets,
+    /// by `<incoming asset code>/<outgoing asset code>`
+    #[serde(default)]
+    pub asset_pairs: HashMap<String, FeeSchedule>,
+}
+
+impl FeeSchedules {
+    /// Returns the schedules which apply to the packets sent from one account to another
+    pub fn matching<'a, A: Account>(
+        &'a self,
+        from: &A,
+        to: &A,
+    ) -> impl Iterator<Item = &'a FeeSchedule> {
+        let pair = format!("{}/{}", from.asset_code(), to.asset_code());
+        self.incoming_accounts
+            .get(&from.username().to_lowercase())
+            .into_iter()
+            .chain(self.outgoing_accounts.get(&to.username().to_lowercase()))
+            .chain(self.asset_pairs.get(&pair))
+    }
+
+    /// Returns the total fee charged on a packet of the provided amount
+    /// sent from one account to another
+    pub fn fee<A: Account>(&self, from: &A, to: &A, amount: u64) -> BigInt {
+        self.matching(from, to)
+            .map(|schedule| schedule.fee(amount))
+            .sum()
+    }
+}
+
+/// Store trait for the fee schedules and for the fees which were earned
+#[async_trait]
+pub trait FeeStore {
+    /// Returns the fee schedules. They are kept in memory so that
+    /// they can be read synchronously while processing packets
+    fn get_fee_schedules(&self) -> Arc<FeeSchedules>;
+
+    /// Replaces all of the fee schedules
+    async fn set_fee_schedules(&self, schedules: FeeSchedules) -> Result<(), FeeStoreError>;
+
+    /// Adds the fee earned on a fulfilled packet to the revenue of the account it was received from
+    async fn record_fee_revenue(&self, account_id: Uuid, fee: u64) -> Result<(), FeeStoreError>;
+
+    /// Returns the total fees earned on the packets received from each account,
+    /// in units of the account's asset
+    async fn get_fee_revenue(&self) -> Result<HashMap<Uuid, u64>, FeeStoreError>;
+}
+
+/// # Fee Service
+///
+/// Outgoing Service responsible for charging the fees of the `FeeSchedules` which match each packet.
+/// The fee is deducted from the amount of the prepare packet before it is converted
+/// to the outgoing account's asset, so it is charged in units of the incoming account's asset.
+/// Packets whose amount does not cover the fee are rejected.
+///
+/// Fees are only earned on fulfilled packets, which are recorded in the store
+/// as revenue of the incoming account.
+/// Requires a `FeeStore`.
+#[derive(Clone)]
+pub struct FeeService<S, O, A> {
+    store: S,
+    next: O,
+    account_type: PhantomData<A>,
+}
+
+impl<S, O, A> FeeService<S, O, A>
+where
+    S: AddressStore + FeeStore,
+    O: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(store: S, next: O) -> Self {
+        FeeService {
+            store,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<S, O, A> OutgoingService<A> for FeeService<S, O, A>
+where
+    S: AddressStore + FeeStore + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Send + Sync + Clone + 'static,
+    A: Account + Send + Sync + 'static,
+{
+    /// On send request:
+    /// 1. If the prepare packet's amount is 0 or no fee applies to it, it just forwards
+    /// 1. Rejects the packet if its amount does not cover the fee
+    /// 1. Deducts the fee from the prepare packet's amount and forwards it
+    /// 1. If the packet is fulfilled, records the fee as revenue of the incoming account
+    async fn send_request(&mut self, mut request: OutgoingRequest<A>) -> IlpResult {
+        let amount = request.prepare.amount();
+        if amount == 0 {
+            return self.next.send_request(request).await;
+        }
+        let fee = self
+            .store
+            .get_fee_schedules()
+            .fee(&request.from, &request.to, amount);
+        let fee = match fee.to_u64() {
+            Some(0) => return self.next.send_request(request).await,
+            Some(fee) if fee < amount => fee,
+            _ => {
+                return Err(RejectBuilder {
+                    code: ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT,
+                    message: format!(
+                        "Amount does not cover the fee. Amount: {}, fee: {}",
+                        amount, fee
+                    )
+                    .as_bytes(),
+                    triggered_by: Some(&self.store.get_ilp_address()),
+                    data: &[],
+                }
+                .build());
+            }
+        };
+
+        request.prepare.set_amount(amount - fee);
+        let from_id = request.from.id();
+        let asset_code = request.from.asset_code().to_string();
+        let asset_scale = request.from.asset_scale();
+        let to_id = request.to.id();
+        let fulfill = self.next.send_request(request).await?;
+
+        debug!(
+            "Earned fee of {} {} (scale {}) on packet from account {} to account {}",
+            fee, asset_code, asset_scale, from_id, to_id
+        );
+        // The packet was already fulfilled, so failing to record the fee does not reject it
+        if let Err(err) = self.store.record_fee_revenue(from_id, fee).await {
+            error!(
+                "Error recording fee of {} earned on packet from account {}: {}",
+                fee, from_id, err
+            );
+        }
+        Ok(fulfill)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_errors::AddressStoreError;
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use interledger_rates::parse_rate;
+    use interledger_service::{outgoing_service_fn, Account};
+    use once_cell::sync::Lazy;
+    use parking_lot::RwLock;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+    use std::time::SystemTime;
+
+    static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static BOB: Lazy<Username> = Lazy::new(|| Username::from_str("bob").unwrap());
+    static ALICE_ID: Lazy<Uuid> = Lazy::new(Uuid::new_v4);
+    static BOB_ID: Lazy<Uuid> = Lazy::new(Uuid::new_v4);
+
+    fn schedule(fixed: u64, percentage: &str, minimum: u64) -> FeeSchedule {
+        FeeSchedule {
+            fixed,
+            percentage: parse_rate(percentage).unwrap(),
+            minimum,
+        }
+    }
+
+    #[test]
+    fn calculates_fees() {
+        assert_eq!(schedule(0, "0", 0).fee(1000), BigInt::from(0));
+        assert_eq!(schedule(3, "0", 0).fee(1000), BigInt::from(3));
+        assert_eq!(schedule(0, "0.5", 0).fee(1000), BigInt::from(5));
+        // percentage fees are rounded up
+        assert_eq!(schedule(0, "0.5", 0).fee(1001), BigInt::from(6));
+        assert_eq!(schedule(2, "0.5", 0).fee(1000), BigInt::from(7));
+        assert_eq!(schedule(2, "0.5", 10).fee(1000), BigInt::from(10));
+        assert_eq!(schedule(2, "0.5", 10).fee(10_000), BigInt::from(52));
+        assert_eq!(schedule(1, "-5", 0).fee(1000), BigInt::from(1));
+        // fees do not overflow
+        assert_eq!(
+            schedule(std::u64::MAX, "100", 0).fee(std::u64::MAX),
+            BigInt::from(std::u64::MAX) * BigInt::from(2u8)
+        );
+    }
+
+    #[tokio::test]
+    async fn adds_up_matching_schedules() {
+        let mut schedules = FeeSchedules::default();
+        schedules
+            .incoming_accounts
+            .insert("alice".to_string(), schedule(1, "0", 0));
+        schedules
+            .outgoing_accounts
+            .insert("bob".to_string(), schedule(0, "1", 0));
+        schedules
+            .asset_pairs
+            .insert("ABC/XYZ".to_string(), schedule(0, "0", 5));
+        // these do not match
+        schedules
+            .incoming_accounts
+            .insert("bob".to_string(), schedule(100, "0", 0));
+        schedules
+            .asset_pairs
+            .insert("XYZ/ABC".to_string(), schedule(100, "0", 0));
+
+        let (result, requests, store) = send_packet(schedules, 1000).await;
+        assert!(result.is_ok());
+        assert_eq!(requests[0].prepare.amount(), 1000 - 1 - 10 - 5);
+        assert_eq!(requests[0].original_amount, 1000);
+        assert_eq!(*store.revenue.lock().unwrap(), vec![(*ALICE_ID, 16)]);
+    }
+
+    #[tokio::test]
+    async fn forwards_packets_without_fees() {
+        let (result, requests, store) = send_packet(FeeSchedules::default(), 1000).await;
+        assert!(result.is_ok());
+        assert_eq!(requests[0].prepare.amount(), 1000);
+        assert!(store.revenue.lock().unwrap().is_empty());
+
+        // zero amount packets are not charged
+        let mut schedules = FeeSchedules::default();
+        schedules
+            .incoming_accounts
+            .insert("alice".to_string(), schedule(1, "0", 0));
+        let (result, requests, store) = send_packet(schedules, 0).await;
+        assert!(result.is_ok());
+        assert_eq!(requests[0].prepare.amount(), 0);
+        assert!(store.revenue.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_packets_which_do_not_cover_the_fee() {
+        let mut schedules = FeeSchedules::default();
+        schedules
+            .incoming_accounts
+            .insert("alice".to_string(), schedule(0, "0", 10));
+        let (result, requests, store) = send_packet(schedules, 10).await;
+        let reject = result.unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT);
+        assert!(reject
+            .message()
+            .starts_with(b"Amount does not cover the fee"));
+        assert!(requests.is_empty());
+        assert!(store.revenue.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_earns_fees_on_fulfilled_packets() {
+        let mut schedules = FeeSchedules::default();
+        schedules
+            .incoming_accounts
+            .insert("alice".to_string(), schedule(1, "0", 0));
+        let store = TestStore::new(schedules);
+        let mut service = FeeService::new(
+            store.clone(),
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let result = service.send_request(request(100)).await;
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        );
+        assert!(store.revenue.lock().unwrap().is_empty());
+    }
+
+    async fn send_packet(
+        schedules: FeeSchedules,
+        amount: u64,
+    ) -> (IlpResult, Vec<OutgoingRequest<TestAccount>>, TestStore) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+        let store = TestStore::new(schedules);
+        let mut service = FeeService::new(
+            store.clone(),
+            outgoing_service_fn(move |request| {
+                requests_clone.lock().unwrap().push(request);
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let result = service.send_request(request(amount)).await;
+        let requests = requests.lock().unwrap().clone();
+        (result, requests, store)
+    }
+
+    fn request(amount: u64) -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: TestAccount::new(*ALICE_ID, &ALICE, "ABC"),
+            to: TestAccount::new(*BOB_ID, &BOB, "XYZ"),
+            original_amount: amount,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount,
+                expires_at: SystemTime::now(),
+                execution_condition: &[1; 32],
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestAccount {
+        id: Uuid,
+        username: Username,
+        ilp_address: Address,
+        asset_code: String,
+    }
+
+    impl TestAccount {
+        fn new(id: Uuid, username: &Username, asset_code: &str) -> Self {
+            TestAccount {
+                id,
+                username: username.clone(),
+                ilp_address: Address::from_str("example.account").unwrap(),
+                asset_code: asset_code.to_string(),
+            }
+        }
+    }
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn username(&self) -> &Username {
+            &self.username
+        }
+
+        fn asset_code(&self) -> &str {
+            &self.asset_code
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &self.ilp_address
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestStore {
+        schedules: Arc<RwLock<Arc<FeeSchedules>>>,
+        revenue: Arc<Mutex<Vec<(Uuid, u64)>>>,
+    }
+
+    impl TestStore {
+        fn new(schedules: FeeSchedules) -> Self {
+            TestStore {
+                schedules: Arc::new(RwLock::new(Arc::new(schedules))),
+                revenue: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl FeeStore for TestStore {
+        fn get_fee_schedules(&self) -> Arc<FeeSchedules> {
+            self.schedules.read().clone()
+        }
+
+        async fn set_fee_schedules(&self, schedules: FeeSchedules) -> Result<(), FeeStoreError> {
+            *self.schedules.write() = Arc::new(schedules);
+            Ok(())
+        }
+
+        async fn record_fee_revenue(
+            &self,
+            account_id: Uuid,
+            fee: u64,
+        ) -> Result<(), FeeStoreError> {
+            self.revenue.lock().unwrap().push((account_id, fee));
+            Ok(())
+        }
+
+        async fn get_fee_revenue(&self) -> Result<HashMap<Uuid, u64>, FeeStoreError> {
+            unimplemented!()
+        }
+    }
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _ilp_address: Address) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            Address::from_str("example.connector").unwrap()
+        }
+    }
+}
@@ -0,0 +1,470 @@
+use async_trait::async_trait;
+use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_service::{Account, AddressStore, IlpResult, OutgoingRequest, OutgoingService};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Weight of the latest packet's latency in the average latency of the pipeline
+const LATENCY_SMOOTHING: f64 = 0.1;
+
+/// Extension trait for [`Account`](../interledger_service/trait.Account.html) with the limits
+/// of the packets which may be in flight at once
+pub trait InFlightLimitAccount: Account {
+    /// The maximum number of Prepare packets to or from this account which may be awaiting a response
+    fn max_in_flight_packets(&self) -> Option<u32> {
+        None
+    }
+
+    /// The maximum total amount of the Prepare packets to or from this account which may be awaiting a response
+    fn max_in_flight_amount(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Settings of the connector-wide load shedding
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LoadSheddingSettings {
+    /// Average time, in milliseconds, the forwarded packets should take to be fulfilled or rejected.
+    /// Above it, a share of the packets proportional to the excess latency is rejected
+    pub latency_target: u64,
+}
+
+/// The packets of one account, in one direction, which are awaiting a response
+#[derive(Default)]
+struct InFlight {
+    packets: u32,
+    amount: u64,
+}
+
+type InFlightPackets = Arc<Mutex<HashMap<Uuid, InFlight>>>;
+
+/// A packet counted in the in-flight packets of an account until it is dropped
+struct Reservation {
+    in_flight: InFlightPackets,
+    account_id: Uuid,
+    amount: u64,
+}
+
+impl Reservation {
+    /// Counts the packet in the account's in-flight packets, unless that would exceed its limits
+    fn new(
+        in_flight: &InFlightPackets,
+        account_id: Uuid,
+        amount: u64,
+        max_packets: Option<u32>,
+        max_amount: Option<u64>,
+    ) -> Option<Self> {
+        let mut accounts = in_flight.lock().unwrap();
+        let account = accounts.entry(account_id).or_default();
+        let packets_exceeded = max_packets.map_or(false, |max| account.packets >= max);
+        let amount_exceeded =
+            max_amount.map_or(false, |max| account.amount.saturating_add(amount) > max);
+        if packets_exceeded || amount_exceeded {
+            if account.packets == 0 {
+                accounts.remove(&account_id);
+            }
+            return None;
+        }
+        account.packets += 1;
+        account.amount = account.amount.saturating_add(amount);
+        Some(Reservation {
+            in_flight: in_flight.clone(),
+            account_id,
+            amount,
+        })
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut accounts = self.in_flight.lock().unwrap();
+        if let Some(account) = accounts.get_mut(&self.account_id) {
+            account.packets = account.packets.saturating_sub(1);
+            account.amount = account.amount.saturating_sub(self.amount);
+            if account.packets == 0 {
+                accounts.remove(&self.account_id);
+            }
+        }
+    }
+}
+
+/// Tracks the average latency of the pipeline and decides which packets to admit when it is too high
+#[derive(Default)]
+struct LoadShedder {
+    /// Exponential moving average of the latency of the forwarded packets, in milliseconds
+    average_latency: Option<f64>,
+    /// Share of a packet accrued towards admitting the next one while overloaded
+    credit: f64,
+}
+
+impl LoadShedder {
+    /// While the average latency is above the target, only admits the share `target / average latency`
+    /// of the packets, so that the load is reduced proportionally to the excess latency
+    fn admit(&mut self, latency_target: f64) -> bool {
+        match self.average_latency {
+            Some(average) if average > latency_target => {
+                self.credit += latency_target / average;
+                if self.credit >= 1.0 {
+                    self.credit -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => {
+                self.credit = 0.0;
+                true
+            }
+        }
+    }
+
+    fn record(&mut self, latency: f64) {
+        self.average_latency = Some(match self.average_latency {
+            Some(average) => average + LATENCY_SMOOTHING * (latency - average),
+            None => latency,
+        });
+    }
+}
+
+/// # In-flight Limit Service
+///
+/// Caps the number and total amount of the Prepare packets which may be awaiting a response at once,
+/// so that one account cannot tie up the node's liquidity and connections.
+/// The limits of the incoming account apply to the packets it sends, in its asset, and are
+/// enforced with T03 rejects. The limits of the outgoing account apply to the packets sent to it,
+/// in its asset, and are enforced with T02 rejects.
+/// If load shedding is configured, the service also tracks the average latency of the packets it forwards
+/// and, while it is above the target, rejects a share of the packets with T03 to relieve the node.
+/// Requires an `InFlightLimitAccount` and an `AddressStore`.
+#[derive(Clone)]
+pub struct InFlightLimitService<S, O> {
+    store: S,
+    next: O,
+    load_shedding: Option<LoadSheddingSettings>,
+    incoming: InFlightPackets,
+    outgoing: InFlightPackets,
+    load_shedder: Arc<Mutex<LoadShedder>>,
+}
+
+impl<S, O> InFlightLimitService<S, O> {
+    /// Creates a service which sheds load according to the settings, if they are provided
+    pub fn new(store: S, load_shedding: Option<LoadSheddingSettings>, next: O) -> Self {
+        InFlightLimitService {
+            store,
+            next,
+            load_shedding,
+            incoming: Arc::new(Mutex::new(HashMap::new())),
+            outgoing: Arc::new(Mutex::new(HashMap::new())),
+            load_shedder: Arc::new(Mutex::new(LoadShedder::default())),
+        }
+    }
+}
+
+#[async_trait]
+impl<S, O, A> OutgoingService<A> for InFlightLimitService<S, O>
+where
+    S: AddressStore + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Send + Sync + Clone + 'static,
+    A: InFlightLimitAccount + Send + Sync + 'static,
+{
+    /// On send request:
+    /// 1. Count the packet in the in-flight packets of the incoming and outgoing accounts,
+    ///    or reject it if that would exceed their limits
+    /// 1. Reject it if the pipeline is overloaded and the packet is not admitted
+    /// 1. Otherwise forward it, and record its latency once it is fulfilled or rejected
+    async fn send_request(&mut self, request: OutgoingRequest<A>) -> IlpResult {
+        let from = &request.from;
+        let to = &request.to;
+        let incoming = Reservation::new(
+            &self.incoming,
+            from.id(),
+            request.original_amount,
+            from.max_in_flight_packets(),
+            from.max_in_flight_amount(),
+        );
+        let outgoing = incoming.as_ref().and_then(|_| {
+            Reservation::new(
+                &self.outgoing,
+                to.id(),
+                request.prepare.amount(),
+                to.max_in_flight_packets(),
+                to.max_in_flight_amount(),
+            )
+        });
+
+        let rejection = if incoming.is_none() {
+            warn!(
+                "Account {} has too many packets in flight. Limits are: {:?} packets, {:?} amount",
+                from.username(),
+                from.max_in_flight_packets(),
+                from.max_in_flight_amount()
+            );
+            Some((
+                ErrorCode::T03_CONNECTOR_BUSY,
+                format!(
+                    "Too many packets in flight from account {}",
+                    from.username()
+                ),
+            ))
+        } else if outgoing.is_none() {
+            warn!(
+                "Account {} has too many packets in flight to it. Limits are: {:?} packets, {:?} amount",
+                to.username(),
+                to.max_in_flight_packets(),
+                to.max_in_flight_amount()
+            );
+            Some((
+                ErrorCode::T02_PEER_BUSY,
+                format!("Too many packets in flight to account {}", to.username()),
+            ))
+        } else if let Some(ref settings) = self.load_shedding {
+            if self
+                .load_shedder
+                .lock()
+                .unwrap()
+                .admit(settings.latency_target as f64)
+            {
+                None
+            } else {
+                debug!(
+                    "Shedding a packet from account {}, the latency is above the target of {}ms",
+                    from.username(),
+                    settings.latency_target
+                );
+                Some((
+                    ErrorCode::T03_CONNECTOR_BUSY,
+                    "The connector is overloaded".to_string(),
+                ))
+            }
+        } else {
+            None
+        };
+
+        if let Some((code, message)) = rejection {
+            return Err(RejectBuilder {
+                code,
+                message: message.as_bytes(),
+                triggered_by: Some(&self.store.get_ilp_address()),
+                data: &[],
+            }
+            .build());
+        }
+
+        let started_at = Instant::now();
+        let result = self.next.send_request(request).await;
+        if self.load_shedding.is_some() {
+            let latency = started_at.elapsed().as_secs_f64() * 1000.0;
+            self.load_shedder.lock().unwrap().record(latency);
+        }
+        drop(outgoing);
+        drop(incoming);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_errors::AddressStoreError;
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use interledger_service::Username;
+    use once_cell::sync::Lazy;
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+
+    static CONNECTOR: Lazy<Address> = Lazy::new(|| Address::from_str("example.connector").unwrap());
+
+    #[tokio::test]
+    async fn limits_the_packets_in_flight_from_an_account() {
+        let mut service = test_service(None);
+        let alice = TestAccount::new("alice", Some(2), None);
+        let bob = TestAccount::new("bob", None, None);
+        let (mut first, mut second, mut third) =
+            (service.clone(), service.clone(), service.clone());
+        let (first, second, third) = tokio::join!(
+            first.send_request(request(&alice, &bob, 100)),
+            second.send_request(request(&alice, &bob, 100)),
+            third.send_request(request(&alice, &bob, 100)),
+        );
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+        let reject = third.unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T03_CONNECTOR_BUSY);
+        assert_eq!(reject.triggered_by().unwrap(), *CONNECTOR);
+
+        // The packets are no longer in flight once they are fulfilled
+        assert!(service
+            .send_request(request(&alice, &bob, 100))
+            .await
+            .is_ok());
+        assert!(service.incoming.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn limits_the_amount_in_flight_to_an_account() {
+        let service = test_service(None);
+        let alice = TestAccount::new("alice", None, None);
+        let charlie = TestAccount::new("charlie", None, None);
+        let bob = TestAccount::new("bob", None, Some(150));
+        let (mut first, mut second, mut third) =
+            (service.clone(), service.clone(), service.clone());
+        let (first, second, third) = tokio::join!(
+            first.send_request(request(&alice, &bob, 100)),
+            second.send_request(request(&charlie, &bob, 100)),
+            third.send_request(request(&charlie, &bob, 50)),
+        );
+        assert!(first.is_ok());
+        assert_eq!(second.unwrap_err().code(), ErrorCode::T02_PEER_BUSY);
+        assert!(third.is_ok());
+        assert!(service.outgoing.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sheds_load_when_the_latency_is_above_the_target() {
+        let mut service = test_service(Some(LoadSheddingSettings { latency_target: 5 }));
+        let alice = TestAccount::new("alice", None, None);
+        let bob = TestAccount::new("bob", None, None);
+        assert!(service
+            .send_request(request(&alice, &bob, 100))
+            .await
+            .is_ok());
+        let reject = service
+            .send_request(request(&alice, &bob, 100))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T03_CONNECTOR_BUSY);
+        assert_eq!(reject.message(), b"The connector is overloaded");
+    }
+
+    #[test]
+    fn admits_a_share_of_the_packets_proportional_to_the_latency() {
+        let mut load_shedder = LoadShedder::default();
+        assert!(load_shedder.admit(10.0));
+        load_shedder.record(40.0);
+        let admitted = (0..8).filter(|_| load_shedder.admit(10.0)).count();
+        assert_eq!(admitted, 2);
+
+        // The average latency goes back below the target as faster packets are recorded
+        for _ in 0..20 {
+            load_shedder.record(5.0);
+        }
+        assert!((0..8).all(|_| load_shedder.admit(10.0)));
+    }
+
+    /// Service which takes 20ms to fulfill every packet
+    fn test_service(
+        load_shedding: Option<LoadSheddingSettings>,
+    ) -> InFlightLimitService<TestStore, SlowService> {
+        InFlightLimitService::new(TestStore, load_shedding, SlowService)
+    }
+
+    #[derive(Clone)]
+    struct SlowService;
+
+    #[async_trait]
+    impl OutgoingService<TestAccount> for SlowService {
+        async fn send_request(&mut self, _request: OutgoingRequest<TestAccount>) -> IlpResult {
+            tokio::time::delay_for(Duration::from_millis(20)).await;
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &[],
+            }
+            .build())
+        }
+    }
+
+    fn request(from: &TestAccount, to: &TestAccount, amount: u64) -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: from.clone(),
+            to: to.clone(),
+            original_amount: amount,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[1; 32],
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestAccount {
+        id: Uuid,
+        username: Username,
+        max_in_flight_packets: Option<u32>,
+        max_in_flight_amount: Option<u64>,
+    }
+
+    impl TestAccount {
+        fn new(
+            username: &str,
+            max_in_flight_packets: Option<u32>,
+            max_in_flight_amount: Option<u64>,
+        ) -> Self {
+            TestAccount {
+                id: Uuid::new_v4(),
+                username: Username::from_str(username).unwrap(),
+                max_in_flight_packets,
+                max_in_flight_amount,
+            }
+        }
+    }
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn username(&self) -> &Username {
+            &self.username
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &CONNECTOR
+        }
+    }
+
+    impl InFlightLimitAccount for TestAccount {
+        fn max_in_flight_packets(&self) -> Option<u32> {
+            self.max_in_flight_packets
+        }
+
+        fn max_in_flight_amount(&self) -> Option<u64> {
+            self.max_in_flight_amount
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestStore;
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _ilp_address: Address) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            CONNECTOR.clone()
+        }
+    }
+}
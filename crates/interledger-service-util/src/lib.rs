@@ -4,6 +4,10 @@
 
 /// Balance tracking service
 mod balance_service;
+/// Service responsible for capturing the packets received, so that they can be replayed
+mod capture_service;
+/// Service responsible for rejecting the packets to accounts which failed too many packets in a row
+mod circuit_breaker_service;
 /// Service which implements the echo protocol
 mod echo_service;
 /// Service responsible for setting and fetching dollar denominated exchange rates
@@ -11,8 +15,15 @@ mod exchange_rates_service;
 /// Service responsible for shortening the expiry time of packets,
 /// to take into account for network latency
 mod expiry_shortener_service;
+/// Service responsible for charging the fees configured per account and per asset pair
+mod fee_service;
+/// Service responsible for capping the packets and amount in flight to and from each account,
+/// and for shedding load when the node is overloaded
+mod in_flight_limit_service;
 /// Service responsible for capping the amount an account can send in a packet
 mod max_packet_amount_service;
+/// Client of the echo protocol, which measures the reachability of ILP addresses
+mod ping;
 /// Service responsible for capping the amount of packets and amount in packets an account can send
 mod rate_limit_service;
 /// Service responsible for checking that packets are not expired and that prepare packets' fulfillment conditions
@@ -20,13 +31,26 @@ mod rate_limit_service;
 mod validator_service;
 
 pub use self::balance_service::{BalanceService, BalanceStore};
+pub use self::capture_service::{
+    replay, CaptureFilter, CaptureReader, CaptureRecord, CaptureService, CaptureSettings,
+    CaptureStatus, CaptureWriter, PacketCapture, ReplayOutcome,
+};
+pub use self::circuit_breaker_service::{
+    CircuitBreakerAccount, CircuitBreakerService, CircuitBreakerSettings, CircuitBreakerStatus,
+    CircuitBreakers, CircuitState,
+};
 pub use self::echo_service::EchoService;
 pub use self::exchange_rates_service::ExchangeRateService;
 pub use self::expiry_shortener_service::{
     ExpiryShortenerService, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
+pub use self::fee_service::{FeeSchedule, FeeSchedules, FeeService, FeeStore};
+pub use self::in_flight_limit_service::{
+    InFlightLimitAccount, InFlightLimitService, LoadSheddingSettings,
+};
 pub use self::max_packet_amount_service::{MaxPacketAmountAccount, MaxPacketAmountService};
+pub use self::ping::{ping, PingOptions, PingReport, PingResult};
 pub use self::rate_limit_service::{
-    RateLimitAccount, RateLimitError, RateLimitService, RateLimitStore,
+    RateLimitAccount, RateLimitError, RateLimitService, RateLimitStore, RemainingRateLimits,
 };
 pub use self::validator_service::ValidatorService;
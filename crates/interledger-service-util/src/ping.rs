@@ -0,0 +1,316 @@
+use super::echo_service::{echo_response_data, EchoPacketType, ECHO_PREFIX, ECHO_PREFIX_LEN};
+use bytes::{BufMut, BytesMut};
+use interledger_packet::{Address, PrepareBuilder};
+use interledger_service::{Account, IncomingRequest, IncomingService};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::time::delay_for;
+use tracing::debug;
+
+/// Options for pinging an ILP address
+#[derive(Clone, Debug)]
+pub struct PingOptions {
+    /// Number of echo requests to send
+    pub count: u32,
+    /// Amount of each echo request, in the sending account's asset
+    pub amount: u64,
+    /// Time to wait between two echo requests
+    pub interval: Duration,
+    /// Time after which an echo request expires
+    pub timeout: Duration,
+}
+
+impl Default for PingOptions {
+    fn default() -> Self {
+        PingOptions {
+            count: 4,
+            amount: 0,
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// The outcome of a single echo request
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PingResult {
+    /// Position of the echo request, starting from 0
+    pub sequence: u32,
+    /// Whether the destination echoed the request
+    pub echoed: bool,
+    /// Time, in milliseconds, until the echo or the reject came back
+    pub round_trip_time: f64,
+    /// Code of the reject, if the request wasn't echoed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Message of the reject, if the request wasn't echoed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Address of the hop which rejected the request, if the request wasn't echoed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triggered_by: Option<String>,
+}
+
+/// Summary of pinging an ILP address
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PingReport {
+    pub destination: String,
+    pub sent: u32,
+    pub received: u32,
+    /// Share of the echo requests which weren't echoed, from 0 to 1
+    pub loss: f64,
+    /// Shortest, average and longest round-trip time of the echoed requests, in milliseconds
+    pub min_round_trip_time: Option<f64>,
+    pub avg_round_trip_time: Option<f64>,
+    pub max_round_trip_time: Option<f64>,
+    pub results: Vec<PingResult>,
+}
+
+/// Sends unidirectional echo requests to the destination through the service, on behalf of
+/// the `from` account, and reports which of them were echoed, how long they took and
+/// which hops rejected the others.
+///
+/// The destination must be served by an `EchoService`, which echoes requests without
+/// a source address by rejecting them with an echo response.
+pub async fn ping<I, A>(
+    mut service: I,
+    from: A,
+    destination: Address,
+    options: &PingOptions,
+) -> PingReport
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    let mut data = BytesMut::with_capacity(ECHO_PREFIX_LEN + 1);
+    data.put(ECHO_PREFIX.as_bytes());
+    data.put_u8(EchoPacketType::Request as u8);
+    let echo_response = echo_response_data();
+    let rng = SystemRandom::new();
+
+    let mut results = Vec::with_capacity(options.count as usize);
+    for sequence in 0..options.count {
+        if sequence > 0 {
+            delay_for(options.interval).await;
+        }
+
+        // Nobody can fulfill the request, the condition only makes it unique
+        let mut execution_condition = [0; 32];
+        rng.fill(&mut execution_condition)
+            .expect("Failed to generate a random condition");
+        let prepare = PrepareBuilder {
+            amount: options.amount,
+            expires_at: SystemTime::now() + options.timeout,
+            execution_condition: &execution_condition,
+            destination: destination.clone(),
+            data: &data,
+        }
+        .build();
+
+        let sent_at = Instant::now();
+        let result = service
+            .handle_request(IncomingRequest {
+                from: from.clone(),
+                prepare,
+            })
+            .await;
+        let round_trip_time = sent_at.elapsed().as_secs_f64() * 1000.0;
+
+        let result = match result {
+            Ok(_) => PingResult {
+                sequence,
+                echoed: true,
+                round_trip_time,
+                code: None,
+                message: None,
+                triggered_by: None,
+            },
+            Err(reject) => {
+                let triggered_by = reject.triggered_by();
+                let echoed = reject.data() == &echo_response[..]
+                    && triggered_by.as_ref() == Some(&destination);
+                if echoed {
+                    PingResult {
+                        sequence,
+                        echoed,
+                        round_trip_time,
+                        code: None,
+                        message: None,
+                        triggered_by: None,
+                    }
+                } else {
+                    PingResult {
+                        sequence,
+                        echoed,
+                        round_trip_time,
+                        code: Some(reject.code().to_string()),
+                        message: Some(String::from_utf8_lossy(reject.message()).to_string()),
+                        triggered_by: triggered_by.map(|address| address.to_string()),
+                    }
+                }
+            }
+        };
+        debug!("Ping {} to {}: {:?}", sequence, destination, result);
+        results.push(result);
+    }
+
+    report(destination, results)
+}
+
+fn report(destination: Address, results: Vec<PingResult>) -> PingReport {
+    let round_trip_times: Vec<f64> = results
+        .iter()
+        .filter(|result| result.echoed)
+        .map(|result| result.round_trip_time)
+        .collect();
+    let sent = results.len() as u32;
+    let received = round_trip_times.len() as u32;
+    let loss = if sent > 0 {
+        f64::from(sent - received) / f64::from(sent)
+    } else {
+        0.0
+    };
+    let (min, avg, max) = if round_trip_times.is_empty() {
+        (None, None, None)
+    } else {
+        (
+            Some(
+                round_trip_times
+                    .iter()
+                    .cloned()
+                    .fold(f64::INFINITY, f64::min),
+            ),
+            Some(round_trip_times.iter().sum::<f64>() / f64::from(received)),
+            Some(round_trip_times.iter().cloned().fold(0.0, f64::max)),
+        )
+    };
+
+    PingReport {
+        destination: destination.to_string(),
+        sent,
+        received,
+        loss,
+        min_round_trip_time: min,
+        avg_round_trip_time: avg,
+        max_round_trip_time: max,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EchoService;
+    use async_trait::async_trait;
+    use interledger_errors::AddressStoreError;
+    use interledger_packet::{ErrorCode, RejectBuilder};
+    use interledger_service::{incoming_service_fn, AddressStore, IlpResult, Username};
+    use once_cell::sync::Lazy;
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static NODE_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("example.node").unwrap());
+    static PEER_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("example.peer").unwrap());
+
+    #[derive(Clone)]
+    struct TestStore;
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _ilp_address: Address) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            unimplemented!()
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            PEER_ADDRESS.clone()
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            Uuid::nil()
+        }
+
+        fn username(&self) -> &Username {
+            &ALICE
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &NODE_ADDRESS
+        }
+    }
+
+    fn options(count: u32) -> PingOptions {
+        PingOptions {
+            count,
+            interval: Duration::from_millis(1),
+            ..PingOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_echoed_requests() {
+        let echo_service = EchoService::new(
+            TestStore,
+            incoming_service_fn(|_| -> IlpResult {
+                panic!("The echo request should not be forwarded");
+            }),
+        );
+        let report = ping(echo_service, TestAccount, PEER_ADDRESS.clone(), &options(3)).await;
+        assert_eq!(report.destination, "example.peer");
+        assert_eq!(report.sent, 3);
+        assert_eq!(report.received, 3);
+        assert!(report.loss < f64::EPSILON);
+        assert!(report.min_round_trip_time <= report.avg_round_trip_time);
+        assert!(report.avg_round_trip_time <= report.max_round_trip_time);
+        assert_eq!(
+            report
+                .results
+                .iter()
+                .map(|result| (result.sequence, result.echoed))
+                .collect::<Vec<_>>(),
+            vec![(0, true), (1, true), (2, true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_the_hop_which_rejected_requests() {
+        let unreachable = incoming_service_fn(|_| {
+            Err(RejectBuilder {
+                code: ErrorCode::T01_PEER_UNREACHABLE,
+                message: b"Peer unreachable",
+                triggered_by: Some(&NODE_ADDRESS),
+                data: &[],
+            }
+            .build())
+        });
+        let report = ping(unreachable, TestAccount, PEER_ADDRESS.clone(), &options(2)).await;
+        assert_eq!(report.sent, 2);
+        assert_eq!(report.received, 0);
+        assert!((report.loss - 1.0).abs() < f64::EPSILON);
+        assert_eq!(report.min_round_trip_time, None);
+        let result = &report.results[1];
+        assert_eq!(result.sequence, 1);
+        assert!(!result.echoed);
+        assert_eq!(result.code, Some("T01".to_string()));
+        assert_eq!(result.message, Some("Peer unreachable".to_string()));
+        assert_eq!(result.triggered_by, Some("example.node".to_string()));
+    }
+}
@@ -1,9 +1,16 @@
 use async_trait::async_trait;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::{Account, AddressStore, IlpResult, IncomingRequest, IncomingService};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{error, warn};
+use uuid::Uuid;
+
+const MINUTE: Duration = Duration::from_secs(60);
 
 /// Extension trait for [`Account`](../interledger_service/trait.Account.html) with rate limiting related information
 pub trait RateLimitAccount: Account {
@@ -16,6 +23,28 @@ pub trait RateLimitAccount: Account {
     fn amount_per_minute_limit(&self) -> Option<u64> {
         None
     }
+
+    /// The maximum packets this account can send at once, after which it is held
+    /// to its packets per minute limit. Defaults to the packets per minute limit
+    fn packets_burst_limit(&self) -> Option<u32> {
+        None
+    }
+
+    /// The maximum units this account can send at once, after which it is held
+    /// to its amount per minute limit. Defaults to the amount per minute limit
+    fn amount_burst_limit(&self) -> Option<u64> {
+        None
+    }
+
+    /// The maximum units this account can send per calendar day (in UTC)
+    fn amount_per_day_limit(&self) -> Option<u64> {
+        None
+    }
+
+    /// The maximum units this account can send per calendar month (in UTC)
+    fn amount_per_month_limit(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// Rate limiting related errors
@@ -25,10 +54,23 @@ pub enum RateLimitError {
     PacketLimitExceeded,
     /// Account exceeded their amount limit
     ThroughputLimitExceeded,
+    /// Account exceeded their daily or monthly volume cap
+    VolumeLimitExceeded,
     /// There was an internal error when trying to connect to the store
     StoreError,
 }
 
+/// What remains of the limits of an account, as seen by the store
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RemainingRateLimits {
+    /// Packets the account can send right away, if it has a packet limit
+    pub packets: Option<u64>,
+    /// Units the account can send right away, if it has an amount limit
+    pub amount: Option<u64>,
+    /// Units the account can send before it reaches its daily or monthly volume cap, if it has one
+    pub volume: Option<u64>,
+}
+
 /// Store trait which manages the rate limit related information of accounts
 #[async_trait]
 pub trait RateLimitStore {
@@ -36,14 +78,14 @@ pub trait RateLimitStore {
     type Account: RateLimitAccount;
 
     /// Apply rate limits based on the packets per minute and amount of per minute
-    /// limits set on the provided account
+    /// limits set on the provided account, along with its burst limits and volume caps
     async fn apply_rate_limits(
         &self,
         account: Self::Account,
         prepare_amount: u64,
     ) -> Result<(), RateLimitError>;
 
-    /// Refunds the throughput limit which was charged to an account
+    /// Refunds the throughput limit and the volume caps which were charged to an account
     /// Called if the node receives a reject packet after trying to forward
     /// a packet to a peer, meaning that effectively reject packets do not
     /// count towards a node's throughput limits
@@ -52,6 +94,303 @@ pub trait RateLimitStore {
         account: Self::Account,
         prepare_amount: u64,
     ) -> Result<(), RateLimitError>;
+
+    /// Charges the packets and amount which a local rate limiter let through since it last
+    /// reconciled to the account's limits, as far as they allow, and returns what remains of them.
+    /// This lets the nodes sharing a store enforce its limits approximately, without a round trip
+    /// to the store for every packet
+    async fn reconcile_rate_limits(
+        &self,
+        account: Self::Account,
+        packets: u64,
+        amount: u64,
+    ) -> Result<RemainingRateLimits, RateLimitError>;
+}
+
+/// In-process limit which refills at `rate` units per minute, of which up to `burst` units
+/// can be used at once. Like the stores' limits, it tracks the theoretical arrival time
+/// of the next unit, as in the Generic Cell Rate Algorithm
+struct TokenBucket {
+    rate: u64,
+    burst: u64,
+    /// Theoretical arrival time, in nanoseconds after the limiter's epoch
+    tat: u128,
+}
+
+impl TokenBucket {
+    /// Nanoseconds it takes to refill one unit
+    fn interval(&self) -> u128 {
+        MINUTE.as_nanos() / u128::from(self.rate.max(1))
+    }
+
+    fn remaining(&self, now: u128) -> u64 {
+        let interval = self.interval();
+        let full_at = now + u128::from(self.burst) * interval;
+        u64::try_from(full_at.saturating_sub(self.tat.max(now)) / interval)
+            .unwrap_or(u64::max_value())
+    }
+
+    fn take(&mut self, quantity: u64, now: u128) {
+        self.tat = self.tat.max(now) + u128::from(quantity) * self.interval();
+    }
+
+    fn refund(&mut self, quantity: u64, now: u128) {
+        self.tat = self
+            .tat
+            .saturating_sub(u128::from(quantity) * self.interval())
+            .max(now);
+    }
+
+    /// Lowers the units which can be used right away to `remaining`, if there are more
+    fn limit_to(&mut self, remaining: u64, now: u128) {
+        let interval = self.interval();
+        let tat = (now + u128::from(self.burst) * interval)
+            .saturating_sub(u128::from(remaining) * interval);
+        self.tat = self.tat.max(tat);
+    }
+}
+
+/// Keeps the bucket in line with the account's limits, which may be changed at any time
+fn update_bucket(
+    bucket: Option<TokenBucket>,
+    rate: Option<u64>,
+    burst: Option<u64>,
+) -> Option<TokenBucket> {
+    rate.map(|rate| {
+        let burst = burst.unwrap_or(rate);
+        match bucket {
+            Some(bucket) => TokenBucket {
+                rate,
+                burst,
+                tat: bucket.tat,
+            },
+            None => TokenBucket {
+                rate,
+                burst,
+                tat: 0,
+            },
+        }
+    })
+}
+
+/// The local limits of an account, along with its usage which was not reported to the store yet
+struct LocalLimits<A> {
+    account: A,
+    packets: Option<TokenBucket>,
+    amount: Option<TokenBucket>,
+    /// Units left before the volume caps as of the last reconciliation, minus the units sent since
+    volume: Option<u64>,
+    unreported_packets: u64,
+    /// Negative if more was refunded than sent since the last reconciliation
+    unreported_amount: i128,
+    /// Whether the account sent packets since the last reconciliation
+    active: bool,
+}
+
+impl<A: RateLimitAccount> LocalLimits<A> {
+    fn new(account: A) -> Self {
+        let mut limits = LocalLimits {
+            account: account.clone(),
+            packets: None,
+            amount: None,
+            volume: None,
+            unreported_packets: 0,
+            unreported_amount: 0,
+            active: true,
+        };
+        limits.update(account);
+        limits
+    }
+
+    fn update(&mut self, account: A) {
+        self.packets = update_bucket(
+            self.packets.take(),
+            account.packets_per_minute_limit().map(u64::from),
+            account.packets_burst_limit().map(u64::from),
+        );
+        self.amount = update_bucket(
+            self.amount.take(),
+            account.amount_per_minute_limit(),
+            account.amount_burst_limit(),
+        );
+        self.account = account;
+        self.active = true;
+    }
+
+    fn apply(&mut self, amount: u64, now: u128) -> Result<(), RateLimitError> {
+        if let Some(ref packets) = self.packets {
+            if packets.remaining(now) < 1 {
+                return Err(RateLimitError::PacketLimitExceeded);
+            }
+        }
+        if let Some(ref throughput) = self.amount {
+            if throughput.remaining(now) < amount {
+                return Err(RateLimitError::ThroughputLimitExceeded);
+            }
+        }
+        if let Some(volume) = self.volume {
+            if volume < amount {
+                return Err(RateLimitError::VolumeLimitExceeded);
+            }
+        }
+
+        if let Some(ref mut packets) = self.packets {
+            packets.take(1, now);
+        }
+        if let Some(ref mut throughput) = self.amount {
+            throughput.take(amount, now);
+        }
+        self.volume = self.volume.map(|volume| volume - amount);
+        self.unreported_packets += 1;
+        self.unreported_amount += i128::from(amount);
+        Ok(())
+    }
+
+    fn refund(&mut self, amount: u64, now: u128) {
+        if let Some(ref mut throughput) = self.amount {
+            throughput.refund(amount, now);
+        }
+        self.volume = self.volume.map(|volume| volume.saturating_add(amount));
+        self.unreported_amount -= i128::from(amount);
+    }
+
+    fn limit_to(&mut self, remaining: &RemainingRateLimits, now: u128) {
+        if let (Some(packets), Some(remaining)) = (self.packets.as_mut(), remaining.packets) {
+            packets.limit_to(remaining, now);
+        }
+        if let (Some(throughput), Some(remaining)) = (self.amount.as_mut(), remaining.amount) {
+            throughput.limit_to(remaining, now);
+        }
+        self.volume = remaining.volume;
+    }
+}
+
+/// The local limits of every account which recently sent packets, shared by the clones of a
+/// [`RateLimitService`](./struct.RateLimitService.html)
+#[derive(Clone)]
+struct LocalRateLimiter<A> {
+    /// Reference point of the buckets' arrival times
+    epoch: Instant,
+    accounts: Arc<Mutex<HashMap<Uuid, LocalLimits<A>>>>,
+}
+
+impl<A: RateLimitAccount> LocalRateLimiter<A> {
+    fn new() -> Self {
+        LocalRateLimiter {
+            epoch: Instant::now(),
+            accounts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn now(&self) -> u128 {
+        self.epoch.elapsed().as_nanos()
+    }
+
+    async fn apply<S>(&self, store: &S, account: A, amount: u64) -> Result<(), RateLimitError>
+    where
+        S: RateLimitStore<Account = A>,
+    {
+        let known = self.accounts.lock().unwrap().contains_key(&account.id());
+        if !known {
+            // Start from what remains of the shared limits when the account sends its first packet
+            let remaining = store
+                .reconcile_rate_limits(account.clone(), 0, 0)
+                .await
+                .unwrap_or_else(|err| {
+                    error!(
+                        "Error loading the rate limits of account {}, applying them from scratch: {:?}",
+                        account.username(),
+                        err
+                    );
+                    RemainingRateLimits::default()
+                });
+            let now = self.now();
+            self.accounts
+                .lock()
+                .unwrap()
+                .entry(account.id())
+                .or_insert_with(|| {
+                    let mut limits = LocalLimits::new(account.clone());
+                    limits.limit_to(&remaining, now);
+                    limits
+                });
+        }
+
+        let now = self.now();
+        let mut accounts = self.accounts.lock().unwrap();
+        let limits = accounts
+            .entry(account.id())
+            .or_insert_with(|| LocalLimits::new(account.clone()));
+        limits.update(account);
+        limits.apply(amount, now)
+    }
+
+    fn refund(&self, account: &A, amount: u64) {
+        let now = self.now();
+        if let Some(limits) = self.accounts.lock().unwrap().get_mut(&account.id()) {
+            limits.refund(amount, now);
+        }
+    }
+
+    async fn reconcile<S>(&self, store: &S)
+    where
+        S: RateLimitStore<Account = A>,
+    {
+        let usage: Vec<(A, u64, i128)> = {
+            let mut accounts = self.accounts.lock().unwrap();
+            accounts.retain(|_, limits| limits.active);
+            accounts
+                .values_mut()
+                .map(|limits| {
+                    let usage = (
+                        limits.account.clone(),
+                        limits.unreported_packets,
+                        limits.unreported_amount,
+                    );
+                    limits.unreported_packets = 0;
+                    limits.unreported_amount = 0;
+                    limits.active = false;
+                    usage
+                })
+                .collect()
+        };
+
+        for (account, packets, amount) in usage {
+            if amount < 0 {
+                let refunded = u64::try_from(-amount).unwrap_or(u64::max_value());
+                if let Err(err) = store
+                    .refund_throughput_limit(account.clone(), refunded)
+                    .await
+                {
+                    error!("Error refunding throughput limit: {:?}", err);
+                }
+            }
+            let charged = u64::try_from(amount.max(0)).unwrap_or(u64::max_value());
+            let result = store
+                .reconcile_rate_limits(account.clone(), packets, charged)
+                .await;
+            let now = self.now();
+            let mut accounts = self.accounts.lock().unwrap();
+            let limits = match accounts.get_mut(&account.id()) {
+                Some(limits) => limits,
+                None => continue,
+            };
+            match result {
+                Ok(remaining) => limits.limit_to(&remaining, now),
+                Err(err) => {
+                    error!(
+                        "Error reconciling the rate limits of account {}: {:?}",
+                        account.username(),
+                        err
+                    );
+                    // Report the usage again on the next reconciliation
+                    limits.unreported_packets += packets;
+                    limits.unreported_amount += i128::from(charged);
+                    limits.active = true;
+                }
+            }
+        }
+    }
 }
 
 /// # Rate Limit Service
@@ -60,7 +399,11 @@ pub trait RateLimitStore {
 /// by users who have reached their account's rate limit.
 /// Talks with the associated Store in order to figure out
 /// and set the rate limits per account.
-/// This service does packet based limiting and amount based limiting.
+/// This service does packet based limiting and amount based limiting,
+/// and enforces daily and monthly volume caps.
+///
+/// With [`local_limits`](#method.local_limits), the limits are applied with in-process
+/// token buckets instead, which are periodically reconciled with the store.
 ///
 /// Forwards everything else.
 /// Requires a `RateLimitAccount` and a `RateLimitStore`.
@@ -70,6 +413,7 @@ pub struct RateLimitService<S, I, A> {
     store: S,
     next: I, // Can we somehow omit the PhantomData
     account_type: PhantomData<A>,
+    local: Option<LocalRateLimiter<A>>,
 }
 
 impl<S, I, A> RateLimitService<S, I, A>
@@ -83,6 +427,29 @@ where
             store,
             next,
             account_type: PhantomData,
+            local: None,
+        }
+    }
+
+    /// Sets whether the limits are applied by in-process token buckets, rather than by the store
+    /// for every packet. The local limits must then be reconciled with the store periodically,
+    /// with [`reconcile_local_limits`](#method.reconcile_local_limits)
+    pub fn local_limits(&mut self, enabled: bool) -> &mut Self {
+        self.local = if enabled {
+            Some(LocalRateLimiter::new())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Reports the packets and amount sent since the last reconciliation to the store, and lowers
+    /// the local limits to what remains of the shared ones, which the other nodes using the store
+    /// may have used as well. The accounts which sent no packets since the last reconciliation
+    /// are forgotten, and their limits loaded from the store again when they send packets
+    pub async fn reconcile_local_limits(&self) {
+        if let Some(ref local) = self.local {
+            local.reconcile(&self.store).await;
         }
     }
 }
@@ -105,26 +472,42 @@ where
         let account = request.from.clone();
         let account_clone = account.clone();
         let prepare_amount = request.prepare.amount();
-        let has_throughput_limit = account.amount_per_minute_limit().is_some();
+        let has_throughput_limit = account.amount_per_minute_limit().is_some()
+            || account.amount_per_day_limit().is_some()
+            || account.amount_per_month_limit().is_some();
+        let has_limits = has_throughput_limit || account.packets_per_minute_limit().is_some();
         // request.from and request.amount are used for apply_rate_limits, can't the previous service
         // always set the account to have None for both?
-        match self
-            .store
-            .apply_rate_limits(request.from.clone(), request.prepare.amount())
-            .await
-        {
+        let applied = match self.local {
+            Some(ref local) if has_limits => {
+                local
+                    .apply(&self.store, request.from.clone(), prepare_amount)
+                    .await
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.store
+                    .apply_rate_limits(request.from.clone(), request.prepare.amount())
+                    .await
+            }
+        };
+        match applied {
             Ok(_) => {
                 let packet = self.next.handle_request(request).await;
                 // If we did not get a fulfill, we should refund the sender
                 if packet.is_err() && has_throughput_limit {
-                    let refunded = self
-                        .store
-                        .refund_throughput_limit(account_clone, prepare_amount)
-                        .await;
-                    // if refunding failed, that's too bad, we will just return the reject
-                    // from the peer
-                    if let Err(err) = refunded {
-                        error!("Error refunding throughput limit: {:?}", err);
+                    if let Some(ref local) = self.local {
+                        local.refund(&account_clone, prepare_amount);
+                    } else {
+                        let refunded = self
+                            .store
+                            .refund_throughput_limit(account_clone, prepare_amount)
+                            .await;
+                        // if refunding failed, that's too bad, we will just return the reject
+                        // from the peer
+                        if let Err(err) = refunded {
+                            error!("Error refunding throughput limit: {:?}", err);
+                        }
                     }
                 }
 
@@ -145,9 +528,12 @@ where
                         }
                         ErrorCode::T04_INSUFFICIENT_LIQUIDITY
                     }
+                    RateLimitError::VolumeLimitExceeded => {
+                        warn!("Account {} reached its volume cap. Limits are: {:?} per day, {:?} per month", account.id(), account.amount_per_day_limit(), account.amount_per_month_limit());
+                        ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+                    }
                     RateLimitError::StoreError => ErrorCode::T00_INTERNAL_ERROR,
                 };
-
                 let reject = RejectBuilder {
                     code,
                     triggered_by: Some(&ilp_address),
@@ -268,16 +654,139 @@ mod tests {
         assert_eq!(*store.was_refunded.read(), false);
     }
 
+    #[tokio::test]
+    async fn applies_local_limits_without_the_store() {
+        let next = incoming_service_fn(move |_| {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: b"test data",
+            }
+            .build())
+        });
+        // The store would reject every packet
+        let store = TestStore::new(Err(RateLimitError::PacketLimitExceeded));
+        let mut service = RateLimitService::new(store.clone(), next);
+        service.local_limits(true);
+        assert!(service.handle_request(TEST_REQUEST.clone()).await.is_ok());
+        assert!(service.handle_request(TEST_REQUEST.clone()).await.is_ok());
+        // The burst of 2 packets is used up
+        let reject = service
+            .handle_request(TEST_REQUEST.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T05_RATE_LIMITED);
+        // The limits were only loaded from the store once
+        assert_eq!(*store.reconciled.read(), vec![(0, 0)]);
+    }
+
+    #[tokio::test]
+    async fn reconciles_local_limits_with_the_store() {
+        let next = incoming_service_fn(move |_| {
+            Err(RejectBuilder {
+                code: ErrorCode::T00_INTERNAL_ERROR,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build())
+        });
+        let mut store = TestStore::new(Ok(()));
+        store.remaining = RemainingRateLimits {
+            packets: None,
+            amount: None,
+            volume: Some(150),
+        };
+        let mut service = RateLimitService::new(store.clone(), next);
+        service.local_limits(true);
+        // The rejected packet's amount is refunded
+        assert!(service.handle_request(TEST_REQUEST.clone()).await.is_err());
+        assert!(service.handle_request(TEST_REQUEST.clone()).await.is_err());
+        service.reconcile_local_limits().await;
+        assert_eq!(*store.reconciled.read(), vec![(0, 0), (2, 0)]);
+        assert_eq!(*store.was_refunded.read(), false);
+
+        // The other nodes used up the packets of the account
+        store.remaining = RemainingRateLimits {
+            packets: Some(0),
+            amount: None,
+            volume: Some(150),
+        };
+        let mut service = RateLimitService::new(store.clone(), next_with_fulfill());
+        service.local_limits(true);
+        assert_eq!(
+            service
+                .handle_request(TEST_REQUEST.clone())
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::T05_RATE_LIMITED
+        );
+    }
+
+    #[tokio::test]
+    async fn applies_local_volume_caps() {
+        let mut store = TestStore::new(Ok(()));
+        store.remaining = RemainingRateLimits {
+            packets: None,
+            amount: None,
+            volume: Some(150),
+        };
+        let mut service = RateLimitService::new(store.clone(), next_with_fulfill());
+        service.local_limits(true);
+        assert!(service.handle_request(TEST_REQUEST.clone()).await.is_ok());
+        let reject = service
+            .handle_request(TEST_REQUEST.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+
+        service.reconcile_local_limits().await;
+        assert_eq!(*store.reconciled.read(), vec![(0, 0), (1, 100)]);
+    }
+
+    #[test]
+    fn token_bucket_refills_at_its_rate() {
+        let mut bucket = TokenBucket {
+            rate: 60,
+            burst: 10,
+            tat: 0,
+        };
+        let second = Duration::from_secs(1).as_nanos();
+        let now = 100 * second;
+        assert_eq!(bucket.remaining(now), 10);
+        bucket.take(10, now);
+        assert_eq!(bucket.remaining(now), 0);
+        assert_eq!(bucket.remaining(now + 3 * second), 3);
+        bucket.refund(2, now);
+        assert_eq!(bucket.remaining(now), 2);
+        bucket.limit_to(1, now);
+        assert_eq!(bucket.remaining(now), 1);
+        bucket.limit_to(5, now);
+        assert_eq!(bucket.remaining(now), 1);
+        assert_eq!(bucket.remaining(now + 60 * second), 10);
+    }
+
+    fn next_with_fulfill() -> impl IncomingService<TestAccount> + Clone {
+        incoming_service_fn(move |_| {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: b"test data",
+            }
+            .build())
+        })
+    }
+
     #[derive(Debug, Clone)]
     struct TestAccount;
 
     static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static ALICE_ID: Lazy<Uuid> = Lazy::new(Uuid::new_v4);
     static EXAMPLE_ADDRESS: Lazy<Address> =
         Lazy::new(|| Address::from_str("example.alice").unwrap());
 
     impl Account for TestAccount {
         fn id(&self) -> Uuid {
-            Uuid::new_v4()
+            *ALICE_ID
         }
 
         fn username(&self) -> &Username {
@@ -307,12 +816,26 @@ mod tests {
         fn amount_per_minute_limit(&self) -> Option<u64> {
             Some(100)
         }
+
+        fn packets_burst_limit(&self) -> Option<u32> {
+            Some(2)
+        }
+
+        fn amount_burst_limit(&self) -> Option<u64> {
+            Some(1000)
+        }
+
+        fn amount_per_day_limit(&self) -> Option<u64> {
+            Some(150)
+        }
     }
 
     #[derive(Clone)]
     struct TestStore {
         pub return_data: Result<(), RateLimitError>,
         pub was_refunded: Arc<RwLock<bool>>,
+        pub remaining: RemainingRateLimits,
+        pub reconciled: Arc<RwLock<Vec<(u64, u64)>>>,
     }
 
     impl TestStore {
@@ -320,6 +843,8 @@ mod tests {
             Self {
                 return_data,
                 was_refunded: Arc::new(RwLock::new(false)),
+                remaining: RemainingRateLimits::default(),
+                reconciled: Arc::new(RwLock::new(Vec::new())),
             }
         }
     }
@@ -360,6 +885,16 @@ mod tests {
             *self.was_refunded.write() = true;
             Ok(())
         }
+
+        async fn reconcile_rate_limits(
+            &self,
+            _: Self::Account,
+            packets: u64,
+            amount: u64,
+        ) -> Result<RemainingRateLimits, RateLimitError> {
+            self.reconciled.write().push((packets, amount));
+            Ok(self.remaining.clone())
+        }
     }
 
     static TEST_REQUEST: Lazy<IncomingRequest<TestAccount>> = Lazy::new(|| IncomingRequest {
@@ -0,0 +1,9 @@
+/// A TLS client certificate which the node presents to a peer, instead of or on top of
+/// the peer's token, when it connects to the peer over HTTPS or WSS
+#[derive(Clone)]
+pub struct ClientCertificate {
+    /// DER-encoded PKCS #12 archive holding the certificate chain and its private key
+    pub pkcs12: Vec<u8>,
+    /// The password the archive is encrypted with
+    pub password: String,
+}
@@ -25,6 +25,11 @@
 //! ### STREAM Receiver
 //!
 //! HttpServerService --> ValidatorService --> StreamReceiverService
+//!
+//! ## Tower
+//!
+//! Services can be turned into [tower](https://github.com/tower-rs/tower) Services and back with
+//! `into_tower` and `from_tower`, so that tower's middleware can be used along with them.
 
 use async_trait::async_trait;
 use interledger_errors::{AccountStoreError, AddressStoreError};
@@ -37,10 +42,16 @@ use std::{
 };
 use uuid::Uuid;
 
+mod client_certificate;
+pub use client_certificate::ClientCertificate;
 mod username;
 pub use username::Username;
+mod tower_compat;
 #[cfg(feature = "trace")]
 mod trace;
+pub use tower_compat::{
+    from_tower, into_tower, FromTower, FromTowerLayer, IntoTower, IntoTowerLayer, WrapLayer,
+};
 
 /// Result wrapper over [Fulfill](../interledger_packet/struct.Fulfill.html) and [Reject](../interledger_packet/struct.Reject.html)
 pub type IlpResult = Result<Fulfill, Reject>;
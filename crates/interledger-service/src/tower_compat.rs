@@ -0,0 +1,432 @@
+//! Adapters between the Interledger service traits and [tower](https://github.com/tower-rs/tower)'s
+//! `Service` and `Layer` traits, so that tower's middleware (timeouts, concurrency limits,
+//! load shedding, buffers, retries...) can be used in the node's service chains.
+use crate::*;
+use futures::future::{poll_fn, ready, Ready};
+use interledger_packet::{ErrorCode, RejectBuilder};
+use std::{
+    error::Error as StdError,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Turns the error of a tower Service into a Reject. Rejects are passed through, while the
+/// errors of tower's middleware, e.g. when a request timed out or was shed, become T00 rejects
+fn into_reject<E: Into<BoxError>>(err: E) -> Reject {
+    match err.into().downcast::<Reject>() {
+        Ok(reject) => *reject,
+        Err(err) => RejectBuilder {
+            code: ErrorCode::T00_INTERNAL_ERROR,
+            message: err.to_string().as_bytes(),
+            triggered_by: None,
+            data: &[],
+        }
+        .build(),
+    }
+}
+
+/// Use a tower Service, which returns Fulfills as responses and Rejects as errors,
+/// as an IncomingService or OutgoingService. Each request waits until the service is ready.
+pub fn from_tower<S, A>(service: S) -> FromTower<S, A>
+where
+    A: Account,
+{
+    FromTower {
+        inner: service,
+        account_type: PhantomData,
+    }
+}
+
+/// An IncomingService or OutgoingService created by `from_tower`
+#[derive(Clone)]
+pub struct FromTower<S, A> {
+    inner: S,
+    account_type: PhantomData<A>,
+}
+
+#[async_trait]
+impl<S, A> IncomingService<A> for FromTower<S, A>
+where
+    S: Service<IncomingRequest<A>, Response = Fulfill> + Send,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    A: Account + 'static,
+{
+    async fn handle_request(&mut self, request: IncomingRequest<A>) -> IlpResult {
+        poll_fn(|cx| self.inner.poll_ready(cx))
+            .await
+            .map_err(into_reject)?;
+        self.inner.call(request).await.map_err(into_reject)
+    }
+}
+
+#[async_trait]
+impl<S, A> OutgoingService<A> for FromTower<S, A>
+where
+    S: Service<OutgoingRequest<A>, Response = Fulfill> + Send,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    A: Account + 'static,
+{
+    async fn send_request(&mut self, request: OutgoingRequest<A>) -> IlpResult {
+        poll_fn(|cx| self.inner.poll_ready(cx))
+            .await
+            .map_err(into_reject)?;
+        self.inner.call(request).await.map_err(into_reject)
+    }
+}
+
+/// Use an IncomingService or OutgoingService as a tower Service, which returns the Fulfills
+/// as responses and the Rejects as errors. It is always ready, and each request is handled
+/// by a clone of the service.
+pub fn into_tower<S, A>(service: S) -> IntoTower<S, A>
+where
+    A: Account,
+{
+    IntoTower {
+        inner: service,
+        account_type: PhantomData,
+    }
+}
+
+/// A tower Service created by `into_tower`
+#[derive(Clone)]
+pub struct IntoTower<S, A> {
+    inner: S,
+    account_type: PhantomData<A>,
+}
+
+impl<S, A> Service<IncomingRequest<A>> for IntoTower<S, A>
+where
+    S: IncomingService<A> + Clone + Send + 'static,
+    A: Account + 'static,
+{
+    type Response = Fulfill;
+    type Error = Reject;
+    type Future = Pin<Box<dyn Future<Output = IlpResult> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Reject>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        let mut inner = self.inner.clone();
+        Box::pin(async move { inner.handle_request(request).await })
+    }
+}
+
+impl<S, A> Service<OutgoingRequest<A>> for IntoTower<S, A>
+where
+    S: OutgoingService<A> + Clone + Send + 'static,
+    A: Account + 'static,
+{
+    type Response = Fulfill;
+    type Error = Reject;
+    type Future = Pin<Box<dyn Future<Output = IlpResult> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Reject>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let mut inner = self.inner.clone();
+        Box::pin(async move { inner.send_request(request).await })
+    }
+}
+
+/// A tower Layer which turns tower Services into Interledger services, see `from_tower`
+#[derive(Clone)]
+pub struct FromTowerLayer<A> {
+    account_type: PhantomData<A>,
+}
+
+impl<A> FromTowerLayer<A> {
+    pub fn new() -> Self {
+        FromTowerLayer {
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<A> Default for FromTowerLayer<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, A: Account> Layer<S> for FromTowerLayer<A> {
+    type Service = FromTower<S, A>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        from_tower(inner)
+    }
+}
+
+/// A tower Layer which turns Interledger services into tower Services, see `into_tower`
+#[derive(Clone)]
+pub struct IntoTowerLayer<A> {
+    account_type: PhantomData<A>,
+}
+
+impl<A> IntoTowerLayer<A> {
+    pub fn new() -> Self {
+        IntoTowerLayer {
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<A> Default for IntoTowerLayer<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, A: Account> Layer<S> for IntoTowerLayer<A> {
+    type Service = IntoTower<S, A>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        into_tower(inner)
+    }
+}
+
+/// A tower Layer which wraps services in a `WrappedService` that calls the given function
+/// on every request, like `IncomingService::wrap` and `OutgoingService::wrap` do.
+#[derive(Clone)]
+pub struct WrapLayer<F, A> {
+    f: F,
+    account_type: PhantomData<A>,
+}
+
+impl<F, A> WrapLayer<F, A> {
+    pub fn new(f: F) -> Self {
+        WrapLayer {
+            f,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<F, I, A> Layer<I> for WrapLayer<F, A>
+where
+    F: Clone,
+{
+    type Service = WrappedService<F, I, A>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        WrappedService {
+            f: self.f.clone(),
+            inner: Arc::new(inner),
+            account_type: PhantomData,
+        }
+    }
+}
+
+/// The handlers of `incoming_service_fn` and `outgoing_service_fn` are also tower
+/// Services, so they can be the innermost service of a tower stack.
+impl<F, A> Service<IncomingRequest<A>> for ServiceFn<F, A>
+where
+    A: Account,
+    F: FnMut(IncomingRequest<A>) -> IlpResult,
+{
+    type Response = Fulfill;
+    type Error = Reject;
+    type Future = Ready<IlpResult>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Reject>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        ready((self.handler)(request))
+    }
+}
+
+impl<F, A> Service<OutgoingRequest<A>> for ServiceFn<F, A>
+where
+    A: Account,
+    F: FnMut(OutgoingRequest<A>) -> IlpResult,
+{
+    type Response = Fulfill;
+    type Error = Reject;
+    type Future = Ready<IlpResult>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Reject>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        ready((self.handler)(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TestAccount;
+    use interledger_packet::{FulfillBuilder, PrepareBuilder};
+    use std::{
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+    use tower::{ServiceBuilder, ServiceExt};
+
+    fn prepare(amount: u64) -> Prepare {
+        PrepareBuilder {
+            destination: Address::from_str("example.destination").unwrap(),
+            amount,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &[0; 32],
+            data: &[],
+        }
+        .build()
+    }
+
+    fn incoming_request(amount: u64) -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount,
+            prepare: prepare(amount),
+        }
+    }
+
+    fn fulfill() -> Fulfill {
+        FulfillBuilder {
+            fulfillment: &[0; 32],
+            data: b"fulfilled",
+        }
+        .build()
+    }
+
+    fn reject(code: ErrorCode) -> Reject {
+        RejectBuilder {
+            code,
+            message: b"rejected",
+            triggered_by: None,
+            data: &[],
+        }
+        .build()
+    }
+
+    /// Fulfills the packets of up to 100 units and rejects the others
+    fn handle(amount: u64) -> IlpResult {
+        if amount <= 100 {
+            Ok(fulfill())
+        } else {
+            Err(reject(ErrorCode::F08_AMOUNT_TOO_LARGE))
+        }
+    }
+
+    #[tokio::test]
+    async fn uses_tower_services_as_ilp_services() {
+        let mut service = from_tower(tower::service_fn(
+            |request: IncomingRequest<TestAccount>| async move { handle(request.prepare.amount()) },
+        ));
+        assert_eq!(
+            service.handle_request(incoming_request(100)).await,
+            Ok(fulfill())
+        );
+        assert_eq!(
+            service.handle_request(incoming_request(101)).await,
+            Err(reject(ErrorCode::F08_AMOUNT_TOO_LARGE))
+        );
+
+        let mut service = from_tower(tower::service_fn(
+            |request: OutgoingRequest<TestAccount>| async move { handle(request.prepare.amount()) },
+        ));
+        let request = incoming_request(101).into_outgoing(TestAccount);
+        assert_eq!(
+            service.send_request(request).await,
+            Err(reject(ErrorCode::F08_AMOUNT_TOO_LARGE))
+        );
+    }
+
+    #[tokio::test]
+    async fn uses_ilp_services_as_tower_services() {
+        let service = into_tower(incoming_service_fn(
+            |request: IncomingRequest<TestAccount>| handle(request.prepare.amount()),
+        ));
+        assert_eq!(
+            service.clone().oneshot(incoming_request(100)).await,
+            Ok(fulfill())
+        );
+        assert_eq!(
+            service.oneshot(incoming_request(101)).await,
+            Err(reject(ErrorCode::F08_AMOUNT_TOO_LARGE))
+        );
+
+        // Service functions are tower services on their own
+        let service = outgoing_service_fn(|request: OutgoingRequest<TestAccount>| {
+            handle(request.prepare.amount())
+        });
+        let request = incoming_request(100).into_outgoing(TestAccount);
+        assert_eq!(service.oneshot(request).await, Ok(fulfill()));
+    }
+
+    #[tokio::test]
+    async fn applies_tower_middleware() {
+        let slow_service = incoming_service_fn(|request: IncomingRequest<TestAccount>| {
+            handle(request.prepare.amount())
+        })
+        .wrap(|request, mut next| async move {
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+            next.handle_request(request).await
+        });
+
+        let mut service = ServiceBuilder::new()
+            .layer(FromTowerLayer::new())
+            .timeout(Duration::from_millis(10))
+            .layer(IntoTowerLayer::new())
+            .service(slow_service.clone());
+        let timed_out = service
+            .handle_request(incoming_request(100))
+            .await
+            .unwrap_err();
+        assert_eq!(timed_out.code(), ErrorCode::T00_INTERNAL_ERROR);
+
+        // The rejects of the inner service are passed through the middleware
+        let mut service = ServiceBuilder::new()
+            .layer(FromTowerLayer::new())
+            .timeout(Duration::from_secs(1))
+            .concurrency_limit(1)
+            .layer(IntoTowerLayer::new())
+            .service(slow_service);
+        assert_eq!(
+            service.handle_request(incoming_request(100)).await,
+            Ok(fulfill())
+        );
+        assert_eq!(
+            service.handle_request(incoming_request(101)).await,
+            Err(reject(ErrorCode::F08_AMOUNT_TOO_LARGE))
+        );
+    }
+
+    #[tokio::test]
+    async fn wraps_services_with_a_layer() {
+        let mut service = ServiceBuilder::new()
+            .layer(WrapLayer::new(
+                |request: IncomingRequest<TestAccount>,
+                 mut next: Box<dyn IncomingService<TestAccount> + Send>| async move {
+                    if request.prepare.amount() == 0 {
+                        Err(reject(ErrorCode::F02_UNREACHABLE))
+                    } else {
+                        next.handle_request(request).await
+                    }
+                },
+            ))
+            .service(incoming_service_fn(
+                |request: IncomingRequest<TestAccount>| handle(request.prepare.amount()),
+            ));
+        assert_eq!(
+            service.handle_request(incoming_request(0)).await,
+            Err(reject(ErrorCode::F02_UNREACHABLE))
+        );
+        assert_eq!(
+            service.handle_request(incoming_request(100)).await,
+            Ok(fulfill())
+        );
+    }
+}
@@ -1,6 +1,11 @@
 /// Wrapper around String to perform sanitization for usernames
 use regex::Regex;
-use std::{convert::TryFrom, fmt::Display, str::FromStr};
+use std::{
+    convert::TryFrom,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
 
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
@@ -28,6 +33,13 @@ impl PartialEq for Username {
 
 impl Eq for Username {}
 
+// Hashes the same way as the usernames are compared, so they can be used as map keys
+impl Hash for Username {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        UniCase::new(self).hash(state)
+    }
+}
+
 impl Display for Username {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
         f.write_str(&self.0)
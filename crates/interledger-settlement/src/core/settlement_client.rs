@@ -83,9 +83,38 @@ impl SettlementClient {
         engine_url: Url,
         amount: u64,
         asset_scale: u8,
+    ) -> Response {
+        let idempotency_key = Uuid::new_v4().to_hyphenated().to_string();
+        self.send_settlement_with_idempotency_key(
+            id,
+            engine_url,
+            amount,
+            asset_scale,
+            &idempotency_key,
+        )
+        .await
+    }
+
+    /// Same as `send_settlement`, but with the provided idempotency key, which is sent
+    /// with every retry so that the engine executes the settlement at most once
+    pub async fn send_settlement_with_idempotency_key(
+        &self,
+        id: Uuid,
+        engine_url: Url,
+        amount: u64,
+        asset_scale: u8,
+        idempotency_key: &str,
     ) -> Response {
         FutureRetry::new(
-            move || self.send_settlement_once(id, engine_url.clone(), amount, asset_scale),
+            move || {
+                self.send_settlement_once(
+                    id,
+                    engine_url.clone(),
+                    amount,
+                    asset_scale,
+                    idempotency_key,
+                )
+            },
             RequestErrorHandler::new(self.max_retries),
         )
         .await
@@ -118,6 +147,7 @@ impl SettlementClient {
         engine_url: Url,
         amount: u64,
         asset_scale: u8,
+        idempotency_key: &str,
     ) -> Response {
         let mut settlement_engine_url = engine_url;
 
@@ -133,14 +163,12 @@ impl SettlementClient {
             amount, settlement_engine_url
         );
 
-        // Mark the request as idempotent
-        let idempotency_uuid = Uuid::new_v4().to_hyphenated().to_string();
-
         // Make the POST request future
         let response = self
             .client
             .post(settlement_engine_url.as_ref())
-            .header("Idempotency-Key", idempotency_uuid)
+            // Mark the request as idempotent
+            .header("Idempotency-Key", idempotency_key)
             .json(&json!(Quantity::new(amount, asset_scale)))
             .send()
             .await?;
@@ -236,6 +264,28 @@ mod tests {
         assert!(ret.is_ok());
     }
 
+    #[tokio::test]
+    async fn reuses_idempotency_key_across_retries() {
+        let m = mock_settlement(500)
+            .match_header("Idempotency-Key", "some-key")
+            .create()
+            .expect(2);
+        let client = SettlementClient::new(Duration::from_secs(1), 1);
+
+        let ret = client
+            .send_settlement_with_idempotency_key(
+                Uuid::new_v4(),
+                "http://localhost:1234".parse().unwrap(),
+                100,
+                6,
+                "some-key",
+            )
+            .await;
+
+        m.assert();
+        assert!(ret.is_err());
+    }
+
     #[tokio::test]
     async fn engine_rejects() {
         let m = mock_settlement(500)
@@ -5,7 +5,8 @@ use interledger_errors::{ApiError, ApiErrorType, ProblemType};
 use interledger_errors::{LeftoversStoreError, SettlementStoreError};
 use interledger_packet::Address;
 use interledger_service::Account;
-use num_bigint::BigUint;
+use num_bigint::{BigInt, BigUint};
+use num_rational::BigRational;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::ops::{Div, Mul};
@@ -136,6 +137,55 @@ pub trait SettlementStore {
     ) -> Result<(), SettlementStoreError>;
 }
 
+/// An outgoing settlement whose amount was moved out of the account's balance,
+/// but which was neither confirmed by the settlement engine nor refunded yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutgoingSettlement {
+    /// Idempotency key sent to the engine with every attempt to send this settlement
+    pub idempotency_key: String,
+    /// The account which is being settled with
+    pub account_id: Uuid,
+    /// Amount to settle, in the account's asset scale
+    pub amount: u64,
+    /// The account's asset scale
+    pub asset_scale: u8,
+    /// Base URL of the account's settlement engine
+    pub engine_url: Url,
+}
+
+#[async_trait]
+/// Trait used by the connector to persist outgoing settlements before they are sent to the
+/// settlement engine, so that they can be retried (or refunded) if the node crashes in between
+pub trait SettlementOutboxStore {
+    /// Saves an outgoing settlement before it is sent to the settlement engine
+    async fn save_outgoing_settlement(
+        &self,
+        settlement: OutgoingSettlement,
+    ) -> Result<(), SettlementStoreError>;
+
+    /// Deletes the outgoing settlement with the provided idempotency key,
+    /// once the settlement engine accepted it
+    async fn complete_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError>;
+
+    /// Deletes the outgoing settlement with the provided idempotency key and refunds its amount
+    /// to the account's balance, once the settlement engine refused it.
+    ///
+    /// This MUST be atomic and do nothing if the settlement was already completed or refunded,
+    /// so that a settlement is never refunded twice
+    async fn refund_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError>;
+
+    /// Loads all of the outgoing settlements which were neither completed nor refunded
+    async fn get_pending_outgoing_settlements(
+        &self,
+    ) -> Result<Vec<OutgoingSettlement>, SettlementStoreError>;
+}
+
 /// Trait used by the connector and engine to track amounts which should have been
 /// settled but were not due to precision loss
 #[async_trait]
@@ -192,7 +242,7 @@ pub struct ConvertDetails {
     pub to: u8,
 }
 
-/// Helper trait for u64 and f64 asset code conversions for amounts and rates
+/// Helper trait for u64, BigUint and BigRational asset code conversions for amounts and rates
 pub trait Convert {
     type Item: Sized;
 
@@ -219,22 +269,18 @@ impl Convert for u64 {
     }
 }
 
-impl Convert for f64 {
-    type Item = f64;
-    // Not overflow safe. Would require using a package for Big floating point
-    // numbers such as BigDecimal
+impl Convert for BigRational {
+    type Item = BigRational;
+
+    /// Exact, so it never fails. Amounts which are not whole in the target scale keep
+    /// their fractional part, and the caller decides how to round them
     fn normalize_scale(&self, details: ConvertDetails) -> Result<Self::Item, ()> {
-        let scale_diff = (details.from as i8 - details.to as i8).abs() as u8;
-        let scale = 10f64.powi(scale_diff.into());
-        let res = if details.to >= details.from {
-            self * scale
-        } else {
-            self / scale
-        };
-        if res == std::f64::INFINITY {
-            Err(())
+        let scale_diff = (i16::from(details.from) - i16::from(details.to)).abs() as usize;
+        let scale = BigRational::from_integer(num_traits::pow(BigInt::from(10u8), scale_diff));
+        if details.to >= details.from {
+            Ok(self.mul(scale))
         } else {
-            Ok(res)
+            Ok(self.div(scale))
         }
     }
 }
@@ -345,80 +391,59 @@ mod tests {
         );
     }
 
-    #[allow(clippy::float_cmp)]
     #[test]
-    fn f64_test() {
-        // overflow
-        assert!(std::f64::MAX
-            .normalize_scale(ConvertDetails {
-                from: 1,
-                to: std::u8::MAX,
-            })
-            .is_err(),);
-
-        // 1 unit with base 1, is 1 unit with base 1
+    fn big_rational_test() {
+        let ratio = |numer: u64, denom: u64| {
+            BigRational::new(
+                BigInt::from_u64(numer).unwrap(),
+                BigInt::from_u64(denom).unwrap(),
+            )
+        };
+        // no overflow, even for the largest scale difference
         assert_eq!(
-            1f64.normalize_scale(ConvertDetails { from: 1, to: 1 })
+            ratio(std::u64::MAX, 1)
+                .normalize_scale(ConvertDetails {
+                    from: 0,
+                    to: std::u8::MAX,
+                })
                 .unwrap(),
-            1.0
+            BigRational::from_integer(
+                BigInt::from_u64(std::u64::MAX).unwrap() * num_traits::pow(BigInt::from(10u8), 255)
+            )
         );
-        // 1 unit with base 10, is 10 units with base 1
+        // 1 unit with base 1, is 1 unit with base 1
         assert_eq!(
-            1f64.normalize_scale(ConvertDetails { from: 1, to: 2 })
+            ratio(1, 1)
+                .normalize_scale(ConvertDetails { from: 1, to: 1 })
                 .unwrap(),
-            10.0
+            ratio(1, 1)
         );
         // 1 sat is 1e9 wei (multiplied by rate)
         assert_eq!(
-            1f64.normalize_scale(ConvertDetails { from: 9, to: 18 })
+            ratio(1, 1)
+                .normalize_scale(ConvertDetails { from: 9, to: 18 })
                 .unwrap(),
-            1_000_000_000.0
-        );
-
-        // 1.0 unit with base 2 is 0.1 unit with base 1
-        assert_eq!(
-            1f64.normalize_scale(ConvertDetails { from: 2, to: 1 })
-                .unwrap(),
-            0.1
+            ratio(1_000_000_000, 1)
         );
+        // 10.5 units with base 2 is exactly 1.05 units with base 1
         assert_eq!(
-            10.5f64
+            ratio(21, 2)
                 .normalize_scale(ConvertDetails { from: 2, to: 1 })
                 .unwrap(),
-            1.05
+            ratio(105, 100)
         );
-        // 100 units with base 3 is 10 units with base 2
+        // 299 units with base 3 is exactly 29.9 with base 2
         assert_eq!(
-            100f64
+            ratio(299, 1)
                 .normalize_scale(ConvertDetails { from: 3, to: 2 })
                 .unwrap(),
-            10.0
-        );
-        // 299 units with base 3 is 29.9 with base 2
-        assert_eq!(
-            299f64
-                .normalize_scale(ConvertDetails { from: 3, to: 2 })
-                .unwrap(),
-            29.9
-        );
-
-        assert_eq!(
-            999f64
-                .normalize_scale(ConvertDetails { from: 9, to: 6 })
-                .unwrap(),
-            0.999
-        );
-        assert_eq!(
-            1000f64
-                .normalize_scale(ConvertDetails { from: 9, to: 6 })
-                .unwrap(),
-            1.0
+            ratio(299, 10)
         );
         assert_eq!(
-            1999f64
+            ratio(1999, 1)
                 .normalize_scale(ConvertDetails { from: 9, to: 6 })
                 .unwrap(),
-            1.999
+            ratio(1999, 1000)
         );
     }
 }
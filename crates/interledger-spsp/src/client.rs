@@ -3,7 +3,7 @@ use futures::TryFutureExt;
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
 use interledger_service::{Account, IncomingService};
-use interledger_stream::{send_money, StreamDelivery};
+use interledger_stream::{send_money_with_options, SendMoneyOptions, StreamDelivery};
 use reqwest::Client;
 use std::convert::TryFrom;
 use tracing::{debug, error, trace};
@@ -41,6 +41,34 @@ pub async fn pay<I, A, S>(
     source_amount: u64,
     slippage: f64,
 ) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    pay_with_options(
+        service,
+        from_account,
+        store,
+        receiver,
+        source_amount,
+        slippage,
+        SendMoneyOptions::default(),
+    )
+    .await
+}
+
+/// Like [`pay`](./fn.pay.html), but sends the STREAM payment with the given options,
+/// such as a handle to follow its progress and cancel it
+pub async fn pay_with_options<I, A, S>(
+    service: I,
+    from_account: A,
+    store: S,
+    receiver: &str,
+    source_amount: u64,
+    slippage: f64,
+    options: SendMoneyOptions,
+) -> Result<StreamDelivery, Error>
 where
     I: IncomingService<A> + Clone + Send + Sync + 'static,
     A: Account + Send + Sync + 'static,
@@ -55,7 +83,7 @@ where
     })?;
     debug!("Sending SPSP payment to address: {}", addr);
 
-    let receipt = send_money(
+    let receipt = send_money_with_options(
         service,
         &from_account,
         store,
@@ -63,6 +91,7 @@ where
         shared_secret,
         source_amount,
         slippage,
+        options,
     )
     .map_err(move |err| {
         error!("Error sending payment: {:?}", err);
@@ -14,7 +14,7 @@ mod client;
 /// An SPSP Server implementing an HTTP Service which generates ILP Addresses and Shared Secrets
 mod server;
 
-pub use client::{pay, query};
+pub use client::{pay, pay_with_options, query};
 pub use server::SpspResponder;
 
 #[derive(Debug, thiserror::Error)]
@@ -42,6 +42,22 @@ pub struct SpspResponse {
     /// to be consumed for the STREAM connection
     #[serde(with = "serde_base64")]
     shared_secret: Vec<u8>,
+    /// Base-64 encoded nonce the receiver includes in the STREAM receipts for this connection,
+    /// if the client asked for receipts
+    #[serde(
+        default,
+        with = "serde_base64_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    receipt_nonce: Option<Vec<u8>>,
+    /// Base-64 encoded secret the receiver signs the STREAM receipts for this connection with,
+    /// if the client asked for receipts
+    #[serde(
+        default,
+        with = "serde_base64_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    receipt_secret: Option<Vec<u8>>,
 }
 
 // From https://github.com/serde-rs/json/issues/360#issuecomment-330095360
@@ -65,3 +81,56 @@ mod serde_base64 {
         base64::decode(s).map_err(de::Error::custom)
     }
 }
+
+#[doc(hidden)]
+mod serde_base64_option {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match bytes {
+            Some(bytes) => super::serde_base64::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = <Option<&str>>::deserialize(deserializer)?;
+        s.map(|s| base64::decode(s).map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod spsp_response {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn serializes_receipt_details_only_if_present() {
+        let response = SpspResponse {
+            destination_account: Address::from_str("example.receiver").unwrap(),
+            shared_secret: vec![1; 32],
+            receipt_nonce: None,
+            receipt_secret: None,
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert!(json.get("receipt_nonce").is_none());
+        assert!(json.get("receipt_secret").is_none());
+
+        let response = SpspResponse {
+            receipt_nonce: Some(vec![2; 16]),
+            receipt_secret: Some(vec![3; 32]),
+            ..response
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        let parsed: SpspResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.receipt_nonce, Some(vec![2; 16]));
+        assert_eq!(parsed.receipt_secret, Some(vec![3; 32]));
+    }
+}
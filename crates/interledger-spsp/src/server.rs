@@ -1,8 +1,8 @@
 use super::SpspResponse;
 use bytes::Bytes;
-use hyper::{service::Service as HttpService, Body, Error, Request, Response};
+use hyper::{service::Service as HttpService, Body, Error, HeaderMap, Request, Response};
 use interledger_packet::Address;
-use interledger_stream::ConnectionGenerator;
+use interledger_stream::{ConnectionGenerator, RECEIPT_NONCE_LENGTH, RECEIPT_SECRET_LENGTH};
 use std::error::Error as StdError;
 use std::{
     fmt, str,
@@ -40,17 +40,105 @@ impl SpspResponder {
             "Generated address and secret for: {:?}",
             destination_account
         );
-        let response = SpspResponse {
+        spsp_http_response(&SpspResponse {
             destination_account,
             shared_secret: shared_secret.to_vec(),
-        };
-
-        Response::builder()
-            .header("Content-Type", "application/spsp4+json")
-            .header("Cache-Control", "max-age=60")
-            .status(200)
-            .body(Body::from(serde_json::to_string(&response).unwrap()))
-            .unwrap()
+            receipt_nonce: None,
+            receipt_secret: None,
+        })
+    }
+
+    /// Returns an HTTP Response for a connection whose fulfills will carry
+    /// [STREAM receipts](../interledger_stream/struct.Receipt.html) signed with the
+    /// `receipt_secret`. The receipt nonce and secret are echoed in the response.
+    ///
+    /// Note that the STREAM receiver only signs receipts if it tracks connections.
+    pub fn generate_http_response_with_receipts(
+        &self,
+        receipt_nonce: [u8; RECEIPT_NONCE_LENGTH],
+        receipt_secret: [u8; RECEIPT_SECRET_LENGTH],
+    ) -> Response<Body> {
+        let (destination_account, shared_secret) = self
+            .connection_generator
+            .generate_address_and_secret_with_receipts(
+                &self.ilp_address,
+                receipt_nonce,
+                receipt_secret,
+            );
+        debug!(
+            "Generated address and secret with receipts for: {:?}",
+            destination_account
+        );
+        spsp_http_response(&SpspResponse {
+            destination_account,
+            shared_secret: shared_secret.to_vec(),
+            receipt_nonce: Some(receipt_nonce.to_vec()),
+            receipt_secret: Some(receipt_secret.to_vec()),
+        })
+    }
+
+    /// Returns an HTTP Response for an SPSP query with the given headers, asking for
+    /// receipts if the query has the base-64 encoded `Receipt-Nonce` and `Receipt-Secret`
+    /// headers. Responds with 400 Bad Request if those are invalid.
+    pub fn generate_http_response_from_headers(&self, headers: &HeaderMap) -> Response<Body> {
+        match receipt_details_from_headers(headers) {
+            Ok(Some((receipt_nonce, receipt_secret))) => {
+                self.generate_http_response_with_receipts(receipt_nonce, receipt_secret)
+            }
+            Ok(None) => self.generate_http_response(),
+            Err(err) => {
+                debug!("Invalid SPSP receipt headers: {}", err);
+                Response::builder()
+                    .status(400)
+                    .body(Body::from(err))
+                    .unwrap()
+            }
+        }
+    }
+}
+
+fn spsp_http_response(response: &SpspResponse) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/spsp4+json")
+        .header("Cache-Control", "max-age=60")
+        .status(200)
+        .body(Body::from(serde_json::to_string(response).unwrap()))
+        .unwrap()
+}
+
+/// Reads the receipt nonce and secret from the `Receipt-Nonce` and `Receipt-Secret`
+/// headers, which must either both be present or both be absent
+#[allow(clippy::type_complexity)]
+fn receipt_details_from_headers(
+    headers: &HeaderMap,
+) -> Result<Option<([u8; RECEIPT_NONCE_LENGTH], [u8; RECEIPT_SECRET_LENGTH])>, &'static str> {
+    let decode = |name: &str, length: usize| -> Result<Option<Vec<u8>>, &'static str> {
+        match headers.get(name) {
+            Some(value) => {
+                let value = value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| base64::decode(value.trim()).ok())
+                    .filter(|value| value.len() == length)
+                    .ok_or("Receipt-Nonce and Receipt-Secret must be base-64 encoded 16 and 32 byte values")?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    };
+    match (
+        decode("Receipt-Nonce", RECEIPT_NONCE_LENGTH)?,
+        decode("Receipt-Secret", RECEIPT_SECRET_LENGTH)?,
+    ) {
+        (Some(nonce), Some(secret)) => {
+            let mut receipt_nonce = [0; RECEIPT_NONCE_LENGTH];
+            receipt_nonce.copy_from_slice(&nonce);
+            let mut receipt_secret = [0; RECEIPT_SECRET_LENGTH];
+            receipt_secret.copy_from_slice(&secret);
+            Ok(Some((receipt_nonce, receipt_secret)))
+        }
+        (None, None) => Ok(None),
+        _ => Err("Receipt-Nonce and Receipt-Secret must be provided together"),
     }
 }
 
@@ -63,8 +151,8 @@ impl HttpService<Request<Body>> for SpspResponder {
         Ok(()).into()
     }
 
-    fn call(&mut self, _request: Request<Body>) -> Self::Future {
-        futures::future::ok(self.generate_http_response())
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        futures::future::ok(self.generate_http_response_from_headers(request.headers()))
     }
 }
 
@@ -113,4 +201,41 @@ mod spsp_server_test {
             "max-age=60"
         );
     }
+
+    #[tokio::test]
+    async fn spsp_response_with_receipts() {
+        let addr = Address::from_str("example.receiver").unwrap();
+        let mut responder = SpspResponder::new(addr, Bytes::from(&[0; 32][..]));
+        let response = responder
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri("http://example.com")
+                    .header("Accept", "application/spsp4+json")
+                    .header("Receipt-Nonce", base64::encode(&[1; 16]))
+                    .header("Receipt-Secret", base64::encode(&[2; 32]))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let response: SpspResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response.receipt_nonce, Some(vec![1; 16]));
+        assert_eq!(response.receipt_secret, Some(vec![2; 32]));
+
+        let response = responder
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri("http://example.com")
+                    .header("Receipt-Nonce", base64::encode(&[1; 16]))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
 }
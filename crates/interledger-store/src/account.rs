@@ -7,7 +7,8 @@ use interledger_http::HttpAccount;
 use interledger_packet::Address;
 use interledger_service::{Account as AccountTrait, Username};
 use interledger_service_util::{
-    MaxPacketAmountAccount, RateLimitAccount, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
+    CircuitBreakerAccount, InFlightLimitAccount, MaxPacketAmountAccount, RateLimitAccount,
+    RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
 use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
 use ring::aead;
@@ -79,6 +80,22 @@ pub struct Account {
     pub(crate) packets_per_minute_limit: Option<u32>,
     /// The maximum amount the account can send per minute
     pub(crate) amount_per_minute_limit: Option<u64>,
+    /// The maximum number of packets the account can send at once, after which it is held to its packets per minute limit
+    pub(crate) packets_burst_limit: Option<u32>,
+    /// The maximum amount the account can send at once, after which it is held to its amount per minute limit
+    pub(crate) amount_burst_limit: Option<u64>,
+    /// The maximum amount the account can send per calendar day (in UTC)
+    pub(crate) amount_per_day_limit: Option<u64>,
+    /// The maximum amount the account can send per calendar month (in UTC)
+    pub(crate) amount_per_month_limit: Option<u64>,
+    /// The maximum number of packets to or from the account which may be in flight at once
+    pub(crate) max_in_flight_packets: Option<u32>,
+    /// The maximum amount of the packets to or from the account which may be in flight at once
+    pub(crate) max_in_flight_amount: Option<u64>,
+    /// The number of consecutive failed packets to the account after which its circuit breaker opens
+    pub(crate) circuit_breaker_failure_threshold: Option<u32>,
+    /// The time, in milliseconds, during which the packets to the account are rejected once its circuit breaker opens
+    pub(crate) circuit_breaker_cool_down: Option<u64>,
     /// The account's settlement engine URL. If a global engine url is configured
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
@@ -172,6 +189,14 @@ impl Account {
             round_trip_time: details.round_trip_time.unwrap_or(DEFAULT_ROUND_TRIP_TIME),
             packets_per_minute_limit: details.packets_per_minute_limit,
             amount_per_minute_limit: details.amount_per_minute_limit,
+            packets_burst_limit: details.packets_burst_limit,
+            amount_burst_limit: details.amount_burst_limit,
+            amount_per_day_limit: details.amount_per_day_limit,
+            amount_per_month_limit: details.amount_per_month_limit,
+            max_in_flight_packets: details.max_in_flight_packets,
+            max_in_flight_amount: details.max_in_flight_amount,
+            circuit_breaker_failure_threshold: details.circuit_breaker_failure_threshold,
+            circuit_breaker_cool_down: details.circuit_breaker_cool_down,
             settlement_engine_url,
         })
     }
@@ -347,6 +372,42 @@ impl RateLimitAccount for Account {
     fn packets_per_minute_limit(&self) -> Option<u32> {
         self.packets_per_minute_limit
     }
+
+    fn packets_burst_limit(&self) -> Option<u32> {
+        self.packets_burst_limit
+    }
+
+    fn amount_burst_limit(&self) -> Option<u64> {
+        self.amount_burst_limit
+    }
+
+    fn amount_per_day_limit(&self) -> Option<u64> {
+        self.amount_per_day_limit
+    }
+
+    fn amount_per_month_limit(&self) -> Option<u64> {
+        self.amount_per_month_limit
+    }
+}
+
+impl InFlightLimitAccount for Account {
+    fn max_in_flight_packets(&self) -> Option<u32> {
+        self.max_in_flight_packets
+    }
+
+    fn max_in_flight_amount(&self) -> Option<u64> {
+        self.max_in_flight_amount
+    }
+}
+
+impl CircuitBreakerAccount for Account {
+    fn circuit_breaker_failure_threshold(&self) -> Option<u32> {
+        self.circuit_breaker_failure_threshold
+    }
+
+    fn circuit_breaker_cool_down(&self) -> Option<u64> {
+        self.circuit_breaker_cool_down
+    }
 }
 
 impl SettlementAccount for Account {
@@ -384,6 +445,14 @@ mod test {
         round_trip_time: Some(600),
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
+        packets_burst_limit: None,
+        amount_burst_limit: None,
+        amount_per_day_limit: None,
+        amount_per_month_limit: None,
+        max_in_flight_packets: None,
+        max_in_flight_amount: None,
+        circuit_breaker_failure_threshold: None,
+        circuit_breaker_cool_down: None,
         settlement_engine_url: None,
     });
 
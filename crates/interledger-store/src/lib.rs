@@ -6,6 +6,17 @@
 pub mod account;
 /// Cryptographic utilities for encrypting/decrypting data as well as clearing data from memory
 pub mod crypto;
+/// An in-memory backend which does not persist any data
+#[cfg(feature = "memory")]
+pub mod memory;
 /// A redis backend using [redis-rs](https://github.com/mitsuhiko/redis-rs/)
 #[cfg(feature = "redis")]
 pub mod redis;
+/// An SQL backend supporting PostgreSQL and SQLite using [sqlx](https://github.com/launchbadge/sqlx)
+#[cfg(feature = "sql")]
+pub mod sql;
+
+#[cfg(any(feature = "memory", feature = "redis", feature = "sql"))]
+mod rate_limits;
+#[cfg(any(feature = "memory", feature = "sql"))]
+mod throttle;
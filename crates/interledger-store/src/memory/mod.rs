@@ -0,0 +1,1569 @@
+// The in-memory store keeps the same data the RedisStore keeps in redis,
+// but in plain Rust data structures guarded by a single lock:
+//   accounts                 account details, balance and prepaid amount for each account
+//   usernames                maps each username to its account id
+//   routes                   dynamic routing table (local accounts and CCP routes)
+//   static_routes            static routing table
+//   multipath_routes         next hops of the prefixes with several CCP routes
+//   static_multipath_routes  next hops of the static routes with several next hops
+//   default_route            catch-all route
+//   settlement_engines       globally configured settlement engine per asset code
+//   parent_ilp_address       address received from our parent, if any
+//   stream_connections       running totals of the STREAM connections tracked by the receiver
+//   outgoing_settlements     settlements sent to the engines which were not completed or refunded yet
+//   payments                 payments sent and received by each account, oldest first
+//   api_tokens               API tokens issued by the node's administrator, keyed by their hash
+//   audit_log                administrative actions taken through the API, oldest first
+//   fee_revenue              fees earned on the packets received from each account
+// Every operation which is implemented as a Lua script in the RedisStore
+// is executed while holding the write lock, which makes it atomic.
+// Nothing is persisted: all data is lost when the store is dropped.
+use super::account::Account;
+use super::throttle::Throttle;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::channel::mpsc::UnboundedSender;
+use http::StatusCode;
+use interledger_api::{
+    AccountDetails, AccountSettings, ApiToken, ApiTokenStore, AuditLogEntry, AuditLogStore,
+    AuditQuery, NodeStore,
+};
+use interledger_btp::BtpStore;
+use interledger_ccp::{CcpRoutingAccount, CcpRoutingStore, RoutingRelation};
+use interledger_errors::*;
+use interledger_http::HttpStore;
+use interledger_packet::Address;
+use interledger_rates::ExchangeRateStore;
+use interledger_router::{NextHop, PrefixMap, RouterStore};
+use interledger_service::{Account as AccountTrait, AccountStore, AddressStore, Username};
+use interledger_service_util::{
+    BalanceStore, FeeSchedules, FeeStore, RateLimitError, RateLimitStore, RemainingRateLimits,
+};
+use interledger_settlement::core::{
+    idempotency::{IdempotentData, IdempotentStore},
+    scale_with_precision_loss,
+    types::{
+        Convert, ConvertDetails, LeftoversStore, OutgoingSettlement, SettlementOutboxStore,
+        SettlementStore,
+    },
+};
+use interledger_stream::{
+    unix_timestamp, PaymentHistoryStore, PaymentNotification, PaymentQuery, PaymentRecord,
+    StreamConnectionDetails, StreamConnectionStore, StreamNotificationsStore,
+};
+use num_bigint::BigUint;
+use num_rational::BigRational;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use secrecy::{ExposeSecret, SecretBytesMut};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tracing::{debug, error, trace, warn};
+use url::Url;
+use uuid::Uuid;
+
+/// Idempotency keys expire after 24 hours, like they do in redis
+const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(86400);
+
+/// The node's default ILP Address
+static DEFAULT_ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("local.host").unwrap());
+
+/// Errors which are specific to the in-memory store. They are returned
+/// to callers wrapped in the `Other` variant of each trait's error type.
+#[derive(Error, Debug)]
+enum MemoryStoreError {
+    #[error("account `{0}` was not found")]
+    AccountNotFound(Uuid),
+    #[error("Incoming prepare of {amount} would bring account {account_id} under its minimum balance. Current balance: {balance}, min balance: {min_balance}")]
+    BelowMinBalance {
+        account_id: Uuid,
+        amount: u64,
+        balance: i64,
+        min_balance: i64,
+    },
+}
+
+/// Builder for the in-memory Store
+pub struct MemoryStoreBuilder {
+    /// Connector's ILP Address. Used to insert `Child` accounts as
+    node_ilp_address: Address,
+}
+
+impl Default for MemoryStoreBuilder {
+    fn default() -> Self {
+        MemoryStoreBuilder::new()
+    }
+}
+
+impl MemoryStoreBuilder {
+    /// Simple Constructor
+    pub fn new() -> Self {
+        MemoryStoreBuilder {
+            node_ilp_address: DEFAULT_ILP_ADDRESS.clone(),
+        }
+    }
+
+    /// Sets the ILP Address corresponding to the node
+    pub fn node_ilp_address(&mut self, node_ilp_address: Address) -> &mut Self {
+        self.node_ilp_address = node_ilp_address;
+        self
+    }
+
+    /// Creates an empty store
+    pub fn build(&self) -> MemoryStore {
+        MemoryStore {
+            ilp_address: Arc::new(RwLock::new(self.node_ilp_address.clone())),
+            data: Arc::new(RwLock::new(MemoryStoreData::default())),
+            throttle: Arc::new(RwLock::new(Throttle::new())),
+            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            exchange_rates: Arc::new(RwLock::new(HashMap::new())),
+            routes: Arc::new(RwLock::new(Arc::new(PrefixMap::new()))),
+            multipath_routes: Arc::new(RwLock::new(Arc::new(PrefixMap::new()))),
+            fee_schedules: Arc::new(RwLock::new(Arc::new(FeeSchedules::default()))),
+        }
+    }
+}
+
+/// An account along with its balance-related details
+#[derive(Clone, Debug)]
+struct AccountEntry {
+    account: Account,
+    balance: i64,
+    prepaid_amount: i64,
+}
+
+#[derive(Default)]
+struct MemoryStoreData {
+    accounts: HashMap<Uuid, AccountEntry>,
+    usernames: HashMap<String, Uuid>,
+    routes: HashMap<String, Uuid>,
+    static_routes: HashMap<String, Uuid>,
+    multipath_routes: HashMap<String, Vec<NextHop>>,
+    static_multipath_routes: HashMap<String, Vec<NextHop>>,
+    default_route: Option<Uuid>,
+    settlement_engines: HashMap<String, Url>,
+    parent_ilp_address: Option<Address>,
+    /// Cached API responses along with the time they were saved
+    idempotent_data: HashMap<String, (IdempotentData, Instant)>,
+    /// Idempotency keys of the incoming settlements which were already credited
+    settlement_idempotency_keys: HashMap<String, Instant>,
+    /// Amounts (and their scales) which could not be credited due to precision loss
+    uncredited_amounts: HashMap<Uuid, Vec<(BigUint, u8)>>,
+    /// STREAM connections tracked by the receiver, keyed by their connection tag
+    stream_connections: HashMap<String, StreamConnectionDetails>,
+    /// Outgoing settlements which were not completed or refunded yet, keyed by idempotency key
+    outgoing_settlements: HashMap<String, OutgoingSettlement>,
+    /// Payments sent and received by each account, in the order they were saved
+    payments: HashMap<Uuid, Vec<PaymentRecord>>,
+    /// API tokens issued by the node's administrator, keyed by the hash of the token
+    api_tokens: HashMap<[u8; 32], ApiToken>,
+    /// Administrative actions taken through the API, in the order they were logged
+    audit_log: Vec<AuditLogEntry>,
+    /// Fees earned on the packets received from each account
+    fee_revenue: HashMap<Uuid, u64>,
+}
+
+impl MemoryStoreData {
+    /// Returns the account as it would be loaded from the store. If the
+    /// account does not have a settlement engine url set, but there is one
+    /// configured for its asset code, the globally configured one is used
+    fn load_account(&self, id: &Uuid) -> Option<Account> {
+        self.accounts.get(id).map(|entry| {
+            let mut account = entry.account.clone();
+            if account.settlement_engine_url.is_none() {
+                account.settlement_engine_url =
+                    self.settlement_engines.get(&account.asset_code).cloned();
+            }
+            account
+        })
+    }
+
+    fn load_accounts_where<F>(&self, predicate: F) -> Vec<Account>
+    where
+        F: Fn(&Account) -> bool,
+    {
+        self.accounts
+            .iter()
+            .filter(|(_, entry)| predicate(&entry.account))
+            .filter_map(|(id, _)| self.load_account(id))
+            .collect()
+    }
+
+    fn entry_mut(&mut self, id: Uuid) -> Result<&mut AccountEntry, MemoryStoreError> {
+        self.accounts
+            .get_mut(&id)
+            .ok_or(MemoryStoreError::AccountNotFound(id))
+    }
+
+    /// Credits the account for a fulfilled packet, and returns its balance
+    /// (including the prepaid amount) along with the amount which should be settled
+    fn process_fulfill(
+        &mut self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+    ) -> Result<(i64, u64), MemoryStoreError> {
+        let entry = self.entry_mut(to_account_id)?;
+        entry.balance += outgoing_amount as i64;
+
+        // Settlement is triggered if the balance reaches the settle threshold
+        // and the threshold is greater than the amount to settle down to
+        let mut amount_to_settle = 0;
+        if let (Some(settle_threshold), Some(settle_to)) =
+            (entry.account.settle_threshold, entry.account.settle_to)
+        {
+            if entry.balance >= settle_threshold && settle_threshold > settle_to {
+                amount_to_settle = (entry.balance - settle_to) as u64;
+                // Update the balance _before_ sending the settlement so that we don't accidentally send
+                // multiple settlements for the same balance. If the settlement fails we'll roll back
+                // the balance change by re-adding the amount back to the balance
+                entry.balance = settle_to;
+            }
+        }
+        Ok((entry.balance + entry.prepaid_amount, amount_to_settle))
+    }
+
+    /// Builds the routing table which is used by the Router
+    fn routing_table(&self) -> PrefixMap<Uuid> {
+        let mut table: PrefixMap<Uuid> = self
+            .routes
+            .iter()
+            .map(|(prefix, id)| (prefix, *id))
+            .collect();
+        // If there is a default route set, set the entry for ""
+        // in the routing table to route to that account
+        if let Some(id) = self.default_route {
+            table.insert("", id);
+        }
+        // Having the static routes inserted after ensures that they will overwrite
+        // any routes with the same prefix from the other routes
+        table.extend(self.static_routes.iter().map(|(prefix, id)| (prefix, *id)));
+        table
+    }
+
+    /// Builds the table of prefixes with several next hops which is used by the Router.
+    /// Only the prefixes whose first next hop is the one in the given routing table are
+    /// included, so that a static route overrides the next hops learned over CCP
+    fn multipath_routing_table(&self, routing_table: &PrefixMap<Uuid>) -> PrefixMap<Vec<NextHop>> {
+        let mut table = PrefixMap::new();
+        for (prefix, next_hops) in self
+            .multipath_routes
+            .iter()
+            .chain(self.static_multipath_routes.iter())
+        {
+            if routing_table.get(prefix) == next_hops.first().map(|hop| &hop.account_id) {
+                table.insert(prefix, next_hops.clone());
+            }
+        }
+        table
+    }
+}
+
+/// A Store that keeps all of its data in memory.
+///
+/// This store is intended for development nodes and tests which do not
+/// want to depend on an external database. All balance updates are done
+/// while holding a lock, so they have the same atomicity guarantees as the
+/// Lua scripts used by the RedisStore. Since nothing is persisted and the
+/// data cannot be shared between processes, it must not be used for nodes
+/// which are scaled horizontally or which need to survive restarts.
+#[derive(Clone)]
+pub struct MemoryStore {
+    /// The Store's ILP Address
+    ilp_address: Arc<RwLock<Address>>,
+    data: Arc<RwLock<MemoryStoreData>>,
+    throttle: Arc<RwLock<Throttle>>,
+    /// WebSocket sender which publishes incoming payment updates
+    subscriptions: Arc<RwLock<HashMap<Uuid, UnboundedSender<PaymentNotification>>>>,
+    exchange_rates: Arc<RwLock<HashMap<String, BigRational>>>,
+    /// The routing table is kept separately from the rest of the data so that
+    /// the Router can read it without contending with balance updates.
+    /// The inner `Arc<PrefixMap>` is used so that the `routing_table` method can
+    /// return a reference to the routing table without cloning the underlying data.
+    routes: Arc<RwLock<Arc<PrefixMap<Uuid>>>>,
+    multipath_routes: Arc<RwLock<Arc<PrefixMap<Vec<NextHop>>>>>,
+    /// The fee schedules are kept separately for the same reason as the routing table
+    fee_schedules: Arc<RwLock<Arc<FeeSchedules>>>,
+}
+
+impl MemoryStore {
+    /// Rebuilds the routing table which is read by the Router. Must be called
+    /// after any change to the routes while still holding the data lock so that
+    /// concurrent updates are applied in order.
+    fn update_routes(&self, data: &MemoryStoreData) {
+        let routes = data.routing_table();
+        let multipath_routes = data.multipath_routing_table(&routes);
+        trace!("Routing table is: {:?}", routes);
+        *self.routes.write() = Arc::new(routes);
+        *self.multipath_routes.write() = Arc::new(multipath_routes);
+    }
+
+    fn get_account_from_username(&self, username: &Username) -> Option<Account> {
+        let data = self.data.read();
+        data.usernames
+            .get(username.as_ref())
+            .and_then(|id| data.load_account(id))
+    }
+}
+
+#[async_trait]
+impl AccountStore for MemoryStore {
+    type Account = Account;
+
+    async fn get_accounts(
+        &self,
+        account_ids: Vec<Uuid>,
+    ) -> Result<Vec<Account>, AccountStoreError> {
+        let data = self.data.read();
+        let accounts: Vec<Account> = account_ids
+            .iter()
+            .filter_map(|id| data.load_account(id))
+            .collect();
+        if accounts.len() == account_ids.len() {
+            Ok(accounts)
+        } else {
+            Err(AccountStoreError::WrongLength {
+                expected: account_ids.len(),
+                actual: accounts.len(),
+            })
+        }
+    }
+
+    async fn get_account_id_from_username(
+        &self,
+        username: &Username,
+    ) -> Result<Uuid, AccountStoreError> {
+        match self.data.read().usernames.get(username.as_ref()) {
+            Some(id) => Ok(*id),
+            None => {
+                debug!("Username not found: {}", username);
+                Err(AccountStoreError::AccountNotFound(username.to_string()))
+            }
+        }
+    }
+}
+
+impl StreamNotificationsStore for MemoryStore {
+    type Account = Account;
+
+    fn add_payment_notification_subscription(
+        &self,
+        id: Uuid,
+        sender: UnboundedSender<PaymentNotification>,
+    ) {
+        trace!("Added payment notification listener for {}", id);
+        self.subscriptions.write().insert(id, sender);
+    }
+
+    fn publish_payment_notification(&self, payment: PaymentNotification) {
+        let account_id = match self.data.read().usernames.get(payment.to_username.as_ref()) {
+            Some(id) => *id,
+            None => {
+                error!(
+                    "Failed to find account ID corresponding to username: {}",
+                    payment.to_username
+                );
+                return;
+            }
+        };
+
+        debug!(
+            "Publishing payment notification {:?} for account {}",
+            payment, account_id
+        );
+        match self.subscriptions.read().get(&account_id) {
+            Some(sender) => {
+                if let Err(err) = sender.unbounded_send(payment) {
+                    error!("Failed to send message: {}", err);
+                }
+            }
+            None => trace!(
+                "Ignoring message for account {} because there were no open subscriptions",
+                account_id
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl StreamConnectionStore for MemoryStore {
+    async fn create_stream_connection(
+        &self,
+        connection_tag: &str,
+        receive_max: Option<u64>,
+    ) -> Result<(), StreamConnectionStoreError> {
+        self.data.write().stream_connections.insert(
+            connection_tag.to_string(),
+            StreamConnectionDetails {
+                total_received: 0,
+                receive_max,
+                closed: false,
+                last_activity: unix_timestamp(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        Ok(self
+            .data
+            .read()
+            .stream_connections
+            .get(connection_tag)
+            .cloned())
+    }
+
+    async fn add_stream_connection_amount(
+        &self,
+        connection_tag: &str,
+        amount: u64,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        let mut data = self.data.write();
+        let details = data
+            .stream_connections
+            .entry(connection_tag.to_string())
+            .or_default();
+        if amount > details.remaining() {
+            debug!(
+                "Not adding {} to STREAM connection {} which can only receive {} more",
+                amount,
+                connection_tag,
+                details.remaining()
+            );
+            return Ok(None);
+        }
+        details.total_received += amount;
+        details.last_activity = unix_timestamp();
+        Ok(Some(details.clone()))
+    }
+
+    async fn close_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<(), StreamConnectionStoreError> {
+        if let Some(details) = self.data.write().stream_connections.get_mut(connection_tag) {
+            details.closed = true;
+        }
+        Ok(())
+    }
+
+    async fn delete_idle_stream_connections(
+        &self,
+        idle_timeout: Duration,
+    ) -> Result<(), StreamConnectionStoreError> {
+        let cutoff = unix_timestamp().saturating_sub(idle_timeout.as_secs());
+        self.data
+            .write()
+            .stream_connections
+            .retain(|_, details| details.last_activity > cutoff);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PaymentHistoryStore for MemoryStore {
+    async fn save_payment(&self, payment: PaymentRecord) -> Result<(), PaymentHistoryStoreError> {
+        self.data
+            .write()
+            .payments
+            .entry(payment.account_id)
+            .or_default()
+            .push(payment);
+        Ok(())
+    }
+
+    async fn get_payments(
+        &self,
+        account_id: Uuid,
+        query: PaymentQuery,
+    ) -> Result<Vec<PaymentRecord>, PaymentHistoryStoreError> {
+        let data = self.data.read();
+        let payments = match data.payments.get(&account_id) {
+            Some(payments) => payments,
+            None => return Ok(Vec::new()),
+        };
+        Ok(payments
+            .iter()
+            .rev()
+            .filter(|payment| query.matches(payment))
+            .skip(query.offset)
+            .take(query.limit())
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ApiTokenStore for MemoryStore {
+    async fn insert_api_token(
+        &self,
+        token: ApiToken,
+        token_hash: [u8; 32],
+    ) -> Result<(), ApiTokenStoreError> {
+        self.data.write().api_tokens.insert(token_hash, token);
+        Ok(())
+    }
+
+    async fn get_api_token(
+        &self,
+        token_hash: [u8; 32],
+    ) -> Result<Option<ApiToken>, ApiTokenStoreError> {
+        Ok(self.data.read().api_tokens.get(&token_hash).cloned())
+    }
+
+    async fn get_all_api_tokens(&self) -> Result<Vec<ApiToken>, ApiTokenStoreError> {
+        let mut tokens: Vec<ApiToken> = self.data.read().api_tokens.values().cloned().collect();
+        tokens.sort_by_key(|token| (token.created_at, token.id));
+        Ok(tokens)
+    }
+
+    async fn delete_api_token(&self, id: Uuid) -> Result<ApiToken, ApiTokenStoreError> {
+        let mut data = self.data.write();
+        let token_hash = data
+            .api_tokens
+            .iter()
+            .find(|(_, token)| token.id == id)
+            .map(|(token_hash, _)| *token_hash)
+            .ok_or_else(|| ApiTokenStoreError::TokenNotFound(id.to_string()))?;
+        Ok(data.api_tokens.remove(&token_hash).unwrap())
+    }
+}
+
+#[async_trait]
+impl AuditLogStore for MemoryStore {
+    async fn append_audit_entry(&self, entry: AuditLogEntry) -> Result<(), AuditLogStoreError> {
+        self.data.write().audit_log.push(entry);
+        Ok(())
+    }
+
+    async fn get_audit_entries(
+        &self,
+        query: AuditQuery,
+    ) -> Result<Vec<AuditLogEntry>, AuditLogStoreError> {
+        Ok(self
+            .data
+            .read()
+            .audit_log
+            .iter()
+            .rev()
+            .filter(|entry| query.matches(entry))
+            .skip(query.offset)
+            .take(query.limit())
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl FeeStore for MemoryStore {
+    fn get_fee_schedules(&self) -> Arc<FeeSchedules> {
+        self.fee_schedules.read().clone()
+    }
+
+    async fn set_fee_schedules(&self, schedules: FeeSchedules) -> Result<(), FeeStoreError> {
+        *self.fee_schedules.write() = Arc::new(schedules);
+        Ok(())
+    }
+
+    async fn record_fee_revenue(&self, account_id: Uuid, fee: u64) -> Result<(), FeeStoreError> {
+        let mut data = self.data.write();
+        let revenue = data.fee_revenue.entry(account_id).or_insert(0);
+        *revenue = revenue.saturating_add(fee);
+        Ok(())
+    }
+
+    async fn get_fee_revenue(&self) -> Result<HashMap<Uuid, u64>, FeeStoreError> {
+        Ok(self.data.read().fee_revenue.clone())
+    }
+}
+
+#[async_trait]
+impl BalanceStore for MemoryStore {
+    /// Returns the balance **from the account holder's perspective**, meaning the sum of
+    /// the Payable Balance and Pending Outgoing minus the Receivable Balance and the Pending Incoming.
+    async fn get_balance(&self, account_id: Uuid) -> Result<i64, BalanceStoreError> {
+        match self.data.read().accounts.get(&account_id) {
+            Some(entry) => Ok(entry.balance + entry.prepaid_amount),
+            None => Err(BalanceStoreError::Other(Box::new(
+                MemoryStoreError::AccountNotFound(account_id),
+            ))),
+        }
+    }
+
+    async fn update_balances_for_prepare(
+        &self,
+        from_account_id: Uuid,
+        incoming_amount: u64,
+    ) -> Result<(), BalanceStoreError> {
+        // Don't do anything if the amount was 0
+        if incoming_amount == 0 {
+            return Ok(());
+        }
+
+        let mut data = self.data.write();
+        let entry = data
+            .entry_mut(from_account_id)
+            .map_err(|err| BalanceStoreError::Other(Box::new(err)))?;
+        let amount = incoming_amount as i64;
+
+        // Check that the prepare wouldn't go under the account's minimum balance
+        if let Some(min_balance) = entry.account.min_balance {
+            if entry.balance + entry.prepaid_amount - amount < min_balance {
+                return Err(BalanceStoreError::Other(Box::new(
+                    MemoryStoreError::BelowMinBalance {
+                        account_id: from_account_id,
+                        amount: incoming_amount,
+                        balance: entry.balance,
+                        min_balance,
+                    },
+                )));
+            }
+        }
+
+        // Deduct the amount from the prepaid_amount and/or the balance
+        if entry.prepaid_amount >= amount {
+            entry.prepaid_amount -= amount;
+        } else if entry.prepaid_amount > 0 {
+            entry.balance -= amount - entry.prepaid_amount;
+            entry.prepaid_amount = 0;
+        } else {
+            entry.balance -= amount;
+        }
+
+        trace!(
+            "Processed prepare with incoming amount: {}. Account {} has balance (including prepaid amount): {} ",
+            incoming_amount, from_account_id, entry.balance + entry.prepaid_amount
+        );
+        Ok(())
+    }
+
+    async fn update_balances_for_fulfill(
+        &self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+    ) -> Result<(i64, u64), BalanceStoreError> {
+        let (balance, amount_to_settle) = self
+            .data
+            .write()
+            .process_fulfill(to_account_id, outgoing_amount)
+            .map_err(|err| BalanceStoreError::Other(Box::new(err)))?;
+        trace!(
+            "Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
+            to_account_id,
+            outgoing_amount,
+            balance,
+            amount_to_settle,
+        );
+        Ok((balance, amount_to_settle))
+    }
+
+    async fn update_balances_for_fulfill_with_settlement(
+        &self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+        settlement: OutgoingSettlement,
+    ) -> Result<(i64, Option<OutgoingSettlement>), BalanceStoreError> {
+        // The settlement is saved under the same lock as the balance change
+        let mut data = self.data.write();
+        let (balance, amount_to_settle) = data
+            .process_fulfill(to_account_id, outgoing_amount)
+            .map_err(|err| BalanceStoreError::Other(Box::new(err)))?;
+        trace!(
+            "Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
+            to_account_id,
+            outgoing_amount,
+            balance,
+            amount_to_settle,
+        );
+        if amount_to_settle == 0 {
+            return Ok((balance, None));
+        }
+        let settlement = OutgoingSettlement {
+            amount: amount_to_settle,
+            ..settlement
+        };
+        data.outgoing_settlements
+            .insert(settlement.idempotency_key.clone(), settlement.clone());
+        Ok((balance, Some(settlement)))
+    }
+
+    async fn update_balances_for_reject(
+        &self,
+        from_account_id: Uuid,
+        incoming_amount: u64,
+    ) -> Result<(), BalanceStoreError> {
+        if incoming_amount == 0 {
+            return Ok(());
+        }
+
+        let mut data = self.data.write();
+        let entry = data
+            .entry_mut(from_account_id)
+            .map_err(|err| BalanceStoreError::Other(Box::new(err)))?;
+        entry.balance += incoming_amount as i64;
+
+        trace!(
+            "Processed reject for incoming amount: {}. Account {} has balance (including prepaid amount): {}",
+            incoming_amount, from_account_id, entry.balance + entry.prepaid_amount
+        );
+        Ok(())
+    }
+}
+
+impl ExchangeRateStore for MemoryStore {
+    fn get_exchange_rates(
+        &self,
+        asset_codes: &[&str],
+    ) -> Result<Vec<BigRational>, ExchangeRateStoreError> {
+        let rates: Vec<BigRational> = asset_codes
+            .iter()
+            .filter_map(|code| (*self.exchange_rates.read()).get(*code).cloned())
+            .collect();
+        if rates.len() == asset_codes.len() {
+            Ok(rates)
+        } else {
+            Err(ExchangeRateStoreError::PairNotFound {
+                from: asset_codes[0].to_string(),
+                to: asset_codes[1].to_string(),
+            })
+        }
+    }
+
+    fn get_all_exchange_rates(
+        &self,
+    ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError> {
+        Ok((*self.exchange_rates.read()).clone())
+    }
+
+    fn set_exchange_rates(
+        &self,
+        rates: HashMap<String, BigRational>,
+    ) -> Result<(), ExchangeRateStoreError> {
+        (*self.exchange_rates.write()) = rates;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BtpStore for MemoryStore {
+    type Account = Account;
+
+    async fn get_account_from_btp_auth(
+        &self,
+        username: &Username,
+        token: &str,
+    ) -> Result<Self::Account, BtpStoreError> {
+        if let Some(account) = self.get_account_from_username(username) {
+            if let Some(ref t) = account.ilp_over_btp_incoming_token {
+                let t = t.expose_secret();
+                if t.as_ref() == token.as_bytes() {
+                    Ok(account)
+                } else {
+                    debug!(
+                        "Found account {} but BTP auth token was wrong",
+                        account.username
+                    );
+                    Err(BtpStoreError::Unauthorized(username.to_string()))
+                }
+            } else {
+                debug!(
+                    "Account {} does not have an incoming btp token configured",
+                    account.username
+                );
+                Err(BtpStoreError::Unauthorized(username.to_string()))
+            }
+        } else {
+            warn!("No account found with BTP token");
+            Err(BtpStoreError::AccountNotFound(username.to_string()))
+        }
+    }
+
+    async fn get_btp_outgoing_accounts(&self) -> Result<Vec<Self::Account>, BtpStoreError> {
+        Ok(self
+            .data
+            .read()
+            .load_accounts_where(|account| account.ilp_over_btp_url.is_some()))
+    }
+}
+
+#[async_trait]
+impl HttpStore for MemoryStore {
+    type Account = Account;
+
+    /// Checks if the stored token for the provided account id matches the
+    /// provided token, and if so, returns the account associated with that token
+    async fn get_account_from_http_auth(
+        &self,
+        username: &Username,
+        token: &str,
+    ) -> Result<Self::Account, HttpStoreError> {
+        if let Some(account) = self.get_account_from_username(username) {
+            if let Some(ref t) = account.ilp_over_http_incoming_token {
+                let t = t.expose_secret();
+                if t.as_ref() == token.as_bytes() {
+                    Ok(account)
+                } else {
+                    Err(HttpStoreError::Unauthorized(username.to_string()))
+                }
+            } else {
+                Err(HttpStoreError::Unauthorized(username.to_string()))
+            }
+        } else {
+            warn!("No account found with given HTTP auth");
+            Err(HttpStoreError::AccountNotFound(username.to_string()))
+        }
+    }
+}
+
+impl RouterStore for MemoryStore {
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+        self.routes.read().clone()
+    }
+
+    fn multipath_routing_table(&self) -> Arc<PrefixMap<Vec<NextHop>>> {
+        self.multipath_routes.read().clone()
+    }
+}
+
+#[async_trait]
+impl NodeStore for MemoryStore {
+    type Account = Account;
+
+    async fn insert_account(
+        &self,
+        account: AccountDetails,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let id = Uuid::new_v4();
+        let account = Account::try_from(id, account, self.get_ilp_address())
+            .map_err(NodeStoreError::InvalidAccount)?;
+        debug!(
+            "Generated account id for {}: {}",
+            account.username, account.id
+        );
+
+        let mut data = self.data.write();
+        // Check that there isn't already an account with values that MUST be unique
+        if data.usernames.contains_key(account.username.as_ref())
+            || (account.routing_relation == RoutingRelation::Parent
+                && data.parent_ilp_address.is_some())
+        {
+            warn!(
+                "An account already exists with the same {}. Cannot insert account: {:?}",
+                account.id, account
+            );
+            return Err(NodeStoreError::AccountExists(account.username.to_string()));
+        }
+
+        data.usernames.insert(account.username.to_string(), id);
+        data.routes.insert(account.ilp_address.to_string(), id);
+        data.accounts.insert(
+            id,
+            AccountEntry {
+                account: account.clone(),
+                balance: 0,
+                prepaid_amount: 0,
+            },
+        );
+        self.update_routes(&data);
+        debug!(
+            "Inserted account {} (ILP address: {})",
+            account.id, account.ilp_address
+        );
+        Ok(account)
+    }
+
+    async fn delete_account(&self, id: Uuid) -> Result<Account, NodeStoreError> {
+        let mut data = self.data.write();
+        let account = data.load_account(&id);
+        let account = account.ok_or_else(|| NodeStoreError::AccountNotFound(id.to_string()))?;
+
+        data.accounts.remove(&id);
+        data.usernames.remove(account.username.as_ref());
+        data.routes.remove(&account.ilp_address.to_string());
+        data.uncredited_amounts.remove(&id);
+        self.update_routes(&data);
+        self.throttle.write().clear_prefix(&format!("limit:{}", id));
+        debug!("Deleted account {}", account.id);
+        Ok(account)
+    }
+
+    async fn update_account(
+        &self,
+        id: Uuid,
+        account: AccountDetails,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let account = Account::try_from(id, account, self.get_ilp_address())
+            .map_err(NodeStoreError::InvalidAccount)?;
+
+        let mut data = self.data.write();
+        let old_account = match data.accounts.get(&id) {
+            Some(entry) => entry.account.clone(),
+            None => {
+                warn!(
+                    "No account exists with ID {}, cannot update account {:?}",
+                    account.id, account
+                );
+                return Err(NodeStoreError::AccountNotFound(account.id.to_string()));
+            }
+        };
+
+        data.usernames.remove(old_account.username.as_ref());
+        data.usernames.insert(account.username.to_string(), id);
+        data.routes.remove(&old_account.ilp_address.to_string());
+        data.routes.insert(account.ilp_address.to_string(), id);
+        data.entry_mut(id)
+            .map_err(|err| NodeStoreError::Other(Box::new(err)))?
+            .account = account.clone();
+        self.update_routes(&data);
+        debug!(
+            "Updated account {} (id: {}, ILP address: {})",
+            account.username, account.id, account.ilp_address
+        );
+        Ok(account)
+    }
+
+    async fn modify_account_settings(
+        &self,
+        id: Uuid,
+        settings: AccountSettings,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let ilp_over_http_url = match settings.ilp_over_http_url {
+            Some(ref url) => Some(Url::parse(url).map_err(|err| {
+                NodeStoreError::InvalidAccount(CreateAccountError::InvalidHttpUrl(err))
+            })?),
+            None => None,
+        };
+        let ilp_over_btp_url = match settings.ilp_over_btp_url {
+            Some(ref url) => Some(Url::parse(url).map_err(|err| {
+                NodeStoreError::InvalidAccount(CreateAccountError::InvalidBtpUrl(err))
+            })?),
+            None => None,
+        };
+        if let Some(settle_to) = settings.settle_to {
+            if settle_to > i64::MAX as u64 {
+                return Err(NodeStoreError::InvalidAccount(
+                    CreateAccountError::ParamTooLarge("settle_to".to_owned()),
+                ));
+            }
+        }
+
+        let mut data = self.data.write();
+        let account = &mut data
+            .accounts
+            .get_mut(&id)
+            .ok_or_else(|| NodeStoreError::AccountNotFound(id.to_string()))?
+            .account;
+
+        if ilp_over_btp_url.is_some() {
+            account.ilp_over_btp_url = ilp_over_btp_url;
+        }
+        if ilp_over_http_url.is_some() {
+            account.ilp_over_http_url = ilp_over_http_url;
+        }
+        if let Some(token) = settings.ilp_over_btp_outgoing_token {
+            account.ilp_over_btp_outgoing_token =
+                Some(SecretBytesMut::new(token.expose_secret().as_str()));
+        }
+        if let Some(token) = settings.ilp_over_http_outgoing_token {
+            account.ilp_over_http_outgoing_token =
+                Some(SecretBytesMut::new(token.expose_secret().as_str()));
+        }
+        if let Some(token) = settings.ilp_over_btp_incoming_token {
+            account.ilp_over_btp_incoming_token =
+                Some(SecretBytesMut::new(token.expose_secret().as_str()));
+        }
+        if let Some(token) = settings.ilp_over_http_incoming_token {
+            account.ilp_over_http_incoming_token =
+                Some(SecretBytesMut::new(token.expose_secret().as_str()));
+        }
+        if let Some(settle_threshold) = settings.settle_threshold {
+            account.settle_threshold = Some(settle_threshold);
+        }
+        if let Some(settle_to) = settings.settle_to {
+            account.settle_to = Some(settle_to as i64);
+        }
+        if let Some(threshold) = settings.circuit_breaker_failure_threshold {
+            account.circuit_breaker_failure_threshold = Some(threshold);
+        }
+        if let Some(cool_down) = settings.circuit_breaker_cool_down {
+            account.circuit_breaker_cool_down = Some(cool_down);
+        }
+
+        // return the updated account
+        data.load_account(&id)
+            .ok_or_else(|| NodeStoreError::AccountNotFound(id.to_string()))
+    }
+
+    async fn get_all_accounts(&self) -> Result<Vec<Self::Account>, NodeStoreError> {
+        Ok(self.data.read().load_accounts_where(|_| true))
+    }
+
+    async fn set_static_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Uuid)> + Send + 'async_trait,
+    {
+        let routes: HashMap<String, Uuid> = routes.into_iter().collect();
+        let mut data = self.data.write();
+        if !routes
+            .values()
+            .all(|account_id| data.accounts.contains_key(account_id))
+        {
+            error!("Error setting static routes because not all of the given accounts exist");
+            return Err(NodeStoreError::MissingAccounts);
+        }
+
+        data.static_routes = routes;
+        data.static_multipath_routes.clear();
+        self.update_routes(&data);
+        Ok(())
+    }
+
+    async fn set_static_multipath_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    {
+        let routes: HashMap<String, Vec<NextHop>> = routes
+            .into_iter()
+            .filter(|(_, next_hops)| !next_hops.is_empty())
+            .collect();
+        let mut data = self.data.write();
+        if !routes
+            .values()
+            .flatten()
+            .all(|next_hop| data.accounts.contains_key(&next_hop.account_id))
+        {
+            error!("Error setting static routes because not all of the given accounts exist");
+            return Err(NodeStoreError::MissingAccounts);
+        }
+
+        data.static_routes = routes
+            .iter()
+            .map(|(prefix, next_hops)| (prefix.clone(), next_hops[0].account_id))
+            .collect();
+        data.static_multipath_routes = routes
+            .into_iter()
+            .filter(|(_, next_hops)| next_hops.len() > 1)
+            .collect();
+        self.update_routes(&data);
+        Ok(())
+    }
+
+    async fn set_static_route(
+        &self,
+        prefix: String,
+        account_id: Uuid,
+    ) -> Result<(), NodeStoreError> {
+        let mut data = self.data.write();
+        if !data.accounts.contains_key(&account_id) {
+            error!(
+                "Cannot set static route for prefix: {} because account {} does not exist",
+                prefix, account_id
+            );
+            return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
+        }
+
+        data.static_multipath_routes.remove(&prefix);
+        data.static_routes.insert(prefix, account_id);
+        self.update_routes(&data);
+        Ok(())
+    }
+
+    async fn set_default_route(&self, account_id: Uuid) -> Result<(), NodeStoreError> {
+        let mut data = self.data.write();
+        if !data.accounts.contains_key(&account_id) {
+            error!(
+                "Cannot set default route because account {} does not exist",
+                account_id
+            );
+            return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
+        }
+
+        data.default_route = Some(account_id);
+        debug!("Set default route to account id: {}", account_id);
+        self.update_routes(&data);
+        Ok(())
+    }
+
+    async fn set_settlement_engines(
+        &self,
+        asset_to_url_map: impl IntoIterator<Item = (String, Url)> + Send + 'async_trait,
+    ) -> Result<(), NodeStoreError> {
+        let asset_to_url_map: Vec<(String, Url)> = asset_to_url_map.into_iter().collect();
+        debug!("Setting settlement engines to {:?}", asset_to_url_map);
+        self.data
+            .write()
+            .settlement_engines
+            .extend(asset_to_url_map);
+        Ok(())
+    }
+
+    async fn get_asset_settlement_engine(
+        &self,
+        asset_code: &str,
+    ) -> Result<Option<Url>, NodeStoreError> {
+        Ok(self.data.read().settlement_engines.get(asset_code).cloned())
+    }
+}
+
+#[async_trait]
+impl AddressStore for MemoryStore {
+    // Updates the ILP address of the store & iterates over all children and
+    // updates their ILP Address to match the new address.
+    async fn set_ilp_address(&self, ilp_address: Address) -> Result<(), AddressStoreError> {
+        debug!("Setting ILP address to: {}", ilp_address);
+        let mut data = self.data.write();
+
+        // Set the ILP address we have in memory
+        (*self.ilp_address.write()) = ilp_address.clone();
+        data.parent_ilp_address = Some(ilp_address.clone());
+
+        let first_segment = ilp_address
+            .segments()
+            .next_back()
+            .expect("address did not have a first segment, this should be impossible");
+        let mut updated_routes = Vec::new();
+        for entry in data.accounts.values_mut() {
+            let account = &mut entry.account;
+            // Update the address and routes of all children and non-routing accounts.
+            if account.routing_relation() != RoutingRelation::Parent
+                && account.routing_relation() != RoutingRelation::Peer
+            {
+                // if the username of the account ends with the
+                // node's address, we're already configured so no
+                // need to append anything.
+                let new_ilp_address = if first_segment == account.username().to_string() {
+                    ilp_address.clone()
+                } else {
+                    ilp_address
+                        .with_suffix(account.username().as_bytes())
+                        .unwrap()
+                };
+                let old_ilp_address =
+                    std::mem::replace(&mut account.ilp_address, new_ilp_address.clone());
+                updated_routes.push((old_ilp_address, new_ilp_address, account.id));
+            }
+        }
+
+        for (old_ilp_address, new_ilp_address, id) in updated_routes {
+            data.routes.remove(&old_ilp_address.to_string());
+            data.routes.insert(new_ilp_address.to_string(), id);
+        }
+        self.update_routes(&data);
+        Ok(())
+    }
+
+    async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+        self.data.write().parent_ilp_address = None;
+
+        // overwrite the ilp address with the default value
+        *(self.ilp_address.write()) = DEFAULT_ILP_ADDRESS.clone();
+        Ok(())
+    }
+
+    fn get_ilp_address(&self) -> Address {
+        // read consumes the Arc<RwLock<T>> so we cannot return a reference
+        self.ilp_address.read().clone()
+    }
+}
+
+type RoutingTable<A> = HashMap<String, A>;
+
+#[async_trait]
+impl CcpRoutingStore for MemoryStore {
+    type Account = Account;
+
+    async fn get_accounts_to_send_routes_to(
+        &self,
+        ignore_accounts: Vec<Uuid>,
+    ) -> Result<Vec<Account>, CcpRoutingStoreError> {
+        Ok(self.data.read().load_accounts_where(|account| {
+            account.should_send_routes() && !ignore_accounts.contains(&account.id)
+        }))
+    }
+
+    async fn get_accounts_to_receive_routes_from(
+        &self,
+    ) -> Result<Vec<Account>, CcpRoutingStoreError> {
+        Ok(self
+            .data
+            .read()
+            .load_accounts_where(|account| account.should_receive_routes()))
+    }
+
+    async fn get_local_and_configured_routes(
+        &self,
+    ) -> Result<(RoutingTable<Account>, RoutingTable<Account>), CcpRoutingStoreError> {
+        let data = self.data.read();
+        let accounts = data.load_accounts_where(|_| true);
+
+        let local_table = accounts
+            .iter()
+            .map(|account| (account.ilp_address.to_string(), account.clone()))
+            .collect();
+
+        let configured_table = data
+            .static_routes
+            .iter()
+            .filter_map(|(prefix, account_id)| {
+                if let Some(account) = data.load_account(account_id) {
+                    Some((prefix.clone(), account))
+                } else {
+                    warn!(
+                        "No account for ID: {}, ignoring configured route for prefix: {}",
+                        account_id, prefix
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        Ok((local_table, configured_table))
+    }
+
+    async fn set_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Account)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let routes: HashMap<String, Uuid> = routes
+            .into_iter()
+            .map(|(prefix, account)| (prefix, account.id))
+            .collect();
+        let num_routes = routes.len();
+
+        let mut data = self.data.write();
+        data.routes = routes;
+        trace!("Saved {} routes to the store", num_routes);
+        self.update_routes(&data);
+        Ok(())
+    }
+
+    async fn set_multipath_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let mut data = self.data.write();
+        data.multipath_routes = routes.into_iter().collect();
+        trace!(
+            "Saved {} routes with several next hops to the store",
+            data.multipath_routes.len()
+        );
+        self.update_routes(&data);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    type Account = Account;
+
+    /// Apply rate limits for number of packets and amount of money per minute,
+    /// and the daily and monthly volume caps
+    ///
+    /// This uses the same algorithm as [redis-cell](https://github.com/brandur/redis-cell)
+    /// which is used by the RedisStore
+    async fn apply_rate_limits(
+        &self,
+        account: Account,
+        prepare_amount: u64,
+    ) -> Result<(), RateLimitError> {
+        self.throttle
+            .write()
+            .apply_rate_limits(&account, prepare_amount)
+    }
+
+    async fn refund_throughput_limit(
+        &self,
+        account: Account,
+        prepare_amount: u64,
+    ) -> Result<(), RateLimitError> {
+        self.throttle
+            .write()
+            .refund_throughput_limit(&account, prepare_amount);
+        Ok(())
+    }
+
+    async fn reconcile_rate_limits(
+        &self,
+        account: Account,
+        packets: u64,
+        amount: u64,
+    ) -> Result<RemainingRateLimits, RateLimitError> {
+        Ok(self
+            .throttle
+            .write()
+            .reconcile_rate_limits(&account, packets, amount))
+    }
+}
+
+#[async_trait]
+impl IdempotentStore for MemoryStore {
+    async fn load_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Result<Option<IdempotentData>, IdempotentStoreError> {
+        let data = self.data.read();
+        match data.idempotent_data.get(&idempotency_key) {
+            Some((idempotent_data, saved_at)) if saved_at.elapsed() < IDEMPOTENCY_KEY_TTL => {
+                trace!(
+                    "Loaded idempotency key {:?} - {:?}",
+                    idempotency_key,
+                    idempotent_data
+                );
+                Ok(Some(idempotent_data.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn save_idempotent_data(
+        &self,
+        idempotency_key: String,
+        input_hash: [u8; 32],
+        status_code: StatusCode,
+        data: Bytes,
+    ) -> Result<(), IdempotentStoreError> {
+        trace!(
+            "Cached {:?}: {:?}, {:?}",
+            idempotency_key,
+            status_code,
+            data,
+        );
+        let mut store_data = self.data.write();
+        store_data
+            .idempotent_data
+            .retain(|_, (_, saved_at)| saved_at.elapsed() < IDEMPOTENCY_KEY_TTL);
+        store_data.idempotent_data.insert(
+            idempotency_key,
+            (
+                IdempotentData::new(status_code, data, input_hash),
+                Instant::now(),
+            ),
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SettlementStore for MemoryStore {
+    type Account = Account;
+
+    async fn update_balance_for_incoming_settlement(
+        &self,
+        account_id: Uuid,
+        amount: u64,
+        idempotency_key: Option<String>,
+    ) -> Result<(), SettlementStoreError> {
+        let mut data = self.data.write();
+        data.settlement_idempotency_keys
+            .retain(|_, saved_at| saved_at.elapsed() < IDEMPOTENCY_KEY_TTL);
+        // If idempotency key has been used, then do not perform any operations
+        if let Some(idempotency_key) = idempotency_key {
+            if data
+                .settlement_idempotency_keys
+                .insert(idempotency_key, Instant::now())
+                .is_some()
+            {
+                return Ok(());
+            }
+        }
+
+        let entry = data
+            .entry_mut(account_id)
+            .map_err(|err| SettlementStoreError::Other(Box::new(err)))?;
+        let amount = amount as i64;
+        // Credit the incoming settlement to the balance and/or prepaid amount,
+        // depending on whether that account currently owes money or not
+        if entry.balance >= 0 {
+            entry.prepaid_amount += amount;
+        } else if entry.balance.abs() >= amount {
+            entry.balance += amount;
+        } else {
+            entry.prepaid_amount += amount + entry.balance;
+            entry.balance = 0;
+        }
+
+        trace!(
+            "Processed incoming settlement from account: {} for amount: {}. Balance is now: {}",
+            account_id,
+            amount,
+            entry.balance + entry.prepaid_amount
+        );
+        Ok(())
+    }
+
+    async fn refund_settlement(
+        &self,
+        account_id: Uuid,
+        settle_amount: u64,
+    ) -> Result<(), SettlementStoreError> {
+        trace!(
+            "Refunding settlement for account: {} of amount: {}",
+            account_id,
+            settle_amount
+        );
+        let mut data = self.data.write();
+        let entry = data
+            .entry_mut(account_id)
+            .map_err(|err| SettlementStoreError::Other(Box::new(err)))?;
+        entry.balance += settle_amount as i64;
+
+        trace!(
+            "Refunded settlement for account: {} of amount: {}. Balance is now: {}",
+            account_id,
+            settle_amount,
+            entry.balance
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SettlementOutboxStore for MemoryStore {
+    async fn save_outgoing_settlement(
+        &self,
+        settlement: OutgoingSettlement,
+    ) -> Result<(), SettlementStoreError> {
+        self.data
+            .write()
+            .outgoing_settlements
+            .insert(settlement.idempotency_key.clone(), settlement);
+        Ok(())
+    }
+
+    async fn complete_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError> {
+        self.data
+            .write()
+            .outgoing_settlements
+            .remove(idempotency_key);
+        Ok(())
+    }
+
+    async fn refund_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError> {
+        let mut data = self.data.write();
+        let settlement = match data.outgoing_settlements.remove(idempotency_key) {
+            Some(settlement) => settlement,
+            // It was already completed or refunded
+            None => return Ok(()),
+        };
+        let entry = data
+            .entry_mut(settlement.account_id)
+            .map_err(|err| SettlementStoreError::Other(Box::new(err)))?;
+        entry.balance += settlement.amount as i64;
+        trace!(
+            "Refunded outgoing settlement {} for account: {} of amount: {}. Balance is now: {}",
+            idempotency_key,
+            settlement.account_id,
+            settlement.amount,
+            entry.balance
+        );
+        Ok(())
+    }
+
+    async fn get_pending_outgoing_settlements(
+        &self,
+    ) -> Result<Vec<OutgoingSettlement>, SettlementStoreError> {
+        Ok(self
+            .data
+            .read()
+            .outgoing_settlements
+            .values()
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl LeftoversStore for MemoryStore {
+    type AccountId = Uuid;
+    type AssetType = BigUint;
+
+    async fn get_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+    ) -> Result<(Self::AssetType, u8), LeftoversStoreError> {
+        // get the amounts and instantly delete them
+        let amounts = self
+            .data
+            .write()
+            .uncredited_amounts
+            .remove(&account_id)
+            .unwrap_or_default();
+
+        // We must scale them to the largest scale, and then add them together
+        let max_scale = amounts.iter().map(|(_, scale)| *scale).max().unwrap_or(0);
+        let mut sum = BigUint::from(0u32);
+        for (num, scale) in amounts {
+            sum += num
+                .normalize_scale(ConvertDetails {
+                    from: scale,
+                    to: max_scale,
+                })
+                .unwrap();
+        }
+        Ok((sum, max_scale))
+    }
+
+    async fn save_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+        uncredited_settlement_amount: (Self::AssetType, u8),
+    ) -> Result<(), LeftoversStoreError> {
+        trace!(
+            "Saving uncredited_settlement_amount {:?} {:?}",
+            account_id,
+            uncredited_settlement_amount
+        );
+        self.data
+            .write()
+            .uncredited_amounts
+            .entry(account_id)
+            .or_default()
+            .push(uncredited_settlement_amount);
+        Ok(())
+    }
+
+    async fn load_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+        local_scale: u8,
+    ) -> Result<Self::AssetType, LeftoversStoreError> {
+        trace!("Loading uncredited_settlement_amount {:?}", account_id);
+        let amount = self.get_uncredited_settlement_amount(account_id).await?;
+        // scale the amount from the max scale to the local scale, and then
+        // save any potential leftovers to the store
+        let (scaled_amount, precision_loss) =
+            scale_with_precision_loss(amount.0, local_scale, amount.1);
+
+        if precision_loss > BigUint::from(0u32) {
+            self.save_uncredited_settlement_amount(
+                account_id,
+                (precision_loss, std::cmp::max(local_scale, amount.1)),
+            )
+            .await?;
+        }
+
+        Ok(scaled_amount)
+    }
+
+    async fn clear_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+    ) -> Result<(), LeftoversStoreError> {
+        trace!("Clearing uncredited_settlement_amount {:?}", account_id);
+        self.data.write().uncredited_amounts.remove(&account_id);
+        Ok(())
+    }
+}
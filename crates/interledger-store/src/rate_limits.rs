@@ -0,0 +1,67 @@
+use crate::account::Account;
+use chrono::Utc;
+
+/// The arguments of a limit of the Generic Cell Rate Algorithm, as taken by
+/// the `CL.THROTTLE` command of [redis-cell](https://github.com/brandur/redis-cell).
+/// The largest burst allowed is one unit more than `max_burst`
+pub(crate) struct Limit {
+    pub(crate) max_burst: u64,
+    pub(crate) count_per_minute: u64,
+}
+
+impl Limit {
+    fn new(per_minute: u64, burst: Option<u64>) -> Self {
+        Limit {
+            max_burst: burst.unwrap_or(per_minute).saturating_sub(1),
+            count_per_minute: per_minute.saturating_sub(1),
+        }
+    }
+}
+
+/// The limit of the number of packets the account can send
+pub(crate) fn packets_limit(account: &Account) -> Option<Limit> {
+    account
+        .packets_per_minute_limit
+        .map(|limit| Limit::new(u64::from(limit), account.packets_burst_limit.map(u64::from)))
+}
+
+/// The limit of the amount the account can send
+pub(crate) fn throughput_limit(account: &Account) -> Option<Limit> {
+    account
+        .amount_per_minute_limit
+        .map(|limit| Limit::new(limit, account.amount_burst_limit))
+}
+
+/// A cap of the volume the account can send in the current calendar day or month (in UTC)
+pub(crate) struct VolumeCap {
+    /// `day` or `month`
+    pub(crate) name: &'static str,
+    /// The current day or month, such as `2020-01-31` or `2020-01`
+    pub(crate) period: String,
+    pub(crate) limit: u64,
+    /// Seconds after which the volume of the period no longer matters
+    pub(crate) expires_after: u64,
+}
+
+/// The daily and monthly volume caps of the account
+pub(crate) fn volume_caps(account: &Account) -> Vec<VolumeCap> {
+    let now = Utc::now();
+    let mut caps = Vec::new();
+    if let Some(limit) = account.amount_per_day_limit {
+        caps.push(VolumeCap {
+            name: "day",
+            period: now.format("%Y-%m-%d").to_string(),
+            limit,
+            expires_after: 2 * 24 * 60 * 60,
+        });
+    }
+    if let Some(limit) = account.amount_per_month_limit {
+        caps.push(VolumeCap {
+            name: "month",
+            period: now.format("%Y-%m").to_string(),
+            limit,
+            expires_after: 32 * 24 * 60 * 60,
+        });
+    }
+    caps
+}
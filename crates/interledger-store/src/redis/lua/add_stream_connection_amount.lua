@@ -0,0 +1,19 @@
+local connection_tag = ARGV[1]
+local connection_key = 'stream_connections:' .. connection_tag
+local amount = tonumber(ARGV[2])
+local now = ARGV[3]
+local total_received, receive_max, closed = unpack(redis.call('HMGET', connection_key, 'total_received', 'receive_max', 'closed'))
+
+-- Leave the connection unchanged if it is closed or the amount would bring it over its receive max
+if closed == '1' then
+    return nil
+end
+if receive_max and (tonumber(total_received) or 0) + amount > tonumber(receive_max) then
+    return nil
+end
+
+redis.call('HINCRBY', connection_key, 'total_received', ARGV[2])
+redis.call('HSET', connection_key, 'last_activity', now)
+redis.call('ZADD', 'stream_connections_by_activity', now, connection_tag)
+
+return redis.call('HGETALL', connection_key)
@@ -0,0 +1,7 @@
+local connection_key = 'stream_connections:' .. ARGV[1]
+
+-- Only mark connections which are tracked, so we don't leave behind a
+-- hash which would never be deleted as idle
+if redis.call('EXISTS', connection_key) == 1 then
+    redis.call('HSET', connection_key, 'closed', 1)
+end
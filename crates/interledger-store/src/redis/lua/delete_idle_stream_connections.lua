@@ -0,0 +1,9 @@
+local cutoff = ARGV[1]
+local connection_tags = redis.call('ZRANGEBYSCORE', 'stream_connections_by_activity', '-inf', cutoff)
+
+for _, connection_tag in ipairs(connection_tags) do
+    redis.call('DEL', 'stream_connections:' .. connection_tag)
+end
+redis.call('ZREMRANGEBYSCORE', 'stream_connections_by_activity', '-inf', cutoff)
+
+return #connection_tags
@@ -17,6 +17,12 @@ if (settle_threshold and settle_to) and (balance >= tonumber(settle_threshold))
     -- the balance change by re-adding the amount back to the balance
     balance = settle_to
     redis.call('HSET', to_account, 'balance', balance)
+
+    -- If an outgoing settlement was provided, it is saved along with the balance change
+    -- so that it cannot be lost. Its JSON is passed without the amount, which ends it
+    if ARGV[3] then
+        redis.call('HSET', 'outgoing_settlements', ARGV[3], ARGV[4] .. string.format('%d', settle_amount) .. '}')
+    end
 end
 
 return {balance + prepaid_amount, settle_amount}
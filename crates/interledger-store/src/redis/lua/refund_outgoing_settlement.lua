@@ -0,0 +1,8 @@
+local idempotency_key = ARGV[1]
+local account = 'accounts:' .. ARGV[2]
+local settle_amount = tonumber(ARGV[3])
+
+-- Only refund the settlement if it was not already completed or refunded
+if redis.call('HDEL', 'outgoing_settlements', idempotency_key) == 1 then
+    return redis.call('HINCRBY', account, 'balance', settle_amount)
+end
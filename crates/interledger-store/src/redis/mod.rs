@@ -5,7 +5,17 @@
 //   rates:current          hash        exchange rates
 //   routes:current         hash        dynamic routing table
 //   routes:static          hash        static routing table
+//   routes:multipath       hash        next hops (JSON) of the prefixes with several CCP routes
+//   routes:static:multipath hash       next hops (JSON) of the static routes with several next hops
 //   accounts:<id>          hash        information for each account
+//   stream_connections:<tag> hash      running totals of each STREAM connection tracked by the receiver
+//   stream_connections_by_activity zset  tags of the tracked STREAM connections, scored by last activity
+//   outgoing_settlements   hash        settlements (JSON) sent to the engines which were not completed or refunded yet
+//   payments:<id>          zset        payments (JSON) sent and received by each account, scored by timestamp
+//   api_tokens             hash        API tokens (JSON) issued by the node's administrator, keyed by their hash
+//   audit_log              zset        administrative actions (JSON) taken through the API, scored by timestamp
+//   fee_schedules          string      fee schedules (JSON) charged on the packets
+//   fee_revenue            hash        fees earned on the packets received from each account
 //   btp_outgoing
 // For interactive exploration of the store,
 // use the redis-cli tool included with your redis install.
@@ -19,29 +29,41 @@ use reconnect::RedisReconnect;
 
 use super::account::{Account, AccountWithEncryptedTokens};
 use super::crypto::{encrypt_token, generate_keys, DecryptionKey, EncryptionKey};
+use super::rate_limits::{packets_limit, throughput_limit, volume_caps, Limit, VolumeCap};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use futures::channel::mpsc::UnboundedSender;
 use http::StatusCode;
-use interledger_api::{AccountDetails, AccountSettings, EncryptedAccountSettings, NodeStore};
+use interledger_api::{
+    AccountDetails, AccountSettings, ApiToken, ApiTokenStore, AuditLogEntry, AuditLogStore,
+    AuditQuery, EncryptedAccountSettings, NodeStore,
+};
 use interledger_btp::BtpStore;
 use interledger_ccp::{CcpRoutingAccount, CcpRoutingStore, RoutingRelation};
 use interledger_errors::*;
 use interledger_http::HttpStore;
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, PrefixMap, RouterStore};
 use interledger_service::{Account as AccountTrait, AccountStore, AddressStore, Username};
 use interledger_service_util::{
-    BalanceStore, RateLimitError, RateLimitStore, DEFAULT_ROUND_TRIP_TIME,
+    BalanceStore, FeeSchedules, FeeStore, RateLimitError, RateLimitStore, RemainingRateLimits,
+    DEFAULT_ROUND_TRIP_TIME,
 };
 use interledger_settlement::core::{
     idempotency::{IdempotentData, IdempotentStore},
     scale_with_precision_loss,
-    types::{Convert, ConvertDetails, LeftoversStore, SettlementStore},
+    types::{
+        Convert, ConvertDetails, LeftoversStore, OutgoingSettlement, SettlementOutboxStore,
+        SettlementStore,
+    },
+};
+use interledger_stream::{
+    unix_timestamp, PaymentHistoryStore, PaymentNotification, PaymentQuery, PaymentRecord,
+    StreamConnectionDetails, StreamConnectionStore, StreamNotificationsStore,
 };
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
 use num_bigint::BigUint;
+use num_rational::BigRational;
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 use redis_crate::AsyncCommands;
@@ -68,14 +90,22 @@ use uuid::Uuid;
 use zeroize::Zeroize;
 
 const DEFAULT_POLL_INTERVAL: u64 = 30000; // 30 seconds
-const ACCOUNT_DETAILS_FIELDS: usize = 21;
+const ACCOUNT_DETAILS_FIELDS: usize = 27;
 
 static PARENT_ILP_KEY: &str = "parent_node_account_address";
 static ROUTES_KEY: &str = "routes:current";
 static STATIC_ROUTES_KEY: &str = "routes:static";
+static MULTIPATH_ROUTES_KEY: &str = "routes:multipath";
+static STATIC_MULTIPATH_ROUTES_KEY: &str = "routes:static:multipath";
 static DEFAULT_ROUTE_KEY: &str = "routes:default";
 static STREAM_NOTIFICATIONS_PREFIX: &str = "stream_notifications:";
 static SETTLEMENT_ENGINES_KEY: &str = "settlement_engines";
+static STREAM_CONNECTIONS_BY_ACTIVITY_KEY: &str = "stream_connections_by_activity";
+static OUTGOING_SETTLEMENTS_KEY: &str = "outgoing_settlements";
+static API_TOKENS_KEY: &str = "api_tokens";
+static AUDIT_LOG_KEY: &str = "audit_log";
+static FEE_SCHEDULES_KEY: &str = "fee_schedules";
+static FEE_REVENUE_KEY: &str = "fee_revenue";
 
 /// Domain separator for leftover amounts
 fn uncredited_amount_key(account_id: impl ToString) -> String {
@@ -92,6 +122,30 @@ fn accounts_key(account_id: Uuid) -> String {
     format!("accounts:{}", account_id)
 }
 
+/// Domain separator for STREAM connections
+fn stream_connection_key(connection_tag: &str) -> String {
+    format!("stream_connections:{}", connection_tag)
+}
+
+/// Domain separator for the payments of an account
+fn payments_key(account_id: Uuid) -> String {
+    format!("payments:{}", account_id)
+}
+
+/// Reads a STREAM connection from its hash, which is empty if the connection is not tracked
+fn stream_connection_from_hash(hash: HashMap<String, String>) -> Option<StreamConnectionDetails> {
+    if hash.is_empty() {
+        return None;
+    }
+    let parse = |field: &str| hash.get(field).and_then(|value| value.parse::<u64>().ok());
+    Some(StreamConnectionDetails {
+        total_received: parse("total_received").unwrap_or_default(),
+        receive_max: parse("receive_max"),
+        closed: parse("closed") == Some(1),
+        last_activity: parse("last_activity").unwrap_or_default(),
+    })
+}
+
 // TODO: Add descriptive errors inside the lua scripts!
 
 // The following are Lua scripts that are used to atomically execute the given logic
@@ -131,10 +185,28 @@ static PROCESS_REJECT: Lazy<Script> =
 static REFUND_SETTLEMENT: Lazy<Script> =
     Lazy::new(|| Script::new(include_str!("lua/refund_settlement.lua")));
 
+/// Lua script which deletes an outgoing settlement and increases the provided account's balance
+/// by its amount, unless it was already completed or refunded
+static REFUND_OUTGOING_SETTLEMENT: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("lua/refund_outgoing_settlement.lua")));
+
 /// Lua script which increases the provided account's balance after an incoming settlement succeeded
 static PROCESS_INCOMING_SETTLEMENT: Lazy<Script> =
     Lazy::new(|| Script::new(include_str!("lua/process_incoming_settlement.lua")));
 
+/// Lua script which adds an amount to a STREAM connection's total received, unless it is closed
+/// or the amount would bring it over its receive max
+static ADD_STREAM_CONNECTION_AMOUNT: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("lua/add_stream_connection_amount.lua")));
+
+/// Lua script which marks a tracked STREAM connection as closed
+static CLOSE_STREAM_CONNECTION: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("lua/close_stream_connection.lua")));
+
+/// Lua script which deletes the STREAM connections whose last activity is older than the given timestamp
+static DELETE_IDLE_STREAM_CONNECTIONS: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("lua/delete_idle_stream_connections.lua")));
+
 /// Builder for the Redis Store
 pub struct RedisStoreBuilder {
     redis_url: ConnectionInfo,
@@ -173,7 +245,7 @@ impl RedisStoreBuilder {
     /// 1. Generates encryption and decryption keys
     /// 1. Connects to the redis store (ensuring that it reconnects in case of drop)
     /// 1. Gets the Node address assigned to us by our parent (if it exists)
-    /// 1. Starts polling for routing table updates
+    /// 1. Starts polling for routing table and fee schedule updates
     /// 1. Spawns a thread to notify incoming payments over WebSockets
     pub async fn connect(&mut self) -> Result<RedisStore, ()> {
         let redis_info = self.redis_url.clone();
@@ -215,16 +287,20 @@ impl RedisStoreBuilder {
             connection,
             subscriptions: Arc::new(RwLock::new(HashMap::new())),
             exchange_rates: Arc::new(RwLock::new(HashMap::new())),
-            routes: Arc::new(RwLock::new(Arc::new(HashMap::new()))),
+            routes: Arc::new(RwLock::new(Arc::new(PrefixMap::new()))),
+            multipath_routes: Arc::new(RwLock::new(Arc::new(PrefixMap::new()))),
+            fee_schedules: Arc::new(RwLock::new(Arc::new(FeeSchedules::default()))),
             encryption_key: Arc::new(encryption_key),
             decryption_key: Arc::new(decryption_key),
         };
 
-        // Poll for routing table updates
+        // Poll for routing table and fee schedule updates
         // Note: if this behavior changes, make sure to update the Drop implementation
         let connection_clone = Arc::downgrade(&store.connection.conn);
         let redis_info = store.connection.redis_info.clone();
         let routing_table = store.routes.clone();
+        let multipath_routing_table = store.multipath_routes.clone();
+        let fee_schedules = store.fee_schedules.clone();
 
         let poll_routes = async move {
             let mut interval = tokio::time::interval(Duration::from_millis(poll_interval));
@@ -232,15 +308,20 @@ impl RedisStoreBuilder {
             loop {
                 interval.tick().await;
                 if let Some(conn) = connection_clone.upgrade() {
+                    let connection = RedisReconnect {
+                        conn,
+                        redis_info: redis_info.clone(),
+                    };
                     let _ = update_routes(
-                        RedisReconnect {
-                            conn,
-                            redis_info: redis_info.clone(),
-                        },
+                        connection.clone(),
                         routing_table.clone(),
+                        multipath_routing_table.clone(),
                     )
                     .map_err(|err| error!("{}", err))
                     .await;
+                    let _ = update_fee_schedules(connection, fee_schedules.clone())
+                        .map_err(|err| error!("Error polling for fee schedule updates: {}", err))
+                        .await;
                 } else {
                     debug!("Not polling routes anymore because connection was closed");
                     break;
@@ -310,14 +391,19 @@ pub struct RedisStore {
     connection: RedisReconnect,
     /// WebSocket sender which publishes incoming payment updates
     subscriptions: Arc<RwLock<HashMap<Uuid, UnboundedSender<PaymentNotification>>>>,
-    exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
+    exchange_rates: Arc<RwLock<HashMap<String, BigRational>>>,
     /// The store keeps the routing table in memory so that it can be returned
     /// synchronously while the Router is processing packets.
     /// The outer `Arc<RwLock>` is used so that we can update the stored routing
     /// table after polling the store for updates.
-    /// The inner `Arc<HashMap>` is used so that the `routing_table` method can
+    /// The inner `Arc<PrefixMap>` is used so that the `routing_table` method can
     /// return a reference to the routing table without cloning the underlying data.
-    routes: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    routes: Arc<RwLock<Arc<PrefixMap<Uuid>>>>,
+    /// The next hops of the prefixes which have several of them, kept in memory
+    /// for the same reasons as the routing table
+    multipath_routes: Arc<RwLock<Arc<PrefixMap<Vec<NextHop>>>>>,
+    /// The fee schedules, kept in memory for the same reasons as the routing table
+    fee_schedules: Arc<RwLock<Arc<FeeSchedules>>>,
     /// Encryption Key so that the no cleartext data are stored
     encryption_key: Arc<Secret<EncryptionKey>>,
     /// Decryption Key to provide cleartext data to users
@@ -405,9 +491,9 @@ impl RedisStore {
 
         // The parent account settings are done via the API. We just
         // had to check for the existence of a parent
-        pipe.query_async(&mut connection).await?;
+        pipe.query_async::<_, ()>(&mut connection).await?;
 
-        update_routes(connection, routing_table).await?;
+        update_routes(connection, routing_table, self.multipath_routes.clone()).await?;
         debug!(
             "Inserted account {} (ILP address: {})",
             account.id, account.ilp_address
@@ -475,8 +561,8 @@ impl RedisStore {
         )
         .ignore();
 
-        pipe.query_async(&mut connection).await?;
-        update_routes(connection, routing_table).await?;
+        pipe.query_async::<_, ()>(&mut connection).await?;
+        update_routes(connection, routing_table, self.multipath_routes.clone()).await?;
         debug!(
             "Inserted account {} (id: {}, ILP address: {})",
             account.username, account.id, account.ilp_address
@@ -548,7 +634,20 @@ impl RedisStore {
             pipe.hset(accounts_key(id), "settle_to", settle_to);
         }
 
-        pipe.query_async(&mut self.connection.clone()).await?;
+        if let Some(threshold) = settings.circuit_breaker_failure_threshold {
+            pipe.hset(
+                accounts_key(id),
+                "circuit_breaker_failure_threshold",
+                threshold,
+            );
+        }
+
+        if let Some(cool_down) = settings.circuit_breaker_cool_down {
+            pipe.hset(accounts_key(id), "circuit_breaker_cool_down", cool_down);
+        }
+
+        pipe.query_async::<_, ()>(&mut self.connection.clone())
+            .await?;
 
         // return the updated account
         self.redis_get_account(id).await
@@ -605,8 +704,13 @@ impl RedisStore {
         pipe.del(uncredited_amount_key(id));
 
         let mut connection = self.connection.clone();
-        pipe.query_async(&mut connection).await?;
-        update_routes(connection, self.routes.clone()).await?;
+        pipe.query_async::<_, ()>(&mut connection).await?;
+        update_routes(
+            connection,
+            self.routes.clone(),
+            self.multipath_routes.clone(),
+        )
+        .await?;
         debug!("Deleted account {}", account.id);
         Ok(encrypted)
     }
@@ -704,7 +808,7 @@ impl StreamNotificationsStore for RedisStore {
             redis_crate::cmd("PUBLISH")
                 .arg(published_args)
                 .arg(message)
-                .query_async(&mut connection)
+                .query_async::<_, ()>(&mut connection)
                 .map_err(move |err| error!("Error publish message to Redis: {:?}", err))
                 .await?;
 
@@ -713,6 +817,300 @@ impl StreamNotificationsStore for RedisStore {
     }
 }
 
+#[async_trait]
+impl StreamConnectionStore for RedisStore {
+    async fn create_stream_connection(
+        &self,
+        connection_tag: &str,
+        receive_max: Option<u64>,
+    ) -> Result<(), StreamConnectionStoreError> {
+        let key = stream_connection_key(connection_tag);
+        let now = unix_timestamp();
+        let mut pipe = redis_crate::pipe();
+        pipe.atomic();
+        pipe.del(&key).ignore();
+        pipe.hset_multiple(
+            &key,
+            &[("total_received", 0), ("closed", 0), ("last_activity", now)],
+        )
+        .ignore();
+        if let Some(receive_max) = receive_max {
+            pipe.hset(&key, "receive_max", receive_max).ignore();
+        }
+        pipe.zadd(STREAM_CONNECTIONS_BY_ACTIVITY_KEY, connection_tag, now)
+            .ignore();
+        pipe.query_async::<_, ()>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn get_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        let hash: HashMap<String, String> = self
+            .connection
+            .clone()
+            .hgetall(stream_connection_key(connection_tag))
+            .await?;
+        Ok(stream_connection_from_hash(hash))
+    }
+
+    async fn add_stream_connection_amount(
+        &self,
+        connection_tag: &str,
+        amount: u64,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        let hash: Option<HashMap<String, String>> = ADD_STREAM_CONNECTION_AMOUNT
+            .arg(connection_tag)
+            .arg(amount)
+            .arg(unix_timestamp())
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        if hash.is_none() {
+            debug!(
+                "Not adding {} to STREAM connection {} which is closed or would exceed its receive max",
+                amount, connection_tag
+            );
+        }
+        Ok(hash.and_then(stream_connection_from_hash))
+    }
+
+    async fn close_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<(), StreamConnectionStoreError> {
+        CLOSE_STREAM_CONNECTION
+            .arg(connection_tag)
+            .invoke_async::<_, ()>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_idle_stream_connections(
+        &self,
+        idle_timeout: Duration,
+    ) -> Result<(), StreamConnectionStoreError> {
+        let deleted: u64 = DELETE_IDLE_STREAM_CONNECTIONS
+            .arg(unix_timestamp().saturating_sub(idle_timeout.as_secs()))
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        trace!("Deleted {} idle STREAM connections", deleted);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PaymentHistoryStore for RedisStore {
+    async fn save_payment(&self, payment: PaymentRecord) -> Result<(), PaymentHistoryStoreError> {
+        let json = serde_json::to_string(&payment)
+            .map_err(|err| PaymentHistoryStoreError::Other(Box::new(err)))?;
+        let _: () = self
+            .connection
+            .clone()
+            .zadd(payments_key(payment.account_id), json, payment.timestamp)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_payments(
+        &self,
+        account_id: Uuid,
+        query: PaymentQuery,
+    ) -> Result<Vec<PaymentRecord>, PaymentHistoryStoreError> {
+        let key = payments_key(account_id);
+        let max = query
+            .until
+            .map_or_else(|| "+inf".to_string(), |until| until.to_string());
+        let min = query
+            .since
+            .map_or_else(|| "-inf".to_string(), |since| since.to_string());
+        let mut connection = self.connection.clone();
+        // Redis can only paginate by itself when filtering on the timestamp
+        let paginated = query.direction.is_none() && query.status.is_none();
+        let payments: Vec<String> = if paginated {
+            connection
+                .zrevrangebyscore_limit(
+                    &key,
+                    max,
+                    min,
+                    query.offset as isize,
+                    query.limit() as isize,
+                )
+                .await?
+        } else {
+            connection.zrevrangebyscore(&key, max, min).await?
+        };
+        let payments = payments
+            .iter()
+            .map(|json| {
+                serde_json::from_str::<PaymentRecord>(json)
+                    .map_err(|err| PaymentHistoryStoreError::Other(Box::new(err)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if paginated {
+            return Ok(payments);
+        }
+        Ok(payments
+            .into_iter()
+            .filter(|payment| query.matches(payment))
+            .skip(query.offset)
+            .take(query.limit())
+            .collect())
+    }
+}
+
+/// Reads an API token from its JSON
+fn api_token_from_json(json: &str) -> Result<ApiToken, ApiTokenStoreError> {
+    serde_json::from_str(json).map_err(|err| ApiTokenStoreError::Other(Box::new(err)))
+}
+
+#[async_trait]
+impl ApiTokenStore for RedisStore {
+    async fn insert_api_token(
+        &self,
+        token: ApiToken,
+        token_hash: [u8; 32],
+    ) -> Result<(), ApiTokenStoreError> {
+        let json = serde_json::to_string(&token)
+            .map_err(|err| ApiTokenStoreError::Other(Box::new(err)))?;
+        let _: () = self
+            .connection
+            .clone()
+            .hset(API_TOKENS_KEY, &token_hash[..], json)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_api_token(
+        &self,
+        token_hash: [u8; 32],
+    ) -> Result<Option<ApiToken>, ApiTokenStoreError> {
+        let json: Option<String> = self
+            .connection
+            .clone()
+            .hget(API_TOKENS_KEY, &token_hash[..])
+            .await?;
+        json.as_deref().map(api_token_from_json).transpose()
+    }
+
+    async fn get_all_api_tokens(&self) -> Result<Vec<ApiToken>, ApiTokenStoreError> {
+        let tokens: Vec<String> = self.connection.clone().hvals(API_TOKENS_KEY).await?;
+        let mut tokens = tokens
+            .iter()
+            .map(|json| api_token_from_json(json))
+            .collect::<Result<Vec<_>, _>>()?;
+        tokens.sort_by_key(|token| (token.created_at, token.id));
+        Ok(tokens)
+    }
+
+    async fn delete_api_token(&self, id: Uuid) -> Result<ApiToken, ApiTokenStoreError> {
+        let mut connection = self.connection.clone();
+        // Tokens are looked up by their hash, so revoking one means finding it among all of them
+        let tokens: HashMap<Vec<u8>, String> = connection.hgetall(API_TOKENS_KEY).await?;
+        for (token_hash, json) in tokens {
+            let token = api_token_from_json(&json)?;
+            if token.id == id {
+                let _: () = connection.hdel(API_TOKENS_KEY, token_hash).await?;
+                return Ok(token);
+            }
+        }
+        Err(ApiTokenStoreError::TokenNotFound(id.to_string()))
+    }
+}
+
+#[async_trait]
+impl AuditLogStore for RedisStore {
+    async fn append_audit_entry(&self, entry: AuditLogEntry) -> Result<(), AuditLogStoreError> {
+        // Each entry has its own id, so no two members of the set are equal
+        let json = serde_json::to_string(&entry)
+            .map_err(|err| AuditLogStoreError::Other(Box::new(err)))?;
+        let _: () = self
+            .connection
+            .clone()
+            .zadd(AUDIT_LOG_KEY, json, entry.timestamp)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_audit_entries(
+        &self,
+        query: AuditQuery,
+    ) -> Result<Vec<AuditLogEntry>, AuditLogStoreError> {
+        let max = query
+            .until
+            .map_or_else(|| "+inf".to_string(), |until| until.to_string());
+        let min = query
+            .since
+            .map_or_else(|| "-inf".to_string(), |since| since.to_string());
+        let mut connection = self.connection.clone();
+        // Redis can only paginate by itself when filtering on the timestamp
+        let paginated = query.actor.is_none() && query.action.is_none() && query.target.is_none();
+        let entries: Vec<String> = if paginated {
+            connection
+                .zrevrangebyscore_limit(
+                    AUDIT_LOG_KEY,
+                    max,
+                    min,
+                    query.offset as isize,
+                    query.limit() as isize,
+                )
+                .await?
+        } else {
+            connection.zrevrangebyscore(AUDIT_LOG_KEY, max, min).await?
+        };
+        let entries = entries
+            .iter()
+            .map(|json| {
+                serde_json::from_str::<AuditLogEntry>(json)
+                    .map_err(|err| AuditLogStoreError::Other(Box::new(err)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if paginated {
+            return Ok(entries);
+        }
+        Ok(entries
+            .into_iter()
+            .filter(|entry| query.matches(entry))
+            .skip(query.offset)
+            .take(query.limit())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl FeeStore for RedisStore {
+    fn get_fee_schedules(&self) -> Arc<FeeSchedules> {
+        self.fee_schedules.read().clone()
+    }
+
+    async fn set_fee_schedules(&self, schedules: FeeSchedules) -> Result<(), FeeStoreError> {
+        let json =
+            serde_json::to_string(&schedules).map_err(|err| FeeStoreError::Other(Box::new(err)))?;
+        let _: () = self.connection.clone().set(FEE_SCHEDULES_KEY, json).await?;
+        *self.fee_schedules.write() = Arc::new(schedules);
+        Ok(())
+    }
+
+    async fn record_fee_revenue(&self, account_id: Uuid, fee: u64) -> Result<(), FeeStoreError> {
+        let _: i64 = self
+            .connection
+            .clone()
+            .hincr(FEE_REVENUE_KEY, RedisAccountId(account_id), fee)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_fee_revenue(&self) -> Result<HashMap<Uuid, u64>, FeeStoreError> {
+        let revenue: HashMap<RedisAccountId, u64> =
+            self.connection.clone().hgetall(FEE_REVENUE_KEY).await?;
+        Ok(revenue
+            .into_iter()
+            .map(|(account_id, fee)| (account_id.0, fee))
+            .collect())
+    }
+}
+
 #[async_trait]
 impl BalanceStore for RedisStore {
     /// Returns the balance **from the account holder's perspective**, meaning the sum of
@@ -773,6 +1171,41 @@ impl BalanceStore for RedisStore {
         Ok((balance, amount_to_settle))
     }
 
+    async fn update_balances_for_fulfill_with_settlement(
+        &self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+        settlement: OutgoingSettlement,
+    ) -> Result<(i64, Option<OutgoingSettlement>), BalanceStoreError> {
+        // The script saves the settlement along with the balance change
+        let json = outgoing_settlement_json_without_amount(&settlement)
+            .map_err(|err| BalanceStoreError::Other(Box::new(err)))?;
+        let (balance, amount_to_settle): (i64, u64) = PROCESS_FULFILL
+            .arg(RedisAccountId(to_account_id))
+            .arg(outgoing_amount)
+            .arg(&settlement.idempotency_key)
+            .arg(json)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+
+        trace!(
+            "Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
+            to_account_id,
+            outgoing_amount,
+            balance,
+            amount_to_settle,
+        );
+        let settlement = if amount_to_settle > 0 {
+            Some(OutgoingSettlement {
+                amount: amount_to_settle,
+                ..settlement
+            })
+        } else {
+            None
+        };
+        Ok((balance, settlement))
+    }
+
     async fn update_balances_for_reject(
         &self,
         from_account_id: Uuid,
@@ -798,8 +1231,11 @@ impl BalanceStore for RedisStore {
 }
 
 impl ExchangeRateStore for RedisStore {
-    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ExchangeRateStoreError> {
-        let rates: Vec<f64> = asset_codes
+    fn get_exchange_rates(
+        &self,
+        asset_codes: &[&str],
+    ) -> Result<Vec<BigRational>, ExchangeRateStoreError> {
+        let rates: Vec<BigRational> = asset_codes
             .iter()
             .filter_map(|code| (*self.exchange_rates.read()).get(*code).cloned())
             .collect();
@@ -814,13 +1250,15 @@ impl ExchangeRateStore for RedisStore {
         }
     }
 
-    fn get_all_exchange_rates(&self) -> Result<HashMap<String, f64>, ExchangeRateStoreError> {
+    fn get_all_exchange_rates(
+        &self,
+    ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError> {
         Ok((*self.exchange_rates.read()).clone())
     }
 
     fn set_exchange_rates(
         &self,
-        rates: HashMap<String, f64>,
+        rates: HashMap<String, BigRational>,
     ) -> Result<(), ExchangeRateStoreError> {
         // TODO publish rate updates through a pubsub mechanism to support horizontally scaling nodes
         (*self.exchange_rates.write()) = rates;
@@ -922,9 +1360,13 @@ impl HttpStore for RedisStore {
 }
 
 impl RouterStore for RedisStore {
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
         self.routes.read().clone()
     }
+
+    fn multipath_routing_table(&self) -> Arc<PrefixMap<Vec<NextHop>>> {
+        self.multipath_routes.read().clone()
+    }
 }
 
 #[async_trait]
@@ -983,6 +1425,8 @@ impl NodeStore for RedisStore {
         let settings = EncryptedAccountSettings {
             settle_to: settings.settle_to,
             settle_threshold: settings.settle_threshold,
+            circuit_breaker_failure_threshold: settings.circuit_breaker_failure_threshold,
+            circuit_breaker_cool_down: settings.circuit_breaker_cool_down,
             ilp_over_btp_url: settings.ilp_over_btp_url,
             ilp_over_http_url: settings.ilp_over_http_url,
             ilp_over_btp_incoming_token: settings.ilp_over_btp_incoming_token.map(|token| {
@@ -1072,12 +1516,71 @@ impl NodeStore for RedisStore {
         pipe.atomic()
             .del(STATIC_ROUTES_KEY)
             .ignore()
+            .del(STATIC_MULTIPATH_ROUTES_KEY)
+            .ignore()
             .hset_multiple(STATIC_ROUTES_KEY, &routes)
             .ignore();
 
-        pipe.query_async(&mut connection).await?;
+        pipe.query_async::<_, ()>(&mut connection).await?;
+
+        update_routes(connection, routing_table, self.multipath_routes.clone()).await?;
+        Ok(())
+    }
+
+    async fn set_static_multipath_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    {
+        let mut connection = self.connection.clone();
+        let routes: Vec<(String, Vec<NextHop>)> = routes
+            .into_iter()
+            .filter(|(_, next_hops)| !next_hops.is_empty())
+            .collect();
+        let accounts: HashSet<Uuid> = routes
+            .iter()
+            .flat_map(|(_prefix, next_hops)| next_hops.iter().map(|hop| hop.account_id))
+            .collect();
+        let mut pipe = redis_crate::pipe();
+        for account_id in accounts {
+            pipe.exists(accounts_key(account_id));
+        }
+
+        let routing_table = self.routes.clone();
+
+        let accounts_exist: Vec<bool> = pipe.query_async(&mut connection).await?;
+
+        if !accounts_exist.iter().all(|a| *a) {
+            error!("Error setting static routes because not all of the given accounts exist");
+            return Err(NodeStoreError::MissingAccounts);
+        }
+
+        let static_routes: Vec<(String, RedisAccountId)> = routes
+            .iter()
+            .map(|(prefix, next_hops)| (prefix.clone(), RedisAccountId(next_hops[0].account_id)))
+            .collect();
+        let mut multipath_routes: Vec<(String, String)> = Vec::new();
+        for (prefix, next_hops) in routes.iter().filter(|(_, next_hops)| next_hops.len() > 1) {
+            let next_hops = serde_json::to_string(next_hops)
+                .map_err(|err| NodeStoreError::Other(Box::new(err)))?;
+            multipath_routes.push((prefix.clone(), next_hops));
+        }
+
+        let mut pipe = redis_crate::pipe();
+        pipe.atomic()
+            .del(STATIC_ROUTES_KEY)
+            .ignore()
+            .del(STATIC_MULTIPATH_ROUTES_KEY)
+            .ignore()
+            .hset_multiple(STATIC_ROUTES_KEY, &static_routes)
+            .ignore();
+        if !multipath_routes.is_empty() {
+            pipe.hset_multiple(STATIC_MULTIPATH_ROUTES_KEY, &multipath_routes)
+                .ignore();
+        }
+
+        pipe.query_async::<_, ()>(&mut connection).await?;
 
-        update_routes(connection, routing_table).await?;
+        update_routes(connection, routing_table, self.multipath_routes.clone()).await?;
         Ok(())
     }
 
@@ -1098,11 +1601,15 @@ impl NodeStore for RedisStore {
             return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
         }
 
-        connection
+        let mut pipe = redis_crate::pipe();
+        pipe.atomic()
+            .hdel(STATIC_MULTIPATH_ROUTES_KEY, &prefix)
+            .ignore()
             .hset(STATIC_ROUTES_KEY, prefix, RedisAccountId(account_id))
-            .await?;
+            .ignore();
+        pipe.query_async::<_, ()>(&mut connection).await?;
 
-        update_routes(connection, routing_table).await?;
+        update_routes(connection, routing_table, self.multipath_routes.clone()).await?;
 
         Ok(())
     }
@@ -1121,10 +1628,10 @@ impl NodeStore for RedisStore {
         }
 
         connection
-            .set(DEFAULT_ROUTE_KEY, RedisAccountId(account_id))
+            .set::<_, _, ()>(DEFAULT_ROUTE_KEY, RedisAccountId(account_id))
             .await?;
         debug!("Set default route to account id: {}", account_id);
-        update_routes(connection, routing_table).await?;
+        update_routes(connection, routing_table, self.multipath_routes.clone()).await?;
         Ok(())
     }
 
@@ -1139,7 +1646,7 @@ impl NodeStore for RedisStore {
             .collect();
         debug!("Setting settlement engines to {:?}", asset_to_url_map);
         connection
-            .hset_multiple(SETTLEMENT_ENGINES_KEY, &asset_to_url_map)
+            .hset_multiple::<_, _, _, ()>(SETTLEMENT_ENGINES_KEY, &asset_to_url_map)
             .await?;
         Ok(())
     }
@@ -1184,7 +1691,7 @@ impl AddressStore for RedisStore {
 
         // Save it to Redis
         connection
-            .set(PARENT_ILP_KEY, ilp_address.as_bytes())
+            .set::<_, _, ()>(PARENT_ILP_KEY, ilp_address.as_bytes())
             .await?;
 
         let accounts = self.get_all_accounts().await?;
@@ -1237,15 +1744,15 @@ impl AddressStore for RedisStore {
             }
         }
 
-        pipe.query_async(&mut connection.clone()).await?;
-        update_routes(connection, routing_table).await?;
+        pipe.query_async::<_, ()>(&mut connection.clone()).await?;
+        update_routes(connection, routing_table, self.multipath_routes.clone()).await?;
         Ok(())
     }
 
     async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
         self.connection
             .clone()
-            .del(PARENT_ILP_KEY)
+            .del::<_, ()>(PARENT_ILP_KEY)
             .map_err(|err| AddressStoreError::Other(Box::new(err)))
             .await?;
 
@@ -1357,10 +1864,50 @@ impl CcpRoutingStore for RedisStore {
             .hset_multiple(ROUTES_KEY, &routes)
             .ignore();
 
-        pipe.query_async(&mut connection).await?;
+        pipe.query_async::<_, ()>(&mut connection).await?;
         trace!("Saved {} routes to Redis", num_routes);
 
-        update_routes(connection, self.routes.clone()).await?;
+        update_routes(
+            connection,
+            self.routes.clone(),
+            self.multipath_routes.clone(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn set_multipath_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let mut multipath_routes: Vec<(String, String)> = Vec::new();
+        for (prefix, next_hops) in routes {
+            let next_hops = serde_json::to_string(&next_hops)
+                .map_err(|err| CcpRoutingStoreError::Other(Box::new(err)))?;
+            multipath_routes.push((prefix, next_hops));
+        }
+        let num_routes = multipath_routes.len();
+        let mut connection = self.connection.clone();
+
+        let mut pipe = redis_crate::pipe();
+        pipe.atomic().del(MULTIPATH_ROUTES_KEY).ignore();
+        if !multipath_routes.is_empty() {
+            pipe.hset_multiple(MULTIPATH_ROUTES_KEY, &multipath_routes)
+                .ignore();
+        }
+
+        pipe.query_async::<_, ()>(&mut connection).await?;
+        trace!(
+            "Saved {} routes with several next hops to Redis",
+            num_routes
+        );
+
+        update_routes(
+            connection,
+            self.routes.clone(),
+            self.multipath_routes.clone(),
+        )
+        .await?;
         Ok(())
     }
 }
@@ -1369,7 +1916,8 @@ impl CcpRoutingStore for RedisStore {
 impl RateLimitStore for RedisStore {
     type Account = Account;
 
-    /// Apply rate limits for number of packets per minute and amount of money per minute
+    /// Apply rate limits for number of packets and amount of money per minute,
+    /// and the daily and monthly volume caps
     ///
     /// This uses https://github.com/brandur/redis-cell so the redis-cell module MUST be loaded into redis before this is run
     async fn apply_rate_limits(
@@ -1377,34 +1925,28 @@ impl RateLimitStore for RedisStore {
         account: Account,
         prepare_amount: u64,
     ) -> Result<(), RateLimitError> {
-        if account.amount_per_minute_limit.is_some() || account.packets_per_minute_limit.is_some() {
-            let mut pipe = redis_crate::pipe();
-            let packet_limit = account.packets_per_minute_limit.is_some();
-            let amount_limit = account.amount_per_minute_limit.is_some();
-
-            if let Some(limit) = account.packets_per_minute_limit {
-                let limit = limit - 1;
-                let packets_limit = format!("limit:packets:{}", account.id);
-                pipe.cmd("CL.THROTTLE")
-                    .arg(packets_limit)
-                    .arg(limit)
-                    .arg(limit)
-                    .arg(60)
-                    .arg(1);
-            }
-
-            if let Some(limit) = account.amount_per_minute_limit {
-                let limit = limit - 1;
-                let throughput_limit = format!("limit:throughput:{}", account.id);
-                pipe.cmd("CL.THROTTLE")
-                    .arg(throughput_limit)
-                    // TODO allow separate configuration for burst limit
-                    .arg(limit)
-                    .arg(limit)
-                    .arg(60)
-                    .arg(prepare_amount);
-            }
+        let mut limits = Vec::new();
+        let mut pipe = redis_crate::pipe();
+        if let Some(limit) = packets_limit(&account) {
+            limits.push(RateLimitError::PacketLimitExceeded);
+            pipe.cmd("CL.THROTTLE")
+                .arg(format!("limit:packets:{}", account.id))
+                .arg(limit.max_burst)
+                .arg(limit.count_per_minute)
+                .arg(60)
+                .arg(1);
+        }
+        if let Some(limit) = throughput_limit(&account) {
+            limits.push(RateLimitError::ThroughputLimitExceeded);
+            pipe.cmd("CL.THROTTLE")
+                .arg(format!("limit:throughput:{}", account.id))
+                .arg(limit.max_burst)
+                .arg(limit.count_per_minute)
+                .arg(60)
+                .arg(prepare_amount);
+        }
 
+        if !limits.is_empty() {
             let results: Vec<Vec<i64>> = pipe
                 .query_async(&mut self.connection.clone())
                 .map_err(|err| {
@@ -1412,25 +1954,30 @@ impl RateLimitStore for RedisStore {
                     RateLimitError::StoreError
                 })
                 .await?;
+            if let Some((error, _)) = limits
+                .into_iter()
+                .zip(results.iter())
+                .find(|(_, result)| result[0] == 1)
+            {
+                return Err(error);
+            }
+        }
 
-            if packet_limit && amount_limit {
-                if results[0][0] == 1 {
-                    Err(RateLimitError::PacketLimitExceeded)
-                } else if results[1][0] == 1 {
-                    Err(RateLimitError::ThroughputLimitExceeded)
-                } else {
-                    Ok(())
-                }
-            } else if packet_limit && results[0][0] == 1 {
-                Err(RateLimitError::PacketLimitExceeded)
-            } else if amount_limit && results[0][0] == 1 {
-                Err(RateLimitError::ThroughputLimitExceeded)
-            } else {
-                Ok(())
+        let caps = volume_caps(&account);
+        if !caps.is_empty() {
+            let totals = self.add_volumes(&account, &caps, prepare_amount).await?;
+            if caps
+                .iter()
+                .zip(totals.iter())
+                .any(|(cap, total)| *total > cap.limit)
+            {
+                // Take the amount back out so a rejected packet doesn't use up the cap
+                self.remove_volumes(&account, &caps, prepare_amount).await?;
+                return Err(RateLimitError::VolumeLimitExceeded);
             }
-        } else {
-            Ok(())
         }
+
+        Ok(())
     }
 
     async fn refund_throughput_limit(
@@ -1438,23 +1985,146 @@ impl RateLimitStore for RedisStore {
         account: Account,
         prepare_amount: u64,
     ) -> Result<(), RateLimitError> {
-        if let Some(limit) = account.amount_per_minute_limit {
-            let limit = limit - 1;
+        if let Some(limit) = throughput_limit(&account) {
             let throughput_limit = format!("limit:throughput:{}", account.id);
             cmd("CL.THROTTLE")
                 .arg(throughput_limit)
-                .arg(limit)
-                .arg(limit)
+                .arg(limit.max_burst)
+                .arg(limit.count_per_minute)
                 .arg(60)
                 // TODO make sure this doesn't overflow
                 .arg(0i64 - (prepare_amount as i64))
-                .query_async(&mut self.connection.clone())
+                .query_async::<_, ()>(&mut self.connection.clone())
                 .map_err(|_| RateLimitError::StoreError)
                 .await?;
         }
 
+        let caps = volume_caps(&account);
+        if !caps.is_empty() {
+            self.remove_volumes(&account, &caps, prepare_amount).await?;
+        }
+
         Ok(())
     }
+
+    async fn reconcile_rate_limits(
+        &self,
+        account: Account,
+        packets: u64,
+        amount: u64,
+    ) -> Result<RemainingRateLimits, RateLimitError> {
+        let mut remaining = RemainingRateLimits::default();
+        if let Some(limit) = packets_limit(&account) {
+            let packets_limit = format!("limit:packets:{}", account.id);
+            remaining.packets = Some(self.charge_limit(&packets_limit, &limit, packets).await?);
+        }
+        if let Some(limit) = throughput_limit(&account) {
+            let throughput_limit = format!("limit:throughput:{}", account.id);
+            remaining.amount = Some(self.charge_limit(&throughput_limit, &limit, amount).await?);
+        }
+
+        // The packets were already forwarded, so their amount is added to the volumes even past the caps
+        let caps = volume_caps(&account);
+        if !caps.is_empty() {
+            let totals = self.add_volumes(&account, &caps, amount).await?;
+            remaining.volume = caps
+                .iter()
+                .zip(totals.iter())
+                .map(|(cap, total)| cap.limit.saturating_sub(*total))
+                .min();
+        }
+
+        Ok(remaining)
+    }
+}
+
+impl RedisStore {
+    /// Applies up to `quantity` against the limit, as far as it allows, and returns
+    /// the units which can still be applied right away
+    async fn charge_limit(
+        &self,
+        key: &str,
+        limit: &Limit,
+        quantity: u64,
+    ) -> Result<u64, RateLimitError> {
+        let mut connection = self.connection.clone();
+        let throttle = |quantity: u64| {
+            cmd("CL.THROTTLE")
+                .arg(key)
+                .arg(limit.max_burst)
+                .arg(limit.count_per_minute)
+                .arg(60)
+                .arg(quantity)
+                .to_owned()
+        };
+
+        // Applying a quantity of 0 reads the limit without changing it
+        let result: Vec<i64> = throttle(0)
+            .query_async(&mut connection)
+            .map_err(|err| {
+                error!("Error reading rate limit: {:?}", err);
+                RateLimitError::StoreError
+            })
+            .await?;
+        let charged = quantity.min(result[2].max(0) as u64);
+        if charged == 0 {
+            return Ok(result[2].max(0) as u64);
+        }
+
+        let result: Vec<i64> = throttle(charged)
+            .query_async(&mut connection)
+            .map_err(|err| {
+                error!("Error reconciling rate limit: {:?}", err);
+                RateLimitError::StoreError
+            })
+            .await?;
+        Ok(result[2].max(0) as u64)
+    }
+
+    /// Adds the amount to the volume of the current period of each cap and returns the totals
+    async fn add_volumes(
+        &self,
+        account: &Account,
+        caps: &[VolumeCap],
+        amount: u64,
+    ) -> Result<Vec<u64>, RateLimitError> {
+        let mut pipe = redis_crate::pipe();
+        for cap in caps.iter() {
+            let key = volume_key(account, cap);
+            pipe.incr(&key, amount)
+                .expire(&key, cap.expires_after as usize)
+                .ignore();
+        }
+        pipe.query_async(&mut self.connection.clone())
+            .map_err(|err| {
+                error!("Error applying volume caps: {:?}", err);
+                RateLimitError::StoreError
+            })
+            .await
+    }
+
+    async fn remove_volumes(
+        &self,
+        account: &Account,
+        caps: &[VolumeCap],
+        amount: u64,
+    ) -> Result<(), RateLimitError> {
+        let mut pipe = redis_crate::pipe();
+        for cap in caps.iter() {
+            pipe.incr(volume_key(account, cap), -(amount as i64))
+                .ignore();
+        }
+        pipe.query_async(&mut self.connection.clone())
+            .map_err(|err| {
+                error!("Error refunding volume caps: {:?}", err);
+                RateLimitError::StoreError
+            })
+            .await
+    }
+}
+
+fn volume_key(account: &Account, cap: &VolumeCap) -> String {
+    format!("limit:volume:{}:{}:{}", cap.name, account.id, cap.period)
 }
 
 #[async_trait]
@@ -1507,7 +2177,7 @@ impl IdempotentStore for RedisStore {
             .ignore()
             .expire(&prefixed_idempotency_key(&idempotency_key), 86400)
             .ignore();
-        pipe.query_async(&mut connection).await?;
+        pipe.query_async::<_, ()>(&mut connection).await?;
 
         trace!(
             "Cached {:?}: {:?}, {:?}",
@@ -1660,6 +2330,96 @@ impl FromRedisValue for AmountWithScale {
     }
 }
 
+/// Serializes the outgoing settlement up to its amount, e.g. `{"account_id":"...",...,"amount":`,
+/// so that the fulfill script can complete it once it knows how much to settle
+fn outgoing_settlement_json_without_amount(
+    settlement: &OutgoingSettlement,
+) -> Result<String, serde_json::Error> {
+    let mut json = serde_json::to_value(settlement)?;
+    if let Some(fields) = json.as_object_mut() {
+        fields.remove("amount");
+    }
+    let json = json.to_string();
+    Ok(format!("{},\"amount\":", &json[..json.len() - 1]))
+}
+
+#[async_trait]
+impl SettlementOutboxStore for RedisStore {
+    async fn save_outgoing_settlement(
+        &self,
+        settlement: OutgoingSettlement,
+    ) -> Result<(), SettlementStoreError> {
+        let json = serde_json::to_string(&settlement)
+            .map_err(|err| SettlementStoreError::Other(Box::new(err)))?;
+        let _: () = self
+            .connection
+            .clone()
+            .hset(OUTGOING_SETTLEMENTS_KEY, &settlement.idempotency_key, json)
+            .await?;
+        Ok(())
+    }
+
+    async fn complete_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError> {
+        let _: () = self
+            .connection
+            .clone()
+            .hdel(OUTGOING_SETTLEMENTS_KEY, idempotency_key)
+            .await?;
+        Ok(())
+    }
+
+    async fn refund_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError> {
+        let mut connection = self.connection.clone();
+        let json: Option<String> = connection
+            .hget(OUTGOING_SETTLEMENTS_KEY, idempotency_key)
+            .await?;
+        let settlement: OutgoingSettlement = match json {
+            Some(json) => serde_json::from_str(&json)
+                .map_err(|err| SettlementStoreError::Other(Box::new(err)))?,
+            // It was already completed or refunded
+            None => return Ok(()),
+        };
+        let balance: Option<i64> = REFUND_OUTGOING_SETTLEMENT
+            .arg(idempotency_key)
+            .arg(RedisAccountId(settlement.account_id))
+            .arg(settlement.amount)
+            .invoke_async(&mut connection)
+            .await?;
+        if let Some(balance) = balance {
+            trace!(
+                "Refunded outgoing settlement {} for account: {} of amount: {}. Balance is now: {}",
+                idempotency_key,
+                settlement.account_id,
+                settlement.amount,
+                balance
+            );
+        }
+        Ok(())
+    }
+
+    async fn get_pending_outgoing_settlements(
+        &self,
+    ) -> Result<Vec<OutgoingSettlement>, SettlementStoreError> {
+        let settlements: Vec<String> = self
+            .connection
+            .clone()
+            .hvals(OUTGOING_SETTLEMENTS_KEY)
+            .await?;
+        settlements
+            .iter()
+            .map(|json| {
+                serde_json::from_str(json).map_err(|err| SettlementStoreError::Other(Box::new(err)))
+            })
+            .collect()
+    }
+}
+
 #[async_trait]
 impl LeftoversStore for RedisStore {
     type AccountId = Uuid;
@@ -1699,7 +2459,7 @@ impl LeftoversStore for RedisStore {
         // type and sum them up.
         let mut connection = self.connection.clone();
         connection
-            .rpush(
+            .rpush::<_, _, ()>(
                 uncredited_amount_key(account_id),
                 AmountWithScale {
                     num: uncredited_settlement_amount.0,
@@ -1726,7 +2486,7 @@ impl LeftoversStore for RedisStore {
         if precision_loss > BigUint::from(0u32) {
             self.connection
                 .clone()
-                .rpush(
+                .rpush::<_, _, ()>(
                     uncredited_amount_key(account_id),
                     AmountWithScale {
                         num: precision_loss,
@@ -1746,7 +2506,7 @@ impl LeftoversStore for RedisStore {
         trace!("Clearing uncredited_settlement_amount {:?}", account_id);
         self.connection
             .clone()
-            .del(uncredited_amount_key(account_id))
+            .del::<_, ()>(uncredited_amount_key(account_id))
             .await?;
         Ok(())
     }
@@ -1759,14 +2519,23 @@ use futures::future::TryFutureExt;
 // TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
 async fn update_routes(
     mut connection: RedisReconnect,
-    routing_table: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    routing_table: Arc<RwLock<Arc<PrefixMap<Uuid>>>>,
+    multipath_routing_table: Arc<RwLock<Arc<PrefixMap<Vec<NextHop>>>>>,
 ) -> Result<(), RedisError> {
     let mut pipe = redis_crate::pipe();
     pipe.hgetall(ROUTES_KEY)
         .hgetall(STATIC_ROUTES_KEY)
-        .get(DEFAULT_ROUTE_KEY);
-    let (routes, static_routes, default_route): (RouteVec, RouteVec, Option<RedisAccountId>) =
-        pipe.query_async(&mut connection).await?;
+        .get(DEFAULT_ROUTE_KEY)
+        .hgetall(MULTIPATH_ROUTES_KEY)
+        .hgetall(STATIC_MULTIPATH_ROUTES_KEY);
+    #[allow(clippy::type_complexity)]
+    let (routes, static_routes, default_route, multipath_routes, static_multipath_routes): (
+        RouteVec,
+        RouteVec,
+        Option<RedisAccountId>,
+        Vec<(String, String)>,
+        Vec<(String, String)>,
+    ) = pipe.query_async(&mut connection).await?;
     trace!(
         "Loaded routes from redis. Static routes: {:?}, default route: {:?}, other routes: {:?}",
         static_routes,
@@ -1778,7 +2547,7 @@ async fn update_routes(
     let default_route_iter = iter::once(default_route)
         .filter_map(|r| r)
         .map(|rid| (String::new(), rid.0));
-    let routes = HashMap::from_iter(
+    let routes = PrefixMap::from_iter(
         routes
             .into_iter()
             .map(|(s, rid)| (s, rid.0))
@@ -1788,10 +2557,43 @@ async fn update_routes(
             // any routes with the same prefix from the first set
             .chain(static_routes.into_iter().map(|(s, rid)| (s, rid.0))),
     );
+    // Only the next hops which start with the route in the routing table are used,
+    // so that a static route overrides the next hops learned over CCP
+    let mut multipath_routes_table = PrefixMap::new();
+    for (prefix, next_hops) in multipath_routes.into_iter().chain(static_multipath_routes) {
+        match serde_json::from_str::<Vec<NextHop>>(&next_hops) {
+            Ok(next_hops) => {
+                if routes.get(&prefix) == next_hops.first().map(|hop| &hop.account_id) {
+                    multipath_routes_table.insert(&prefix, next_hops);
+                }
+            }
+            Err(_) => warn!("Ignoring invalid next hops for prefix: {}", prefix),
+        }
+    }
     // TODO we may not want to print this because the routing table will be very big
     // if the node has a lot of local accounts
     trace!("Routing table is: {:?}", routes);
     *routing_table.write() = Arc::new(routes);
+    *multipath_routing_table.write() = Arc::new(multipath_routes_table);
+    Ok(())
+}
+
+/// Loads the fee schedules from redis. Invalid fee schedules are ignored
+async fn update_fee_schedules(
+    mut connection: RedisReconnect,
+    fee_schedules: Arc<RwLock<Arc<FeeSchedules>>>,
+) -> Result<(), RedisError> {
+    let json: Option<String> = connection.get(FEE_SCHEDULES_KEY).await?;
+    let schedules = match json.map(|json| serde_json::from_str::<FeeSchedules>(&json)) {
+        Some(Ok(schedules)) => schedules,
+        Some(Err(err)) => {
+            warn!("Ignoring invalid fee schedules: {}", err);
+            return Ok(());
+        }
+        None => FeeSchedules::default(),
+    };
+    trace!("Fee schedules are: {:?}", schedules);
+    *fee_schedules.write() = Arc::new(schedules);
     Ok(())
 }
 
@@ -1918,6 +2720,38 @@ impl ToRedisArgs for &AccountWithEncryptedTokens {
             "amount_per_minute_limit".write_redis_args(&mut rv);
             limit.write_redis_args(&mut rv);
         }
+        if let Some(limit) = account.packets_burst_limit {
+            "packets_burst_limit".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(limit) = account.amount_burst_limit {
+            "amount_burst_limit".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(limit) = account.amount_per_day_limit {
+            "amount_per_day_limit".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(limit) = account.amount_per_month_limit {
+            "amount_per_month_limit".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(limit) = account.max_in_flight_packets {
+            "max_in_flight_packets".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(limit) = account.max_in_flight_amount {
+            "max_in_flight_amount".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(threshold) = account.circuit_breaker_failure_threshold {
+            "circuit_breaker_failure_threshold".write_redis_args(&mut rv);
+            threshold.write_redis_args(&mut rv);
+        }
+        if let Some(cool_down) = account.circuit_breaker_cool_down {
+            "circuit_breaker_cool_down".write_redis_args(&mut rv);
+            cool_down.write_redis_args(&mut rv);
+        }
         if let Some(min_balance) = account.min_balance {
             "min_balance".write_redis_args(&mut rv);
             min_balance.write_redis_args(&mut rv);
@@ -1992,6 +2826,17 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 round_trip_time,
                 packets_per_minute_limit: get_value_option("packets_per_minute_limit", &hash)?,
                 amount_per_minute_limit: get_value_option("amount_per_minute_limit", &hash)?,
+                packets_burst_limit: get_value_option("packets_burst_limit", &hash)?,
+                amount_burst_limit: get_value_option("amount_burst_limit", &hash)?,
+                amount_per_day_limit: get_value_option("amount_per_day_limit", &hash)?,
+                amount_per_month_limit: get_value_option("amount_per_month_limit", &hash)?,
+                max_in_flight_packets: get_value_option("max_in_flight_packets", &hash)?,
+                max_in_flight_amount: get_value_option("max_in_flight_amount", &hash)?,
+                circuit_breaker_failure_threshold: get_value_option(
+                    "circuit_breaker_failure_threshold",
+                    &hash,
+                )?,
+                circuit_breaker_cool_down: get_value_option("circuit_breaker_cool_down", &hash)?,
                 settlement_engine_url: get_url_option("settlement_engine_url", &hash)?,
             },
         })
@@ -2064,4 +2909,25 @@ mod tests {
         .await;
         assert!(result.is_err());
     }
+
+    #[test]
+    fn completes_outgoing_settlement_json_with_the_amount() {
+        let settlement = OutgoingSettlement {
+            idempotency_key: "key".to_string(),
+            account_id: Uuid::new_v4(),
+            amount: 0,
+            asset_scale: 9,
+            engine_url: Url::parse("http://localhost:3000").unwrap(),
+        };
+        let json = outgoing_settlement_json_without_amount(&settlement).unwrap();
+        let completed: OutgoingSettlement =
+            serde_json::from_str(&format!("{}{}}}", json, 1_000_000_000_000_000u64)).unwrap();
+        assert_eq!(
+            completed,
+            OutgoingSettlement {
+                amount: 1_000_000_000_000_000,
+                ..settlement
+            }
+        );
+    }
 }
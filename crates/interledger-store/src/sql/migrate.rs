@@ -0,0 +1,110 @@
+use sqlx::{any::AnyPool, Executor, Row};
+use tracing::debug;
+
+/// A versioned change to the database schema
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
+
+/// All of the schema migrations, in the order they must be applied.
+/// Migrations which have been released must never be modified, any changes
+/// to the schema must be done by appending a new migration to this list.
+static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: include_str!("migrations/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "multipath routes",
+        sql: include_str!("migrations/0002_multipath_routes.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "stream connections",
+        sql: include_str!("migrations/0003_stream_connections.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "outgoing settlements",
+        sql: include_str!("migrations/0004_outgoing_settlements.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "payments",
+        sql: include_str!("migrations/0005_payments.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "api tokens",
+        sql: include_str!("migrations/0006_api_tokens.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "audit log",
+        sql: include_str!("migrations/0007_audit_log.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "fee revenue",
+        sql: include_str!("migrations/0008_fee_revenue.sql"),
+    },
+    Migration {
+        version: 9,
+        description: "in-flight limits",
+        sql: include_str!("migrations/0009_in_flight_limits.sql"),
+    },
+    Migration {
+        version: 10,
+        description: "rate limit bursts and volume caps",
+        sql: include_str!("migrations/0010_rate_limit_bursts_and_volume_caps.sql"),
+    },
+    Migration {
+        version: 11,
+        description: "circuit breakers",
+        sql: include_str!("migrations/0011_circuit_breakers.sql"),
+    },
+];
+
+/// Applies all the migrations which have not yet been applied to the database.
+/// Each migration is applied in its own transaction, along with the row which
+/// records that it was applied.
+pub(super) async fn run_migrations(pool: &AnyPool) -> Result<(), sqlx::Error> {
+    pool.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL
+        )",
+    )
+    .await?;
+
+    let applied: Vec<i64> = sqlx::query("SELECT version FROM schema_migrations")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.try_get("version"))
+        .collect::<Result<_, _>>()?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+    {
+        debug!(
+            "Applying database migration {}: {}",
+            migration.version, migration.description
+        );
+        let mut tx = pool.begin().await?;
+        tx.execute(migration.sql).await?;
+        sqlx::query("INSERT INTO schema_migrations (version, description) VALUES ($1, $2)")
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
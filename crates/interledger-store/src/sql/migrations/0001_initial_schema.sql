@@ -0,0 +1,76 @@
+-- Accounts, their balances and the tokens used to authenticate them.
+-- Tokens are stored encrypted (hex encoded) and unsigned 64-bit values
+-- are stored as text since neither PostgreSQL nor SQLite support them.
+CREATE TABLE accounts (
+    id VARCHAR(36) PRIMARY KEY,
+    username VARCHAR(32) NOT NULL UNIQUE,
+    ilp_address VARCHAR(1023) NOT NULL,
+    asset_code VARCHAR(255) NOT NULL,
+    asset_scale BIGINT NOT NULL,
+    max_packet_amount VARCHAR(20) NOT NULL,
+    min_balance BIGINT,
+    ilp_over_http_url TEXT,
+    ilp_over_http_incoming_token TEXT,
+    ilp_over_http_outgoing_token TEXT,
+    ilp_over_btp_url TEXT,
+    ilp_over_btp_incoming_token TEXT,
+    ilp_over_btp_outgoing_token TEXT,
+    settle_threshold BIGINT,
+    settle_to BIGINT,
+    routing_relation VARCHAR(32) NOT NULL,
+    round_trip_time BIGINT NOT NULL,
+    packets_per_minute_limit BIGINT,
+    amount_per_minute_limit VARCHAR(20),
+    settlement_engine_url TEXT,
+    balance BIGINT NOT NULL DEFAULT 0,
+    prepaid_amount BIGINT NOT NULL DEFAULT 0
+);
+
+-- Routes to local accounts and the ones received via CCP
+CREATE TABLE routes (
+    prefix VARCHAR(1023) PRIMARY KEY,
+    account_id VARCHAR(36) NOT NULL
+);
+
+-- Routes configured by the node operator, which take precedence over all other routes
+CREATE TABLE static_routes (
+    prefix VARCHAR(1023) PRIMARY KEY,
+    account_id VARCHAR(36) NOT NULL
+);
+
+-- Single values such as the default route and the address received from our parent
+CREATE TABLE settings (
+    name VARCHAR(255) PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+-- Globally configured settlement engine for each asset code
+CREATE TABLE settlement_engines (
+    asset_code VARCHAR(255) PRIMARY KEY,
+    url TEXT NOT NULL
+);
+
+-- Cached responses of the settlement API, keyed by idempotency key
+CREATE TABLE idempotent_data (
+    idempotency_key VARCHAR(255) PRIMARY KEY,
+    status_code BIGINT NOT NULL,
+    body TEXT NOT NULL,
+    input_hash VARCHAR(64) NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+-- Idempotency keys of the incoming settlements which were already credited
+CREATE TABLE settlement_idempotency_keys (
+    idempotency_key VARCHAR(255) PRIMARY KEY,
+    created_at BIGINT NOT NULL
+);
+
+-- Amounts (and their scales) which could not be credited due to precision loss
+CREATE TABLE uncredited_settlement_amounts (
+    account_id VARCHAR(36) NOT NULL,
+    amount TEXT NOT NULL,
+    scale BIGINT NOT NULL
+);
+
+CREATE INDEX uncredited_settlement_amounts_account_id
+    ON uncredited_settlement_amounts (account_id);
@@ -0,0 +1,12 @@
+-- Next hops (as a JSON list, best first) of the prefixes we learned several routes for
+CREATE TABLE multipath_routes (
+    prefix VARCHAR(1023) PRIMARY KEY,
+    next_hops TEXT NOT NULL
+);
+
+-- Next hops (as a JSON list) of the static routes which were configured with several of them.
+-- The first next hop of each of these prefixes is also stored in static_routes
+CREATE TABLE static_multipath_routes (
+    prefix VARCHAR(1023) PRIMARY KEY,
+    next_hops TEXT NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Running totals of the STREAM connections tracked by the receiver, keyed by connection tag.
+-- closed is 0 or 1 and last_activity is a unix timestamp in seconds
+CREATE TABLE stream_connections (
+    connection_tag VARCHAR(255) PRIMARY KEY,
+    total_received BIGINT NOT NULL DEFAULT 0,
+    receive_max BIGINT,
+    closed BIGINT NOT NULL DEFAULT 0,
+    last_activity BIGINT NOT NULL
+);
+
+CREATE INDEX stream_connections_last_activity ON stream_connections (last_activity);
@@ -0,0 +1,9 @@
+-- Outgoing settlements which were sent to the settlement engines but
+-- not completed or refunded yet, keyed by the idempotency key sent to the engine
+CREATE TABLE outgoing_settlements (
+    idempotency_key VARCHAR(255) PRIMARY KEY,
+    account_id VARCHAR(36) NOT NULL,
+    amount BIGINT NOT NULL,
+    asset_scale BIGINT NOT NULL,
+    engine_url VARCHAR(1023) NOT NULL
+);
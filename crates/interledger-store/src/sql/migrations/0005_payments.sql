@@ -0,0 +1,19 @@
+-- Payments sent and received by each account. direction is 'incoming' or 'outgoing',
+-- status is 'completed' or 'failed' and paid_at is a unix timestamp in seconds
+CREATE TABLE payments (
+    id VARCHAR(36) PRIMARY KEY,
+    account_id VARCHAR(36) NOT NULL,
+    direction VARCHAR(16) NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    destination VARCHAR(1023) NOT NULL,
+    counterparty VARCHAR(1023) NOT NULL,
+    amount BIGINT NOT NULL,
+    asset_code VARCHAR(255) NOT NULL,
+    asset_scale BIGINT NOT NULL,
+    delivered_amount BIGINT,
+    delivered_asset_code VARCHAR(255),
+    delivered_asset_scale BIGINT,
+    paid_at BIGINT NOT NULL
+);
+
+CREATE INDEX payments_account_id_paid_at ON payments (account_id, paid_at);
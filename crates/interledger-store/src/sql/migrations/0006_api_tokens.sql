@@ -0,0 +1,11 @@
+-- API tokens issued by the node's administrator. Only the SHA-256 hash of each token is
+-- stored (hex encoded), scopes is a comma separated list and the timestamps are in seconds
+CREATE TABLE api_tokens (
+    id VARCHAR(36) PRIMARY KEY,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    account_id VARCHAR(36),
+    scopes VARCHAR(255) NOT NULL,
+    expires_at BIGINT,
+    created_at BIGINT NOT NULL,
+    description VARCHAR(1023)
+);
@@ -0,0 +1,14 @@
+-- Administrative actions taken through the API. Rows are only ever inserted.
+-- before_value and after_value are JSON with the secrets redacted,
+-- and logged_at is a unix timestamp in seconds
+CREATE TABLE audit_log (
+    id VARCHAR(36) PRIMARY KEY,
+    logged_at BIGINT NOT NULL,
+    actor VARCHAR(255) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    target VARCHAR(1023),
+    before_value TEXT,
+    after_value TEXT
+);
+
+CREATE INDEX audit_log_logged_at ON audit_log (logged_at);
@@ -0,0 +1,6 @@
+-- Fees earned on the fulfilled packets received from each account, in units of the account's asset.
+-- The fee schedules themselves are stored as JSON in the settings table
+CREATE TABLE fee_revenue (
+    account_id VARCHAR(36) PRIMARY KEY,
+    amount BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Limits of the packets to or from each account which may be in flight at once.
+-- Amounts are stored as strings since they may not fit in a BIGINT
+ALTER TABLE accounts ADD COLUMN max_in_flight_packets BIGINT;
+ALTER TABLE accounts ADD COLUMN max_in_flight_amount VARCHAR(20);
@@ -0,0 +1,6 @@
+-- Burst limits and daily and monthly volume caps of each account.
+-- Amounts are stored as strings since they may not fit in a BIGINT
+ALTER TABLE accounts ADD COLUMN packets_burst_limit BIGINT;
+ALTER TABLE accounts ADD COLUMN amount_burst_limit VARCHAR(20);
+ALTER TABLE accounts ADD COLUMN amount_per_day_limit VARCHAR(20);
+ALTER TABLE accounts ADD COLUMN amount_per_month_limit VARCHAR(20);
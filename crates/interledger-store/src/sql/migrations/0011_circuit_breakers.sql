@@ -0,0 +1,4 @@
+-- Settings of the circuit breaker of each account.
+-- The cool-down is stored as a string since it may not fit in a BIGINT
+ALTER TABLE accounts ADD COLUMN circuit_breaker_failure_threshold BIGINT;
+ALTER TABLE accounts ADD COLUMN circuit_breaker_cool_down VARCHAR(20);
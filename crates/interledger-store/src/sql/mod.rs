@@ -0,0 +1,2708 @@
+// The schema of our data is defined by the migrations in the `migrations` directory:
+//   accounts                       account details, balance and prepaid amount for each account
+//   routes                         dynamic routing table (local accounts and CCP routes)
+//   static_routes                  static routing table
+//   multipath_routes               next hops of the prefixes with several CCP routes
+//   static_multipath_routes        next hops of the static routes with several next hops
+//   settings                       default route, address received from our parent and fee schedules
+//   settlement_engines             globally configured settlement engine per asset code
+//   idempotent_data                cached settlement API responses
+//   settlement_idempotency_keys    incoming settlements which were already credited
+//   uncredited_settlement_amounts  leftovers due to precision loss
+//   stream_connections             running totals of the STREAM connections tracked by the receiver
+//   outgoing_settlements           settlements sent to the engines which were not completed or refunded yet
+//   payments                       payments sent and received by each account
+//   api_tokens                     API tokens issued by the node's administrator, by their hash
+//   audit_log                      administrative actions taken through the API
+//   fee_revenue                    fees earned on the packets received from each account
+// Every operation which is implemented as a Lua script in the RedisStore
+// is a single conditional statement or a transaction, so that it is
+// atomic under concurrent access (including from multiple nodes).
+// The same queries are used for PostgreSQL and SQLite, so they must
+// only use syntax which is supported by both.
+mod migrate;
+
+use super::account::{Account, AccountWithEncryptedTokens};
+use super::crypto::{encrypt_token, generate_keys, DecryptionKey, EncryptionKey};
+use super::throttle::Throttle;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::channel::mpsc::UnboundedSender;
+use http::StatusCode;
+use interledger_api::{
+    AccountDetails, AccountSettings, ApiToken, ApiTokenStore, AuditLogEntry, AuditLogStore,
+    AuditQuery, NodeStore,
+};
+use interledger_btp::BtpStore;
+use interledger_ccp::{CcpRoutingAccount, CcpRoutingStore, RoutingRelation};
+use interledger_errors::*;
+use interledger_http::HttpStore;
+use interledger_packet::Address;
+use interledger_rates::ExchangeRateStore;
+use interledger_router::{NextHop, PrefixMap, RouterStore};
+use interledger_service::{Account as AccountTrait, AccountStore, AddressStore, Username};
+use interledger_service_util::{
+    BalanceStore, FeeSchedules, FeeStore, RateLimitError, RateLimitStore, RemainingRateLimits,
+};
+use interledger_settlement::core::{
+    idempotency::{IdempotentData, IdempotentStore},
+    scale_with_precision_loss,
+    types::{
+        Convert, ConvertDetails, LeftoversStore, OutgoingSettlement, SettlementOutboxStore,
+        SettlementStore,
+    },
+};
+use interledger_stream::{
+    PaymentHistoryStore, PaymentNotification, PaymentQuery, PaymentRecord, StreamConnectionDetails,
+    StreamConnectionStore, StreamNotificationsStore,
+};
+use num_bigint::BigUint;
+use num_rational::BigRational;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use secrecy::{ExposeSecret, Secret, SecretBytesMut};
+use sqlx::{
+    any::{AnyPool, AnyPoolOptions, AnyRow},
+    Row,
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use tracing::{debug, error, trace, warn};
+use url::Url;
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+const DEFAULT_POLL_INTERVAL: u64 = 30000; // 30 seconds
+/// Idempotency keys expire after 24 hours, like they do in redis
+const IDEMPOTENCY_KEY_TTL: i64 = 86400;
+
+static PARENT_ILP_KEY: &str = "parent_node_account_address";
+static DEFAULT_ROUTE_KEY: &str = "default_route";
+static FEE_SCHEDULES_KEY: &str = "fee_schedules";
+
+/// The node's default ILP Address
+static DEFAULT_ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("local.host").unwrap());
+
+/// Loads accounts along with the globally configured settlement engine for their asset
+static SELECT_ACCOUNTS: &str = "SELECT accounts.id, accounts.username, accounts.ilp_address,
+    accounts.asset_code, accounts.asset_scale, accounts.max_packet_amount, accounts.min_balance,
+    accounts.ilp_over_http_url, accounts.ilp_over_http_incoming_token,
+    accounts.ilp_over_http_outgoing_token, accounts.ilp_over_btp_url,
+    accounts.ilp_over_btp_incoming_token, accounts.ilp_over_btp_outgoing_token,
+    accounts.settle_threshold, accounts.settle_to, accounts.routing_relation,
+    accounts.round_trip_time, accounts.packets_per_minute_limit,
+    accounts.amount_per_minute_limit, accounts.packets_burst_limit,
+    accounts.amount_burst_limit, accounts.amount_per_day_limit,
+    accounts.amount_per_month_limit, accounts.max_in_flight_packets,
+    accounts.max_in_flight_amount, accounts.circuit_breaker_failure_threshold,
+    accounts.circuit_breaker_cool_down, accounts.settlement_engine_url,
+    settlement_engines.url AS global_settlement_engine_url
+    FROM accounts LEFT JOIN settlement_engines
+    ON settlement_engines.asset_code = accounts.asset_code";
+
+/// Errors which are specific to the SQL store. They are returned
+/// to callers wrapped in the `Other` variant of each trait's error type.
+#[derive(Error, Debug)]
+enum SqlStoreError {
+    #[error("account `{0}` was not found")]
+    AccountNotFound(Uuid),
+    #[error("Incoming prepare of {amount} would bring account {account_id} under its minimum balance. Current balance: {balance}, min balance: {min_balance}")]
+    BelowMinBalance {
+        account_id: Uuid,
+        amount: u64,
+        balance: i64,
+        min_balance: i64,
+    },
+    #[error("invalid value in column `{0}`")]
+    InvalidColumn(&'static str),
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+macro_rules! impl_from_sql_store_error {
+    ($($error:ident),*) => {
+        $(
+            impl From<SqlStoreError> for $error {
+                fn from(src: SqlStoreError) -> Self {
+                    $error::Other(Box::new(src))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_sql_store_error!(
+    AccountStoreError,
+    AddressStoreError,
+    ApiTokenStoreError,
+    AuditLogStoreError,
+    BalanceStoreError,
+    BtpStoreError,
+    CcpRoutingStoreError,
+    FeeStoreError,
+    HttpStoreError,
+    NodeStoreError,
+    PaymentHistoryStoreError,
+    SettlementStoreError,
+    StreamConnectionStoreError
+);
+
+/// Returns the current unix timestamp in seconds, used to expire idempotency keys
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Returns a comma separated list of `count` placeholders, starting at `$start`
+fn placeholders(start: usize, count: usize) -> String {
+    (start..start + count)
+        .map(|i| format!("${}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn encode_token(token: &Option<SecretBytesMut>) -> Option<String> {
+    token
+        .as_ref()
+        .map(|token| hex::encode(&token.expose_secret()[..]))
+}
+
+fn get_token(row: &AnyRow, column: &'static str) -> Result<Option<SecretBytesMut>, SqlStoreError> {
+    match row.try_get::<Option<String>, _>(column)? {
+        Some(token) => {
+            let token = hex::decode(token).map_err(|_| SqlStoreError::InvalidColumn(column))?;
+            Ok(Some(SecretBytesMut::new(&token[..])))
+        }
+        None => Ok(None),
+    }
+}
+
+fn get_url(row: &AnyRow, column: &'static str) -> Result<Option<Url>, SqlStoreError> {
+    match row.try_get::<Option<String>, _>(column)? {
+        Some(url) => Ok(Some(
+            Url::parse(&url).map_err(|_| SqlStoreError::InvalidColumn(column))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Amounts are stored as strings since they may not fit in a BIGINT
+fn get_amount(row: &AnyRow, column: &'static str) -> Result<Option<u64>, SqlStoreError> {
+    match row.try_get::<Option<String>, _>(column)? {
+        Some(amount) => Ok(Some(
+            u64::from_str(&amount).map_err(|_| SqlStoreError::InvalidColumn(column))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+fn get_parsed<T: FromStr>(row: &AnyRow, column: &'static str) -> Result<T, SqlStoreError> {
+    let value: String = row.try_get(column)?;
+    T::from_str(&value).map_err(|_| SqlStoreError::InvalidColumn(column))
+}
+
+/// Parses an account loaded with `SELECT_ACCOUNTS`. The account's tokens remain encrypted.
+fn account_from_row(row: &AnyRow) -> Result<AccountWithEncryptedTokens, SqlStoreError> {
+    let asset_scale: i64 = row.try_get("asset_scale")?;
+    let round_trip_time: i64 = row.try_get("round_trip_time")?;
+    let packets_per_minute_limit: Option<i64> = row.try_get("packets_per_minute_limit")?;
+    let packets_burst_limit: Option<i64> = row.try_get("packets_burst_limit")?;
+    let max_in_flight_packets: Option<i64> = row.try_get("max_in_flight_packets")?;
+    let circuit_breaker_failure_threshold: Option<i64> =
+        row.try_get("circuit_breaker_failure_threshold")?;
+    let settlement_engine_url = match get_url(row, "settlement_engine_url")? {
+        Some(url) => Some(url),
+        None => get_url(row, "global_settlement_engine_url")?,
+    };
+
+    let account = Account {
+        id: get_parsed(row, "id")?,
+        username: get_parsed(row, "username")?,
+        ilp_address: get_parsed(row, "ilp_address")?,
+        asset_code: row.try_get("asset_code")?,
+        asset_scale: asset_scale as u8,
+        max_packet_amount: get_parsed(row, "max_packet_amount")?,
+        min_balance: row.try_get("min_balance")?,
+        ilp_over_http_url: get_url(row, "ilp_over_http_url")?,
+        ilp_over_http_incoming_token: get_token(row, "ilp_over_http_incoming_token")?,
+        ilp_over_http_outgoing_token: get_token(row, "ilp_over_http_outgoing_token")?,
+        ilp_over_btp_url: get_url(row, "ilp_over_btp_url")?,
+        ilp_over_btp_incoming_token: get_token(row, "ilp_over_btp_incoming_token")?,
+        ilp_over_btp_outgoing_token: get_token(row, "ilp_over_btp_outgoing_token")?,
+        settle_threshold: row.try_get("settle_threshold")?,
+        settle_to: row.try_get("settle_to")?,
+        routing_relation: get_parsed(row, "routing_relation")?,
+        round_trip_time: round_trip_time as u32,
+        packets_per_minute_limit: packets_per_minute_limit.map(|limit| limit as u32),
+        amount_per_minute_limit: get_amount(row, "amount_per_minute_limit")?,
+        packets_burst_limit: packets_burst_limit.map(|limit| limit as u32),
+        amount_burst_limit: get_amount(row, "amount_burst_limit")?,
+        amount_per_day_limit: get_amount(row, "amount_per_day_limit")?,
+        amount_per_month_limit: get_amount(row, "amount_per_month_limit")?,
+        max_in_flight_packets: max_in_flight_packets.map(|limit| limit as u32),
+        max_in_flight_amount: get_amount(row, "max_in_flight_amount")?,
+        circuit_breaker_failure_threshold: circuit_breaker_failure_threshold
+            .map(|threshold| threshold as u32),
+        circuit_breaker_cool_down: get_amount(row, "circuit_breaker_cool_down")?,
+        settlement_engine_url,
+    };
+    Ok(AccountWithEncryptedTokens { account })
+}
+
+pub struct SqlStoreBuilder {
+    database_url: String,
+    secret: [u8; 32],
+    poll_interval: u64,
+    /// Connector's ILP Address. Used to insert `Child` accounts as
+    node_ilp_address: Address,
+}
+
+impl SqlStoreBuilder {
+    /// Simple Constructor. The database url must start with `postgres://`,
+    /// `postgresql://` or `sqlite:` (for example `sqlite::memory:`)
+    pub fn new(database_url: String, secret: [u8; 32]) -> Self {
+        SqlStoreBuilder {
+            database_url,
+            secret,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            node_ilp_address: DEFAULT_ILP_ADDRESS.clone(),
+        }
+    }
+
+    /// Sets the ILP Address corresponding to the node
+    pub fn node_ilp_address(&mut self, node_ilp_address: Address) -> &mut Self {
+        self.node_ilp_address = node_ilp_address;
+        self
+    }
+
+    /// Sets the poll interval at which the store will update its routes
+    pub fn poll_interval(&mut self, poll_interval: u64) -> &mut Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Connects to the SQL Store
+    ///
+    /// Specifically
+    /// 1. Generates encryption and decryption keys
+    /// 1. Connects to the database and applies any pending schema migrations
+    /// 1. Gets the Node address assigned to us by our parent (if it exists)
+    /// 1. Starts polling for routing table and fee schedule updates
+    pub async fn connect(&mut self) -> Result<SqlStore, ()> {
+        let (encryption_key, decryption_key) = generate_keys(&self.secret[..]);
+        self.secret.zeroize(); // clear the secret after it has been used for key generation
+        let poll_interval = self.poll_interval;
+
+        let mut pool_options = AnyPoolOptions::new();
+        if self.database_url.contains(":memory:") {
+            // Each in-memory SQLite database only lives as long as a connection to it is open,
+            // so we keep exactly one connection around for the lifetime of the store
+            pool_options = pool_options
+                .max_connections(1)
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
+        let pool = pool_options
+            .connect(&self.database_url)
+            .await
+            .map_err(|err| error!("Error connecting to the database: {:?}", err))?;
+        migrate::run_migrations(&pool)
+            .await
+            .map_err(|err| error!("Error applying database migrations: {:?}", err))?;
+
+        // Before initializing the store, check if we have an address
+        // that was configured due to adding a parent. If no parent was
+        // found, use the builder's provided address (local.host) or the
+        // one we decided to override it with
+        let address = sqlx::query("SELECT value FROM settings WHERE name = $1")
+            .bind(PARENT_ILP_KEY)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    "Error checking whether we have a parent configured: {:?}",
+                    err
+                )
+            })?;
+        let node_ilp_address = match address {
+            Some(row) => get_parsed(&row, "value").map_err(|err| {
+                error!("Invalid parent ILP address stored in the database: {}", err)
+            })?,
+            None => self.node_ilp_address.clone(),
+        };
+
+        let store = SqlStore {
+            ilp_address: Arc::new(RwLock::new(node_ilp_address)),
+            pool,
+            throttle: Arc::new(RwLock::new(Throttle::new())),
+            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            exchange_rates: Arc::new(RwLock::new(HashMap::new())),
+            routes: Arc::new(RwLock::new(Arc::new(PrefixMap::new()))),
+            multipath_routes: Arc::new(RwLock::new(Arc::new(PrefixMap::new()))),
+            fee_schedules: Arc::new(RwLock::new(Arc::new(FeeSchedules::default()))),
+            encryption_key: Arc::new(encryption_key),
+            decryption_key: Arc::new(decryption_key),
+        };
+        store
+            .update_routes()
+            .await
+            .map_err(|err| error!("Error loading routes: {}", err))?;
+        update_fee_schedules(&store.pool, &store.fee_schedules)
+            .await
+            .map_err(|err| error!("Error loading fee schedules: {}", err))?;
+
+        // Poll for routing table and fee schedule updates, which may have been made by other
+        // nodes using the same database. Polling stops once all copies of the store are dropped.
+        let pool = store.pool.clone();
+        let routing_table = Arc::downgrade(&store.routes);
+        let multipath_routing_table = Arc::downgrade(&store.multipath_routes);
+        let fee_schedules = Arc::downgrade(&store.fee_schedules);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(poll_interval));
+            loop {
+                interval.tick().await;
+                if let (Some(routing_table), Some(multipath_routing_table), Some(fee_schedules)) = (
+                    routing_table.upgrade(),
+                    multipath_routing_table.upgrade(),
+                    fee_schedules.upgrade(),
+                ) {
+                    if let Err(err) =
+                        update_routes(&pool, &routing_table, &multipath_routing_table).await
+                    {
+                        error!("Error polling for routing table updates: {}", err);
+                    }
+                    if let Err(err) = update_fee_schedules(&pool, &fee_schedules).await {
+                        error!("Error polling for fee schedule updates: {}", err);
+                    }
+                } else {
+                    debug!("Not polling routes anymore because the store was dropped");
+                    break;
+                }
+            }
+        });
+
+        Ok(store)
+    }
+}
+
+/// A Store that uses PostgreSQL or SQLite as its underlying database.
+///
+/// Balance updates are done with conditional statements and transactions so
+/// that they have the same atomicity guarantees as the Lua scripts used by the
+/// RedisStore. The routing table is cached in memory and polled for updates.
+///
+/// Unlike the RedisStore, rate limits and payment notifications are handled
+/// in memory, so they are not shared between nodes using the same database.
+#[derive(Clone)]
+pub struct SqlStore {
+    /// The Store's ILP Address
+    ilp_address: Arc<RwLock<Address>>,
+    /// A pool of connections to the database
+    pool: AnyPool,
+    throttle: Arc<RwLock<Throttle>>,
+    /// WebSocket sender which publishes incoming payment updates
+    subscriptions: Arc<RwLock<HashMap<Uuid, UnboundedSender<PaymentNotification>>>>,
+    exchange_rates: Arc<RwLock<HashMap<String, BigRational>>>,
+    /// The store keeps the routing table in memory so that it can be returned
+    /// synchronously while the Router is processing packets.
+    /// The inner `Arc<PrefixMap>` is used so that the `routing_table` method can
+    /// return a reference to the routing table without cloning the underlying data.
+    routes: Arc<RwLock<Arc<PrefixMap<Uuid>>>>,
+    multipath_routes: Arc<RwLock<Arc<PrefixMap<Vec<NextHop>>>>>,
+    fee_schedules: Arc<RwLock<Arc<FeeSchedules>>>,
+    /// Encryption Key so that the no cleartext data are stored
+    encryption_key: Arc<Secret<EncryptionKey>>,
+    /// Decryption Key to provide cleartext data to users
+    decryption_key: Arc<Secret<DecryptionKey>>,
+}
+
+impl SqlStore {
+    /// Reloads the routing table which is read by the Router
+    async fn update_routes(&self) -> Result<(), sqlx::Error> {
+        update_routes(&self.pool, &self.routes, &self.multipath_routes).await
+    }
+
+    /// Loads the accounts matching the provided condition and decrypts their tokens
+    async fn load_accounts_where(
+        &self,
+        condition: &str,
+        params: Vec<String>,
+    ) -> Result<Vec<Account>, SqlStoreError> {
+        let sql = format!("{} WHERE {}", SELECT_ACCOUNTS, condition);
+        let mut query = sqlx::query(&sql);
+        for param in params {
+            query = query.bind(param);
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| {
+                account_from_row(row)
+                    .map(|account| account.decrypt_tokens(&self.decryption_key.expose_secret().0))
+            })
+            .collect()
+    }
+
+    async fn load_all_accounts(&self) -> Result<Vec<Account>, SqlStoreError> {
+        self.load_accounts_where("1 = 1", Vec::new()).await
+    }
+
+    async fn load_account(&self, id: Uuid) -> Result<Option<Account>, SqlStoreError> {
+        let mut accounts = self
+            .load_accounts_where("accounts.id = $1", vec![id.to_string()])
+            .await?;
+        Ok(accounts.pop())
+    }
+
+    async fn load_account_from_username(
+        &self,
+        username: &Username,
+    ) -> Result<Option<Account>, SqlStoreError> {
+        let mut accounts = self
+            .load_accounts_where("accounts.username = $1", vec![username.to_string()])
+            .await?;
+        Ok(accounts.pop())
+    }
+
+    /// Returns true if all of the provided account ids exist
+    async fn accounts_exist(&self, ids: &[Uuid]) -> Result<bool, sqlx::Error> {
+        let mut ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        ids.sort();
+        ids.dedup();
+        if ids.is_empty() {
+            return Ok(true);
+        }
+        let sql = format!(
+            "SELECT COUNT(*) AS count FROM accounts WHERE id IN ({})",
+            placeholders(1, ids.len())
+        );
+        let mut query = sqlx::query(&sql);
+        for id in &ids {
+            query = query.bind(id.as_str());
+        }
+        let count: i64 = query.fetch_one(&self.pool).await?.try_get("count")?;
+        Ok(count as usize == ids.len())
+    }
+
+    /// Inserts or updates all the columns of the account, except its balance
+    async fn save_account(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Any>,
+        account: &AccountWithEncryptedTokens,
+        insert: bool,
+    ) -> Result<(), sqlx::Error> {
+        let account = &account.account;
+        let columns = vec![
+            ("username", Value::from(account.username.to_string())),
+            ("ilp_address", account.ilp_address.to_string().into()),
+            ("asset_code", account.asset_code.clone().into()),
+            ("asset_scale", i64::from(account.asset_scale).into()),
+            (
+                "max_packet_amount",
+                account.max_packet_amount.to_string().into(),
+            ),
+            ("min_balance", account.min_balance.into()),
+            (
+                "ilp_over_http_url",
+                account
+                    .ilp_over_http_url
+                    .as_ref()
+                    .map(Url::to_string)
+                    .into(),
+            ),
+            (
+                "ilp_over_http_incoming_token",
+                encode_token(&account.ilp_over_http_incoming_token).into(),
+            ),
+            (
+                "ilp_over_http_outgoing_token",
+                encode_token(&account.ilp_over_http_outgoing_token).into(),
+            ),
+            (
+                "ilp_over_btp_url",
+                account.ilp_over_btp_url.as_ref().map(Url::to_string).into(),
+            ),
+            (
+                "ilp_over_btp_incoming_token",
+                encode_token(&account.ilp_over_btp_incoming_token).into(),
+            ),
+            (
+                "ilp_over_btp_outgoing_token",
+                encode_token(&account.ilp_over_btp_outgoing_token).into(),
+            ),
+            ("settle_threshold", account.settle_threshold.into()),
+            ("settle_to", account.settle_to.into()),
+            (
+                "routing_relation",
+                account.routing_relation.to_string().into(),
+            ),
+            ("round_trip_time", i64::from(account.round_trip_time).into()),
+            (
+                "packets_per_minute_limit",
+                account.packets_per_minute_limit.map(i64::from).into(),
+            ),
+            (
+                "amount_per_minute_limit",
+                account
+                    .amount_per_minute_limit
+                    .map(|limit| limit.to_string())
+                    .into(),
+            ),
+            (
+                "packets_burst_limit",
+                account.packets_burst_limit.map(i64::from).into(),
+            ),
+            (
+                "amount_burst_limit",
+                account
+                    .amount_burst_limit
+                    .map(|limit| limit.to_string())
+                    .into(),
+            ),
+            (
+                "amount_per_day_limit",
+                account
+                    .amount_per_day_limit
+                    .map(|limit| limit.to_string())
+                    .into(),
+            ),
+            (
+                "amount_per_month_limit",
+                account
+                    .amount_per_month_limit
+                    .map(|limit| limit.to_string())
+                    .into(),
+            ),
+            (
+                "max_in_flight_packets",
+                account.max_in_flight_packets.map(i64::from).into(),
+            ),
+            (
+                "max_in_flight_amount",
+                account
+                    .max_in_flight_amount
+                    .map(|limit| limit.to_string())
+                    .into(),
+            ),
+            (
+                "circuit_breaker_failure_threshold",
+                account
+                    .circuit_breaker_failure_threshold
+                    .map(i64::from)
+                    .into(),
+            ),
+            (
+                "circuit_breaker_cool_down",
+                account
+                    .circuit_breaker_cool_down
+                    .map(|cool_down| cool_down.to_string())
+                    .into(),
+            ),
+            (
+                "settlement_engine_url",
+                account
+                    .settlement_engine_url
+                    .as_ref()
+                    .map(Url::to_string)
+                    .into(),
+            ),
+        ];
+
+        if insert {
+            let mut columns = columns;
+            columns.push(("id", account.id.to_string().into()));
+            let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+            let values: Vec<Value> = columns.into_iter().map(|(_, value)| value).collect();
+            let sql = format!(
+                "INSERT INTO accounts ({}) VALUES ({})",
+                names.join(", "),
+                Value::expressions(&values, 1).join(", ")
+            );
+            Value::bind_all(sqlx::query(&sql), values)
+                .execute(tx)
+                .await?;
+        } else {
+            update_account_columns(tx, account.id, columns).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A value which is written to the database.
+///
+/// The `Any` driver silently drops `None` values when binding an `Option`,
+/// which would shift all the following parameters, so NULLs are written
+/// directly into the query instead of being bound.
+enum Value {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+impl Value {
+    /// Returns the SQL expression of each value, numbering the placeholders of
+    /// the non-NULL values consecutively from `$start`
+    fn expressions(values: &[Value], start: usize) -> Vec<String> {
+        let mut next = start;
+        values
+            .iter()
+            .map(|value| match value {
+                Value::Null => "NULL".to_owned(),
+                _ => {
+                    next += 1;
+                    format!("${}", next - 1)
+                }
+            })
+            .collect()
+    }
+
+    /// Binds the non-NULL values to the query, in order
+    fn bind_all<'q>(
+        mut query: sqlx::query::Query<'q, sqlx::Any, sqlx::any::AnyArguments<'q>>,
+        values: Vec<Value>,
+    ) -> sqlx::query::Query<'q, sqlx::Any, sqlx::any::AnyArguments<'q>> {
+        for value in values {
+            query = match value {
+                Value::Null => query,
+                Value::Integer(value) => query.bind(value),
+                Value::Text(value) => query.bind(value),
+            };
+        }
+        query
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+/// Sets the provided columns of an account. Returns false if the account does not exist.
+async fn update_account_columns(
+    tx: &mut sqlx::Transaction<'_, sqlx::Any>,
+    id: Uuid,
+    columns: Vec<(&str, Value)>,
+) -> Result<bool, sqlx::Error> {
+    let (names, mut values): (Vec<&str>, Vec<Value>) = columns.into_iter().unzip();
+    values.push(id.to_string().into());
+    let mut expressions = Value::expressions(&values, 1);
+    let id_expression = expressions.pop().unwrap_or_default();
+    let assignments: Vec<String> = names
+        .iter()
+        .zip(expressions)
+        .map(|(name, expression)| format!("{} = {}", name, expression))
+        .collect();
+    let sql = format!(
+        "UPDATE accounts SET {} WHERE id = {}",
+        assignments.join(", "),
+        id_expression
+    );
+    let done = Value::bind_all(sqlx::query(&sql), values)
+        .execute(tx)
+        .await?;
+    Ok(done.rows_affected() > 0)
+}
+
+/// Credits the account for a fulfilled packet, and returns its balance
+/// (including the prepaid amount) along with the amount which should be settled
+async fn process_fulfill(
+    tx: &mut sqlx::Transaction<'_, sqlx::Any>,
+    to_account_id: Uuid,
+    outgoing_amount: u64,
+) -> Result<(i64, u64), SqlStoreError> {
+    // Updating the row first locks it until the end of the transaction,
+    // so the settlement check below cannot race with other balance updates
+    let done = sqlx::query("UPDATE accounts SET balance = balance + $1 WHERE id = $2")
+        .bind(outgoing_amount as i64)
+        .bind(to_account_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    if done.rows_affected() == 0 {
+        return Err(SqlStoreError::AccountNotFound(to_account_id));
+    }
+
+    let row = sqlx::query(
+        "SELECT balance, prepaid_amount, settle_threshold, settle_to
+        FROM accounts WHERE id = $1",
+    )
+    .bind(to_account_id.to_string())
+    .fetch_one(&mut *tx)
+    .await?;
+    let mut balance: i64 = row.try_get("balance")?;
+    let prepaid_amount: i64 = row.try_get("prepaid_amount")?;
+    let settle_threshold: Option<i64> = row.try_get("settle_threshold")?;
+    let settle_to: Option<i64> = row.try_get("settle_to")?;
+
+    // Settlement is triggered if the balance reaches the settle threshold
+    // and the threshold is greater than the amount to settle down to
+    let mut amount_to_settle = 0;
+    if let (Some(settle_threshold), Some(settle_to)) = (settle_threshold, settle_to) {
+        if balance >= settle_threshold && settle_threshold > settle_to {
+            amount_to_settle = (balance - settle_to) as u64;
+            // Update the balance _before_ sending the settlement so that we don't accidentally send
+            // multiple settlements for the same balance. If the settlement fails we'll roll back
+            // the balance change by re-adding the amount back to the balance
+            balance = settle_to;
+            sqlx::query("UPDATE accounts SET balance = $1 WHERE id = $2")
+                .bind(balance)
+                .bind(to_account_id.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    Ok((balance + prepaid_amount, amount_to_settle))
+}
+
+/// Loads the routing table and the next hops of the prefixes which have several of them
+/// from the database
+async fn update_routes(
+    pool: &AnyPool,
+    routing_table: &RwLock<Arc<PrefixMap<Uuid>>>,
+    multipath_routing_table: &RwLock<Arc<PrefixMap<Vec<NextHop>>>>,
+) -> Result<(), sqlx::Error> {
+    let mut routes = PrefixMap::new();
+    let parse_routes = |rows: Vec<AnyRow>, routes: &mut PrefixMap<Uuid>| {
+        for row in rows {
+            let prefix: String = row.try_get("prefix")?;
+            let account_id: String = row.try_get("account_id")?;
+            match Uuid::from_str(&account_id) {
+                Ok(account_id) => {
+                    routes.insert(&prefix, account_id);
+                }
+                Err(_) => warn!("Ignoring route with invalid account id: {}", account_id),
+            }
+        }
+        Ok::<(), sqlx::Error>(())
+    };
+
+    let rows = sqlx::query("SELECT prefix, account_id FROM routes")
+        .fetch_all(pool)
+        .await?;
+    parse_routes(rows, &mut routes)?;
+    // If there is a default route set in the db,
+    // set the entry for "" in the routing table to route to that account
+    let default_route = sqlx::query("SELECT value FROM settings WHERE name = $1")
+        .bind(DEFAULT_ROUTE_KEY)
+        .fetch_optional(pool)
+        .await?;
+    if let Some(row) = default_route {
+        let account_id: String = row.try_get("value")?;
+        if let Ok(account_id) = Uuid::from_str(&account_id) {
+            routes.insert("", account_id);
+        }
+    }
+    // Having the static routes inserted after ensures that they will overwrite
+    // any routes with the same prefix from the other routes
+    let rows = sqlx::query("SELECT prefix, account_id FROM static_routes")
+        .fetch_all(pool)
+        .await?;
+    parse_routes(rows, &mut routes)?;
+
+    // Only the next hops which start with the route in the routing table are used,
+    // so that a static route overrides the next hops learned over CCP
+    let mut multipath_routes = PrefixMap::new();
+    for table in &["multipath_routes", "static_multipath_routes"] {
+        let rows = sqlx::query(&format!("SELECT prefix, next_hops FROM {}", table))
+            .fetch_all(pool)
+            .await?;
+        for row in rows {
+            let prefix: String = row.try_get("prefix")?;
+            let next_hops: String = row.try_get("next_hops")?;
+            match serde_json::from_str::<Vec<NextHop>>(&next_hops) {
+                Ok(next_hops) => {
+                    if routes.get(&prefix) == next_hops.first().map(|hop| &hop.account_id) {
+                        multipath_routes.insert(&prefix, next_hops);
+                    }
+                }
+                Err(_) => warn!("Ignoring invalid next hops for prefix: {}", prefix),
+            }
+        }
+    }
+
+    trace!("Routing table is: {:?}", routes);
+    *routing_table.write() = Arc::new(routes);
+    *multipath_routing_table.write() = Arc::new(multipath_routes);
+    Ok(())
+}
+
+/// Loads the fee schedules from the database. Invalid fee schedules are ignored
+async fn update_fee_schedules(
+    pool: &AnyPool,
+    fee_schedules: &RwLock<Arc<FeeSchedules>>,
+) -> Result<(), sqlx::Error> {
+    let row = sqlx::query("SELECT value FROM settings WHERE name = $1")
+        .bind(FEE_SCHEDULES_KEY)
+        .fetch_optional(pool)
+        .await?;
+    let schedules = match row {
+        Some(row) => {
+            let json: String = row.try_get("value")?;
+            match serde_json::from_str::<FeeSchedules>(&json) {
+                Ok(schedules) => schedules,
+                Err(err) => {
+                    warn!("Ignoring invalid fee schedules: {}", err);
+                    return Ok(());
+                }
+            }
+        }
+        None => FeeSchedules::default(),
+    };
+    trace!("Fee schedules are: {:?}", schedules);
+    *fee_schedules.write() = Arc::new(schedules);
+    Ok(())
+}
+
+#[async_trait]
+impl AccountStore for SqlStore {
+    type Account = Account;
+
+    async fn get_accounts(
+        &self,
+        account_ids: Vec<Uuid>,
+    ) -> Result<Vec<Account>, AccountStoreError> {
+        let condition = format!(
+            "accounts.id IN ({})",
+            placeholders(1, account_ids.len().max(1))
+        );
+        let params = if account_ids.is_empty() {
+            vec![String::new()]
+        } else {
+            account_ids.iter().map(|id| id.to_string()).collect()
+        };
+        let mut loaded: HashMap<Uuid, Account> = self
+            .load_accounts_where(&condition, params)
+            .await?
+            .into_iter()
+            .map(|account| (account.id, account))
+            .collect();
+
+        // Return the accounts in the order they were requested
+        let accounts: Vec<Account> = account_ids
+            .iter()
+            .filter_map(|id| loaded.remove(id))
+            .collect();
+        if accounts.len() == account_ids.len() {
+            Ok(accounts)
+        } else {
+            Err(AccountStoreError::WrongLength {
+                expected: account_ids.len(),
+                actual: accounts.len(),
+            })
+        }
+    }
+
+    async fn get_account_id_from_username(
+        &self,
+        username: &Username,
+    ) -> Result<Uuid, AccountStoreError> {
+        let row = sqlx::query("SELECT id FROM accounts WHERE username = $1")
+            .bind(username.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(row) => Ok(get_parsed(&row, "id")?),
+            None => {
+                debug!("Username not found: {}", username);
+                Err(AccountStoreError::AccountNotFound(username.to_string()))
+            }
+        }
+    }
+}
+
+impl StreamNotificationsStore for SqlStore {
+    type Account = Account;
+
+    fn add_payment_notification_subscription(
+        &self,
+        id: Uuid,
+        sender: UnboundedSender<PaymentNotification>,
+    ) {
+        trace!("Added payment notification listener for {}", id);
+        self.subscriptions.write().insert(id, sender);
+    }
+
+    fn publish_payment_notification(&self, payment: PaymentNotification) {
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let account_id = self_clone
+                .get_account_id_from_username(&payment.to_username)
+                .await
+                .map_err(|_| {
+                    error!(
+                        "Failed to find account ID corresponding to username: {}",
+                        payment.to_username
+                    )
+                })?;
+
+            debug!(
+                "Publishing payment notification {:?} for account {}",
+                payment, account_id
+            );
+            match self_clone.subscriptions.read().get(&account_id) {
+                Some(sender) => {
+                    if let Err(err) = sender.unbounded_send(payment) {
+                        error!("Failed to send message: {}", err);
+                    }
+                }
+                None => trace!(
+                    "Ignoring message for account {} because there were no open subscriptions",
+                    account_id
+                ),
+            }
+            Ok::<(), ()>(())
+        });
+    }
+}
+
+/// Reads a row of the stream_connections table
+fn stream_connection_from_row(row: &AnyRow) -> Result<StreamConnectionDetails, sqlx::Error> {
+    Ok(StreamConnectionDetails {
+        total_received: row.try_get::<i64, _>("total_received")? as u64,
+        receive_max: row
+            .try_get::<Option<i64>, _>("receive_max")?
+            .map(|receive_max| receive_max as u64),
+        closed: row.try_get::<i64, _>("closed")? != 0,
+        last_activity: row.try_get::<i64, _>("last_activity")? as u64,
+    })
+}
+
+/// Reads a row of the payments table
+fn payment_from_row(row: &AnyRow) -> Result<PaymentRecord, SqlStoreError> {
+    Ok(PaymentRecord {
+        id: get_parsed(row, "id")?,
+        account_id: get_parsed(row, "account_id")?,
+        direction: get_parsed(row, "direction")?,
+        status: get_parsed(row, "status")?,
+        destination: get_parsed(row, "destination")?,
+        counterparty: row.try_get("counterparty")?,
+        amount: row.try_get::<i64, _>("amount")? as u64,
+        asset_code: row.try_get("asset_code")?,
+        asset_scale: row.try_get::<i64, _>("asset_scale")? as u8,
+        delivered_amount: row
+            .try_get::<Option<i64>, _>("delivered_amount")?
+            .map(|amount| amount as u64),
+        delivered_asset_code: row.try_get("delivered_asset_code")?,
+        delivered_asset_scale: row
+            .try_get::<Option<i64>, _>("delivered_asset_scale")?
+            .map(|scale| scale as u8),
+        timestamp: row.try_get::<i64, _>("paid_at")? as u64,
+    })
+}
+
+#[async_trait]
+impl StreamConnectionStore for SqlStore {
+    async fn create_stream_connection(
+        &self,
+        connection_tag: &str,
+        receive_max: Option<u64>,
+    ) -> Result<(), StreamConnectionStoreError> {
+        let values = vec![
+            Value::from(connection_tag.to_string()),
+            Value::from(receive_max.map(|receive_max| receive_max as i64)),
+            Value::from(now()),
+        ];
+        let expressions = Value::expressions(&values, 1);
+        let sql = format!(
+            "INSERT INTO stream_connections
+                (connection_tag, total_received, receive_max, closed, last_activity)
+            VALUES ({}, 0, {}, 0, {})
+            ON CONFLICT (connection_tag) DO UPDATE SET total_received = 0,
+                receive_max = excluded.receive_max, closed = 0,
+                last_activity = excluded.last_activity",
+            expressions[0], expressions[1], expressions[2]
+        );
+        Value::bind_all(sqlx::query(&sql), values)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        let row = sqlx::query(
+            "SELECT total_received, receive_max, closed, last_activity FROM stream_connections
+            WHERE connection_tag = $1",
+        )
+        .bind(connection_tag)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.as_ref().map(stream_connection_from_row).transpose()?)
+    }
+
+    async fn add_stream_connection_amount(
+        &self,
+        connection_tag: &str,
+        amount: u64,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO stream_connections (connection_tag, last_activity) VALUES ($1, $2)
+            ON CONFLICT (connection_tag) DO NOTHING",
+        )
+        .bind(connection_tag)
+        .bind(now())
+        .execute(&mut tx)
+        .await?;
+        // Only add the amount if the connection is open and stays within its receive max
+        let done = sqlx::query(
+            "UPDATE stream_connections SET total_received = total_received + $1,
+                last_activity = $2
+            WHERE connection_tag = $3 AND closed = 0
+                AND (receive_max IS NULL OR total_received + $1 <= receive_max)",
+        )
+        .bind(amount as i64)
+        .bind(now())
+        .bind(connection_tag)
+        .execute(&mut tx)
+        .await?;
+        if done.rows_affected() == 0 {
+            debug!(
+                "Not adding {} to STREAM connection {} which is closed or would exceed its receive max",
+                amount, connection_tag
+            );
+            return Ok(None);
+        }
+        let row = sqlx::query(
+            "SELECT total_received, receive_max, closed, last_activity FROM stream_connections
+            WHERE connection_tag = $1",
+        )
+        .bind(connection_tag)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(stream_connection_from_row(&row)?))
+    }
+
+    async fn close_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<(), StreamConnectionStoreError> {
+        sqlx::query("UPDATE stream_connections SET closed = 1 WHERE connection_tag = $1")
+            .bind(connection_tag)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_idle_stream_connections(
+        &self,
+        idle_timeout: Duration,
+    ) -> Result<(), StreamConnectionStoreError> {
+        let done = sqlx::query("DELETE FROM stream_connections WHERE last_activity <= $1")
+            .bind(now() - idle_timeout.as_secs() as i64)
+            .execute(&self.pool)
+            .await?;
+        trace!("Deleted {} idle STREAM connections", done.rows_affected());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PaymentHistoryStore for SqlStore {
+    async fn save_payment(&self, payment: PaymentRecord) -> Result<(), PaymentHistoryStoreError> {
+        let values = vec![
+            Value::from(payment.id.to_string()),
+            Value::from(payment.account_id.to_string()),
+            Value::from(payment.direction.as_str().to_string()),
+            Value::from(payment.status.as_str().to_string()),
+            Value::from(payment.destination.to_string()),
+            Value::from(payment.counterparty),
+            Value::from(payment.amount as i64),
+            Value::from(payment.asset_code),
+            Value::from(i64::from(payment.asset_scale)),
+            Value::from(payment.delivered_amount.map(|amount| amount as i64)),
+            Value::from(payment.delivered_asset_code),
+            Value::from(payment.delivered_asset_scale.map(i64::from)),
+            Value::from(payment.timestamp as i64),
+        ];
+        let sql = format!(
+            "INSERT INTO payments
+                (id, account_id, direction, status, destination, counterparty, amount,
+                asset_code, asset_scale, delivered_amount, delivered_asset_code,
+                delivered_asset_scale, paid_at)
+            VALUES ({})",
+            Value::expressions(&values, 1).join(", ")
+        );
+        Value::bind_all(sqlx::query(&sql), values)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_payments(
+        &self,
+        account_id: Uuid,
+        query: PaymentQuery,
+    ) -> Result<Vec<PaymentRecord>, PaymentHistoryStoreError> {
+        let mut conditions = vec!["account_id = $1".to_string()];
+        let mut values = vec![Value::from(account_id.to_string())];
+        if let Some(direction) = query.direction {
+            values.push(Value::from(direction.as_str().to_string()));
+            conditions.push(format!("direction = ${}", values.len()));
+        }
+        if let Some(status) = query.status {
+            values.push(Value::from(status.as_str().to_string()));
+            conditions.push(format!("status = ${}", values.len()));
+        }
+        if let Some(since) = query.since {
+            values.push(Value::from(since as i64));
+            conditions.push(format!("paid_at >= ${}", values.len()));
+        }
+        if let Some(until) = query.until {
+            values.push(Value::from(until as i64));
+            conditions.push(format!("paid_at <= ${}", values.len()));
+        }
+        values.push(Value::from(query.limit() as i64));
+        values.push(Value::from(query.offset as i64));
+        let sql = format!(
+            "SELECT id, account_id, direction, status, destination, counterparty, amount,
+                asset_code, asset_scale, delivered_amount, delivered_asset_code,
+                delivered_asset_scale, paid_at
+            FROM payments WHERE {}
+            ORDER BY paid_at DESC, id DESC LIMIT ${} OFFSET ${}",
+            conditions.join(" AND "),
+            values.len() - 1,
+            values.len()
+        );
+        let rows = Value::bind_all(sqlx::query(&sql), values)
+            .fetch_all(&self.pool)
+            .await?;
+        let payments = rows
+            .iter()
+            .map(payment_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(payments)
+    }
+}
+
+/// Reads a row of the api_tokens table
+fn api_token_from_row(row: &AnyRow) -> Result<ApiToken, SqlStoreError> {
+    let scopes: String = row.try_get("scopes")?;
+    let scopes = scopes
+        .split(',')
+        .map(|scope| scope.parse())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| SqlStoreError::InvalidColumn("scopes"))?;
+    let account_id = match row.try_get::<Option<String>, _>("account_id")? {
+        Some(account_id) => Some(
+            Uuid::from_str(&account_id).map_err(|_| SqlStoreError::InvalidColumn("account_id"))?,
+        ),
+        None => None,
+    };
+    Ok(ApiToken {
+        id: get_parsed(row, "id")?,
+        account_id,
+        scopes,
+        expires_at: row
+            .try_get::<Option<i64>, _>("expires_at")?
+            .map(|expires_at| expires_at as u64),
+        created_at: row.try_get::<i64, _>("created_at")? as u64,
+        description: row.try_get("description")?,
+    })
+}
+
+#[async_trait]
+impl ApiTokenStore for SqlStore {
+    async fn insert_api_token(
+        &self,
+        token: ApiToken,
+        token_hash: [u8; 32],
+    ) -> Result<(), ApiTokenStoreError> {
+        let scopes: Vec<&str> = token.scopes.iter().map(|scope| scope.as_str()).collect();
+        let values = vec![
+            Value::from(token.id.to_string()),
+            Value::from(hex::encode(&token_hash[..])),
+            Value::from(token.account_id.map(|id| id.to_string())),
+            Value::from(scopes.join(",")),
+            Value::from(token.expires_at.map(|expires_at| expires_at as i64)),
+            Value::from(token.created_at as i64),
+            Value::from(token.description),
+        ];
+        let sql = format!(
+            "INSERT INTO api_tokens
+                (id, token_hash, account_id, scopes, expires_at, created_at, description)
+            VALUES ({})",
+            Value::expressions(&values, 1).join(", ")
+        );
+        Value::bind_all(sqlx::query(&sql), values)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_api_token(
+        &self,
+        token_hash: [u8; 32],
+    ) -> Result<Option<ApiToken>, ApiTokenStoreError> {
+        let row = sqlx::query(
+            "SELECT id, account_id, scopes, expires_at, created_at, description
+            FROM api_tokens WHERE token_hash = $1",
+        )
+        .bind(hex::encode(&token_hash[..]))
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.as_ref().map(api_token_from_row).transpose()?)
+    }
+
+    async fn get_all_api_tokens(&self) -> Result<Vec<ApiToken>, ApiTokenStoreError> {
+        let rows = sqlx::query(
+            "SELECT id, account_id, scopes, expires_at, created_at, description
+            FROM api_tokens ORDER BY created_at, id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let tokens = rows
+            .iter()
+            .map(api_token_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tokens)
+    }
+
+    async fn delete_api_token(&self, id: Uuid) -> Result<ApiToken, ApiTokenStoreError> {
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query(
+            "SELECT id, account_id, scopes, expires_at, created_at, description
+            FROM api_tokens WHERE id = $1",
+        )
+        .bind(id.to_string())
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| ApiTokenStoreError::TokenNotFound(id.to_string()))?;
+        let token = api_token_from_row(&row)?;
+        sqlx::query("DELETE FROM api_tokens WHERE id = $1")
+            .bind(id.to_string())
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(token)
+    }
+}
+
+/// Reads a row of the audit_log table
+fn audit_entry_from_row(row: &AnyRow) -> Result<AuditLogEntry, SqlStoreError> {
+    let before: Option<String> = row.try_get("before_value")?;
+    let after: Option<String> = row.try_get("after_value")?;
+    Ok(AuditLogEntry {
+        id: get_parsed(row, "id")?,
+        timestamp: row.try_get::<i64, _>("logged_at")? as u64,
+        actor: row.try_get("actor")?,
+        action: get_parsed(row, "action")?,
+        target: row.try_get("target")?,
+        before: before.map(|json| serde_json::from_str(&json)).transpose()?,
+        after: after.map(|json| serde_json::from_str(&json)).transpose()?,
+    })
+}
+
+#[async_trait]
+impl AuditLogStore for SqlStore {
+    async fn append_audit_entry(&self, entry: AuditLogEntry) -> Result<(), AuditLogStoreError> {
+        let values = vec![
+            Value::from(entry.id.to_string()),
+            Value::from(entry.timestamp as i64),
+            Value::from(entry.actor),
+            Value::from(entry.action.as_str().to_string()),
+            Value::from(entry.target),
+            Value::from(entry.before.map(|before| before.to_string())),
+            Value::from(entry.after.map(|after| after.to_string())),
+        ];
+        let sql = format!(
+            "INSERT INTO audit_log
+                (id, logged_at, actor, action, target, before_value, after_value)
+            VALUES ({})",
+            Value::expressions(&values, 1).join(", ")
+        );
+        Value::bind_all(sqlx::query(&sql), values)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_audit_entries(
+        &self,
+        query: AuditQuery,
+    ) -> Result<Vec<AuditLogEntry>, AuditLogStoreError> {
+        let mut conditions = vec!["1 = 1".to_string()];
+        let mut values = Vec::new();
+        if let Some(actor) = query.actor.clone() {
+            values.push(Value::from(actor));
+            conditions.push(format!("actor = ${}", values.len()));
+        }
+        if let Some(action) = query.action {
+            values.push(Value::from(action.as_str().to_string()));
+            conditions.push(format!("action = ${}", values.len()));
+        }
+        if let Some(target) = query.target.clone() {
+            values.push(Value::from(target));
+            conditions.push(format!("target = ${}", values.len()));
+        }
+        if let Some(since) = query.since {
+            values.push(Value::from(since as i64));
+            conditions.push(format!("logged_at >= ${}", values.len()));
+        }
+        if let Some(until) = query.until {
+            values.push(Value::from(until as i64));
+            conditions.push(format!("logged_at <= ${}", values.len()));
+        }
+        values.push(Value::from(query.limit() as i64));
+        values.push(Value::from(query.offset as i64));
+        let sql = format!(
+            "SELECT id, logged_at, actor, action, target, before_value, after_value
+            FROM audit_log WHERE {}
+            ORDER BY logged_at DESC, id DESC LIMIT ${} OFFSET ${}",
+            conditions.join(" AND "),
+            values.len() - 1,
+            values.len()
+        );
+        let rows = Value::bind_all(sqlx::query(&sql), values)
+            .fetch_all(&self.pool)
+            .await?;
+        let entries = rows
+            .iter()
+            .map(audit_entry_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl FeeStore for SqlStore {
+    fn get_fee_schedules(&self) -> Arc<FeeSchedules> {
+        self.fee_schedules.read().clone()
+    }
+
+    async fn set_fee_schedules(&self, schedules: FeeSchedules) -> Result<(), FeeStoreError> {
+        let json = serde_json::to_string(&schedules).map_err(SqlStoreError::from)?;
+        sqlx::query(
+            "INSERT INTO settings (name, value) VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+        )
+        .bind(FEE_SCHEDULES_KEY)
+        .bind(json)
+        .execute(&self.pool)
+        .await?;
+        *self.fee_schedules.write() = Arc::new(schedules);
+        Ok(())
+    }
+
+    async fn record_fee_revenue(&self, account_id: Uuid, fee: u64) -> Result<(), FeeStoreError> {
+        let fee = i64::try_from(fee).map_err(|_| SqlStoreError::InvalidColumn("amount"))?;
+        sqlx::query(
+            "INSERT INTO fee_revenue (account_id, amount) VALUES ($1, $2)
+            ON CONFLICT (account_id) DO UPDATE SET amount = fee_revenue.amount + excluded.amount",
+        )
+        .bind(account_id.to_string())
+        .bind(fee)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_fee_revenue(&self) -> Result<HashMap<Uuid, u64>, FeeStoreError> {
+        let rows = sqlx::query("SELECT account_id, amount FROM fee_revenue")
+            .fetch_all(&self.pool)
+            .await?;
+        let revenue = rows
+            .iter()
+            .map(|row| {
+                let amount: i64 = row.try_get("amount")?;
+                Ok((get_parsed(row, "account_id")?, amount as u64))
+            })
+            .collect::<Result<HashMap<Uuid, u64>, SqlStoreError>>()?;
+        Ok(revenue)
+    }
+}
+
+#[async_trait]
+impl BalanceStore for SqlStore {
+    /// Returns the balance **from the account holder's perspective**, meaning the sum of
+    /// the Payable Balance and Pending Outgoing minus the Receivable Balance and the Pending Incoming.
+    async fn get_balance(&self, account_id: Uuid) -> Result<i64, BalanceStoreError> {
+        let row = sqlx::query("SELECT balance, prepaid_amount FROM accounts WHERE id = $1")
+            .bind(account_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(SqlStoreError::AccountNotFound(account_id))?;
+        let balance: i64 = row.try_get("balance")?;
+        let prepaid_amount: i64 = row.try_get("prepaid_amount")?;
+        Ok(balance + prepaid_amount)
+    }
+
+    async fn update_balances_for_prepare(
+        &self,
+        from_account_id: Uuid,
+        incoming_amount: u64,
+    ) -> Result<(), BalanceStoreError> {
+        // Don't do anything if the amount was 0
+        if incoming_amount == 0 {
+            return Ok(());
+        }
+
+        // Deduct the amount from the prepaid_amount and/or the balance, unless
+        // doing so would bring the account under its minimum balance
+        let done = sqlx::query(
+            "UPDATE accounts SET
+                balance = CASE WHEN prepaid_amount >= $1 THEN balance
+                    ELSE balance - ($1 - prepaid_amount) END,
+                prepaid_amount = CASE WHEN prepaid_amount >= $1 THEN prepaid_amount - $1
+                    ELSE 0 END
+            WHERE id = $2
+                AND (min_balance IS NULL OR balance + prepaid_amount - $1 >= min_balance)",
+        )
+        .bind(incoming_amount as i64)
+        .bind(from_account_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        if done.rows_affected() == 0 {
+            let row = sqlx::query("SELECT balance, min_balance FROM accounts WHERE id = $1")
+                .bind(from_account_id.to_string())
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or(SqlStoreError::AccountNotFound(from_account_id))?;
+            return Err(SqlStoreError::BelowMinBalance {
+                account_id: from_account_id,
+                amount: incoming_amount,
+                balance: row.try_get("balance")?,
+                min_balance: row
+                    .try_get::<Option<i64>, _>("min_balance")?
+                    .unwrap_or_default(),
+            }
+            .into());
+        }
+
+        trace!(
+            "Processed prepare with incoming amount: {} for account {}",
+            incoming_amount,
+            from_account_id
+        );
+        Ok(())
+    }
+
+    async fn update_balances_for_fulfill(
+        &self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+    ) -> Result<(i64, u64), BalanceStoreError> {
+        let mut tx = self.pool.begin().await?;
+        let (balance, amount_to_settle) =
+            process_fulfill(&mut tx, to_account_id, outgoing_amount).await?;
+        tx.commit().await?;
+
+        trace!(
+            "Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
+            to_account_id,
+            outgoing_amount,
+            balance,
+            amount_to_settle,
+        );
+        Ok((balance, amount_to_settle))
+    }
+
+    async fn update_balances_for_fulfill_with_settlement(
+        &self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+        settlement: OutgoingSettlement,
+    ) -> Result<(i64, Option<OutgoingSettlement>), BalanceStoreError> {
+        // The settlement is saved in the same transaction as the balance change
+        let mut tx = self.pool.begin().await?;
+        let (balance, amount_to_settle) =
+            process_fulfill(&mut tx, to_account_id, outgoing_amount).await?;
+        let settlement = if amount_to_settle > 0 {
+            let settlement = OutgoingSettlement {
+                amount: amount_to_settle,
+                ..settlement
+            };
+            insert_outgoing_settlement(&mut tx, &settlement).await?;
+            Some(settlement)
+        } else {
+            None
+        };
+        tx.commit().await?;
+
+        trace!(
+            "Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
+            to_account_id,
+            outgoing_amount,
+            balance,
+            amount_to_settle,
+        );
+        Ok((balance, settlement))
+    }
+
+    async fn update_balances_for_reject(
+        &self,
+        from_account_id: Uuid,
+        incoming_amount: u64,
+    ) -> Result<(), BalanceStoreError> {
+        if incoming_amount == 0 {
+            return Ok(());
+        }
+
+        let done = sqlx::query("UPDATE accounts SET balance = balance + $1 WHERE id = $2")
+            .bind(incoming_amount as i64)
+            .bind(from_account_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(SqlStoreError::AccountNotFound(from_account_id).into());
+        }
+
+        trace!(
+            "Processed reject for incoming amount: {} for account {}",
+            incoming_amount,
+            from_account_id
+        );
+        Ok(())
+    }
+}
+
+impl ExchangeRateStore for SqlStore {
+    fn get_exchange_rates(
+        &self,
+        asset_codes: &[&str],
+    ) -> Result<Vec<BigRational>, ExchangeRateStoreError> {
+        let rates: Vec<BigRational> = asset_codes
+            .iter()
+            .filter_map(|code| (*self.exchange_rates.read()).get(*code).cloned())
+            .collect();
+        if rates.len() == asset_codes.len() {
+            Ok(rates)
+        } else {
+            Err(ExchangeRateStoreError::PairNotFound {
+                from: asset_codes[0].to_string(),
+                to: asset_codes[1].to_string(),
+            })
+        }
+    }
+
+    fn get_all_exchange_rates(
+        &self,
+    ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError> {
+        Ok((*self.exchange_rates.read()).clone())
+    }
+
+    fn set_exchange_rates(
+        &self,
+        rates: HashMap<String, BigRational>,
+    ) -> Result<(), ExchangeRateStoreError> {
+        // TODO publish rate updates through the database so that every node uses the same ones
+        (*self.exchange_rates.write()) = rates;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BtpStore for SqlStore {
+    type Account = Account;
+
+    async fn get_account_from_btp_auth(
+        &self,
+        username: &Username,
+        token: &str,
+    ) -> Result<Self::Account, BtpStoreError> {
+        if let Some(account) = self.load_account_from_username(username).await? {
+            if let Some(ref t) = account.ilp_over_btp_incoming_token {
+                let t = t.expose_secret();
+                if t.as_ref() == token.as_bytes() {
+                    Ok(account)
+                } else {
+                    debug!(
+                        "Found account {} but BTP auth token was wrong",
+                        account.username
+                    );
+                    Err(BtpStoreError::Unauthorized(username.to_string()))
+                }
+            } else {
+                debug!(
+                    "Account {} does not have an incoming btp token configured",
+                    account.username
+                );
+                Err(BtpStoreError::Unauthorized(username.to_string()))
+            }
+        } else {
+            warn!("No account found with BTP token");
+            Err(BtpStoreError::AccountNotFound(username.to_string()))
+        }
+    }
+
+    async fn get_btp_outgoing_accounts(&self) -> Result<Vec<Self::Account>, BtpStoreError> {
+        Ok(self
+            .load_accounts_where("accounts.ilp_over_btp_url IS NOT NULL", Vec::new())
+            .await?)
+    }
+}
+
+#[async_trait]
+impl HttpStore for SqlStore {
+    type Account = Account;
+
+    /// Checks if the stored token for the provided account id matches the
+    /// provided token, and if so, returns the account associated with that token
+    async fn get_account_from_http_auth(
+        &self,
+        username: &Username,
+        token: &str,
+    ) -> Result<Self::Account, HttpStoreError> {
+        if let Some(account) = self.load_account_from_username(username).await? {
+            if let Some(ref t) = account.ilp_over_http_incoming_token {
+                let t = t.expose_secret();
+                if t.as_ref() == token.as_bytes() {
+                    Ok(account)
+                } else {
+                    Err(HttpStoreError::Unauthorized(username.to_string()))
+                }
+            } else {
+                Err(HttpStoreError::Unauthorized(username.to_string()))
+            }
+        } else {
+            warn!("No account found with given HTTP auth");
+            Err(HttpStoreError::AccountNotFound(username.to_string()))
+        }
+    }
+}
+
+impl RouterStore for SqlStore {
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+        self.routes.read().clone()
+    }
+
+    fn multipath_routing_table(&self) -> Arc<PrefixMap<Vec<NextHop>>> {
+        self.multipath_routes.read().clone()
+    }
+}
+
+#[async_trait]
+impl NodeStore for SqlStore {
+    type Account = Account;
+
+    async fn insert_account(
+        &self,
+        account: AccountDetails,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let id = Uuid::new_v4();
+        let account = Account::try_from(id, account, self.get_ilp_address())
+            .map_err(NodeStoreError::InvalidAccount)?;
+        debug!(
+            "Generated account id for {}: {}",
+            account.username, account.id
+        );
+        let encrypted = account
+            .clone()
+            .encrypt_tokens(&self.encryption_key.expose_secret().0);
+
+        let mut tx = self.pool.begin().await?;
+        // Check that there isn't already an account with values that MUST be unique
+        let username_exists = sqlx::query("SELECT id FROM accounts WHERE username = $1")
+            .bind(account.username.to_string())
+            .fetch_optional(&mut tx)
+            .await?
+            .is_some();
+        let parent_exists = account.routing_relation == RoutingRelation::Parent
+            && sqlx::query("SELECT value FROM settings WHERE name = $1")
+                .bind(PARENT_ILP_KEY)
+                .fetch_optional(&mut tx)
+                .await?
+                .is_some();
+        if username_exists || parent_exists {
+            warn!(
+                "An account already exists with the same {}. Cannot insert account: {:?}",
+                account.id, account
+            );
+            return Err(NodeStoreError::AccountExists(account.username.to_string()));
+        }
+
+        self.save_account(&mut tx, &encrypted, true).await?;
+        sqlx::query(
+            "INSERT INTO routes (prefix, account_id) VALUES ($1, $2)
+            ON CONFLICT (prefix) DO UPDATE SET account_id = excluded.account_id",
+        )
+        .bind(account.ilp_address.to_string())
+        .bind(id.to_string())
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        self.update_routes().await?;
+        debug!(
+            "Inserted account {} (ILP address: {})",
+            account.id, account.ilp_address
+        );
+        Ok(account)
+    }
+
+    async fn delete_account(&self, id: Uuid) -> Result<Account, NodeStoreError> {
+        let account = self
+            .load_account(id)
+            .await?
+            .ok_or_else(|| NodeStoreError::AccountNotFound(id.to_string()))?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM accounts WHERE id = $1")
+            .bind(id.to_string())
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM routes WHERE prefix = $1")
+            .bind(account.ilp_address.to_string())
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM uncredited_settlement_amounts WHERE account_id = $1")
+            .bind(id.to_string())
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        self.update_routes().await?;
+        self.throttle.write().clear_prefix(&format!("limit:{}", id));
+        debug!("Deleted account {}", account.id);
+        Ok(account)
+    }
+
+    async fn update_account(
+        &self,
+        id: Uuid,
+        account: AccountDetails,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let account = Account::try_from(id, account, self.get_ilp_address())
+            .map_err(NodeStoreError::InvalidAccount)?;
+        let encrypted = account
+            .clone()
+            .encrypt_tokens(&self.encryption_key.expose_secret().0);
+
+        let mut tx = self.pool.begin().await?;
+        let old_ilp_address: String =
+            match sqlx::query("SELECT ilp_address FROM accounts WHERE id = $1")
+                .bind(id.to_string())
+                .fetch_optional(&mut tx)
+                .await?
+            {
+                Some(row) => row.try_get("ilp_address")?,
+                None => {
+                    warn!(
+                        "No account exists with ID {}, cannot update account {:?}",
+                        account.id, account
+                    );
+                    return Err(NodeStoreError::AccountNotFound(account.id.to_string()));
+                }
+            };
+
+        self.save_account(&mut tx, &encrypted, false).await?;
+        sqlx::query("DELETE FROM routes WHERE prefix = $1")
+            .bind(old_ilp_address)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO routes (prefix, account_id) VALUES ($1, $2)
+            ON CONFLICT (prefix) DO UPDATE SET account_id = excluded.account_id",
+        )
+        .bind(account.ilp_address.to_string())
+        .bind(id.to_string())
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        self.update_routes().await?;
+        debug!(
+            "Updated account {} (id: {}, ILP address: {})",
+            account.username, account.id, account.ilp_address
+        );
+        Ok(account)
+    }
+
+    async fn modify_account_settings(
+        &self,
+        id: Uuid,
+        settings: AccountSettings,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let mut columns: Vec<(&str, Value)> = Vec::new();
+        if let Some(ref url) = settings.ilp_over_http_url {
+            let url = Url::parse(url).map_err(|err| {
+                NodeStoreError::InvalidAccount(CreateAccountError::InvalidHttpUrl(err))
+            })?;
+            columns.push(("ilp_over_http_url", url.to_string().into()));
+        }
+        if let Some(ref url) = settings.ilp_over_btp_url {
+            let url = Url::parse(url).map_err(|err| {
+                NodeStoreError::InvalidAccount(CreateAccountError::InvalidBtpUrl(err))
+            })?;
+            columns.push(("ilp_over_btp_url", url.to_string().into()));
+        }
+        if let Some(settle_to) = settings.settle_to {
+            if settle_to > i64::MAX as u64 {
+                return Err(NodeStoreError::InvalidAccount(
+                    CreateAccountError::ParamTooLarge("settle_to".to_owned()),
+                ));
+            }
+        }
+
+        let encryption_key = &self.encryption_key.expose_secret().0;
+        let tokens = vec![
+            (
+                "ilp_over_btp_outgoing_token",
+                settings.ilp_over_btp_outgoing_token,
+            ),
+            (
+                "ilp_over_http_outgoing_token",
+                settings.ilp_over_http_outgoing_token,
+            ),
+            (
+                "ilp_over_btp_incoming_token",
+                settings.ilp_over_btp_incoming_token,
+            ),
+            (
+                "ilp_over_http_incoming_token",
+                settings.ilp_over_http_incoming_token,
+            ),
+        ];
+        for (column, token) in tokens {
+            if let Some(token) = token {
+                let encrypted = encrypt_token(encryption_key, token.expose_secret().as_bytes());
+                columns.push((column, hex::encode(&encrypted[..]).into()));
+            }
+        }
+
+        if let Some(settle_threshold) = settings.settle_threshold {
+            columns.push(("settle_threshold", settle_threshold.into()));
+        }
+        if let Some(settle_to) = settings.settle_to {
+            columns.push(("settle_to", (settle_to as i64).into()));
+        }
+        if let Some(threshold) = settings.circuit_breaker_failure_threshold {
+            columns.push((
+                "circuit_breaker_failure_threshold",
+                i64::from(threshold).into(),
+            ));
+        }
+        if let Some(cool_down) = settings.circuit_breaker_cool_down {
+            columns.push(("circuit_breaker_cool_down", cool_down.to_string().into()));
+        }
+
+        if !columns.is_empty() {
+            let mut tx = self.pool.begin().await?;
+            update_account_columns(&mut tx, id, columns).await?;
+            tx.commit().await?;
+        }
+
+        // return the updated account
+        self.load_account(id)
+            .await?
+            .ok_or_else(|| NodeStoreError::AccountNotFound(id.to_string()))
+    }
+
+    async fn get_all_accounts(&self) -> Result<Vec<Self::Account>, NodeStoreError> {
+        Ok(self.load_all_accounts().await?)
+    }
+
+    async fn set_static_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Uuid)> + Send + 'async_trait,
+    {
+        let routes: HashMap<String, Uuid> = routes.into_iter().collect();
+        let account_ids: Vec<Uuid> = routes.values().cloned().collect();
+        if !self.accounts_exist(&account_ids).await? {
+            error!("Error setting static routes because not all of the given accounts exist");
+            return Err(NodeStoreError::MissingAccounts);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM static_routes")
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM static_multipath_routes")
+            .execute(&mut tx)
+            .await?;
+        for (prefix, account_id) in routes {
+            sqlx::query("INSERT INTO static_routes (prefix, account_id) VALUES ($1, $2)")
+                .bind(prefix)
+                .bind(account_id.to_string())
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        self.update_routes().await?;
+        Ok(())
+    }
+
+    async fn set_static_multipath_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    {
+        let routes: HashMap<String, Vec<NextHop>> = routes
+            .into_iter()
+            .filter(|(_, next_hops)| !next_hops.is_empty())
+            .collect();
+        let account_ids: Vec<Uuid> = routes
+            .values()
+            .flatten()
+            .map(|next_hop| next_hop.account_id)
+            .collect();
+        if !self.accounts_exist(&account_ids).await? {
+            error!("Error setting static routes because not all of the given accounts exist");
+            return Err(NodeStoreError::MissingAccounts);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM static_routes")
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM static_multipath_routes")
+            .execute(&mut tx)
+            .await?;
+        for (prefix, next_hops) in routes {
+            sqlx::query("INSERT INTO static_routes (prefix, account_id) VALUES ($1, $2)")
+                .bind(prefix.clone())
+                .bind(next_hops[0].account_id.to_string())
+                .execute(&mut tx)
+                .await?;
+            if next_hops.len() > 1 {
+                sqlx::query(
+                    "INSERT INTO static_multipath_routes (prefix, next_hops) VALUES ($1, $2)",
+                )
+                .bind(prefix)
+                .bind(serde_json::to_string(&next_hops).map_err(SqlStoreError::from)?)
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        self.update_routes().await?;
+        Ok(())
+    }
+
+    async fn set_static_route(
+        &self,
+        prefix: String,
+        account_id: Uuid,
+    ) -> Result<(), NodeStoreError> {
+        if !self.accounts_exist(&[account_id]).await? {
+            error!(
+                "Cannot set static route for prefix: {} because account {} does not exist",
+                prefix, account_id
+            );
+            return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM static_multipath_routes WHERE prefix = $1")
+            .bind(prefix.clone())
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO static_routes (prefix, account_id) VALUES ($1, $2)
+            ON CONFLICT (prefix) DO UPDATE SET account_id = excluded.account_id",
+        )
+        .bind(prefix)
+        .bind(account_id.to_string())
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        self.update_routes().await?;
+        Ok(())
+    }
+
+    async fn set_default_route(&self, account_id: Uuid) -> Result<(), NodeStoreError> {
+        if !self.accounts_exist(&[account_id]).await? {
+            error!(
+                "Cannot set default route because account {} does not exist",
+                account_id
+            );
+            return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
+        }
+
+        sqlx::query(
+            "INSERT INTO settings (name, value) VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+        )
+        .bind(DEFAULT_ROUTE_KEY)
+        .bind(account_id.to_string())
+        .execute(&self.pool)
+        .await?;
+        debug!("Set default route to account id: {}", account_id);
+
+        self.update_routes().await?;
+        Ok(())
+    }
+
+    async fn set_settlement_engines(
+        &self,
+        asset_to_url_map: impl IntoIterator<Item = (String, Url)> + Send + 'async_trait,
+    ) -> Result<(), NodeStoreError> {
+        let asset_to_url_map: Vec<(String, Url)> = asset_to_url_map.into_iter().collect();
+        debug!("Setting settlement engines to {:?}", asset_to_url_map);
+        let mut tx = self.pool.begin().await?;
+        for (asset_code, url) in asset_to_url_map {
+            sqlx::query(
+                "INSERT INTO settlement_engines (asset_code, url) VALUES ($1, $2)
+                ON CONFLICT (asset_code) DO UPDATE SET url = excluded.url",
+            )
+            .bind(asset_code)
+            .bind(url.to_string())
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_asset_settlement_engine(
+        &self,
+        asset_code: &str,
+    ) -> Result<Option<Url>, NodeStoreError> {
+        let row = sqlx::query("SELECT url FROM settlement_engines WHERE asset_code = $1")
+            .bind(asset_code)
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(row) => {
+                let url: String = row.try_get("url")?;
+                Url::parse(&url)
+                    .map(Some)
+                    .map_err(|_| NodeStoreError::InvalidEngineUrl(url))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl AddressStore for SqlStore {
+    // Updates the ILP address of the store & iterates over all children and
+    // updates their ILP Address to match the new address.
+    async fn set_ilp_address(&self, ilp_address: Address) -> Result<(), AddressStoreError> {
+        debug!("Setting ILP address to: {}", ilp_address);
+        // Set the ILP address we have in memory
+        (*self.ilp_address.write()) = ilp_address.clone();
+
+        let accounts = self.load_all_accounts().await?;
+        let first_segment = ilp_address
+            .segments()
+            .next_back()
+            .expect("address did not have a first segment, this should be impossible");
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO settings (name, value) VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+        )
+        .bind(PARENT_ILP_KEY)
+        .bind(ilp_address.to_string())
+        .execute(&mut tx)
+        .await?;
+        for account in &accounts {
+            // Update the address and routes of all children and non-routing accounts.
+            if account.routing_relation() != RoutingRelation::Parent
+                && account.routing_relation() != RoutingRelation::Peer
+            {
+                // if the username of the account ends with the
+                // node's address, we're already configured so no
+                // need to append anything.
+                let new_ilp_address = if first_segment == account.username().to_string() {
+                    ilp_address.clone()
+                } else {
+                    ilp_address
+                        .with_suffix(account.username().as_bytes())
+                        .unwrap()
+                };
+                sqlx::query("UPDATE accounts SET ilp_address = $1 WHERE id = $2")
+                    .bind(new_ilp_address.to_string())
+                    .bind(account.id.to_string())
+                    .execute(&mut tx)
+                    .await?;
+                sqlx::query("DELETE FROM routes WHERE prefix = $1")
+                    .bind(account.ilp_address.to_string())
+                    .execute(&mut tx)
+                    .await?;
+                sqlx::query(
+                    "INSERT INTO routes (prefix, account_id) VALUES ($1, $2)
+                    ON CONFLICT (prefix) DO UPDATE SET account_id = excluded.account_id",
+                )
+                .bind(new_ilp_address.to_string())
+                .bind(account.id.to_string())
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        self.update_routes().await?;
+        Ok(())
+    }
+
+    async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+        sqlx::query("DELETE FROM settings WHERE name = $1")
+            .bind(PARENT_ILP_KEY)
+            .execute(&self.pool)
+            .await?;
+
+        // overwrite the ilp address with the default value
+        *(self.ilp_address.write()) = DEFAULT_ILP_ADDRESS.clone();
+        Ok(())
+    }
+
+    fn get_ilp_address(&self) -> Address {
+        // read consumes the Arc<RwLock<T>> so we cannot return a reference
+        self.ilp_address.read().clone()
+    }
+}
+
+type RoutingTable<A> = HashMap<String, A>;
+
+#[async_trait]
+impl CcpRoutingStore for SqlStore {
+    type Account = Account;
+
+    async fn get_accounts_to_send_routes_to(
+        &self,
+        ignore_accounts: Vec<Uuid>,
+    ) -> Result<Vec<Account>, CcpRoutingStoreError> {
+        let accounts = self.load_all_accounts().await?;
+        Ok(accounts
+            .into_iter()
+            .filter(|account| {
+                account.should_send_routes() && !ignore_accounts.contains(&account.id)
+            })
+            .collect())
+    }
+
+    async fn get_accounts_to_receive_routes_from(
+        &self,
+    ) -> Result<Vec<Account>, CcpRoutingStoreError> {
+        let accounts = self.load_all_accounts().await?;
+        Ok(accounts
+            .into_iter()
+            .filter(|account| account.should_receive_routes())
+            .collect())
+    }
+
+    async fn get_local_and_configured_routes(
+        &self,
+    ) -> Result<(RoutingTable<Account>, RoutingTable<Account>), CcpRoutingStoreError> {
+        let accounts: HashMap<Uuid, Account> = self
+            .load_all_accounts()
+            .await?
+            .into_iter()
+            .map(|account| (account.id, account))
+            .collect();
+
+        let local_table = accounts
+            .values()
+            .map(|account| (account.ilp_address.to_string(), account.clone()))
+            .collect();
+
+        let rows = sqlx::query("SELECT prefix, account_id FROM static_routes")
+            .fetch_all(&self.pool)
+            .await?;
+        let mut configured_table = HashMap::new();
+        for row in rows {
+            let prefix: String = row.try_get("prefix")?;
+            let account_id: Uuid = get_parsed(&row, "account_id")?;
+            if let Some(account) = accounts.get(&account_id) {
+                configured_table.insert(prefix, account.clone());
+            } else {
+                warn!(
+                    "No account for ID: {}, ignoring configured route for prefix: {}",
+                    account_id, prefix
+                );
+            }
+        }
+
+        Ok((local_table, configured_table))
+    }
+
+    async fn set_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Account)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let routes: Vec<(String, Uuid)> = routes
+            .into_iter()
+            .map(|(prefix, account)| (prefix, account.id))
+            .collect();
+        let num_routes = routes.len();
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM routes").execute(&mut tx).await?;
+        for (prefix, account_id) in routes {
+            sqlx::query(
+                "INSERT INTO routes (prefix, account_id) VALUES ($1, $2)
+                ON CONFLICT (prefix) DO UPDATE SET account_id = excluded.account_id",
+            )
+            .bind(prefix)
+            .bind(account_id.to_string())
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        trace!("Saved {} routes to the database", num_routes);
+
+        self.update_routes().await?;
+        Ok(())
+    }
+
+    async fn set_multipath_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let routes: Vec<(String, Vec<NextHop>)> = routes.into_iter().collect();
+        let num_routes = routes.len();
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM multipath_routes")
+            .execute(&mut tx)
+            .await?;
+        for (prefix, next_hops) in routes {
+            sqlx::query("INSERT INTO multipath_routes (prefix, next_hops) VALUES ($1, $2)")
+                .bind(prefix)
+                .bind(serde_json::to_string(&next_hops).map_err(SqlStoreError::from)?)
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        trace!(
+            "Saved {} routes with several next hops to the database",
+            num_routes
+        );
+
+        self.update_routes().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for SqlStore {
+    type Account = Account;
+
+    /// Apply rate limits for number of packets and amount of money per minute,
+    /// and the daily and monthly volume caps
+    ///
+    /// This uses the same algorithm as [redis-cell](https://github.com/brandur/redis-cell)
+    /// which is used by the RedisStore, but the limits are only tracked by this node
+    async fn apply_rate_limits(
+        &self,
+        account: Account,
+        prepare_amount: u64,
+    ) -> Result<(), RateLimitError> {
+        self.throttle
+            .write()
+            .apply_rate_limits(&account, prepare_amount)
+    }
+
+    async fn refund_throughput_limit(
+        &self,
+        account: Account,
+        prepare_amount: u64,
+    ) -> Result<(), RateLimitError> {
+        self.throttle
+            .write()
+            .refund_throughput_limit(&account, prepare_amount);
+        Ok(())
+    }
+
+    async fn reconcile_rate_limits(
+        &self,
+        account: Account,
+        packets: u64,
+        amount: u64,
+    ) -> Result<RemainingRateLimits, RateLimitError> {
+        Ok(self
+            .throttle
+            .write()
+            .reconcile_rate_limits(&account, packets, amount))
+    }
+}
+
+#[async_trait]
+impl IdempotentStore for SqlStore {
+    async fn load_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Result<Option<IdempotentData>, IdempotentStoreError> {
+        let row = sqlx::query(
+            "SELECT status_code, body, input_hash FROM idempotent_data
+            WHERE idempotency_key = $1 AND created_at > $2",
+        )
+        .bind(idempotency_key.clone())
+        .bind(now() - IDEMPOTENCY_KEY_TTL)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(row) = row {
+            let status_code: i64 = row.try_get("status_code")?;
+            let body: String = row.try_get("body")?;
+            let input_hash: String = row.try_get("input_hash")?;
+            let status = StatusCode::from_u16(status_code as u16)
+                .map_err(|err| IdempotentStoreError::Other(Box::new(err)))?;
+            let body =
+                hex::decode(body).map_err(|err| IdempotentStoreError::Other(Box::new(err)))?;
+            let mut hash = [0; 32];
+            hex::decode_to_slice(input_hash, &mut hash)
+                .map_err(|err| IdempotentStoreError::Other(Box::new(err)))?;
+            let data = IdempotentData::new(status, Bytes::from(body), hash);
+            trace!("Loaded idempotency key {:?} - {:?}", idempotency_key, data);
+            Ok(Some(data))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn save_idempotent_data(
+        &self,
+        idempotency_key: String,
+        input_hash: [u8; 32],
+        status_code: StatusCode,
+        data: Bytes,
+    ) -> Result<(), IdempotentStoreError> {
+        trace!(
+            "Cached {:?}: {:?}, {:?}",
+            idempotency_key,
+            status_code,
+            data,
+        );
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM idempotent_data WHERE created_at <= $1")
+            .bind(now() - IDEMPOTENCY_KEY_TTL)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO idempotent_data (idempotency_key, status_code, body, input_hash, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (idempotency_key) DO UPDATE SET status_code = excluded.status_code,
+                body = excluded.body, input_hash = excluded.input_hash,
+                created_at = excluded.created_at",
+        )
+        .bind(idempotency_key)
+        .bind(i64::from(status_code.as_u16()))
+        .bind(hex::encode(&data[..]))
+        .bind(hex::encode(&input_hash[..]))
+        .bind(now())
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SettlementStore for SqlStore {
+    type Account = Account;
+
+    async fn update_balance_for_incoming_settlement(
+        &self,
+        account_id: Uuid,
+        amount: u64,
+        idempotency_key: Option<String>,
+    ) -> Result<(), SettlementStoreError> {
+        let mut tx = self.pool.begin().await?;
+        // If idempotency key has been used, then do not perform any operations
+        if let Some(idempotency_key) = idempotency_key {
+            sqlx::query("DELETE FROM settlement_idempotency_keys WHERE created_at <= $1")
+                .bind(now() - IDEMPOTENCY_KEY_TTL)
+                .execute(&mut tx)
+                .await?;
+            let done = sqlx::query(
+                "INSERT INTO settlement_idempotency_keys (idempotency_key, created_at)
+                VALUES ($1, $2) ON CONFLICT (idempotency_key) DO NOTHING",
+            )
+            .bind(idempotency_key)
+            .bind(now())
+            .execute(&mut tx)
+            .await?;
+            if done.rows_affected() == 0 {
+                return Ok(());
+            }
+        }
+
+        // Credit the incoming settlement to the balance and/or prepaid amount,
+        // depending on whether that account currently owes money or not
+        let done = sqlx::query(
+            "UPDATE accounts SET
+                prepaid_amount = CASE WHEN balance >= 0 THEN prepaid_amount + $1
+                    WHEN -balance >= $1 THEN prepaid_amount
+                    ELSE prepaid_amount + $1 + balance END,
+                balance = CASE WHEN balance >= 0 THEN balance
+                    WHEN -balance >= $1 THEN balance + $1
+                    ELSE 0 END
+            WHERE id = $2",
+        )
+        .bind(amount as i64)
+        .bind(account_id.to_string())
+        .execute(&mut tx)
+        .await?;
+        if done.rows_affected() == 0 {
+            return Err(SqlStoreError::AccountNotFound(account_id).into());
+        }
+        tx.commit().await?;
+
+        trace!(
+            "Processed incoming settlement from account: {} for amount: {}",
+            account_id,
+            amount
+        );
+        Ok(())
+    }
+
+    async fn refund_settlement(
+        &self,
+        account_id: Uuid,
+        settle_amount: u64,
+    ) -> Result<(), SettlementStoreError> {
+        trace!(
+            "Refunding settlement for account: {} of amount: {}",
+            account_id,
+            settle_amount
+        );
+        let done = sqlx::query("UPDATE accounts SET balance = balance + $1 WHERE id = $2")
+            .bind(settle_amount as i64)
+            .bind(account_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(SqlStoreError::AccountNotFound(account_id).into());
+        }
+        Ok(())
+    }
+}
+
+/// Reads a row of the outgoing_settlements table
+fn outgoing_settlement_from_row(row: &AnyRow) -> Result<OutgoingSettlement, SqlStoreError> {
+    Ok(OutgoingSettlement {
+        idempotency_key: row.try_get("idempotency_key")?,
+        account_id: get_parsed(row, "account_id")?,
+        amount: row.try_get::<i64, _>("amount")? as u64,
+        asset_scale: row.try_get::<i64, _>("asset_scale")? as u8,
+        engine_url: get_parsed(row, "engine_url")?,
+    })
+}
+
+async fn insert_outgoing_settlement(
+    tx: &mut sqlx::Transaction<'_, sqlx::Any>,
+    settlement: &OutgoingSettlement,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO outgoing_settlements
+            (idempotency_key, account_id, amount, asset_scale, engine_url)
+        VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(settlement.idempotency_key.as_str())
+    .bind(settlement.account_id.to_string())
+    .bind(settlement.amount as i64)
+    .bind(i64::from(settlement.asset_scale))
+    .bind(settlement.engine_url.to_string())
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl SettlementOutboxStore for SqlStore {
+    async fn save_outgoing_settlement(
+        &self,
+        settlement: OutgoingSettlement,
+    ) -> Result<(), SettlementStoreError> {
+        let mut tx = self.pool.begin().await?;
+        insert_outgoing_settlement(&mut tx, &settlement).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn complete_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError> {
+        sqlx::query("DELETE FROM outgoing_settlements WHERE idempotency_key = $1")
+            .bind(idempotency_key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn refund_outgoing_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<(), SettlementStoreError> {
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query(
+            "SELECT idempotency_key, account_id, amount, asset_scale, engine_url
+            FROM outgoing_settlements WHERE idempotency_key = $1",
+        )
+        .bind(idempotency_key)
+        .fetch_optional(&mut tx)
+        .await?;
+        let settlement = match row {
+            Some(row) => outgoing_settlement_from_row(&row)?,
+            // It was already completed or refunded
+            None => return Ok(()),
+        };
+        // Only the transaction which deletes the settlement refunds it
+        let done = sqlx::query("DELETE FROM outgoing_settlements WHERE idempotency_key = $1")
+            .bind(idempotency_key)
+            .execute(&mut tx)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Ok(());
+        }
+        let done = sqlx::query("UPDATE accounts SET balance = balance + $1 WHERE id = $2")
+            .bind(settlement.amount as i64)
+            .bind(settlement.account_id.to_string())
+            .execute(&mut tx)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(SqlStoreError::AccountNotFound(settlement.account_id).into());
+        }
+        tx.commit().await?;
+        trace!(
+            "Refunded outgoing settlement {} for account: {} of amount: {}",
+            idempotency_key,
+            settlement.account_id,
+            settlement.amount
+        );
+        Ok(())
+    }
+
+    async fn get_pending_outgoing_settlements(
+        &self,
+    ) -> Result<Vec<OutgoingSettlement>, SettlementStoreError> {
+        let rows = sqlx::query(
+            "SELECT idempotency_key, account_id, amount, asset_scale, engine_url
+            FROM outgoing_settlements",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let settlements = rows
+            .iter()
+            .map(outgoing_settlement_from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(settlements)
+    }
+}
+
+#[async_trait]
+impl LeftoversStore for SqlStore {
+    type AccountId = Uuid;
+    type AssetType = BigUint;
+
+    async fn get_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+    ) -> Result<(Self::AssetType, u8), LeftoversStoreError> {
+        // get the amounts and instantly delete them
+        let mut tx = self.pool.begin().await?;
+        let rows = sqlx::query(
+            "SELECT amount, scale FROM uncredited_settlement_amounts WHERE account_id = $1",
+        )
+        .bind(account_id.to_string())
+        .fetch_all(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM uncredited_settlement_amounts WHERE account_id = $1")
+            .bind(account_id.to_string())
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        let mut amounts = Vec::with_capacity(rows.len());
+        for row in rows {
+            let amount: String = row.try_get("amount")?;
+            let amount = BigUint::from_str(&amount)
+                .map_err(|err| LeftoversStoreError::Other(Box::new(err)))?;
+            let scale: i64 = row.try_get("scale")?;
+            amounts.push((amount, scale as u8));
+        }
+
+        // We must scale them to the largest scale, and then add them together
+        let max_scale = amounts.iter().map(|(_, scale)| *scale).max().unwrap_or(0);
+        let mut sum = BigUint::from(0u32);
+        for (num, scale) in amounts {
+            sum += num
+                .normalize_scale(ConvertDetails {
+                    from: scale,
+                    to: max_scale,
+                })
+                .unwrap();
+        }
+        Ok((sum, max_scale))
+    }
+
+    async fn save_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+        uncredited_settlement_amount: (Self::AssetType, u8),
+    ) -> Result<(), LeftoversStoreError> {
+        trace!(
+            "Saving uncredited_settlement_amount {:?} {:?}",
+            account_id,
+            uncredited_settlement_amount
+        );
+        sqlx::query(
+            "INSERT INTO uncredited_settlement_amounts (account_id, amount, scale)
+            VALUES ($1, $2, $3)",
+        )
+        .bind(account_id.to_string())
+        .bind(uncredited_settlement_amount.0.to_string())
+        .bind(i64::from(uncredited_settlement_amount.1))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+        local_scale: u8,
+    ) -> Result<Self::AssetType, LeftoversStoreError> {
+        trace!("Loading uncredited_settlement_amount {:?}", account_id);
+        let amount = self.get_uncredited_settlement_amount(account_id).await?;
+        // scale the amount from the max scale to the local scale, and then
+        // save any potential leftovers to the store
+        let (scaled_amount, precision_loss) =
+            scale_with_precision_loss(amount.0, local_scale, amount.1);
+
+        if precision_loss > BigUint::from(0u32) {
+            self.save_uncredited_settlement_amount(
+                account_id,
+                (precision_loss, std::cmp::max(local_scale, amount.1)),
+            )
+            .await?;
+        }
+
+        Ok(scaled_amount)
+    }
+
+    async fn clear_uncredited_settlement_amount(
+        &self,
+        account_id: Uuid,
+    ) -> Result<(), LeftoversStoreError> {
+        trace!("Clearing uncredited_settlement_amount {:?}", account_id);
+        sqlx::query("DELETE FROM uncredited_settlement_amounts WHERE account_id = $1")
+            .bind(account_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
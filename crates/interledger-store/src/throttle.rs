@@ -0,0 +1,311 @@
+use crate::account::Account;
+use crate::rate_limits::{packets_limit, throughput_limit, volume_caps, VolumeCap};
+use interledger_service_util::{RateLimitError, RemainingRateLimits};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// In-process equivalent of the `CL.THROTTLE` command provided by
+/// [redis-cell](https://github.com/brandur/redis-cell).
+///
+/// Implements the Generic Cell Rate Algorithm (GCRA): each key stores a
+/// "theoretical arrival time" (TAT) and a request is allowed if the TAT
+/// after adding the request's cost does not run further ahead of the
+/// current time than the burst allows.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    /// Reference point for all the stored arrival times
+    epoch: Instant,
+    /// Theoretical arrival time for each key, in nanoseconds after `epoch`
+    arrival_times: HashMap<String, i128>,
+    /// Volume of the current period for each key, along with the period
+    volumes: HashMap<String, (String, u64)>,
+}
+
+impl Throttle {
+    pub(crate) fn new() -> Self {
+        Throttle {
+            epoch: Instant::now(),
+            arrival_times: HashMap::new(),
+            volumes: HashMap::new(),
+        }
+    }
+
+    /// Applies `quantity` against the limit stored under `key`, allowing
+    /// `count_per_period` units per `period` plus bursts of up to `max_burst`.
+    /// A negative quantity refunds a previously applied amount.
+    /// Returns true if the request was rate limited (and therefore not applied).
+    pub(crate) fn throttle(
+        &mut self,
+        key: &str,
+        max_burst: u64,
+        count_per_period: u64,
+        period: Duration,
+        quantity: i128,
+    ) -> bool {
+        let now = self.epoch.elapsed().as_nanos() as i128;
+        let period = period.as_nanos() as i128;
+        // redis-cell refuses a rate of zero, we treat it as the smallest possible rate instead
+        let count_per_period = i128::from(count_per_period.max(1));
+
+        let increment = quantity * period / count_per_period;
+        let delay_variation_tolerance = (i128::from(max_burst) + 1) * period / count_per_period;
+
+        let tat = self.arrival_times.get(key).cloned().unwrap_or(now).max(now);
+        let new_tat = tat + increment;
+
+        if new_tat - delay_variation_tolerance > now {
+            return true;
+        }
+
+        if new_tat > now {
+            self.arrival_times.insert(key.to_string(), new_tat);
+        } else {
+            // The key has fully recovered so there is nothing left to track
+            self.arrival_times.remove(key);
+        }
+        false
+    }
+
+    /// Units which can be applied right away against the limit stored under `key`
+    pub(crate) fn remaining(
+        &self,
+        key: &str,
+        max_burst: u64,
+        count_per_period: u64,
+        period: Duration,
+    ) -> u64 {
+        let now = self.epoch.elapsed().as_nanos() as i128;
+        let period = period.as_nanos() as i128;
+        let count_per_period = i128::from(count_per_period.max(1));
+
+        let emission_interval = (period / count_per_period).max(1);
+        let delay_variation_tolerance = (i128::from(max_burst) + 1) * period / count_per_period;
+        let tat = self.arrival_times.get(key).cloned().unwrap_or(now).max(now);
+        let remaining = (now + delay_variation_tolerance - tat) / emission_interval;
+        u64::try_from(remaining.max(0)).unwrap_or(u64::max_value())
+    }
+
+    /// Applies up to `quantity` against the limit stored under `key`, as far as it allows,
+    /// and returns the units which can still be applied right away
+    pub(crate) fn charge(
+        &mut self,
+        key: &str,
+        max_burst: u64,
+        count_per_period: u64,
+        period: Duration,
+        quantity: u64,
+    ) -> u64 {
+        let charged = quantity.min(self.remaining(key, max_burst, count_per_period, period));
+        if charged > 0 {
+            self.throttle(
+                key,
+                max_burst,
+                count_per_period,
+                period,
+                i128::from(charged),
+            );
+        }
+        self.remaining(key, max_burst, count_per_period, period)
+    }
+
+    /// The volume of the period stored under `key`
+    pub(crate) fn volume(&self, key: &str, period: &str) -> u64 {
+        match self.volumes.get(key) {
+            Some((current, volume)) if current == period => *volume,
+            _ => 0,
+        }
+    }
+
+    /// Adds `quantity` to the volume of the period stored under `key`, which replaces
+    /// the volume of the previous period. A negative quantity refunds a previously added amount.
+    pub(crate) fn add_volume(&mut self, key: &str, period: &str, quantity: i128) {
+        let volume = i128::from(self.volume(key, period)) + quantity;
+        let volume = u64::try_from(volume.max(0)).unwrap_or(u64::max_value());
+        self.volumes
+            .insert(key.to_string(), (period.to_string(), volume));
+    }
+
+    /// Removes all limits whose key starts with the provided prefix
+    pub(crate) fn clear_prefix(&mut self, prefix: &str) {
+        self.arrival_times.retain(|key, _| !key.starts_with(prefix));
+        self.volumes.retain(|key, _| !key.starts_with(prefix));
+    }
+
+    /// Applies the packet and throughput limits and the volume caps of the account
+    /// to a packet, on behalf of the stores which keep their limits in memory
+    pub(crate) fn apply_rate_limits(
+        &mut self,
+        account: &Account,
+        prepare_amount: u64,
+    ) -> Result<(), RateLimitError> {
+        if let Some(limit) = packets_limit(account) {
+            let packets_limit = format!("limit:{}:packets", account.id);
+            if self.throttle(
+                &packets_limit,
+                limit.max_burst,
+                limit.count_per_minute,
+                MINUTE,
+                1,
+            ) {
+                return Err(RateLimitError::PacketLimitExceeded);
+            }
+        }
+
+        if let Some(limit) = throughput_limit(account) {
+            let throughput_limit = format!("limit:{}:throughput", account.id);
+            if self.throttle(
+                &throughput_limit,
+                limit.max_burst,
+                limit.count_per_minute,
+                MINUTE,
+                i128::from(prepare_amount),
+            ) {
+                return Err(RateLimitError::ThroughputLimitExceeded);
+            }
+        }
+
+        let caps = volume_caps(account);
+        if caps.iter().any(|cap| {
+            self.volume(&volume_key(account, cap), &cap.period)
+                .saturating_add(prepare_amount)
+                > cap.limit
+        }) {
+            return Err(RateLimitError::VolumeLimitExceeded);
+        }
+        for cap in caps.iter() {
+            self.add_volume(
+                &volume_key(account, cap),
+                &cap.period,
+                i128::from(prepare_amount),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Refunds the amount of a rejected packet to the throughput limit and the volume caps of the account
+    pub(crate) fn refund_throughput_limit(&mut self, account: &Account, prepare_amount: u64) {
+        if let Some(limit) = throughput_limit(account) {
+            let throughput_limit = format!("limit:{}:throughput", account.id);
+            self.throttle(
+                &throughput_limit,
+                limit.max_burst,
+                limit.count_per_minute,
+                MINUTE,
+                -i128::from(prepare_amount),
+            );
+        }
+
+        for cap in volume_caps(account).iter() {
+            self.add_volume(
+                &volume_key(account, cap),
+                &cap.period,
+                -i128::from(prepare_amount),
+            );
+        }
+    }
+
+    /// Charges the packets and amount let through by a local rate limiter to the limits
+    /// of the account, and returns what remains of them
+    pub(crate) fn reconcile_rate_limits(
+        &mut self,
+        account: &Account,
+        packets: u64,
+        amount: u64,
+    ) -> RemainingRateLimits {
+        let remaining_packets = packets_limit(account).map(|limit| {
+            let packets_limit = format!("limit:{}:packets", account.id);
+            self.charge(
+                &packets_limit,
+                limit.max_burst,
+                limit.count_per_minute,
+                MINUTE,
+                packets,
+            )
+        });
+        let remaining_amount = throughput_limit(account).map(|limit| {
+            let throughput_limit = format!("limit:{}:throughput", account.id);
+            self.charge(
+                &throughput_limit,
+                limit.max_burst,
+                limit.count_per_minute,
+                MINUTE,
+                amount,
+            )
+        });
+        // The packets were already forwarded, so their amount is added to the volumes even past the caps
+        let remaining_volume = volume_caps(account)
+            .iter()
+            .map(|cap| {
+                let key = volume_key(account, cap);
+                self.add_volume(&key, &cap.period, i128::from(amount));
+                cap.limit.saturating_sub(self.volume(&key, &cap.period))
+            })
+            .min();
+
+        RemainingRateLimits {
+            packets: remaining_packets,
+            amount: remaining_amount,
+            volume: remaining_volume,
+        }
+    }
+}
+
+fn volume_key(account: &Account, cap: &VolumeCap) -> String {
+    format!("limit:{}:{}", account.id, cap.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_after_burst() {
+        let mut throttle = Throttle::new();
+        // 2 per minute means bursts of up to 2 are allowed
+        assert!(!throttle.throttle("a", 1, 1, MINUTE, 1));
+        assert!(!throttle.throttle("a", 1, 1, MINUTE, 1));
+        assert!(throttle.throttle("a", 1, 1, MINUTE, 1));
+        // other keys are tracked separately
+        assert!(!throttle.throttle("b", 1, 1, MINUTE, 1));
+    }
+
+    #[test]
+    fn charges_as_far_as_the_limit_allows() {
+        let mut throttle = Throttle::new();
+        assert_eq!(throttle.remaining("a", 9, 9, MINUTE), 10);
+        assert_eq!(throttle.charge("a", 9, 9, MINUTE, 4), 6);
+        assert_eq!(throttle.charge("a", 9, 9, MINUTE, 100), 0);
+        assert!(throttle.throttle("a", 9, 9, MINUTE, 1));
+    }
+
+    #[test]
+    fn tracks_the_volume_of_the_current_period() {
+        let mut throttle = Throttle::new();
+        throttle.add_volume("a", "2020-01-31", 100);
+        throttle.add_volume("a", "2020-01-31", 50);
+        assert_eq!(throttle.volume("a", "2020-01-31"), 150);
+        throttle.add_volume("a", "2020-01-31", -200);
+        assert_eq!(throttle.volume("a", "2020-01-31"), 0);
+        throttle.add_volume("a", "2020-01-31", 100);
+        // the volume of the previous period is forgotten
+        throttle.add_volume("a", "2020-02-01", 10);
+        assert_eq!(throttle.volume("a", "2020-02-01"), 10);
+        assert_eq!(throttle.volume("a", "2020-01-31"), 0);
+    }
+
+    #[test]
+    fn refunds_with_negative_quantity() {
+        let mut throttle = Throttle::new();
+        assert!(!throttle.throttle("a", 999, 999, MINUTE, 1000));
+        assert!(throttle.throttle("a", 999, 999, MINUTE, 1));
+        assert!(!throttle.throttle("a", 999, 999, MINUTE, -500));
+        assert!(!throttle.throttle("a", 999, 999, MINUTE, 500));
+        assert!(throttle.throttle("a", 999, 999, MINUTE, 1));
+    }
+}
@@ -0,0 +1,63 @@
+use interledger_api::{hash_api_token, ApiToken, ApiTokenScope, ApiTokenStore};
+use interledger_errors::ApiTokenStoreError;
+use interledger_service::Account as AccountTrait;
+use interledger_store::account::Account;
+use uuid::Uuid;
+
+pub async fn saves_loads_and_deletes_api_tokens<S>(store: S, accs: Vec<Account>)
+where
+    S: ApiTokenStore,
+{
+    let token = ApiToken {
+        id: Uuid::new_v4(),
+        account_id: Some(accs[0].id()),
+        scopes: vec![ApiTokenScope::Pay, ApiTokenScope::ReadOnly],
+        expires_at: Some(4_102_444_800),
+        created_at: 10,
+        description: Some("payments from the shop".to_string()),
+    };
+    let other_token = ApiToken {
+        id: Uuid::new_v4(),
+        account_id: None,
+        scopes: vec![ApiTokenScope::ManageRates],
+        expires_at: None,
+        created_at: 20,
+        description: None,
+    };
+    store
+        .insert_api_token(token.clone(), hash_api_token("ilp_api_first"))
+        .await
+        .unwrap();
+    store
+        .insert_api_token(other_token.clone(), hash_api_token("ilp_api_second"))
+        .await
+        .unwrap();
+
+    let loaded = store
+        .get_api_token(hash_api_token("ilp_api_first"))
+        .await
+        .unwrap();
+    assert_eq!(loaded, Some(token.clone()));
+    let loaded = store
+        .get_api_token(hash_api_token("ilp_api_unknown"))
+        .await
+        .unwrap();
+    assert_eq!(loaded, None);
+    let tokens = store.get_all_api_tokens().await.unwrap();
+    assert_eq!(tokens, vec![token.clone(), other_token.clone()]);
+
+    let deleted = store.delete_api_token(token.id).await.unwrap();
+    assert_eq!(deleted, token);
+    let loaded = store
+        .get_api_token(hash_api_token("ilp_api_first"))
+        .await
+        .unwrap();
+    assert_eq!(loaded, None);
+    let tokens = store.get_all_api_tokens().await.unwrap();
+    assert_eq!(tokens, vec![other_token]);
+
+    match store.delete_api_token(token.id).await {
+        Err(ApiTokenStoreError::TokenNotFound(_)) => {}
+        other => panic!("Expected TokenNotFound, got: {:?}", other),
+    }
+}
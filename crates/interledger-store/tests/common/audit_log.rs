@@ -0,0 +1,64 @@
+use interledger_api::{AuditAction, AuditActor, AuditLogEntry, AuditLogStore, AuditQuery};
+use serde_json::json;
+
+fn entry(action: AuditAction, target: Option<&str>, timestamp: u64) -> AuditLogEntry {
+    AuditLogEntry {
+        timestamp,
+        ..AuditLogEntry::new(
+            &AuditActor::Admin,
+            action,
+            target.map(ToString::to_string),
+            Some(json!({ "min_balance": -100 })),
+            Some(json!({ "min_balance": -1000 })),
+        )
+    }
+}
+
+pub async fn appends_and_queries_audit_log<S>(store: S)
+where
+    S: AuditLogStore,
+{
+    let rates = entry(AuditAction::SetRates, None, 10);
+    let update = entry(AuditAction::UpdateAccount, Some("alice"), 20);
+    let delete = entry(AuditAction::DeleteAccount, Some("bob"), 30);
+    for logged in &[rates.clone(), update.clone(), delete.clone()] {
+        store.append_audit_entry(logged.clone()).await.unwrap();
+    }
+
+    // The most recent entries come first
+    let entries = store
+        .get_audit_entries(AuditQuery::default())
+        .await
+        .unwrap();
+    assert_eq!(entries, vec![delete.clone(), update.clone(), rates.clone()]);
+
+    let entries = store
+        .get_audit_entries(AuditQuery {
+            target: Some("alice".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries, vec![update.clone()]);
+
+    let entries = store
+        .get_audit_entries(AuditQuery {
+            actor: Some("admin".to_string()),
+            action: Some(AuditAction::SetRates),
+            until: Some(15),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries, vec![rates]);
+
+    let entries = store
+        .get_audit_entries(AuditQuery {
+            offset: 1,
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries, vec![update]);
+}
@@ -0,0 +1,40 @@
+use interledger_service::Account as AccountTrait;
+use interledger_service_util::{FeeSchedules, FeeStore};
+use interledger_store::account::Account;
+use serde_json::json;
+
+pub fn schedules() -> FeeSchedules {
+    serde_json::from_value(json!({
+        "incoming_accounts": { "alice": { "fixed": 1, "percentage": "0.5" } },
+        "asset_pairs": { "ABC/XYZ": { "minimum": 10 } },
+    }))
+    .unwrap()
+}
+
+pub async fn sets_and_gets_fee_schedules<S>(store: S)
+where
+    S: FeeStore,
+{
+    assert_eq!(*store.get_fee_schedules(), FeeSchedules::default());
+    store.set_fee_schedules(schedules()).await.unwrap();
+    assert_eq!(*store.get_fee_schedules(), schedules());
+    store
+        .set_fee_schedules(FeeSchedules::default())
+        .await
+        .unwrap();
+    assert_eq!(*store.get_fee_schedules(), FeeSchedules::default());
+}
+
+pub async fn adds_up_fee_revenue<S>(store: S, accs: Vec<Account>)
+where
+    S: FeeStore,
+{
+    assert!(store.get_fee_revenue().await.unwrap().is_empty());
+    store.record_fee_revenue(accs[0].id(), 10).await.unwrap();
+    store.record_fee_revenue(accs[0].id(), 5).await.unwrap();
+    store.record_fee_revenue(accs[1].id(), 1).await.unwrap();
+    let revenue = store.get_fee_revenue().await.unwrap();
+    assert_eq!(revenue.len(), 2);
+    assert_eq!(revenue[&accs[0].id()], 15);
+    assert_eq!(revenue[&accs[1].id()], 1);
+}
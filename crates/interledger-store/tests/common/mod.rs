@@ -0,0 +1,8 @@
+//! Tests which are run against every store backend. Each backend's test suite
+//! creates its store and calls these with it
+pub mod api_tokens;
+pub mod audit_log;
+pub mod fees;
+pub mod payments;
+pub mod settlement;
+pub mod stream_connections;
@@ -0,0 +1,96 @@
+use interledger_packet::Address;
+use interledger_service::Account as AccountTrait;
+use interledger_store::account::Account;
+use interledger_stream::{
+    PaymentDirection, PaymentHistoryStore, PaymentQuery, PaymentRecord, PaymentStatus,
+};
+use std::str::FromStr;
+use uuid::Uuid;
+
+fn payment(
+    account_id: Uuid,
+    direction: PaymentDirection,
+    status: PaymentStatus,
+    timestamp: u64,
+) -> PaymentRecord {
+    PaymentRecord {
+        id: Uuid::new_v4(),
+        account_id,
+        direction,
+        status,
+        destination: Address::from_str("example.bob.abc").unwrap(),
+        counterparty: "$example.com/bob".to_string(),
+        amount: 100,
+        asset_code: "XYZ".to_string(),
+        asset_scale: 6,
+        delivered_amount: Some(90),
+        delivered_asset_code: Some("ABC".to_string()),
+        delivered_asset_scale: Some(9),
+        timestamp,
+    }
+}
+
+pub async fn saves_and_loads_payments<S>(store: S, accs: Vec<Account>)
+where
+    S: PaymentHistoryStore,
+{
+    let id = accs[0].id();
+    let incoming = payment(id, PaymentDirection::Incoming, PaymentStatus::Completed, 10);
+    let outgoing = payment(id, PaymentDirection::Outgoing, PaymentStatus::Failed, 20);
+    store.save_payment(incoming.clone()).await.unwrap();
+    store.save_payment(outgoing.clone()).await.unwrap();
+    store
+        .save_payment(payment(
+            accs[1].id(),
+            PaymentDirection::Incoming,
+            PaymentStatus::Completed,
+            30,
+        ))
+        .await
+        .unwrap();
+
+    // The most recent payments come first
+    let payments = store
+        .get_payments(id, PaymentQuery::default())
+        .await
+        .unwrap();
+    assert_eq!(payments, vec![outgoing.clone(), incoming.clone()]);
+
+    let payments = store
+        .get_payments(
+            id,
+            PaymentQuery {
+                direction: Some(PaymentDirection::Incoming),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(payments, vec![incoming.clone()]);
+
+    let payments = store
+        .get_payments(
+            id,
+            PaymentQuery {
+                status: Some(PaymentStatus::Failed),
+                since: Some(15),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(payments, vec![outgoing]);
+
+    let payments = store
+        .get_payments(
+            id,
+            PaymentQuery {
+                offset: 1,
+                limit: Some(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(payments, vec![incoming]);
+}
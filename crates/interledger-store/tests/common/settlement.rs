@@ -0,0 +1,120 @@
+use interledger_service_util::BalanceStore;
+use interledger_settlement::core::types::{OutgoingSettlement, SettlementOutboxStore};
+use url::Url;
+use uuid::Uuid;
+
+const IDEMPOTENCY_KEY: &str = "AJKJNUjM0oyiAN46";
+
+fn outgoing_settlement(account_id: Uuid, amount: u64) -> OutgoingSettlement {
+    OutgoingSettlement {
+        idempotency_key: IDEMPOTENCY_KEY.to_string(),
+        account_id,
+        amount,
+        asset_scale: 9,
+        engine_url: Url::parse("http://settle.example").unwrap(),
+    }
+}
+
+/// The account must have a zero balance
+pub async fn refunds_outgoing_settlement_once<S>(store: S, account_id: Uuid)
+where
+    S: BalanceStore + SettlementOutboxStore,
+{
+    let settlement = outgoing_settlement(account_id, 100);
+    store
+        .save_outgoing_settlement(settlement.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_pending_outgoing_settlements().await.unwrap(),
+        vec![settlement]
+    );
+
+    store
+        .refund_outgoing_settlement(IDEMPOTENCY_KEY)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(account_id).await.unwrap(), 100);
+    // Refunding it again does nothing since it is no longer pending
+    store
+        .refund_outgoing_settlement(IDEMPOTENCY_KEY)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(account_id).await.unwrap(), 100);
+    assert!(store
+        .get_pending_outgoing_settlements()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+/// The account must have a zero balance
+pub async fn completes_outgoing_settlement<S>(store: S, account_id: Uuid)
+where
+    S: BalanceStore + SettlementOutboxStore,
+{
+    store
+        .save_outgoing_settlement(outgoing_settlement(account_id, 100))
+        .await
+        .unwrap();
+    store
+        .complete_outgoing_settlement(IDEMPOTENCY_KEY)
+        .await
+        .unwrap();
+    assert!(store
+        .get_pending_outgoing_settlements()
+        .await
+        .unwrap()
+        .is_empty());
+    // A completed settlement cannot be refunded
+    store
+        .refund_outgoing_settlement(IDEMPOTENCY_KEY)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(account_id).await.unwrap(), 0);
+}
+
+/// The account must have a zero balance, a settle threshold of 0 and settle to -1000
+pub async fn saves_outgoing_settlement_with_fulfill<S>(store: S, account_id: Uuid)
+where
+    S: BalanceStore + SettlementOutboxStore,
+{
+    let (balance, settlement) = store
+        .update_balances_for_fulfill_with_settlement(
+            account_id,
+            100,
+            outgoing_settlement(account_id, 0),
+        )
+        .await
+        .unwrap();
+    assert_eq!(balance, -1000);
+    let settlement = settlement.unwrap();
+    assert_eq!(settlement, outgoing_settlement(account_id, 1100));
+    assert_eq!(
+        store.get_pending_outgoing_settlements().await.unwrap(),
+        vec![settlement]
+    );
+
+    // Nothing is saved if the balance does not need to be settled
+    let (balance, settlement) = store
+        .update_balances_for_fulfill_with_settlement(
+            account_id,
+            100,
+            OutgoingSettlement {
+                idempotency_key: "other".to_string(),
+                ..outgoing_settlement(account_id, 0)
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(balance, -900);
+    assert_eq!(settlement, None);
+    assert_eq!(
+        store
+            .get_pending_outgoing_settlements()
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
@@ -0,0 +1,93 @@
+use interledger_stream::StreamConnectionStore;
+use std::time::Duration;
+
+pub async fn tracks_connection_totals<S>(store: S)
+where
+    S: StreamConnectionStore,
+{
+    store
+        .create_stream_connection("invoice", Some(150))
+        .await
+        .unwrap();
+
+    let details = store
+        .add_stream_connection_amount("invoice", 100)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(details.total_received, 100);
+    assert_eq!(details.receive_max, Some(150));
+
+    // Going over the receive max leaves the connection unchanged
+    assert!(store
+        .add_stream_connection_amount("invoice", 51)
+        .await
+        .unwrap()
+        .is_none());
+    let details = store
+        .get_stream_connection("invoice")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(details.total_received, 100);
+
+    // Connections without a receive max are tracked on their first packet
+    let details = store
+        .add_stream_connection_amount("other", 500)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(details.total_received, 500);
+    assert_eq!(details.receive_max, None);
+}
+
+pub async fn closed_connections_do_not_receive_money<S>(store: S)
+where
+    S: StreamConnectionStore,
+{
+    store
+        .add_stream_connection_amount("closing", 10)
+        .await
+        .unwrap();
+    store.close_stream_connection("closing").await.unwrap();
+
+    assert!(store
+        .add_stream_connection_amount("closing", 1)
+        .await
+        .unwrap()
+        .is_none());
+    let details = store
+        .get_stream_connection("closing")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(details.closed);
+    assert_eq!(details.total_received, 10);
+
+    // Closing a connection which isn't tracked does nothing
+    store.close_stream_connection("unknown").await.unwrap();
+    assert!(store
+        .get_stream_connection("unknown")
+        .await
+        .unwrap()
+        .is_none());
+}
+
+pub async fn deletes_idle_connections<S>(store: S)
+where
+    S: StreamConnectionStore,
+{
+    store.create_stream_connection("idle", None).await.unwrap();
+
+    store
+        .delete_idle_stream_connections(Duration::from_secs(3600))
+        .await
+        .unwrap();
+    assert!(store.get_stream_connection("idle").await.unwrap().is_some());
+
+    store
+        .delete_idle_stream_connections(Duration::from_secs(0))
+        .await
+        .unwrap();
+    assert!(store.get_stream_connection("idle").await.unwrap().is_none());
+}
@@ -0,0 +1,229 @@
+use super::{fixtures::*, store_helpers::*};
+use interledger_api::{AccountSettings, NodeStore};
+use interledger_btp::BtpAccount;
+use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_http::HttpAccount;
+use interledger_packet::Address;
+use interledger_service::Account as AccountTrait;
+use interledger_service::{AccountStore, AddressStore, Username};
+use interledger_service_util::{BalanceStore, CircuitBreakerAccount};
+use secrecy::{ExposeSecret, SecretString};
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn insert_accounts() {
+    let (store, _) = test_store().await.unwrap();
+    let account = store
+        .insert_account(ACCOUNT_DETAILS_2.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        *account.ilp_address(),
+        Address::from_str("example.alice.user1.charlie").unwrap()
+    );
+
+    // cannot insert duplicate accounts
+    let err = store
+        .insert_account(ACCOUNT_DETAILS_2.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "account `charlie` already exists");
+}
+
+#[tokio::test]
+async fn cannot_insert_invalid_accounts() {
+    let (store, _) = test_store().await.unwrap();
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.ilp_over_http_url = Some("asdf".to_owned());
+    let err = store.insert_account(acc).await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid account: the provided http url is not valid: relative URL without a base"
+    );
+}
+
+#[tokio::test]
+async fn update_ilp_and_children_addresses() {
+    let (store, accs) = test_store().await.unwrap();
+    // Add a NonRoutingAccount to make sure its address
+    // gets updated as well
+    let acc2 = store
+        .insert_account(ACCOUNT_DETAILS_2.clone())
+        .await
+        .unwrap();
+    let mut accs = accs.clone();
+    accs.push(acc2);
+    accs.sort_by_key(|a| a.username().clone());
+    let ilp_address = Address::from_str("test.parent.our_address").unwrap();
+
+    store.set_ilp_address(ilp_address.clone()).await.unwrap();
+    assert_eq!(ilp_address, store.get_ilp_address());
+
+    let mut accounts = store.get_all_accounts().await.unwrap();
+    accounts.sort_by_key(|a| a.username().clone());
+    for (a, b) in accounts.into_iter().zip(&accs) {
+        if a.routing_relation() == RoutingRelation::Child
+            || a.routing_relation() == RoutingRelation::NonRoutingAccount
+        {
+            assert_eq!(
+                *a.ilp_address(),
+                ilp_address.with_suffix(a.username().as_bytes()).unwrap()
+            );
+        } else {
+            assert_eq!(a.ilp_address(), b.ilp_address());
+        }
+    }
+}
+
+#[tokio::test]
+async fn only_one_parent_allowed() {
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.routing_relation = Some("Parent".to_owned());
+    acc.username = Username::from_str("another_name").unwrap();
+    acc.ilp_address = Some(Address::from_str("example.another_name").unwrap());
+    let (store, accs) = test_store().await.unwrap();
+    let res = store.insert_account(acc.clone()).await;
+    // This should fail
+    assert!(res.is_err());
+    store.delete_account(accs[0].id()).await.unwrap();
+    // must also clear the ILP Address to indicate that we no longer
+    // have a parent account configured
+    store.clear_ilp_address().await.unwrap();
+    let res = store.insert_account(acc).await;
+    assert!(res.is_ok());
+}
+
+#[tokio::test]
+async fn delete_accounts() {
+    let (store, _) = test_store().await.unwrap();
+    let accounts = store.get_all_accounts().await.unwrap();
+    let id = accounts[0].id();
+    store.delete_account(id).await.unwrap();
+    let accounts = store.get_all_accounts().await.unwrap();
+    for a in &accounts {
+        assert_ne!(id, a.id());
+    }
+
+    // clear all accounts and try again
+    store.delete_account(accounts[0].id()).await.unwrap();
+    let accounts = store.get_all_accounts().await.unwrap();
+    assert_eq!(accounts.len(), 0);
+
+    // try deleting an account which does not exist
+    let err = store.delete_account(id).await.unwrap_err();
+    assert_eq!(err.to_string(), format!("account `{}` was not found", id));
+}
+
+#[tokio::test]
+async fn update_accounts() {
+    let (store, accounts) = test_store().await.unwrap();
+    let id = accounts[0].id();
+    let mut new = ACCOUNT_DETAILS_0.clone();
+    new.asset_code = String::from("TUV");
+    let account = store.update_account(id, new.clone()).await.unwrap();
+    assert_eq!(account.asset_code(), "TUV");
+
+    let id = Uuid::new_v4();
+    let err = store.update_account(id, new).await.unwrap_err();
+    assert_eq!(err.to_string(), format!("account `{}` was not found", id));
+}
+
+#[tokio::test]
+async fn modify_account_settings_settle_to_overflow() {
+    let (store, accounts) = test_store().await.unwrap();
+    let settings = AccountSettings {
+        settle_to: Some(i64::MAX as u64 + 1),
+        ..Default::default()
+    };
+    let err = store
+        .modify_account_settings(accounts[0].id(), settings)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid account: the provided value for parameter `settle_to` was too large"
+    );
+}
+
+#[tokio::test]
+async fn modify_account_settings() {
+    let (store, accounts) = test_store().await.unwrap();
+    let settings = AccountSettings {
+        ilp_over_http_outgoing_token: Some(SecretString::new("test_token".to_owned())),
+        ilp_over_http_incoming_token: Some(SecretString::new("http_in_new".to_owned())),
+        ilp_over_btp_outgoing_token: Some(SecretString::new("dylan:test".to_owned())),
+        ilp_over_btp_incoming_token: Some(SecretString::new("btp_in_new".to_owned())),
+        ilp_over_http_url: Some("http://example.com/accounts/dylan/ilp".to_owned()),
+        ilp_over_btp_url: Some("http://example.com/accounts/dylan/ilp/btp".to_owned()),
+        settle_threshold: Some(-50),
+        settle_to: Some(100),
+        circuit_breaker_failure_threshold: Some(10),
+        circuit_breaker_cool_down: Some(60_000),
+    };
+    let ret = store
+        .modify_account_settings(accounts[0].id(), settings.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        ret.get_http_auth_token().unwrap().expose_secret(),
+        "test_token",
+    );
+    assert_eq!(
+        ret.get_ilp_over_btp_outgoing_token().unwrap(),
+        &b"dylan:test"[..],
+    );
+    assert_eq!(ret.circuit_breaker_failure_threshold(), Some(10));
+    assert_eq!(ret.circuit_breaker_cool_down(), Some(60_000));
+
+    let id = Uuid::new_v4();
+    let err = store
+        .modify_account_settings(id, settings)
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), format!("account `{}` was not found", id));
+}
+
+#[tokio::test]
+async fn starts_with_zero_balance() {
+    let (store, accs) = test_store().await.unwrap();
+    let balance = store.get_balance(accs[0].id()).await.unwrap();
+    assert_eq!(balance, 0);
+}
+
+#[tokio::test]
+async fn fetches_account_from_username() {
+    let (store, accs) = test_store().await.unwrap();
+    let account_id = store
+        .get_account_id_from_username(&Username::from_str("alice").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(account_id, accs[0].id());
+
+    let err = store
+        .get_account_id_from_username(&Username::from_str("random").unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "account `random` was not found");
+}
+
+#[tokio::test]
+async fn gets_multiple() {
+    let (store, accs) = test_store().await.unwrap();
+    // set account ids in reverse order
+    let account_ids: Vec<Uuid> = accs.iter().rev().map(|a| a.id()).collect::<_>();
+    let accounts = store.get_accounts(account_ids).await.unwrap();
+    // note reverse order is intentional
+    assert_eq!(accounts[0].ilp_address(), accs[1].ilp_address());
+    assert_eq!(accounts[1].ilp_address(), accs[0].ilp_address());
+}
+
+#[tokio::test]
+async fn errors_for_unknown_accounts() {
+    let (store, _) = test_store().await.unwrap();
+    let err = store
+        .get_accounts(vec![Uuid::new_v4(), Uuid::new_v4()])
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "wrong account length (expected 2, got 0)");
+}
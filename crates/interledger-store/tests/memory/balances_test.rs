@@ -0,0 +1,111 @@
+use super::{fixtures::*, store_helpers::*};
+use interledger_api::NodeStore;
+use interledger_service::{Account as AccountTrait, AccountStore};
+use interledger_service_util::BalanceStore;
+
+#[tokio::test]
+async fn prepare_then_fulfill_with_settlement() {
+    let (store, accs) = test_store().await.unwrap();
+    let account0_id = accs[0].id();
+    let account1_id = accs[1].id();
+    // reduce account 0's balance by 100
+    store
+        .update_balances_for_prepare(account0_id, 100)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(account0_id).await.unwrap(), -100);
+    assert_eq!(store.get_balance(account1_id).await.unwrap(), 0);
+
+    store
+        .update_balances_for_fulfill(account1_id, 100)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(account0_id).await.unwrap(), -100);
+    assert_eq!(store.get_balance(account1_id).await.unwrap(), -1000);
+}
+
+#[tokio::test]
+async fn process_fulfill_no_settle_to() {
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.settle_to = None;
+    let (store, _) = test_store().await.unwrap();
+    let account = store.insert_account(acc).await.unwrap();
+    let (balance, amount_to_settle) = store
+        .update_balances_for_fulfill(account.id(), 100)
+        .await
+        .unwrap();
+    assert_eq!(balance, 100);
+    assert_eq!(amount_to_settle, 0);
+}
+
+#[tokio::test]
+async fn process_fulfill_ok() {
+    // account with settle to = 0 (not falsy) with settle_threshold > 0, gets settlements
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.settle_to = Some(0);
+    acc.settle_threshold = Some(100);
+    let (store, _) = test_store().await.unwrap();
+    let account = store.insert_account(acc).await.unwrap();
+    let (balance, amount_to_settle) = store
+        .update_balances_for_fulfill(account.id(), 101)
+        .await
+        .unwrap();
+    assert_eq!(balance, 0);
+    assert_eq!(amount_to_settle, 101);
+}
+
+#[tokio::test]
+async fn prepare_then_reject() {
+    let (store, accs) = test_store().await.unwrap();
+    let acc0 = accs[0].id();
+    store.update_balances_for_prepare(acc0, 100).await.unwrap();
+    assert_eq!(store.get_balance(acc0).await.unwrap(), -100);
+    store.update_balances_for_reject(acc0, 100).await.unwrap();
+    assert_eq!(store.get_balance(acc0).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn enforces_minimum_balance() {
+    let (store, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    let err = store
+        .update_balances_for_prepare(id, 10000)
+        .await
+        .unwrap_err();
+    let expected = format!("Incoming prepare of 10000 would bring account {} under its minimum balance. Current balance: 0, min balance: -1000", id);
+    assert!(err.to_string().contains(&expected));
+    // the failed prepare must not have changed the balance
+    assert_eq!(store.get_balance(id).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn netting_fulfilled_balances() {
+    let (store, accs) = test_store().await.unwrap();
+    let acc = store
+        .insert_account(ACCOUNT_DETAILS_2.clone())
+        .await
+        .unwrap();
+    let account0 = accs[0].id();
+    let account1 = acc.id();
+
+    store
+        .update_balances_for_prepare(account0, 100)
+        .await
+        .unwrap();
+    store
+        .update_balances_for_fulfill(account1, 100)
+        .await
+        .unwrap();
+    store
+        .update_balances_for_prepare(account1, 80)
+        .await
+        .unwrap();
+    store
+        .update_balances_for_fulfill(account0, 80)
+        .await
+        .unwrap();
+
+    let accounts = store.get_accounts(vec![account0, account1]).await.unwrap();
+    assert_eq!(store.get_balance(accounts[0].id()).await.unwrap(), -20);
+    assert_eq!(store.get_balance(accounts[1].id()).await.unwrap(), 20);
+}
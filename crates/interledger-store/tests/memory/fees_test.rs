@@ -0,0 +1,14 @@
+use super::common::fees;
+use super::store_helpers::*;
+
+#[tokio::test]
+async fn sets_and_gets_fee_schedules() {
+    let (store, _accs) = test_store().await.unwrap();
+    fees::sets_and_gets_fee_schedules(store).await;
+}
+
+#[tokio::test]
+async fn adds_up_fee_revenue() {
+    let (store, accs) = test_store().await.unwrap();
+    fees::adds_up_fee_revenue(store, accs).await;
+}
@@ -0,0 +1,135 @@
+use super::{fixtures::*, store_helpers::*};
+use interledger_api::NodeStore;
+use interledger_service_util::{RateLimitError, RateLimitStore, RemainingRateLimits};
+
+#[tokio::test]
+async fn rate_limits_number_of_packets() {
+    let (store, accs) = test_store().await.unwrap();
+    let account = accs[0].clone();
+    let results = vec![
+        store.apply_rate_limits(account.clone(), 10).await,
+        store.apply_rate_limits(account.clone(), 10).await,
+        store.apply_rate_limits(account.clone(), 10).await,
+    ];
+    // The account is only allowed 2 packets per minute
+    assert_eq!(
+        results,
+        vec![Ok(()), Ok(()), Err(RateLimitError::PacketLimitExceeded)]
+    );
+}
+
+#[tokio::test]
+async fn limits_amount_throughput() {
+    let (store, accs) = test_store().await.unwrap();
+    let account = accs[1].clone();
+    let results = vec![
+        store.apply_rate_limits(account.clone(), 500).await,
+        store.apply_rate_limits(account.clone(), 500).await,
+        store.apply_rate_limits(account.clone(), 1).await,
+    ];
+    // The account is only allowed 1000 units of currency per minute
+    assert_eq!(
+        results,
+        vec![Ok(()), Ok(()), Err(RateLimitError::ThroughputLimitExceeded)]
+    );
+}
+
+#[tokio::test]
+async fn refunds_throughput_limit_for_rejected_packets() {
+    let (store, accs) = test_store().await.unwrap();
+    let account = accs[1].clone();
+    store.apply_rate_limits(account.clone(), 500).await.unwrap();
+    store.apply_rate_limits(account.clone(), 500).await.unwrap();
+
+    // We refund the throughput limit once, meaning we can do 1 more call before
+    // the error
+    store
+        .refund_throughput_limit(account.clone(), 500)
+        .await
+        .unwrap();
+    store.apply_rate_limits(account.clone(), 500).await.unwrap();
+
+    let result = store.apply_rate_limits(account.clone(), 1).await;
+    assert_eq!(result.unwrap_err(), RateLimitError::ThroughputLimitExceeded);
+}
+
+#[tokio::test]
+async fn allows_bursts_above_the_sustained_rate() {
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.packets_per_minute_limit = Some(2);
+    acc.packets_burst_limit = Some(4);
+    let (store, _) = test_store().await.unwrap();
+    let account = store.insert_account(acc).await.unwrap();
+    let mut results = Vec::new();
+    for _ in 0..5 {
+        results.push(store.apply_rate_limits(account.clone(), 10).await);
+    }
+    assert_eq!(
+        results,
+        vec![
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Err(RateLimitError::PacketLimitExceeded)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn caps_daily_volume() {
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.amount_per_day_limit = Some(1000);
+    acc.amount_per_month_limit = Some(5000);
+    let (store, _) = test_store().await.unwrap();
+    let account = store.insert_account(acc).await.unwrap();
+    store.apply_rate_limits(account.clone(), 600).await.unwrap();
+    assert_eq!(
+        store.apply_rate_limits(account.clone(), 600).await,
+        Err(RateLimitError::VolumeLimitExceeded)
+    );
+    // The rejected packet didn't use up the cap
+    store.apply_rate_limits(account.clone(), 400).await.unwrap();
+
+    // Refunds are taken back out of the volume
+    store
+        .refund_throughput_limit(account.clone(), 400)
+        .await
+        .unwrap();
+    store.apply_rate_limits(account.clone(), 400).await.unwrap();
+    assert_eq!(
+        store.apply_rate_limits(account.clone(), 1).await,
+        Err(RateLimitError::VolumeLimitExceeded)
+    );
+}
+
+#[tokio::test]
+async fn reconciles_local_rate_limits() {
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.packets_per_minute_limit = Some(10);
+    acc.amount_per_minute_limit = Some(1000);
+    acc.amount_per_day_limit = Some(5000);
+    let (store, _) = test_store().await.unwrap();
+    let account = store.insert_account(acc).await.unwrap();
+    assert_eq!(
+        store.reconcile_rate_limits(account.clone(), 0, 0).await,
+        Ok(RemainingRateLimits {
+            packets: Some(10),
+            amount: Some(1000),
+            volume: Some(5000),
+        })
+    );
+    // The packets were already let through, so they count even past the limits
+    assert_eq!(
+        store.reconcile_rate_limits(account.clone(), 4, 2000).await,
+        Ok(RemainingRateLimits {
+            packets: Some(6),
+            amount: Some(0),
+            volume: Some(3000),
+        })
+    );
+    assert_eq!(
+        store.apply_rate_limits(account.clone(), 1).await,
+        Err(RateLimitError::ThroughputLimitExceeded)
+    );
+}
@@ -0,0 +1,244 @@
+use super::{fixtures::*, store_helpers::*};
+
+use interledger_api::NodeStore;
+use interledger_ccp::CcpRoutingStore;
+use interledger_packet::Address;
+use interledger_router::{NextHop, RouterStore};
+use interledger_service::{Account as AccountTrait, AddressStore};
+use interledger_store::account::Account;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn updates_routing_table_when_accounts_change() {
+    let (store, accs) = test_store().await.unwrap();
+    let routing_table = store.routing_table();
+    assert_eq!(routing_table.len(), 2);
+    assert_eq!(routing_table["example.alice"], accs[0].id());
+    assert_eq!(routing_table["example.alice.user1.bob"], accs[1].id());
+
+    store.delete_account(accs[1].id()).await.unwrap();
+    let routing_table = store.routing_table();
+    assert_eq!(routing_table.len(), 1);
+    assert!(routing_table.get("example.alice.user1.bob").is_none());
+}
+
+#[tokio::test]
+async fn gets_accounts_to_send_routes_to() {
+    let (store, _) = test_store().await.unwrap();
+    let accounts = store
+        .get_accounts_to_send_routes_to(Vec::new())
+        .await
+        .unwrap();
+    // We send to child accounts but not parents
+    assert_eq!(accounts[0].username().as_ref(), "bob");
+    assert_eq!(accounts.len(), 1);
+}
+
+#[tokio::test]
+async fn gets_accounts_to_send_routes_to_and_skips_ignored() {
+    let (store, accs) = test_store().await.unwrap();
+    let accounts = store
+        .get_accounts_to_send_routes_to(vec![accs[1].id()])
+        .await
+        .unwrap();
+    assert!(accounts.is_empty());
+}
+
+#[tokio::test]
+async fn gets_accounts_to_receive_routes_from() {
+    let (store, _) = test_store().await.unwrap();
+    let accounts = store.get_accounts_to_receive_routes_from().await.unwrap();
+    assert_eq!(
+        *accounts[0].ilp_address(),
+        Address::from_str("example.alice").unwrap()
+    );
+}
+
+#[tokio::test]
+async fn gets_local_and_configured_routes() {
+    let (store, _) = test_store().await.unwrap();
+    let (local, configured) = store.get_local_and_configured_routes().await.unwrap();
+    assert_eq!(local.len(), 2);
+    assert!(configured.is_empty());
+}
+
+#[tokio::test]
+async fn static_routes_override_others() {
+    let (store, accs) = test_store().await.unwrap();
+    store
+        .set_static_routes(vec![
+            ("example.a".to_string(), accs[0].id()),
+            ("example.b".to_string(), accs[0].id()),
+        ])
+        .await
+        .unwrap();
+
+    let account1_id = Uuid::new_v4();
+    let account1 = Account::try_from(
+        account1_id,
+        ACCOUNT_DETAILS_1.clone(),
+        store.get_ilp_address(),
+    )
+    .unwrap();
+    store
+        .clone()
+        .set_routes(vec![
+            ("example.a".to_string(), account1.clone()),
+            ("example.b".to_string(), account1.clone()),
+            ("example.c".to_string(), account1),
+        ])
+        .await
+        .unwrap();
+
+    let routes = store.routing_table();
+    assert_eq!(routes["example.a"], accs[0].id());
+    assert_eq!(routes["example.b"], accs[0].id());
+    assert_eq!(routes["example.c"], account1_id);
+    assert_eq!(routes.len(), 3);
+}
+
+#[tokio::test]
+async fn default_route() {
+    let (store, accs) = test_store().await.unwrap();
+    store.set_default_route(accs[0].id()).await.unwrap();
+    let account1_id = Uuid::new_v4();
+    let account1 = Account::try_from(
+        account1_id,
+        ACCOUNT_DETAILS_1.clone(),
+        store.get_ilp_address(),
+    )
+    .unwrap();
+    store
+        .clone()
+        .set_routes(vec![
+            ("example.a".to_string(), account1.clone()),
+            ("example.b".to_string(), account1),
+        ])
+        .await
+        .unwrap();
+
+    let routes = store.routing_table();
+    assert_eq!(routes[""], accs[0].id());
+    assert_eq!(routes["example.a"], account1_id);
+    assert_eq!(routes["example.b"], account1_id);
+    assert_eq!(routes.len(), 3);
+}
+
+#[tokio::test]
+async fn static_routes_with_several_next_hops() {
+    let (store, accs) = test_store().await.unwrap();
+    store
+        .set_static_multipath_routes(vec![
+            (
+                "example.a".to_string(),
+                vec![
+                    NextHop {
+                        account_id: accs[0].id(),
+                        weight: 3,
+                    },
+                    NextHop {
+                        account_id: accs[1].id(),
+                        weight: 1,
+                    },
+                ],
+            ),
+            (
+                "example.b".to_string(),
+                vec![NextHop {
+                    account_id: accs[1].id(),
+                    weight: 1,
+                }],
+            ),
+        ])
+        .await
+        .unwrap();
+
+    let routes = store.routing_table();
+    assert_eq!(routes["example.a"], accs[0].id());
+    assert_eq!(routes["example.b"], accs[1].id());
+    let multipath_routes = store.multipath_routing_table();
+    assert_eq!(multipath_routes.len(), 1);
+    assert_eq!(multipath_routes["example.a"].len(), 2);
+    assert_eq!(multipath_routes["example.a"][1].account_id, accs[1].id());
+    assert_eq!(multipath_routes["example.a"][1].weight, 1);
+
+    // Setting a single static route replaces the next hops of that prefix
+    store
+        .set_static_route("example.a".to_string(), accs[1].id())
+        .await
+        .unwrap();
+    assert_eq!(store.routing_table()["example.a"], accs[1].id());
+    assert!(store.multipath_routing_table().is_empty());
+}
+
+#[tokio::test]
+async fn doesnt_set_static_next_hops_for_nonexistent_accounts() {
+    let (store, accs) = test_store().await.unwrap();
+    let result = store
+        .set_static_multipath_routes(vec![(
+            "example.a".to_string(),
+            vec![
+                NextHop {
+                    account_id: accs[0].id(),
+                    weight: 1,
+                },
+                NextHop {
+                    account_id: Uuid::new_v4(),
+                    weight: 1,
+                },
+            ],
+        )])
+        .await;
+    assert!(result.is_err());
+    assert!(store.routing_table().get("example.a").is_none());
+}
+
+#[tokio::test]
+async fn sets_next_hops_learned_over_ccp() {
+    let (store, accs) = test_store().await.unwrap();
+    let account1_id = Uuid::new_v4();
+    let account1 = Account::try_from(
+        account1_id,
+        ACCOUNT_DETAILS_1.clone(),
+        store.get_ilp_address(),
+    )
+    .unwrap();
+    let next_hops = vec![
+        NextHop {
+            account_id: account1_id,
+            weight: 1,
+        },
+        NextHop {
+            account_id: accs[0].id(),
+            weight: 0,
+        },
+    ];
+    let mut ccp_store = store.clone();
+    ccp_store
+        .set_routes(vec![
+            ("example.a".to_string(), account1.clone()),
+            ("example.b".to_string(), account1),
+        ])
+        .await
+        .unwrap();
+    ccp_store
+        .set_multipath_routes(vec![
+            ("example.a".to_string(), next_hops.clone()),
+            ("example.b".to_string(), next_hops.clone()),
+        ])
+        .await
+        .unwrap();
+    let multipath_routes = store.multipath_routing_table();
+    assert_eq!(multipath_routes.len(), 2);
+    assert_eq!(multipath_routes["example.a"], next_hops);
+
+    // Static routes override the next hops learned over CCP
+    store
+        .set_static_route("example.b".to_string(), accs[1].id())
+        .await
+        .unwrap();
+    let multipath_routes = store.multipath_routing_table();
+    assert_eq!(multipath_routes.len(), 1);
+    assert!(multipath_routes.get("example.b").is_none());
+}
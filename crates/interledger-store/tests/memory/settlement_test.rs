@@ -0,0 +1,171 @@
+use super::common::settlement;
+use super::store_helpers::*;
+use bytes::Bytes;
+
+use http::StatusCode;
+use interledger_api::NodeStore;
+use interledger_service::{Account, AccountStore};
+use interledger_service_util::BalanceStore;
+use interledger_settlement::core::{
+    idempotency::{IdempotentData, IdempotentStore},
+    types::{LeftoversStore, SettlementAccount, SettlementStore},
+};
+use num_bigint::BigUint;
+use once_cell::sync::Lazy;
+use url::Url;
+use uuid::Uuid;
+
+static IDEMPOTENCY_KEY: Lazy<String> = Lazy::new(|| String::from("AJKJNUjM0oyiAN46"));
+
+#[tokio::test]
+async fn saves_gets_clears_uncredited_settlement_amount_properly() {
+    let (store, _accs) = test_store().await.unwrap();
+    let amounts: Vec<(BigUint, u8)> = vec![
+        (BigUint::from(5u32), 11),   // 5
+        (BigUint::from(855u32), 12), // 905
+        (BigUint::from(1u32), 10),   // 1005 total
+    ];
+    let acc = Uuid::new_v4();
+    for a in amounts {
+        store
+            .save_uncredited_settlement_amount(acc, a)
+            .await
+            .unwrap();
+    }
+    let ret = store
+        .load_uncredited_settlement_amount(acc, 9u8)
+        .await
+        .unwrap();
+    // 1 uncredited unit for scale 9
+    assert_eq!(ret, BigUint::from(1u32));
+    // rest should be in the leftovers store
+    let ret = store.get_uncredited_settlement_amount(acc).await.unwrap();
+    assert_eq!(ret, (BigUint::from(5u32), 12));
+
+    // clears uncredited amount
+    store.clear_uncredited_settlement_amount(acc).await.unwrap();
+    let ret = store.get_uncredited_settlement_amount(acc).await.unwrap();
+    assert_eq!(ret, (BigUint::from(0u32), 0));
+}
+
+#[tokio::test]
+async fn saves_and_loads_idempotency_key_data_properly() {
+    let (store, _) = test_store().await.unwrap();
+    let input_hash: [u8; 32] = Default::default();
+    store
+        .save_idempotent_data(
+            IDEMPOTENCY_KEY.clone(),
+            input_hash,
+            StatusCode::OK,
+            Bytes::from("TEST"),
+        )
+        .await
+        .unwrap();
+    let data1 = store
+        .load_idempotent_data(IDEMPOTENCY_KEY.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        data1.unwrap(),
+        IdempotentData::new(StatusCode::OK, Bytes::from("TEST"), input_hash)
+    );
+
+    let data2 = store
+        .load_idempotent_data("asdf".to_string())
+        .await
+        .unwrap();
+    assert!(data2.is_none());
+}
+
+#[tokio::test]
+async fn idempotent_settlement_calls() {
+    let (store, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    store
+        .update_balance_for_incoming_settlement(id, 100, Some(IDEMPOTENCY_KEY.clone()))
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(id).await.unwrap(), 100);
+
+    store
+        .update_balance_for_incoming_settlement(id, 100, Some(IDEMPOTENCY_KEY.clone()))
+        .await
+        .unwrap();
+    // Since it's idempotent there will be no state update
+    assert_eq!(store.get_balance(id).await.unwrap(), 100);
+}
+
+#[tokio::test]
+async fn clears_balance_owed_and_puts_remainder_as_prepaid() {
+    let (store, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    store.update_balances_for_prepare(id, 40).await.unwrap();
+    store
+        .update_balance_for_incoming_settlement(id, 100, Some(IDEMPOTENCY_KEY.clone()))
+        .await
+        .unwrap();
+    // the reported balance includes the prepaid amount
+    assert_eq!(store.get_balance(id).await.unwrap(), 60);
+    // the next prepare is paid for out of the prepaid amount
+    store.update_balances_for_prepare(id, 60).await.unwrap();
+    assert_eq!(store.get_balance(id).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn loads_globally_configured_settlement_engine_url() {
+    let (store, accs) = test_store().await.unwrap();
+    assert!(accs[0].settlement_engine_details().is_some());
+    assert!(accs[1].settlement_engine_details().is_none());
+    let account_ids = vec![accs[0].id(), accs[1].id()];
+
+    store
+        .set_settlement_engines(vec![
+            (
+                "ABC".to_string(),
+                Url::parse("http://settle-abc.example").unwrap(),
+            ),
+            (
+                "XYZ".to_string(),
+                Url::parse("http://settle-xyz.example").unwrap(),
+            ),
+        ])
+        .await
+        .unwrap();
+    let accounts = store.get_accounts(account_ids).await.unwrap();
+    // It should not overwrite the one that was individually configured
+    assert_eq!(
+        accounts[0]
+            .settlement_engine_details()
+            .unwrap()
+            .url
+            .as_str(),
+        "http://settlement.example/"
+    );
+    // It should set the URL for the account that did not have one configured
+    assert_eq!(
+        accounts[1]
+            .settlement_engine_details()
+            .unwrap()
+            .url
+            .as_str(),
+        "http://settle-abc.example/"
+    );
+}
+
+#[tokio::test]
+async fn refunds_outgoing_settlement_once() {
+    let (store, accs) = test_store().await.unwrap();
+    settlement::refunds_outgoing_settlement_once(store, accs[0].id()).await;
+}
+
+#[tokio::test]
+async fn completes_outgoing_settlement() {
+    let (store, accs) = test_store().await.unwrap();
+    settlement::completes_outgoing_settlement(store, accs[0].id()).await;
+}
+
+#[tokio::test]
+async fn saves_outgoing_settlement_with_fulfill() {
+    let (store, accs) = test_store().await.unwrap();
+    settlement::saves_outgoing_settlement_with_fulfill(store, accs[0].id()).await;
+}
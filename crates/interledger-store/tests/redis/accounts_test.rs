@@ -6,7 +6,7 @@ use interledger_http::HttpAccount;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
 use interledger_service::{AccountStore, AddressStore, Username};
-use interledger_service_util::BalanceStore;
+use interledger_service_util::{BalanceStore, CircuitBreakerAccount};
 use interledger_store::redis::RedisStoreBuilder;
 use redis_crate::Client;
 use secrecy::ExposeSecret;
@@ -245,6 +245,8 @@ async fn modify_account_settings() {
         ilp_over_btp_url: Some("http://example.com/accounts/dylan/ilp/btp".to_owned()),
         settle_threshold: Some(-50),
         settle_to: Some(100),
+        circuit_breaker_failure_threshold: Some(10),
+        circuit_breaker_cool_down: Some(60_000),
     };
     let account = accounts[0].clone();
 
@@ -261,6 +263,8 @@ async fn modify_account_settings() {
         ret.get_ilp_over_btp_outgoing_token().unwrap(),
         &b"dylan:test"[..],
     );
+    assert_eq!(ret.circuit_breaker_failure_threshold(), Some(10));
+    assert_eq!(ret.circuit_breaker_cool_down(), Some(60_000));
 
     let id = Uuid::new_v4();
     let err = store
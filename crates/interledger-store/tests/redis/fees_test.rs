@@ -0,0 +1,34 @@
+use super::common::fees;
+use super::store_helpers::*;
+
+use interledger_packet::Address;
+use interledger_service_util::FeeStore;
+use interledger_store::redis::RedisStoreBuilder;
+use std::{str::FromStr, time::Duration};
+
+#[tokio::test]
+async fn sets_and_gets_fee_schedules() {
+    let (store, _context, _accs) = test_store().await.unwrap();
+    fees::sets_and_gets_fee_schedules(store).await;
+}
+
+#[tokio::test]
+async fn adds_up_fee_revenue() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    fees::adds_up_fee_revenue(store, accs).await;
+}
+
+#[tokio::test]
+async fn polls_for_fee_schedule_updates() {
+    let (store, context, _accs) = test_store().await.unwrap();
+    // Another node using the same database sees the changes
+    let other_store = RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+        .poll_interval(1)
+        .node_ilp_address(Address::from_str("example.node").unwrap())
+        .connect()
+        .await
+        .unwrap();
+    store.set_fee_schedules(fees::schedules()).await.unwrap();
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    assert_eq!(*other_store.get_fee_schedules(), fees::schedules());
+}
@@ -1,7 +1,7 @@
 use super::{fixtures::*, store_helpers::*};
 use futures::future::join_all;
 use interledger_service::AddressStore;
-use interledger_service_util::{RateLimitError, RateLimitStore};
+use interledger_service_util::{RateLimitError, RateLimitStore, RemainingRateLimits};
 use interledger_store::account::Account;
 use uuid::Uuid;
 
@@ -78,3 +78,84 @@ async fn refunds_throughput_limit_for_rejected_packets() {
     let result = store.apply_rate_limits(account.clone(), 1).await;
     assert_eq!(result.unwrap_err(), RateLimitError::ThroughputLimitExceeded);
 }
+
+#[tokio::test]
+async fn allows_bursts_above_the_sustained_rate() {
+    let (store, _context, _) = test_store().await.unwrap();
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.packets_per_minute_limit = Some(2);
+    acc.packets_burst_limit = Some(4);
+    let account = Account::try_from(Uuid::new_v4(), acc, store.get_ilp_address()).unwrap();
+    let mut results = Vec::new();
+    for _ in 0..5 {
+        results.push(store.apply_rate_limits(account.clone(), 10).await);
+    }
+    assert_eq!(
+        results,
+        vec![
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Err(RateLimitError::PacketLimitExceeded)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn caps_daily_volume() {
+    let (store, _context, _) = test_store().await.unwrap();
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.amount_per_day_limit = Some(1000);
+    acc.amount_per_month_limit = Some(5000);
+    let account = Account::try_from(Uuid::new_v4(), acc, store.get_ilp_address()).unwrap();
+    store.apply_rate_limits(account.clone(), 600).await.unwrap();
+    assert_eq!(
+        store.apply_rate_limits(account.clone(), 600).await,
+        Err(RateLimitError::VolumeLimitExceeded)
+    );
+    // The rejected packet didn't use up the cap
+    store.apply_rate_limits(account.clone(), 400).await.unwrap();
+
+    // Refunds are taken back out of the volume
+    store
+        .refund_throughput_limit(account.clone(), 400)
+        .await
+        .unwrap();
+    store.apply_rate_limits(account.clone(), 400).await.unwrap();
+    assert_eq!(
+        store.apply_rate_limits(account.clone(), 1).await,
+        Err(RateLimitError::VolumeLimitExceeded)
+    );
+}
+
+#[tokio::test]
+async fn reconciles_local_rate_limits() {
+    let (store, _context, _) = test_store().await.unwrap();
+    let mut acc = ACCOUNT_DETAILS_2.clone();
+    acc.packets_per_minute_limit = Some(10);
+    acc.amount_per_minute_limit = Some(1000);
+    acc.amount_per_day_limit = Some(5000);
+    let account = Account::try_from(Uuid::new_v4(), acc, store.get_ilp_address()).unwrap();
+    assert_eq!(
+        store.reconcile_rate_limits(account.clone(), 0, 0).await,
+        Ok(RemainingRateLimits {
+            packets: Some(10),
+            amount: Some(1000),
+            volume: Some(5000),
+        })
+    );
+    // The packets were already let through, so they count even past the limits
+    assert_eq!(
+        store.reconcile_rate_limits(account.clone(), 4, 2000).await,
+        Ok(RemainingRateLimits {
+            packets: Some(6),
+            amount: Some(0),
+            volume: Some(3000),
+        })
+    );
+    assert_eq!(
+        store.apply_rate_limits(account.clone(), 1).await,
+        Err(RateLimitError::ThroughputLimitExceeded)
+    );
+}
@@ -1,6 +1,6 @@
 use super::store_helpers::*;
 
-use interledger_rates::ExchangeRateStore;
+use interledger_rates::{format_rate, parse_rate, ExchangeRateStore};
 
 #[tokio::test]
 async fn set_rates() {
@@ -9,14 +9,17 @@ async fn set_rates() {
     assert!(rates.is_err());
     store
         .set_exchange_rates(
-            [("ABC".to_string(), 500.0), ("XYZ".to_string(), 0.005)]
-                .iter()
-                .cloned()
-                .collect(),
+            [
+                ("ABC".to_string(), parse_rate("500").unwrap()),
+                ("XYZ".to_string(), parse_rate("0.005").unwrap()),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
         )
         .unwrap();
 
     let rates = store.get_exchange_rates(&["XYZ", "ABC"]).unwrap();
-    assert_eq!(rates[0].to_string(), "0.005");
-    assert_eq!(rates[1].to_string(), "500");
+    assert_eq!(format_rate(&rates[0]), "0.005");
+    assert_eq!(format_rate(&rates[1]), "500");
 }
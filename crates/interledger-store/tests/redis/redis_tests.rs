@@ -1,11 +1,19 @@
+#[path = "../common/mod.rs"]
+mod common;
+
 mod accounts_test;
+mod api_tokens_test;
+mod audit_log_test;
 mod balances_test;
 mod btp_test;
+mod fees_test;
 mod http_test;
+mod payments_test;
 mod rate_limiting_test;
 mod rates_test;
 mod routing_test;
 mod settlement_test;
+mod stream_connections_test;
 
 mod fixtures {
 
@@ -36,6 +44,14 @@ mod fixtures {
         round_trip_time: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(2),
+        packets_burst_limit: None,
+        amount_burst_limit: None,
+        amount_per_day_limit: None,
+        amount_per_month_limit: None,
+        max_in_flight_packets: None,
+        max_in_flight_amount: None,
+        circuit_breaker_failure_threshold: None,
+        circuit_breaker_cool_down: None,
         settlement_engine_url: Some("http://settlement.example".to_string()),
     });
     pub static ACCOUNT_DETAILS_1: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
@@ -58,6 +74,14 @@ mod fixtures {
         round_trip_time: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(20),
+        packets_burst_limit: None,
+        amount_burst_limit: None,
+        amount_per_day_limit: None,
+        amount_per_month_limit: None,
+        max_in_flight_packets: None,
+        max_in_flight_amount: None,
+        circuit_breaker_failure_threshold: None,
+        circuit_breaker_cool_down: None,
         settlement_engine_url: None,
     });
     pub static ACCOUNT_DETAILS_2: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
@@ -79,6 +103,14 @@ mod fixtures {
         round_trip_time: None,
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
+        packets_burst_limit: None,
+        amount_burst_limit: None,
+        amount_per_day_limit: None,
+        amount_per_month_limit: None,
+        max_in_flight_packets: None,
+        max_in_flight_amount: None,
+        circuit_breaker_failure_threshold: None,
+        circuit_breaker_cool_down: None,
         settlement_engine_url: None,
     });
 }
@@ -3,7 +3,7 @@ use super::{fixtures::*, redis_helpers::*, store_helpers::*};
 use interledger_api::{AccountDetails, NodeStore};
 use interledger_ccp::CcpRoutingStore;
 use interledger_packet::Address;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, RouterStore};
 use interledger_service::{Account as AccountTrait, AddressStore, Username};
 use interledger_store::{account::Account, redis::RedisStoreBuilder};
 use std::str::FromStr;
@@ -51,6 +51,14 @@ async fn polls_for_route_updates() {
             round_trip_time: None,
             amount_per_minute_limit: None,
             packets_per_minute_limit: None,
+            packets_burst_limit: None,
+            amount_burst_limit: None,
+            amount_per_day_limit: None,
+            amount_per_month_limit: None,
+            max_in_flight_packets: None,
+            max_in_flight_amount: None,
+            circuit_breaker_failure_threshold: None,
+            circuit_breaker_cool_down: None,
             settlement_engine_url: None,
         })
         .await
@@ -271,3 +279,121 @@ async fn returns_configured_routes_for_route_manager() {
     assert_eq!(configured["example.a"].id(), accs[0].id());
     assert_eq!(configured["example.b"].id(), accs[1].id());
 }
+
+#[tokio::test]
+async fn static_routes_with_several_next_hops() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    store
+        .set_static_multipath_routes(vec![
+            (
+                "example.a".to_string(),
+                vec![
+                    NextHop {
+                        account_id: accs[0].id(),
+                        weight: 3,
+                    },
+                    NextHop {
+                        account_id: accs[1].id(),
+                        weight: 1,
+                    },
+                ],
+            ),
+            (
+                "example.b".to_string(),
+                vec![NextHop {
+                    account_id: accs[1].id(),
+                    weight: 1,
+                }],
+            ),
+        ])
+        .await
+        .unwrap();
+
+    let routes = store.routing_table();
+    assert_eq!(routes["example.a"], accs[0].id());
+    assert_eq!(routes["example.b"], accs[1].id());
+    let multipath_routes = store.multipath_routing_table();
+    assert_eq!(multipath_routes.len(), 1);
+    assert_eq!(multipath_routes["example.a"].len(), 2);
+    assert_eq!(multipath_routes["example.a"][1].account_id, accs[1].id());
+    assert_eq!(multipath_routes["example.a"][1].weight, 1);
+
+    // Setting a single static route replaces the next hops of that prefix
+    store
+        .set_static_route("example.a".to_string(), accs[1].id())
+        .await
+        .unwrap();
+    assert_eq!(store.routing_table()["example.a"], accs[1].id());
+    assert!(store.multipath_routing_table().is_empty());
+}
+
+#[tokio::test]
+async fn doesnt_set_static_next_hops_for_nonexistent_accounts() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let result = store
+        .set_static_multipath_routes(vec![(
+            "example.a".to_string(),
+            vec![
+                NextHop {
+                    account_id: accs[0].id(),
+                    weight: 1,
+                },
+                NextHop {
+                    account_id: Uuid::new_v4(),
+                    weight: 1,
+                },
+            ],
+        )])
+        .await;
+    assert!(result.is_err());
+    assert!(store.routing_table().get("example.a").is_none());
+}
+
+#[tokio::test]
+async fn sets_next_hops_learned_over_ccp() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let account1_id = Uuid::new_v4();
+    let account1 = Account::try_from(
+        account1_id,
+        ACCOUNT_DETAILS_1.clone(),
+        store.get_ilp_address(),
+    )
+    .unwrap();
+    let next_hops = vec![
+        NextHop {
+            account_id: account1_id,
+            weight: 1,
+        },
+        NextHop {
+            account_id: accs[0].id(),
+            weight: 0,
+        },
+    ];
+    let mut ccp_store = store.clone();
+    ccp_store
+        .set_routes(vec![
+            ("example.a".to_string(), account1.clone()),
+            ("example.b".to_string(), account1),
+        ])
+        .await
+        .unwrap();
+    ccp_store
+        .set_multipath_routes(vec![
+            ("example.a".to_string(), next_hops.clone()),
+            ("example.b".to_string(), next_hops.clone()),
+        ])
+        .await
+        .unwrap();
+    let multipath_routes = store.multipath_routing_table();
+    assert_eq!(multipath_routes.len(), 2);
+    assert_eq!(multipath_routes["example.a"], next_hops);
+
+    // Static routes override the next hops learned over CCP
+    store
+        .set_static_route("example.b".to_string(), accs[1].id())
+        .await
+        .unwrap();
+    let multipath_routes = store.multipath_routing_table();
+    assert_eq!(multipath_routes.len(), 1);
+    assert!(multipath_routes.get("example.b").is_none());
+}
@@ -1,3 +1,4 @@
+use super::common::settlement;
 use super::store_helpers::*;
 use bytes::Bytes;
 
@@ -236,3 +237,21 @@ async fn loads_globally_configured_settlement_engine_url() {
         "http://settle-abc.example/"
     );
 }
+
+#[tokio::test]
+async fn refunds_outgoing_settlement_once() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    settlement::refunds_outgoing_settlement_once(store, accs[0].id()).await;
+}
+
+#[tokio::test]
+async fn completes_outgoing_settlement() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    settlement::completes_outgoing_settlement(store, accs[0].id()).await;
+}
+
+#[tokio::test]
+async fn saves_outgoing_settlement_with_fulfill() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    settlement::saves_outgoing_settlement_with_fulfill(store, accs[0].id()).await;
+}
@@ -0,0 +1,8 @@
+use super::common::api_tokens;
+use super::store_helpers::*;
+
+#[tokio::test(threaded_scheduler)]
+async fn saves_loads_and_deletes_api_tokens() {
+    let (store, accs) = test_store().await.unwrap();
+    api_tokens::saves_loads_and_deletes_api_tokens(store, accs).await;
+}
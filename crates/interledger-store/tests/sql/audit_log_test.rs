@@ -0,0 +1,8 @@
+use super::common::audit_log;
+use super::store_helpers::*;
+
+#[tokio::test(threaded_scheduler)]
+async fn appends_and_queries_audit_log() {
+    let (store, _accs) = test_store().await.unwrap();
+    audit_log::appends_and_queries_audit_log(store).await;
+}
@@ -0,0 +1,37 @@
+use super::common::fees;
+use super::store_helpers::*;
+
+use interledger_service::Account as AccountTrait;
+use interledger_service_util::FeeStore;
+use interledger_store::sql::SqlStoreBuilder;
+use uuid::Uuid;
+
+#[tokio::test(threaded_scheduler)]
+async fn sets_and_gets_fee_schedules() {
+    let (store, _accs) = test_store().await.unwrap();
+    fees::sets_and_gets_fee_schedules(store).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn adds_up_fee_revenue() {
+    let (store, accs) = test_store().await.unwrap();
+    fees::adds_up_fee_revenue(store, accs).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn persists_fees_across_connections() {
+    let path = std::env::temp_dir().join(format!("ilp-sql-store-{}.db", Uuid::new_v4()));
+    let database_url = format!("sqlite://{}?mode=rwc", path.display());
+    let (store, accs) = test_store_at(&database_url).await.unwrap();
+    store.set_fee_schedules(fees::schedules()).await.unwrap();
+    store.record_fee_revenue(accs[0].id(), 10).await.unwrap();
+    drop(store);
+
+    let store = SqlStoreBuilder::new(database_url, [0; 32])
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(*store.get_fee_schedules(), fees::schedules());
+    assert_eq!(store.get_fee_revenue().await.unwrap()[&accs[0].id()], 10);
+    let _ = std::fs::remove_file(path);
+}
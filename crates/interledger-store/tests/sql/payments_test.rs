@@ -0,0 +1,8 @@
+use super::common::payments;
+use super::store_helpers::*;
+
+#[tokio::test(threaded_scheduler)]
+async fn saves_and_loads_payments() {
+    let (store, accs) = test_store().await.unwrap();
+    payments::saves_and_loads_payments(store, accs).await;
+}
@@ -0,0 +1,20 @@
+use super::common::stream_connections;
+use super::store_helpers::*;
+
+#[tokio::test(threaded_scheduler)]
+async fn tracks_connection_totals() {
+    let (store, _accs) = test_store().await.unwrap();
+    stream_connections::tracks_connection_totals(store).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn closed_connections_do_not_receive_money() {
+    let (store, _accs) = test_store().await.unwrap();
+    stream_connections::closed_connections_do_not_receive_money(store).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn deletes_idle_connections() {
+    let (store, _accs) = test_store().await.unwrap();
+    stream_connections::deletes_idle_connections(store).await;
+}
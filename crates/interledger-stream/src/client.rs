@@ -1,15 +1,22 @@
-use super::congestion::CongestionController;
+use super::congestion::{
+    CongestionControl, CongestionController, CongestionStrategy, FastRampController,
+    PacketWindowController,
+};
 use super::crypto::*;
 use super::error::Error;
 use super::packet::*;
+use super::receipt::Receipt;
 use bytes::Bytes;
 use bytes::BytesMut;
+use futures::future::{pending, poll_fn, Either};
 use futures::stream::{FuturesUnordered, StreamExt};
+use futures::task::{AtomicWaker, Poll};
+use futures::Future;
 use interledger_packet::{
     Address, ErrorClass, ErrorCode as IlpErrorCode, PacketType as IlpPacketType, PrepareBuilder,
     Reject,
 };
-use interledger_rates::ExchangeRateStore;
+use interledger_rates::{rate_from_f64, ExchangeRateStore};
 use interledger_service::*;
 use num::rational::BigRational;
 use num::traits::cast::{FromPrimitive, ToPrimitive};
@@ -26,19 +33,32 @@ use tracing::{debug, error, warn};
 use std::cmp::{max, min};
 use std::marker::{Send, Sync};
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 
-/// Maximum time we should wait since last fulfill before we error out to avoid
+/// Default maximum time we should wait since last fulfill before we error out to avoid
 /// getting into an infinite loop of sending packets and effectively DoSing ourselves
 const MAX_TIME_SINCE_LAST_FULFILL: Duration = Duration::from_secs(30);
 
-/// Minimum number of packet attempts before defaulting to failure rate
+/// Default minimum number of packet attempts before defaulting to failure rate
 const FAIL_FAST_MINIMUM_PACKET_ATTEMPTS: u64 = 200;
 
-/// Minimum rate of rejected packets in order to terminate the payment
+/// Default minimum rate of rejected packets in order to terminate the payment
 const FAIL_FAST_MINIMUM_FAILURE_RATE: f64 = 0.99;
 
+/// Default time after which each Prepare expires
+const PACKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default factor the amount in flight is divided by per reject for insufficient liquidity
+const DECREASE_FACTOR: f64 = 2.0;
+
+/// Source amount of the first unfulfillable packet sent to probe the exchange rate
+const PROBE_AMOUNT: u64 = 1_000_000;
+
+/// Maximum number of unfulfillable packets sent to probe the exchange rate
+const MAX_PROBE_ATTEMPTS: usize = 10;
+
 /// Receipt for STREAM payment to account for how much and what assets were sent & delivered
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct StreamDelivery {
@@ -64,6 +84,10 @@ pub struct StreamDelivery {
     /// Receiver's asset code
     /// Updated after we received a `ConnectionAssetDetails` frame.
     pub destination_asset_code: Option<String>,
+    /// Base64-encoded [STREAM receipt](./struct.Receipt.html) with the highest total received,
+    /// if the receiver signed any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<String>,
 }
 
 impl StreamDelivery {
@@ -79,14 +103,192 @@ impl StreamDelivery {
             destination_asset_scale: None,
             destination_asset_code: None,
             delivered_amount: 0,
+            receipt: None,
+        }
+    }
+
+    /// Keeps the receipt if it reports a higher total received than the latest one.
+    /// The receipt is not verified because only the verifier knows the receipt secret
+    pub(crate) fn apply_receipt(&mut self, receipt: &[u8]) {
+        let total_received = match Receipt::from_bytes_unverified(receipt) {
+            Ok(parsed) => parsed.total_received,
+            Err(err) => {
+                warn!("Ignoring invalid STREAM receipt: {}", err);
+                return;
+            }
+        };
+        let latest_total_received = self
+            .receipt
+            .as_ref()
+            .and_then(|latest| base64::decode(latest).ok())
+            .and_then(|latest| Receipt::from_bytes_unverified(&latest).ok())
+            .map(|latest| latest.total_received);
+        match latest_total_received {
+            Some(latest) if latest >= total_received => {}
+            _ => self.receipt = Some(base64::encode(receipt)),
+        }
+    }
+}
+
+/// Options for sending STREAM payments. The defaults are the ones
+/// [`send_money`](./fn.send_money.html) uses
+#[derive(Clone, Debug, PartialEq)]
+pub struct SendMoneyOptions {
+    /// How the amount in flight adapts to fulfills and rejects
+    pub congestion_strategy: CongestionStrategy,
+    /// Amount the AIMD strategy adds to the max amount in flight per fulfill once it
+    /// saw insufficient liquidity. Defaults to a tenth of the source amount
+    pub increase_amount: Option<u64>,
+    /// Factor the max amount, or number of packets, in flight is divided by per
+    /// reject for insufficient liquidity
+    pub decrease_factor: f64,
+    /// Maximum number of packets in flight at once, if limited
+    pub max_packets_in_flight: Option<usize>,
+    /// Time after which each Prepare expires
+    pub packet_timeout: Duration,
+    /// Maximum time to wait since the last fulfill before terminating the payment
+    pub max_time_since_last_fulfill: Duration,
+    /// Minimum number of packet attempts before the fail-fast failure rate applies
+    pub fail_fast_minimum_packet_attempts: u64,
+    /// Rate of rejected packets above which the payment is terminated
+    pub fail_fast_minimum_failure_rate: f64,
+    /// Handle which reports the payment's progress and allows cancelling it
+    pub handle: Option<PaymentHandle>,
+}
+
+impl Default for SendMoneyOptions {
+    fn default() -> Self {
+        SendMoneyOptions {
+            congestion_strategy: CongestionStrategy::default(),
+            increase_amount: None,
+            decrease_factor: DECREASE_FACTOR,
+            max_packets_in_flight: None,
+            packet_timeout: PACKET_TIMEOUT,
+            max_time_since_last_fulfill: MAX_TIME_SINCE_LAST_FULFILL,
+            fail_fast_minimum_packet_attempts: FAIL_FAST_MINIMUM_PACKET_ATTEMPTS,
+            fail_fast_minimum_failure_rate: FAIL_FAST_MINIMUM_FAILURE_RATE,
+            handle: None,
         }
     }
 }
 
+impl SendMoneyOptions {
+    /// Builds the congestion controller of the selected strategy for sending the given amount
+    pub(crate) fn congestion_controller(
+        &self,
+        source_amount: u64,
+    ) -> Box<dyn CongestionControl + Send> {
+        match self.congestion_strategy {
+            CongestionStrategy::Aimd => Box::new(CongestionController::new(
+                source_amount,
+                self.increase_amount.unwrap_or(source_amount / 10),
+                self.decrease_factor,
+            )),
+            CongestionStrategy::FastRamp => {
+                Box::new(FastRampController::new(source_amount, self.decrease_factor))
+            }
+            CongestionStrategy::PacketWindow => Box::new(PacketWindowController::new(
+                self.max_packets_in_flight.unwrap_or(usize::MAX),
+                self.decrease_factor,
+            )),
+        }
+    }
+
+    /// Given enough packet attempts, does the rate of rejects indicate the payment is failing?
+    pub(crate) fn is_failing(&self, num_packets: u64, rejected_packets: u64) -> bool {
+        num_packets >= self.fail_fast_minimum_packet_attempts
+            && (rejected_packets as f64 / num_packets as f64) > self.fail_fast_minimum_failure_rate
+    }
+}
+
+/// Handle to a STREAM payment sent with [`SendMoneyOptions::handle`](./struct.SendMoneyOptions.html),
+/// which reports the payment's progress while it is being sent and allows cancelling it.
+/// Clones of the handle refer to the same payment
+#[derive(Clone, Debug, Default)]
+pub struct PaymentHandle {
+    inner: Arc<PaymentHandleInner>,
+}
+
+#[derive(Debug, Default)]
+struct PaymentHandleInner {
+    /// Latest receipt of the payment, once it started sending packets
+    progress: parking_lot::Mutex<Option<StreamDelivery>>,
+    cancelled: AtomicBool,
+    /// Wakes up the payment waiting on packets in flight when it is cancelled
+    waker: AtomicWaker,
+}
+
+impl PaymentHandle {
+    pub fn new() -> Self {
+        PaymentHandle::default()
+    }
+
+    /// The sent, in-flight and delivered amounts of the payment so far,
+    /// or None if it did not start sending packets yet
+    pub fn progress(&self) -> Option<StreamDelivery> {
+        self.inner.progress.lock().clone()
+    }
+
+    /// Stops the payment from sending more packets. The payment then waits for the
+    /// packets in flight, closes the STREAM connection and fails
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.waker.wake();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn set_progress(&self, receipt: &StreamDelivery) {
+        *self.inner.progress.lock() = Some(receipt.clone());
+    }
+
+    /// Resolves once the payment is cancelled
+    fn cancelled(&self) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| {
+            self.inner.waker.register(cx.waker());
+            if self.is_cancelled() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl PartialEq for PaymentHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+/// Exchange rate and path details probed by [`quote`](./fn.quote.html) without sending any money
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StreamQuote {
+    /// Sender's ILP Address
+    pub from: Address,
+    /// Receiver's ILP Address
+    pub to: Address,
+    /// Asset scale of sender
+    pub source_asset_scale: u8,
+    /// Asset code of sender
+    pub source_asset_code: String,
+    /// Receiver's asset scale, if the receiver shared its asset details
+    pub destination_asset_scale: Option<u8>,
+    /// Receiver's asset code, if the receiver shared its asset details
+    pub destination_asset_code: Option<String>,
+    /// Amount the receiver got per unit sent, both in their smallest units
+    pub rate: f64,
+    /// Largest amount the path forwards in a single packet, in source units,
+    /// if a node in the path rejected a probe for being too large
+    pub max_packet_amount: Option<u64>,
+}
+
 /// Stream payment mutable state: amounts & assets sent and received, sequence, packet counts, and flow control parameters
 struct StreamPayment {
-    /// The [congestion controller](./../congestion/struct.CongestionController.html) to adjust flow control and the in-flight amount
-    congestion_controller: CongestionController,
+    /// The [congestion controller](./../congestion/trait.CongestionControl.html) to adjust flow control and the in-flight amount
+    congestion_controller: Box<dyn CongestionControl + Send>,
     /// The [StreamDelivery](./struct.StreamDelivery.html) receipt to account for the delivered amounts
     receipt: StreamDelivery,
     /// Do we need to send our source account information to the recipient?
@@ -101,6 +303,15 @@ struct StreamPayment {
     fail_fast_rejects: u64,
     /// Timestamp when a packet was last fulfilled for this payment
     last_fulfill_time: Instant,
+    /// Amount to deliver, in destination units, if the payment delivers a fixed amount.
+    /// The receipt's source amount is then the most the payment may send
+    destination_amount: Option<u64>,
+    /// Probed rate used to size the packets of fixed delivery payments
+    probed_rate: Option<BigRational>,
+    /// Minimum rate fixed for the whole payment, instead of the exchange rate store's rate
+    min_rate: Option<BigRational>,
+    /// Amount expected to be delivered by the packets in flight, in destination units
+    in_flight_destination_amount: u64,
 }
 
 impl StreamPayment {
@@ -111,15 +322,18 @@ impl StreamPayment {
         // Determine scaled rate with slippage used for enforcing minimum destination amount
         // and computing its corresponding minimum source amount,
         // where source_amount * scaled_rate = dest_amount.
-        let rate = get_rate(
-            store,
-            self.receipt.source_asset_scale,
-            &self.receipt.source_asset_code,
-            self.receipt.destination_asset_scale,
-            self.receipt.destination_asset_code.as_deref(),
-            slippage,
-        )
-        .unwrap_or_else(BigRational::zero);
+        let rate = match self.min_rate {
+            Some(ref min_rate) => min_rate.clone(),
+            None => get_rate(
+                store,
+                self.receipt.source_asset_scale,
+                &self.receipt.source_asset_code,
+                self.receipt.destination_asset_scale,
+                self.receipt.destination_asset_code.as_deref(),
+                slippage,
+            )
+            .unwrap_or_else(BigRational::zero),
+        };
 
         // Margin of error is the minimum difference between our scaled rate and scaled rate of intermediaries.
         // This should probably be much smaller than the slippage we're willing to accept.
@@ -178,6 +392,9 @@ impl StreamPayment {
         self.congestion_controller.prepare(source_amount);
         self.receipt.sent_amount = self.receipt.sent_amount.saturating_add(source_amount);
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_add(source_amount);
+        self.in_flight_destination_amount = self
+            .in_flight_destination_amount
+            .saturating_add(self.expected_destination_amount(source_amount));
 
         // Compute the minimum destination amount using the same rate
         let min_destination_amount = convert(source_amount, rate).unwrap_or(0);
@@ -190,6 +407,9 @@ impl StreamPayment {
         self.congestion_controller.fulfill(source_amount);
 
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_sub(source_amount);
+        self.in_flight_destination_amount = self
+            .in_flight_destination_amount
+            .saturating_sub(self.expected_destination_amount(source_amount));
         self.receipt.delivered_amount = self
             .receipt
             .delivered_amount
@@ -206,6 +426,9 @@ impl StreamPayment {
 
         self.receipt.sent_amount = self.receipt.sent_amount.saturating_sub(amount);
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_sub(amount);
+        self.in_flight_destination_amount = self
+            .in_flight_destination_amount
+            .saturating_sub(self.expected_destination_amount(amount));
 
         self.rejected_packets += 1;
 
@@ -254,18 +477,57 @@ impl StreamPayment {
     }
 
     /// Has the entire intended source amount been fulfilled by the recipient?
+    /// For fixed delivery payments, has the recipient received the entire destination amount?
     #[inline]
     fn is_complete(&self) -> bool {
-        self.get_remaining_amount() == 0
+        match self.destination_amount {
+            Some(destination_amount) => self.receipt.delivered_amount >= destination_amount,
+            None => self.get_remaining_amount() == 0,
+        }
+    }
+
+    /// Has a fixed delivery payment sent as much as it may without delivering the destination amount?
+    #[inline]
+    fn is_exhausted(&self) -> bool {
+        !self.is_complete()
+            && self.receipt.in_flight_amount == 0
+            && self.get_amount_available_to_send() == 0
     }
 
     /// Return the amount of money available to be sent in the payment (amount remaining minus in-flight)
     #[inline]
     fn get_amount_available_to_send(&self) -> u64 {
         // Sent amount also includes the amount in-flight, which should be subtracted from the amount available
-        self.receipt
+        let available = self
+            .receipt
             .source_amount
-            .saturating_sub(self.receipt.sent_amount)
+            .saturating_sub(self.receipt.sent_amount);
+        match (self.destination_amount, &self.probed_rate) {
+            // Only send what the recipient still needs to get, net of the packets in flight
+            (Some(destination_amount), Some(probed_rate)) => {
+                let remaining_destination_amount = destination_amount
+                    .saturating_sub(self.receipt.delivered_amount)
+                    .saturating_sub(self.in_flight_destination_amount);
+                let needed = BigRational::from_u64(remaining_destination_amount)
+                    .and_then(|remaining| remaining.checked_div(probed_rate))
+                    .and_then(|needed| needed.ceil().to_integer().to_u64())
+                    .unwrap_or(available);
+                min(available, needed)
+            }
+            _ => available,
+        }
+    }
+
+    /// Amount a packet is expected to deliver at the probed rate, in destination units.
+    /// Zero unless the payment delivers a fixed amount
+    #[inline]
+    fn expected_destination_amount(&self, source_amount: u64) -> u64 {
+        match self.probed_rate {
+            Some(ref probed_rate) => BigRational::from_u64(source_amount)
+                .and_then(|amount| (amount * probed_rate).floor().to_integer().to_u64())
+                .unwrap_or(0),
+            None => 0,
+        }
     }
 
     /// Is as much money as possible in-flight?
@@ -280,10 +542,9 @@ impl StreamPayment {
     /// Given we've attempted sending enough packets, does the rate of rejects
     /// that count towards fail-fast indicate the payment is failing?
     #[inline]
-    fn is_failing(&self) -> bool {
+    fn is_failing(&self, options: &SendMoneyOptions) -> bool {
         let num_packets = self.fulfilled_packets + self.rejected_packets;
-        num_packets >= FAIL_FAST_MINIMUM_PACKET_ATTEMPTS
-            && (self.fail_fast_rejects as f64 / num_packets as f64) > FAIL_FAST_MINIMUM_FAILURE_RATE
+        options.is_failing(num_packets, self.fail_fast_rejects)
     }
 }
 
@@ -303,40 +564,198 @@ where
     A: Account + Send + Sync + 'static,
     S: ExchangeRateStore + Send + Sync + 'static,
 {
-    let shared_secret = Bytes::from(shared_secret);
-
-    let from = from_account.ilp_address();
-    if from.scheme() != destination_account.scheme() {
-        warn!(
-            "Destination ILP address starts with a different scheme prefix (\"{}\') than ours (\"{}\'), this probably won't work",
-            destination_account.scheme(),
-            from.scheme()
-        );
-    }
+    send_money_with_options(
+        service,
+        from_account,
+        store,
+        destination_account,
+        shared_secret,
+        source_amount,
+        slippage,
+        SendMoneyOptions::default(),
+    )
+    .await
+}
 
-    let mut sender = StreamSender {
-        next: service,
-        from_account: from_account.clone(),
+/// Like [`send_money`](./fn.send_money.html), but with the given congestion control strategy,
+/// timeouts and fail-fast thresholds
+#[allow(clippy::too_many_arguments)]
+pub async fn send_money_with_options<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    source_amount: u64,
+    slippage: f64,
+    options: SendMoneyOptions,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let sender = StreamSender::new(
+        service,
+        from_account,
+        store,
+        destination_account,
         shared_secret,
+        source_amount,
+        slippage,
+        options,
+    );
+    run_payment(sender).await
+}
+
+/// Deliver exactly the given destination amount with packetized Interledger payments using the
+/// STREAM transport protocol.
+///
+/// The exchange rate is first probed with unfulfillable packets. The payment then sends up to the
+/// source amount needed to deliver the destination amount at the probed rate minus the slippage,
+/// and fails if the rate drops below that or the exchange rate store's rate minus the slippage.
+/// Returns the receipt with sent & delivered amounts, asset & account details
+#[allow(clippy::too_many_arguments)]
+pub async fn send_money_fixed_delivery<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    destination_amount: u64,
+    slippage: f64,
+    options: SendMoneyOptions,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let mut sender = StreamSender::new(
+        service,
+        from_account,
         store,
+        destination_account,
+        shared_secret,
+        0,
         slippage,
-        payment: Arc::new(Mutex::new(StreamPayment {
-            // TODO Make configurable to get money flowing ASAP vs as much as possible per-packet
-            congestion_controller: CongestionController::new(
-                source_amount,
-                source_amount / 10,
-                2.0,
-            ),
-            receipt: StreamDelivery::new(from_account, destination_account, source_amount),
-            should_send_source_account: true,
-            sequence: 1,
-            fulfilled_packets: 0,
-            rejected_packets: 0,
-            fail_fast_rejects: 0,
-            last_fulfill_time: Instant::now(),
-        })),
-    };
+        options,
+    );
+    let (probe_source_amount, probe_destination_amount) = sender.probe_rate().await?;
+
+    {
+        let mut payment = sender.payment.lock().await;
+        let probed_rate = BigRational::new(
+            BigInt::from(probe_destination_amount),
+            BigInt::from(probe_source_amount),
+        );
+        let slippage_factor = BigRational::one()
+            - rate_from_f64(slippage)
+                .ok_or_else(|| Error::SendMoneyError(format!("Invalid slippage: {}", slippage)))?;
+        // Tolerate the rate dropping by the slippage during the payment,
+        // but never below the exchange rate store's rate minus the slippage
+        let mut min_rate = probed_rate.clone() * slippage_factor;
+        if let Some(store_rate) = get_rate(
+            &sender.store,
+            payment.receipt.source_asset_scale,
+            &payment.receipt.source_asset_code,
+            payment.receipt.destination_asset_scale,
+            payment.receipt.destination_asset_code.as_deref(),
+            slippage,
+        ) {
+            if probed_rate < store_rate {
+                return Err(Error::SendMoneyError(format!(
+                    "Probed exchange rate of {} is below the minimum rate of {}",
+                    rate_to_f64(&probed_rate),
+                    rate_to_f64(&store_rate),
+                )));
+            }
+            min_rate = max(min_rate, store_rate);
+        }
+
+        let source_amount = BigRational::from_u64(destination_amount)
+            .and_then(|amount| amount.checked_div(&min_rate))
+            .and_then(|amount| amount.ceil().to_integer().to_u64())
+            .ok_or_else(|| {
+                Error::SendMoneyError(format!(
+                    "Unable to compute the source amount needed to deliver {}",
+                    destination_amount
+                ))
+            })?;
+        debug!(
+            "Sending up to {} to deliver {} at a probed rate of {}",
+            source_amount,
+            destination_amount,
+            rate_to_f64(&probed_rate)
+        );
+
+        // Keep the max packet amount the probes discovered
+        let max_packet_amount = payment.congestion_controller.get_max_packet_amount();
+        payment.congestion_controller = sender.options.congestion_controller(source_amount);
+        if max_packet_amount < u64::MAX {
+            payment
+                .congestion_controller
+                .set_max_packet_amount(max_packet_amount);
+        }
+        payment.receipt.source_amount = source_amount;
+        payment.destination_amount = Some(destination_amount);
+        payment.probed_rate = Some(probed_rate);
+        payment.min_rate = Some(min_rate);
+    }
+
+    run_payment(sender).await
+}
 
+/// Probe the exchange rate and max packet amount of the path to the receiver with
+/// unfulfillable packets, without sending any money
+pub async fn quote<I, A>(
+    service: I,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+) -> Result<StreamQuote, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+{
+    let mut sender = StreamSender::new(
+        service,
+        from_account,
+        (),
+        destination_account,
+        shared_secret,
+        0,
+        0.0,
+        SendMoneyOptions::default(),
+    );
+    let (probe_source_amount, probe_destination_amount) = sender.probe_rate().await?;
+    sender.try_send_connection_close().await;
+
+    let payment = sender.payment.lock().await;
+    let max_packet_amount = payment.congestion_controller.get_max_packet_amount();
+    Ok(StreamQuote {
+        from: payment.receipt.from.clone(),
+        to: payment.receipt.to.clone(),
+        source_asset_scale: payment.receipt.source_asset_scale,
+        source_asset_code: payment.receipt.source_asset_code.clone(),
+        destination_asset_scale: payment.receipt.destination_asset_scale,
+        destination_asset_code: payment.receipt.destination_asset_code.clone(),
+        rate: probe_destination_amount as f64 / probe_source_amount as f64,
+        max_packet_amount: if max_packet_amount < u64::MAX {
+            Some(max_packet_amount)
+        } else {
+            None
+        },
+    })
+}
+
+/// Send packets until the payment completes or fails, and return its receipt
+async fn run_payment<I, A, S>(mut sender: StreamSender<I, A, S>) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
     let mut pending_requests = FuturesUnordered::new();
 
     /// Actions corresponding to the state of the payment
@@ -351,22 +770,42 @@ where
         Timeout,
         /// Too many packets are rejected, such as if the exchange rate is too low: terminate the payment
         FailFast,
+        /// Sent the most a fixed delivery payment may send without delivering enough: terminate the payment
+        Exhausted,
+        /// The payment was cancelled through its handle: close the connection and terminate the payment
+        Cancelled,
     }
 
     loop {
         let event = {
             let mut payment = sender.payment.lock().await;
+            if let Some(ref handle) = sender.options.handle {
+                handle.set_progress(&payment.receipt);
+            }
 
-            if payment.last_fulfill_time.elapsed() >= MAX_TIME_SINCE_LAST_FULFILL {
+            let max_packets_in_flight = sender.options.max_packets_in_flight.unwrap_or(usize::MAX);
+            if sender
+                .options
+                .handle
+                .as_ref()
+                .map_or(false, PaymentHandle::is_cancelled)
+            {
+                PaymentEvent::Cancelled
+            } else if payment.last_fulfill_time.elapsed()
+                >= sender.options.max_time_since_last_fulfill
+            {
                 PaymentEvent::Timeout
-            } else if payment.is_failing() {
+            } else if payment.is_failing(&sender.options) {
                 PaymentEvent::FailFast
             } else if payment.is_complete() {
                 PaymentEvent::CloseConnection
-            } else if payment.is_max_in_flight() {
+            } else if payment.is_exhausted() {
+                PaymentEvent::Exhausted
+            } else if payment.is_max_in_flight() || pending_requests.len() >= max_packets_in_flight
+            {
                 let deadline = payment
                     .last_fulfill_time
-                    .checked_add(MAX_TIME_SINCE_LAST_FULFILL)
+                    .checked_add(sender.options.max_time_since_last_fulfill)
                     .unwrap();
                 PaymentEvent::MaxInFlight(deadline)
             } else {
@@ -382,12 +821,23 @@ where
                 }));
             }
             PaymentEvent::MaxInFlight(deadline) => {
+                let cancelled = match sender.options.handle {
+                    Some(ref handle) => Either::Left(handle.cancelled()),
+                    None => Either::Right(pending::<()>()),
+                };
                 // Wait for any request to complete, or if after reach deadline since last fulfill,
-                // run loop again, which should timeout the payment
-                let result = timeout_at(deadline, pending_requests.select_next_some()).await;
+                // run loop again, which should timeout the payment.
+                // If the payment is cancelled meanwhile, run loop again to stop it right away
+                let result = tokio::select! {
+                    result = timeout_at(deadline, pending_requests.select_next_some()) => result,
+                    _ = cancelled => continue,
+                };
 
                 if let Ok(Ok(Err(error))) = result {
                     error!("Send money stopped because of error: {:?}", error);
+                    if let Some(ref handle) = sender.options.handle {
+                        handle.set_progress(&sender.payment.lock().await.receipt);
+                    }
                     return Err(error);
                 }
             }
@@ -400,6 +850,9 @@ where
 
                 // Return final receipt
                 let payment = sender.payment.lock().await;
+                if let Some(ref handle) = sender.options.handle {
+                    handle.set_progress(&payment.receipt);
+                }
                 debug!(
                     "Send money future finished. Delivered: {} ({} packets fulfilled, {} packets rejected)",
                     payment.receipt.delivered_amount,
@@ -422,6 +875,30 @@ where
                     payment.rejected_packets,
                 )));
             }
+            PaymentEvent::Cancelled => {
+                // Wait for the packets in flight so the receipt accounts for them
+                pending_requests.map(|_| ()).collect::<()>().await;
+                sender.try_send_connection_close().await;
+
+                let payment = sender.payment.lock().await;
+                if let Some(ref handle) = sender.options.handle {
+                    handle.set_progress(&payment.receipt);
+                }
+                return Err(Error::SendMoneyError(format!(
+                    "Payment was cancelled after sending {} and delivering {}",
+                    payment.receipt.sent_amount, payment.receipt.delivered_amount,
+                )));
+            }
+            PaymentEvent::Exhausted => {
+                sender.try_send_connection_close().await;
+                let payment = sender.payment.lock().await;
+                return Err(Error::SendMoneyError(format!(
+                    "Sent {} but only delivered {} of {}, since the exchange rate dropped",
+                    payment.receipt.sent_amount,
+                    payment.receipt.delivered_amount,
+                    payment.destination_amount.unwrap_or_default(),
+                )));
+            }
         }
     }
 }
@@ -439,6 +916,8 @@ struct StreamSender<I, A, S> {
     store: S,
     /// Maximum acceptable slippage percentage below calculated minimum exchange rate
     slippage: f64,
+    /// Congestion control strategy, timeouts and fail-fast thresholds
+    options: SendMoneyOptions,
     /// Mutable payment state
     payment: Arc<Mutex<StreamPayment>>,
 }
@@ -447,8 +926,173 @@ impl<I, A, S> StreamSender<I, A, S>
 where
     I: IncomingService<A>,
     A: Account,
-    S: ExchangeRateStore,
 {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        next: I,
+        from_account: &A,
+        store: S,
+        destination_account: Address,
+        shared_secret: Vec<u8>,
+        source_amount: u64,
+        slippage: f64,
+        options: SendMoneyOptions,
+    ) -> Self {
+        let from = from_account.ilp_address();
+        if from.scheme() != destination_account.scheme() {
+            warn!(
+                "Destination ILP address starts with a different scheme prefix (\"{}\') than ours (\"{}\'), this probably won't work",
+                destination_account.scheme(),
+                from.scheme()
+            );
+        }
+
+        StreamSender {
+            next,
+            from_account: from_account.clone(),
+            shared_secret: Bytes::from(shared_secret),
+            store,
+            slippage,
+            payment: Arc::new(Mutex::new(StreamPayment {
+                congestion_controller: options.congestion_controller(source_amount),
+                receipt: StreamDelivery::new(from_account, destination_account, source_amount),
+                should_send_source_account: true,
+                sequence: 1,
+                fulfilled_packets: 0,
+                rejected_packets: 0,
+                fail_fast_rejects: 0,
+                last_fulfill_time: Instant::now(),
+                destination_amount: None,
+                probed_rate: None,
+                min_rate: None,
+                in_flight_destination_amount: 0,
+            })),
+            options,
+        }
+    }
+
+    /// Send unfulfillable Prepares to learn the receiver's asset details, the exchange rate
+    /// and the max packet amount of the path, without sending any money.
+    /// Returns the source amount of the successful probe and the amount the receiver got for it
+    async fn probe_rate(&mut self) -> Result<(u64, u64), Error> {
+        let mut amount = PROBE_AMOUNT;
+        for _ in 0..MAX_PROBE_ATTEMPTS {
+            let (prepare, sequence) = {
+                let mut payment = self.payment.lock().await;
+                amount = min(
+                    amount,
+                    payment.congestion_controller.get_max_packet_amount(),
+                );
+                let sequence = payment.next_sequence();
+                let frames = [Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
+                    source_account: payment.receipt.from.clone(),
+                })];
+                let data = StreamPacketBuilder {
+                    ilp_packet_type: IlpPacketType::Prepare,
+                    prepare_amount: 0,
+                    sequence,
+                    frames: &frames,
+                }
+                .build()
+                .into_encrypted(&self.shared_secret);
+                let prepare = PrepareBuilder {
+                    destination: payment.receipt.to.clone(),
+                    amount,
+                    execution_condition: &random_condition(),
+                    expires_at: SystemTime::now() + self.options.packet_timeout,
+                    data: &data[..],
+                }
+                .build();
+                (prepare, sequence)
+            };
+
+            debug!(
+                "Probing exchange rate with packet {} of {}",
+                sequence, amount
+            );
+            let reject = match self
+                .next
+                .handle_request(IncomingRequest {
+                    from: self.from_account.clone(),
+                    prepare,
+                })
+                .await
+            {
+                Ok(_) => {
+                    return Err(Error::SendMoneyError(
+                        "Unfulfillable probe packet was fulfilled".to_string(),
+                    ))
+                }
+                Err(reject) => reject,
+            };
+
+            let mut payment = self.payment.lock().await;
+            let stream_reply_packet =
+                StreamPacket::from_encrypted(&self.shared_secret, BytesMut::from(reject.data()))
+                    .ok()
+                    .filter(|packet| packet.sequence() == sequence);
+            if let Some(stream_reply_packet) = stream_reply_packet {
+                payment.should_send_source_account = false;
+                for frame in stream_reply_packet.frames() {
+                    if let Frame::ConnectionAssetDetails(frame) = frame {
+                        payment.set_destination_asset_details(
+                            frame.source_asset_code.to_string(),
+                            frame.source_asset_scale,
+                        );
+                    }
+                }
+
+                let received = stream_reply_packet.prepare_amount();
+                if received > 0 {
+                    return Ok((amount, received));
+                }
+                // The amount rounded down to zero along the path, so try a larger one
+                if amount >= payment.congestion_controller.get_max_packet_amount()
+                    || amount > u64::MAX / 10
+                {
+                    return Err(Error::SendMoneyError(format!(
+                        "Receiver got nothing for a probe of {}",
+                        amount
+                    )));
+                }
+                amount *= 10;
+                continue;
+            }
+
+            match (reject.code().class(), reject.code()) {
+                (_, IlpErrorCode::F08_AMOUNT_TOO_LARGE) => {
+                    payment.congestion_controller.prepare(amount);
+                    payment.congestion_controller.reject(amount, &reject);
+                    let max_packet_amount = payment.congestion_controller.get_max_packet_amount();
+                    amount = if max_packet_amount < amount {
+                        max_packet_amount
+                    } else {
+                        amount / 10
+                    };
+                    if amount == 0 {
+                        return Err(Error::SendMoneyError(
+                            "Path does not forward packets with any money".to_string(),
+                        ));
+                    }
+                }
+                (_, IlpErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT) => {
+                    amount = amount.saturating_mul(10);
+                }
+                (ErrorClass::Temporary, _) => {}
+                _ => {
+                    return Err(Error::SendMoneyError(format!(
+                        "Probe was rejected with error: {} {}",
+                        reject.code(),
+                        str::from_utf8(reject.message()).unwrap_or_default(),
+                    )))
+                }
+            }
+        }
+        Err(Error::SendMoneyError(
+            "Unable to probe the exchange rate".to_string(),
+        ))
+    }
+
     /// Send a Prepare for the given source amount and apply the resulting Fulfill or Reject
     #[inline]
     pub async fn send_money_packet(
@@ -498,7 +1142,7 @@ where
                 destination: payment.receipt.to.clone(),
                 amount: source_amount,
                 execution_condition: &execution_condition,
-                expires_at: SystemTime::now() + Duration::from_secs(30),
+                expires_at: SystemTime::now() + self.options.packet_timeout,
                 // TODO Don't copy the data
                 data: &prepare_data[..],
             }
@@ -563,6 +1207,12 @@ where
                         }
                     }
 
+                    for frame in stream_reply_packet.frames() {
+                        if let Frame::StreamReceipt(frame) = frame {
+                            payment.receipt.apply_receipt(frame.receipt);
+                        }
+                    }
+
                     stream_reply_packet.prepare_amount()
                 }
             }
@@ -648,7 +1298,7 @@ where
                 destination: payment.receipt.to.clone(),
                 amount: 0,
                 execution_condition: &random_condition(),
-                expires_at: SystemTime::now() + Duration::from_secs(30),
+                expires_at: SystemTime::now() + self.options.packet_timeout,
                 data: &data[..],
             }
             .build()
@@ -674,7 +1324,7 @@ where
 /// fetching from the provider's exchange rates, subtracting slippage, and adjusting scales.
 /// Returns None if destination asset details are unknown or rate cannot be calculated.
 #[inline]
-fn get_rate<S: ExchangeRateStore>(
+pub(crate) fn get_rate<S: ExchangeRateStore>(
     store: &S,
     source_scale: u8,
     source_code: &str,
@@ -689,13 +1339,13 @@ fn get_rate<S: ExchangeRateStore>(
     let rate: BigRational = if source_code == dest_code {
         BigRational::one()
     } else if let Ok(prices) = store.get_exchange_rates(&[&source_code, &dest_code]) {
-        BigRational::from_f64(prices[0])? / BigRational::from_f64(prices[1])?
+        prices[0].checked_div(&prices[1])?
     } else {
         return None;
     };
 
     // Subtract slippage from rate
-    let slippage = BigRational::from_f64(slippage)?;
+    let slippage = rate_from_f64(slippage)?;
     let mut rate = rate * (BigRational::one() - slippage);
 
     // Scale rate based on source scale
@@ -707,10 +1357,18 @@ fn get_rate<S: ExchangeRateStore>(
     Some(rate)
 }
 
+/// Approximate the rate as a float, for logging and quotes
+fn rate_to_f64(rate: &BigRational) -> f64 {
+    match (rate.numer().to_f64(), rate.denom().to_f64()) {
+        (Some(numer), Some(denom)) => numer / denom,
+        _ => 0.0,
+    }
+}
+
 /// Convert the given source amount into a destination amount
 /// using the provided rate. Round up for safety.
 #[inline]
-fn convert(source_amount: u64, rate: BigRational) -> Option<u64> {
+pub(crate) fn convert(source_amount: u64, rate: BigRational) -> Option<u64> {
     // First, convert scaled source amount to base unit
     let source_amount = BigRational::from_u64(source_amount)?;
 
@@ -915,6 +1573,148 @@ mod send_money_tests {
         assert_eq!(num_requests_in_flight.load(Ordering::Relaxed), 5);
     }
 
+    #[tokio::test]
+    async fn limits_packets_in_flight() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: Some(10),
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account)),
+            price_1: None,
+            price_2: None,
+        };
+
+        #[derive(Clone)]
+        struct CounterService {
+            pub num_requests_in_flight: Arc<AtomicUsize>,
+        }
+
+        impl CounterService {
+            pub fn new(num_requests_in_flight: Arc<AtomicUsize>) -> Self {
+                CounterService {
+                    num_requests_in_flight,
+                }
+            }
+        }
+
+        #[async_trait]
+        impl<A> IncomingService<A> for CounterService
+        where
+            A: Account + 'static,
+        {
+            async fn handle_request(&mut self, _: IncomingRequest<A>) -> IlpResult {
+                self.num_requests_in_flight.fetch_add(1, Ordering::Relaxed);
+
+                // Wait for 100ms while all requests are received, then reject with final error to terminate stream
+                timeout(
+                    Duration::from_millis(100),
+                    futures::future::pending::<IlpResult>(),
+                )
+                .await
+                .unwrap_or_else(|_| {
+                    Err(RejectBuilder {
+                        code: IlpErrorCode::F00_BAD_REQUEST,
+                        message: b"some final error",
+                        triggered_by: Some(&EXAMPLE_CONNECTOR),
+                        data: &[],
+                    }
+                    .build())
+                })
+            }
+        }
+
+        let num_requests_in_flight = Arc::new(AtomicUsize::new(0));
+        let counter_service = CounterService::new(num_requests_in_flight.clone());
+
+        let result = send_money_with_options(
+            MaxPacketAmountService::new(store, counter_service),
+            &TestAccount {
+                id: Uuid::new_v4(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                ilp_address: destination_address.clone(),
+                max_packet_amount: Some(10),
+            },
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address.clone(),
+            vec![0; 32],
+            50,
+            0.0,
+            SendMoneyOptions {
+                max_packets_in_flight: Some(2),
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(num_requests_in_flight.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_when_cancelled() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let handle = PaymentHandle::new();
+        let handle_clone = handle.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            handle_clone.cancel();
+        });
+
+        let result = timeout(
+            Duration::from_secs(5),
+            send_money_with_options(
+                incoming_service_fn(move |_| {
+                    Err(RejectBuilder {
+                        code: IlpErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                        message: b"settle up!",
+                        triggered_by: Some(&EXAMPLE_CONNECTOR),
+                        data: &[],
+                    }
+                    .build())
+                }),
+                &account,
+                TestStore {
+                    route: None,
+                    price_1: None,
+                    price_2: None,
+                },
+                destination_address.clone(),
+                vec![0; 32],
+                100,
+                0.0,
+                SendMoneyOptions {
+                    handle: Some(handle.clone()),
+                    ..SendMoneyOptions::default()
+                },
+            ),
+        )
+        .await
+        .expect("Cancelled payment should stop before timing out");
+
+        assert!(result.unwrap_err().to_string().contains("cancelled"));
+        assert!(handle.is_cancelled());
+        let progress = handle.progress().unwrap();
+        assert_eq!(progress.in_flight_amount, 0);
+        assert_eq!(progress.delivered_amount, 0);
+    }
+
     #[tokio::test]
     async fn computes_min_destination_amount() {
         struct TestData<'a> {
@@ -8,11 +8,69 @@ use std::cmp::{max, min};
 use std::io;
 use tracing::{debug, warn};
 
+/// Flow control of a STREAM sender, which decides how much money may be in flight
+/// and how large packets may be, based on the fulfills and rejects it gets
+pub trait CongestionControl {
+    /// Maximium allowed packet amount allowed to send in a packet per F08s
+    fn get_max_packet_amount(&self) -> u64;
+
+    /// Sets the maximum packet amount, such as one discovered before sending money
+    fn set_max_packet_amount(&mut self, max_packet_amount: u64);
+
+    /// The maximum amount availble to be sent in a new packet
+    fn get_amount_left_in_window(&self) -> u64;
+
+    /// Accounts for a packet of the provided amount being sent
+    fn prepare(&mut self, amount: u64);
+
+    /// Accounts for a packet of the provided amount being fulfilled
+    fn fulfill(&mut self, prepare_amount: u64);
+
+    /// Accounts for a packet of the provided amount being rejected
+    fn reject(&mut self, prepare_amount: u64, reject: &Reject);
+}
+
+/// The congestion control strategies a STREAM sender may use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CongestionStrategy {
+    /// Double the amount in flight until a packet is rejected for insufficient liquidity,
+    /// then use Additive Increase, Multiplicative Decrease (AIMD)
+    #[default]
+    Aimd,
+    /// Put the whole amount in flight from the start, and double the amount in flight after
+    /// every fulfill to recover quickly from rejects for insufficient liquidity
+    FastRamp,
+    /// Limit the number of packets in flight rather than the amount, so each packet
+    /// carries as much as the max packet amount allows
+    PacketWindow,
+}
+
+/// Lowers the max packet amount after an F08 reject, from the amounts in the reject data
+/// if it has them, or by the decrease factor otherwise
+fn apply_amount_too_large(
+    max_packet_amount: &mut Option<u64>,
+    prepare_amount: u64,
+    reject: &Reject,
+    decrease_factor: f64,
+) {
+    if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
+        let new_max_packet_amount: u64 =
+            prepare_amount * details.max_amount() / details.amount_received();
+        if let Some(current) = *max_packet_amount {
+            *max_packet_amount = Some(min(current, new_max_packet_amount));
+        } else {
+            *max_packet_amount = Some(new_max_packet_amount);
+        }
+    } else {
+        warn!("Got F08: Amount Too Large Error without max packet amount details attached");
+        if let Some(current) = *max_packet_amount {
+            *max_packet_amount = Some((current as f64 / decrease_factor) as u64);
+        }
+    }
+}
+
 /// A basic congestion controller that implements an
 /// Additive Increase, Multiplicative Decrease (AIMD) algorithm.
-///
-/// Future implementations of this will use more advanced congestion
-/// control algorithms.
 pub struct CongestionController {
     state: CongestionState,
     /// Amount which is added to `max_in_flight` per fulfill
@@ -60,18 +118,35 @@ impl CongestionController {
         }
     }
 
-    /// Maximium allowed packet amount allowed to send in a packet per F08s
-    pub fn get_max_packet_amount(&self) -> u64 {
-        self.max_packet_amount.unwrap_or(u64::max_value())
+    #[cfg(feature = "metrics_csv")]
+    fn log_stats(&mut self, amount_sent: u64) {
+        self.csv_writer
+            .write_record(&[
+                format!("{}", Utc::now().timestamp_millis()),
+                format!("{}", self.max_in_flight),
+                format!("{}", amount_sent),
+            ])
+            .unwrap();
+        self.csv_writer.flush().unwrap();
+    }
+}
+
+impl CongestionControl for CongestionController {
+    fn get_max_packet_amount(&self) -> u64 {
+        self.max_packet_amount.unwrap_or(u64::MAX)
+    }
+
+    fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
+        self.max_packet_amount = Some(max_packet_amount)
     }
 
     /// The maximum amount availble to be sent is the maximum amount in flight minus the current amount in flight
-    pub fn get_amount_left_in_window(&self) -> u64 {
+    fn get_amount_left_in_window(&self) -> u64 {
         self.max_in_flight.saturating_sub(self.amount_in_flight)
     }
 
     /// Increments the amount in flight by the provided amount
-    pub fn prepare(&mut self, amount: u64) {
+    fn prepare(&mut self, amount: u64) {
         if amount > 0 {
             self.amount_in_flight += amount;
             debug!(
@@ -83,7 +158,7 @@ impl CongestionController {
 
     /// Decrements the amount in flight by the provided amount
     /// Increases the allowed max in flight amount cap
-    pub fn fulfill(&mut self, prepare_amount: u64) {
+    fn fulfill(&mut self, prepare_amount: u64) {
         self.amount_in_flight -= prepare_amount;
 
         // Before we know how much we should be sending at a time,
@@ -92,10 +167,10 @@ impl CongestionController {
         // Multiplicative Decrease (AIMD) congestion avosequenceance
         if self.state == CongestionState::SlowStart {
             // Double the max in flight but don't exceed the u64 max value
-            if u64::max_value() / 2 >= self.max_in_flight {
+            if u64::MAX / 2 >= self.max_in_flight {
                 self.max_in_flight *= 2;
             } else {
-                self.max_in_flight = u64::max_value();
+                self.max_in_flight = u64::MAX;
             }
             debug!(
                 "Fulfilled packet of {}, doubling max in flight to: {}",
@@ -103,10 +178,10 @@ impl CongestionController {
             );
         } else {
             // Add to the max in flight but don't exeed the u64 max value
-            if u64::max_value() - self.increase_amount >= self.max_in_flight {
+            if u64::MAX - self.increase_amount >= self.max_in_flight {
                 self.max_in_flight += self.increase_amount;
             } else {
-                self.max_in_flight = u64::max_value();
+                self.max_in_flight = u64::MAX;
             }
             debug!(
                 "Fulfilled packet of {}, increasing max in flight to: {}",
@@ -120,7 +195,7 @@ impl CongestionController {
 
     /// Decrements the amount in flight by the provided amount
     /// Decreases the allowed max in flight amount cap
-    pub fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
+    fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
         self.amount_in_flight -= prepare_amount;
 
         match reject.code() {
@@ -135,45 +210,176 @@ impl CongestionController {
                 #[cfg(feature = "metrics_csv")]
                 self.log_stats(0);
             }
-            ErrorCode::F08_AMOUNT_TOO_LARGE => {
-                if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
-                    let new_max_packet_amount: u64 =
-                        prepare_amount * details.max_amount() / details.amount_received();
-                    if let Some(max_packet_amount) = self.max_packet_amount {
-                        self.max_packet_amount =
-                            Some(min(max_packet_amount, new_max_packet_amount));
-                    } else {
-                        self.max_packet_amount = Some(new_max_packet_amount);
-                    }
-                } else {
-                    warn!("Got F08: Amount Too Large Error without max packet amount details attached");
-                    if let Some(max_packet_amount) = self.max_packet_amount {
-                        self.max_packet_amount =
-                            Some((max_packet_amount as f64 / self.decrease_factor) as u64);
-                    }
-                }
-            }
+            ErrorCode::F08_AMOUNT_TOO_LARGE => apply_amount_too_large(
+                &mut self.max_packet_amount,
+                prepare_amount,
+                reject,
+                self.decrease_factor,
+            ),
             _ => {
                 // No special treatment for other errors
             }
         }
     }
+}
+
+/// A congestion controller which gets money flowing as soon as possible. The whole amount
+/// may be in flight from the start, and the maximum amount in flight doubles after every
+/// fulfill, even after it was divided because of insufficient liquidity.
+pub struct FastRampController {
+    /// Divide `max_in_flight` by this factor per reject with code for insufficient liquidity
+    decrease_factor: f64,
+    /// The maximum amount we are allowed to add in a packet, set by `F08_AMOUNT_TOO_LARGE` errors
+    max_packet_amount: Option<u64>,
+    /// The current amount in flight
+    amount_in_flight: u64,
+    /// The maximum allowed amount to be in flight
+    max_in_flight: u64,
+}
+
+impl FastRampController {
+    /// Constructs a new congestion controller
+    pub fn new(start_amount: u64, decrease_factor: f64) -> Self {
+        FastRampController {
+            decrease_factor,
+            max_packet_amount: None,
+            amount_in_flight: 0,
+            max_in_flight: start_amount,
+        }
+    }
+}
+
+impl CongestionControl for FastRampController {
+    fn get_max_packet_amount(&self) -> u64 {
+        self.max_packet_amount.unwrap_or(u64::MAX)
+    }
 
-    #[cfg(test)]
     fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
         self.max_packet_amount = Some(max_packet_amount)
     }
 
-    #[cfg(feature = "metrics_csv")]
-    fn log_stats(&mut self, amount_sent: u64) {
-        self.csv_writer
-            .write_record(&[
-                format!("{}", Utc::now().timestamp_millis()),
-                format!("{}", self.max_in_flight),
-                format!("{}", amount_sent),
-            ])
-            .unwrap();
-        self.csv_writer.flush().unwrap();
+    fn get_amount_left_in_window(&self) -> u64 {
+        self.max_in_flight.saturating_sub(self.amount_in_flight)
+    }
+
+    fn prepare(&mut self, amount: u64) {
+        self.amount_in_flight = self.amount_in_flight.saturating_add(amount);
+    }
+
+    fn fulfill(&mut self, prepare_amount: u64) {
+        self.amount_in_flight = self.amount_in_flight.saturating_sub(prepare_amount);
+        self.max_in_flight = self.max_in_flight.saturating_mul(2);
+        debug!(
+            "Fulfilled packet of {}, doubling max in flight to: {}",
+            prepare_amount, self.max_in_flight
+        );
+    }
+
+    fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
+        self.amount_in_flight = self.amount_in_flight.saturating_sub(prepare_amount);
+        match reject.code() {
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY => {
+                self.max_in_flight = max(
+                    (self.max_in_flight as f64 / self.decrease_factor).floor() as u64,
+                    1,
+                );
+                debug!(
+                    "Rejected packet with T04 error, decreasing max in flight to: {}",
+                    self.max_in_flight
+                );
+            }
+            ErrorCode::F08_AMOUNT_TOO_LARGE => apply_amount_too_large(
+                &mut self.max_packet_amount,
+                prepare_amount,
+                reject,
+                self.decrease_factor,
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// A congestion controller which limits the number of packets in flight rather than the
+/// amount, so each packet carries as much as the max packet amount allows. It starts with a
+/// single packet in flight, allows one more per fulfill up to the maximum, and divides the
+/// number of packets in flight by the decrease factor per reject for insufficient liquidity.
+pub struct PacketWindowController {
+    /// The most packets ever allowed in flight
+    max_packets_in_flight: usize,
+    /// Divide `packet_window` by this factor per reject with code for insufficient liquidity
+    decrease_factor: f64,
+    /// The maximum amount we are allowed to add in a packet, set by `F08_AMOUNT_TOO_LARGE` errors
+    max_packet_amount: Option<u64>,
+    /// The number of packets currently in flight
+    packets_in_flight: usize,
+    /// The number of packets currently allowed in flight
+    packet_window: usize,
+}
+
+impl PacketWindowController {
+    /// Constructs a new congestion controller
+    pub fn new(max_packets_in_flight: usize, decrease_factor: f64) -> Self {
+        PacketWindowController {
+            max_packets_in_flight: max(max_packets_in_flight, 1),
+            decrease_factor,
+            max_packet_amount: None,
+            packets_in_flight: 0,
+            packet_window: 1,
+        }
+    }
+}
+
+impl CongestionControl for PacketWindowController {
+    fn get_max_packet_amount(&self) -> u64 {
+        self.max_packet_amount.unwrap_or(u64::MAX)
+    }
+
+    fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
+        self.max_packet_amount = Some(max_packet_amount)
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
+        if self.packets_in_flight < self.packet_window {
+            u64::MAX
+        } else {
+            0
+        }
+    }
+
+    fn prepare(&mut self, _amount: u64) {
+        self.packets_in_flight += 1;
+    }
+
+    fn fulfill(&mut self, _prepare_amount: u64) {
+        self.packets_in_flight = self.packets_in_flight.saturating_sub(1);
+        self.packet_window = min(self.packet_window + 1, self.max_packets_in_flight);
+        debug!(
+            "Fulfilled packet, allowing {} packets in flight",
+            self.packet_window
+        );
+    }
+
+    fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
+        self.packets_in_flight = self.packets_in_flight.saturating_sub(1);
+        match reject.code() {
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY => {
+                self.packet_window = max(
+                    (self.packet_window as f64 / self.decrease_factor).floor() as usize,
+                    1,
+                );
+                debug!(
+                    "Rejected packet with T04 error, allowing {} packets in flight",
+                    self.packet_window
+                );
+            }
+            ErrorCode::F08_AMOUNT_TOO_LARGE => apply_amount_too_large(
+                &mut self.max_packet_amount,
+                prepare_amount,
+                reject,
+                self.decrease_factor,
+            ),
+            _ => {}
+        }
     }
 }
 
@@ -212,7 +418,7 @@ mod tests {
                 decrease_factor: 2.0,
                 max_packet_amount: None,
                 amount_in_flight: 0,
-                max_in_flight: u64::max_value() - 1,
+                max_in_flight: u64::MAX - 1,
                 #[cfg(feature = "metrics_csv")]
                 csv_writer: csv::Writer::from_writer(io::stdout()),
             };
@@ -220,7 +426,7 @@ mod tests {
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
             controller.fulfill(amount);
-            assert_eq!(controller.get_amount_left_in_window(), u64::max_value());
+            assert_eq!(controller.get_amount_left_in_window(), u64::MAX);
         }
     }
 
@@ -361,7 +567,7 @@ mod tests {
                 decrease_factor: 2.0,
                 max_packet_amount: None,
                 amount_in_flight: 0,
-                max_in_flight: u64::max_value() - 1,
+                max_in_flight: u64::MAX - 1,
                 #[cfg(feature = "metrics_csv")]
                 csv_writer: csv::Writer::from_writer(io::stdout()),
             };
@@ -369,7 +575,7 @@ mod tests {
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
             controller.fulfill(amount);
-            assert_eq!(controller.get_amount_left_in_window(), u64::max_value());
+            assert_eq!(controller.get_amount_left_in_window(), u64::MAX);
         }
     }
 
@@ -397,4 +603,77 @@ mod tests {
             assert_eq!(max_amount, 1000 - 600 - 100);
         }
     }
+
+    mod fast_ramp {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+
+        #[test]
+        fn doubles_after_insufficient_liquidity() {
+            let mut controller = FastRampController::new(1000, 2.0);
+            assert_eq!(controller.get_amount_left_in_window(), 1000);
+
+            controller.prepare(1000);
+            controller.reject(
+                1000,
+                &RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            assert_eq!(controller.get_amount_left_in_window(), 500);
+
+            // Unlike AIMD, it keeps doubling rather than adding
+            for expected in &[1000, 2000, 4000] {
+                let amount = controller.get_amount_left_in_window();
+                controller.prepare(amount);
+                controller.fulfill(amount);
+                assert_eq!(controller.get_amount_left_in_window(), *expected);
+            }
+        }
+    }
+
+    mod packet_window {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+
+        #[test]
+        fn limits_packets_in_flight() {
+            let mut controller = PacketWindowController::new(3, 2.0);
+            assert_eq!(controller.get_amount_left_in_window(), u64::MAX);
+            controller.prepare(100);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+
+            // One more packet allowed per fulfill, up to the maximum
+            controller.fulfill(100);
+            controller.prepare(100);
+            controller.prepare(100);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+            controller.fulfill(100);
+            controller.fulfill(100);
+            controller.prepare(100);
+            controller.prepare(100);
+            controller.prepare(100);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+            controller.fulfill(100);
+            controller.fulfill(100);
+            assert_eq!(controller.packet_window, 3);
+
+            controller.reject(
+                100,
+                &RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            assert_eq!(controller.packet_window, 1);
+            assert_eq!(controller.get_amount_left_in_window(), u64::MAX);
+        }
+    }
 }
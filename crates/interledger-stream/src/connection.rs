@@ -0,0 +1,1071 @@
+use super::client::{convert, get_rate, SendMoneyOptions, StreamDelivery};
+use super::congestion::CongestionControl;
+use super::crypto::*;
+use super::error::Error;
+use super::packet::*;
+use bytes::{Bytes, BytesMut};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use interledger_packet::{
+    Address, ErrorClass, ErrorCode as IlpErrorCode, PacketType as IlpPacketType, PrepareBuilder,
+};
+use interledger_rates::ExchangeRateStore;
+use interledger_service::*;
+use parking_lot::Mutex;
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap};
+use std::str;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Number of bytes each endpoint is willing to buffer per stream before the application reads them
+const DEFAULT_STREAM_WINDOW: u64 = 16_384;
+
+/// Maximum number of bytes of stream data to put in a single packet.
+/// This leaves enough room within the 32767 byte limit on ILP packet data
+/// for the other frames and the encryption overhead.
+const MAX_DATA_PER_PACKET: usize = 16_384;
+
+/// Money and data sent and received on a single stream
+#[derive(Default)]
+struct StreamState {
+    /// Total amount the application asked to send on this stream, in source units
+    send_max: u64,
+    /// Amount fulfilled on this stream, in source units
+    total_sent: u64,
+    /// Amount received on this stream, in our units
+    total_received: u64,
+    /// Data the application has written that the remote endpoint has not acknowledged yet
+    outgoing: BytesMut,
+    /// Offset in the stream of the first byte in `outgoing`
+    outgoing_offset: u64,
+    /// Offset up to which the remote endpoint is willing to receive data
+    remote_max_offset: u64,
+    /// The `max_offset` of the last StreamDataBlocked frame we sent
+    blocked_offset_sent: u64,
+    /// Data received from the remote endpoint, keyed by offset, which the application has not read yet
+    incoming: BTreeMap<u64, Bytes>,
+    /// Offset of the next byte the application will read
+    read_offset: u64,
+    /// The `max_offset` of the last StreamMaxData frame we sent
+    max_offset_sent: u64,
+    /// Offset up to which the remote endpoint told us it wants to send data
+    remote_blocked_offset: u64,
+    /// The application closed the stream, so we will tell the remote endpoint once everything is sent
+    closing: bool,
+    /// We sent a StreamClose frame for this stream
+    close_sent: bool,
+    /// The remote endpoint sent us a StreamClose frame for this stream
+    remote_closed: bool,
+}
+
+impl StreamState {
+    fn new() -> Self {
+        StreamState {
+            remote_max_offset: DEFAULT_STREAM_WINDOW,
+            max_offset_sent: DEFAULT_STREAM_WINDOW,
+            ..Default::default()
+        }
+    }
+
+    /// Amount the application asked to send which hasn't been fulfilled yet
+    fn amount_to_send(&self) -> u64 {
+        self.send_max.saturating_sub(self.total_sent)
+    }
+
+    /// Number of bytes of `outgoing` the remote endpoint is currently willing to receive
+    fn sendable_data_len(&self) -> usize {
+        let window = self.remote_max_offset.saturating_sub(self.outgoing_offset);
+        min(self.outgoing.len() as u64, window) as usize
+    }
+
+    /// Offset of the end of the data we received without any gaps
+    fn contiguous_offset(&self) -> u64 {
+        let mut end = self.read_offset;
+        for (offset, data) in self.incoming.iter() {
+            if *offset > end {
+                break;
+            }
+            end = max(end, offset + data.len() as u64);
+        }
+        end
+    }
+
+    /// The offset we want to allow the remote endpoint to send data up to
+    fn max_offset(&self) -> u64 {
+        self.read_offset + DEFAULT_STREAM_WINDOW
+    }
+
+    fn read(&mut self) -> Bytes {
+        let mut data = BytesMut::new();
+        while let Some(offset) = self.incoming.keys().next().cloned() {
+            if offset > self.read_offset {
+                break;
+            }
+            let chunk = self.incoming.remove(&offset).unwrap();
+            // Skip any bytes that overlap with data we already read
+            let skip = (self.read_offset - offset) as usize;
+            if skip < chunk.len() {
+                data.extend_from_slice(&chunk[skip..]);
+                self.read_offset = offset + chunk.len() as u64;
+            }
+        }
+        data.freeze()
+    }
+}
+
+/// Frames we are going to send to the remote endpoint. These own their data so they
+/// can be built while the connection is locked and serialized afterwards.
+#[derive(Default)]
+pub(crate) struct OutgoingFrames {
+    /// Stream ID and number of shares of each StreamMoney frame
+    money: Vec<(u64, u64)>,
+    /// Stream ID, offset and data of each StreamData frame
+    data: Vec<(u64, u64, Bytes)>,
+    /// Stream ID and max offset of each StreamMaxData frame
+    max_data: Vec<(u64, u64)>,
+    /// Stream ID and max offset of each StreamDataBlocked frame
+    data_blocked: Vec<(u64, u64)>,
+    /// IDs of the streams we are closing
+    closes: Vec<u64>,
+}
+
+impl OutgoingFrames {
+    pub(crate) fn frames(&self) -> Vec<Frame<'_>> {
+        let money = self.money.iter().map(|(stream_id, shares)| {
+            Frame::StreamMoney(StreamMoneyFrame {
+                stream_id: *stream_id,
+                shares: *shares,
+            })
+        });
+        let data = self.data.iter().map(|(stream_id, offset, data)| {
+            Frame::StreamData(StreamDataFrame {
+                stream_id: *stream_id,
+                offset: *offset,
+                data: &data[..],
+            })
+        });
+        let max_data = self.max_data.iter().map(|(stream_id, max_offset)| {
+            Frame::StreamMaxData(StreamMaxDataFrame {
+                stream_id: *stream_id,
+                max_offset: *max_offset,
+            })
+        });
+        let data_blocked = self.data_blocked.iter().map(|(stream_id, max_offset)| {
+            Frame::StreamDataBlocked(StreamDataBlockedFrame {
+                stream_id: *stream_id,
+                max_offset: *max_offset,
+            })
+        });
+        let closes = self.closes.iter().map(|stream_id| {
+            Frame::StreamClose(StreamCloseFrame {
+                stream_id: *stream_id,
+                code: ErrorCode::NoError,
+                message: "",
+            })
+        });
+        money
+            .chain(data)
+            .chain(max_data)
+            .chain(data_blocked)
+            .chain(closes)
+            .collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.money.is_empty()
+            && self.data.is_empty()
+            && self.max_data.is_empty()
+            && self.data_blocked.is_empty()
+            && self.closes.is_empty()
+    }
+}
+
+/// State of a STREAM connection shared by the sending and receiving roles
+pub(crate) struct ConnectionState {
+    streams: BTreeMap<u64, StreamState>,
+    /// The ID of the next stream we open. Clients use odd and servers use even IDs
+    next_stream_id: u64,
+    /// Streams opened by the remote endpoint which the application hasn't accepted yet
+    new_remote_streams: Vec<u64>,
+    /// The remote endpoint's ILP address, if it told us
+    remote_address: Option<Address>,
+    /// Either endpoint closed the connection
+    closed: bool,
+}
+
+impl ConnectionState {
+    pub(crate) fn new(is_client: bool) -> Self {
+        ConnectionState {
+            streams: BTreeMap::new(),
+            next_stream_id: if is_client { 1 } else { 2 },
+            new_remote_streams: Vec::new(),
+            remote_address: None,
+            closed: false,
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn open_stream(&mut self) -> u64 {
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 2;
+        self.streams.insert(stream_id, StreamState::new());
+        stream_id
+    }
+
+    /// Returns the stream with the given ID, creating it if the remote endpoint opened it.
+    /// Returns None for streams we opened that no longer exist.
+    fn remote_stream(&mut self, stream_id: u64) -> Option<&mut StreamState> {
+        if !self.streams.contains_key(&stream_id) {
+            if stream_id == 0 || stream_id % 2 == self.next_stream_id % 2 {
+                return None;
+            }
+            debug!("Remote endpoint opened stream {}", stream_id);
+            self.streams.insert(stream_id, StreamState::new());
+            self.new_remote_streams.push(stream_id);
+        }
+        self.streams.get_mut(&stream_id)
+    }
+
+    fn stream(&mut self, stream_id: u64) -> &mut StreamState {
+        self.streams
+            .get_mut(&stream_id)
+            .expect("Streams are never removed from a connection")
+    }
+
+    /// Amount the application asked to send which hasn't been fulfilled yet, in source units
+    fn amount_to_send(&self) -> u64 {
+        self.streams
+            .values()
+            .map(StreamState::amount_to_send)
+            .fold(0, u64::saturating_add)
+    }
+
+    fn total_send_max(&self) -> u64 {
+        self.streams
+            .values()
+            .map(|stream| stream.send_max)
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Does the remote endpoint want to send us data that we have room for?
+    fn is_remote_blocked(&self) -> bool {
+        self.streams.values().any(|stream| {
+            let received = stream.contiguous_offset();
+            stream.remote_blocked_offset > received && stream.max_offset() > received
+        })
+    }
+
+    /// Builds the frames for the next packet or reply, putting the given amount of money on
+    /// the streams which still have money to send
+    pub(crate) fn outgoing_frames(&mut self, amount: u64) -> OutgoingFrames {
+        let mut frames = OutgoingFrames::default();
+        let mut data_budget = MAX_DATA_PER_PACKET;
+        for (stream_id, stream) in self.streams.iter_mut() {
+            let stream_id = *stream_id;
+            if amount > 0 && stream.amount_to_send() > 0 {
+                frames.money.push((stream_id, stream.amount_to_send()));
+            }
+
+            let data_len = min(stream.sendable_data_len(), data_budget);
+            if data_len > 0 {
+                frames.data.push((
+                    stream_id,
+                    stream.outgoing_offset,
+                    Bytes::from(&stream.outgoing[..data_len]),
+                ));
+                data_budget -= data_len;
+            }
+            let outgoing_end = stream.outgoing_offset + stream.outgoing.len() as u64;
+            if outgoing_end > stream.remote_max_offset && outgoing_end > stream.blocked_offset_sent
+            {
+                frames.data_blocked.push((stream_id, outgoing_end));
+            }
+
+            if !stream.remote_closed && stream.max_offset() > stream.max_offset_sent {
+                frames.max_data.push((stream_id, stream.max_offset()));
+            }
+
+            if stream.closing
+                && !stream.close_sent
+                && stream.amount_to_send() == 0
+                && stream.outgoing.len() == data_len
+            {
+                frames.closes.push(stream_id);
+            }
+        }
+        frames
+    }
+
+    /// Records that the remote endpoint received the given frames
+    pub(crate) fn apply_sent(&mut self, frames: &OutgoingFrames) {
+        for (stream_id, offset, data) in frames.data.iter() {
+            let stream = self.stream(*stream_id);
+            if *offset == stream.outgoing_offset {
+                stream.outgoing.advance(data.len());
+                stream.outgoing_offset += data.len() as u64;
+            }
+        }
+        for (stream_id, max_offset) in frames.max_data.iter() {
+            let stream = self.stream(*stream_id);
+            stream.max_offset_sent = max(stream.max_offset_sent, *max_offset);
+        }
+        for (stream_id, max_offset) in frames.data_blocked.iter() {
+            let stream = self.stream(*stream_id);
+            stream.blocked_offset_sent = max(stream.blocked_offset_sent, *max_offset);
+        }
+        for stream_id in frames.closes.iter() {
+            self.stream(*stream_id).close_sent = true;
+        }
+    }
+
+    /// Splits the fulfilled source amount between the streams the frames put money on
+    fn apply_money_sent(&mut self, frames: &OutgoingFrames, amount: u64) {
+        for (stream_id, stream_amount) in split_by_shares(amount, &frames.money) {
+            let stream = self.stream(stream_id);
+            stream.total_sent = stream.total_sent.saturating_add(stream_amount);
+        }
+    }
+
+    /// Applies the frames the remote endpoint sent us. If the packet carrying the frames
+    /// is going to be fulfilled, the received amount is split between the streams according
+    /// to the StreamMoney frames.
+    pub(crate) fn handle_incoming_frames(&mut self, frames: FrameIterator, amount: Option<u64>) {
+        let mut money = Vec::new();
+        for frame in frames {
+            match frame {
+                Frame::StreamMoney(frame) if self.remote_stream(frame.stream_id).is_some() => {
+                    money.push((frame.stream_id, frame.shares));
+                }
+                Frame::StreamData(frame) => {
+                    if let Some(stream) = self.remote_stream(frame.stream_id) {
+                        // Drop anything past the window we gave the remote endpoint
+                        let len = min(
+                            frame.data.len() as u64,
+                            stream.max_offset().saturating_sub(frame.offset),
+                        ) as usize;
+                        if len > 0 && frame.offset + len as u64 > stream.read_offset {
+                            stream
+                                .incoming
+                                .insert(frame.offset, Bytes::from(&frame.data[..len]));
+                        }
+                    }
+                }
+                Frame::StreamMaxData(frame) => {
+                    if let Some(stream) = self.remote_stream(frame.stream_id) {
+                        stream.remote_max_offset = max(stream.remote_max_offset, frame.max_offset);
+                    }
+                }
+                Frame::StreamDataBlocked(frame) => {
+                    if let Some(stream) = self.remote_stream(frame.stream_id) {
+                        stream.remote_blocked_offset =
+                            max(stream.remote_blocked_offset, frame.max_offset);
+                    }
+                }
+                Frame::StreamClose(frame) => {
+                    if let Some(stream) = self.remote_stream(frame.stream_id) {
+                        stream.remote_closed = true;
+                    }
+                }
+                Frame::ConnectionNewAddress(frame) => {
+                    self.remote_address = Some(frame.source_account);
+                }
+                Frame::ConnectionClose(frame) => {
+                    debug!(
+                        "Remote endpoint closed the connection with code: {:?}",
+                        frame.code
+                    );
+                    self.closed = true;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(amount) = amount {
+            for (stream_id, stream_amount) in split_by_shares(amount, &money) {
+                let stream = self.stream(stream_id);
+                stream.total_received = stream.total_received.saturating_add(stream_amount);
+            }
+        }
+    }
+}
+
+/// Splits the amount proportionally to the shares of each stream, rounding down.
+/// The remainder goes to the first stream.
+fn split_by_shares(amount: u64, shares: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let total_shares: u128 = shares.iter().map(|(_, shares)| *shares as u128).sum();
+    if total_shares == 0 {
+        return Vec::new();
+    }
+    let mut amounts: Vec<(u64, u64)> = shares
+        .iter()
+        .map(|(stream_id, shares)| {
+            let stream_amount = amount as u128 * *shares as u128 / total_shares;
+            (*stream_id, stream_amount as u64)
+        })
+        .collect();
+    let remainder = amount - amounts.iter().map(|(_, amount)| amount).sum::<u64>();
+    amounts[0].1 += remainder;
+    amounts
+}
+
+/// A stream of money and data within a STREAM connection.
+///
+/// Money and data written to the stream are queued and sent the next time the
+/// connection is flushed (or, on the receiving side, with the replies to the
+/// sender's next packets).
+#[derive(Clone)]
+pub struct DataAndMoneyStream {
+    id: u64,
+    connection: Arc<Mutex<ConnectionState>>,
+}
+
+impl DataAndMoneyStream {
+    /// The stream's ID. Streams opened by the sender have odd and streams opened
+    /// by the receiver have even IDs.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Queues the given amount of money, in source units, to be sent on this stream
+    pub fn send_money(&self, amount: u64) {
+        let mut connection = self.connection.lock();
+        let stream = connection.stream(self.id);
+        stream.send_max = stream.send_max.saturating_add(amount);
+    }
+
+    /// Queues the given bytes to be sent on this stream
+    pub fn write(&self, data: &[u8]) {
+        let mut connection = self.connection.lock();
+        connection.stream(self.id).outgoing.extend_from_slice(data);
+    }
+
+    /// Returns the bytes received on this stream since the last call, in order.
+    /// Reading frees up space in the window the remote endpoint is allowed to send in.
+    pub fn read(&self) -> Bytes {
+        self.connection.lock().stream(self.id).read()
+    }
+
+    /// Total amount sent and fulfilled on this stream, in source units
+    pub fn total_sent(&self) -> u64 {
+        self.connection.lock().stream(self.id).total_sent
+    }
+
+    /// Total amount received on this stream
+    pub fn total_received(&self) -> u64 {
+        self.connection.lock().stream(self.id).total_received
+    }
+
+    /// Closes the stream once all of the money and data queued on it is sent
+    pub fn close(&self) {
+        self.connection.lock().stream(self.id).closing = true;
+    }
+
+    /// Did the remote endpoint close this stream?
+    pub fn is_remote_closed(&self) -> bool {
+        self.connection.lock().stream(self.id).remote_closed
+    }
+}
+
+fn accept_streams(connection: &Arc<Mutex<ConnectionState>>) -> Vec<DataAndMoneyStream> {
+    let mut state = connection.lock();
+    state
+        .new_remote_streams
+        .drain(..)
+        .map(|id| DataAndMoneyStream {
+            id,
+            connection: connection.clone(),
+        })
+        .collect()
+}
+
+/// The sending side of a STREAM connection, which can carry money and data on several streams.
+///
+/// Unlike [`send_money`](./fn.send_money.html), which sends a single amount on a single
+/// stream, a connection queues the money and data written to each of its streams and
+/// sends them when it is flushed. Data sent by the receiver is delivered in the replies
+/// to our packets.
+pub struct StreamConnection<I, A, S> {
+    /// Next service to send Interledger packets to the network
+    next: I,
+    /// The account sending the packets
+    from_account: A,
+    /// Store for fetching and enforcing minimum exchange rates
+    store: S,
+    /// Symmetric secret generated by receiver to encrypt and authenticate this connections' packets
+    shared_secret: Bytes,
+    /// Maximum acceptable slippage percentage below calculated minimum exchange rate
+    slippage: f64,
+    state: Arc<Mutex<ConnectionState>>,
+    /// Running totals of the money sent & delivered over the connection
+    receipt: StreamDelivery,
+    /// Congestion control strategy, timeouts and fail-fast thresholds
+    options: SendMoneyOptions,
+    congestion_controller: Option<Box<dyn CongestionControl + Send>>,
+    /// Do we need to send our source account information to the recipient?
+    should_send_source_account: bool,
+    sequence: u64,
+    fulfilled_packets: u64,
+    rejected_packets: u64,
+}
+
+impl<I, A, S> StreamConnection<I, A, S>
+where
+    I: IncomingService<A>,
+    A: Account,
+    S: ExchangeRateStore,
+{
+    /// Creates a connection to the receiver with the given destination account and shared secret.
+    /// No packets are sent until the connection is flushed.
+    pub fn new(
+        next: I,
+        from_account: &A,
+        store: S,
+        destination_account: Address,
+        shared_secret: Vec<u8>,
+        slippage: f64,
+    ) -> Self {
+        StreamConnection {
+            next,
+            from_account: from_account.clone(),
+            store,
+            shared_secret: Bytes::from(shared_secret),
+            slippage,
+            state: Arc::new(Mutex::new(ConnectionState::new(true))),
+            receipt: StreamDelivery::new(from_account, destination_account, 0),
+            options: SendMoneyOptions::default(),
+            congestion_controller: None,
+            should_send_source_account: true,
+            sequence: 1,
+            fulfilled_packets: 0,
+            rejected_packets: 0,
+        }
+    }
+
+    /// Sets the congestion control strategy, timeouts and fail-fast thresholds of the connection.
+    /// Packets in flight are limited by the connection sending one packet at a time
+    pub fn options(&mut self, options: SendMoneyOptions) -> &mut Self {
+        self.options = options;
+        self.congestion_controller = None;
+        self
+    }
+
+    /// Opens a new stream on this connection
+    pub fn open_stream(&self) -> DataAndMoneyStream {
+        let id = self.state.lock().open_stream();
+        DataAndMoneyStream {
+            id,
+            connection: self.state.clone(),
+        }
+    }
+
+    /// Returns the streams the receiver opened since the last call
+    pub fn accept_streams(&self) -> Vec<DataAndMoneyStream> {
+        accept_streams(&self.state)
+    }
+
+    /// Running totals of the money sent & delivered over the connection
+    pub fn receipt(&self) -> &StreamDelivery {
+        &self.receipt
+    }
+
+    /// Sends packets until all of the money queued on the streams is delivered and all of
+    /// the queued data the receiver is currently willing to accept is sent
+    pub async fn flush(&mut self) -> Result<StreamDelivery, Error> {
+        self.send_until_idle(false).await
+    }
+
+    /// Like `flush`, but always sends at least one packet so the receiver can reply with
+    /// any data it has queued for us
+    pub async fn sync(&mut self) -> Result<StreamDelivery, Error> {
+        self.send_until_idle(true).await
+    }
+
+    /// Flushes the connection and tells the receiver it is closed.
+    /// There's no ACK from the recipient, so we can't confirm it closed.
+    pub async fn close(mut self) -> Result<StreamDelivery, Error> {
+        let receipt = self.flush().await?;
+        let sequence = self.next_sequence();
+        let stream_packet = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence,
+            frames: &[Frame::ConnectionClose(ConnectionCloseFrame {
+                code: ErrorCode::NoError,
+                message: "",
+            })],
+        }
+        .build();
+        let data = stream_packet.into_encrypted(&self.shared_secret);
+        let prepare = PrepareBuilder {
+            destination: self.receipt.to.clone(),
+            amount: 0,
+            execution_condition: &random_condition(),
+            expires_at: SystemTime::now() + self.options.packet_timeout,
+            data: &data[..],
+        }
+        .build();
+
+        debug!("Closing connection");
+        self.next
+            .handle_request(IncomingRequest {
+                from: self.from_account.clone(),
+                prepare,
+            })
+            .await
+            .ok();
+        Ok(receipt)
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        sequence
+    }
+
+    async fn send_until_idle(&mut self, mut force_packet: bool) -> Result<StreamDelivery, Error> {
+        let mut last_progress = Instant::now();
+        loop {
+            let (amount_to_send, frames) = {
+                let mut state = self.state.lock();
+                if state.closed {
+                    return Err(Error::ConnectionError(
+                        "Connection was closed by the receiver".to_string(),
+                    ));
+                }
+                self.receipt.source_amount = state.total_send_max();
+
+                let amount_to_send = state.amount_to_send();
+                // Only put money in packets once we know the receiver's asset details,
+                // otherwise we can't enforce the exchange rate
+                let amount = if self.receipt.destination_asset_code.is_some() {
+                    amount_to_send
+                } else if amount_to_send > 0 && !self.should_send_source_account {
+                    return Err(Error::SendMoneyError(
+                        "Receiver did not send its asset details".to_string(),
+                    ));
+                } else {
+                    0
+                };
+                let frames = state.outgoing_frames(amount);
+                if !force_packet
+                    && amount_to_send == 0
+                    && frames.is_empty()
+                    && !state.is_remote_blocked()
+                {
+                    return Ok(self.receipt.clone());
+                }
+                (amount, frames)
+            };
+            force_packet = false;
+
+            if last_progress.elapsed() >= self.options.max_time_since_last_fulfill {
+                return Err(Error::TimeoutError(
+                    "Time since the receiver last replied exceeded the maximum time limit"
+                        .to_string(),
+                ));
+            }
+            if self.is_failing() {
+                return Err(Error::SendMoneyError(format!(
+                    "Terminating connection since too many packets are rejected ({} packets fulfilled, {} packets rejected)",
+                    self.fulfilled_packets, self.rejected_packets,
+                )));
+            }
+
+            let source_amount = if amount_to_send > 0 {
+                let options = &self.options;
+                let congestion_controller = self
+                    .congestion_controller
+                    .get_or_insert_with(|| options.congestion_controller(amount_to_send));
+                min(
+                    amount_to_send,
+                    min(
+                        congestion_controller.get_amount_left_in_window(),
+                        congestion_controller.get_max_packet_amount(),
+                    ),
+                )
+            } else {
+                0
+            };
+            if self.send_packet(source_amount, frames).await? {
+                last_progress = Instant::now();
+            }
+        }
+    }
+
+    /// Sends a packet with the given source amount and frames and applies the reply.
+    /// Returns whether the receiver replied to the packet.
+    async fn send_packet(
+        &mut self,
+        source_amount: u64,
+        frames: OutgoingFrames,
+    ) -> Result<bool, Error> {
+        let rate = get_rate(
+            &self.store,
+            self.receipt.source_asset_scale,
+            &self.receipt.source_asset_code,
+            self.receipt.destination_asset_scale,
+            self.receipt.destination_asset_code.as_deref(),
+            self.slippage,
+        );
+        let min_destination_amount = rate
+            .and_then(|rate| convert(source_amount, rate))
+            .unwrap_or(0);
+
+        let sequence = self.next_sequence();
+        let mut packet_frames = frames.frames();
+        if self.should_send_source_account {
+            packet_frames.push(Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
+                source_account: self.receipt.from.clone(),
+            }));
+        }
+        let stream_request_packet = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: min_destination_amount,
+            sequence,
+            frames: &packet_frames,
+        }
+        .build();
+        debug!(
+            "Sending packet {} with amount: {} and encrypted STREAM packet: {:?}",
+            sequence, source_amount, stream_request_packet
+        );
+        let prepare_data = stream_request_packet.into_encrypted(&self.shared_secret);
+
+        // Packets without money, or for which we couldn't calculate a minimum destination
+        // amount, MUST be unfulfillable so no money is at risk
+        let execution_condition = if source_amount > 0 && min_destination_amount > 0 {
+            generate_condition(&self.shared_secret, &prepare_data)
+        } else {
+            random_condition()
+        };
+        let prepare = PrepareBuilder {
+            destination: self.receipt.to.clone(),
+            amount: source_amount,
+            execution_condition: &execution_condition,
+            expires_at: SystemTime::now() + self.options.packet_timeout,
+            data: &prepare_data[..],
+        }
+        .build();
+
+        if source_amount > 0 {
+            if let Some(congestion_controller) = self.congestion_controller.as_mut() {
+                congestion_controller.prepare(source_amount);
+            }
+        }
+        let reply = self
+            .next
+            .handle_request(IncomingRequest {
+                from: self.from_account.clone(),
+                prepare,
+            })
+            .await;
+
+        let (packet_type, reply_data) = match &reply {
+            Ok(fulfill) => (IlpPacketType::Fulfill, fulfill.data()),
+            Err(reject) => (IlpPacketType::Reject, reject.data()),
+        };
+        let stream_reply_packet =
+            StreamPacket::from_encrypted(&self.shared_secret, BytesMut::from(reply_data));
+
+        // Parse the stream packet and determine the amount the recipient claims they received
+        let mut claimed_amount = 0;
+        let mut replied = false;
+        match stream_reply_packet {
+            Ok(stream_reply_packet) => {
+                if stream_reply_packet.sequence() != sequence {
+                    warn!(
+                        "Discarding replayed STREAM packet (expected sequence {}, but received {})",
+                        sequence,
+                        stream_reply_packet.sequence()
+                    );
+                } else if stream_reply_packet.ilp_packet_type() == IlpPacketType::Reject
+                    && packet_type == IlpPacketType::Fulfill
+                {
+                    warn!("Discarding STREAM packet (received Fulfill, but recipient said they sent a Reject)");
+                } else {
+                    replied = true;
+                    // Since we decrypted the response, the recipient read the request packet
+                    self.should_send_source_account = false;
+                    if self.receipt.destination_asset_scale.is_none() {
+                        for frame in stream_reply_packet.frames() {
+                            if let Frame::ConnectionAssetDetails(frame) = frame {
+                                debug!(
+                                    "Setting remote asset details ({} with scale {})",
+                                    frame.source_asset_code, frame.source_asset_scale
+                                );
+                                self.receipt.destination_asset_code =
+                                    Some(frame.source_asset_code.to_string());
+                                self.receipt.destination_asset_scale =
+                                    Some(frame.source_asset_scale);
+                            }
+                        }
+                    }
+                    for frame in stream_reply_packet.frames() {
+                        if let Frame::StreamReceipt(frame) = frame {
+                            self.receipt.apply_receipt(frame.receipt);
+                        }
+                    }
+
+                    let mut state = self.state.lock();
+                    state.apply_sent(&frames);
+                    state.handle_incoming_frames(stream_reply_packet.frames(), None);
+                    claimed_amount = stream_reply_packet.prepare_amount();
+                }
+            }
+            Err(_) => warn!(
+                "Unable to parse STREAM packet from response data for sequence {}",
+                sequence
+            ),
+        }
+
+        match reply {
+            Ok(_) => {
+                // Even if the data was invalid, since it was fulfilled, we must assume the
+                // recipient got the packet and at least the minimum amount
+                let delivered_amount = max(min_destination_amount, claimed_amount);
+                if let Some(congestion_controller) = self.congestion_controller.as_mut() {
+                    congestion_controller.fulfill(source_amount);
+                }
+                let mut state = self.state.lock();
+                if !replied {
+                    state.apply_sent(&frames);
+                }
+                state.apply_money_sent(&frames, source_amount);
+                self.receipt.sent_amount = self.receipt.sent_amount.saturating_add(source_amount);
+                self.receipt.delivered_amount = self
+                    .receipt
+                    .delivered_amount
+                    .saturating_add(delivered_amount);
+                self.fulfilled_packets += 1;
+                debug!(
+                    "Prepare {} with amount {} was fulfilled ({} left to send)",
+                    sequence,
+                    source_amount,
+                    state.amount_to_send()
+                );
+                Ok(true)
+            }
+            Err(reject) => {
+                if source_amount > 0 {
+                    if let Some(congestion_controller) = self.congestion_controller.as_mut() {
+                        congestion_controller.reject(source_amount, &reject);
+                    }
+                    self.rejected_packets += 1;
+                }
+                debug!(
+                    "Prepare {} with amount {} was rejected with code: {}",
+                    sequence,
+                    source_amount,
+                    reject.code(),
+                );
+
+                match (reject.code().class(), reject.code()) {
+                    (ErrorClass::Temporary, _) => Ok(replied),
+                    (_, IlpErrorCode::F08_AMOUNT_TOO_LARGE) => Ok(replied),
+                    (_, IlpErrorCode::F99_APPLICATION_ERROR) => Ok(replied),
+                    (_, IlpErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT) => Ok(replied),
+                    _ => Err(Error::SendMoneyError(format!(
+                        "Packet was rejected with error: {} {}",
+                        reject.code(),
+                        str::from_utf8(reject.message()).unwrap_or_default(),
+                    ))),
+                }
+            }
+        }
+    }
+
+    fn is_failing(&self) -> bool {
+        let num_packets = self.fulfilled_packets + self.rejected_packets;
+        self.options.is_failing(num_packets, self.rejected_packets)
+    }
+}
+
+/// The receiving side of a STREAM connection, handed to the application by a
+/// [`StreamReceiverService`](./struct.StreamReceiverService.html) which accepts connections.
+///
+/// The receiver can't send packets of its own, so data written to its streams
+/// is sent in the replies to the sender's packets. Data in a reply that gets
+/// lost on the way back to the sender is not resent.
+#[derive(Clone)]
+pub struct ReceivedConnection {
+    destination_account: Address,
+    state: Arc<Mutex<ConnectionState>>,
+}
+
+impl ReceivedConnection {
+    /// The destination account the sender is sending packets for this connection to
+    pub fn destination_account(&self) -> &Address {
+        &self.destination_account
+    }
+
+    /// The sender's ILP address, if it told us
+    pub fn source_account(&self) -> Option<Address> {
+        self.state.lock().remote_address.clone()
+    }
+
+    /// Opens a new stream for sending data or money to the sender
+    pub fn open_stream(&self) -> DataAndMoneyStream {
+        let id = self.state.lock().open_stream();
+        DataAndMoneyStream {
+            id,
+            connection: self.state.clone(),
+        }
+    }
+
+    /// Returns the streams the sender opened since the last call
+    pub fn accept_streams(&self) -> Vec<DataAndMoneyStream> {
+        accept_streams(&self.state)
+    }
+
+    /// Did the sender close the connection?
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+}
+
+/// The connections a `StreamReceiverService` is tracking, keyed by their destination account
+#[derive(Clone)]
+pub(crate) struct ConnectionRegistry {
+    connections: Arc<Mutex<HashMap<Address, Arc<Mutex<ConnectionState>>>>>,
+    new_connections: UnboundedSender<ReceivedConnection>,
+}
+
+impl ConnectionRegistry {
+    pub(crate) fn new() -> (Self, UnboundedReceiver<ReceivedConnection>) {
+        let (new_connections, receiver) = unbounded();
+        let registry = ConnectionRegistry {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            new_connections,
+        };
+        (registry, receiver)
+    }
+
+    /// Returns the state of the connection with the given destination account,
+    /// handing a new connection to the application if we haven't seen it before
+    pub(crate) fn get_or_create(
+        &self,
+        destination_account: &Address,
+    ) -> Arc<Mutex<ConnectionState>> {
+        let mut connections = self.connections.lock();
+        if let Some(state) = connections.get(destination_account) {
+            return state.clone();
+        }
+        let state = Arc::new(Mutex::new(ConnectionState::new(false)));
+        connections.insert(destination_account.clone(), state.clone());
+        let connection = ReceivedConnection {
+            destination_account: destination_account.clone(),
+            state: state.clone(),
+        };
+        if self.new_connections.unbounded_send(connection).is_err() {
+            debug!("Application stopped accepting STREAM connections");
+        }
+        state
+    }
+
+    /// Stops tracking a connection once it is closed
+    pub(crate) fn remove(&self, destination_account: &Address) {
+        self.connections.lock().remove(destination_account);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet_with_frames(frames: &[Frame]) -> StreamPacket {
+        StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence: 1,
+            frames,
+        }
+        .build()
+    }
+
+    #[test]
+    fn splits_money_by_shares() {
+        assert_eq!(
+            split_by_shares(100, &[(2, 5), (4, 15), (6, 30)]),
+            vec![(2, 10), (4, 30), (6, 60)]
+        );
+        assert_eq!(split_by_shares(10, &[(1, 1), (3, 2)]), vec![(1, 4), (3, 6)]);
+        assert!(split_by_shares(10, &[]).is_empty());
+    }
+
+    #[test]
+    fn reassembles_data_received_out_of_order() {
+        let mut state = ConnectionState::new(false);
+        let packet = packet_with_frames(&[
+            Frame::StreamData(StreamDataFrame {
+                stream_id: 1,
+                offset: 5,
+                data: b" world",
+            }),
+            Frame::StreamData(StreamDataFrame {
+                stream_id: 1,
+                offset: 0,
+                data: b"hello",
+            }),
+        ]);
+        state.handle_incoming_frames(packet.frames(), None);
+        assert_eq!(state.new_remote_streams, vec![1]);
+        assert_eq!(&state.stream(1).read()[..], b"hello world");
+
+        // A resent frame doesn't deliver the data twice
+        let packet = packet_with_frames(&[Frame::StreamData(StreamDataFrame {
+            stream_id: 1,
+            offset: 5,
+            data: b" world",
+        })]);
+        state.handle_incoming_frames(packet.frames(), None);
+        assert!(state.stream(1).read().is_empty());
+    }
+
+    #[test]
+    fn ignores_streams_with_our_parity_which_we_didnt_open() {
+        let mut state = ConnectionState::new(true);
+        let packet = packet_with_frames(&[Frame::StreamMoney(StreamMoneyFrame {
+            stream_id: 3,
+            shares: 1,
+        })]);
+        state.handle_incoming_frames(packet.frames(), Some(100));
+        assert!(state.streams.is_empty());
+    }
+
+    #[test]
+    fn respects_the_remote_window() {
+        let mut state = ConnectionState::new(true);
+        let stream_id = state.open_stream();
+        state
+            .stream(stream_id)
+            .outgoing
+            .extend_from_slice(&[0; DEFAULT_STREAM_WINDOW as usize + 10]);
+
+        let frames = state.outgoing_frames(0);
+        assert_eq!(frames.data.len(), 1);
+        assert_eq!(frames.data[0].2.len(), DEFAULT_STREAM_WINDOW as usize);
+        assert_eq!(
+            frames.data_blocked,
+            vec![(stream_id, DEFAULT_STREAM_WINDOW + 10)]
+        );
+        state.apply_sent(&frames);
+
+        // Nothing else can be sent until the remote endpoint raises the limit
+        assert!(state.outgoing_frames(0).is_empty());
+        let packet = packet_with_frames(&[Frame::StreamMaxData(StreamMaxDataFrame {
+            stream_id,
+            max_offset: DEFAULT_STREAM_WINDOW * 2,
+        })]);
+        state.handle_incoming_frames(packet.frames(), None);
+        let frames = state.outgoing_frames(0);
+        assert_eq!(
+            frames.data,
+            vec![(stream_id, DEFAULT_STREAM_WINDOW, Bytes::from(&[0; 10][..]))]
+        );
+    }
+}
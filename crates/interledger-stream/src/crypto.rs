@@ -112,13 +112,13 @@ fn encrypt_with_nonce(
     });
 
     // Rearrange the bytes so that the tag goes first (should have put it last in the JS implementation, but oh well)
+    // The format is `nonce, auth tag, data`, in that order. The bytes are copied, since
+    // `BytesMut::unsplit` offsets pointers out of bounds for the buffers it keeps inline
     let auth_tag_position = plaintext.len() - AUTH_TAG_LENGTH;
-    let mut tag_data = plaintext.split_off(auth_tag_position);
-    tag_data.unsplit(plaintext);
-
-    // The format is `nonce, auth tag, data`, in that order
-    let mut nonce_tag_data = BytesMut::from(&nonce[..]);
-    nonce_tag_data.unsplit(tag_data);
+    let mut nonce_tag_data = BytesMut::with_capacity(NONCE_LENGTH + plaintext.len());
+    nonce_tag_data.extend_from_slice(&nonce[..]);
+    nonce_tag_data.extend_from_slice(&plaintext[auth_tag_position..]);
+    nonce_tag_data.extend_from_slice(&plaintext[..auth_tag_position]);
 
     nonce_tag_data
 }
@@ -150,7 +150,7 @@ pub fn decrypt(shared_secret: &[u8], mut ciphertext: BytesMut) -> Result<BytesMu
     let additional_data: &[u8] = &[];
 
     // Ring expects the tag to come after the data
-    ciphertext.unsplit(auth_tag);
+    ciphertext.extend_from_slice(&auth_tag);
 
     let length = key
         .open_in_place(
@@ -9,4 +9,6 @@ pub enum Error {
     SendMoneyError(String),
     #[error("Error maximum time exceeded: {0}")]
     TimeoutError(String),
+    #[error("Invalid receipt: {0}")]
+    InvalidReceipt(String),
 }
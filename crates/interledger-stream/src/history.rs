@@ -0,0 +1,259 @@
+use super::client::StreamDelivery;
+use super::server::unix_timestamp;
+use async_trait::async_trait;
+use interledger_errors::PaymentHistoryStoreError;
+use interledger_packet::Address;
+use interledger_service::Account;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// The maximum number of payments returned by a single query
+pub const MAX_PAYMENTS_LIMIT: usize = 1000;
+/// The number of payments returned by a query which does not set a limit
+const DEFAULT_PAYMENTS_LIMIT: usize = 100;
+
+/// Whether a payment was sent or received by the account
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentDirection {
+    Incoming,
+    Outgoing,
+}
+
+impl PaymentDirection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PaymentDirection::Incoming => "incoming",
+            PaymentDirection::Outgoing => "outgoing",
+        }
+    }
+}
+
+impl FromStr for PaymentDirection {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "incoming" => Ok(PaymentDirection::Incoming),
+            "outgoing" => Ok(PaymentDirection::Outgoing),
+            _ => Err(format!("Invalid payment direction: {}", src)),
+        }
+    }
+}
+
+/// The final state of a payment
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Completed,
+    Failed,
+}
+
+impl PaymentStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PaymentStatus::Completed => "completed",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for PaymentStatus {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "completed" => Ok(PaymentStatus::Completed),
+            "failed" => Ok(PaymentStatus::Failed),
+            _ => Err(format!("Invalid payment status: {}", src)),
+        }
+    }
+}
+
+/// A payment sent or received by an account, as kept in the
+/// [`PaymentHistoryStore`](./trait.PaymentHistoryStore.html)
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PaymentRecord {
+    /// Unique id of the payment
+    pub id: Uuid,
+    /// The account which sent or received the payment
+    pub account_id: Uuid,
+    pub direction: PaymentDirection,
+    pub status: PaymentStatus,
+    /// The STREAM address the money was sent to: the receiver's address for outgoing
+    /// payments, or one of the account's connection addresses for incoming payments
+    pub destination: Address,
+    /// The payment pointer or SPSP URL of the receiver of an outgoing payment,
+    /// or the username of the account which routed an incoming payment to this node
+    pub counterparty: String,
+    /// The amount sent or received, in the account's asset.
+    /// This is 0 for outgoing payments which failed before any money was sent
+    pub amount: u64,
+    pub asset_code: String,
+    pub asset_scale: u8,
+    /// The amount delivered to the receiver of an outgoing payment, in the receiver's asset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivered_amount: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivered_asset_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivered_asset_scale: Option<u8>,
+    /// UNIX timestamp, in seconds, of when the payment completed or failed
+    pub timestamp: u64,
+}
+
+impl PaymentRecord {
+    /// Creates the record of an outgoing payment which was sent to `receiver`
+    pub fn outgoing_completed(account_id: Uuid, receiver: &str, delivery: &StreamDelivery) -> Self {
+        PaymentRecord {
+            id: Uuid::new_v4(),
+            account_id,
+            direction: PaymentDirection::Outgoing,
+            status: PaymentStatus::Completed,
+            destination: delivery.to.clone(),
+            counterparty: receiver.to_string(),
+            amount: delivery.sent_amount,
+            asset_code: delivery.source_asset_code.clone(),
+            asset_scale: delivery.source_asset_scale,
+            delivered_amount: Some(delivery.delivered_amount),
+            delivered_asset_code: delivery.destination_asset_code.clone(),
+            delivered_asset_scale: delivery.destination_asset_scale,
+            timestamp: unix_timestamp(),
+        }
+    }
+
+    /// Creates the record of an outgoing payment to `receiver` which failed.
+    /// The receiver's address is not known if the SPSP query failed, in which case
+    /// the `destination` is the sending account's address
+    pub fn outgoing_failed<A: Account>(account: &A, receiver: &str) -> Self {
+        PaymentRecord {
+            id: Uuid::new_v4(),
+            account_id: account.id(),
+            direction: PaymentDirection::Outgoing,
+            status: PaymentStatus::Failed,
+            destination: account.ilp_address().clone(),
+            counterparty: receiver.to_string(),
+            amount: 0,
+            asset_code: account.asset_code().to_string(),
+            asset_scale: account.asset_scale(),
+            delivered_amount: None,
+            delivered_asset_code: None,
+            delivered_asset_scale: None,
+            timestamp: unix_timestamp(),
+        }
+    }
+}
+
+/// Filters and pagination applied when loading an account's payments.
+/// Payments are returned from the most recent to the oldest
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PaymentQuery {
+    /// Only return the payments in this direction
+    pub direction: Option<PaymentDirection>,
+    /// Only return the payments with this status
+    pub status: Option<PaymentStatus>,
+    /// Only return the payments made at or after this UNIX timestamp, in seconds
+    pub since: Option<u64>,
+    /// Only return the payments made at or before this UNIX timestamp, in seconds
+    pub until: Option<u64>,
+    /// Number of matching payments to skip
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of payments to return, capped at `MAX_PAYMENTS_LIMIT`. Defaults to 100
+    pub limit: Option<usize>,
+}
+
+impl PaymentQuery {
+    /// The maximum number of payments to return
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAYMENTS_LIMIT)
+            .min(MAX_PAYMENTS_LIMIT)
+    }
+
+    /// Whether the payment passes all of the query's filters
+    pub fn matches(&self, payment: &PaymentRecord) -> bool {
+        self.direction.map_or(true, |d| d == payment.direction)
+            && self.status.map_or(true, |s| s == payment.status)
+            && self.since.map_or(true, |since| payment.timestamp >= since)
+            && self.until.map_or(true, |until| payment.timestamp <= until)
+    }
+}
+
+/// Store trait which keeps the history of the payments sent and received by each account
+#[async_trait]
+pub trait PaymentHistoryStore {
+    /// Saves a payment which was sent or received
+    async fn save_payment(&self, payment: PaymentRecord) -> Result<(), PaymentHistoryStoreError>;
+
+    /// Loads the account's payments matching the query, from the most recent to the oldest
+    async fn get_payments(
+        &self,
+        account_id: Uuid,
+        query: PaymentQuery,
+    ) -> Result<Vec<PaymentRecord>, PaymentHistoryStoreError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(direction: PaymentDirection, timestamp: u64) -> PaymentRecord {
+        PaymentRecord {
+            id: Uuid::new_v4(),
+            account_id: Uuid::new_v4(),
+            direction,
+            status: PaymentStatus::Completed,
+            destination: Address::from_str("example.receiver").unwrap(),
+            counterparty: "alice".to_string(),
+            amount: 100,
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            delivered_amount: None,
+            delivered_asset_code: None,
+            delivered_asset_scale: None,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn query_matches_filters() {
+        let query = PaymentQuery {
+            direction: Some(PaymentDirection::Incoming),
+            since: Some(10),
+            until: Some(20),
+            ..Default::default()
+        };
+        assert!(query.matches(&payment(PaymentDirection::Incoming, 10)));
+        assert!(query.matches(&payment(PaymentDirection::Incoming, 20)));
+        assert!(!query.matches(&payment(PaymentDirection::Outgoing, 15)));
+        assert!(!query.matches(&payment(PaymentDirection::Incoming, 9)));
+        assert!(!query.matches(&payment(PaymentDirection::Incoming, 21)));
+        assert!(PaymentQuery::default().matches(&payment(PaymentDirection::Outgoing, 0)));
+    }
+
+    #[test]
+    fn parses_direction_and_status() {
+        for direction in &[PaymentDirection::Incoming, PaymentDirection::Outgoing] {
+            assert_eq!(
+                PaymentDirection::from_str(direction.as_str()),
+                Ok(*direction)
+            );
+        }
+        for status in &[PaymentStatus::Completed, PaymentStatus::Failed] {
+            assert_eq!(PaymentStatus::from_str(status.as_str()), Ok(*status));
+        }
+        assert!(PaymentDirection::from_str("sideways").is_err());
+    }
+
+    #[test]
+    fn query_limit_is_capped() {
+        assert_eq!(PaymentQuery::default().limit(), DEFAULT_PAYMENTS_LIMIT);
+        let query = PaymentQuery {
+            limit: Some(MAX_PAYMENTS_LIMIT + 1),
+            ..Default::default()
+        };
+        assert_eq!(query.limit(), MAX_PAYMENTS_LIMIT);
+    }
+}
@@ -8,19 +8,37 @@
 mod client;
 /// Congestion controller consumed by the [stream client](./client/fn.send_money.html)
 mod congestion;
+/// Connections carrying money and data on multiple streams, for both the sending and receiving side
+mod connection;
 /// Cryptographic utilities for generating fulfillments and encrypting/decrypting STREAM packets
 mod crypto;
 /// Stream errors
 mod error;
+/// History of the payments sent and received by each account
+mod history;
 /// Stream Packet implementation, [as specified in the RFC](https://interledger.org/rfcs/0029-stream/#5-packet-and-frame-specification)
 mod packet;
+/// Signing and verification of [STREAM receipts](https://interledger.org/rfcs/0039-stream-receipts/), which prove how much a receiver got
+mod receipt;
 /// A stream server implementing an [Outgoing Service](../interledger_service/trait.OutgoingService.html) for receiving STREAM payments from peers
 mod server;
 
-pub use client::{send_money, StreamDelivery};
+pub use client::{
+    quote, send_money, send_money_fixed_delivery, send_money_with_options, PaymentHandle,
+    SendMoneyOptions, StreamDelivery, StreamQuote,
+};
+pub use congestion::CongestionStrategy;
+pub use connection::{DataAndMoneyStream, ReceivedConnection, StreamConnection};
 pub use error::Error;
+pub use history::{
+    PaymentDirection, PaymentHistoryStore, PaymentQuery, PaymentRecord, PaymentStatus,
+    MAX_PAYMENTS_LIMIT,
+};
+pub use receipt::{verify_receipt, Receipt, RECEIPT_NONCE_LENGTH, RECEIPT_SECRET_LENGTH};
 pub use server::{
-    ConnectionGenerator, PaymentNotification, StreamNotificationsStore, StreamReceiverService,
+    connection_tag, unix_timestamp, ConnectionGenerator, PaymentNotification,
+    StreamConnectionDetails, StreamConnectionStore, StreamNotificationsStore,
+    StreamReceiverService,
 };
 
 #[cfg(test)]
@@ -28,17 +46,23 @@ pub mod test_helpers {
     use super::*;
     use async_trait::async_trait;
     use futures::channel::mpsc::UnboundedSender;
-    use interledger_errors::{AccountStoreError, AddressStoreError, ExchangeRateStoreError};
+    use interledger_errors::{
+        AccountStoreError, AddressStoreError, ExchangeRateStoreError, PaymentHistoryStoreError,
+        StreamConnectionStoreError,
+    };
     use interledger_packet::Address;
-    use interledger_rates::ExchangeRateStore;
-    use interledger_router::RouterStore;
+    use interledger_rates::{rate_from_f64, ExchangeRateStore};
+    use interledger_router::{PrefixMap, RouterStore};
     use interledger_service::{Account, AccountStore, AddressStore, Username};
     use interledger_service_util::MaxPacketAmountAccount;
+    use num::rational::BigRational;
     use once_cell::sync::Lazy;
+    use parking_lot::Mutex;
     use std::collections::HashMap;
     use std::iter::FromIterator;
     use std::str::FromStr;
     use std::sync::Arc;
+    use std::time::Duration;
     use uuid::Uuid;
 
     pub static EXAMPLE_CONNECTOR: Lazy<Address> =
@@ -100,6 +124,175 @@ pub mod test_helpers {
         fn publish_payment_notification(&self, _payment: PaymentNotification) {}
     }
 
+    #[async_trait]
+    impl StreamConnectionStore for DummyStore {
+        async fn create_stream_connection(
+            &self,
+            _connection_tag: &str,
+            _receive_max: Option<u64>,
+        ) -> Result<(), StreamConnectionStoreError> {
+            unimplemented!()
+        }
+
+        async fn get_stream_connection(
+            &self,
+            _connection_tag: &str,
+        ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+            unimplemented!()
+        }
+
+        async fn add_stream_connection_amount(
+            &self,
+            _connection_tag: &str,
+            _amount: u64,
+        ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+            unimplemented!()
+        }
+
+        async fn close_stream_connection(
+            &self,
+            _connection_tag: &str,
+        ) -> Result<(), StreamConnectionStoreError> {
+            unimplemented!()
+        }
+
+        async fn delete_idle_stream_connections(
+            &self,
+            _idle_timeout: Duration,
+        ) -> Result<(), StreamConnectionStoreError> {
+            unimplemented!()
+        }
+    }
+
+    #[async_trait]
+    impl PaymentHistoryStore for DummyStore {
+        async fn save_payment(
+            &self,
+            _payment: PaymentRecord,
+        ) -> Result<(), PaymentHistoryStoreError> {
+            unimplemented!()
+        }
+
+        async fn get_payments(
+            &self,
+            _account_id: Uuid,
+            _query: PaymentQuery,
+        ) -> Result<Vec<PaymentRecord>, PaymentHistoryStoreError> {
+            unimplemented!()
+        }
+    }
+
+    /// Keeps STREAM connections and payments in memory, for testing the receiver
+    /// when it tracks connections or records payments
+    #[derive(Clone, Default)]
+    pub struct TestConnectionStore {
+        pub connections: Arc<Mutex<HashMap<String, StreamConnectionDetails>>>,
+        pub payments: Arc<Mutex<Vec<PaymentRecord>>>,
+    }
+
+    impl super::StreamNotificationsStore for TestConnectionStore {
+        type Account = TestAccount;
+
+        fn add_payment_notification_subscription(
+            &self,
+            _account_id: Uuid,
+            _sender: UnboundedSender<PaymentNotification>,
+        ) {
+        }
+
+        fn publish_payment_notification(&self, _payment: PaymentNotification) {}
+    }
+
+    #[async_trait]
+    impl StreamConnectionStore for TestConnectionStore {
+        async fn create_stream_connection(
+            &self,
+            connection_tag: &str,
+            receive_max: Option<u64>,
+        ) -> Result<(), StreamConnectionStoreError> {
+            self.connections.lock().insert(
+                connection_tag.to_string(),
+                StreamConnectionDetails {
+                    receive_max,
+                    last_activity: unix_timestamp(),
+                    ..Default::default()
+                },
+            );
+            Ok(())
+        }
+
+        async fn get_stream_connection(
+            &self,
+            connection_tag: &str,
+        ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+            Ok(self.connections.lock().get(connection_tag).cloned())
+        }
+
+        async fn add_stream_connection_amount(
+            &self,
+            connection_tag: &str,
+            amount: u64,
+        ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError> {
+            let mut connections = self.connections.lock();
+            let details = connections.entry(connection_tag.to_string()).or_default();
+            if amount > details.remaining() {
+                return Ok(None);
+            }
+            details.total_received += amount;
+            details.last_activity = unix_timestamp();
+            Ok(Some(details.clone()))
+        }
+
+        async fn close_stream_connection(
+            &self,
+            connection_tag: &str,
+        ) -> Result<(), StreamConnectionStoreError> {
+            if let Some(details) = self.connections.lock().get_mut(connection_tag) {
+                details.closed = true;
+            }
+            Ok(())
+        }
+
+        async fn delete_idle_stream_connections(
+            &self,
+            idle_timeout: Duration,
+        ) -> Result<(), StreamConnectionStoreError> {
+            let cutoff = unix_timestamp().saturating_sub(idle_timeout.as_secs());
+            self.connections
+                .lock()
+                .retain(|_, details| details.last_activity > cutoff);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl PaymentHistoryStore for TestConnectionStore {
+        async fn save_payment(
+            &self,
+            payment: PaymentRecord,
+        ) -> Result<(), PaymentHistoryStoreError> {
+            self.payments.lock().push(payment);
+            Ok(())
+        }
+
+        async fn get_payments(
+            &self,
+            account_id: Uuid,
+            query: PaymentQuery,
+        ) -> Result<Vec<PaymentRecord>, PaymentHistoryStoreError> {
+            Ok(self
+                .payments
+                .lock()
+                .iter()
+                .rev()
+                .filter(|payment| payment.account_id == account_id && query.matches(payment))
+                .skip(query.offset)
+                .take(query.limit())
+                .cloned()
+                .collect())
+        }
+    }
+
     #[derive(Clone)]
     pub struct TestStore {
         pub route: Option<(String, TestAccount)>,
@@ -128,8 +321,8 @@ pub mod test_helpers {
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-            Arc::new(HashMap::from_iter(
+        fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+            Arc::new(PrefixMap::from_iter(
                 vec![(
                     self.route.clone().unwrap().0,
                     self.route.clone().unwrap().1.id(),
@@ -158,9 +351,15 @@ pub mod test_helpers {
 
     #[async_trait]
     impl ExchangeRateStore for TestStore {
-        fn get_exchange_rates(&self, codes: &[&str]) -> Result<Vec<f64>, ExchangeRateStoreError> {
+        fn get_exchange_rates(
+            &self,
+            codes: &[&str],
+        ) -> Result<Vec<BigRational>, ExchangeRateStoreError> {
             match (self.price_1, self.price_2) {
-                (Some(price_1), Some(price_2)) => Ok(vec![price_1, price_2]),
+                (Some(price_1), Some(price_2)) => Ok(vec![
+                    rate_from_f64(price_1).unwrap(),
+                    rate_from_f64(price_2).unwrap(),
+                ]),
                 _ => Err(ExchangeRateStoreError::PairNotFound {
                     from: codes[0].to_string(),
                     to: codes[1].to_string(),
@@ -170,12 +369,14 @@ pub mod test_helpers {
 
         fn set_exchange_rates(
             &self,
-            _rates: HashMap<String, f64>,
+            _rates: HashMap<String, BigRational>,
         ) -> Result<(), ExchangeRateStoreError> {
             unimplemented!("Cannot set exchange rates")
         }
 
-        fn get_all_exchange_rates(&self) -> Result<HashMap<String, f64>, ExchangeRateStoreError> {
+        fn get_all_exchange_rates(
+            &self,
+        ) -> Result<HashMap<String, BigRational>, ExchangeRateStoreError> {
             unimplemented!("Cannot get all exchange rates")
         }
     }
@@ -186,11 +387,13 @@ mod send_money_to_receiver {
     use super::test_helpers::*;
     use super::*;
     use bytes::Bytes;
+    use futures::StreamExt;
     use interledger_packet::Address;
     use interledger_packet::{ErrorCode, RejectBuilder};
     use interledger_router::Router;
-    use interledger_service::outgoing_service_fn;
-    use interledger_service_util::ExchangeRateService;
+    use interledger_service::{outgoing_service_fn, IncomingService};
+    use interledger_service_util::{ExchangeRateService, MaxPacketAmountService};
+    use num::rational::BigRational;
     use std::str::FromStr;
     use uuid::Uuid;
 
@@ -298,7 +501,11 @@ mod send_money_to_receiver {
             }),
         );
 
-        let server = ExchangeRateService::new(0.02, store.clone(), server);
+        let server = ExchangeRateService::new(
+            BigRational::new(2.into(), 100.into()),
+            store.clone(),
+            server,
+        );
         let server = Router::new(store.clone(), server);
 
         let (destination_account, shared_secret) =
@@ -321,4 +528,300 @@ mod send_money_to_receiver {
             _ => panic!("Payment should fail fast due to poor exchange rates"),
         }
     }
+
+    /// Sender with scale 6 paying a receiver with scale 9 through a connector taking a 2% spread
+    fn cross_currency_receiver(
+        sender_max_packet_amount: Option<u64>,
+    ) -> (
+        impl IncomingService<TestAccount> + Clone + Send + Sync + 'static,
+        TestAccount,
+        TestStore,
+        Address,
+        [u8; 32],
+    ) {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let sender_account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: Address::from_str("example.sender").unwrap(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 6,
+            max_packet_amount: sender_max_packet_amount,
+        };
+        let recipient_account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "ABC".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), recipient_account)),
+            price_1: Some(1.0),
+            price_2: Some(1.0),
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let server = ExchangeRateService::new(
+            BigRational::new(2.into(), 100.into()),
+            store.clone(),
+            server,
+        );
+        let server = Router::new(store.clone(), server);
+        let server = MaxPacketAmountService::new(store.clone(), server);
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+        (
+            server,
+            sender_account,
+            store,
+            destination_account,
+            shared_secret,
+        )
+    }
+
+    #[tokio::test]
+    async fn delivers_fixed_destination_amount() {
+        let (server, sender_account, store, destination_account, shared_secret) =
+            cross_currency_receiver(Some(200));
+
+        let receipt = send_money_fixed_delivery(
+            server,
+            &sender_account,
+            store,
+            destination_account,
+            shared_secret.to_vec(),
+            1_000_000,
+            0.03,
+            SendMoneyOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        // Each source unit delivers 980 destination units
+        assert!(receipt.delivered_amount >= 1_000_000);
+        assert!(receipt.delivered_amount < 1_000_980);
+        assert_eq!(receipt.sent_amount, 1021);
+        assert_eq!(receipt.destination_asset_code, Some("ABC".to_string()));
+    }
+
+    #[tokio::test]
+    async fn fixed_delivery_fails_if_probed_rate_is_too_low() {
+        let (server, sender_account, store, destination_account, shared_secret) =
+            cross_currency_receiver(None);
+
+        // Connector takes 2% spread, but we're only willing to tolerate 1.4%
+        let result = send_money_fixed_delivery(
+            server,
+            &sender_account,
+            store,
+            destination_account,
+            shared_secret.to_vec(),
+            1_000_000,
+            0.014,
+            SendMoneyOptions::default(),
+        )
+        .await;
+        match result {
+            Err(Error::SendMoneyError(_)) => {}
+            _ => panic!("Payment should fail due to the probed exchange rate"),
+        }
+    }
+
+    #[tokio::test]
+    async fn quotes_rate_and_max_packet_amount() {
+        let (server, sender_account, _, destination_account, shared_secret) =
+            cross_currency_receiver(Some(1000));
+
+        let quote = quote(
+            server,
+            &sender_account,
+            destination_account,
+            shared_secret.to_vec(),
+        )
+        .await
+        .unwrap();
+
+        assert!((quote.rate - 980.0).abs() < 0.001);
+        assert_eq!(quote.max_packet_amount, Some(1000));
+        assert_eq!(quote.destination_asset_code, Some("ABC".to_string()));
+        assert_eq!(quote.destination_asset_scale, Some(9));
+    }
+
+    #[tokio::test]
+    async fn sends_money_and_data_on_multiple_streams() {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account)),
+            price_1: None,
+            price_2: None,
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let mut connections = server.accept_connections();
+        let server = Router::new(store, server);
+
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+
+        let sender_account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: Address::from_str("example.sender").unwrap(),
+            max_packet_amount: None,
+        };
+        let mut connection = StreamConnection::new(
+            server,
+            &sender_account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_account.clone(),
+            shared_secret.to_vec(),
+            0.0,
+        );
+        let invoice = connection.open_stream();
+        invoice.send_money(100);
+        invoice.write(b"invoice #1");
+        let metadata = connection.open_stream();
+        metadata.write(b"some metadata");
+        metadata.send_money(50);
+        let receipt = connection.flush().await.unwrap();
+        assert_eq!(receipt.delivered_amount, 150);
+        assert_eq!(invoice.total_sent(), 100);
+        assert_eq!(metadata.total_sent(), 50);
+
+        let received = connections.next().await.unwrap();
+        assert_eq!(received.destination_account(), &destination_account);
+        assert_eq!(
+            received.source_account(),
+            Some(Address::from_str("example.sender").unwrap())
+        );
+        let received_streams = received.accept_streams();
+        assert_eq!(received_streams.len(), 2);
+        assert_eq!(received_streams[0].id(), invoice.id());
+        assert_eq!(&received_streams[0].read()[..], b"invoice #1");
+        assert_eq!(received_streams[0].total_received(), 100);
+        assert_eq!(&received_streams[1].read()[..], b"some metadata");
+        assert_eq!(received_streams[1].total_received(), 50);
+
+        // The receiver replies on the sender's stream and opens one of its own
+        received_streams[0].write(b"paid");
+        let receipt_stream = received.open_stream();
+        receipt_stream.write(b"receipt");
+        connection.sync().await.unwrap();
+        assert_eq!(&invoice.read()[..], b"paid");
+        let streams = connection.accept_streams();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].id(), receipt_stream.id());
+        assert_eq!(&streams[0].read()[..], b"receipt");
+
+        invoice.close();
+        connection.close().await.unwrap();
+        assert!(received_streams[0].is_remote_closed());
+        assert!(!received_streams[1].is_remote_closed());
+        assert!(received.is_closed());
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_receiver_to_read_data() {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account.clone())),
+            price_1: None,
+            price_2: None,
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let mut server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let mut connections = server.accept_connections();
+        let server = Router::new(store, server);
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+
+        let mut connection = StreamConnection::new(
+            server,
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_account,
+            shared_secret.to_vec(),
+            0.0,
+        );
+        let stream = connection.open_stream();
+        let data: Vec<u8> = (0..40_000).map(|i| i as u8).collect();
+        stream.write(&data);
+
+        // The receiver only buffers part of the data until the application reads it
+        connection.flush().await.unwrap();
+        let received = connections.next().await.unwrap();
+        let received_stream = received.accept_streams().pop().unwrap();
+        let mut received_data = received_stream.read().to_vec();
+        assert!(received_data.len() < data.len());
+
+        while received_data.len() < data.len() {
+            connection.sync().await.unwrap();
+            let chunk = received_stream.read();
+            assert!(!chunk.is_empty());
+            received_data.extend_from_slice(&chunk);
+        }
+        assert_eq!(received_data, data);
+    }
 }
@@ -0,0 +1,132 @@
+use super::crypto::hmac_sha256;
+use super::error::Error;
+use byteorder::{BigEndian, ReadBytesExt};
+use interledger_packet::oer::{BufOerExt, MutBufOerExt};
+use ring::hmac;
+
+/// The STREAM Receipts version
+const RECEIPT_VERSION: u8 = 1;
+/// Length of the HMAC at the end of each receipt
+const RECEIPT_HMAC_LENGTH: usize = 32;
+/// Length of the nonce the verifier picks to identify the receipts of a payment
+pub const RECEIPT_NONCE_LENGTH: usize = 16;
+/// Length of the secret the verifier shares with the receiver to sign receipts
+pub const RECEIPT_SECRET_LENGTH: usize = 32;
+
+/// A [STREAM Receipt](https://interledger.org/rfcs/0039-stream-receipts/), which proves
+/// to a verifier who knows the receipt secret how much a receiver got on a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    /// Nonce the verifier picked to identify the receipts of a payment
+    pub nonce: [u8; RECEIPT_NONCE_LENGTH],
+    /// Identifier of the stream the money was received on
+    pub stream_id: u64,
+    /// Total amount received on the stream, in the receiver's units
+    pub total_received: u64,
+}
+
+impl Receipt {
+    /// Serializes the receipt and signs it with the receipt secret
+    pub fn sign(&self, receipt_secret: &[u8]) -> Vec<u8> {
+        let mut receipt = Vec::with_capacity(1 + RECEIPT_NONCE_LENGTH + 9 + 8 + 32);
+        receipt.push(RECEIPT_VERSION);
+        receipt.extend_from_slice(&self.nonce);
+        receipt.put_var_uint(self.stream_id);
+        receipt.extend_from_slice(&self.total_received.to_be_bytes());
+        let receipt_hmac = hmac_sha256(receipt_secret, &receipt);
+        receipt.extend_from_slice(&receipt_hmac);
+        receipt
+    }
+
+    /// Parses a receipt **without** checking that it was signed with the receipt secret.
+    ///
+    /// This is useful for senders, which do not know the secret but want to read the
+    /// total received. Verifiers must use [`verify_receipt`](./fn.verify_receipt.html) instead.
+    pub fn from_bytes_unverified(receipt: &[u8]) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidReceipt(reason.to_string());
+        let mut reader = receipt;
+        let version = reader.read_u8().map_err(|_| invalid("receipt is empty"))?;
+        if version != RECEIPT_VERSION {
+            return Err(Error::InvalidReceipt(format!(
+                "unsupported receipt version {}",
+                version
+            )));
+        }
+        if reader.len() < RECEIPT_NONCE_LENGTH {
+            return Err(invalid("receipt is too short"));
+        }
+        let mut nonce = [0; RECEIPT_NONCE_LENGTH];
+        nonce.copy_from_slice(&reader[..RECEIPT_NONCE_LENGTH]);
+        reader = &reader[RECEIPT_NONCE_LENGTH..];
+        let stream_id = reader
+            .read_var_uint()
+            .map_err(|_| invalid("invalid stream id"))?;
+        let total_received = reader
+            .read_u64::<BigEndian>()
+            .map_err(|_| invalid("receipt is too short"))?;
+        if reader.len() != RECEIPT_HMAC_LENGTH {
+            return Err(invalid("invalid receipt length"));
+        }
+        Ok(Receipt {
+            nonce,
+            stream_id,
+            total_received,
+        })
+    }
+}
+
+/// Checks that the receipt was signed with the receipt secret and returns its details.
+///
+/// Verifiers should only credit the increase of `total_received` over the
+/// previous receipt they accepted with the same nonce and stream id.
+pub fn verify_receipt(receipt: &[u8], receipt_secret: &[u8]) -> Result<Receipt, Error> {
+    let details = Receipt::from_bytes_unverified(receipt)?;
+    let (body, receipt_hmac) = receipt.split_at(receipt.len() - RECEIPT_HMAC_LENGTH);
+    let key = hmac::Key::new(hmac::HMAC_SHA256, receipt_secret);
+    hmac::verify(&key, body, receipt_hmac).map_err(|_| {
+        Error::InvalidReceipt("receipt was not signed with this secret".to_string())
+    })?;
+    Ok(details)
+}
+
+#[cfg(test)]
+mod verifying_receipts {
+    use super::*;
+
+    static NONCE: [u8; RECEIPT_NONCE_LENGTH] = [7; RECEIPT_NONCE_LENGTH];
+    static SECRET: [u8; RECEIPT_SECRET_LENGTH] = [9; RECEIPT_SECRET_LENGTH];
+
+    fn receipt() -> Receipt {
+        Receipt {
+            nonce: NONCE,
+            stream_id: 1,
+            total_received: 1000,
+        }
+    }
+
+    #[test]
+    fn verifies_signed_receipts() {
+        let signed = receipt().sign(&SECRET);
+        assert_eq!(signed.len(), 59);
+        assert_eq!(verify_receipt(&signed, &SECRET).unwrap(), receipt());
+    }
+
+    #[test]
+    fn rejects_receipts_signed_with_another_secret() {
+        let signed = receipt().sign(&[1; RECEIPT_SECRET_LENGTH]);
+        assert!(verify_receipt(&signed, &SECRET).is_err());
+        // The sender can still read it
+        assert_eq!(Receipt::from_bytes_unverified(&signed).unwrap(), receipt());
+    }
+
+    #[test]
+    fn rejects_modified_receipts() {
+        let mut signed = receipt().sign(&SECRET);
+        // Bump the total received
+        signed[25] += 1;
+        assert!(verify_receipt(&signed, &SECRET).is_err());
+
+        let signed = receipt().sign(&SECRET);
+        assert!(verify_receipt(&signed[..signed.len() - 1], &SECRET).is_err());
+    }
+}
@@ -1,9 +1,13 @@
+use super::connection::{ConnectionRegistry, ReceivedConnection};
 use super::crypto::*;
+use super::history::{PaymentDirection, PaymentHistoryStore, PaymentRecord, PaymentStatus};
 use super::packet::*;
+use super::receipt::{Receipt, RECEIPT_NONCE_LENGTH, RECEIPT_SECRET_LENGTH};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use chrono::{DateTime, Utc};
-use futures::channel::mpsc::UnboundedSender;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use interledger_errors::StreamConnectionStoreError;
 use interledger_packet::{
     Address, ErrorCode, Fulfill, FulfillBuilder, PacketType as IlpPacketType, Prepare, Reject,
     RejectBuilder,
@@ -11,8 +15,8 @@ use interledger_packet::{
 use interledger_service::{Account, IlpResult, OutgoingRequest, OutgoingService, Username};
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
-use std::time::SystemTime;
-use tracing::debug;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error};
 use uuid::Uuid;
 
 // Note we are using the same magic bytes as the Javascript
@@ -23,6 +27,10 @@ use uuid::Uuid;
 // running the same STREAM implementation so it doesn't matter what
 // this string is.
 const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_shared_secret";
+/// Protocol specific string for deriving the key which encrypts receipt details in the address
+const STREAM_RECEIPTS_KEY_GENERATOR: &[u8] = b"ilp_stream_receipts";
+/// Length of the random part of the connection tag
+const TOKEN_LENGTH: usize = 18;
 
 /// A STREAM connection generator that creates `destination_account` and `shared_secret` values
 /// based on a single root secret.
@@ -61,6 +69,33 @@ impl ConnectionGenerator {
         (destination_account, shared_secret)
     }
 
+    /// Generate the STREAM parameters for a connection whose fulfills will carry
+    /// [STREAM receipts](./struct.Receipt.html) signed with the `receipt_secret`.
+    ///
+    /// The receipt nonce and secret are encrypted into the `destination_account`,
+    /// so the receiver does not need to store them.
+    pub fn generate_address_and_secret_with_receipts(
+        &self,
+        base_address: &Address,
+        receipt_nonce: [u8; RECEIPT_NONCE_LENGTH],
+        receipt_secret: [u8; RECEIPT_SECRET_LENGTH],
+    ) -> (Address, [u8; 32]) {
+        let mut receipt_details =
+            BytesMut::with_capacity(RECEIPT_NONCE_LENGTH + RECEIPT_SECRET_LENGTH);
+        receipt_details.extend_from_slice(&receipt_nonce);
+        receipt_details.extend_from_slice(&receipt_secret);
+        let encrypted_details = encrypt(&self.receipts_key(), receipt_details);
+
+        let mut token = generate_token().to_vec();
+        token.extend_from_slice(&encrypted_details);
+        let token = base64::encode_config(&token, base64::URL_SAFE_NO_PAD);
+        let shared_secret = hmac_sha256(&self.secret_generator[..], token.as_bytes());
+        let destination_account = base_address.with_suffix(token.as_ref()).unwrap();
+
+        debug!("Generated address with receipts: {}", destination_account);
+        (destination_account, shared_secret)
+    }
+
     /// Rederive the `shared_secret` from a `destination_account`.
     ///
     /// Although it is not strictly necessary, this uses the same logic as the Javascript
@@ -75,12 +110,39 @@ impl ConnectionGenerator {
     /// This method returns a Result in case we want to change the internal
     /// logic in the future.
     pub fn rederive_secret(&self, destination_account: &Address) -> Result<[u8; 32], ()> {
-        let local_part = destination_account.segments().rev().next().unwrap();
+        let local_part = connection_tag(destination_account);
         // Note this computes the HMAC with the token _encoded as UTF8_,
         // rather than decoding the base64 first.
         let shared_secret = hmac_sha256(&self.secret_generator[..], &local_part.as_bytes()[..]);
         Ok(shared_secret)
     }
+
+    /// Recover the receipt nonce and secret from a `destination_account` generated by
+    /// [`generate_address_and_secret_with_receipts`](#method.generate_address_and_secret_with_receipts)
+    pub(crate) fn receipt_details(
+        &self,
+        destination_account: &Address,
+    ) -> Option<([u8; RECEIPT_NONCE_LENGTH], [u8; RECEIPT_SECRET_LENGTH])> {
+        let token =
+            base64::decode_config(connection_tag(destination_account), base64::URL_SAFE_NO_PAD)
+                .ok()?;
+        if token.len() <= TOKEN_LENGTH {
+            return None;
+        }
+        let details = decrypt(&self.receipts_key(), BytesMut::from(&token[TOKEN_LENGTH..])).ok()?;
+        if details.len() != RECEIPT_NONCE_LENGTH + RECEIPT_SECRET_LENGTH {
+            return None;
+        }
+        let mut receipt_nonce = [0; RECEIPT_NONCE_LENGTH];
+        receipt_nonce.copy_from_slice(&details[..RECEIPT_NONCE_LENGTH]);
+        let mut receipt_secret = [0; RECEIPT_SECRET_LENGTH];
+        receipt_secret.copy_from_slice(&details[RECEIPT_NONCE_LENGTH..]);
+        Some((receipt_nonce, receipt_secret))
+    }
+
+    fn receipts_key(&self) -> [u8; 32] {
+        hmac_sha256(&self.secret_generator[..], STREAM_RECEIPTS_KEY_GENERATOR)
+    }
 }
 
 /// Notification that STREAM fulfilled a packet and received a single Interledger payment, used by Pubsub API consumers
@@ -98,6 +160,88 @@ pub struct PaymentNotification {
     pub timestamp: String,
 }
 
+/// Returns the tag identifying the STREAM connection a `destination_account` belongs to,
+/// which is the last segment of the address
+pub fn connection_tag(destination_account: &Address) -> &str {
+    destination_account.segments().last().unwrap()
+}
+
+/// The running totals of a STREAM connection, tracked by a
+/// [`StreamReceiverService`](./struct.StreamReceiverService.html) which
+/// [tracks connections](./struct.StreamReceiverService.html#method.track_connections)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StreamConnectionDetails {
+    /// The total amount fulfilled on this connection, denominated in the receiving account's asset
+    pub total_received: u64,
+    /// The maximum total amount this connection may receive, if it is limited
+    pub receive_max: Option<u64>,
+    /// Whether the sender closed the connection
+    pub closed: bool,
+    /// UNIX timestamp, in seconds, of when the connection was created or last received money
+    pub last_activity: u64,
+}
+
+impl StreamConnectionDetails {
+    /// The amount this connection may still receive
+    pub fn remaining(&self) -> u64 {
+        if self.closed {
+            return 0;
+        }
+        match self.receive_max {
+            Some(receive_max) => receive_max.saturating_sub(self.total_received),
+            None => u64::MAX,
+        }
+    }
+}
+
+/// Returns the current UNIX timestamp in seconds, as used for a connection's `last_activity`
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Store trait which keeps the state of the STREAM connections received by a
+/// [`StreamReceiverService`](./struct.StreamReceiverService.html), keyed by their connection tag
+#[async_trait]
+pub trait StreamConnectionStore {
+    /// Starts tracking a connection which may receive up to `receive_max`, if provided.
+    /// Resets the connection's state if it was already being tracked
+    async fn create_stream_connection(
+        &self,
+        connection_tag: &str,
+        receive_max: Option<u64>,
+    ) -> Result<(), StreamConnectionStoreError>;
+
+    /// Loads the details of a connection, if it is being tracked
+    async fn get_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError>;
+
+    /// Atomically adds the amount to the connection's total received and refreshes its
+    /// last activity, tracking the connection if it wasn't already. Leaves the connection
+    /// unchanged and returns `None` if it is closed or the amount exceeds its receive max
+    async fn add_stream_connection_amount(
+        &self,
+        connection_tag: &str,
+        amount: u64,
+    ) -> Result<Option<StreamConnectionDetails>, StreamConnectionStoreError>;
+
+    /// Marks a tracked connection as closed so it will not receive any more money
+    async fn close_stream_connection(
+        &self,
+        connection_tag: &str,
+    ) -> Result<(), StreamConnectionStoreError>;
+
+    /// Stops tracking the connections whose last activity is older than `idle_timeout`
+    async fn delete_idle_stream_connections(
+        &self,
+        idle_timeout: Duration,
+    ) -> Result<(), StreamConnectionStoreError>;
+}
+
 /// A trait representing the Publish side of a pub/sub store
 pub trait StreamNotificationsStore {
     type Account: Account;
@@ -116,16 +260,21 @@ pub trait StreamNotificationsStore {
 
 /// An OutgoingService that fulfills incoming STREAM packets.
 ///
-/// Note this does **not** maintain STREAM state, but instead fulfills
-/// all incoming packets to collect the money.
-///
-/// This does not currently support handling data sent via STREAM.
+/// By default this does **not** maintain STREAM state, but instead fulfills
+/// all incoming packets to collect the money and ignores any data sent via STREAM.
+/// Use [`accept_connections`](#method.accept_connections) to handle the money
+/// and data sent on each connection's streams, or
+/// [`track_connections`](#method.track_connections) to keep each connection's
+/// total received in the store and enforce its receive max.
 #[derive(Clone)]
 pub struct StreamReceiverService<S, O: OutgoingService<A>, A: Account> {
     connection_generator: ConnectionGenerator,
     next: O,
     account_type: PhantomData<A>,
     store: S,
+    connections: Option<ConnectionRegistry>,
+    track_connections: bool,
+    record_payments: bool,
 }
 
 impl<S, O, A> StreamReceiverService<S, O, A>
@@ -141,14 +290,47 @@ where
             next,
             account_type: PhantomData,
             store,
+            connections: None,
+            track_connections: false,
+            record_payments: false,
         }
     }
+
+    /// Keep each connection's total received, receive max and close state in the
+    /// [`StreamConnectionStore`](./trait.StreamConnectionStore.html), rejecting packets
+    /// which would exceed the receive max or arrive after the connection was closed
+    pub fn track_connections(&mut self, track_connections: bool) -> &mut Self {
+        self.track_connections = track_connections;
+        self
+    }
+
+    /// Save each fulfilled packet as an incoming payment in the
+    /// [`PaymentHistoryStore`](./trait.PaymentHistoryStore.html)
+    pub fn record_payments(&mut self, record_payments: bool) -> &mut Self {
+        self.record_payments = record_payments;
+        self
+    }
+
+    /// Keep track of the state of each connection and hand new connections to the
+    /// application through the returned channel, so it can read the data and money
+    /// sent on their streams and reply with data of its own
+    pub fn accept_connections(&mut self) -> UnboundedReceiver<ReceivedConnection> {
+        let (registry, receiver) = ConnectionRegistry::new();
+        self.connections = Some(registry);
+        receiver
+    }
 }
 
 #[async_trait]
 impl<S, O, A> OutgoingService<A> for StreamReceiverService<S, O, A>
 where
-    S: StreamNotificationsStore + Send + Sync + 'static + Clone,
+    S: StreamNotificationsStore
+        + StreamConnectionStore
+        + PaymentHistoryStore
+        + Send
+        + Sync
+        + 'static
+        + Clone,
     O: OutgoingService<A> + Send + Sync + Clone,
     A: Account + Send + Sync + Clone,
 {
@@ -167,21 +349,115 @@ where
         // The case where the request is bound for this server
         if dest.starts_with(to_address.as_ref()) {
             if let Ok(shared_secret) = self.connection_generator.rederive_secret(&destination) {
-                let response = receive_money(
+                let tag = connection_tag(&destination);
+                let mut details = if self.track_connections {
+                    match store.get_stream_connection(tag).await {
+                        Ok(details) => Some(details.unwrap_or_else(|| StreamConnectionDetails {
+                            last_activity: unix_timestamp(),
+                            ..Default::default()
+                        })),
+                        Err(err) => {
+                            error!("Error loading STREAM connection {}: {}", tag, err);
+                            return Err(RejectBuilder {
+                                code: ErrorCode::T00_INTERNAL_ERROR,
+                                message: &[],
+                                triggered_by: Some(to_address),
+                                data: &[],
+                            }
+                            .build());
+                        }
+                    }
+                } else {
+                    None
+                };
+                // Receipts report the connection's total, so they are only signed for tracked connections
+                let receipt_details = if details.is_some() {
+                    self.connection_generator.receipt_details(&destination)
+                } else {
+                    None
+                };
+                let was_closed =
+                    matches!(details, Some(StreamConnectionDetails { closed: true, .. }));
+
+                let mut response = receive_money(
                     &shared_secret,
                     &to_address,
                     request.to.asset_code(),
                     request.to.asset_scale(),
                     &request.prepare,
+                    self.connections.as_ref(),
+                    details.as_mut(),
+                    receipt_details.as_ref(),
                 );
+
+                if let Some(details) = details {
+                    if response.is_ok() && amount > 0 {
+                        // Another packet may have been fulfilled on this connection in the meantime,
+                        // so the store is the one to decide whether the amount fits
+                        match store.add_stream_connection_amount(tag, amount).await {
+                            Ok(Some(_)) => {}
+                            Ok(None) => {
+                                debug!(
+                                    "Rejecting packet which would exceed the receive max of STREAM connection {}",
+                                    tag
+                                );
+                                response = Err(RejectBuilder {
+                                    code: ErrorCode::F99_APPLICATION_ERROR,
+                                    message: b"Exceeded the connection's receive max",
+                                    triggered_by: Some(to_address),
+                                    data: &[],
+                                }
+                                .build());
+                            }
+                            Err(err) => {
+                                error!("Error updating STREAM connection {}: {}", tag, err);
+                                response = Err(RejectBuilder {
+                                    code: ErrorCode::T00_INTERNAL_ERROR,
+                                    message: &[],
+                                    triggered_by: Some(to_address),
+                                    data: &[],
+                                }
+                                .build());
+                            }
+                        }
+                    }
+                    if details.closed && !was_closed {
+                        if let Err(err) = store.close_stream_connection(tag).await {
+                            error!("Error closing STREAM connection {}: {}", tag, err);
+                        }
+                    }
+                }
+
                 match response {
-                    Ok(ref _fulfill) => store.publish_payment_notification(PaymentNotification {
-                        to_username,
-                        from_username,
-                        amount,
-                        destination: destination.clone(),
-                        timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
-                    }),
+                    Ok(ref _fulfill) => {
+                        if self.record_payments {
+                            let payment = PaymentRecord {
+                                id: Uuid::new_v4(),
+                                account_id: request.to.id(),
+                                direction: PaymentDirection::Incoming,
+                                status: PaymentStatus::Completed,
+                                destination: destination.clone(),
+                                counterparty: from_username.to_string(),
+                                amount,
+                                asset_code: request.to.asset_code().to_string(),
+                                asset_scale: request.to.asset_scale(),
+                                delivered_amount: None,
+                                delivered_asset_code: None,
+                                delivered_asset_scale: None,
+                                timestamp: unix_timestamp(),
+                            };
+                            if let Err(err) = store.save_payment(payment).await {
+                                error!("Error saving incoming payment to {}: {}", destination, err);
+                            }
+                        }
+                        store.publish_payment_notification(PaymentNotification {
+                            to_username,
+                            from_username,
+                            amount,
+                            destination: destination.clone(),
+                            timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
+                        })
+                    }
                     Err(ref reject) => {
                         if reject.code() == ErrorCode::F06_UNEXPECTED_PAYMENT {
                             // Assume the packet isn't for us if the decryption step fails.
@@ -202,6 +478,7 @@ where
 
 // TODO send asset code and scale back to sender also
 #[allow(clippy::cognitive_complexity)]
+#[allow(clippy::too_many_arguments)]
 fn receive_money(
     shared_secret: &[u8; 32],
     // Our node's ILP Address ( we are the receiver, so we should return that
@@ -210,6 +487,12 @@ fn receive_money(
     asset_code: &str,
     asset_scale: u8,
     prepare: &Prepare,
+    // Tracks the state of each connection, if the service accepts connections
+    connections: Option<&ConnectionRegistry>,
+    // The connection's running totals, if the service tracks connections
+    mut details: Option<&mut StreamConnectionDetails>,
+    // The nonce and secret to sign receipts with, if the sender asked for them
+    receipt_details: Option<&([u8; RECEIPT_NONCE_LENGTH], [u8; RECEIPT_SECRET_LENGTH])>,
 ) -> Result<Fulfill, Reject> {
     // Generate fulfillment
     let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
@@ -221,10 +504,9 @@ fn receive_money(
     let prepare_amount = prepare.amount();
 
     // Note that we are copying the Prepare packet data. This is a bad idea
-    // in cases where STREAM is used to send a significant amount of data.
-    // This implementation doesn't currently support handling the STREAM data
-    // so copying the bytes of the other STREAM frames shouldn't be a big
-    // performance hit in practice.
+    // in cases where STREAM is used to send a significant amount of data,
+    // but the amount of data per packet is limited by the flow control
+    // windows so this shouldn't be a big performance hit in practice.
     // The data is copied so that we can take the Prepare packet by
     // reference in the case that the decryption fails and we want to pass
     // the request on to the next service.
@@ -241,19 +523,100 @@ fn receive_money(
         .build()
     })?;
 
+    let receivable = details
+        .as_ref()
+        .map_or(u64::MAX, |details| details.remaining());
+    let will_fulfill = is_fulfillable
+        && prepare_amount >= stream_packet.prepare_amount()
+        && prepare_amount <= receivable;
+
+    // Let the connection apply the money and data, and reply with the data it has queued for the sender
+    let connection_frames = connections.map(|connections| {
+        let destination = prepare.destination();
+        let connection = connections.get_or_create(&destination);
+        let mut connection = connection.lock();
+        let amount = if will_fulfill {
+            Some(prepare_amount)
+        } else {
+            None
+        };
+        connection.handle_incoming_frames(stream_packet.frames(), amount);
+        let frames = connection.outgoing_frames(0);
+        connection.apply_sent(&frames);
+        if connection.is_closed() {
+            connections.remove(&destination);
+        }
+        frames
+    });
+
+    // Sign a receipt for each stream the money was sent on, if the sender asked for them.
+    // They report the connection's total so they never claim more than was received
+    let receipts: Vec<(u64, Vec<u8>)> = match (&details, receipt_details) {
+        (Some(details), Some((nonce, receipt_secret))) if will_fulfill => {
+            let total_received = details.total_received.saturating_add(prepare_amount);
+            stream_packet
+                .frames()
+                .filter_map(|frame| match frame {
+                    Frame::StreamMoney(frame) => Some(frame.stream_id),
+                    _ => None,
+                })
+                .map(|stream_id| {
+                    let receipt = Receipt {
+                        nonce: *nonce,
+                        stream_id,
+                        total_received,
+                    };
+                    (stream_id, receipt.sign(&receipt_secret[..]))
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
     let mut response_frames: Vec<Frame> = Vec::new();
 
     // Handle STREAM frames
-    // TODO reject if they send data?
     for frame in stream_packet.frames() {
-        // Tell the sender the stream can handle lots of money
+        // Tell the sender how much more the stream can handle, which is lots of money
+        // unless the connection has a receive max
         if let Frame::StreamMoney(ref frame) = frame {
+            let (total_received, receive_max) = match details {
+                Some(ref details) => {
+                    let total_received = if will_fulfill {
+                        details.total_received.saturating_add(prepare_amount)
+                    } else {
+                        details.total_received
+                    };
+                    let receive_max = if details.closed {
+                        details.total_received
+                    } else {
+                        details.receive_max.unwrap_or(u64::MAX)
+                    };
+                    (total_received, receive_max)
+                }
+                // TODO will returning zero here cause problems?
+                None => (0, u64::MAX),
+            };
             response_frames.push(Frame::StreamMaxMoney(StreamMaxMoneyFrame {
                 stream_id: frame.stream_id,
-                // TODO will returning zero here cause problems?
-                total_received: 0,
-                receive_max: u64::max_value(),
+                total_received,
+                receive_max,
             }));
+            if let Some((_, receipt)) = receipts
+                .iter()
+                .find(|(stream_id, _)| *stream_id == frame.stream_id)
+            {
+                response_frames.push(Frame::StreamReceipt(StreamReceiptFrame {
+                    stream_id: frame.stream_id,
+                    receipt,
+                }));
+            }
+        }
+
+        if let Frame::ConnectionClose(_) = frame {
+            if let Some(ref mut details) = details {
+                details.closed = true;
+            }
         }
 
         // If we receive a ConnectionNewAddress frame, then send them our asset
@@ -267,9 +630,18 @@ fn receive_money(
             }));
         }
     }
+    if let Some(ref connection_frames) = connection_frames {
+        response_frames.extend(connection_frames.frames());
+    }
+
+    if will_fulfill {
+        if let Some(details) = details {
+            details.total_received = details.total_received.saturating_add(prepare_amount);
+        }
+    }
 
     // Return Fulfill or Reject Packet
-    if is_fulfillable && prepare_amount >= stream_packet.prepare_amount() {
+    if will_fulfill {
         let response_packet = StreamPacketBuilder {
             sequence: stream_packet.sequence(),
             ilp_packet_type: IlpPacketType::Fulfill,
@@ -346,6 +718,38 @@ mod connection_generator {
                 .unwrap(),
             shared_secret
         );
+        assert!(connection_generator
+            .receipt_details(&destination_account)
+            .is_none());
+    }
+
+    #[test]
+    fn encrypts_receipt_details_in_the_address() {
+        let server_secret = [9; 32];
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&server_secret[..]));
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&receiver_address, [1; 16], [2; 32]);
+
+        assert!(destination_account
+            .to_bytes()
+            .starts_with(receiver_address.as_ref()));
+        assert_eq!(
+            connection_generator
+                .rederive_secret(&destination_account)
+                .unwrap(),
+            shared_secret
+        );
+        assert_eq!(
+            connection_generator.receipt_details(&destination_account),
+            Some(([1; 16], [2; 32]))
+        );
+
+        // Another server can't read them
+        let other_generator = ConnectionGenerator::new(Bytes::from(&[8; 32][..]));
+        assert!(other_generator
+            .receipt_details(&destination_account)
+            .is_none());
     }
 }
 
@@ -395,7 +799,16 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            None,
+            None,
+        );
         assert!(result.is_ok());
     }
 
@@ -423,7 +836,16 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            None,
+            None,
+        );
         assert!(result.is_ok());
     }
 
@@ -452,7 +874,16 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            None,
+            None,
+        );
         assert!(result.is_err());
     }
 
@@ -491,7 +922,16 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            None,
+            None,
+        );
         assert!(result.is_err());
     }
 
@@ -513,8 +953,17 @@ mod receiving_money {
                 .as_ref() as &[u8],
             "did not regenerate the same shared secret",
         );
-        let fulfill = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare)
-            .expect("Receiver should be able to generate the fulfillment");
+        let fulfill = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            None,
+            None,
+        )
+        .expect("Receiver should be able to generate the fulfillment");
         assert_eq!(
             &hash_sha256(fulfill.fulfillment())[..],
             &condition[..],
@@ -527,6 +976,7 @@ mod receiving_money {
 mod stream_receiver_service {
     use super::*;
     use crate::test_helpers::*;
+    use crate::PaymentQuery;
     use interledger_packet::PrepareBuilder;
     use interledger_service::outgoing_service_fn;
 
@@ -711,4 +1161,257 @@ mod stream_receiver_service {
             Address::from_str("example.other-receiver").unwrap(),
         );
     }
+
+    fn stream_request(
+        connection_generator: &ConnectionGenerator,
+        destination_account: &Address,
+        to_address: &Address,
+        amount: u64,
+        frames: &[Frame],
+    ) -> OutgoingRequest<TestAccount> {
+        let shared_secret = connection_generator
+            .rederive_secret(destination_account)
+            .unwrap();
+        let data = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence: 1,
+            frames,
+        }
+        .build()
+        .into_encrypted(&shared_secret[..]);
+        let execution_condition = generate_condition(&shared_secret[..], &data);
+        let prepare = PrepareBuilder {
+            destination: destination_account.clone(),
+            amount,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build();
+        OutgoingRequest {
+            from: TestAccount {
+                id: Uuid::new_v4(),
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                max_packet_amount: None,
+            },
+            to: TestAccount {
+                id: Uuid::new_v4(),
+                ilp_address: to_address.clone(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                max_packet_amount: None,
+            },
+            original_amount: amount,
+            prepare,
+        }
+    }
+
+    #[tokio::test]
+    async fn enforces_the_connection_receive_max() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, _) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let tag = connection_tag(&destination_account);
+        let store = TestConnectionStore::default();
+        store
+            .create_stream_connection(tag, Some(150))
+            .await
+            .unwrap();
+
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            store.clone(),
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.track_connections(true);
+
+        let frames = [Frame::StreamMoney(StreamMoneyFrame {
+            stream_id: 1,
+            shares: 1,
+        })];
+        let result = service
+            .send_request(stream_request(
+                &connection_generator,
+                &destination_account,
+                &ilp_address,
+                100,
+                &frames,
+            ))
+            .await;
+        assert!(result.is_ok());
+
+        let result = service
+            .send_request(stream_request(
+                &connection_generator,
+                &destination_account,
+                &ilp_address,
+                100,
+                &frames,
+            ))
+            .await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F99_APPLICATION_ERROR);
+
+        let details = store.get_stream_connection(tag).await.unwrap().unwrap();
+        assert_eq!(details.total_received, 100);
+        assert_eq!(details.receive_max, Some(150));
+    }
+
+    #[tokio::test]
+    async fn records_incoming_payments() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, _) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let store = TestConnectionStore::default();
+
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            store.clone(),
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.record_payments(true);
+
+        let frames = [Frame::StreamMoney(StreamMoneyFrame {
+            stream_id: 1,
+            shares: 1,
+        })];
+        let request = stream_request(
+            &connection_generator,
+            &destination_account,
+            &ilp_address,
+            100,
+            &frames,
+        );
+        let account_id = request.to.id();
+        let result = service.send_request(request).await;
+        assert!(result.is_ok());
+
+        let payments = store
+            .get_payments(account_id, PaymentQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].direction, PaymentDirection::Incoming);
+        assert_eq!(payments[0].status, PaymentStatus::Completed);
+        assert_eq!(payments[0].destination, destination_account);
+        assert_eq!(payments[0].amount, 100);
+    }
+
+    #[tokio::test]
+    async fn rejects_money_after_the_connection_is_closed() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, _) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let tag = connection_tag(&destination_account);
+        let store = TestConnectionStore::default();
+
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            store.clone(),
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.track_connections(true);
+
+        let result = service
+            .send_request(stream_request(
+                &connection_generator,
+                &destination_account,
+                &ilp_address,
+                100,
+                &[
+                    Frame::StreamMoney(StreamMoneyFrame {
+                        stream_id: 1,
+                        shares: 1,
+                    }),
+                    Frame::ConnectionClose(ConnectionCloseFrame {
+                        code: crate::packet::ErrorCode::NoError,
+                        message: "",
+                    }),
+                ],
+            ))
+            .await;
+        assert!(result.is_ok());
+        let details = store.get_stream_connection(tag).await.unwrap().unwrap();
+        assert_eq!(details.total_received, 100);
+        assert!(details.closed);
+
+        let result = service
+            .send_request(stream_request(
+                &connection_generator,
+                &destination_account,
+                &ilp_address,
+                1,
+                &[Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: 1,
+                    shares: 1,
+                })],
+            ))
+            .await;
+        assert!(result.is_err());
+        let details = store.get_stream_connection(tag).await.unwrap().unwrap();
+        assert_eq!(details.total_received, 100);
+    }
+
+    #[tokio::test]
+    async fn attaches_receipts_to_fulfills() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&ilp_address, [3; 16], [4; 32]);
+
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            TestConnectionStore::default(),
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        );
+        service.track_connections(true);
+
+        let frames = [Frame::StreamMoney(StreamMoneyFrame {
+            stream_id: 1,
+            shares: 1,
+        })];
+        for expected_total in &[100, 200] {
+            let fulfill = service
+                .send_request(stream_request(
+                    &connection_generator,
+                    &destination_account,
+                    &ilp_address,
+                    100,
+                    &frames,
+                ))
+                .await
+                .unwrap();
+            let response =
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(fulfill.data()))
+                    .unwrap();
+            let receipt = response
+                .frames()
+                .find_map(|frame| match frame {
+                    Frame::StreamReceipt(frame) => Some(frame.receipt.to_vec()),
+                    _ => None,
+                })
+                .unwrap();
+            let receipt = crate::verify_receipt(&receipt, &[4; 32]).unwrap();
+            assert_eq!(receipt.nonce, [3; 16]);
+            assert_eq!(receipt.stream_id, 1);
+            assert_eq!(receipt.total_received, *expected_total);
+        }
+    }
 }